@@ -0,0 +1,41 @@
+/* C bindings of the ab-av1 crf-search planner, see src/ffi.rs.
+ *
+ * Link against the ab_av1 cdylib, e.g. libab_av1.so, built with `cargo build --release --lib`.
+ */
+#ifndef AB_AV1_H
+#define AB_AV1_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* ab_av1_planner_next results. */
+#define AB_AV1_PLAN_NEXT 0
+#define AB_AV1_PLAN_DONE 1
+#define AB_AV1_PLAN_ERROR -1
+
+typedef struct AbAv1Planner AbAv1Planner;
+
+/* Search min_crf..=max_crf, in crf_increment steps, for the highest crf with a score of
+ * at least min_score & an encoded size of at most max_encoded_percent of the input.
+ * Returns NULL for invalid args. Free with ab_av1_planner_free. */
+AbAv1Planner *ab_av1_planner_new(float min_crf, float max_crf, float crf_increment,
+                                 float min_score, float max_encoded_percent);
+
+/* The first crf to sample. */
+float ab_av1_planner_initial_crf(const AbAv1Planner *planner);
+
+/* Adds the mean sample score & encode_percent of the last proposed crf.
+ * Returns AB_AV1_PLAN_NEXT writing the next crf to sample to crf_out,
+ * AB_AV1_PLAN_DONE writing the best crf, or AB_AV1_PLAN_ERROR if no crf
+ * delivers the min score or for non-finite & inconsistent samples. */
+int ab_av1_planner_next(AbAv1Planner *planner, float crf, float score, double encode_percent,
+                        float *crf_out);
+
+void ab_av1_planner_free(AbAv1Planner *planner);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
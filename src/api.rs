@@ -0,0 +1,432 @@
+//! Library API to run crf-search, auto-encode & VMAF scoring without the cli.
+use crate::{
+    command::{
+        auto_encode,
+        crf_search::{self, QualityValue},
+        sample_encode, vmaf,
+    },
+    error::Error,
+    stdin, temporary,
+};
+use anyhow::anyhow;
+use clap::Parser;
+use futures_util::StreamExt;
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget};
+use std::{ffi::OsString, future::Future, iter, path::PathBuf, pin::pin, time::Duration};
+use tokio::{sync::mpsc, task::LocalSet};
+
+tokio::task_local! {
+    /// Progress event sender of the current api call.
+    static PROGRESS: mpsc::UnboundedSender<Progress>;
+}
+
+/// Progress event sent while running.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum Progress {
+    /// crf-search sample encoding & scoring.
+    CrfSearch {
+        /// Run number starting from `1`.
+        crf_run: usize,
+        crf: f32,
+        /// Estimated overall search progress `[0, 1]`.
+        progress: f32,
+        /// Encode or scoring fps, `0.0` if unknown.
+        fps: f32,
+    },
+    /// A crf-search run result, excluding the final result.
+    CrfRun(CrfSearchResult),
+    /// Final encode progress `[0, 1]`.
+    Encode { progress: f32 },
+    /// VMAF scoring progress `[0, 1]`.
+    Vmaf { progress: f32 },
+}
+
+/// crf-search result.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct CrfSearchResult {
+    pub crf: f32,
+    /// Mean sample score, e.g. VMAF.
+    pub score: f32,
+    /// Score metric, e.g. "VMAF".
+    pub score_kind: &'static str,
+    /// Mean encoded sample size as a percentage of the input.
+    pub encode_percent: f64,
+    /// Predicted encoded video stream size in bytes.
+    pub predicted_encode_size: u64,
+    /// Predicted encode size ± error at ~95% confidence, `0` if unknown.
+    pub predicted_encode_size_error: u64,
+    pub predicted_encode_time: Duration,
+    /// The next worse crf failed to deliver the min score, see crf-search docs.
+    pub quantization_limited: bool,
+    /// The search stopped at --max-iterations with the best attempt so far.
+    pub iteration_limited: bool,
+    /// The min score minus the score, if accepted as the nearest with --accept-nearest.
+    pub score_shortfall: Option<f32>,
+}
+
+impl From<&crf_search::Sample> for CrfSearchResult {
+    fn from(sample: &crf_search::Sample) -> Self {
+        Self {
+            crf: sample.crf(),
+            score: sample.enc.score,
+            score_kind: sample.enc.score_kind.display_str(),
+            encode_percent: sample.enc.encode_percent,
+            predicted_encode_size: sample.enc.prediction.size,
+            predicted_encode_size_error: sample.enc.prediction.size_error,
+            predicted_encode_time: sample.enc.prediction.time,
+            quantization_limited: sample.quantization_limited,
+            iteration_limited: sample.iteration_limited,
+            score_shortfall: sample.score_shortfall,
+        }
+    }
+}
+
+/// auto-encode result.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct AutoEncodeResult {
+    pub output: PathBuf,
+    /// crf-search result, `None` when using --per-scene-crf.
+    pub search: Option<CrfSearchResult>,
+}
+
+/// Full VMAF scores.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[non_exhaustive]
+pub struct VmafScore {
+    /// VMAF score, pooled using --vmaf-pool.
+    pub vmaf: f32,
+    /// PSNR with --also-psnr.
+    pub psnr: Option<f32>,
+    /// SSIM with --also-ssim.
+    pub ssim: Option<f32>,
+}
+
+/// Interpolated crf-search, see `ab-av1 crf-search --help`.
+///
+/// ```no_run
+/// # async fn example() -> Result<(), ab_av1::Error> {
+/// let (tx, mut progress) = tokio::sync::mpsc::unbounded_channel();
+/// let search = ab_av1::CrfSearch::new(["-i", "vid.mkv", "--preset", "8", "--min-vmaf", "95"])?;
+/// let best = search.run(tx).await?;
+/// println!("crf {}", best.crf);
+/// # Ok(()) }
+/// ```
+pub struct CrfSearch(crf_search::Args);
+
+impl CrfSearch {
+    /// Configure using crf-search cli args, e.g. `["-i", "vid.mkv", "--min-vmaf", "95"]`.
+    pub fn new<T: Into<OsString>>(args: impl IntoIterator<Item = T>) -> Result<Self, Error> {
+        parse("crf-search", args).map(Self)
+    }
+
+    /// Run the crf-search sending progress events to `progress`.
+    pub async fn run(
+        self,
+        progress: mpsc::UnboundedSender<Progress>,
+    ) -> Result<CrfSearchResult, Error> {
+        let Self(mut args) = self;
+        run_local(progress, async move {
+            stdin::ensure_seekable(&args.args.input)?;
+            let probe = args.args.probe_input().await?;
+            args.sample
+                .set_extension_from_input(&args.args.input, &args.args.encoder, &probe);
+            args.lengthen_subsampled(&probe, &ProgressBar::hidden());
+            let thorough = args.thorough;
+
+            let mut run = pin!(crf_search::run(args, probe.into()));
+            while let Some(update) = run.next().await {
+                let update = update?;
+                if let crf_search::Update::Done(best) = &update {
+                    return Ok(best.into());
+                }
+                emit_crf_update(&update, thorough);
+            }
+            Err(anyhow!("no crf-search best?"))
+        })
+        .await
+    }
+}
+
+/// crf-search then encode, see `ab-av1 auto-encode --help`.
+pub struct AutoEncode(auto_encode::Args);
+
+impl AutoEncode {
+    /// Configure using auto-encode cli args, e.g. `["-i", "vid.mkv", "--min-vmaf", "95"]`.
+    pub fn new<T: Into<OsString>>(args: impl IntoIterator<Item = T>) -> Result<Self, Error> {
+        parse("auto-encode", args).map(Self)
+    }
+
+    /// Run the crf-search & encode sending progress events to `progress`.
+    pub async fn run(
+        self,
+        progress: mpsc::UnboundedSender<Progress>,
+    ) -> Result<AutoEncodeResult, Error> {
+        let Self(args) = self;
+        run_local(progress, async move {
+            let hidden = MultiProgress::with_draw_target(ProgressDrawTarget::hidden());
+            let auto_encode::Encoded { output, best } = auto_encode::run(args, &hidden).await?;
+            Ok(AutoEncodeResult {
+                output,
+                search: best.as_ref().map(CrfSearchResult::from),
+            })
+        })
+        .await
+    }
+}
+
+/// Full VMAF score calculation, see `ab-av1 vmaf --help`.
+pub struct Vmaf(vmaf::Args);
+
+impl Vmaf {
+    /// Configure using vmaf cli args, e.g. `["--reference", "vid.mkv", "--distorted", "vid.av1.mkv"]`.
+    pub fn new<T: Into<OsString>>(args: impl IntoIterator<Item = T>) -> Result<Self, Error> {
+        parse("vmaf", args).map(Self)
+    }
+
+    /// Calculate the VMAF score sending progress events to `progress`.
+    pub async fn score(
+        self,
+        progress: mpsc::UnboundedSender<Progress>,
+    ) -> Result<VmafScore, Error> {
+        let Self(args) = self;
+        run_local(progress, async move {
+            let bar = ProgressBar::hidden();
+            let score = vmaf::score(args, &bar);
+            let vmaf::Scores {
+                vmaf, psnr, ssim, ..
+            } = with_bar_progress(&bar, score, |progress| Progress::Vmaf { progress }).await?;
+            Ok(VmafScore { vmaf, psnr, ssim })
+        })
+        .await
+    }
+}
+
+/// The crf-search decision logic without any encoding or scoring, so other frontends,
+/// e.g. via the C or wasm bindings, can search exactly like crf-search while running
+/// their own sample encodes.
+///
+/// ```
+/// # fn sample_encode(crf: f32) -> (f32, f64) { (110.0 - crf, crf as f64) }
+/// let mut planner = ab_av1::CrfPlanner::new(10.0, 55.0, 1.0, 95.0, 80.0);
+/// let mut crf = planner.initial_crf();
+/// let best = loop {
+///     let (vmaf, encode_percent) = sample_encode(crf);
+///     match planner.next(crf, vmaf, encode_percent)? {
+///         ab_av1::CrfPlan::Next { crf: next } => crf = next,
+///         ab_av1::CrfPlan::Done { crf, .. } => break crf,
+///         _ => unreachable!(),
+///     }
+/// };
+/// assert!(best <= 15.0);
+/// # Ok::<_, ab_av1::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct CrfPlanner(crf_search::planner::Planner);
+
+/// Next step of a [`CrfPlanner`] search.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[non_exhaustive]
+pub enum CrfPlan {
+    /// Sample encode & score this crf next.
+    Next { crf: f32 },
+    /// The search is complete, `crf` is the best.
+    Done {
+        crf: f32,
+        score: f32,
+        encode_percent: f64,
+    },
+}
+
+impl CrfPlanner {
+    /// Search `min_crf..=max_crf`, in `crf_increment` steps, for the highest crf with a
+    /// score of at least `min_score` & an encoded size of at most `max_encoded_percent`
+    /// of the input.
+    pub fn new(
+        min_crf: f32,
+        max_crf: f32,
+        crf_increment: f32,
+        min_score: f32,
+        max_encoded_percent: f32,
+    ) -> Self {
+        Self(crf_search::planner::Planner::new(
+            crf_search::q_from_crf(min_crf, crf_increment),
+            crf_search::q_from_crf(max_crf, crf_increment),
+            false,
+            crf_increment,
+            false,
+            min_score,
+            None,
+            false,
+            max_encoded_percent,
+            None,
+        ))
+    }
+
+    /// The first crf to sample.
+    pub fn initial_crf(&self) -> f32 {
+        self.0.initial_q().to_crf(self.0.crf_increment)
+    }
+
+    /// Add the mean sample `score` & `encode_percent` of the last proposed `crf` & plan
+    /// the next step.
+    ///
+    /// Returns [`Error::CrfNotFound`] if no crf delivers the min score, or
+    /// [`Error::Other`] for non-finite or inconsistent samples.
+    pub fn next(&mut self, crf: f32, score: f32, encode_percent: f64) -> Result<CrfPlan, Error> {
+        if !(crf.is_finite() && score.is_finite() && encode_percent.is_finite()) {
+            return Err(Error::Other(anyhow!(
+                "non-finite sample: crf {crf}, score {score}, encode percent {encode_percent}"
+            )));
+        }
+        let crf_increment = self.0.crf_increment;
+        let sample = crf_search::Sample {
+            enc: sample_encode::Output {
+                score,
+                score_error: 0.0,
+                score_kind: sample_encode::ScoreKind::Vmaf,
+                min_frame_score: None,
+                frame_stats: None,
+                psnr: None,
+                ssim: None,
+                encode_percent,
+                prediction: <_>::default(),
+                from_cache: false,
+            },
+            crf_increment,
+            q: crf_search::q_from_crf(crf, crf_increment),
+            quantization_limited: false,
+            iteration_limited: false,
+            score_shortfall: None,
+        };
+        Ok(match self.0.next(sample)? {
+            crf_search::planner::Plan::Next(q) => CrfPlan::Next {
+                crf: q.to_crf(crf_increment),
+            },
+            crf_search::planner::Plan::Done { best, .. } => CrfPlan::Done {
+                crf: best.crf(),
+                score: best.enc.score,
+                encode_percent: best.enc.encode_percent,
+            },
+        })
+    }
+}
+
+fn parse<A: Parser, T: Into<OsString>>(
+    name: &str,
+    args: impl IntoIterator<Item = T>,
+) -> Result<A, Error> {
+    let args = iter::once(OsString::from(name)).chain(args.into_iter().map(Into::into));
+    A::try_parse_from(args).map_err(|err| Error::Other(err.into()))
+}
+
+/// Run `f` in a new local task set & temp file scope, sending progress events to `progress`.
+///
+/// Temp files are deleted afterwards.
+async fn run_local<T>(
+    progress: mpsc::UnboundedSender<Progress>,
+    f: impl Future<Output = anyhow::Result<T>>,
+) -> Result<T, Error> {
+    let f = temporary::scope(async {
+        let out = f.await;
+        temporary::clean_all().await;
+        out
+    });
+    LocalSet::new()
+        .run_until(PROGRESS.scope(progress, f))
+        .await
+        .map_err(Error::from)
+}
+
+/// Send a progress event, if running within an api call.
+fn emit(progress: impl FnOnce() -> Progress) {
+    _ = PROGRESS.try_with(|tx| tx.send(progress()));
+}
+
+/// Send progress events for crf-search updates, if running within an api call.
+pub fn emit_crf_update(update: &crf_search::Update, thorough: bool) {
+    match update {
+        crf_search::Update::Status {
+            crf_run,
+            crf,
+            sample: sample_encode::Status { progress, fps, .. },
+        } => emit(|| Progress::CrfSearch {
+            crf_run: *crf_run,
+            crf: *crf,
+            progress: (crf_search::guess_progress(*crf_run, *progress, thorough)
+                / crf_search::BAR_LEN as f64) as _,
+            fps: *fps,
+        }),
+        crf_search::Update::RunResult(result) => emit(|| Progress::CrfRun(result.into())),
+        crf_search::Update::SampleResult { .. } | crf_search::Update::Done(_) => {}
+    }
+}
+
+/// Run `f` sending `progress(position / length)` events of the `bar`,
+/// if running within an api call.
+pub async fn with_bar_progress<F: Future>(
+    bar: &ProgressBar,
+    f: F,
+    progress: impl Fn(f32) -> Progress,
+) -> F::Output {
+    if PROGRESS.try_with(|_| ()).is_err() {
+        return f.await;
+    }
+    let mut f = pin!(f);
+    let mut interval = tokio::time::interval(Duration::from_millis(250));
+    let mut last = None;
+    loop {
+        tokio::select! {
+            out = &mut f => return out,
+            _ = interval.tick() => {
+                let fraction = match bar.length() {
+                    Some(len) if len > 0 => (bar.position() as f64 / len as f64) as f32,
+                    _ => continue,
+                };
+                if last != Some(fraction) {
+                    last = Some(fraction);
+                    emit(|| progress(fraction));
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn parse_api_args() {
+    assert!(CrfSearch::new(["-i", "vid.mkv", "--min-vmaf", "95"]).is_ok());
+    assert!(CrfSearch::new(["--min-vmaf", "95"]).is_err());
+    assert!(Vmaf::new(["--reference", "vid.mkv", "--distorted", "vid.av1.mkv"]).is_ok());
+    // not in an api call
+    emit(|| unreachable!());
+}
+
+#[test]
+fn crf_planner_search() {
+    let mut planner = CrfPlanner::new(10.0, 55.0, 1.0, 95.0, 80.0);
+    let mut crf = planner.initial_crf();
+    assert_eq!(crf, 32.0);
+    let mut runs = 0;
+    let best = loop {
+        runs += 1;
+        match planner.next(crf, 120.0 - crf, 20.0).unwrap() {
+            CrfPlan::Next { crf: next } => crf = next,
+            CrfPlan::Done { crf, score, .. } => break (crf, score),
+        }
+    };
+    assert_eq!(best, (24.0, 96.0));
+    assert!(runs < 8, "{runs}");
+
+    let mut planner = CrfPlanner::new(10.0, 55.0, 1.0, 95.0, 80.0);
+    let mut crf = planner.initial_crf();
+    let err = loop {
+        match planner.next(crf, 50.0, 20.0) {
+            Ok(CrfPlan::Next { crf: next }) => crf = next,
+            Ok(done) => panic!("{done:?}"),
+            Err(err) => break err,
+        }
+    };
+    assert!(matches!(err, Error::CrfNotFound { .. }), "{err}");
+}
@@ -0,0 +1,339 @@
+//! Chunked encoding: split the input at scene changes & encode chunks in parallel.
+use crate::{
+    command::SmallDuration,
+    error::Error,
+    ffmpeg::{self, FfmpegEncodeArgs},
+    ffprobe::Ffprobe,
+    process::{CommandJobEnv, FfmpegOut, program},
+    scheduler,
+    temporary::{self, TempKind},
+};
+use anyhow::ensure;
+use futures_util::{StreamExt, TryStreamExt, stream};
+use indicatif::ProgressBar;
+use std::{
+    cell::RefCell,
+    ffi::OsString,
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::process::Command;
+use tracing::{debug, info};
+
+/// Chunks shorter than this are merged with the next.
+const MIN_CHUNK: Duration = Duration::from_secs(10);
+
+/// Chunked encoding options.
+#[derive(Debug, Clone)]
+pub struct Opts {
+    /// Number of chunks to encode concurrently.
+    pub workers: u16,
+    /// Cuda GPU indices to distribute chunks across.
+    pub gpus: Vec<u8>,
+    /// ffmpeg scdet scene change threshold.
+    pub scene_threshold: f32,
+}
+
+/// Part of the input to encode separately.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Chunk {
+    pub start: Duration,
+    /// `None` means until the end of the input.
+    pub end: Option<Duration>,
+    /// Chunk specific crf, overriding the encode crf.
+    pub crf: Option<f32>,
+}
+
+impl Chunk {
+    /// Chunk duration given the full input `duration`.
+    pub fn duration(&self, duration: Duration) -> Duration {
+        self.end.unwrap_or(duration).saturating_sub(self.start)
+    }
+}
+
+/// Detect scene changes of the input `video_stream` using the ffmpeg scdet filter.
+pub async fn detect_scenes(
+    input: &Path,
+    video_stream: usize,
+    threshold: f32,
+) -> anyhow::Result<Vec<Duration>> {
+    let output = Command::new(program::ffmpeg())
+        .job_env()
+        .kill_on_drop(true)
+        .arg("-i")
+        .arg(input)
+        .arg("-map")
+        .arg(format!("0:v:{video_stream}"))
+        .arg("-vf")
+        .arg(format!("scdet=threshold={threshold}"))
+        .args(["-an", "-sn", "-dn", "-f", "null", "-"])
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .map_err(Error::spawn("ffmpeg scdet"))?;
+    ensure!(
+        output.status.success(),
+        "ffmpeg scdet failed: {}",
+        String::from_utf8_lossy(&output.stderr).trim()
+    );
+    Ok(parse_scdet(&String::from_utf8_lossy(&output.stderr)))
+}
+
+/// Parse scene change times from ffmpeg scdet output lines, e.g.
+/// "[scdet @ 0x5591] lavfi.scd.score: 59.252, lavfi.scd.time: 12.512".
+fn parse_scdet(stderr: &str) -> Vec<Duration> {
+    let mut scenes: Vec<_> = stderr
+        .lines()
+        .filter_map(|line| {
+            let time = &line[line.find("lavfi.scd.time:")? + "lavfi.scd.time:".len()..];
+            let secs: f64 = time.split_whitespace().next()?.parse().ok()?;
+            Some(Duration::from_secs_f64(secs.max(0.0)))
+        })
+        .collect();
+    scenes.sort();
+    scenes
+}
+
+/// Split the input at `scenes` into chunks at least `min` long.
+fn plan_chunks(scenes: &[Duration], duration: Duration, min: Duration) -> Vec<Chunk> {
+    let mut chunks = vec![];
+    let mut start = Duration::ZERO;
+    for &cut in scenes {
+        if cut.saturating_sub(start) >= min && duration.saturating_sub(cut) >= min {
+            chunks.push(Chunk {
+                start,
+                end: Some(cut),
+                crf: None,
+            });
+            start = cut;
+        }
+    }
+    chunks.push(Chunk {
+        start,
+        end: None,
+        crf: None,
+    });
+    chunks
+}
+
+/// Detect scene changes & split the input into chunks.
+pub async fn plan(
+    input: &Path,
+    video_stream: usize,
+    probe: &Ffprobe,
+    scene_threshold: f32,
+) -> anyhow::Result<Vec<Chunk>> {
+    let duration = probe.duration.clone()?;
+    let scenes = detect_scenes(input, video_stream, scene_threshold).await?;
+    let chunks = plan_chunks(&scenes, duration, MIN_CHUNK);
+    info!(
+        "{} chunks, {} scene changes detected",
+        chunks.len(),
+        scenes.len()
+    );
+    Ok(chunks)
+}
+
+/// Encode the video in `chunks`, or chunks split at scene changes if `None`,
+/// concurrently using `opts.workers`.
+///
+/// Returns the path of an ffmpeg concat list of all encoded chunks.
+pub async fn encode_chunks(
+    enc_args: &FfmpegEncodeArgs<'_>,
+    output: &Path,
+    probe: &Ffprobe,
+    chunks: Option<Vec<Chunk>>,
+    opts: &Opts,
+    bar: &ProgressBar,
+) -> anyhow::Result<PathBuf> {
+    let chunks = match chunks {
+        Some(chunks) => chunks,
+        None => {
+            bar.set_message("detecting scenes, ");
+            plan(
+                enc_args.input,
+                enc_args.video_stream,
+                probe,
+                opts.scene_threshold,
+            )
+            .await?
+        }
+    };
+    bar.set_message("encoding, ");
+
+    let dir = temporary::process_dir(None);
+    let name = output.file_stem().unwrap_or_default().to_string_lossy();
+    let files: Vec<_> = (0..chunks.len())
+        .map(|idx| dir.join(format!("{name}.chunk{idx:05}.mkv")))
+        .collect();
+
+    // gpu for each worker slot, taken while a chunk encodes
+    let free_gpus = RefCell::new(match opts.gpus.is_empty() {
+        true => vec![],
+        false => (0..opts.workers.into())
+            .map(|w: usize| opts.gpus[w % opts.gpus.len()])
+            .rev()
+            .collect(),
+    });
+    let chunk_progress = RefCell::new(vec![Duration::ZERO; chunks.len()]);
+
+    stream::iter(chunks.into_iter().zip(&files).enumerate())
+        .map(|(idx, (chunk, file))| {
+            let free_gpus = &free_gpus;
+            let chunk_progress = &chunk_progress;
+            async move {
+                let gpu = free_gpus.borrow_mut().pop();
+                let mut args = match gpu {
+                    Some(gpu) => with_gpu(enc_args, gpu),
+                    None => enc_args.clone(),
+                };
+                if let Some(crf) = chunk.crf {
+                    args.crf = crf;
+                }
+                temporary::add(file, TempKind::NotKeepable);
+                let permit = scheduler::acquire(args.resources()).await;
+                debug!(
+                    "encoding chunk {idx} {chunk:?} crf {} gpu {gpu:?}",
+                    args.crf
+                );
+                let mut enc = ffmpeg::encode_chunk(
+                    args,
+                    chunk.start,
+                    chunk.end.map(|end| end - chunk.start),
+                    file,
+                )?;
+                while let Some(progress) = enc.next().await {
+                    if let FfmpegOut::Progress { time, .. } = progress? {
+                        let mut progress = chunk_progress.borrow_mut();
+                        progress[idx] = time;
+                        let done: Duration = progress.iter().sum();
+                        bar.set_position(done.as_micros_u64());
+                    }
+                }
+                enc.wait().await?;
+                drop(permit);
+                if let Some(gpu) = gpu {
+                    free_gpus.borrow_mut().push(gpu);
+                }
+                anyhow::Ok(())
+            }
+        })
+        .buffer_unordered(opts.workers.max(1).into())
+        .try_collect::<()>()
+        .await?;
+
+    let concat: String = files
+        .iter()
+        .filter_map(|f| f.file_name())
+        // concat demuxer quoting, a `'` is written as `'\''`
+        .map(|f| format!("file '{}'\n", f.to_string_lossy().replace('\'', r"'\''")))
+        .collect();
+    let mut list = OsString::from(dir.join(&*name));
+    list.push(".chunks.txt");
+    let list = PathBuf::from(list);
+    temporary::add(&list, TempKind::NotKeepable);
+    tokio::fs::write(&list, concat).await?;
+    Ok(list)
+}
+
+/// Returns args using cuda `gpu` for nvenc encoding & cuda decoding.
+fn with_gpu<'a>(args: &FfmpegEncodeArgs<'a>, gpu: u8) -> FfmpegEncodeArgs<'a> {
+    let mut args = args.clone();
+    let gpu: Arc<String> = Arc::new(gpu.to_string());
+    if args.vcodec.ends_with("_nvenc") {
+        args.output_args.push(Arc::new("-gpu".into()));
+        args.output_args.push(gpu.clone());
+    }
+    let hwaccel_cuda = args
+        .input_args
+        .windows(2)
+        .any(|w| w[0].as_str() == "-hwaccel" && w[1].as_str() == "cuda");
+    if hwaccel_cuda {
+        args.input_args.push(Arc::new("-hwaccel_device".into()));
+        args.input_args.push(gpu.clone());
+    }
+    if args.input_args.iter().any(|a| a.ends_with("_cuvid")) {
+        args.input_args.push(Arc::new("-gpu".into()));
+        args.input_args.push(gpu);
+    }
+    args
+}
+
+#[test]
+fn parse_scdet_times() {
+    const FFMPEG_OUT: &str = "\
+[scdet @ 0x55d1c8e2c0] lavfi.scd.score: 59.252, lavfi.scd.time: 42.5
+frame=  300 fps=0.0 q=-0.0 size=N/A time=00:00:12.00 bitrate=N/A speed=23.9x
+[scdet @ 0x55d1c8e2c0] lavfi.scd.score: 21.100, lavfi.scd.time: 12.512
+";
+    assert_eq!(
+        parse_scdet(FFMPEG_OUT),
+        [
+            Duration::from_secs_f64(12.512),
+            Duration::from_secs_f64(42.5)
+        ]
+    );
+}
+
+#[test]
+fn plan_chunks_min_length() {
+    let s = Duration::from_secs;
+    let chunks = plan_chunks(&[s(3), s(15), s(20), s(30), s(55)], s(60), s(10));
+    assert_eq!(
+        chunks,
+        [
+            Chunk {
+                start: s(0),
+                end: Some(s(15)),
+                crf: None,
+            },
+            Chunk {
+                start: s(15),
+                end: Some(s(30)),
+                crf: None,
+            },
+            Chunk {
+                start: s(30),
+                end: None,
+                crf: None,
+            },
+        ]
+    );
+}
+
+#[test]
+fn with_gpu_nvenc_cuda() {
+    let args = FfmpegEncodeArgs {
+        input: Path::new("vid.mkv"),
+        video_stream: 0,
+        vcodec: "hevc_nvenc".into(),
+        vfilter: None,
+        pix_fmt: None,
+        crf: 30.0,
+        preset: None,
+        output_args: vec![],
+        input_args: vec![Arc::new("-hwaccel".into()), Arc::new("cuda".into())],
+        video_only: false,
+        subtitles: <_>::default(),
+        maps: vec![],
+        mp4_compat: false,
+        reproducible: false,
+        metadata: <_>::default(),
+    };
+    let args = with_gpu(&args, 1);
+    assert_eq!(
+        args.output_args,
+        [Arc::new("-gpu".into()), Arc::new("1".into())]
+    );
+    assert_eq!(
+        args.input_args,
+        [
+            Arc::new("-hwaccel".into()),
+            Arc::new("cuda".into()),
+            Arc::new("-hwaccel_device".into()),
+            Arc::new("1".into()),
+        ]
+    );
+}
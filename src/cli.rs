@@ -0,0 +1,290 @@
+//! Command line interface, run by the `ab-av1` binary.
+use crate::{
+    command, compat, config,
+    error::{Error, exit_code},
+    log, process, scheduler, sink, temporary,
+};
+use anyhow::anyhow;
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
+use futures_util::FutureExt;
+use std::path::PathBuf;
+
+#[derive(Parser)]
+#[command(version, about)]
+pub(crate) struct Cli {
+    #[command(subcommand)]
+    command: Command,
+
+    #[command(flatten)]
+    jobs: scheduler::Args,
+
+    #[command(flatten)]
+    process: process::job_env::Args,
+
+    #[command(flatten)]
+    compat: compat::Args,
+
+    #[command(flatten)]
+    sink: sink::Args,
+
+    #[command(flatten)]
+    log: log::Args,
+
+    /// Config file of persistent default args.
+    /// Defaults to `~/.config/ab-av1/config.toml` on linux.
+    #[arg(long, global = true, env = config::CONFIG_ENV)]
+    config: Option<PathBuf>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    SampleEncode(command::sample_encode::Args),
+    Vmaf(command::vmaf::Args),
+    Xpsnr(command::xpsnr::Args),
+    Encode(command::encode::Args),
+    CrfSearch(command::crf_search::Args),
+    AutoEncode(command::auto_encode::Args),
+    Image(command::image::Args),
+    Batch(command::batch::Args),
+    Ladder(command::ladder::Args),
+    Candidates(command::candidates::Args),
+    Compare(command::compare::Args),
+    Sweep(command::sweep::Args),
+    Live(command::live::Args),
+    Clean(command::clean::Args),
+    Cache(command::cache::Args),
+    Config(command::config::Args),
+    Doctor(command::doctor::Args),
+    SelfUpdate(command::self_update::Args),
+    PrintCompletions(command::print_completions::Args),
+}
+
+/// Run the cli, exiting the process on error.
+#[tokio::main(flavor = "current_thread")]
+pub async fn main() {
+    let Cli {
+        command: action,
+        jobs,
+        process,
+        compat,
+        sink,
+        log,
+        config,
+    } = parse_cli();
+    if let Err(err) = log::init(log) {
+        eprintln!("Error: {err:#}");
+        std::process::exit(exit_code::USAGE);
+    }
+    scheduler::init(jobs);
+    process::job_env::init(process);
+    process::child::init();
+    compat::init(compat);
+    sink::init(sink);
+    command::sample_encode::cache::init(action.cache());
+    let keep = action.keep_temp_files();
+    temporary::init(action.temp_dir());
+    let config_path = config.or_else(config::default_path);
+
+    let local = tokio::task::LocalSet::new();
+    let command = local.run_until(match action {
+        Command::SampleEncode(args) => command::sample_encode(args).boxed_local(),
+        Command::Vmaf(args) => command::vmaf(args).boxed_local(),
+        Command::Xpsnr(args) => command::xpsnr(args).boxed_local(),
+        Command::Encode(args) => command::encode(args).boxed_local(),
+        Command::CrfSearch(args) => command::crf_search(args).boxed_local(),
+        Command::AutoEncode(args) => command::auto_encode(args).boxed_local(),
+        Command::Image(args) => command::image(args).boxed_local(),
+        Command::Batch(args) => command::batch(args).boxed_local(),
+        Command::Ladder(args) => command::ladder(args).boxed_local(),
+        Command::Candidates(args) => command::candidates(args).boxed_local(),
+        Command::Compare(args) => command::compare(args).boxed_local(),
+        Command::Sweep(args) => command::sweep(args).boxed_local(),
+        Command::Live(args) => command::live(args).boxed_local(),
+        Command::Clean(args) => command::clean(args).boxed_local(),
+        Command::Cache(args) => command::cache(args, config_path).boxed_local(),
+        Command::Config(args) => command::config(args, config_path).boxed_local(),
+        Command::Doctor(args) => command::doctor(args).boxed_local(),
+        Command::SelfUpdate(args) => command::self_update(args).boxed_local(),
+        Command::PrintCompletions(args) => return command::print_completions(args),
+    });
+
+    let (out, interrupted) = tokio::select! {
+        r = command => (r, false),
+        _ = process::child::interrupted() => (Err(anyhow!("ctrl_c")), true),
+    };
+    drop(local);
+
+    if interrupted {
+        process::child::kill();
+        process::child::restore_terminal();
+    }
+    process::child::wait().await;
+
+    // Final cleanup. Samples are already deleted (if wished by the user) during `command::sample_encode::run`.
+    temporary::clean(keep).await;
+    temporary::release_locks();
+
+    if let Err(err) = out {
+        eprintln!("Error: {err}");
+        std::process::exit(match compat::upstream() {
+            true => exit_code::OTHER,
+            false => Error::from(err).exit_code(),
+        });
+    }
+}
+
+/// Parse cli args, using config values as defaults.
+fn parse_cli() -> Cli {
+    let args: Vec<_> = std::env::args_os().collect();
+    let upstream_compat = compat::requested(&args);
+    let cli = command(upstream_compat);
+    let config = config::path(&args).filter(|_| !upstream_compat);
+    let args = match config.map(|p| config::load(&p)) {
+        Some(Ok(config)) => config::apply(&cli, &config, args),
+        Some(Err(err)) => Err(err),
+        None => Ok(args),
+    }
+    .unwrap_or_else(|err| {
+        eprintln!("Error: {err:#}");
+        std::process::exit(exit_code::USAGE);
+    });
+
+    cli.try_get_matches_from(args)
+        .and_then(|matches| Cli::from_arg_matches(&matches))
+        .unwrap_or_else(|err| exit_usage(err, upstream_compat))
+}
+
+/// Print the clap error, e.g. invalid args or --help, & exit with [`exit_code::USAGE`]
+/// if it's an actual error. Uses clap's exit code, like upstream, if `upstream_compat`.
+fn exit_usage(err: clap::Error, upstream_compat: bool) -> ! {
+    if upstream_compat {
+        err.exit();
+    }
+    _ = err.print();
+    std::process::exit(match err.use_stderr() {
+        true => exit_code::USAGE,
+        false => 0,
+    })
+}
+
+/// Returns the cli command, using upstream ab-av1 defaults if `upstream_compat`.
+fn command(upstream_compat: bool) -> clap::Command {
+    let mut cli = Cli::command();
+    // config values are inserted before user args, allow the user args to override them
+    let names: Vec<_> = cli
+        .get_subcommands()
+        .map(|c| c.get_name().to_owned())
+        .collect();
+    for name in names {
+        cli = cli.mut_subcommand(name, |c| match upstream_compat {
+            true => compat::upstream_defaults(c.args_override_self(true)),
+            false => c.args_override_self(true),
+        });
+    }
+    cli
+}
+
+impl Command {
+    /// This decides what commands will keep temp files.
+    ///
+    /// # Important
+    ///
+    /// Add commands using the sample sub-args here referencing the `keep` flag,
+    /// or the temp files will be removed anyways.
+    fn keep_temp_files(&self) -> bool {
+        match self {
+            Self::SampleEncode(args) => args.sample.keep,
+            Self::CrfSearch(args) => args.sample.keep,
+            Self::AutoEncode(args) => args.search.sample.keep,
+            Self::Batch(args) => args.auto_encode.search.sample.keep,
+            Self::Ladder(args) => args.auto_encode.search.sample.keep,
+            Self::Compare(args) => args.search.sample.keep,
+            Self::Sweep(args) => args.sample.keep,
+            _ => false,
+        }
+    }
+
+    /// The --cache setting of commands that sample-encode, otherwise true.
+    fn cache(&self) -> bool {
+        match self {
+            Self::SampleEncode(args) => args.cache,
+            Self::CrfSearch(args) => args.cache,
+            Self::AutoEncode(args) => args.search.cache,
+            Self::Batch(args) => args.auto_encode.search.cache,
+            Self::Ladder(args) => args.auto_encode.search.cache,
+            Self::Compare(args) => args.search.cache,
+            Self::Sweep(args) => args.cache,
+            _ => true,
+        }
+    }
+
+    /// The --temp-dir of commands using the sample sub-args.
+    fn temp_dir(&self) -> Option<PathBuf> {
+        match self {
+            Self::SampleEncode(args) => args.sample.temp_dir.clone(),
+            Self::CrfSearch(args) => args.sample.temp_dir.clone(),
+            Self::AutoEncode(args) => args.search.sample.temp_dir.clone(),
+            Self::Batch(args) => args.auto_encode.search.sample.temp_dir.clone(),
+            Self::Ladder(args) => args.auto_encode.search.sample.temp_dir.clone(),
+            Self::Compare(args) => args.search.sample.temp_dir.clone(),
+            Self::Sweep(args) => args.sample.temp_dir.clone(),
+            Self::Image(args) => args.temp_dir.clone(),
+            _ => None,
+        }
+    }
+}
+
+#[test]
+fn upstream_compat_args() {
+    // upstream ab-av1 usage
+    let matrix = [
+        "crf-search -i vid.mkv --preset 8 --min-vmaf 95",
+        "crf-search -i vid.mkv --max-encoded-percent 60 --thorough",
+        "crf-search -i vid.mkv --samples 4 --sample-duration 10s",
+        "crf-search -i vid.mkv --stdout-format json --cache false",
+        "crf-search -i vid.mkv --min-xpsnr 40",
+        "auto-encode -i vid.mkv --preset 6 -o out.mkv --acodec aac",
+        "auto-encode -i vid.mkv --encoder libx265 --enc x265-params=lossless=0",
+        "auto-encode -i vid.mkv --vfilter scale=1280:-1 --keyint 10s",
+        "auto-encode -i vid.mkv --downmix-to-stereo --video-only --keep",
+        "sample-encode -i vid.mkv --crf 30 --pix-format yuv420p10le",
+        "sample-encode -i vid.mkv --crf 30 --svt tune=0 --scd true",
+        "encode -i vid.mkv --crf 30 --preset 4 --enc-input hwaccel=none",
+        "vmaf --reference vid.mkv --distorted vid.av1.mkv --vmaf n_threads=4",
+        "vmaf --reference vid.mkv --distorted vid.av1.mkv --vmaf-scale none",
+        "vmaf --reference vid.mkv --distorted vid.av1.mkv --vmaf-fps 24",
+        "xpsnr --reference vid.mkv --distorted vid.av1.mkv",
+        "print-completions zsh",
+    ];
+    for args in matrix {
+        let matches = command(true)
+            .try_get_matches_from(
+                ["ab-av1", "--upstream-compat"]
+                    .into_iter()
+                    .chain(args.split_whitespace()),
+            )
+            .unwrap_or_else(|err| panic!("{args}: {err}"));
+        let cli = Cli::from_arg_matches(&matches).unwrap();
+        assert!(cli.compat.upstream_compat);
+        let enc = match &cli.command {
+            Command::CrfSearch(args) => Some(&args.args),
+            Command::AutoEncode(args) => Some(&args.search.args),
+            Command::SampleEncode(args) => Some(&args.args),
+            Command::Encode(args) => Some(&args.args),
+            _ => None,
+        };
+        if let Some(enc) = enc {
+            assert_eq!(enc.deinterlace, command::args::Deinterlace::Off, "{args}");
+            assert_eq!(enc.hw_fallback, command::args::HwFallback::Off, "{args}");
+        }
+    }
+
+    let matches = command(false)
+        .try_get_matches_from(["ab-av1", "encode", "-i", "vid.mkv", "--crf", "30"])
+        .unwrap();
+    let Command::Encode(args) = Cli::from_arg_matches(&matches).unwrap().command else {
+        unreachable!()
+    };
+    assert_eq!(args.args.deinterlace, command::args::Deinterlace::Auto);
+    assert_eq!(args.args.hw_fallback, command::args::HwFallback::On);
+}
@@ -1,24 +1,48 @@
 pub mod args;
 pub mod auto_encode;
+pub mod batch;
+pub mod cache;
+pub mod candidates;
+pub mod clean;
+pub mod compare;
+pub mod config;
 pub mod crf_search;
+pub mod doctor;
 pub mod encode;
+pub mod image;
+pub mod ladder;
+pub mod live;
 pub mod print_completions;
 pub mod sample_encode;
+pub mod self_update;
+pub mod sweep;
 pub mod vmaf;
 pub mod xpsnr;
 
 pub use auto_encode::auto_encode;
+pub use batch::batch;
+pub use cache::cache;
+pub use candidates::candidates;
+pub use clean::clean;
+pub use compare::compare;
+pub use config::config;
 pub use crf_search::crf_search;
+pub use doctor::doctor;
 pub use encode::encode;
+pub use image::image;
+pub use ladder::ladder;
+pub use live::live;
 pub use print_completions::print_completions;
 pub use sample_encode::sample_encode;
+pub use self_update::self_update;
+pub use sweep::sweep;
 pub use vmaf::vmaf;
 pub use xpsnr::xpsnr;
 
 const PROGRESS_CHARS: &str = "##-";
 
 /// Helper trait for durations under 584942 years or so.
-trait SmallDuration {
+pub trait SmallDuration {
     /// Returns the total number of whole microseconds.
     fn as_micros_u64(&self) -> u64;
 }
@@ -5,20 +5,39 @@ mod vmaf;
 pub use encode::*;
 pub use vmaf::*;
 
-use crate::{command::encode::default_output_ext, ffprobe::Ffprobe};
+use crate::{
+    chunk::Chunk,
+    command::encode::default_output_ext,
+    compat, ffmpeg,
+    ffprobe::{self, Ffprobe},
+};
 use clap::{Parser, ValueHint};
 use std::{
+    fmt,
     path::{Path, PathBuf},
     sync::Arc,
     time::Duration,
 };
 
+/// Minimum frames VMAF should score across all samples, below which scores are noisy.
+pub const MIN_SCORED_FRAMES: u64 = 240;
+
+/// Minimum frames VMAF should score when `n_subsample` is set automatically, i.e.
+/// samples totalling at least twice this many frames are subsampled.
+pub const AUTO_SUBSAMPLE_FRAMES: u64 = 2400;
+
+/// Maximum automatically set VMAF `n_subsample`.
+const MAX_AUTO_SUBSAMPLE: u32 = 4;
+
 /// Encoding args that apply when encoding to an output.
 #[derive(Parser, Clone)]
 pub struct EncodeToOutput {
     /// Output file, by default the same as input with `.av1` before the extension.
     ///
     /// E.g. if unspecified: -i vid.mkv --> vid.av1.mkv
+    ///
+    /// The container must support the encoder, e.g. av1 in .mkv, .mp4 or .webm.
+    /// Raw single stream outputs, e.g. .265, require --video-only unless the input is an image.
     #[arg(short, long, value_hint = ValueHint::FilePath)]
     pub output: Option<PathBuf>,
 
@@ -41,6 +60,214 @@ pub struct EncodeToOutput {
     /// The output will be a single video stream.
     #[arg(long)]
     pub video_only: bool,
+
+    /// Don't include subtitle streams in the output.
+    ///
+    /// By default all subtitle streams, chapters & attachments are carried over.
+    #[arg(long, conflicts_with = "sub_langs")]
+    pub no_subs: bool,
+
+    /// Only include subtitle streams with these languages in the output.
+    /// E.g. `--sub-langs eng,jpn`.
+    #[arg(long, value_delimiter = ',')]
+    pub sub_langs: Vec<Arc<str>>,
+
+    /// Select the input streams to include in the output, as ffmpeg `-map` values.
+    /// E.g. `--map 0:a:1 --map 0:s:m:language:eng`.
+    ///
+    /// The main video stream is always included. Otherwise the maps replace the default
+    /// stream selection, unless all are negative, e.g. `--map -0:a:1`, which remove
+    /// streams from the default selection.
+    ///
+    /// Maps are checked against the input streams, so typos fail before encoding.
+    #[arg(long, allow_hyphen_values = true, conflicts_with = "video_only")]
+    pub map: Vec<Arc<str>>,
+
+    /// Favour playback compatibility with hardware (e.g. TV) mp4 players.
+    ///
+    /// For .mp4 outputs sets the standard video codec tag (av01, hvc1, avc1),
+    /// converts subtitles to mov_text, drops data streams & defaults re-encoded
+    /// audio to aac. Warns about features common players may not support.
+    #[arg(long)]
+    pub mp4_compat: bool,
+
+    /// Strip volatile output metadata, e.g. muxer/encoder version tags & dates, so
+    /// re-running the same command produces an identical output.
+    ///
+    /// The env var `SOURCE_DATE_EPOCH`, unix seconds, sets the output creation time.
+    #[arg(long)]
+    pub reproducible: bool,
+
+    /// Output metadata handling. "copy", the default, carries over the input global &
+    /// stream tags, e.g. title & language. "strip" removes them, e.g. for privacy.
+    /// `KEY=VALUE` sets a global tag & may be repeated,
+    /// e.g. `--metadata strip --metadata title="My Video"`.
+    ///
+    /// An ENCODER_SETTINGS tag with the equivalent ab-av1 encode command is also set,
+    /// unless using --upstream-compat.
+    #[arg(long)]
+    pub metadata: Vec<MetadataArg>,
+
+    /// After a successful encode write a provenance sidecar next to the output,
+    /// e.g. "vid.av1.mkv.ab-av1.json", with the command args, ffmpeg & encoder versions,
+    /// crf, timings &, for auto-encode, the predicted & any --verify-vmaf score.
+    #[arg(long)]
+    pub provenance: bool,
+
+    /// Two-pass encode, running a first analysis pass before the final encode.
+    /// Supported by libvpx-vp9, libx264, libx265, libaom-av1 & *_nvenc, which use
+    /// `-multipass fullres` within a single encode.
+    ///
+    /// Not supported with --resume, --chunked or --also-encode.
+    #[arg(long)]
+    pub two_pass: bool,
+
+    /// Encode video in segments, writing a checkpoint file next to the output, so an
+    /// interrupted encode continues from the last completed segment when the same
+    /// command is run again.
+    #[arg(long, conflicts_with = "chunked")]
+    pub resume: bool,
+
+    /// Split the input at scene changes & encode the chunks in parallel, then
+    /// losslessly concatenate them.
+    #[arg(long)]
+    pub chunked: bool,
+
+    /// Number of chunks to encode concurrently with --chunked.
+    #[arg(long, default_value_t = 2, value_parser = clap::value_parser!(u16).range(1..))]
+    pub workers: u16,
+
+    /// Cuda GPU indices to distribute --chunked workers across, e.g. `--gpus 0,1`.
+    /// Used for *_nvenc encoding & cuda decoding.
+    ///
+    /// Note: --max-gpu-jobs should also be increased to run multiple GPU jobs concurrently.
+    #[arg(long, value_delimiter = ',')]
+    pub gpus: Vec<u8>,
+
+    /// ffmpeg scdet scene change threshold (0-100) used to split --chunked encodes.
+    /// Lower values detect more scene changes.
+    #[arg(long, default_value_t = 10.0)]
+    pub scene_threshold: f32,
+
+    /// Also encode a secondary compatibility output in the same run, decoding & filtering
+    /// the input only once. E.g. `--also-encode h264:crf=20`.
+    ///
+    /// Format is `VCODEC[:crf=CRF][:preset=PRESET][:output=FILE]`. VCODEC may be an
+    /// ffmpeg encoder or "h264", "h265" or "av1". The output defaults to the input name
+    /// with e.g. ".x264.mp4", using yuv420p & aac audio.
+    ///
+    /// Not supported with --resume or --chunked.
+    #[arg(long)]
+    pub also_encode: Option<AlsoEncode>,
+
+    /// Write the final ffmpeg encode command, including the crf & all filters, to a
+    /// standalone commented shell script instead of encoding. E.g. to run the encode
+    /// later on a different machine or under a scheduler.
+    ///
+    /// Not supported with --resume, --chunked, --two-pass or stdin input.
+    #[arg(long, value_hint = ValueHint::FilePath, conflicts_with_all = ["resume", "chunked", "two_pass"])]
+    pub emit_script: Option<PathBuf>,
+
+    /// Pre-planned --chunked chunks, e.g. with per-scene crf values.
+    #[arg(skip)]
+    pub chunks: Option<Vec<Chunk>>,
+}
+
+/// A secondary compatibility encode, e.g. "h264:crf=20".
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlsoEncode {
+    pub vcodec: Arc<str>,
+    pub crf: Option<f32>,
+    pub preset: Option<Arc<str>>,
+    pub output: Option<PathBuf>,
+}
+
+impl AlsoEncode {
+    /// Output path, by default the input name with e.g. ".x264.mp4".
+    pub fn output(&self, input: &Path) -> PathBuf {
+        match &self.output {
+            Some(output) => output.clone(),
+            None => {
+                let pre = ffmpeg::pre_extension_name(&self.vcodec);
+                ffprobe::local_name(input).with_extension(format!("{pre}.mp4"))
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for AlsoEncode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let mut parts = s.split(':');
+        let vcodec = match parts.next().unwrap_or_default().trim() {
+            "" => anyhow::bail!("missing vcodec"),
+            "h264" | "x264" => "libx264",
+            "h265" | "hevc" | "x265" => "libx265",
+            "av1" | "svt-av1" => "libsvtav1",
+            vcodec => vcodec,
+        };
+        let mut also = Self {
+            vcodec: vcodec.into(),
+            crf: None,
+            preset: None,
+            output: None,
+        };
+        for part in parts {
+            match part.split_once('=') {
+                Some(("crf", crf)) => also.crf = Some(crf.parse()?),
+                Some(("preset", preset)) => also.preset = Some(preset.into()),
+                Some(("output", output)) => also.output = Some(output.into()),
+                _ => anyhow::bail!("invalid option `{part}`, expected crf=, preset= or output="),
+            }
+        }
+        Ok(also)
+    }
+}
+
+/// A `--metadata` value, "copy", "strip" or a "KEY=VALUE" tag.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MetadataArg {
+    Copy,
+    Strip,
+    Tag(Arc<str>, Arc<str>),
+}
+
+impl MetadataArg {
+    /// Returns the output metadata of `args`, the last of "copy" or "strip" applying.
+    pub fn to_output_metadata(args: &[Self]) -> ffmpeg::OutputMetadata {
+        let strip = args.iter().rev().find_map(|arg| match arg {
+            Self::Copy => Some(false),
+            Self::Strip => Some(true),
+            Self::Tag(..) => None,
+        });
+        let tags = args
+            .iter()
+            .filter_map(|arg| match arg {
+                Self::Tag(k, v) => Some((k.clone(), v.clone())),
+                _ => None,
+            })
+            .collect();
+        ffmpeg::OutputMetadata {
+            strip: strip.unwrap_or_default(),
+            tags,
+        }
+    }
+}
+
+impl std::str::FromStr for MetadataArg {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        Ok(match s.trim() {
+            "copy" => Self::Copy,
+            "strip" => Self::Strip,
+            tag => match tag.split_once('=') {
+                Some((k, v)) if !k.trim().is_empty() => Self::Tag(k.trim().into(), v.into()),
+                _ => anyhow::bail!("expected copy, strip or KEY=VALUE"),
+            },
+        })
+    }
 }
 
 /// Sampling arguments.
@@ -55,9 +282,13 @@ pub struct Sample {
     /// So "12m" would mean with an input 25-36 minutes long, 3 samples would be used.
     /// More samples take longer but may provide a more accurate result.
     ///
+    /// By default 12m for inputs up to 1 hour, increasing with the square root of
+    /// longer durations, e.g. 17m for 2 hours, so long inputs use fewer samples than a
+    /// fixed spacing would. Always 12m with --upstream-compat.
+    ///
     /// Setting --samples overrides this value.
-    #[arg(long, default_value = "12m", value_parser = humantime::parse_duration)]
-    pub sample_every: Duration,
+    #[arg(long, value_parser = humantime::parse_duration)]
+    pub sample_every: Option<Duration>,
 
     /// Minimum number of samples. So at least this many samples will be used.
     #[arg(long)]
@@ -67,12 +298,36 @@ pub struct Sample {
     #[arg(long, default_value = "20s", value_parser = humantime::parse_duration)]
     pub sample_duration: Duration,
 
+    /// Start each sample at the next keyframe after its evenly spaced position, found
+    /// with a fast keyframe only ffprobe. Faster, as stream copied samples otherwise
+    /// include frames from the preceding keyframe which are decoded then discarded.
+    #[arg(long)]
+    pub samples_from_keyframes: bool,
+
+    /// How sample positions are chosen.
+    ///
+    /// complexity: Pick samples spanning the bitrate distribution of the input, from
+    /// low to high complexity, instead of evenly spaced positions. Reduces prediction
+    /// error on mixed content. Falls back to even for short inputs.
+    #[arg(long, value_enum, default_value_t = SampleSelection::Even)]
+    pub sample_selection: SampleSelection,
+
+    /// Number of samples to encode & score concurrently. Limited to the number of
+    /// CPU cores, and by --max-gpu-jobs, --max-cpu-jobs & NVENC session limits.
+    ///
+    /// Speeds up multi-sample crf-searches when a single sample encode doesn't
+    /// fully use the CPU or GPU.
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..))]
+    pub parallel_samples: u16,
+
     /// Keep temporary files after exiting.
     #[arg(long)]
     pub keep: bool,
 
     /// Directory to store temporary sample data in.
     /// Defaults to using the input's directory.
+    ///
+    /// Temp dirs left by crashed runs are removed when a new temp dir is created.
     #[arg(long, env = "AB_AV1_TEMP_DIR", value_hint = ValueHint::DirPath)]
     pub temp_dir: Option<PathBuf>,
 
@@ -82,21 +337,97 @@ pub struct Sample {
 }
 
 impl Sample {
+    /// Samples to encode & score concurrently of `samples`, see --parallel-samples.
+    pub fn sample_jobs(&self, samples: u64) -> usize {
+        let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+        usize::from(self.parallel_samples)
+            .min(samples.try_into().unwrap_or(usize::MAX))
+            .min(cores)
+            .max(1)
+    }
+
     /// Calculate the desired sample count using `samples` or `sample_every` & `min_samples`.
     pub fn sample_count(&self, input_duration: Duration) -> u64 {
         match self.samples {
             Some(s) => s,
             None => {
-                (input_duration.as_secs_f64() / self.sample_every.as_secs_f64().max(1.0)).ceil()
-                    as _
+                let every = self.sample_every(input_duration);
+                (input_duration.as_secs_f64() / every.as_secs_f64().max(1.0)).ceil() as _
             }
         }
         .max(self.min_samples.unwrap_or(1))
         .max(1)
     }
 
+    /// Returns --sample-every, or the default scaled by `input_duration`.
+    /// The default is a fixed 12m, like upstream, with --upstream-compat.
+    pub fn sample_every(&self, input_duration: Duration) -> Duration {
+        const DEFAULT: Duration = Duration::from_secs(12 * 60);
+        const HOUR: f64 = 3600.0;
+        self.sample_every.unwrap_or_else(|| {
+            let hours = input_duration.as_secs_f64() / HOUR;
+            match hours > 1.0 && !compat::upstream() {
+                true => DEFAULT.mul_f64(hours.sqrt()),
+                false => DEFAULT,
+            }
+        })
+    }
+
+    /// Returns a VMAF `n_subsample` for long inputs whose samples total over
+    /// [`AUTO_SUBSAMPLE_FRAMES`] frames, so at least that many are still scored.
+    ///
+    /// `None` if `--vmaf n_subsample=` is set, every frame should be scored or
+    /// with --upstream-compat.
+    pub fn auto_n_subsample(&self, vmaf: &Vmaf, probe: &Ffprobe) -> Option<u32> {
+        if vmaf.n_subsample_arg().is_some()
+            || probe.is_image
+            || self.sample_duration.is_zero()
+            || compat::upstream()
+        {
+            return None;
+        }
+        let duration = probe.duration.as_ref().ok()?;
+        let fps = vmaf
+            .fps()
+            .map(f64::from)
+            .or_else(|| probe.fps.as_ref().ok().copied())
+            .filter(|fps| *fps > 0.0)?;
+
+        let samples = self.sample_count(*duration) as f64;
+        let frames = samples * self.sample_duration.as_secs_f64() * fps;
+        let n = ((frames / AUTO_SUBSAMPLE_FRAMES as f64) as u32).min(MAX_AUTO_SUBSAMPLE);
+        (n > 1).then_some(n)
+    }
+
+    /// Lengthen --sample-duration, if needed, so VMAF scores at least [`MIN_SCORED_FRAMES`]
+    /// across all samples when only every `n_subsample`th frame is scored.
+    ///
+    /// Returns the original sample duration if lengthened.
+    pub fn lengthen_for_subsample(&mut self, vmaf: &Vmaf, probe: &Ffprobe) -> Option<Duration> {
+        let n_subsample = vmaf.n_subsample();
+        let duration = probe.duration.as_ref().ok()?;
+        let fps = vmaf
+            .fps()
+            .map(f64::from)
+            .or_else(|| probe.fps.as_ref().ok().copied())
+            .filter(|fps| *fps > 0.0)?;
+        if n_subsample <= 1 || probe.is_image || self.sample_duration.is_zero() {
+            return None;
+        }
+
+        let samples = self.sample_count(*duration) as f64;
+        let scored = samples * self.sample_duration.as_secs_f64() * fps / n_subsample as f64;
+        if scored >= MIN_SCORED_FRAMES as f64 {
+            return None;
+        }
+        let original = self.sample_duration;
+        // samples covering most of the input become a full pass
+        self.sample_duration = original.mul_f64(MIN_SCORED_FRAMES as f64 / scored);
+        Some(original)
+    }
+
     pub fn set_extension_from_input(&mut self, input: &Path, encoder: &Encoder, probe: &Ffprobe) {
-        self.extension = Some(default_output_ext(input, encoder, probe.is_image).into());
+        self.extension = Some(default_output_ext(input, encoder, probe.media_kind()).into());
     }
 
     pub fn set_extension_from_output(&mut self, output: &Path) {
@@ -115,6 +446,14 @@ pub struct ScoreArgs {
     pub reference_vfilter: Option<Arc<str>>,
 }
 
+impl ScoreArgs {
+    /// Returns the reference vfilter to use, `--reference-vfilter` if set
+    /// otherwise the encode's own `vfilter`.
+    pub fn reference_vfilter_or<'a>(&'a self, vfilter: Option<&'a str>) -> Option<&'a str> {
+        self.reference_vfilter.as_deref().or(vfilter)
+    }
+}
+
 /// Common xpsnr options.
 #[derive(Debug, Parser, Clone, Copy)]
 pub struct Xpsnr {
@@ -137,3 +476,280 @@ impl std::hash::Hash for Xpsnr {
         self.xpsnr_fps.to_ne_bytes().hash(state);
     }
 }
+
+/// External metric plugin options.
+#[derive(Debug, Parser, Clone, Hash, Default)]
+pub struct MetricCmd {
+    /// External metric command used to score samples instead of VMAF.
+    /// E.g. --metric-cmd "my-metric --model v2".
+    ///
+    /// Run with the reference & distorted paths appended, or substituted
+    /// for `{ref}` & `{dist}` if present. Must print json `{"score": N}` or
+    /// per-frame scores `{"frames": [N, ...]}`, pooled using --vmaf-pool, to stdout.
+    /// Higher scores must mean better quality.
+    ///
+    /// The reference vfilter, if any, is set in the env var `AB_AV1_REFERENCE_VFILTER`.
+    #[arg(long)]
+    pub metric_cmd: Option<Arc<str>>,
+}
+
+/// Parse a size in bytes, e.g. "4GB", "700MiB", "1.5G", "123456".
+///
+/// Decimal (KB, MB, GB, TB or K, M, G, T) & binary (KiB, MiB, GiB, TiB) units are supported.
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    let (num, unit) = s.split_at(split);
+    let num: f64 = num.parse().map_err(|_| format!("invalid size `{s}`"))?;
+    let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" => 1000,
+        "m" | "mb" => 1000_u64.pow(2),
+        "g" | "gb" => 1000_u64.pow(3),
+        "t" | "tb" => 1000_u64.pow(4),
+        "kib" => 1024,
+        "mib" => 1024_u64.pow(2),
+        "gib" => 1024_u64.pow(3),
+        "tib" => 1024_u64.pow(4),
+        _ => return Err(format!("invalid size unit `{unit}`")),
+    };
+    Ok((num * multiplier as f64).round() as u64)
+}
+
+/// Parse a percentage, e.g. "40%" or "40".
+pub fn parse_percent(s: &str) -> Result<f32, String> {
+    let percent: f32 = s
+        .trim()
+        .trim_end_matches('%')
+        .parse()
+        .map_err(|_| format!("invalid percentage `{s}`"))?;
+    match percent > 0.0 {
+        true => Ok(percent),
+        false => Err("percentage must be greater than 0".into()),
+    }
+}
+
+/// --sample-selection mode.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[clap(rename_all = "kebab-case")]
+pub enum SampleSelection {
+    /// Evenly spaced samples.
+    #[default]
+    Even,
+    /// Samples spanning the input bitrate distribution.
+    Complexity,
+}
+
+impl fmt::Display for SampleSelection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Even => "even",
+            Self::Complexity => "complexity",
+        })
+    }
+}
+
+#[test]
+fn parse_also_encode() {
+    let also: AlsoEncode = "h264:crf=20:preset=fast".parse().unwrap();
+    assert_eq!(
+        also,
+        AlsoEncode {
+            vcodec: "libx264".into(),
+            crf: Some(20.0),
+            preset: Some("fast".into()),
+            output: None,
+        }
+    );
+    assert_eq!(
+        also.output(Path::new("dir/vid.mkv")),
+        Path::new("dir/vid.x264.mp4")
+    );
+
+    let also: AlsoEncode = "h264_nvenc:output=compat.mp4".parse().unwrap();
+    assert_eq!(&*also.vcodec, "h264_nvenc");
+    assert_eq!(also.output(Path::new("vid.mkv")), Path::new("compat.mp4"));
+    assert!("h264:crf=twenty".parse::<AlsoEncode>().is_err());
+    assert!("h264:size=1".parse::<AlsoEncode>().is_err());
+}
+
+#[test]
+fn parse_metadata_args() {
+    let args: Vec<MetadataArg> = ["strip", "title=My Video", "copy", "comment=a=b"]
+        .iter()
+        .map(|a| a.parse().unwrap())
+        .collect();
+    assert_eq!(args[1], MetadataArg::Tag("title".into(), "My Video".into()));
+    let metadata = MetadataArg::to_output_metadata(&args);
+    assert!(!metadata.strip);
+    assert_eq!(
+        metadata.tags,
+        [
+            ("title".into(), "My Video".into()),
+            ("comment".into(), "a=b".into())
+        ]
+    );
+    assert!(MetadataArg::to_output_metadata(&args[..2]).strip);
+    assert!("=x".parse::<MetadataArg>().is_err());
+    assert!("keep".parse::<MetadataArg>().is_err());
+}
+
+#[test]
+fn parse_sizes() {
+    assert_eq!(parse_size("4GB"), Ok(4_000_000_000));
+    assert_eq!(parse_size("1.5G"), Ok(1_500_000_000));
+    assert_eq!(parse_size("700MiB"), Ok(700 * 1024 * 1024));
+    assert_eq!(parse_size("123456"), Ok(123456));
+    assert!(parse_size("4 parsecs").is_err());
+    assert_eq!(parse_percent("40%"), Ok(40.0));
+    assert_eq!(parse_percent("12.5"), Ok(12.5));
+    assert!(parse_percent("0%").is_err());
+}
+
+#[test]
+fn lengthen_subsampled_samples() {
+    let probe = Ffprobe {
+        duration: Ok(Duration::from_secs(600)),
+        has_audio: false,
+        max_audio_channels: None,
+        fps: Ok(24.0),
+        resolution: Some((1920, 1080)),
+        is_image: false,
+        animation: None,
+        trimmed_from: None,
+        pix_fmt: None,
+        field_order: None,
+        size: None,
+        streams: vec![],
+    };
+    let mut sample = Sample::parse_from(["x"]);
+    let mut vmaf = Vmaf::parse_from(["x", "--vmaf", "n_subsample=4"]);
+    assert_eq!(vmaf.n_subsample(), 4);
+
+    // 1 sample of 20s at the default 25 --vmaf-fps scoring every 4th frame: 125 frames
+    assert_eq!(
+        sample.lengthen_for_subsample(&vmaf, &probe),
+        Some(Duration::from_secs(20))
+    );
+    assert_eq!(sample.sample_duration, Duration::from_secs_f64(38.4));
+
+    let mut sample = Sample::parse_from(["x"]);
+    vmaf.vmaf_args = vec!["n_threads=8:n_subsample=2".into()];
+    assert_eq!(vmaf.n_subsample(), 2);
+    assert_eq!(sample.lengthen_for_subsample(&vmaf, &probe), None);
+    assert_eq!(sample.sample_duration, Duration::from_secs(20));
+}
+
+#[test]
+fn auto_n_subsample() {
+    let hours = |h: u64| Ffprobe {
+        duration: Ok(Duration::from_secs(h * 3600)),
+        has_audio: false,
+        max_audio_channels: None,
+        fps: Ok(24.0),
+        resolution: Some((1920, 1080)),
+        is_image: false,
+        animation: None,
+        trimmed_from: None,
+        pix_fmt: None,
+        field_order: None,
+        size: None,
+        streams: vec![],
+    };
+    let sample = Sample::parse_from(["x"]);
+    let vmaf = Vmaf::parse_from(["x"]);
+    // 5 samples of 20s at the default 25 --vmaf-fps, rather than the 24fps input: 2500 frames
+    assert_eq!(sample.auto_n_subsample(&vmaf, &hours(1)), None);
+    // 10 samples: 5000 frames
+    assert_eq!(sample.auto_n_subsample(&vmaf, &hours(4)), Some(2));
+    // 10 samples at the 24fps input: 4800 frames
+    let input_fps = Vmaf::parse_from(["x", "--vmaf-fps", "0"]);
+    assert_eq!(sample.auto_n_subsample(&input_fps, &hours(4)), Some(2));
+    assert_eq!(
+        Sample::parse_from(["x", "--sample-duration", "2m"]).auto_n_subsample(&vmaf, &hours(4)),
+        Some(MAX_AUTO_SUBSAMPLE)
+    );
+
+    let vmaf = Vmaf::parse_from(["x", "--vmaf", "n_subsample=1"]);
+    assert_eq!(sample.auto_n_subsample(&vmaf, &hours(4)), None);
+}
+
+#[test]
+fn default_sample_count() {
+    let sample = Sample::parse_from(["x"]);
+    let minutes = |m: u64| Duration::from_secs(m * 60);
+    assert_eq!(sample.sample_count(minutes(5)), 1);
+    assert_eq!(sample.sample_count(minutes(30)), 3);
+    assert_eq!(sample.sample_count(minutes(60)), 5);
+    // every ~17m
+    assert_eq!(sample.sample_count(minutes(120)), 8);
+    // every 24m
+    assert_eq!(sample.sample_count(minutes(240)), 10);
+
+    let sample = Sample::parse_from(["x", "--sample-every", "12m"]);
+    assert_eq!(sample.sample_count(minutes(240)), 20);
+    let sample = Sample::parse_from(["x", "--samples", "2", "--min-samples", "3"]);
+    assert_eq!(sample.sample_count(minutes(240)), 3);
+}
+
+#[test]
+fn upstream_compat_sampling() {
+    let hours = |h: u64| Ffprobe {
+        duration: Ok(Duration::from_secs(h * 3600)),
+        has_audio: false,
+        max_audio_channels: None,
+        fps: Ok(24.0),
+        resolution: Some((1920, 1080)),
+        is_image: false,
+        animation: None,
+        trimmed_from: None,
+        pix_fmt: None,
+        field_order: None,
+        size: None,
+        streams: vec![],
+    };
+    let sample = Sample::parse_from(["x"]);
+    let vmaf = Vmaf::parse_from(["x"]);
+    assert_eq!(sample.sample_count(Duration::from_secs(4 * 3600)), 10);
+    assert_eq!(sample.auto_n_subsample(&vmaf, &hours(4)), Some(2));
+
+    compat::with_upstream(|| {
+        // upstream samples every 12m & scores every frame
+        assert_eq!(
+            sample.sample_every(Duration::from_secs(4 * 3600)),
+            Duration::from_secs(12 * 60)
+        );
+        assert_eq!(sample.sample_count(Duration::from_secs(4 * 3600)), 20);
+        assert_eq!(sample.auto_n_subsample(&vmaf, &hours(4)), None);
+    });
+}
+
+#[test]
+fn parallel_sample_jobs() {
+    let sample = Sample::parse_from(["x"]);
+    assert_eq!(sample.sample_jobs(5), 1);
+
+    let sample = Sample::parse_from(["x", "--parallel-samples", "4"]);
+    let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+    assert_eq!(sample.sample_jobs(5), cores.min(4));
+    assert_eq!(sample.sample_jobs(1), 1, "limited to sample count");
+    assert!(Sample::try_parse_from(["x", "--parallel-samples", "0"]).is_err());
+}
+
+#[test]
+fn reference_vfilter_overrides_vfilter() {
+    let score = ScoreArgs::parse_from(["x"]);
+    assert_eq!(
+        score.reference_vfilter_or(Some("scale=1280:-1")),
+        Some("scale=1280:-1")
+    );
+    assert_eq!(score.reference_vfilter_or(None), None);
+
+    let score = ScoreArgs::parse_from(["x", "--reference-vfilter", "scale=1280:-1,fps=24"]);
+    assert_eq!(
+        score.reference_vfilter_or(Some("scale=1280:-1")),
+        Some("scale=1280:-1,fps=24")
+    );
+}
@@ -1,19 +1,26 @@
-use anyhow::Context;
-use std::process::Command;
 use crate::{
-    ffmpeg::FfmpegEncodeArgs,
-    ffprobe::{Ffprobe, ProbeError},
+    crop,
+    cuda::caps,
+    error::Error,
+    ffmpeg::{self, FfmpegEncodeArgs},
+    ffprobe::{self, Ffprobe, ProbeError},
     float::TerseF32,
+    grain,
+    process::{CommandJobEnv, program},
+    scheduler, stdin, telecine,
 };
-use anyhow::ensure;
+use anyhow::{Context, ensure};
 use clap::{Parser, ValueHint};
 use std::{
     collections::HashMap,
+    env,
     fmt::{self, Write},
-    path::PathBuf,
-    sync::Arc,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::{Arc, LazyLock},
     time::Duration,
 };
+use tracing::debug;
 
 /// Common svt-av1/ffmpeg input encoding arguments.
 #[derive(Parser, Clone)]
@@ -28,6 +35,33 @@ pub struct Encode {
     #[arg(short, long, value_hint = ValueHint::FilePath)]
     pub input: PathBuf,
 
+    /// Input video stream to encode & score, e.g. `--video-stream 1` for the second
+    /// video stream ("0:v:1"). Default 0.
+    ///
+    /// Useful for inputs with multiple video streams, e.g. alternative angles.
+    /// When set other input video streams are not included in the output.
+    #[arg(long)]
+    pub video_stream: Option<usize>,
+
+    /// Encode from this position of the input, e.g. "1m30s". Maps to ffmpeg input `-ss`.
+    ///
+    /// Also applies to crf-search samples & the VMAF reference, so a portion of a long
+    /// input can be searched & encoded.
+    #[arg(long, value_parser = humantime::parse_duration)]
+    pub start: Option<Duration>,
+
+    /// Encode only this duration of the input, after any --start, e.g. "10m".
+    /// Maps to ffmpeg input `-t`.
+    #[arg(long, value_parser = humantime::parse_duration)]
+    pub duration: Option<Duration>,
+
+    /// Config profile to use, e.g. `--profile anime` uses values from `[profile.anime]`
+    /// in the config file. Args specified on the command line override profile values.
+    ///
+    /// A default profile may be configured with `profile = "anime"`.
+    #[arg(long)]
+    pub profile: Option<String>,
+
     /// Ffmpeg video filter applied to the input before encoding.
     /// E.g. --vfilter "scale=1280:-1,fps=24".
     ///
@@ -43,10 +77,76 @@ pub struct Encode {
     /// A consequence is the VMAF score will not reflect any quality lost
     /// by the vfilter itself, only the encode.
     /// To override the VMAF vfilter set --reference-vfilter.
+    ///
+    /// With *_vulkan encoders frames stay in Vulkan memory where possible, "scale=W:H" is
+    /// converted to scale_vulkan & "crop=W:H[:X:Y]" to libplacebo. Other filters are
+    /// applied in software between hwdownload & hwupload.
     #[arg(long)]
     pub vfilter: Option<String>,
 
+    /// Burn subtitles into the video, after --vfilter. Also applied to the VMAF reference
+    /// so scores remain comparable.
+    ///
+    /// * auto: The input's first text subtitle stream.
+    /// * INDEX: The input's nth subtitle stream, from 0, e.g. --burn-subs 1.
+    /// * FILE: An external subtitle file, e.g. --burn-subs subs.srt.
+    ///
+    /// Uses the software subtitles filter, which supports text subtitles, e.g. srt & ass,
+    /// but not image subtitles like PGS.
+    /// Samples show the subtitles timed from the sample start.
+    #[arg(long, value_hint = ValueHint::FilePath)]
+    pub burn_subs: Option<BurnSubs>,
+
+    /// Inverse telecine, restoring the original progressive frames of 3:2 pulldown
+    /// sources, e.g. 29.97fps DVD film content -> 23.976fps.
+    ///
+    /// Applies "fieldmatch,yadif=deint=interlaced,decimate" before any other filters,
+    /// also to the VMAF reference. Keyint durations use the decimated frame rate.
+    ///
+    /// auto: Detect telecine using ffmpeg idet statistics.
+    #[arg(long, value_enum, default_value_t = Detelecine::Off)]
+    pub detelecine: Detelecine,
+
+    /// Deinterlace using bwdif, or yadif_cuda when using --cuda-decoder.
+    /// Also applied to the VMAF reference.
+    ///
+    /// auto: Deinterlace if ffprobe reports an interlaced field order.
+    /// Not used with --detelecine, which deinterlaces itself.
+    #[arg(long, value_enum, default_value_t = Deinterlace::Auto)]
+    pub deinterlace: Deinterlace,
+
+    /// Measure source noise & if noisy pair a mild hqdn3d denoise with svt-av1
+    /// film-grain synthesis of a matched strength (4-16). Improves compression of grainy sources.
+    ///
+    /// Noise is measured as the PSNR-Y of the source vs a denoised copy.
+    /// PSNR-Y <34 -> film-grain=16, 34-38 -> 12, 38-42 -> 8, 42-45 -> 4, >=45 clean.
+    ///
+    /// The denoise is also applied to the VMAF reference.
+    #[arg(long)]
+    pub auto_grain: bool,
+
+    /// Svt-av1 film-grain synthesis level (0-50), or "auto" to estimate a level
+    /// from the source noise.
+    ///
+    /// auto: Measures the PSNR-Y of source samples vs denoised copies & maps it like
+    /// --auto-grain, but leaves denoising to svt-av1. Clean sources use no film-grain.
+    #[arg(long, conflicts_with = "auto_grain")]
+    pub film_grain: Option<FilmGrain>,
+
+    /// Denoise the input before encoding.
+    ///
+    /// Uses hqdn3d (light, medium) or nlmeans (heavy). When using --cuda-decoder or
+    /// --cuda-filters the GPU bilateral_cuda filter is used instead.
+    ///
+    /// Like --vfilter the denoise is also applied to the VMAF reference, using the
+    /// software filter.
+    #[arg(long, value_enum, conflicts_with = "auto_grain")]
+    pub denoise: Option<Denoise>,
+
     /// Pixel format. libsvtav1, libaom-av1 & librav1e default to yuv420p10le.
+    ///
+    /// Other encoders supporting 10-bit output default to 10-bit for high bit depth
+    /// inputs, e.g. a yuv420p10le input encodes as p010le with hevc_nvenc.
     #[arg(value_enum, long)]
     pub pix_format: Option<PixelFormat>,
 
@@ -55,6 +155,7 @@ pub struct Encode {
     ///
     /// For some ffmpeg encoders a word may be used, e.g. "fast".
     /// libaom-av1 preset is mapped to equivalent -cpu-used argument.
+    /// *_amf encoder preset is mapped to -quality, e.g. "balanced".
     ///
     /// [svt-av1 default: 8]
     #[arg(long, allow_hyphen_values = true)]
@@ -100,36 +201,52 @@ pub struct Encode {
     /// *_vulkan encoder default: `--enc-input hwaccel=vulkan --enc-input hwaccel_output_format=vulkan`.
     #[arg(long = "enc-input", allow_hyphen_values = true, value_parser = parse_enc_arg)]
     pub enc_input_args: Vec<String>,
-     /// CUDA decoder to use (e.g. h264_cuvid, hevc_cuvid)
-     #[arg(long)]
-     pub cuda_decoder: Option<String>,
-
-     /// CUDA-accelerated video filters (e.g. crop_cuda=1920:1080:0:0)
-     #[arg(long)]
-     pub cuda_filters: Vec<String>,
-     /// CUDA scaling method [bilinear/lanczos/bicubic] (default: lanczos)
-     #[arg(long, default_value = "lanczos")]
-     pub cuda_scaling_method: String,
-
-     /// Number of CUDA surfaces (default: 16 for 4GB GPUs)
-     #[arg(long, default_value_t = 16)]
-     pub cuda_surfaces: usize,
-
-    /// Path to VMAF executable
-    #[arg(long, default_value = "vmaf")]
-    pub vmaf_path: PathBuf,
-
-    /// Use CUDA-accelerated VMAF calculation
+
+    /// CUDA decoder to use (e.g. h264_cuvid, hevc_cuvid).
+    /// Decoded frames stay in GPU memory for --cuda-filters.
+    ///
+    /// Leading crop, e.g. autocrop, & bilinear downscale --cuda-filters are done by
+    /// *_cuvid decoders themselves using -crop & -resize.
+    #[arg(long)]
+    pub cuda_decoder: Option<String>,
+
+    /// CUDA-accelerated video filters, e.g. --cuda-filters scale_cuda=1280:-2.
+    ///
+    /// "scale=W:H" is converted to scale_cuda using --cuda-scaling-method.
+    /// "autocrop" is replaced with a detected crop filter.
+    ///
+    /// Merged with --vfilter according to --filter-order.
     #[arg(long)]
-    pub vmaf_cuda: bool,
+    pub cuda_filters: Vec<String>,
+
+    /// CUDA scaling method [nearest/bilinear/bicubic/lanczos].
+    #[arg(long, default_value = "lanczos")]
+    pub cuda_scaling_method: String,
 
-    /// VMAF model path
-    #[arg(long, default_value = "vmaf_v0.6.1.json")]
-    pub vmaf_model: PathBuf,
+    /// Number of CUDA decoder surfaces, i.e. ffmpeg `-extra_hw_frames`, 8-32.
+    ///
+    /// Defaults to auto tuning by GPU VRAM shared between --max-gpu-jobs, detected using
+    /// nvidia-smi, e.g. 16 for 4GB GPUs. 16 if VRAM is unknown.
+    #[arg(long)]
+    pub cuda_surfaces: Option<usize>,
 
-    /// VMAF CUDA surfaces (default: 16)
-    #[arg(long, default_value_t = 16)]
-    pub vmaf_surfaces: usize,
+    /// Order to apply --cuda-filters & --vfilter.
+    ///
+    /// hwupload_cuda/hwdownload are inserted automatically wherever software
+    /// filters follow GPU filters or vice versa. Frames are downloaded from
+    /// GPU memory at the end of the chain unless using a *_nvenc encoder.
+    #[arg(long, value_enum, default_value_t = FilterOrder::HwFirst)]
+    pub filter_order: FilterOrder,
+
+    /// Retry with other hardware, then software, if a CUDA decoder, filter or encoder
+    /// is missing or fails, printing a warning with the reason.
+    ///
+    /// *_nvenc encoders fall back to the *_qsv or *_vaapi equivalent if available,
+    /// then software, e.g. hevc_nvenc -> hevc_qsv -> libx265. --cuda-filters are
+    /// converted to software filters. --preset is reset to the encoder default,
+    /// other args are kept.
+    #[arg(long, value_enum, default_value_t = HwFallback::On)]
+    pub hw_fallback: HwFallback,
 }
 
 fn parse_svt_arg(arg: &str) -> anyhow::Result<Arc<str>> {
@@ -156,20 +273,6 @@ fn parse_enc_arg(arg: &str) -> anyhow::Result<String> {
     Ok(arg)
 }
 
-fn detect_crop(&self) -> anyhow::Result<String> {
-    Command::new("ffmpeg")
-        .args(["-hwaccel", "cuda", "-i", &self.input, ...])
-        .output()?;
-    // Parse crop from output
-}
-
-#[test]
-fn test_cuda_pipeline() {
-    let enc = Encode { cuda_decoder: Some("h264_cuvid".into()), ... };
-    let args = enc.to_ffmpeg_args(...).unwrap();
-    assert!(args.vfilter.contains("hwupload_cuda"));
-}
-
 impl Encode {
     pub fn to_encoder_args(
         &self,
@@ -179,11 +282,95 @@ impl Encode {
         self.to_ffmpeg_args(crf, probe)
     }
 
+    /// Probe the input, with the duration of any --start & --duration trim,
+    /// & detect any --cuda-filters autocrop.
+    pub async fn probe_input(&self) -> anyhow::Result<Ffprobe> {
+        let mut probe = ffprobe::probe(&self.input);
+        if let Some(n) = self.video_stream {
+            probe.select_video_stream(n);
+        }
+        if self.is_trimmed() && !probe.is_image {
+            probe.trim(self.start(), self.duration);
+        }
+        if self.uses_autocrop() {
+            crop::detect(
+                &self.input,
+                self.video_stream(),
+                self.start(),
+                probe.duration.clone().ok(),
+            )
+            .await?;
+        }
+        Ok(probe)
+    }
+
+    /// Replaces --cuda-filters autocrop with the crop detected by [`Self::probe_input`],
+    /// e.g. before encoding scenes or samples of the input as the input.
+    pub fn resolve_autocrop(&mut self) -> anyhow::Result<()> {
+        if self.uses_autocrop() {
+            let crop = self.autocrop()?;
+            for filters in &mut self.cuda_filters {
+                *filters = split_vfilter(filters)
+                    .into_iter()
+                    .map(|f| if f == "autocrop" { crop.as_str() } else { f })
+                    .collect::<Vec<_>>()
+                    .join(",");
+            }
+        }
+        Ok(())
+    }
+
+    fn uses_autocrop(&self) -> bool {
+        self.cuda_filters
+            .iter()
+            .flat_map(|f| split_vfilter(f))
+            .any(|f| f == "autocrop")
+    }
+
+    /// Returns true if --start or --duration trim the input.
+    pub fn is_trimmed(&self) -> bool {
+        self.start.is_some() || self.duration.is_some()
+    }
+
+    /// Returns the --video-stream index, zero if unset.
+    pub fn video_stream(&self) -> usize {
+        self.video_stream.unwrap_or_default()
+    }
+
+    /// Returns the --start position, zero if unset.
+    pub fn start(&self) -> Duration {
+        self.start.unwrap_or_default()
+    }
+
+    /// Ffmpeg input args trimming the input to --start & --duration.
+    pub fn trim_input_args(&self) -> Vec<Arc<String>> {
+        let mut args = vec![];
+        if let Some(start) = self.start.filter(|s| !s.is_zero()) {
+            args.push(Arc::new("-ss".to_owned()));
+            args.push(Arc::new(start.as_secs_f64().to_string()));
+        }
+        if let Some(duration) = self.duration {
+            args.push(Arc::new("-t".to_owned()));
+            args.push(Arc::new(duration.as_secs_f64().to_string()));
+        }
+        args
+    }
+
     pub fn encode_hint(&self, crf: f32) -> String {
         let Self {
             encoder,
             input,
+            video_stream,
+            start,
+            duration,
+            profile: _,
             vfilter,
+            burn_subs,
+            detelecine,
+            deinterlace,
+            auto_grain,
+            film_grain,
+            denoise,
             preset,
             pix_format,
             keyint,
@@ -191,6 +378,12 @@ impl Encode {
             svt_args,
             enc_args,
             enc_input_args,
+            cuda_decoder,
+            cuda_filters,
+            cuda_scaling_method,
+            cuda_surfaces,
+            filter_order,
+            hw_fallback,
         } = self;
 
         let input = shell_escape::escape(input.display().to_string().into());
@@ -203,6 +396,21 @@ impl Encode {
         }
         write!(hint, " -i {input} --crf {}", TerseF32(crf)).unwrap();
 
+        if let Some(n) = video_stream {
+            write!(hint, " --video-stream {n}").unwrap();
+        }
+        if let Some(start) = start {
+            write!(hint, " --start {}", humantime::format_duration(*start)).unwrap();
+        }
+        if let Some(duration) = duration {
+            write!(
+                hint,
+                " --duration {}",
+                humantime::format_duration(*duration)
+            )
+            .unwrap();
+        }
+
         if let Some(preset) = preset {
             write!(hint, " --preset {preset}").unwrap();
         }
@@ -218,6 +426,45 @@ impl Encode {
         if let Some(filter) = vfilter {
             write!(hint, " --vfilter {filter:?}").unwrap();
         }
+        if let Some(subs) = burn_subs {
+            let subs = shell_escape::escape(subs.to_string().into());
+            write!(hint, " --burn-subs {subs}").unwrap();
+        }
+        if *detelecine != Detelecine::Off {
+            write!(hint, " --detelecine {detelecine}").unwrap();
+        }
+        if *deinterlace != Deinterlace::Auto {
+            write!(hint, " --deinterlace {deinterlace}").unwrap();
+        }
+        if *auto_grain {
+            hint.push_str(" --auto-grain");
+        }
+        if let Some(film_grain) = film_grain {
+            write!(hint, " --film-grain {film_grain}").unwrap();
+        }
+        if let Some(denoise) = denoise {
+            write!(hint, " --denoise {denoise}").unwrap();
+        }
+        if let Some(decoder) = cuda_decoder {
+            write!(hint, " --cuda-decoder {decoder}").unwrap();
+            if let Some(surfaces) = cuda_surfaces {
+                write!(hint, " --cuda-surfaces {surfaces}").unwrap();
+            }
+        }
+        for filter in cuda_filters {
+            write!(hint, " --cuda-filters {filter:?}").unwrap();
+        }
+        if !cuda_filters.is_empty() {
+            if cuda_scaling_method != "lanczos" {
+                write!(hint, " --cuda-scaling-method {cuda_scaling_method}").unwrap();
+            }
+            if *filter_order != FilterOrder::HwFirst {
+                write!(hint, " --filter-order {filter_order}").unwrap();
+            }
+        }
+        if *hw_fallback != HwFallback::On {
+            write!(hint, " --hw-fallback {hw_fallback}").unwrap();
+        }
         for arg in svt_args {
             write!(hint, " --svt {arg}").unwrap();
         }
@@ -233,47 +480,259 @@ impl Encode {
         hint
     }
 
-    // Add this method to handle auto-crop detection
-    fn detect_cuda_crop(&self) -> anyhow::Result<String> {
-        let output = Command::new("ffmpeg")
-            .args([
-                "-hwaccel", "cuda",
-                "-i", self.input.to_str().unwrap(),
-                "-vf", "cropdetect=24:16:0",
-                "-f", "null", "-"
-            ])
-            .output()
-            .context("CUDA crop detection failed")?;
+    /// Returns the autocrop filter detected by [`Self::probe_input`].
+    fn autocrop(&self) -> anyhow::Result<String> {
+        crop::detected(&self.input, self.video_stream())
+            .context("autocrop must be detected by probing the input")
+    }
 
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        stderr.lines()
-            .rev()
-            .find(|l| l.contains("crop="))
-            .and_then(|l| l.split_whitespace().find(|s| s.starts_with("crop=")))
-            .map(|s| s.to_string())
-            .ok_or_else(|| anyhow::anyhow!("No crop detected"))
+    /// Returns true if detelecine filters should be used.
+    fn use_detelecine(&self, probe: &Ffprobe) -> anyhow::Result<bool> {
+        match self.detelecine {
+            Detelecine::Off => Ok(false),
+            Detelecine::On => Ok(true),
+            Detelecine::Auto if probe.is_image => Ok(false),
+            Detelecine::Auto => telecine::detect(
+                &self.input,
+                self.video_stream(),
+                self.start(),
+                probe.duration.clone().ok(),
+            ),
+        }
     }
 
+    /// Returns true if deinterlace filters should be used.
+    fn use_deinterlace(&self, probe: &Ffprobe) -> anyhow::Result<bool> {
+        if self.use_detelecine(probe)? {
+            return Ok(false);
+        }
+        Ok(match self.deinterlace {
+            Deinterlace::Off => false,
+            Deinterlace::Force => true,
+            Deinterlace::Auto => probe.is_interlaced(),
+        })
+    }
 
-    fn to_ffmpeg_args(&self, crf: f32, probe: &Ffprobe) -> anyhow::Result<FfmpegEncodeArgs<'_>> {
-        // Add this block
-        if let Some(decoder) = &self.cuda_decoder {
-            let available = get_cuvid_decoders()?;
-            if !available.contains(decoder) {
-                anyhow::bail!(
-                    "CUDA decoder {} not available. Supported: {}",
-                    decoder,
-                    available.join(", ")
-                );
+    /// Returns --auto-grain settings if the input is noisy.
+    fn auto_grain(&self, probe: &Ffprobe) -> anyhow::Result<Option<grain::Settings>> {
+        if !self.auto_grain || probe.is_image {
+            return Ok(None);
+        }
+        let noise = grain::measure_noise(
+            &self.input,
+            self.video_stream(),
+            probe.duration.clone().ok(),
+        )?;
+        Ok(grain::settings(noise))
+    }
+
+    /// Returns the --film-grain level, estimating it for "auto".
+    fn film_grain(&self, probe: &Ffprobe) -> anyhow::Result<Option<u8>> {
+        match self.film_grain {
+            None => Ok(None),
+            Some(FilmGrain::Level(level)) => Ok(Some(level)),
+            Some(FilmGrain::Auto) if probe.is_image => Ok(None),
+            Some(FilmGrain::Auto) => grain::estimate_film_grain(
+                &self.input,
+                self.video_stream(),
+                probe.duration.clone().ok(),
+            ),
+        }
+    }
+
+    /// Returns true if frames are filtered in GPU memory.
+    fn gpu_filtering(&self) -> bool {
+        self.cuda_decoder.is_some() || !self.cuda_filters.is_empty()
+    }
+
+    /// Returns args to retry with after a hardware failure `err` according to
+    /// --hw-fallback, along with a warning describing the reason & fallback.
+    ///
+    /// Returns `None` if `err` isn't a hardware failure or there's nothing to fall back to.
+    pub fn hw_fallback(&self, err: &anyhow::Error) -> Option<(Self, String)> {
+        // stdin input can't be read again
+        if self.hw_fallback == HwFallback::Off || stdin::is_stdin(&self.input) {
+            return None;
+        }
+        let reason = ffmpeg::hw_failure_reason(err)?;
+        match self.fallback_args() {
+            Ok(Some((args, fallback))) => {
+                Some((args, format!("{reason}, falling back to {fallback}")))
+            }
+            Ok(None) => None,
+            Err(err) => {
+                debug!("hw fallback failed: {err:#}");
+                None
+            }
+        }
+    }
+
+    /// Returns the next args in the CUDA -> QSV/VAAPI -> software fallback chain
+    /// & a description, e.g. "hevc_qsv with software decoding".
+    fn fallback_args(&self) -> anyhow::Result<Option<(Self, String)>> {
+        let mut args = self.clone();
+        let cuda_decode =
+            self.gpu_filtering() || self.enc_input_args.iter().any(|a| is_cuda_input_arg(a));
+        if cuda_decode {
+            args.cuda_decoder = None;
+            args.cuda_surfaces = None;
+            args.cuda_filters.clear();
+            args.enc_input_args.retain(|a| !is_cuda_input_arg(a));
+
+            let mut filters = vec![];
+            for filter in self.cuda_filters.iter().flat_map(|f| split_vfilter(f)) {
+                if filter == "autocrop" {
+                    filters.push(self.autocrop()?);
+                } else if let Some(filter) = software_filter(filter, &self.cuda_scaling_method) {
+                    filters.push(filter);
+                }
+            }
+            let user = self.vfilter.iter().cloned();
+            let vfilter = match self.filter_order {
+                FilterOrder::HwFirst => filters.into_iter().chain(user).collect::<Vec<_>>(),
+                FilterOrder::UserFirst => user.chain(filters).collect(),
+            }
+            .join(",");
+            args.vfilter = Some(vfilter).filter(|vf| !vf.is_empty());
+        }
+
+        let vcodec = self.encoder.as_str();
+        if let Some((codec, hw)) = HW_ENCODERS
+            .iter()
+            .find_map(|hw| Some((vcodec.strip_suffix(hw)?, hw)))
+        {
+            // vaapi decodes into GPU memory, so can't be used with software filters
+            let sw_filters = args.vfilter.is_some() || args.denoise.is_some() || args.auto_grain;
+            let next = HW_ENCODERS
+                .iter()
+                .skip_while(|h| h != &hw)
+                .skip(1)
+                .filter(|h| !(h.ends_with("_vaapi") && sw_filters))
+                .map(|h| format!("{codec}{h}"))
+                .find(|e| hw_encoder_available(e))
+                .or_else(|| software_encoder(codec).map(String::from));
+            let Some(next) = next else {
+                return Ok(None);
+            };
+            args.encoder = Encoder(next.into());
+            // presets are encoder specific
+            args.preset = None;
+        } else if !cuda_decode {
+            return Ok(None);
+        }
+
+        let mut fallback = args.encoder.as_str().to_owned();
+        if cuda_decode {
+            fallback.push_str(" with software decoding");
+        }
+        Ok(Some((args, fallback)))
+    }
+
+    /// Returns true if a *_vulkan encoder decodes into Vulkan memory, i.e. the default
+    /// `-hwaccel vulkan -hwaccel_output_format vulkan` aren't overridden by --enc-input.
+    fn vulkan_frames(&self) -> bool {
+        self.encoder.as_str().ends_with("_vulkan")
+            && self
+                .enc_input_args
+                .iter()
+                .all(|arg| match arg.split_once('=') {
+                    Some(("-hwaccel" | "-hwaccel_output_format", val)) => val == "vulkan",
+                    _ => true,
+                })
+    }
+
+    /// Software filters applied before all others, i.e. detelecine, deinterlace & --auto-grain,
+    /// --denoise denoise.
+    fn pre_filters(&self, probe: &Ffprobe) -> anyhow::Result<Vec<&'static str>> {
+        let mut filters = vec![];
+        if self.use_detelecine(probe)? {
+            filters.extend(split_vfilter(telecine::FILTERS));
+        }
+        if self.cuda_decoder.is_none() && self.use_deinterlace(probe)? {
+            filters.push(DEINTERLACE_FILTER);
+        }
+        if let Some(grain) = self.auto_grain(probe)? {
+            filters.push(grain.denoise);
+        }
+        if let Some(denoise) = self.denoise.filter(|_| !self.gpu_filtering()) {
+            filters.push(denoise.filter());
+        }
+        Ok(filters)
+    }
+
+    /// Returns the software filters applied to the VMAF/XPSNR reference,
+    /// i.e. any detelecine, deinterlace & denoise filters then --vfilter.
+    pub fn reference_vfilter(&self, probe: &Ffprobe) -> anyhow::Result<Option<String>> {
+        let mut pre_filters = self.pre_filters(probe)?;
+        if probe.is_paletted() {
+            // convert from the palette before any scaling & the score format conversion
+            pre_filters.insert(0, "format=rgb24");
+        }
+        if self.cuda_decoder.is_some() && self.use_deinterlace(probe)? {
+            // software equivalent of the gpu deinterlace
+            pre_filters.insert(0, CUDA_DEINTERLACE_REF_FILTER);
+        }
+        if let Some(denoise) = self.denoise.filter(|_| self.gpu_filtering()) {
+            // software equivalent of the gpu denoise
+            pre_filters.push(denoise.filter());
+        }
+        let mut filters = pre_filters.join(",");
+        if let Some(vf) = &self.user_vfilter(probe)? {
+            if !filters.is_empty() {
+                filters.push(',');
             }
+            filters.push_str(vf);
         }
+        Ok(Some(filters).filter(|f| !f.is_empty()))
+    }
+
+    /// Returns --vfilter followed by any --burn-subs subtitles filter.
+    fn user_vfilter(&self, probe: &Ffprobe) -> anyhow::Result<Option<String>> {
+        let Some(burn_subs) = &self.burn_subs else {
+            return Ok(self.vfilter.clone());
+        };
+        let subs = burn_subs.filter(&self.input, probe)?;
+        Ok(Some(match &self.vfilter {
+            Some(vf) => format!("{vf},{subs}"),
+            None => subs,
+        }))
+    }
 
-        // Add auto-crop detection
-        let mut filters = self.cuda_filters.clone();
-        if filters.iter().any(|f| f == "autocrop") {
-            let crop = self.detect_cuda_crop()?;
-            filters.push(crop);
+    /// Returns --cuda-filters with "scale=" & "autocrop" converted,
+    /// after any gpu deinterlace & denoise filters.
+    fn cuda_filters(&self, probe: &Ffprobe) -> anyhow::Result<Vec<String>> {
+        ensure!(
+            matches!(
+                self.cuda_scaling_method.as_str(),
+                "nearest" | "bilinear" | "bicubic" | "lanczos"
+            ),
+            "Invalid --cuda-scaling-method {}",
+            self.cuda_scaling_method
+        );
+
+        let mut filters = Vec::with_capacity(self.cuda_filters.len() + 2);
+        if self.cuda_decoder.is_some() && self.use_deinterlace(probe)? {
+            filters.push(CUDA_DEINTERLACE_FILTER.to_owned());
+        }
+        if let Some(denoise) = self.denoise.filter(|_| self.gpu_filtering()) {
+            filters.push(denoise.cuda_filter().to_owned());
+        }
+        for filter in self.cuda_filters.iter().flat_map(|f| split_vfilter(f)) {
+            if filter == "autocrop" {
+                filters.push(self.autocrop()?);
+            } else if let Some(args) = filter.strip_prefix("scale=") {
+                filters.push(format!(
+                    "scale_cuda={args}:interp_algo={}",
+                    self.cuda_scaling_method
+                ));
+            } else {
+                filters.push(filter.to_owned());
+            }
+        }
+        Ok(filters)
+    }
 
+    fn to_ffmpeg_args(&self, crf: f32, probe: &Ffprobe) -> anyhow::Result<FfmpegEncodeArgs<'_>> {
         let vcodec = &self.encoder.0;
         let svtav1 = vcodec.as_ref() == "libsvtav1";
         ensure!(
@@ -281,21 +740,28 @@ impl Encode {
             "--svt may only be used with svt-av1"
         );
 
-        // Validate CUDA configuration
-        if self.cuda_decoder.is_some() {
+        if vcodec.ends_with("_amf") {
+            ensure!(
+                amf_runtime_available(),
+                "AMD AMF runtime {AMF_RUNTIME} not found, is the AMD GPU driver installed?"
+            );
+        }
+
+        if let Some(decoder) = &self.cuda_decoder {
             let available_decoders = get_cuvid_decoders()?;
-            if !available_decoders.contains(&self.cuda_decoder.as_ref().unwrap().as_str()) {
-                anyhow::bail!(
-                    "CUDA decoder {} not available. Supported: {}",
-                    self.cuda_decoder.as_ref().unwrap(),
-                    available_decoders.join(", ")
+            if !available_decoders.contains(decoder) {
+                return Err(Error::DecoderUnavailable {
+                    decoder: decoder.clone(),
+                    available: available_decoders,
+                }
+                .into());
+            }
+            if let Some(surfaces) = self.cuda_surfaces {
+                ensure!(
+                    caps::SURFACES_RANGE.contains(&surfaces),
+                    "CUDA surfaces must be between 8-32 for Pascal GPUs (got {surfaces})",
                 );
             }
-            ensure!(
-                self.cuda_surfaces >= 8 && self.cuda_surfaces <= 32,
-                "CUDA surfaces must be between 8-32 for Pascal GPUs (got {})", 
-                self.cuda_surfaces
-            );
         }
 
         let preset = match &self.preset {
@@ -304,7 +770,27 @@ impl Encode {
             None => None,
         };
 
-        let keyint = self.keyint(probe)?;
+        let detelecine = self.use_detelecine(probe)?;
+        let keyint = self.keyint(probe, detelecine)?;
+
+        let grain = self.auto_grain(probe)?;
+        ensure!(
+            svtav1 || !self.auto_grain,
+            "--auto-grain may only be used with svt-av1"
+        );
+        ensure!(
+            !self.auto_grain || !self.svt_args.iter().any(|a| a.starts_with("film-grain")),
+            "--auto-grain cannot be used with --svt film-grain"
+        );
+        ensure!(
+            svtav1 || self.film_grain.is_none(),
+            "--film-grain may only be used with svt-av1"
+        );
+        ensure!(
+            self.film_grain.is_none() || !self.svt_args.iter().any(|a| a.starts_with("film-grain")),
+            "--film-grain cannot be used with --svt film-grain"
+        );
+        let film_grain = self.film_grain(probe)?;
 
         let mut svtav1_params = vec![];
         if svtav1 {
@@ -313,42 +799,41 @@ impl Encode {
                 _ => 0,
             };
             svtav1_params.push(format!("scd={scd}"));
+            if let Some(grain) = grain {
+                svtav1_params.push(format!("film-grain={}", grain.film_grain));
+                // the source is already denoised
+                svtav1_params.push("film-grain-denoise=0".into());
+            }
+            if let Some(film_grain) = film_grain.filter(|l| *l > 0) {
+                svtav1_params.push(format!("film-grain={film_grain}"));
+            }
             // add all --svt args
             svtav1_params.extend(self.svt_args.iter().map(|a| a.to_string()));
         }
 
-            // Build CUDA-specific arguments
-            let mut cuda_input_args = vec![];
-            let mut cuda_filters = String::new();
-            if let Some(decoder) = &self.cuda_decoder {
-                cuda_input_args.extend([
-                    "-hwaccel".into(),
-                    "cuda".into(),
-                    "-hwaccel_output_format".into(),
-                    "cuda".into(),
-                    "-extra_hw_frames".into(),
-                    self.cuda_surfaces.to_string().into(),
-                    "-c:v".into(),
-                    decoder.clone().into(),
-                ]);
-
-                // Convert standard filters to CUDA variants
-                if !self.cuda_filters.is_empty() {
-                    cuda_filters = self.cuda_filters.join(",")
-                        .replace("crop=", "hwupload_cuda,crop=")
-                        .replace("scale=", "scale_cuda=format=nv12:");
-                    cuda_filters = format!("hwdownload,format=nv12,{},hwupload_cuda", cuda_filters);
-                }
-
-                // Add format conversion and memory transfer
-                if !cuda_filters.is_empty() {
-                    cuda_filters = format!(
-                        "hwdownload,format=nv12,{},hwupload_cuda",
-                        cuda_filters
-                    );
-                }
-            }
+        let mut cuda_input_args: Vec<Arc<String>> = vec![];
+        if let Some(decoder) = &self.cuda_decoder {
+            let surfaces = self
+                .cuda_surfaces
+                .unwrap_or_else(|| caps::auto_surfaces(scheduler::max_gpu_jobs()))
+                .to_string();
+            cuda_input_args.extend(
+                [
+                    "-hwaccel",
+                    "cuda",
+                    "-hwaccel_output_format",
+                    "cuda",
+                    "-extra_hw_frames",
+                    &surfaces,
+                    "-c:v",
+                    decoder,
+                ]
+                .map(|a| Arc::new(a.to_owned())),
+            );
+        }
 
+        // `--enc map=..` are composed with the default maps, like --map
+        let mut maps = vec![];
         let mut args: Vec<Arc<String>> = self
             .enc_args
             .iter()
@@ -357,6 +842,9 @@ impl Encode {
                     if opt == "svtav1-params" {
                         svtav1_params.push(arg.clone());
                         vec![].into_iter()
+                    } else if opt == "map" {
+                        maps.push(val.into());
+                        vec![].into_iter()
                     } else {
                         vec![opt.to_owned().into(), val.to_owned().into()].into_iter()
                     }
@@ -386,21 +874,52 @@ impl Encode {
             }
         }
 
-        let pix_fmt = self.pix_format.or_else(|| match &**vcodec {
-            "libsvtav1" | "libaom-av1" | "librav1e" => Some(PixelFormat::Yuv420p10le),
-            _ if self.cuda_decoder.is_some() => Some(PixelFormat::Nv12),
-            _ => None,
-        });
+        let pix_fmt = self
+            .pix_format
+            .or_else(|| default_pix_format(vcodec, probe.pixel_format(), self.gpu_filtering()));
 
-        // Merge CUDA filters with existing filters
-        let mut vfilter = self.vfilter.clone().unwrap_or_default();
-        if !cuda_filters.is_empty() {
-            if !vfilter.is_empty() {
-                vfilter = format!("{},{}", cuda_filters, vfilter);
-            } else {
-                vfilter = cuda_filters;
-            }
+        let vulkan = self.vulkan_frames();
+        ensure!(
+            !vulkan || !self.gpu_filtering(),
+            "--cuda-decoder & --cuda-filters cannot be used with *_vulkan encoders"
+        );
+        let (hw, user_vfilter) = match vulkan {
+            true => (
+                HwFrames::Vulkan,
+                self.user_vfilter(probe)?.as_deref().map(vulkan_vfilter),
+            ),
+            false => (HwFrames::Cuda, self.user_vfilter(probe)?),
+        };
+        if let Some(vfilter) = &user_vfilter {
+            static FILTERS: LazyLock<Option<Vec<String>>> =
+                LazyLock::new(|| ffmpeg::filters(program::ffmpeg()));
+            validate_vfilter(vfilter, hw, FILTERS.as_deref())?;
+        }
+        let pre_filters = self.pre_filters(probe)?;
+        let mut cuda_filters = self.cuda_filters(probe)?;
+        let cuvid_first = pre_filters.is_empty()
+            && (self.filter_order == FilterOrder::HwFirst || user_vfilter.is_none());
+        if let Some(decoder) = &self.cuda_decoder
+            && decoder.ends_with("_cuvid")
+            && cuvid_first
+        {
+            let opts = cuvid_offload(
+                &mut cuda_filters,
+                probe.resolution,
+                &self.cuda_scaling_method,
+            );
+            cuda_input_args.extend(opts.into_iter().map(Arc::new));
         }
+        let vfilter = merge_vfilters(
+            &pre_filters,
+            &cuda_filters,
+            user_vfilter.as_deref(),
+            self.filter_order,
+            hw,
+            self.cuda_decoder.is_some() || vulkan,
+            vcodec.ends_with("_nvenc") || vulkan,
+            sw_format(probe).as_str(),
+        );
 
         let mut input_args: Vec<Arc<String>> = self
             .enc_input_args
@@ -412,7 +931,7 @@ impl Encode {
                     vec![arg.clone().into()].into_iter()
                 }
             })
-             .chain(cuda_input_args)
+            .chain(cuda_input_args)
             .collect();
 
         for (name, val) in self.encoder.default_ffmpeg_input_args() {
@@ -460,24 +979,34 @@ impl Encode {
 
         Ok(FfmpegEncodeArgs {
             input: &self.input,
+            video_stream: self.video_stream(),
             vcodec: Arc::clone(vcodec),
             pix_fmt,
-            vfilter: self.vfilter.as_deref(),
+            vfilter,
             crf,
             preset,
             output_args: args,
             input_args,
             video_only: false,
+            subtitles: <_>::default(),
+            maps,
+            mp4_compat: false,
+            reproducible: false,
+            metadata: <_>::default(),
         })
     }
 
-    fn keyint(&self, probe: &Ffprobe) -> anyhow::Result<Option<i32>> {
+    fn keyint(&self, probe: &Ffprobe, detelecine: bool) -> anyhow::Result<Option<i32>> {
         const KEYINT_DEFAULT_INPUT_MIN: Duration = Duration::from_secs(60 * 3);
         const KEYINT_DEFAULT: Duration = Duration::from_secs(10);
 
         let filter_fps = self.vfilter.as_deref().and_then(try_parse_fps_vfilter);
+        let input_fps = match detelecine {
+            true => probe.fps.clone().map(|fps| fps * telecine::FPS_FACTOR),
+            false => probe.fps.clone(),
+        };
         Ok(
-            match (self.keyint, &probe.duration, &probe.fps, filter_fps) {
+            match (self.keyint, &probe.duration, &input_fps, filter_fps) {
                 // use the filter-fps if used, otherwise the input fps
                 (Some(ki), .., Some(fps)) => Some(ki.keyint_number(Ok(fps))?),
                 (Some(ki), _, fps, None) => Some(ki.keyint_number(fps.clone())?),
@@ -513,6 +1042,12 @@ impl Encoder {
         }
     }
 
+    /// Returns true if the encoder accepts decimal crf values, e.g. 23.5.
+    pub fn supports_decimal_crf(&self) -> bool {
+        // nvenc -cq is a float option
+        matches!(self.as_str(), "libx264" | "libx265") || self.as_str().ends_with("_nvenc")
+    }
+
     pub fn default_min_crf(&self) -> f32 {
         match self.as_str() {
             "mpeg2video" => 2.0,
@@ -522,7 +1057,7 @@ impl Encoder {
 
     pub fn default_max_crf(&self) -> f32 {
         match self.as_str() {
-            "librav1e" | "av1_vaapi" => 255.0,
+            "librav1e" | "av1_vaapi" | "av1_amf" => 255.0,
             "libx264" | "libx265" => 46.0,
             "mpeg2video" => 30.0,
             // Works well for svt-av1
@@ -530,6 +1065,15 @@ impl Encoder {
         }
     }
 
+    /// Returns the default output extension of animated GIF & APNG inputs.
+    pub fn default_animation_ext(&self) -> &'static str {
+        match ffmpeg::container_supports("avif", self.as_str()) {
+            // animated avif
+            true => "avif",
+            false => "mp4",
+        }
+    }
+
     pub fn default_image_ext(&self) -> &'static str {
         match self.as_str() {
             // ffmpeg doesn't currently have good heif support,
@@ -552,6 +1096,8 @@ impl Encoder {
                 ("-extbrc", "1"),
                 ("-look_ahead_depth", "40"),
             ],
+            // constant qp mode for the crf mapped -qp_i, -qp_p etc
+            "av1_amf" | "hevc_amf" | "h264_amf" => &[("-rc", "cqp")],
             _ => &[],
         }
     }
@@ -565,11 +1111,10 @@ impl Encoder {
             e if e.ends_with("_vulkan") => {
                 &[("-hwaccel", "vulkan"), ("-hwaccel_output_format", "vulkan")]
             }
-            e if e.ends_with("_cuvid") => &[
-            ("-hwaccel", "cuda"),
-            ("-hwaccel_output_format", "cuda")
-        ],
-        _ => &[]
+            e if e.ends_with("_cuvid") => {
+                &[("-hwaccel", "cuda"), ("-hwaccel_output_format", "cuda")]
+            }
+            _ => &[],
         }
     }
 }
@@ -626,22 +1171,70 @@ impl std::str::FromStr for KeyInterval {
     }
 }
 
-/// Ordered by ascending quality.
+/// Ordered by ascending quality, i.e. bit depth then chroma resolution.
+///
+/// Semi-planar nv12 & p010le, as used by NVDEC/NVENC, follow their planar equivalent.
 #[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[clap(rename_all = "lower")]
 pub enum PixelFormat {
     Yuv420p,
+    Nv12,
+    Yuv422p,
+    Yuv444p,
     Yuv420p10le,
+    P010le,
     Yuv422p10le,
     Yuv444p10le,
+    Yuv420p12le,
+    Yuv422p12le,
+    Yuv444p12le,
 }
 
 impl PixelFormat {
-    /// Returns the max quality pixel format, or None if both are None.
+    /// Returns a planar pixel format with the max bit depth & chroma resolution of both,
+    /// or None if both are None.
+    ///
+    /// E.g. yuv444p & yuv420p10le => yuv444p10le.
     pub fn opt_max(a: Option<Self>, b: Option<Self>) -> Option<Self> {
         match (a, b) {
-            (Some(a), Some(b)) => Some(a.max(b)),
-            (a, b) => a.or(b),
+            (Some(a), Some(b)) => Some(Self::planar(
+                a.bit_depth().max(b.bit_depth()),
+                a.chroma().max(b.chroma()),
+            )),
+            (a, b) => a.or(b).map(|p| Self::planar(p.bit_depth(), p.chroma())),
+        }
+    }
+
+    pub fn bit_depth(self) -> u8 {
+        match self {
+            Self::Yuv420p | Self::Nv12 | Self::Yuv422p | Self::Yuv444p => 8,
+            Self::Yuv420p10le | Self::P010le | Self::Yuv422p10le | Self::Yuv444p10le => 10,
+            Self::Yuv420p12le | Self::Yuv422p12le | Self::Yuv444p12le => 12,
+        }
+    }
+
+    /// Chroma subsampling, 420, 422 or 444.
+    fn chroma(self) -> u16 {
+        match self {
+            Self::Yuv420p | Self::Nv12 | Self::Yuv420p10le | Self::P010le | Self::Yuv420p12le => {
+                420
+            }
+            Self::Yuv422p | Self::Yuv422p10le | Self::Yuv422p12le => 422,
+            Self::Yuv444p | Self::Yuv444p10le | Self::Yuv444p12le => 444,
+        }
+    }
+
+    fn planar(bit_depth: u8, chroma: u16) -> Self {
+        match (bit_depth, chroma) {
+            (..=8, ..=420) => Self::Yuv420p,
+            (..=8, 421..=422) => Self::Yuv422p,
+            (..=8, _) => Self::Yuv444p,
+            (9..=10, ..=420) => Self::Yuv420p10le,
+            (9..=10, 421..=422) => Self::Yuv422p10le,
+            (9..=10, _) => Self::Yuv444p10le,
+            (_, ..=420) => Self::Yuv420p12le,
+            (_, 421..=422) => Self::Yuv422p12le,
+            (_, _) => Self::Yuv444p12le,
         }
     }
 }
@@ -652,15 +1245,43 @@ fn pixel_format_order() {
     assert!(Yuv420p < Yuv420p10le);
     assert!(Yuv420p10le < Yuv422p10le);
     assert!(Yuv422p10le < Yuv444p10le);
+    assert!(Yuv444p10le < Yuv420p12le);
+    assert!(Yuv420p12le < Yuv444p12le);
+
+    assert_eq!(
+        PixelFormat::opt_max(Some(Yuv444p), Some(Yuv420p10le)),
+        Some(Yuv444p10le)
+    );
+    assert_eq!(
+        PixelFormat::opt_max(Some(P010le), Some(Yuv420p)),
+        Some(Yuv420p10le)
+    );
+    assert_eq!(
+        PixelFormat::opt_max(Some(Yuv422p12le), Some(Yuv444p)),
+        Some(Yuv444p12le)
+    );
+    assert_eq!(PixelFormat::opt_max(None, Some(Nv12)), Some(Yuv420p));
+    assert_eq!(PixelFormat::opt_max(None, None), None);
+
+    for pf in <PixelFormat as clap::ValueEnum>::value_variants() {
+        assert_eq!(PixelFormat::try_from(pf.as_str()), Ok(*pf));
+    }
 }
 
 impl PixelFormat {
     pub fn as_str(self) -> &'static str {
         match self {
+            Self::Yuv420p => "yuv420p",
+            Self::Nv12 => "nv12",
+            Self::Yuv422p => "yuv422p",
+            Self::Yuv444p => "yuv444p",
             Self::Yuv420p10le => "yuv420p10le",
+            Self::P010le => "p010le",
             Self::Yuv422p10le => "yuv422p10le",
             Self::Yuv444p10le => "yuv444p10le",
-            Self::Yuv420p => "yuv420p",
+            Self::Yuv420p12le => "yuv420p12le",
+            Self::Yuv422p12le => "yuv422p12le",
+            Self::Yuv444p12le => "yuv444p12le",
         }
     }
 }
@@ -676,59 +1297,1331 @@ impl TryFrom<&str> for PixelFormat {
 
     fn try_from(value: &str) -> Result<Self, Self::Error> {
         match value {
+            "yuv420p" => Ok(Self::Yuv420p),
+            "nv12" => Ok(Self::Nv12),
+            "yuv422p" => Ok(Self::Yuv422p),
+            "yuv444p" => Ok(Self::Yuv444p),
             "yuv420p10le" => Ok(Self::Yuv420p10le),
+            "p010le" => Ok(Self::P010le),
             "yuv422p10le" => Ok(Self::Yuv422p10le),
             "yuv444p10le" => Ok(Self::Yuv444p10le),
-            "yuv420p" => Ok(Self::Yuv420p),
+            "yuv420p12le" => Ok(Self::Yuv420p12le),
+            "yuv422p12le" => Ok(Self::Yuv422p12le),
+            "yuv444p12le" => Ok(Self::Yuv444p12le),
             _ => Err(()),
         }
     }
 }
 
-fn try_parse_fps_vfilter(vfilter: &str) -> Option<f64> {
-    let fps_filter = vfilter
-        .split(',')
-        .find_map(|vf| vf.trim().strip_prefix("fps="))?
-        .trim();
+/// Software deinterlace filter, outputting a frame per frame.
+const DEINTERLACE_FILTER: &str = "bwdif=mode=send_frame";
+/// Gpu deinterlace filter used with --cuda-decoder.
+const CUDA_DEINTERLACE_FILTER: &str = "yadif_cuda=mode=send_frame";
+/// Software equivalent of [`CUDA_DEINTERLACE_FILTER`] for the VMAF reference.
+const CUDA_DEINTERLACE_REF_FILTER: &str = "yadif=mode=send_frame";
+
+/// Deinterlace mode.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[clap(rename_all = "kebab-case")]
+pub enum Deinterlace {
+    /// Deinterlace if the input is interlaced.
+    #[default]
+    Auto,
+    Off,
+    Force,
+}
 
-    match fps_filter {
-        "ntsc" => Some(30000.0 / 1001.0),
-        "pal" => Some(25.0),
-        "film" => Some(24.0),
-        "ntsc_film" => Some(24000.0 / 1001.0),
-        _ => crate::ffprobe::parse_frame_rate(fps_filter),
+impl fmt::Display for Deinterlace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Auto => "auto",
+            Self::Off => "off",
+            Self::Force => "force",
+        })
     }
 }
 
-#[test]
-fn test_try_parse_fps_vfilter() {
-    let fps = try_parse_fps_vfilter("scale=1280:-1, fps=24, transpose=1").unwrap();
-    assert!((fps - 24.0).abs() < f64::EPSILON, "{fps:?}");
+/// Inverse telecine mode.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[clap(rename_all = "kebab-case")]
+pub enum Detelecine {
+    #[default]
+    Off,
+    On,
+    /// Use if telecine is detected.
+    Auto,
+}
 
-    let fps = try_parse_fps_vfilter("scale=1280:-1, fps=ntsc, transpose=1").unwrap();
-    assert!((fps - 30000.0 / 1001.0).abs() < f64::EPSILON, "{fps:?}");
+impl fmt::Display for Detelecine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Off => "off",
+            Self::On => "on",
+            Self::Auto => "auto",
+        })
+    }
 }
 
-#[test]
-fn frame_interval_from_str() {
-    use std::str::FromStr;
-    let from_300 = KeyInterval::from_str("300").unwrap();
-    assert_eq!(from_300, KeyInterval::Frames(300));
+/// --denoise strength.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[clap(rename_all = "kebab-case")]
+pub enum Denoise {
+    Light,
+    Medium,
+    Heavy,
 }
 
-#[test]
-fn duration_interval_from_str() {
-    use std::{str::FromStr, time::Duration};
-    let from_10s = KeyInterval::from_str("10s").unwrap();
-    assert_eq!(from_10s, KeyInterval::Duration(Duration::from_secs(10)));
+impl Denoise {
+    /// Software denoise filter.
+    pub fn filter(self) -> &'static str {
+        match self {
+            Self::Light => "hqdn3d=2:1.5:3:2.25",
+            Self::Medium => "hqdn3d=4:3:6:4.5",
+            Self::Heavy => "nlmeans=s=4:p=7:r=15",
+        }
+    }
+
+    /// Denoise filter operating on frames in GPU memory.
+    pub fn cuda_filter(self) -> &'static str {
+        match self {
+            Self::Light => "bilateral_cuda=sigmaS=1:sigmaR=8:window_size=5",
+            Self::Medium => "bilateral_cuda=sigmaS=2:sigmaR=16:window_size=9",
+            Self::Heavy => "bilateral_cuda=sigmaS=4:sigmaR=32:window_size=15",
+        }
+    }
+}
+
+impl fmt::Display for Denoise {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Light => "light",
+            Self::Medium => "medium",
+            Self::Heavy => "heavy",
+        })
+    }
+}
+
+/// --film-grain level or auto estimation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FilmGrain {
+    Auto,
+    Level(u8),
+}
+
+impl fmt::Display for FilmGrain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Auto => f.write_str("auto"),
+            Self::Level(level) => level.fmt(f),
+        }
+    }
+}
+
+impl std::str::FromStr for FilmGrain {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        if s == "auto" {
+            return Ok(Self::Auto);
+        }
+        let level = s.parse().context("expected 0-50 or auto")?;
+        ensure!(level <= 50, "film-grain must be 0-50 or auto");
+        Ok(Self::Level(level))
+    }
+}
+
+/// --burn-subs subtitle source.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BurnSubs {
+    Auto,
+    /// Input subtitle stream index.
+    Index(usize),
+    File(PathBuf),
+}
+
+impl std::str::FromStr for BurnSubs {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        ensure!(
+            !s.is_empty(),
+            "expected auto, a subtitle stream index or file"
+        );
+        Ok(match s {
+            "auto" => Self::Auto,
+            s => match s.parse() {
+                Ok(idx) => Self::Index(idx),
+                Err(_) => Self::File(s.into()),
+            },
+        })
+    }
+}
+
+impl BurnSubs {
+    /// Returns the subtitles filter burning these subtitles onto the `input` video.
+    fn filter(&self, input: &Path, probe: &Ffprobe) -> anyhow::Result<String> {
+        let mut subs = probe
+            .streams
+            .iter()
+            .filter(|s| s.codec_type == "subtitle")
+            .enumerate();
+        let si = match self {
+            Self::File(file) => {
+                ensure!(file.is_file(), "--burn-subs {} not found", file.display());
+                return Ok(format!("subtitles=filename='{}'", filter_path(file)));
+            }
+            Self::Auto => subs
+                .find(|(_, s)| is_text_subtitle(s.codec_name.as_deref()))
+                .map(|(si, _)| si)
+                .context("--burn-subs auto: input has no text subtitle stream")?,
+            Self::Index(idx) => {
+                let (si, stream) = subs.nth(*idx).with_context(|| {
+                    format!("--burn-subs {idx}: input has no subtitle stream {idx}")
+                })?;
+                let codec = stream.codec_name.as_deref();
+                ensure!(
+                    is_text_subtitle(codec),
+                    "--burn-subs {idx}: {} image subtitles are not supported, only text subtitles",
+                    codec.unwrap_or_default()
+                );
+                si
+            }
+        };
+        ensure!(
+            !stdin::is_stdin(input),
+            "--burn-subs {self} requires a file input, not stdin"
+        );
+        Ok(format!(
+            "subtitles=filename='{}':si={si}",
+            filter_path(input)
+        ))
+    }
+}
+
+impl fmt::Display for BurnSubs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Auto => f.write_str("auto"),
+            Self::Index(idx) => idx.fmt(f),
+            Self::File(file) => file.display().fmt(f),
+        }
+    }
+}
+
+/// Returns true unless `codec` is a bitmap subtitle format, which the subtitles filter
+/// can't render.
+fn is_text_subtitle(codec: Option<&str>) -> bool {
+    !matches!(
+        codec,
+        Some("hdmv_pgs_subtitle" | "dvd_subtitle" | "dvb_subtitle" | "dvb_teletext" | "xsub")
+    )
+}
+
+/// Escape a `path` for use as a quoted filter option value.
+fn filter_path(path: &Path) -> String {
+    path.to_string_lossy()
+        .replace('\\', "/")
+        .replace('\'', "")
+        .replace(':', "\\:")
+}
+
+/// Order to merge --cuda-filters & --vfilter.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[clap(rename_all = "kebab-case")]
+pub enum FilterOrder {
+    /// --vfilter then --cuda-filters.
+    UserFirst,
+    /// --cuda-filters then --vfilter.
+    #[default]
+    HwFirst,
+}
+
+impl fmt::Display for FilterOrder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::UserFirst => "user-first",
+            Self::HwFirst => "hw-first",
+        })
+    }
+}
+
+/// --hw-fallback mode.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[clap(rename_all = "kebab-case")]
+pub enum HwFallback {
+    #[default]
+    On,
+    Off,
+}
+
+impl fmt::Display for HwFallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::On => "on",
+            Self::Off => "off",
+        })
+    }
+}
+
+/// Hardware encoder suffixes in fallback order, see --hw-fallback.
+const HW_ENCODERS: &[&str] = &["_nvenc", "_qsv", "_vaapi"];
+
+/// Returns true if ffmpeg has the hardware `encoder` & there's likely a device for it.
+fn hw_encoder_available(encoder: &str) -> bool {
+    let device = match cfg!(windows) {
+        true => !encoder.ends_with("_vaapi"),
+        false => Path::new("/dev/dri").exists(),
+    };
+    device && ffmpeg::has_encoder(program::ffmpeg(), encoder)
+}
+
+/// Returns the software encoder of a hardware encoder `codec`, e.g. "hevc" -> "libx265".
+fn software_encoder(codec: &str) -> Option<&'static str> {
+    Some(match codec {
+        "h264" => "libx264",
+        "hevc" => "libx265",
+        "av1" => "libsvtav1",
+        "vp9" => "libvpx-vp9",
+        "vp8" => "libvpx",
+        "mpeg2" => "mpeg2video",
+        "mjpeg" => "mjpeg",
+        _ => return None,
+    })
+}
+
+/// Returns true if an --enc-input arg uses CUDA decoding, e.g. "-hwaccel=cuda".
+fn is_cuda_input_arg(arg: &str) -> bool {
+    match arg.split_once('=') {
+        Some((opt, val)) => opt.starts_with("-hwaccel") && matches!(val, "cuda" | "nvdec"),
+        None => arg == "-extra_hw_frames",
+    }
+}
+
+/// Returns the software equivalent of a --cuda-filters `filter`,
+/// `None` for filters only moving frames between memory, e.g. hwupload_cuda.
+///
+/// "scale=" & "scale_cuda=" use swscale `flags` like --cuda-scaling-method,
+/// other *_cuda & *_npp filters use the software filter of the same name.
+fn software_filter(filter: &str, scaling_method: &str) -> Option<String> {
+    let (name, args) = filter.split_once('=').unwrap_or((filter, ""));
+    let name = name.trim();
+    if matches!(name, "hwupload" | "hwupload_cuda" | "hwdownload") {
+        return None;
+    }
+    let name = name
+        .strip_suffix("_cuda")
+        .or_else(|| name.strip_suffix("_npp"))
+        .unwrap_or(name);
+    if name != "scale" {
+        return Some(match args {
+            "" => name.to_owned(),
+            args => format!("{name}={args}"),
+        });
+    }
+
+    let mut method = scaling_method;
+    let mut scale = vec![];
+    for arg in args.split(':').filter(|a| !a.is_empty()) {
+        match arg.split_once('=') {
+            Some(("interp_algo", algo)) => method = algo,
+            // gpu only options
+            Some(("format" | "passthrough", _)) => {}
+            _ => scale.push(arg),
+        }
+    }
+    let flags = match method {
+        "nearest" => "neighbor",
+        method => method,
+    };
+    Some(format!("scale={}:flags={flags}", scale.join(":")))
+}
+
+/// Moves leading crop & downscale `cuda_filters` of `resolution` input into *_cuvid decoder
+/// `-crop` & `-resize` options, which avoid the filters entirely. Returns the decoder options.
+///
+/// Downscales are only offloaded with bilinear `scaling_method`, as used by the decoder.
+fn cuvid_offload(
+    cuda_filters: &mut Vec<String>,
+    resolution: Option<(u32, u32)>,
+    scaling_method: &str,
+) -> Vec<String> {
+    let Some(mut res) = resolution else {
+        return vec![];
+    };
+    let mut opts = vec![];
+    if let Some((cropped, crop)) = cuda_filters.first().and_then(|f| cuvid_crop(f, res)) {
+        cuda_filters.remove(0);
+        opts.extend(["-crop".into(), crop]);
+        res = cropped;
+    }
+    if let Some((w, h)) = cuda_filters
+        .first()
+        .and_then(|f| cuvid_resize(f, res, scaling_method))
+    {
+        cuda_filters.remove(0);
+        opts.extend(["-resize".into(), format!("{w}x{h}")]);
+    }
+    opts
+}
+
+/// Returns the cropped resolution & *_cuvid `-crop` "(top)x(bottom)x(left)x(right)" of a
+/// "crop=W:H[:X:Y]" `filter`, e.g. from autocrop.
+fn cuvid_crop(filter: &str, (w, h): (u32, u32)) -> Option<((u32, u32), String)> {
+    let args: Vec<u32> = filter
+        .strip_prefix("crop=")?
+        .split(':')
+        .map(|a| a.trim().parse().ok())
+        .collect::<Option<_>>()?;
+    let (cw, ch, x, y) = match args[..] {
+        [cw, ch] if cw <= w && ch <= h => (cw, ch, (w - cw) / 2, (h - ch) / 2),
+        [cw, ch, x, y] => (cw, ch, x, y),
+        _ => return None,
+    };
+    let right = w.checked_sub(cw)?.checked_sub(x)?;
+    let bottom = h.checked_sub(ch)?.checked_sub(y)?;
+    // odd offsets would split chroma samples
+    if [cw, ch, x, y].iter().any(|v| v % 2 != 0) {
+        return None;
+    }
+    Some(((cw, ch), format!("{y}x{bottom}x{x}x{right}")))
+}
+
+/// Returns the *_cuvid `-resize` resolution of a "scale_cuda=W:H" downscale `filter`,
+/// if using bilinear scaling.
+fn cuvid_resize(filter: &str, (w, h): (u32, u32), scaling_method: &str) -> Option<(u32, u32)> {
+    let mut args = filter.strip_prefix("scale_cuda=")?.split(':');
+    let (sw, sh) = (
+        args.next()?.parse::<i64>().ok()?,
+        args.next()?.parse::<i64>().ok()?,
+    );
+    let mut method = scaling_method;
+    for arg in args {
+        match arg.split_once('=') {
+            Some(("interp_algo", algo)) => method = algo,
+            _ => return None,
+        }
+    }
+    if method != "bilinear" || w == 0 || h == 0 {
+        return None;
+    }
+    // like ffmpeg, negative sizes keep the aspect ratio rounded to a multiple
+    let keep_aspect = |size: i64, other: i64, num: u32, den: u32| {
+        let factor = size.abs();
+        let scaled = (other as f64 * num as f64 / (den as f64 * factor as f64)).round() as i64;
+        scaled * factor
+    };
+    let (sw, sh) = match (sw, sh) {
+        (sw, sh) if sw > 0 && sh > 0 => (sw, sh),
+        (sw, sh) if sw < 0 && sh > 0 => (keep_aspect(sw, sh, w, h), sh),
+        (sw, sh) if sw > 0 && sh < 0 => (sw, keep_aspect(sh, sw, h, w)),
+        _ => return None,
+    };
+    let downscale = (1..=w as i64).contains(&sw) && (1..=h as i64).contains(&sh);
+    (downscale && sw % 2 == 0 && sh % 2 == 0).then_some((sw as _, sh as _))
+}
+
+/// Returns `vfilter` with filters converted to Vulkan equivalents where possible, see [`vulkan_filter`].
+fn vulkan_vfilter(vfilter: &str) -> String {
+    split_vfilter(vfilter)
+        .into_iter()
+        .map(|f| vulkan_filter(f).unwrap_or_else(|| f.to_owned()))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Returns the Vulkan equivalent of a software `filter`, keeping frames in GPU memory.
+///
+/// * "scale=W:H" -> "scale_vulkan=w=W:h=H"
+/// * "crop=W:H:X:Y" -> "libplacebo=w=cw:h=ch:crop_w=W:crop_h=H:crop_x=X:crop_y=Y"
+///
+/// Filters with named options, e.g. "scale=1280:-2:flags=lanczos", aren't converted.
+fn vulkan_filter(filter: &str) -> Option<String> {
+    let (name, args) = filter.split_once('=')?;
+    let args: Vec<_> = args.split(':').collect();
+    if args.iter().any(|a| a.contains('=')) {
+        return None;
+    }
+    match (name.trim(), args.as_slice()) {
+        ("scale", [w, h]) => Some(format!("scale_vulkan=w={w}:h={h}")),
+        ("crop", [w, h, pos @ ..]) if pos.len() <= 2 => {
+            let mut crop = format!("libplacebo=w=cw:h=ch:crop_w={w}:crop_h={h}");
+            // libplacebo also centers by default
+            if let [x, y] = pos {
+                write!(crop, ":crop_x={x}:crop_y={y}").unwrap();
+            }
+            Some(crop)
+        }
+        _ => None,
+    }
+}
+
+/// Pre-flight check of the user `vfilter` for the `hw` frames pipeline.
+///
+/// Software filters are fine, [`merge_vfilters`] inserts hwdownload/hwupload around them.
+/// Errors for filters missing from the `available` ffmpeg filters, or requiring the frames
+/// of another hardware API, e.g. scale_vulkan with --cuda-decoder.
+fn validate_vfilter(
+    vfilter: &str,
+    hw: HwFrames,
+    available: Option<&[String]>,
+) -> anyhow::Result<()> {
+    let is_available = |name: &str| available.is_none_or(|a| a.iter().any(|f| f == name));
+    for filter in split_vfilter(vfilter) {
+        let name = filter
+            .split_once('=')
+            .map_or(filter, |(name, _)| name)
+            .trim();
+        // instance name, e.g. "scale@small"
+        let name = name.split_once('@').map_or(name, |(name, _)| name);
+        ensure!(
+            is_available(name),
+            "--vfilter {name} is not available in ffmpeg, see `ffmpeg -filters`"
+        );
+        let Some(api) = filter_hw_api(name).filter(|api| *api != hw.api()) else {
+            continue;
+        };
+        let mut hint = format!("--vfilter {name} requires {api} frames, which aren't used with ");
+        hint += match hw {
+            HwFrames::Cuda => "--cuda-decoder/--cuda-filters or software frames",
+            HwFrames::Vulkan => "*_vulkan encoders",
+        };
+        let software = name
+            .rsplit_once('_')
+            .map(|(sw, _)| sw)
+            .filter(|sw| is_available(sw) && filter_hw_api(sw).is_none());
+        if let Some(sw) = software {
+            write!(hint, ", use the software {sw} filter instead").unwrap();
+        }
+        anyhow::bail!(hint);
+    }
+    Ok(())
+}
+
+/// Hardware frames API a filter `name` requires, if any.
+fn filter_hw_api(name: &str) -> Option<&'static str> {
+    match name {
+        "hwupload" | "hwupload_cuda" | "hwdownload" | "hwmap" => None,
+        "libplacebo" => Some(HwFrames::Vulkan.api()),
+        _ if HwFrames::Cuda.is_gpu_filter(name) => Some(HwFrames::Cuda.api()),
+        _ if HwFrames::Vulkan.is_gpu_filter(name) => Some(HwFrames::Vulkan.api()),
+        _ => [
+            ("_vaapi", "VAAPI"),
+            ("_qsv", "QSV"),
+            ("_opencl", "OpenCL"),
+            ("_videotoolbox", "VideoToolbox"),
+        ]
+        .into_iter()
+        .find(|(suffix, _)| name.ends_with(suffix))
+        .map(|(_, api)| api),
+    }
+}
+
+/// Hardware frames API of GPU filters & decoded frames.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum HwFrames {
+    /// *_cuda & *_npp filters, NVDEC decoding.
+    Cuda,
+    /// *_vulkan & libplacebo filters, Vulkan decoding for *_vulkan encoders.
+    Vulkan,
+}
+
+impl HwFrames {
+    fn api(self) -> &'static str {
+        match self {
+            Self::Cuda => "CUDA",
+            Self::Vulkan => "Vulkan",
+        }
+    }
+
+    /// Filter uploading software frames to GPU memory.
+    fn upload(self) -> &'static str {
+        match self {
+            Self::Cuda => "hwupload_cuda",
+            Self::Vulkan => "hwupload",
+        }
+    }
+
+    /// Returns true if the filter `name` requires frames in GPU memory.
+    fn is_gpu_filter(self, name: &str) -> bool {
+        match self {
+            Self::Cuda => name.ends_with("_cuda") || name.ends_with("_npp"),
+            Self::Vulkan => name.ends_with("_vulkan") || name == "libplacebo",
+        }
+    }
+
+    /// Returns true if GPU encoders require frames in GPU memory, as *_vulkan encoders do.
+    /// *_nvenc encoders also accept software frames.
+    fn encode_requires_gpu_frames(self) -> bool {
+        self == Self::Vulkan
+    }
+}
+
+/// Where frames are in memory at a point in a filter chain.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum FrameMemory {
+    Cpu,
+    Gpu,
+}
+
+/// Merge gpu `cuda_filters` & the software `vfilter` into a single chain in the given `order`.
+/// Software `pre_filters`, e.g. detelecine, are always applied first.
+///
+/// Frames start in `hw` GPU memory if `gpu_decode`. Uploads, e.g. `hwupload_cuda`, &
+/// `hwdownload,format={sw_format}` are inserted wherever a filter needs frames in the other
+/// memory. Frames are downloaded at the end of the chain unless `gpu_encode`, or uploaded
+/// again if `gpu_encode` requires it, e.g. *_vulkan encoders.
+#[allow(clippy::too_many_arguments)]
+fn merge_vfilters(
+    pre_filters: &[&str],
+    cuda_filters: &[String],
+    vfilter: Option<&str>,
+    order: FilterOrder,
+    hw: HwFrames,
+    gpu_decode: bool,
+    gpu_encode: bool,
+    sw_format: &str,
+) -> Option<Arc<str>> {
+    let user_filters = vfilter.map(split_vfilter).unwrap_or_default();
+    let cuda_filters = cuda_filters.iter().map(|f| f.as_str());
+    let mut filters = pre_filters.to_vec();
+    match order {
+        FilterOrder::HwFirst => filters.extend(cuda_filters.chain(user_filters)),
+        FilterOrder::UserFirst => filters.extend(user_filters.into_iter().chain(cuda_filters)),
+    }
+
+    let download = format!("hwdownload,format={sw_format}");
+    let mut memory = match gpu_decode {
+        true => FrameMemory::Gpu,
+        false => FrameMemory::Cpu,
+    };
+    let mut chain = vec![];
+    for filter in filters {
+        let name = filter
+            .split_once('=')
+            .map_or(filter, |(name, _)| name)
+            .trim();
+        match name {
+            "hwupload" | "hwupload_cuda" => {
+                // uploading gpu frames is an ffmpeg error
+                if memory == FrameMemory::Cpu {
+                    chain.push(filter);
+                }
+                memory = FrameMemory::Gpu;
+            }
+            "hwdownload" => {
+                if memory == FrameMemory::Gpu {
+                    chain.push(filter);
+                }
+                memory = FrameMemory::Cpu;
+            }
+            _ if hw.is_gpu_filter(name) => {
+                if memory == FrameMemory::Cpu {
+                    chain.push(hw.upload());
+                    memory = FrameMemory::Gpu;
+                }
+                chain.push(filter);
+            }
+            _ => {
+                if memory == FrameMemory::Gpu {
+                    chain.push(&download);
+                    memory = FrameMemory::Cpu;
+                }
+                chain.push(filter);
+            }
+        }
+    }
+    match memory {
+        FrameMemory::Gpu if !gpu_encode => chain.push(&download),
+        // upload frames downloaded for software filters
+        FrameMemory::Cpu if gpu_decode && gpu_encode && hw.encode_requires_gpu_frames() => {
+            chain.push(hw.upload())
+        }
+        _ => {}
+    }
+
+    match chain.is_empty() {
+        true => None,
+        false => Some(chain.join(",").into()),
+    }
+}
+
+/// Split a filter chain into individual filters, respecting escaped & quoted commas.
+fn split_vfilter(vfilter: &str) -> Vec<&str> {
+    let mut filters = vec![];
+    let mut start = 0;
+    let mut quoted = false;
+    let mut escaped = false;
+    for (idx, c) in vfilter.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '\'' => quoted = !quoted,
+            ',' if !quoted => {
+                filters.push(vfilter[start..idx].trim());
+                start = idx + 1;
+            }
+            _ => {}
+        }
+    }
+    filters.push(vfilter[start..].trim());
+    filters.retain(|f| !f.is_empty());
+    filters
+}
+
+/// Output pixel format when --pix-format isn't set.
+///
+/// High bit depth `input` is encoded as 10-bit by encoders supporting it,
+/// otherwise the encoder picks, typically matching the input.
+/// `gpu_frames` already download/encode as p010le so don't need a -pix_fmt.
+fn default_pix_format(
+    vcodec: &str,
+    input: Option<PixelFormat>,
+    gpu_frames: bool,
+) -> Option<PixelFormat> {
+    let input = input.filter(|pf| pf.bit_depth() > 8);
+    match vcodec {
+        "libsvtav1" | "libaom-av1" | "librav1e" => Some(PixelFormat::Yuv420p10le),
+        "hevc_nvenc" | "av1_nvenc" if !gpu_frames => input.map(|_| PixelFormat::P010le),
+        "hevc_qsv" | "av1_qsv" | "hevc_amf" | "av1_amf" => input.map(|_| PixelFormat::P010le),
+        "libx265" | "libvpx-vp9" => input.map(|pf| PixelFormat::planar(10, pf.chroma())),
+        _ => None,
+    }
+}
+
+/// Software pixel format to download cuda frames as.
+fn sw_format(probe: &Ffprobe) -> PixelFormat {
+    let high_bit_depth = match probe.pixel_format() {
+        Some(pf) => pf.bit_depth() > 8,
+        None => probe
+            .pix_fmt
+            .as_deref()
+            .is_some_and(|pf| pf.contains("10") || pf.contains("12")),
+    };
+    match high_bit_depth {
+        true => PixelFormat::P010le,
+        false => PixelFormat::Nv12,
+    }
 }
 
-/// Should use keyint & scd defaults for >3m inputs.
+#[test]
+fn merge_vfilters_hw_first() {
+    let vf = merge_vfilters(
+        &[],
+        &["scale_cuda=1280:-2".into()],
+        Some("crop=1280:536,fps=24"),
+        FilterOrder::HwFirst,
+        HwFrames::Cuda,
+        true,
+        false,
+        "nv12",
+    );
+    assert_eq!(
+        vf.as_deref(),
+        Some("scale_cuda=1280:-2,hwdownload,format=nv12,crop=1280:536,fps=24")
+    );
+}
+
+#[test]
+fn merge_vfilters_user_first() {
+    let vf = merge_vfilters(
+        &[],
+        &["scale_cuda=1280:-2".into()],
+        Some("crop=1280:536"),
+        FilterOrder::UserFirst,
+        HwFrames::Cuda,
+        true,
+        false,
+        "p010le",
+    );
+    assert_eq!(
+        vf.as_deref(),
+        Some(
+            "hwdownload,format=p010le,crop=1280:536,hwupload_cuda,scale_cuda=1280:-2,\
+             hwdownload,format=p010le"
+        )
+    );
+
+    // nvenc can use gpu frames directly
+    let vf = merge_vfilters(
+        &[],
+        &["scale_cuda=1280:-2".into()],
+        Some("crop=1280:536"),
+        FilterOrder::UserFirst,
+        HwFrames::Cuda,
+        false,
+        true,
+        "nv12",
+    );
+    assert_eq!(
+        vf.as_deref(),
+        Some("crop=1280:536,hwupload_cuda,scale_cuda=1280:-2")
+    );
+}
+
+#[test]
+fn merge_vfilters_no_filters() {
+    assert_eq!(
+        merge_vfilters(
+            &[],
+            &[],
+            None,
+            FilterOrder::HwFirst,
+            HwFrames::Cuda,
+            false,
+            false,
+            "nv12"
+        ),
+        None
+    );
+    // software encoders need cuda decoded frames downloaded
+    assert_eq!(
+        merge_vfilters(
+            &[],
+            &[],
+            None,
+            FilterOrder::HwFirst,
+            HwFrames::Cuda,
+            true,
+            false,
+            "nv12"
+        )
+        .as_deref(),
+        Some("hwdownload,format=nv12")
+    );
+    assert_eq!(
+        merge_vfilters(
+            &[],
+            &[],
+            None,
+            FilterOrder::HwFirst,
+            HwFrames::Cuda,
+            true,
+            true,
+            "nv12"
+        ),
+        None
+    );
+}
+
+#[test]
+fn merge_vfilters_detelecine_first() {
+    let vf = merge_vfilters(
+        &split_vfilter(telecine::FILTERS),
+        &["scale_cuda=1280:-2".into()],
+        Some("crop=1280:536"),
+        FilterOrder::HwFirst,
+        HwFrames::Cuda,
+        true,
+        true,
+        "nv12",
+    );
+    assert_eq!(
+        vf.as_deref(),
+        Some(
+            "hwdownload,format=nv12,fieldmatch,yadif=deint=interlaced,decimate,\
+             hwupload_cuda,scale_cuda=1280:-2,hwdownload,format=nv12,crop=1280:536"
+        )
+    );
+}
+
+#[test]
+fn merge_vfilters_vulkan() {
+    let vf = merge_vfilters(
+        &[],
+        &[],
+        Some(&vulkan_vfilter("crop=1280:536,scale=960:-2,fps=24")),
+        FilterOrder::HwFirst,
+        HwFrames::Vulkan,
+        true,
+        true,
+        "nv12",
+    );
+    assert_eq!(
+        vf.as_deref(),
+        Some(
+            "libplacebo=w=cw:h=ch:crop_w=1280:crop_h=536,scale_vulkan=w=960:h=-2,\
+             hwdownload,format=nv12,fps=24,hwupload"
+        )
+    );
+    assert_eq!(
+        vulkan_filter("crop=1280:536:0:72").as_deref(),
+        Some("libplacebo=w=cw:h=ch:crop_w=1280:crop_h=536:crop_x=0:crop_y=72")
+    );
+    assert_eq!(vulkan_filter("scale=1280:-2:flags=lanczos"), None);
+}
+
+#[test]
+fn test_split_vfilter() {
+    assert_eq!(
+        split_vfilter("scale=1280:-1, drawtext=text='a,b',select=eq(n\\,0)"),
+        ["scale=1280:-1", "drawtext=text='a,b'", "select=eq(n\\,0)"]
+    );
+}
+
+fn try_parse_fps_vfilter(vfilter: &str) -> Option<f64> {
+    let fps_filter = vfilter
+        .split(',')
+        .find_map(|vf| vf.trim().strip_prefix("fps="))?
+        .trim();
+
+    match fps_filter {
+        "ntsc" => Some(30000.0 / 1001.0),
+        "pal" => Some(25.0),
+        "film" => Some(24.0),
+        "ntsc_film" => Some(24000.0 / 1001.0),
+        _ => crate::ffprobe::parse_frame_rate(fps_filter),
+    }
+}
+
+#[test]
+fn test_try_parse_fps_vfilter() {
+    let fps = try_parse_fps_vfilter("scale=1280:-1, fps=24, transpose=1").unwrap();
+    assert!((fps - 24.0).abs() < f64::EPSILON, "{fps:?}");
+
+    let fps = try_parse_fps_vfilter("scale=1280:-1, fps=ntsc, transpose=1").unwrap();
+    assert!((fps - 30000.0 / 1001.0).abs() < f64::EPSILON, "{fps:?}");
+}
+
+#[test]
+fn frame_interval_from_str() {
+    use std::str::FromStr;
+    let from_300 = KeyInterval::from_str("300").unwrap();
+    assert_eq!(from_300, KeyInterval::Frames(300));
+}
+
+#[test]
+fn duration_interval_from_str() {
+    use std::{str::FromStr, time::Duration};
+    let from_10s = KeyInterval::from_str("10s").unwrap();
+    assert_eq!(from_10s, KeyInterval::Duration(Duration::from_secs(10)));
+}
+
+#[test]
+fn film_grain_from_str() {
+    assert_eq!("auto".parse::<FilmGrain>().unwrap(), FilmGrain::Auto);
+    assert_eq!("8".parse::<FilmGrain>().unwrap(), FilmGrain::Level(8));
+    assert!("51".parse::<FilmGrain>().is_err());
+    assert!("grainy".parse::<FilmGrain>().is_err());
+}
+
+#[test]
+fn deinterlace_filters() {
+    let mut probe = Ffprobe {
+        duration: Ok(Duration::from_secs(60)),
+        has_audio: false,
+        max_audio_channels: None,
+        fps: Ok(25.0),
+        resolution: Some((720, 576)),
+        is_image: false,
+        animation: None,
+        trimmed_from: None,
+        pix_fmt: None,
+        field_order: Some("tt".into()),
+        size: None,
+        streams: vec![],
+    };
+    let enc = Encode::parse_from(["x", "-i", "vid.mkv"]);
+    assert_eq!(enc.pre_filters(&probe).unwrap(), ["bwdif=mode=send_frame"]);
+
+    let enc = Encode::parse_from(["x", "-i", "vid.mkv", "--cuda-decoder", "h264_cuvid"]);
+    assert!(enc.pre_filters(&probe).unwrap().is_empty());
+    assert_eq!(
+        enc.cuda_filters(&probe).unwrap(),
+        ["yadif_cuda=mode=send_frame"]
+    );
+    assert_eq!(
+        enc.reference_vfilter(&probe).unwrap().as_deref(),
+        Some("yadif=mode=send_frame")
+    );
+
+    let enc = Encode::parse_from(["x", "-i", "vid.mkv", "--deinterlace", "off"]);
+    assert!(enc.pre_filters(&probe).unwrap().is_empty());
+
+    probe.field_order = Some("progressive".into());
+    let enc = Encode::parse_from(["x", "-i", "vid.mkv"]);
+    assert!(enc.pre_filters(&probe).unwrap().is_empty());
+    let enc = Encode::parse_from(["x", "-i", "vid.mkv", "--deinterlace", "force"]);
+    assert_eq!(enc.pre_filters(&probe).unwrap(), ["bwdif=mode=send_frame"]);
+}
+
+#[test]
+fn denoise_filters() {
+    let probe = Ffprobe {
+        duration: Ok(Duration::from_secs(60)),
+        has_audio: false,
+        max_audio_channels: None,
+        fps: Ok(24.0),
+        resolution: Some((1280, 720)),
+        is_image: false,
+        animation: None,
+        trimmed_from: None,
+        pix_fmt: None,
+        field_order: None,
+        size: None,
+        streams: vec![],
+    };
+    let enc = Encode::parse_from([
+        "x",
+        "-i",
+        "vid.mkv",
+        "--denoise",
+        "medium",
+        "--vfilter",
+        "crop=1280:536",
+    ]);
+    assert_eq!(enc.pre_filters(&probe).unwrap(), ["hqdn3d=4:3:6:4.5"]);
+    assert_eq!(
+        enc.reference_vfilter(&probe).unwrap().as_deref(),
+        Some("hqdn3d=4:3:6:4.5,crop=1280:536")
+    );
+
+    // gpu denoise, the reference uses the software equivalent
+    let enc = Encode::parse_from([
+        "x",
+        "-i",
+        "vid.mkv",
+        "--denoise",
+        "light",
+        "--cuda-filters",
+        "scale=1280:-2",
+    ]);
+    assert!(enc.pre_filters(&probe).unwrap().is_empty());
+    assert_eq!(
+        enc.cuda_filters(&probe).unwrap(),
+        [
+            "bilateral_cuda=sigmaS=1:sigmaR=8:window_size=5",
+            "scale_cuda=1280:-2:interp_algo=lanczos"
+        ]
+    );
+    assert_eq!(
+        enc.reference_vfilter(&probe).unwrap().as_deref(),
+        Some("hqdn3d=2:1.5:3:2.25")
+    );
+}
+
+#[test]
+fn nvenc_high_bit_depth_pix_fmt() {
+    let mut probe = Ffprobe {
+        duration: Ok(Duration::from_secs(60)),
+        has_audio: false,
+        max_audio_channels: None,
+        fps: Ok(24.0),
+        resolution: Some((3840, 2160)),
+        is_image: false,
+        animation: None,
+        trimmed_from: None,
+        pix_fmt: Some("yuv420p10le".into()),
+        field_order: None,
+        size: None,
+        streams: vec![],
+    };
+    let enc = Encode::parse_from(["x", "-i", "vid.mkv", "-e", "hevc_nvenc"]);
+    let args = enc.to_ffmpeg_args(30.0, &probe).unwrap();
+    assert_eq!(args.pix_fmt, Some(PixelFormat::P010le));
+
+    // cuda frames stay p010le through the gpu filters
+    let enc = Encode::parse_from([
+        "x",
+        "-i",
+        "vid.mkv",
+        "-e",
+        "hevc_nvenc",
+        "--cuda-filters",
+        "scale=1920:-2,unsharp=5:5:0.5",
+    ]);
+    let args = enc.to_ffmpeg_args(30.0, &probe).unwrap();
+    assert_eq!(args.pix_fmt, None);
+    assert!(
+        args.vfilter
+            .as_deref()
+            .unwrap()
+            .contains("hwdownload,format=p010le"),
+        "{:?}",
+        args.vfilter
+    );
+
+    probe.pix_fmt = Some("yuv420p".into());
+    let enc = Encode::parse_from(["x", "-i", "vid.mkv", "-e", "hevc_nvenc"]);
+    assert_eq!(enc.to_ffmpeg_args(30.0, &probe).unwrap().pix_fmt, None);
+}
+
+#[test]
+fn default_pix_formats() {
+    use PixelFormat::*;
+    assert_eq!(
+        default_pix_format("libsvtav1", None, false),
+        Some(Yuv420p10le)
+    );
+    assert_eq!(
+        default_pix_format("libsvtav1", Some(Yuv420p), false),
+        Some(Yuv420p10le)
+    );
+
+    assert_eq!(default_pix_format("libx265", Some(Yuv420p), false), None);
+    assert_eq!(
+        default_pix_format("libx265", Some(Yuv420p10le), false),
+        Some(Yuv420p10le)
+    );
+    assert_eq!(
+        default_pix_format("libx265", Some(Yuv422p12le), false),
+        Some(Yuv422p10le)
+    );
+    assert_eq!(
+        default_pix_format("libvpx-vp9", Some(P010le), false),
+        Some(Yuv420p10le)
+    );
+
+    assert_eq!(
+        default_pix_format("hevc_nvenc", Some(Yuv420p10le), false),
+        Some(P010le)
+    );
+    assert_eq!(
+        default_pix_format("hevc_nvenc", Some(Yuv420p10le), true),
+        None
+    );
+    assert_eq!(
+        default_pix_format("av1_qsv", Some(Yuv420p12le), false),
+        Some(P010le)
+    );
+    assert_eq!(
+        default_pix_format("h264_nvenc", Some(Yuv420p10le), false),
+        None
+    );
+    assert_eq!(
+        default_pix_format("libx264", Some(Yuv420p10le), false),
+        None
+    );
+}
+
+#[test]
+fn validate_vfilters() {
+    let available: Vec<String> = ["scale", "crop", "scale_cuda", "scale_vulkan", "libplacebo"]
+        .map(String::from)
+        .into();
+    let available = Some(available.as_slice());
+
+    // software filters are wrapped with hwdownload/hwupload
+    validate_vfilter("scale=1280:-1,crop=1280:536", HwFrames::Cuda, available).unwrap();
+    validate_vfilter(
+        "scale_cuda=1280:-2,crop=1280:536",
+        HwFrames::Cuda,
+        available,
+    )
+    .unwrap();
+    validate_vfilter("scale_vulkan=w=1280:h=-2", HwFrames::Vulkan, available).unwrap();
+    validate_vfilter("scale@small=640:-1", HwFrames::Cuda, available).unwrap();
+
+    let err = validate_vfilter("scale_vulkan=w=1280:h=-2", HwFrames::Cuda, available).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "--vfilter scale_vulkan requires Vulkan frames, which aren't used with \
+         --cuda-decoder/--cuda-filters or software frames, use the software scale filter instead"
+    );
+    let err = validate_vfilter(
+        "crop=1280:536,scale_cuda=1280:-2",
+        HwFrames::Vulkan,
+        available,
+    )
+    .unwrap_err();
+    assert!(err.to_string().contains("requires CUDA frames"), "{err}");
+    let err = validate_vfilter("libplacebo", HwFrames::Cuda, available).unwrap_err();
+    assert!(!err.to_string().contains("instead"), "{err}");
+
+    let err =
+        validate_vfilter("scale=1280:-1,sclae=1280:-1", HwFrames::Cuda, available).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "--vfilter sclae is not available in ffmpeg, see `ffmpeg -filters`"
+    );
+    // can't list ffmpeg filters
+    validate_vfilter("sclae=1280:-1", HwFrames::Cuda, None).unwrap();
+}
+
+#[test]
+fn cuvid_offload_filters() {
+    let mut filters = vec![
+        "crop=1920:800:0:140".to_owned(),
+        "scale_cuda=1280:-2:interp_algo=bilinear".into(),
+        "hqdn3d".into(),
+    ];
+    assert_eq!(
+        cuvid_offload(&mut filters, Some((1920, 1080)), "lanczos"),
+        ["-crop", "140x140x0x0", "-resize", "1280x534"]
+    );
+    assert_eq!(filters, ["hqdn3d"]);
+
+    // centered crop, lanczos downscale isn't offloaded
+    let mut filters = vec![
+        "crop=1280:536".to_owned(),
+        "scale_cuda=960:-2:interp_algo=lanczos".into(),
+    ];
+    assert_eq!(
+        cuvid_offload(&mut filters, Some((1280, 720)), "lanczos"),
+        ["-crop", "92x92x0x0"]
+    );
+    assert_eq!(filters, ["scale_cuda=960:-2:interp_algo=lanczos"]);
+
+    let mut filters = vec!["scale_cuda=-2:720".to_owned()];
+    assert_eq!(
+        cuvid_offload(&mut filters, Some((1920, 1080)), "bilinear"),
+        ["-resize", "1280x720"]
+    );
+    assert!(filters.is_empty());
+
+    // upscales, odd crops, leading other filters & unknown resolution aren't offloaded
+    for (filter, res) in [
+        ("scale_cuda=3840:2160", Some((1920, 1080))),
+        ("scale_cuda=1280:720:format=nv12", Some((1920, 1080))),
+        ("crop=1920:801:0:139", Some((1920, 1080))),
+        ("crop=2000:800", Some((1920, 1080))),
+        ("yadif_cuda=mode=send_frame", Some((1920, 1080))),
+        ("crop=1920:800:0:140", None),
+    ] {
+        let mut filters = vec![filter.to_owned()];
+        assert!(
+            cuvid_offload(&mut filters, res, "bilinear").is_empty(),
+            "{filter}"
+        );
+        assert_eq!(filters, [filter]);
+    }
+}
+
+#[test]
+fn burn_subs_filters() {
+    let stream = |codec_type: &str, codec_name: &str| ffprobe::StreamInfo {
+        codec_type: codec_type.into(),
+        codec_name: Some(codec_name.into()),
+        language: None,
+        bit_rate: None,
+        video: None,
+    };
+    let probe = Ffprobe {
+        duration: Ok(Duration::from_secs(60)),
+        has_audio: true,
+        max_audio_channels: None,
+        fps: Ok(24.0),
+        resolution: Some((1920, 1080)),
+        is_image: false,
+        animation: None,
+        trimmed_from: None,
+        pix_fmt: None,
+        field_order: None,
+        size: None,
+        streams: vec![
+            stream("video", "h264"),
+            stream("audio", "aac"),
+            stream("subtitle", "hdmv_pgs_subtitle"),
+            stream("subtitle", "subrip"),
+        ],
+    };
+
+    let enc = Encode::parse_from([
+        "x",
+        "-i",
+        "C:\\vids\\vid.mkv",
+        "--burn-subs",
+        "auto",
+        "--vfilter",
+        "scale=1280:-2",
+    ]);
+    let filter = "scale=1280:-2,subtitles=filename='C\\:/vids/vid.mkv':si=1";
+    assert_eq!(enc.user_vfilter(&probe).unwrap().as_deref(), Some(filter));
+    assert_eq!(
+        enc.reference_vfilter(&probe).unwrap().as_deref(),
+        Some(filter)
+    );
+
+    let enc = Encode::parse_from(["x", "-i", "vid.mkv", "--burn-subs", "1"]);
+    assert_eq!(
+        enc.user_vfilter(&probe).unwrap().as_deref(),
+        Some("subtitles=filename='vid.mkv':si=1")
+    );
+    let enc = Encode::parse_from(["x", "-i", "vid.mkv", "--burn-subs", "0"]);
+    assert!(enc.user_vfilter(&probe).is_err());
+    let enc = Encode::parse_from(["x", "-i", "vid.mkv", "--burn-subs", "2"]);
+    assert!(enc.user_vfilter(&probe).is_err());
+
+    assert_eq!(
+        "subs.srt".parse::<BurnSubs>().unwrap(),
+        BurnSubs::File("subs.srt".into())
+    );
+}
+
+#[test]
+fn hw_fallback_args() {
+    assert_eq!(
+        software_filter(
+            "scale_cuda=1280:-2:interp_algo=bicubic:format=nv12",
+            "lanczos"
+        )
+        .as_deref(),
+        Some("scale=1280:-2:flags=bicubic")
+    );
+    assert_eq!(
+        software_filter("scale=1280:-2", "nearest").as_deref(),
+        Some("scale=1280:-2:flags=neighbor")
+    );
+    assert_eq!(
+        software_filter("yadif_cuda=mode=1", "lanczos").as_deref(),
+        Some("yadif=mode=1")
+    );
+    assert_eq!(software_filter("hwupload_cuda", "lanczos"), None);
+
+    // vaapi is the last hw encoder, so falls back to software
+    let enc = Encode::parse_from([
+        "x",
+        "-i",
+        "vid.mkv",
+        "-e",
+        "hevc_vaapi",
+        "--preset",
+        "4",
+        "--cuda-decoder",
+        "h264_cuvid",
+        "--cuda-filters",
+        "scale=1280:-2",
+        "--vfilter",
+        "fps=24",
+        "--enc-input",
+        "hwaccel=cuda",
+    ]);
+    let (fallback, desc) = enc.fallback_args().unwrap().unwrap();
+    assert_eq!(desc, "libx265 with software decoding");
+    assert_eq!(fallback.encoder.as_str(), "libx265");
+    assert_eq!(fallback.preset, None);
+    assert_eq!(fallback.cuda_decoder, None);
+    assert!(fallback.cuda_filters.is_empty());
+    assert!(fallback.enc_input_args.is_empty());
+    assert_eq!(
+        fallback.vfilter.as_deref(),
+        Some("scale=1280:-2:flags=lanczos,fps=24")
+    );
+    // nothing left to fall back to
+    assert!(fallback.fallback_args().unwrap().is_none());
+
+    let err = anyhow::anyhow!("No such filter: 'scale_cuda'");
+    let (_, warning) = enc.hw_fallback(&err).unwrap();
+    assert_eq!(
+        warning,
+        "No such filter: 'scale_cuda', falling back to libx265 with software decoding"
+    );
+    let off = Encode {
+        hw_fallback: HwFallback::Off,
+        ..enc
+    };
+    assert!(off.hw_fallback(&err).is_none());
+}
+
+/// Should use keyint & scd defaults for >3m inputs.
 #[test]
 fn svtav1_to_ffmpeg_args_default_over_3m() {
     let enc = Encode {
         encoder: Encoder("libsvtav1".into()),
         input: "vid.mp4".into(),
+        video_stream: None,
+        start: None,
+        duration: None,
+        profile: None,
         vfilter: Some("scale=320:-1,fps=film".into()),
         preset: None,
         pix_format: None,
@@ -737,6 +2630,18 @@ fn svtav1_to_ffmpeg_args_default_over_3m() {
         svt_args: vec!["film-grain=30".into()],
         enc_args: <_>::default(),
         enc_input_args: <_>::default(),
+        cuda_decoder: None,
+        cuda_filters: vec![],
+        cuda_scaling_method: "lanczos".into(),
+        cuda_surfaces: None,
+        filter_order: FilterOrder::HwFirst,
+        hw_fallback: HwFallback::On,
+        detelecine: Detelecine::Off,
+        deinterlace: Deinterlace::Auto,
+        auto_grain: false,
+        denoise: None,
+        burn_subs: None,
+        film_grain: None,
     };
 
     let probe = Ffprobe {
@@ -746,11 +2651,17 @@ fn svtav1_to_ffmpeg_args_default_over_3m() {
         fps: Ok(30.0),
         resolution: Some((1280, 720)),
         is_image: false,
+        animation: None,
+        trimmed_from: None,
         pix_fmt: None,
+        field_order: None,
+        size: None,
+        streams: vec![],
     };
 
     let FfmpegEncodeArgs {
         input,
+        video_stream: _,
         vcodec,
         vfilter,
         pix_fmt,
@@ -759,15 +2670,22 @@ fn svtav1_to_ffmpeg_args_default_over_3m() {
         output_args,
         input_args,
         video_only,
+        subtitles: _,
+        maps: _,
+        mp4_compat,
+        reproducible,
+        metadata: _,
     } = enc.to_ffmpeg_args(32.0, &probe).expect("to_ffmpeg_args");
 
     assert_eq!(&*vcodec, "libsvtav1");
     assert_eq!(input, enc.input);
-    assert_eq!(vfilter, Some("scale=320:-1,fps=film"));
+    assert_eq!(vfilter.as_deref(), Some("scale=320:-1,fps=film"));
     assert_eq!(crf, 32.0);
     assert_eq!(preset, Some("8".into()));
     assert_eq!(pix_fmt, Some(PixelFormat::Yuv420p10le));
     assert!(!video_only);
+    assert!(!mp4_compat);
+    assert!(!reproducible);
 
     assert!(
         output_args
@@ -792,14 +2710,30 @@ fn svtav1_to_ffmpeg_args_default_under_3m() {
     let enc = Encode {
         encoder: Encoder("libsvtav1".into()),
         input: "vid.mp4".into(),
+        video_stream: None,
+        start: None,
+        duration: None,
+        profile: None,
         vfilter: None,
         preset: Some("7".into()),
         pix_format: Some(PixelFormat::Yuv420p),
         keyint: None,
         scd: None,
         svt_args: vec![],
-        enc_args: <_>::default(),
+        enc_args: vec!["map=0:a:1".into()],
         enc_input_args: <_>::default(),
+        cuda_decoder: None,
+        cuda_filters: vec![],
+        cuda_scaling_method: "lanczos".into(),
+        cuda_surfaces: None,
+        filter_order: FilterOrder::HwFirst,
+        hw_fallback: HwFallback::On,
+        detelecine: Detelecine::Off,
+        deinterlace: Deinterlace::Auto,
+        auto_grain: false,
+        denoise: None,
+        burn_subs: None,
+        film_grain: None,
     };
 
     let probe = Ffprobe {
@@ -809,11 +2743,17 @@ fn svtav1_to_ffmpeg_args_default_under_3m() {
         fps: Ok(24.0),
         resolution: Some((1280, 720)),
         is_image: false,
+        animation: None,
+        trimmed_from: None,
         pix_fmt: None,
+        field_order: None,
+        size: None,
+        streams: vec![],
     };
 
     let FfmpegEncodeArgs {
         input,
+        video_stream: _,
         vcodec,
         vfilter,
         pix_fmt,
@@ -822,6 +2762,11 @@ fn svtav1_to_ffmpeg_args_default_under_3m() {
         output_args,
         input_args,
         video_only,
+        subtitles: _,
+        maps,
+        mp4_compat,
+        reproducible,
+        metadata: _,
     } = enc.to_ffmpeg_args(32.0, &probe).expect("to_ffmpeg_args");
 
     assert_eq!(&*vcodec, "libsvtav1");
@@ -831,11 +2776,18 @@ fn svtav1_to_ffmpeg_args_default_under_3m() {
     assert_eq!(preset, Some("7".into()));
     assert_eq!(pix_fmt, Some(PixelFormat::Yuv420p));
     assert!(!video_only);
+    assert!(!mp4_compat);
+    assert!(!reproducible);
 
     assert!(
         !output_args.iter().any(|a| a.as_str() == "-g"),
         "unexpected -g in {output_args:?}"
     );
+    assert_eq!(maps, [Arc::from("0:a:1")]);
+    assert!(
+        !output_args.iter().any(|a| a.as_str() == "-map"),
+        "unexpected -map in {output_args:?}"
+    );
     let svtargs_idx = output_args
         .iter()
         .position(|a| a.as_str() == "-svtav1-params")
@@ -848,8 +2800,41 @@ fn svtav1_to_ffmpeg_args_default_under_3m() {
     assert!(input_args.is_empty());
 }
 
+/// AMD AMF runtime library loaded by *_amf encoders.
+const AMF_RUNTIME: &str = match cfg!(windows) {
+    true => "amfrt64.dll",
+    false => "libamfrt64.so.1",
+};
+
+/// Returns true if the [`AMF_RUNTIME`] is in a system library directory.
+fn amf_runtime_available() -> bool {
+    static AVAILABLE: LazyLock<bool> = LazyLock::new(|| {
+        let mut dirs: Vec<PathBuf> = match cfg!(windows) {
+            true => env::var_os("SystemRoot")
+                .map(|root| PathBuf::from(root).join("System32"))
+                .into_iter()
+                .collect(),
+            false => [
+                "/usr/lib",
+                "/usr/lib64",
+                "/usr/lib/x86_64-linux-gnu",
+                "/opt/amdgpu-pro/lib64",
+                "/opt/amdgpu-pro/lib/x86_64-linux-gnu",
+            ]
+            .map(PathBuf::from)
+            .into(),
+        };
+        if let Some(paths) = env::var_os("LD_LIBRARY_PATH") {
+            dirs.extend(env::split_paths(&paths));
+        }
+        dirs.iter().any(|dir| dir.join(AMF_RUNTIME).is_file())
+    });
+    *AVAILABLE
+}
+
 fn get_cuvid_decoders() -> anyhow::Result<Vec<String>> {
-    let output = Command::new("ffmpeg")
+    let output = Command::new(program::ffmpeg())
+        .job_env()
         .args(["-hide_banner", "-decoders"])
         .output()
         .context("FFailed to execute ffmpeg for decoder list")?;
@@ -1,7 +1,15 @@
-use crate::command::args::PixelFormat;
-use anyhow::Context;
+use crate::{command::args::PixelFormat, ffprobe::Ffprobe};
+use anyhow::{Context, ensure};
 use clap::Parser;
-use std::{borrow::Cow, fmt::Display, sync::Arc, thread};
+use std::{
+    borrow::Cow,
+    fmt::{Display, Write},
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::Arc,
+    thread,
+};
 
 const DEFAULT_VMAF_FPS: f32 = 25.0;
 
@@ -16,8 +24,24 @@ pub struct Vmaf {
     #[arg(long = "vmaf", value_parser = parse_vmaf_arg)]
     pub vmaf_args: Vec<Arc<str>>,
 
-    /// Video resolution scale to use in VMAF analysis. If set, video streams will be bicubic
-    /// scaled to this during VMAF analysis. `auto` (default) automatically sets
+    /// VMAF model, overriding any --vmaf model=.
+    ///
+    /// * 1k: vmaf_v0.6.1, default for resolutions <= 2560x1440.
+    /// * 4k: vmaf_4k_v0.6.1, default for resolutions > 2560x1440.
+    /// * neg, 4k-neg: "no enhancement gain" variants, which don't reward sharpening.
+    /// * phone: vmaf_v0.6.1 with the phone transform, for small screen viewing.
+    /// * Other built-in libvmaf model versions, e.g. vmaf_b_v0.6.3.
+    /// * A model json file path.
+    /// * A model json http(s) url, downloaded once to the ab-av1 config dir.
+    ///
+    /// May be repeated to calculate several models in one pass, e.g.
+    /// `--vmaf-model neg --vmaf-model 1k`. The first model's score is used,
+    /// the vmaf command also reports the others.
+    #[arg(long)]
+    pub vmaf_model: Vec<VmafModelArg>,
+
+    /// Video resolution scale to use in VMAF analysis. If set, video streams will be scaled
+    /// to this, using --vmaf-scaler, during VMAF analysis. `auto` (default) automatically sets
     /// based on the model and input video resolution. `none` disables any scaling.
     /// `WxH` format may be used to specify custom scaling, e.g. `1920x1080`.
     ///
@@ -34,20 +58,59 @@ pub struct Vmaf {
     #[arg(long, default_value_t, value_parser = parse_vmaf_scale)]
     pub vmaf_scale: VmafScale,
 
+    /// Scaler used by --vmaf-scale.
+    ///
+    /// `lanczos` & `spline36` use the zimg `zscale` filter, which requires ffmpeg built
+    /// with libzimg. Higher quality scalers avoid the bicubic ringing & softness that biases
+    /// scores when comparing lower resolution encodes, e.g. ladder renditions.
+    #[arg(long, default_value_t)]
+    pub vmaf_scaler: VmafScaler,
+
+    /// Method used to pool per-frame VMAF scores into a sample score. Also pools
+    /// per-frame --metric-cmd scores.
+    ///
+    /// Non-mean pooling, e.g. `p5` or `min`, targets worst case quality. With `min`
+    /// the lowest sample score is used, otherwise samples scores are averaged.
+    #[arg(long, default_value_t)]
+    pub vmaf_pool: VmafPool,
+
+    /// Also calculate PSNR in the same ffmpeg pass as VMAF.
+    #[arg(long)]
+    pub also_psnr: bool,
+
+    /// Also calculate SSIM in the same ffmpeg pass as VMAF.
+    #[arg(long)]
+    pub also_ssim: bool,
+
     /// Frame rate override used to analyse both reference & distorted videos.
     /// Maps to ffmpeg `-r` input arg.
     ///
     /// Setting to 0 disables use.
     #[arg(long, default_value_t = DEFAULT_VMAF_FPS)]
     pub vmaf_fps: f32,
+
+    /// Decode the reference with NVDEC when scoring with CPU libvmaf, downloading frames
+    /// to system memory for filtering. Speeds up scoring when software decoding the
+    /// reference, e.g. 4K HEVC, is the bottleneck.
+    ///
+    /// Ignored if the reference codec or pixel format isn't NVDEC decodable, or when
+    /// scoring with libvmaf_cuda, which already uses NVDEC.
+    #[arg(long)]
+    pub reference_nvdec: bool,
 }
 
 impl Default for Vmaf {
     fn default() -> Self {
         Self {
             vmaf_args: <_>::default(),
+            vmaf_model: vec![],
             vmaf_scale: <_>::default(),
+            vmaf_scaler: <_>::default(),
+            vmaf_pool: <_>::default(),
+            also_psnr: false,
+            also_ssim: false,
             vmaf_fps: DEFAULT_VMAF_FPS,
+            reference_nvdec: false,
         }
     }
 }
@@ -55,8 +118,22 @@ impl Default for Vmaf {
 impl std::hash::Hash for Vmaf {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
         self.vmaf_args.hash(state);
+        if !self.vmaf_model.is_empty() {
+            self.vmaf_model.hash(state);
+        }
         self.vmaf_scale.hash(state);
+        // bicubic not hashed to retain cached results from before --vmaf-scaler
+        if self.vmaf_scaler != VmafScaler::Bicubic {
+            self.vmaf_scaler.hash(state);
+        }
+        if self.vmaf_pool != VmafPool::Mean {
+            self.vmaf_pool.hash(state);
+        }
+        if self.also_psnr || self.also_ssim {
+            (self.also_psnr, self.also_ssim).hash(state);
+        }
         self.vmaf_fps.to_ne_bytes().hash(state);
+        // reference_nvdec not hashed, decoding is lossless either way
     }
 }
 
@@ -70,13 +147,124 @@ impl Vmaf {
     }
 
     /// Returns ffmpeg `filter_complex`/`lavfi` value for calculating vmaf.
+    ///
+    /// With `ref_nvdec` the reference is expected to be NVDEC decoded into CUDA memory
+    /// & is downloaded as that pixel format, see [`Self::reference_nvdec`].
     pub fn ffmpeg_lavfi(
         &self,
         distorted_res: Option<(u32, u32)>,
         pix_fmt: Option<PixelFormat>,
         ref_vfilter: Option<&str>,
+        ref_nvdec: Option<&'static str>,
+    ) -> String {
+        let mode = match ref_nvdec {
+            Some(download) => Lavfi::CpuNvdecRef(download),
+            None => Lavfi::Cpu,
+        };
+        self.lavfi(distorted_res, pix_fmt, ref_vfilter, mode)
+    }
+
+    /// Returns the distinct --vmaf-model models, in order.
+    pub fn vmaf_models(&self) -> Vec<&VmafModelArg> {
+        let mut models: Vec<&VmafModelArg> = Vec::with_capacity(self.vmaf_model.len());
+        for model in &self.vmaf_model {
+            if !models.contains(&model) {
+                models.push(model);
+            }
+        }
+        models
+    }
+
+    /// Returns the libvmaf `n_subsample` arg value, `1` if unset, i.e. every frame is scored.
+    pub fn n_subsample(&self) -> u32 {
+        self.n_subsample_arg().unwrap_or(1).max(1)
+    }
+
+    /// Returns the `--vmaf n_subsample=` value, if set.
+    pub fn n_subsample_arg(&self) -> Option<u32> {
+        self.vmaf_args
+            .iter()
+            .flat_map(|arg| arg.split(':'))
+            .find_map(|opt| opt.strip_prefix("n_subsample=")?.parse().ok())
+    }
+
+    /// Returns these args with libvmaf `n_subsample` set to `n`, replacing any existing value.
+    pub fn with_n_subsample(mut self, n: u32) -> Self {
+        for arg in &mut self.vmaf_args {
+            if arg.contains("n_subsample=") {
+                let opts: Vec<_> = arg
+                    .split(':')
+                    .filter(|opt| !opt.starts_with("n_subsample="))
+                    .collect();
+                *arg = opts.join(":").into();
+            }
+        }
+        self.vmaf_args.retain(|arg| !arg.is_empty());
+        self.vmaf_args.push(format!("n_subsample={n}").into());
+        self
+    }
+
+    /// Returns the pixel format to download NVDEC decoded reference frames as,
+    /// if --reference-nvdec & the `reference` is NVDEC decodable.
+    pub fn reference_nvdec(&self, reference: &Ffprobe) -> Option<&'static str> {
+        self.reference_nvdec
+            .then(|| crate::vmaf::nvdec_download_format(reference))
+            .flatten()
+    }
+
+    /// Returns true if `libvmaf_cuda` scoring can use NVDEC decoded frames kept in
+    /// CUDA memory, i.e. no software filtering is needed.
+    pub fn cuda_nvdec(&self, ref_vfilter: Option<&str>) -> bool {
+        ref_vfilter.is_none()
+            && !self.also_psnr
+            && !self.also_ssim
+            && self.vmaf_scaler.cuda_vfilter(0, 0).is_some()
+    }
+
+    /// Returns ffmpeg `filter_complex`/`lavfi` value for calculating vmaf using `libvmaf_cuda`.
+    ///
+    /// If [`Self::cuda_nvdec`] inputs should be NVDEC decoded to CUDA memory, e.g.
+    /// `-hwaccel cuda -hwaccel_output_format cuda`, & are converted & scaled there.
+    /// Otherwise streams are converted to yuv420p in software & uploaded to CUDA memory.
+    pub fn ffmpeg_lavfi_cuda(
+        &self,
+        distorted_res: Option<(u32, u32)>,
+        ref_vfilter: Option<&str>,
+    ) -> String {
+        let mode = match self.cuda_nvdec(ref_vfilter) {
+            true => Lavfi::CudaNvdec,
+            false => Lavfi::CudaUpload,
+        };
+        self.lavfi(distorted_res, Some(PixelFormat::Yuv420p), ref_vfilter, mode)
+    }
+
+    fn lavfi(
+        &self,
+        distorted_res: Option<(u32, u32)>,
+        pix_fmt: Option<PixelFormat>,
+        ref_vfilter: Option<&str>,
+        mode: Lavfi,
     ) -> String {
         let mut args = self.vmaf_args.clone();
+        let models = self.vmaf_models();
+        if !models.is_empty() {
+            // additional models are named so their scores don't clash with the first
+            let model = models
+                .iter()
+                .enumerate()
+                .map(|(idx, m)| match idx {
+                    0 => m.libvmaf_model(),
+                    _ => format!("{}\\:name={}", m.libvmaf_model(), m.name()),
+                })
+                .collect::<Vec<_>>()
+                .join("|");
+            let model = match model.contains(['\\', '|']) {
+                true => format!("'{model}'"),
+                false => model,
+            };
+            args.retain(|a| !a.starts_with("model="));
+            args.push(format!("model={model}").into());
+        }
         if !args.iter().any(|a| a.contains("n_threads")) {
             // default n_threads to all cores
             args.push(
@@ -88,7 +276,14 @@ impl Vmaf {
             );
         }
         let mut lavfi = args.join(":");
-        lavfi.insert_str(0, "libvmaf=shortest=true:ts_sync_mode=nearest:");
+        lavfi.insert_str(0, "=shortest=true:ts_sync_mode=nearest:");
+        lavfi.insert_str(
+            0,
+            match mode {
+                Lavfi::Cpu | Lavfi::CpuNvdecRef(_) => "libvmaf",
+                Lavfi::CudaUpload | Lavfi::CudaNvdec => "libvmaf_cuda",
+            },
+        );
 
         let mut model = VmafModel::from_args(&args);
         if let (None, Some((w, h))) = (model, distorted_res) {
@@ -104,22 +299,73 @@ impl Vmaf {
             Some(vf) if vf.ends_with(',') => vf.into(),
             Some(vf) => format!("{vf},").into(),
         };
-        let format = pix_fmt.map(|v| format!("format={v},")).unwrap_or_default();
-        let scale = self
-            .vf_scale(model.unwrap_or_default(), distorted_res)
-            .map(|(w, h)| format!("scale={w}:{h}:flags=bicubic,"))
-            .unwrap_or_default();
+        let vf_scale = self.vf_scale(model.unwrap_or_default(), distorted_res);
+        let (format, scale) = match mode {
+            // frames are already in CUDA memory, convert & scale there
+            Lavfi::CudaNvdec => (
+                "hwupload_cuda,".to_owned(),
+                vf_scale
+                    .and_then(|(w, h)| self.vmaf_scaler.cuda_vfilter(w, h))
+                    .unwrap_or_else(|| "scale_cuda=format=yuv420p,".into()),
+            ),
+            _ => (
+                pix_fmt.map(|v| format!("format={v},")).unwrap_or_default(),
+                vf_scale
+                    .map(|(w, h)| self.vmaf_scaler.vfilter(w, h))
+                    .unwrap_or_default(),
+            ),
+        };
+
+        let ref_download = match mode {
+            Lavfi::CpuNvdecRef(download) => format!("hwdownload,format={download},"),
+            _ => String::new(),
+        };
 
         // prefix:
+        // * Download NVDEC decoded reference frames if any
         // * Add reference-vfilter if any
         // * convert both streams to common pixel format
         // * scale to vmaf width if necessary
         // * sync presentation timestamp
-        let prefix = format!(
+        let mut prefix = format!(
             "[0:v]{format}{scale}setpts=PTS-STARTPTS,settb=AVTB[dis];\
-             [1:v]{format}{ref_vf}{scale}setpts=PTS-STARTPTS,settb=AVTB[ref];\
-             [dis][ref]"
+             [1:v]{ref_download}{format}{ref_vf}{scale}setpts=PTS-STARTPTS,settb=AVTB[ref];"
         );
+        let extra: Vec<_> = [(self.also_psnr, "psnr"), (self.also_ssim, "ssim")]
+            .into_iter()
+            .filter_map(|(enabled, filter)| enabled.then_some(filter))
+            .collect();
+        let (dis, reference) = match extra.len() {
+            0 => ("dis".to_owned(), "ref".to_owned()),
+            len => {
+                // split streams for each metric, libvmaf last so options may be appended
+                let n = len + 1;
+                let labels =
+                    |label: &str| (0..n).map(|i| format!("[{label}{i}]")).collect::<String>();
+                write!(
+                    prefix,
+                    "[dis]split={n}{};[ref]split={n}{};",
+                    labels("dis"),
+                    labels("ref")
+                )
+                .unwrap();
+                for (i, filter) in extra.into_iter().enumerate() {
+                    write!(prefix, "[dis{i}][ref{i}]{filter};").unwrap();
+                }
+                (format!("dis{len}"), format!("ref{len}"))
+            }
+        };
+        match mode {
+            Lavfi::CudaUpload => write!(
+                prefix,
+                "[{dis}]hwupload_cuda[dis_cuda];[{reference}]hwupload_cuda[ref_cuda];\
+                 [dis_cuda][ref_cuda]"
+            ),
+            Lavfi::Cpu | Lavfi::CpuNvdecRef(_) | Lavfi::CudaNvdec => {
+                write!(prefix, "[{dis}][{reference}]")
+            }
+        }
+        .unwrap();
 
         lavfi.insert_str(0, &prefix);
         lavfi
@@ -147,6 +393,19 @@ impl Vmaf {
     }
 }
 
+/// Where vmaf frames are filtered & scored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Lavfi {
+    /// Software filters & libvmaf.
+    Cpu,
+    /// NVDEC decoded reference downloaded as the pixel format, software filters & libvmaf.
+    CpuNvdecRef(&'static str),
+    /// Software filters, upload to CUDA memory & libvmaf_cuda.
+    CudaUpload,
+    /// NVDEC decoded frames, CUDA filters & libvmaf_cuda.
+    CudaNvdec,
+}
+
 /// Return the smallest ffmpeg vf `(w, h)` scale values so that at least one of the
 /// `target_w` or `target_h` bounds are met.
 fn minimally_scale((from_w, from_h): (u32, u32), (target_w, target_h): (u32, u32)) -> (i32, i32) {
@@ -193,6 +452,216 @@ impl Display for VmafScale {
     }
 }
 
+/// Scaler used to resize videos to the vmaf scale.
+#[derive(clap::ValueEnum, Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[clap(rename_all = "kebab-case")]
+pub enum VmafScaler {
+    /// ffmpeg swscale bicubic.
+    #[default]
+    Bicubic,
+    /// zscale lanczos.
+    Lanczos,
+    /// zscale spline36.
+    Spline36,
+}
+
+impl VmafScaler {
+    /// Returns the vfilter, with a trailing comma, scaling to `w`x`h`.
+    fn vfilter(self, w: i32, h: i32) -> String {
+        match self {
+            Self::Bicubic => format!("scale={w}:{h}:flags=bicubic,"),
+            Self::Lanczos => format!("zscale=w={w}:h={h}:filter=lanczos,"),
+            Self::Spline36 => format!("zscale=w={w}:h={h}:filter=spline36,"),
+        }
+    }
+
+    /// Returns the scale_cuda vfilter, with a trailing comma, scaling to `w`x`h` yuv420p.
+    /// `None` if scale_cuda has no equivalent algorithm.
+    fn cuda_vfilter(self, w: i32, h: i32) -> Option<String> {
+        let algo = match self {
+            Self::Bicubic => "bicubic",
+            Self::Lanczos => "lanczos",
+            Self::Spline36 => return None,
+        };
+        Some(format!(
+            "scale_cuda={w}:{h}:interp_algo={algo}:format=yuv420p,"
+        ))
+    }
+}
+
+impl Display for VmafScaler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Bicubic => "bicubic".fmt(f),
+            Self::Lanczos => "lanczos".fmt(f),
+            Self::Spline36 => "spline36".fmt(f),
+        }
+    }
+}
+
+/// Per-frame VMAF score pooling method.
+#[derive(clap::ValueEnum, Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[clap(rename_all = "kebab-case")]
+pub enum VmafPool {
+    /// Arithmetic mean, the libvmaf score.
+    #[default]
+    Mean,
+    /// Harmonic mean, more sensitive to low scoring frames.
+    #[value(alias = "harmonic_mean")]
+    HarmonicMean,
+    /// 5th percentile frame score.
+    P5,
+    /// 1st percentile frame score.
+    P1,
+    /// Lowest frame score.
+    Min,
+}
+
+impl Display for VmafPool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Mean => "mean".fmt(f),
+            Self::HarmonicMean => "harmonic-mean".fmt(f),
+            Self::P5 => "p5".fmt(f),
+            Self::P1 => "p1".fmt(f),
+            Self::Min => "min".fmt(f),
+        }
+    }
+}
+
+/// --vmaf-model built-in version or model file.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum VmafModelArg {
+    V1k,
+    V4k,
+    Neg,
+    Neg4k,
+    Phone,
+    /// Other built-in model version, e.g. "vmaf_b_v0.6.3".
+    Version(String),
+    /// Model json file, including downloaded urls.
+    Path(PathBuf),
+}
+
+impl std::str::FromStr for VmafModelArg {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        Ok(match s {
+            "1k" => Self::V1k,
+            "4k" => Self::V4k,
+            "neg" => Self::Neg,
+            "4k-neg" => Self::Neg4k,
+            "phone" => Self::Phone,
+            url if url.starts_with("https://") || url.starts_with("http://") => {
+                Self::Path(download_model(url)?)
+            }
+            path if path.ends_with(".json") || path.contains(['/', '\\']) => {
+                ensure!(Path::new(path).is_file(), "vmaf model {path} not found");
+                Self::Path(path.into())
+            }
+            version => {
+                ensure!(
+                    !version.is_empty() && !version.contains([':', '=', '\'']),
+                    "invalid vmaf model `{version}`"
+                );
+                Self::Version(version.into())
+            }
+        })
+    }
+}
+
+impl Display for VmafModelArg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::V1k => f.write_str("1k"),
+            Self::V4k => f.write_str("4k"),
+            Self::Neg => f.write_str("neg"),
+            Self::Neg4k => f.write_str("4k-neg"),
+            Self::Phone => f.write_str("phone"),
+            Self::Version(version) => f.write_str(version),
+            Self::Path(path) => path.display().fmt(f),
+        }
+    }
+}
+
+impl VmafModelArg {
+    /// Returns the libvmaf `model` option value, escaped for use within single quotes.
+    fn libvmaf_model(&self) -> String {
+        match self {
+            Self::V1k => "version=vmaf_v0.6.1".into(),
+            Self::V4k => "version=vmaf_4k_v0.6.1".into(),
+            Self::Neg => "version=vmaf_v0.6.1neg".into(),
+            Self::Neg4k => "version=vmaf_4k_v0.6.1neg".into(),
+            Self::Phone => "version=vmaf_v0.6.1\\:enable_transform=true".into(),
+            Self::Version(version) => format!("version={version}"),
+            Self::Path(path) => {
+                // escape for use as a quoted filter option value
+                let path = path
+                    .to_string_lossy()
+                    .replace('\\', "/")
+                    .replace(['\'', '|'], "")
+                    .replace(':', "\\:");
+                format!("path={path}")
+            }
+        }
+    }
+
+    /// libvmaf name of an additional model, e.g. "vmaf_neg".
+    fn name(&self) -> String {
+        let label = match self {
+            Self::Path(path) => path
+                .file_stem()
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            model => model.to_string(),
+        };
+        let label: String = label
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        format!("vmaf_{label}")
+    }
+}
+
+/// Download a model json `url` into the config dir, unless already downloaded.
+/// Returns the model path.
+fn download_model(url: &str) -> anyhow::Result<PathBuf> {
+    let name = url
+        .rsplit('/')
+        .next()
+        .filter(|n| n.ends_with(".json"))
+        .with_context(|| format!("vmaf model url {url} should end with a .json file name"))?;
+    let mut path = dirs::config_dir().context("no config dir found")?;
+    path.push("ab-av1");
+    path.push("vmaf-models");
+    // hash of the url avoids clashes between same named models
+    let hash = blake3::hash(url.as_bytes()).to_hex();
+    path.push(format!("{}-{name}", &hash[..8]));
+    if path.is_file() {
+        return Ok(path);
+    }
+
+    fs::create_dir_all(path.parent().unwrap())?;
+    let download = path.with_extension("json.download");
+    let out = Command::new("curl")
+        .arg("-fsSL")
+        .arg("-o")
+        .arg(&download)
+        .arg(url)
+        .stdin(Stdio::null())
+        .output()
+        .context("curl")?;
+    ensure!(
+        out.status.success(),
+        "GET {url} failed: {}",
+        String::from_utf8_lossy(&out.stderr).trim()
+    );
+    fs::rename(&download, &path)?;
+    eprintln!("Downloaded vmaf model {}", path.display());
+    Ok(path)
+}
+
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
 enum VmafModel {
     /// Default 1080p model.
@@ -210,11 +679,20 @@ impl VmafModel {
 
         match using_custom_model.len() {
             0 => None,
-            1 => Some(match using_custom_model.remove(0) {
-                v if v.ends_with("version=vmaf_v0.6.1") => Self::Vmaf1K,
-                v if v.ends_with("version=vmaf_4k_v0.6.1") => Self::Vmaf4K,
-                _ => Self::Custom,
-            }),
+            // the first of multiple models, e.g. "model='version=a|version=b\\:name=b'"
+            1 => Some(
+                match using_custom_model
+                    .remove(0)
+                    .split('|')
+                    .next()
+                    .unwrap_or_default()
+                {
+                    // also neg & phone variants
+                    v if v.contains("version=vmaf_v0.6.1") => Self::Vmaf1K,
+                    v if v.contains("version=vmaf_4k_v0.6.1") => Self::Vmaf4K,
+                    _ => Self::Custom,
+                },
+            ),
             _ => Some(Self::Custom),
         }
     }
@@ -230,7 +708,8 @@ fn vmaf_lavfi() {
         vmaf.ffmpeg_lavfi(
             None,
             Some(PixelFormat::Yuv420p),
-            Some("scale=1280:-1,fps=24")
+            Some("scale=1280:-1,fps=24"),
+            None,
         ),
         "[0:v]format=yuv420p,setpts=PTS-STARTPTS,settb=AVTB[dis];\
          [1:v]format=yuv420p,scale=1280:-1,fps=24,setpts=PTS-STARTPTS,settb=AVTB[ref];\
@@ -247,7 +726,7 @@ fn vmaf_lavfi_default() {
          [dis][ref]libvmaf=shortest=true:ts_sync_mode=nearest:n_threads={}",
         thread::available_parallelism().map_or(1, |p| p.get())
     );
-    assert_eq!(vmaf.ffmpeg_lavfi(None, None, None), expected);
+    assert_eq!(vmaf.ffmpeg_lavfi(None, None, None, None), expected);
 }
 
 #[test]
@@ -260,7 +739,7 @@ fn vmaf_lavfi_default_pix_fmt() {
         thread::available_parallelism().map_or(1, |p| p.get())
     );
     assert_eq!(
-        vmaf.ffmpeg_lavfi(None, Some(PixelFormat::Yuv420p10le), None),
+        vmaf.ffmpeg_lavfi(None, Some(PixelFormat::Yuv420p10le), None, None),
         expected
     );
 }
@@ -278,7 +757,7 @@ fn vmaf_lavfi_include_n_threads() {
         thread::available_parallelism().map_or(1, |p| p.get())
     );
     assert_eq!(
-        vmaf.ffmpeg_lavfi(None, Some(PixelFormat::Yuv420p), None),
+        vmaf.ffmpeg_lavfi(None, Some(PixelFormat::Yuv420p), None, None),
         expected
     );
 }
@@ -291,7 +770,7 @@ fn vmaf_lavfi_small_width() {
         ..<_>::default()
     };
     assert_eq!(
-        vmaf.ffmpeg_lavfi(Some((1280, 720)), Some(PixelFormat::Yuv420p), None),
+        vmaf.ffmpeg_lavfi(Some((1280, 720)), Some(PixelFormat::Yuv420p), None, None),
         "[0:v]format=yuv420p,scale=1920:-1:flags=bicubic,setpts=PTS-STARTPTS,settb=AVTB[dis];\
          [1:v]format=yuv420p,scale=1920:-1:flags=bicubic,setpts=PTS-STARTPTS,settb=AVTB[ref];\
          [dis][ref]libvmaf=shortest=true:ts_sync_mode=nearest:n_threads=5:n_subsample=4"
@@ -306,7 +785,7 @@ fn vmaf_lavfi_4k() {
         ..<_>::default()
     };
     assert_eq!(
-        vmaf.ffmpeg_lavfi(Some((3840, 2160)), Some(PixelFormat::Yuv420p), None),
+        vmaf.ffmpeg_lavfi(Some((3840, 2160)), Some(PixelFormat::Yuv420p), None, None),
         "[0:v]format=yuv420p,setpts=PTS-STARTPTS,settb=AVTB[dis];\
          [1:v]format=yuv420p,setpts=PTS-STARTPTS,settb=AVTB[ref];\
          [dis][ref]libvmaf=shortest=true:ts_sync_mode=nearest:n_threads=5:n_subsample=4:model=version=vmaf_4k_v0.6.1"
@@ -321,7 +800,7 @@ fn vmaf_lavfi_3k_upscale_to_4k() {
         ..<_>::default()
     };
     assert_eq!(
-        vmaf.ffmpeg_lavfi(Some((3008, 1692)), Some(PixelFormat::Yuv420p), None),
+        vmaf.ffmpeg_lavfi(Some((3008, 1692)), Some(PixelFormat::Yuv420p), None, None),
         "[0:v]format=yuv420p,scale=3840:-1:flags=bicubic,setpts=PTS-STARTPTS,settb=AVTB[dis];\
          [1:v]format=yuv420p,scale=3840:-1:flags=bicubic,setpts=PTS-STARTPTS,settb=AVTB[ref];\
          [dis][ref]libvmaf=shortest=true:ts_sync_mode=nearest:n_threads=5:model=version=vmaf_4k_v0.6.1"
@@ -340,7 +819,7 @@ fn vmaf_lavfi_small_width_custom_model() {
         ..<_>::default()
     };
     assert_eq!(
-        vmaf.ffmpeg_lavfi(Some((1280, 720)), Some(PixelFormat::Yuv420p), None),
+        vmaf.ffmpeg_lavfi(Some((1280, 720)), Some(PixelFormat::Yuv420p), None, None),
         "[0:v]format=yuv420p,setpts=PTS-STARTPTS,settb=AVTB[dis];\
          [1:v]format=yuv420p,setpts=PTS-STARTPTS,settb=AVTB[ref];\
          [dis][ref]libvmaf=shortest=true:ts_sync_mode=nearest:model=version=foo:n_threads=5:n_subsample=4"
@@ -363,13 +842,133 @@ fn vmaf_lavfi_custom_model_and_width() {
         ..<_>::default()
     };
     assert_eq!(
-        vmaf.ffmpeg_lavfi(Some((1280, 720)), Some(PixelFormat::Yuv420p), None),
+        vmaf.ffmpeg_lavfi(Some((1280, 720)), Some(PixelFormat::Yuv420p), None, None),
         "[0:v]format=yuv420p,scale=123:-1:flags=bicubic,setpts=PTS-STARTPTS,settb=AVTB[dis];\
          [1:v]format=yuv420p,scale=123:-1:flags=bicubic,setpts=PTS-STARTPTS,settb=AVTB[ref];\
          [dis][ref]libvmaf=shortest=true:ts_sync_mode=nearest:model=version=foo:n_threads=5:n_subsample=4"
     );
 }
 
+#[test]
+fn vmaf_lavfi_model_arg() {
+    let vmaf = Vmaf {
+        vmaf_args: vec!["model=version=foo".into(), "n_threads=5".into()],
+        vmaf_model: vec!["neg".parse().unwrap()],
+        ..<_>::default()
+    };
+    // neg is a 1k model so upscales small resolutions to 1080p
+    assert_eq!(
+        vmaf.ffmpeg_lavfi(Some((1280, 720)), Some(PixelFormat::Yuv420p), None, None),
+        "[0:v]format=yuv420p,scale=1920:-1:flags=bicubic,setpts=PTS-STARTPTS,settb=AVTB[dis];\
+         [1:v]format=yuv420p,scale=1920:-1:flags=bicubic,setpts=PTS-STARTPTS,settb=AVTB[ref];\
+         [dis][ref]libvmaf=shortest=true:ts_sync_mode=nearest:n_threads=5:model=version=vmaf_v0.6.1neg"
+    );
+
+    let model = |m: &str| m.parse::<VmafModelArg>().unwrap().libvmaf_model();
+    assert_eq!(model("4k-neg"), "version=vmaf_4k_v0.6.1neg");
+    assert_eq!(
+        model("phone"),
+        "version=vmaf_v0.6.1\\:enable_transform=true"
+    );
+    assert_eq!(model("vmaf_b_v0.6.3"), "version=vmaf_b_v0.6.3");
+    assert_eq!(
+        VmafModelArg::Path("C:\\models\\custom.json".into()).libvmaf_model(),
+        "path=C\\:/models/custom.json"
+    );
+    assert!("missing.json".parse::<VmafModelArg>().is_err());
+    assert!("version=foo".parse::<VmafModelArg>().is_err());
+
+    // several models in one pass, the first unnamed so its score is "vmaf"
+    let vmaf = Vmaf {
+        vmaf_args: vec!["n_threads=5".into()],
+        vmaf_model: ["neg", "1k", "phone", "neg"]
+            .map(|m| m.parse().unwrap())
+            .into(),
+        ..<_>::default()
+    };
+    assert_eq!(
+        vmaf.ffmpeg_lavfi(None, Some(PixelFormat::Yuv420p), None, None),
+        "[0:v]format=yuv420p,setpts=PTS-STARTPTS,settb=AVTB[dis];\
+         [1:v]format=yuv420p,setpts=PTS-STARTPTS,settb=AVTB[ref];\
+         [dis][ref]libvmaf=shortest=true:ts_sync_mode=nearest:n_threads=5:model='version=vmaf_v0.6.1neg\
+         |version=vmaf_v0.6.1\\:name=vmaf_1k\
+         |version=vmaf_v0.6.1\\:enable_transform=true\\:name=vmaf_phone'"
+    );
+}
+
+#[test]
+fn vmaf_lavfi_small_width_spline36() {
+    let vmaf = Vmaf {
+        vmaf_args: vec!["n_threads=5".into()],
+        vmaf_scaler: VmafScaler::Spline36,
+        ..<_>::default()
+    };
+    assert_eq!(
+        vmaf.ffmpeg_lavfi(Some((1280, 720)), Some(PixelFormat::Yuv420p), None, None),
+        "[0:v]format=yuv420p,zscale=w=1920:h=-1:filter=spline36,setpts=PTS-STARTPTS,settb=AVTB[dis];\
+         [1:v]format=yuv420p,zscale=w=1920:h=-1:filter=spline36,setpts=PTS-STARTPTS,settb=AVTB[ref];\
+         [dis][ref]libvmaf=shortest=true:ts_sync_mode=nearest:n_threads=5"
+    );
+}
+
+#[test]
+fn vmaf_lavfi_also_psnr_ssim() {
+    let vmaf = Vmaf {
+        vmaf_args: vec!["n_threads=5".into()],
+        also_psnr: true,
+        also_ssim: true,
+        ..<_>::default()
+    };
+    assert_eq!(
+        vmaf.ffmpeg_lavfi(None, Some(PixelFormat::Yuv420p), None, None),
+        "[0:v]format=yuv420p,setpts=PTS-STARTPTS,settb=AVTB[dis];\
+         [1:v]format=yuv420p,setpts=PTS-STARTPTS,settb=AVTB[ref];\
+         [dis]split=3[dis0][dis1][dis2];[ref]split=3[ref0][ref1][ref2];\
+         [dis0][ref0]psnr;[dis1][ref1]ssim;\
+         [dis2][ref2]libvmaf=shortest=true:ts_sync_mode=nearest:n_threads=5"
+    );
+}
+
+#[test]
+fn vmaf_lavfi_cuda() {
+    let vmaf = Vmaf {
+        vmaf_args: vec!["n_threads=5".into()],
+        ..<_>::default()
+    };
+    // reference vfilter requires software filtering
+    assert_eq!(
+        vmaf.ffmpeg_lavfi_cuda(Some((1280, 720)), Some("crop=1280:720")),
+        "[0:v]format=yuv420p,scale=1920:-1:flags=bicubic,setpts=PTS-STARTPTS,settb=AVTB[dis];\
+         [1:v]format=yuv420p,crop=1280:720,scale=1920:-1:flags=bicubic,setpts=PTS-STARTPTS,settb=AVTB[ref];\
+         [dis]hwupload_cuda[dis_cuda];[ref]hwupload_cuda[ref_cuda];\
+         [dis_cuda][ref_cuda]libvmaf_cuda=shortest=true:ts_sync_mode=nearest:n_threads=5"
+    );
+}
+
+#[test]
+fn vmaf_lavfi_cuda_nvdec() {
+    let vmaf = Vmaf {
+        vmaf_args: vec!["n_threads=5".into()],
+        ..<_>::default()
+    };
+    assert!(vmaf.cuda_nvdec(None));
+    assert!(!vmaf.cuda_nvdec(Some("scale=1280:-1")));
+    assert_eq!(
+        vmaf.ffmpeg_lavfi_cuda(Some((1280, 720)), None),
+        "[0:v]hwupload_cuda,scale_cuda=1920:-1:interp_algo=bicubic:format=yuv420p,\
+         setpts=PTS-STARTPTS,settb=AVTB[dis];\
+         [1:v]hwupload_cuda,scale_cuda=1920:-1:interp_algo=bicubic:format=yuv420p,\
+         setpts=PTS-STARTPTS,settb=AVTB[ref];\
+         [dis][ref]libvmaf_cuda=shortest=true:ts_sync_mode=nearest:n_threads=5"
+    );
+    assert_eq!(
+        vmaf.ffmpeg_lavfi_cuda(Some((1920, 1080)), None),
+        "[0:v]hwupload_cuda,scale_cuda=format=yuv420p,setpts=PTS-STARTPTS,settb=AVTB[dis];\
+         [1:v]hwupload_cuda,scale_cuda=format=yuv420p,setpts=PTS-STARTPTS,settb=AVTB[ref];\
+         [dis][ref]libvmaf_cuda=shortest=true:ts_sync_mode=nearest:n_threads=5"
+    );
+}
+
 #[test]
 fn vmaf_lavfi_1080p() {
     let vmaf = Vmaf {
@@ -377,9 +976,42 @@ fn vmaf_lavfi_1080p() {
         ..<_>::default()
     };
     assert_eq!(
-        vmaf.ffmpeg_lavfi(Some((1920, 1080)), Some(PixelFormat::Yuv420p), None),
+        vmaf.ffmpeg_lavfi(Some((1920, 1080)), Some(PixelFormat::Yuv420p), None, None),
         "[0:v]format=yuv420p,setpts=PTS-STARTPTS,settb=AVTB[dis];\
          [1:v]format=yuv420p,setpts=PTS-STARTPTS,settb=AVTB[ref];\
          [dis][ref]libvmaf=shortest=true:ts_sync_mode=nearest:n_threads=5:n_subsample=4"
     );
 }
+
+#[test]
+fn vmaf_lavfi_ref_nvdec() {
+    let vmaf = Vmaf {
+        vmaf_args: vec!["n_threads=5".into()],
+        ..<_>::default()
+    };
+    assert_eq!(
+        vmaf.ffmpeg_lavfi(
+            Some((3840, 2160)),
+            Some(PixelFormat::Yuv420p10le),
+            Some("crop=3840:1600"),
+            Some("p010le"),
+        ),
+        "[0:v]format=yuv420p10le,setpts=PTS-STARTPTS,settb=AVTB[dis];\
+         [1:v]hwdownload,format=p010le,format=yuv420p10le,crop=3840:1600,setpts=PTS-STARTPTS,settb=AVTB[ref];\
+         [dis][ref]libvmaf=shortest=true:ts_sync_mode=nearest:n_threads=5:model=version=vmaf_4k_v0.6.1"
+    );
+}
+
+#[test]
+fn vmaf_with_n_subsample() {
+    let vmaf = Vmaf {
+        vmaf_args: vec!["n_threads=5:n_subsample=4".into(), "n_subsample=2".into()],
+        ..<_>::default()
+    }
+    .with_n_subsample(10);
+    assert_eq!(vmaf.n_subsample(), 10);
+    assert_eq!(
+        vmaf.vmaf_args,
+        ["n_threads=5".into(), "n_subsample=10".into()] as [Arc<str>; 2]
+    );
+}
@@ -1,22 +1,41 @@
+mod per_scene;
+mod report;
+
 use crate::{
+    api::{self, Progress},
     command::{
         PROGRESS_CHARS, args, crf_search,
         encode::{self, default_output_name},
-        sample_encode::{self, Work},
+        sample_encode::{self, ScoreKind, Work},
+        vmaf,
     },
     console_ext::style,
-    ffprobe,
+    cuda::caps,
+    disk_space,
+    ffprobe::Ffprobe,
     float::TerseF32,
-    temporary,
+    log,
+    process::job_env,
+    sink, stdin, temporary,
 };
-use anyhow::Context;
-use clap::Parser;
+use anyhow::{Context, ensure};
+use clap::{Parser, ValueHint};
 use console::style;
 use futures_util::StreamExt;
-use indicatif::{ProgressBar, ProgressStyle};
-use std::{pin::pin, sync::Arc, time::Duration};
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    pin::pin,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 const BAR_LEN: u64 = 1024 * 1024 * 1024;
+const SPINNER_RUNNING: &str =
+    "{spinner:.cyan.bold} {elapsed_precise:.bold} {prefix} {wide_bar:.cyan/blue} ({msg}eta {eta})";
+const SPINNER_FINISHED: &str =
+    "{spinner:.cyan.bold} {elapsed_precise:.bold} {prefix} {wide_bar:.cyan/blue} ({msg})";
 
 /// Automatically determine the best crf to deliver the min-vmaf and use it to encode a video or image.
 ///
@@ -26,7 +45,7 @@ const BAR_LEN: u64 = 1024 * 1024 * 1024;
 ///
 /// Use -v to print per-crf results.
 /// Use -vv to print per-sample results.
-#[derive(Parser)]
+#[derive(Parser, Clone)]
 #[clap(verbatim_doc_comment)]
 #[group(skip)]
 pub struct Args {
@@ -35,29 +54,128 @@ pub struct Args {
 
     #[clap(flatten)]
     pub encode: args::EncodeToOutput,
+
+    /// Split the --chunked input at scene changes & crf-search each scene separately
+    /// for consistent quality across e.g. dark, grainy & action scenes.
+    ///
+    /// Per-scene crf decisions are printed with --stdout-format json.
+    #[arg(long, requires = "chunked")]
+    pub per_scene_crf: bool,
+
+    /// After encoding, calculate the VMAF of the whole output vs the input, reporting
+    /// whether the real score met the target & warning if the crf-search predicted
+    /// score was off by more than --verify-tolerance.
+    #[arg(long)]
+    pub verify_vmaf: bool,
+
+    /// Score every Nth frame with --verify-vmaf, faster for long videos.
+    /// E.g. 5 scores 20% of the frames.
+    #[arg(long, requires = "verify_vmaf", value_parser = clap::value_parser!(u32).range(1..))]
+    pub verify_subsample: Option<u32>,
+
+    /// Maximum difference of the --verify-vmaf score from the crf-search predicted
+    /// score before warning.
+    #[arg(long, default_value_t = 1.0)]
+    pub verify_tolerance: f32,
+
+    /// Append a row per encoded file to this report, e.g. `--report report.csv`, with the
+    /// input & output size, crf, VMAF, duration, encode fps & GPU used.
+    /// A ".md" extension writes a markdown table, otherwise csv.
+    ///
+    /// Rows accumulate across runs, e.g. for every file of a batch.
+    #[arg(long, value_hint = ValueHint::FilePath)]
+    pub report: Option<PathBuf>,
 }
 
-pub async fn auto_encode(Args { mut search, encode }: Args) -> anyhow::Result<()> {
-    const SPINNER_RUNNING: &str = "{spinner:.cyan.bold} {elapsed_precise:.bold} {prefix} {wide_bar:.cyan/blue} ({msg}eta {eta})";
-    const SPINNER_FINISHED: &str =
-        "{spinner:.cyan.bold} {elapsed_precise:.bold} {prefix} {wide_bar:.cyan/blue} ({msg})";
+/// Auto-encode result.
+pub struct Encoded {
+    pub output: PathBuf,
+    /// Best crf-search result, `None` for --per-scene-crf encodes.
+    pub best: Option<crf_search::Sample>,
+}
+
+pub async fn auto_encode(args: Args) -> anyhow::Result<()> {
+    run(args, &MultiProgress::new()).await?;
+    Ok(())
+}
 
+/// Run auto-encode drawing progress bars within `progress`.
+///
+/// Returns the encoded output path & crf-search result.
+pub async fn run(mut args: Args, progress: &MultiProgress) -> anyhow::Result<Encoded> {
+    loop {
+        match run_once(args.clone(), progress).await {
+            Err(err) => match args.search.args.hw_fallback(&err) {
+                // crf-search again, as crf results don't carry over to another encoder
+                Some((fallback, warning)) => {
+                    progress.println(style!("Warning: {warning}").yellow().to_string())?;
+                    args.search.args = fallback;
+                }
+                None => return Err(err),
+            },
+            done => return done,
+        }
+    }
+}
+
+async fn run_once(
+    Args {
+        mut search,
+        encode,
+        per_scene_crf,
+        verify_vmaf,
+        verify_subsample,
+        verify_tolerance,
+        report,
+    }: Args,
+    progress: &MultiProgress,
+) -> anyhow::Result<Encoded> {
     let defaulting_output = encode.output.is_none();
-    let input_probe = Arc::new(ffprobe::probe(&search.args.input));
+    let emit_script = encode.emit_script.is_some();
+    ensure!(
+        !(emit_script && per_scene_crf),
+        "--emit-script is not supported with --per-scene-crf"
+    );
+    ensure!(
+        !verify_vmaf
+            || (search.min_xpsnr.is_none()
+                && search.min_psnr.is_none()
+                && search.min_metric.is_none()),
+        "--verify-vmaf requires a VMAF crf-search, not --min-xpsnr, --min-psnr or --min-metric"
+    );
+    ensure!(
+        !search.args.is_trimmed()
+            || !(verify_vmaf || per_scene_crf || encode.chunked || encode.resume),
+        "--start & --duration are not supported with --verify-vmaf, --per-scene-crf, --chunked or --resume"
+    );
+    ensure!(
+        search.args.video_stream.is_none() || !verify_vmaf,
+        "--video-stream is not supported with --verify-vmaf"
+    );
+    stdin::ensure_seekable(&search.args.input)?;
+    let input_probe = Arc::new(search.args.probe_input().await?);
 
     let output = encode.output.unwrap_or_else(|| {
         default_output_name(
             &search.args.input,
             &search.args.encoder,
-            input_probe.is_image,
+            input_probe.media_kind(),
         )
     });
+    encode::check_output(
+        &output,
+        &search.args.encoder,
+        encode.video_only,
+        &input_probe,
+    )?;
     search.sample.set_extension_from_output(&output);
 
-    let bar = ProgressBar::new(BAR_LEN).with_style(
-        ProgressStyle::default_bar()
-            .template(SPINNER_RUNNING)?
-            .progress_chars(PROGRESS_CHARS),
+    let bar = progress.add(
+        ProgressBar::new(BAR_LEN).with_style(
+            ProgressStyle::default_bar()
+                .template(SPINNER_RUNNING)?
+                .progress_chars(PROGRESS_CHARS),
+        ),
     );
     bar.enable_steady_tick(Duration::from_millis(100));
 
@@ -65,16 +183,340 @@ pub async fn auto_encode(Args { mut search, encode }: Args) -> anyhow::Result<()
         let out = shell_escape::escape(output.display().to_string().into());
         bar.println(style!("Encoding {out}").dim().to_string());
     }
+    search.print_libvmaf_fallback(&bar);
+    // --verify-vmaf scores every frame unless --verify-subsample
+    let verify_vmaf_args = search.vmaf.clone();
+    search.lengthen_subsampled(&input_probe, &bar);
 
-    let min_score = search.min_score();
-    let max_encoded_percent = search.max_encoded_percent;
+    if job_env::dry_run() {
+        bar.finish_and_clear();
+        let enc_args = search.args.clone();
+        let crf = crf_search::print_dry_run(search, &input_probe)?;
+        let encode_args = encode::Args {
+            args: enc_args,
+            crf,
+            encode: args::EncodeToOutput {
+                output: Some(output.clone()),
+                ..encode
+            },
+        };
+        encode::run(encode_args, input_probe, &bar).await?;
+        return Ok(Encoded { output, best: None });
+    }
+
+    let stdout_format = search.stdout_format;
     let enc_args = search.args.clone();
+    let input = search.args.input.clone();
+    let min_score = search.min_score();
+    let verify = match verify_vmaf {
+        true if search.libvmaf_fallback() => {
+            bar.println(
+                style!("Warning: ffmpeg lacks libvmaf, skipping --verify-vmaf")
+                    .yellow()
+                    .to_string(),
+            );
+            None
+        }
+        true => {
+            let mut score = search.score.clone();
+            if score.reference_vfilter.is_none() {
+                score.reference_vfilter = enc_args.reference_vfilter(&input_probe)?.map(Into::into);
+            }
+            Some(vmaf::Args {
+                reference: input.clone(),
+                distorted: output.clone(),
+                vmaf: match verify_subsample {
+                    Some(n) => verify_vmaf_args.with_n_subsample(n),
+                    None => verify_vmaf_args,
+                },
+                score,
+                vmaf_export: None,
+                worst_frames: 0,
+                save_comparisons: None,
+                sync_tolerance: Duration::from_millis(500),
+                stdout_format: sample_encode::StdoutFormat::Human,
+            })
+        }
+        false => None,
+    };
+
+    let search_start = Instant::now();
+    let (crf, scenes, best) = match per_scene_crf {
+        true => {
+            ensure!(
+                !input_probe.is_image,
+                "--per-scene-crf is not supported for images"
+            );
+            let scenes =
+                per_scene::search(&search, &input_probe, encode.scene_threshold, &bar).await?;
+            let (min_crf, max_crf) = scenes
+                .iter()
+                .map(|s| s.best.crf())
+                .fold((f32::MAX, f32::MIN), |(min, max), crf| {
+                    (min.min(crf), max.max(crf))
+                });
+            bar.set_style(
+                ProgressStyle::default_bar()
+                    .template(SPINNER_FINISHED)?
+                    .progress_chars(PROGRESS_CHARS),
+            );
+            bar.finish_with_message(format!(
+                "{} scenes, crf {}-{}",
+                scenes.len(),
+                style(TerseF32(min_crf)).green(),
+                style(TerseF32(max_crf)).green(),
+            ));
+            (min_crf, Some(scenes), None)
+        }
+        false => {
+            let best = search_crf(search, input_probe.clone(), &bar, progress).await?;
+            (best.crf(), None, Some(best))
+        }
+    };
+    // free sample space before the final encode, which may share the filesystem
+    temporary::clean_all().await;
+    let chunked = scenes.is_some() || encode.chunked || encode.chunks.is_some();
+    // copied or re-encoded audio & subtitles also go into the output
+    let non_video_size = match encode.video_only {
+        true => 0,
+        false => input_probe.non_video_size(),
+    };
+    ensure_encode_space(
+        &output,
+        best.as_ref(),
+        scenes.as_deref(),
+        non_video_size,
+        chunked,
+        encode.resume,
+    )?;
+
+    let bar = progress.add(
+        ProgressBar::new(12).with_style(
+            ProgressStyle::default_bar()
+                .template(SPINNER_RUNNING)?
+                .progress_chars(PROGRESS_CHARS),
+        ),
+    );
+    bar.set_prefix("Encoding");
+    bar.enable_steady_tick(Duration::from_millis(100));
+
+    let chunks = match &scenes {
+        Some(scenes) => Some(scenes.iter().map(|s| s.chunk).collect()),
+        None => encode.chunks.clone(),
+    };
+    let search_time = search_start.elapsed();
+    let encode_start = Instant::now();
+    // written after any --verify-vmaf
+    let provenance = encode.provenance;
+    let encoding = encode::run(
+        encode::Args {
+            args: enc_args.clone(),
+            crf,
+            encode: args::EncodeToOutput {
+                output: Some(output.clone()),
+                chunks,
+                provenance: false,
+                ..encode
+            },
+        },
+        input_probe.clone(),
+        &bar,
+    );
+    api::with_bar_progress(&bar, encoding, |progress| Progress::Encode { progress }).await?;
+
+    if let (Some(scenes), crf_search::StdoutFormat::Json) = (&scenes, stdout_format) {
+        per_scene::print_json(&output, input_probe.duration.clone()?, scenes);
+    }
+    if emit_script {
+        return Ok(Encoded { output, best });
+    }
+    let encode_time = encode_start.elapsed();
+    let verified = match verify {
+        Some(verify) => {
+            let predicted = best.as_ref().map(|b| b.enc.score);
+            Some(verify_score(verify, min_score, predicted, verify_tolerance, progress).await?)
+        }
+        None => None,
+    };
+    if provenance {
+        let mut json = encode::provenance::json(&enc_args, crf, encode_time);
+        json["search_secs"] = search_time.as_secs_f64().into();
+        if let Some(best) = &best {
+            json["crf_search"] = crf_search::result_json(best);
+        }
+        if let Some(vmaf) = verified {
+            json["verified_vmaf"] = vmaf.into();
+        }
+        encode::provenance::write(&output, &json)?;
+    }
+    if let Some(report) = &report {
+        let row = report_row(
+            &enc_args,
+            crf,
+            &input_probe,
+            &output,
+            best.as_ref(),
+            verified,
+            encode_time,
+        );
+        report::append(report, &row)?;
+    }
+    sink::emit(sink::Event::AutoEncode {
+        input: &input,
+        output: &output,
+        crf,
+        input_size: fs::metadata(&input).map_or(0, |m| m.len()),
+        output_size: fs::metadata(&output).map_or(0, |m| m.len()),
+    })
+    .await;
+    Ok(Encoded { output, best })
+}
+
+/// Returns the --report row of an encode.
+fn report_row<'a>(
+    enc_args: &'a args::Encode,
+    crf: f32,
+    input_probe: &Ffprobe,
+    output: &Path,
+    best: Option<&crf_search::Sample>,
+    verified: Option<f32>,
+    encode_time: Duration,
+) -> report::Row<'a> {
+    let uses_gpu = enc_args
+        .to_encoder_args(crf, input_probe)
+        .is_ok_and(|a| a.resources().nvenc || a.resources().nvdec);
+    let gpu = match uses_gpu {
+        false => String::new(),
+        true => match job_env::process_env("CUDA_VISIBLE_DEVICES") {
+            Some(device) => format!("cuda:{device}"),
+            None => caps::gpu().map_or_else(|| "cuda".into(), |gpu| gpu.name),
+        },
+    };
+    let predicted = best
+        .map(|b| &b.enc)
+        .filter(|enc| enc.score_kind == ScoreKind::Vmaf)
+        .map(|enc| enc.score);
+    report::Row {
+        input: &enc_args.input,
+        input_size: input_probe.input_len(&enc_args.input).unwrap_or_default(),
+        output_size: fs::metadata(output).map_or(0, |m| m.len()),
+        crf,
+        vmaf: verified.or(predicted),
+        duration: input_probe.duration.clone().ok(),
+        encode_fps: input_probe
+            .nframes()
+            .ok()
+            .map(|frames| frames as f64 / encode_time.as_secs_f64().max(0.001)),
+        gpu,
+    }
+}
+
+/// Ensure there's disk space for the predicted output, the predicted video plus
+/// `non_video_size`, & any chunk or resume segments written alongside it, combined if
+/// on the same filesystem.
+fn ensure_encode_space(
+    output: &Path,
+    best: Option<&crf_search::Sample>,
+    scenes: Option<&[per_scene::Scene]>,
+    non_video_size: u64,
+    chunked: bool,
+    resume: bool,
+) -> anyhow::Result<()> {
+    let video = match (best, scenes) {
+        (Some(best), _) => best.enc.prediction.max_size(),
+        (None, Some(scenes)) => scenes
+            .iter()
+            .map(|s| s.best.enc.prediction.max_size())
+            .sum(),
+        (None, None) => return Ok(()),
+    };
+    let output_dir = output.parent().unwrap_or(Path::new(""));
+    // chunks are encoded into the process temp dir, in --temp-dir or the working dir,
+    // & resume segments next to the output, both are then concatenated into the output
+    let chunk_dir = chunked.then(|| temporary::process_dir(None));
+    let segments = match (&chunk_dir, resume) {
+        (Some(dir), _) => (dir.parent().unwrap_or(dir), video),
+        (None, true) => (output_dir, video),
+        (None, false) => (output_dir, 0),
+    };
+    disk_space::ensure(&[(output_dir, video + non_video_size), segments])
+}
+
+/// Calculate the full VMAF of the output, see --verify-vmaf.
+///
+/// Prints whether the score met `min_score` & warns if the crf-search `predicted`
+/// score was off by more than `tolerance`.
+async fn verify_score(
+    args: vmaf::Args,
+    min_score: f32,
+    predicted: Option<f32>,
+    tolerance: f32,
+    progress: &MultiProgress,
+) -> anyhow::Result<f32> {
+    let bar = progress.add(
+        ProgressBar::new(1).with_style(
+            ProgressStyle::default_bar()
+                .template(SPINNER_RUNNING)?
+                .progress_chars(PROGRESS_CHARS),
+        ),
+    );
+    bar.set_prefix("Verifying");
+    bar.enable_steady_tick(Duration::from_millis(100));
+    let score = vmaf::score(args, &bar);
+    let vmaf::Scores { vmaf: score, .. } =
+        api::with_bar_progress(&bar, score, |progress| Progress::Vmaf { progress }).await?;
+
+    bar.set_style(
+        ProgressStyle::default_bar()
+            .template(SPINNER_FINISHED)?
+            .progress_chars(PROGRESS_CHARS),
+    );
+    let mut styled = style!("{score:.2}");
+    styled = match score < min_score {
+        true => styled.red(),
+        false => styled.green(),
+    };
+    bar.finish_with_message(format!("VMAF {styled}"));
+
+    if score < min_score {
+        progress.println(
+            style!("Warning: full VMAF {score:.2} is below the target {min_score}")
+                .yellow()
+                .to_string(),
+        )?;
+    }
+    if let Some(predicted) = predicted.filter(|p| (score - p).abs() > tolerance) {
+        progress.println(
+            style!(
+                "Warning: crf-search predicted VMAF {predicted:.2}, off by {:+.2}. \
+                 More representative samples may help, e.g. --samples or --sample-selection complexity",
+                score - predicted
+            )
+            .yellow()
+            .to_string(),
+        )?;
+    }
+    Ok(score)
+}
+
+/// Run crf-search returning the best result.
+async fn search_crf(
+    search: crf_search::Args,
+    input_probe: Arc<Ffprobe>,
+    bar: &ProgressBar,
+    progress: &MultiProgress,
+) -> anyhow::Result<crf_search::Sample> {
+    let min_score = search.min_score();
+    let min_frame_vmaf = search.min_frame_vmaf;
+    let max_encoded_percent = search.max_percent();
     let thorough = search.thorough;
-    let verbose = search.verbose;
 
-    let mut crf_search = pin!(crf_search::run(search, input_probe.clone()));
+    let mut crf_search = pin!(crf_search::run(search, input_probe));
     let mut best = None;
     while let Some(update) = crf_search.next().await {
+        if let Ok(update) = &update {
+            api::emit_crf_update(update, thorough);
+        }
         match update {
             Err(err) => {
                 if let crf_search::Error::NoGoodCrf { last } = &err {
@@ -129,19 +571,13 @@ pub async fn auto_encode(Args { mut search, encode }: Args) -> anyhow::Result<()
                 sample,
                 result,
             }) => {
-                if verbose
-                    .log_level()
-                    .is_some_and(|lvl| lvl > log::Level::Warn)
-                {
-                    result.print_attempt(&bar, sample, Some(crf))
+                if log::verbose(2) {
+                    result.print_attempt(bar, sample, Some(crf))
                 }
             }
             Ok(crf_search::Update::RunResult(result)) => {
-                if verbose
-                    .log_level()
-                    .is_some_and(|lvl| lvl > log::Level::Error)
-                {
-                    result.print_attempt(&bar, min_score, max_encoded_percent)
+                if log::verbose(1) {
+                    result.print_attempt(bar, min_score, min_frame_vmaf, max_encoded_percent)
                 }
             }
             Ok(crf_search::Update::Done(result)) => best = Some(result),
@@ -160,27 +596,14 @@ pub async fn auto_encode(Args { mut search, encode }: Args) -> anyhow::Result<()
         style(best.enc.score).green(),
         style(format!("{:.0}%", best.enc.encode_percent)).green(),
     ));
-    temporary::clean_all().await;
-
-    let bar = ProgressBar::new(12).with_style(
-        ProgressStyle::default_bar()
-            .template(SPINNER_RUNNING)?
-            .progress_chars(PROGRESS_CHARS),
-    );
-    bar.set_prefix("Encoding");
-    bar.enable_steady_tick(Duration::from_millis(100));
-
-    encode::run(
-        encode::Args {
-            args: enc_args,
-            crf: best.crf(),
-            encode: args::EncodeToOutput {
-                output: Some(output),
-                ..encode
-            },
-        },
-        input_probe,
-        &bar,
-    )
-    .await
+    if best.quantization_limited {
+        progress.println(style(best.quantization_limited_note()).dim().to_string())?;
+    }
+    if best.iteration_limited {
+        progress.println(style(best.iteration_limited_note()).yellow().to_string())?;
+    }
+    if let Some(note) = best.accepted_nearest_note() {
+        progress.println(style(note).yellow().to_string())?;
+    }
+    Ok(best)
 }
@@ -0,0 +1,138 @@
+//! Per-scene crf: crf-search each scene chunk separately for consistent quality.
+use super::BAR_LEN;
+use crate::{
+    chunk::{self, Chunk},
+    command::{
+        crf_search,
+        sample_encode::{self, ScoreKind, Work},
+    },
+    ffprobe::{self, Ffprobe},
+    float::TerseF32,
+    sample,
+};
+use anyhow::Context;
+use futures_util::StreamExt;
+use indicatif::ProgressBar;
+use std::{path::Path, pin::pin, sync::Arc, time::Duration};
+use tracing::info;
+
+/// A scene chunk & its crf-search result.
+#[derive(Debug, Clone)]
+pub struct Scene {
+    pub chunk: Chunk,
+    pub best: crf_search::Sample,
+}
+
+/// Split the input into scene chunks & crf-search each one.
+///
+/// Each search uses a stream copy of the chunk as the input, so starts from the
+/// keyframe at, or before, the chunk start.
+pub async fn search(
+    search: &crf_search::Args,
+    probe: &Ffprobe,
+    scene_threshold: f32,
+    bar: &ProgressBar,
+) -> anyhow::Result<Vec<Scene>> {
+    let input = &search.args.input;
+    let duration = probe.duration.clone()?;
+    let fps = probe.fps.clone()?;
+
+    bar.set_message("detecting scenes, ");
+    let video_stream = search.args.video_stream();
+    let chunks = chunk::plan(input, video_stream, probe, scene_threshold).await?;
+    let total = chunks.len();
+
+    let mut scenes = Vec::with_capacity(total);
+    for (idx, mut chunk) in chunks.into_iter().enumerate() {
+        let scene_n = idx + 1;
+        let frames = ((chunk.duration(duration).as_secs_f64() * fps).round() as u32).max(1);
+        let reference = sample::copy(
+            input,
+            video_stream,
+            chunk.start,
+            false,
+            frames,
+            search.sample.temp_dir.clone(),
+        )
+        .await?;
+
+        let mut args = search.clone();
+        // crop detected on the input applies to every scene
+        args.args.resolve_autocrop()?;
+        args.args.input = reference.clone();
+        // the scene sample only contains the selected video stream
+        args.args.video_stream = None;
+        let ref_probe = Arc::new(ffprobe::probe(&reference));
+
+        let mut crf_search = pin!(crf_search::run(args, ref_probe));
+        let mut best = None;
+        while let Some(update) = crf_search.next().await {
+            match update.with_context(|| format!("scene {scene_n}/{total}"))? {
+                crf_search::Update::Status {
+                    crf_run,
+                    crf,
+                    sample:
+                        sample_encode::Status {
+                            work,
+                            fps,
+                            progress,
+                            ..
+                        },
+                } => {
+                    let progress = crf_search::guess_progress(crf_run, progress, search.thorough);
+                    bar.set_position(
+                        ((idx as f64 * BAR_LEN as f64 + progress) / total as f64) as _,
+                    );
+                    bar.set_prefix(format!("scene {scene_n}/{total} crf {}", TerseF32(crf)));
+                    let label = work.fps_label();
+                    match work {
+                        Work::Encode if fps <= 0.0 => bar.set_message("encoding,  "),
+                        _ if fps <= 0.0 => bar.set_message(format!("{label},       ")),
+                        _ => bar.set_message(format!("{label} {fps} fps, ")),
+                    }
+                }
+                crf_search::Update::Done(result) => best = Some(result),
+                _ => {}
+            }
+        }
+        let best = best.context("no crf-search best?")?;
+        info!(
+            "scene {scene_n}/{total} {:?}-{:?} crf {} {} {:.2}",
+            chunk.start,
+            chunk.end.unwrap_or(duration),
+            TerseF32(best.crf()),
+            best.enc.score_kind,
+            best.enc.score,
+        );
+        chunk.crf = Some(best.crf());
+        scenes.push(Scene { chunk, best });
+    }
+    Ok(scenes)
+}
+
+/// Print per-scene crf decisions as json.
+pub fn print_json(output: &Path, duration: Duration, scenes: &[Scene]) {
+    let scenes: Vec<_> = scenes
+        .iter()
+        .map(|Scene { chunk, best }| {
+            let mut json = serde_json::json!({
+                "start": chunk.start.as_secs_f64(),
+                "end": chunk.end.unwrap_or(duration).as_secs_f64(),
+                "crf": best.crf(),
+                "predicted_encode_percent": best.enc.encode_percent,
+            });
+            match best.enc.score_kind {
+                ScoreKind::Vmaf => json["vmaf"] = best.enc.score.into(),
+                ScoreKind::Xpsnr => json["xpsnr"] = best.enc.score.into(),
+                ScoreKind::Psnr => json["psnr"] = best.enc.score.into(),
+                ScoreKind::Metric => json["metric"] = best.enc.score.into(),
+            }
+            json
+        })
+        .collect();
+    let json = serde_json::json!({
+        "output": output,
+        "scenes": scenes,
+    });
+    println!("{json}");
+}
@@ -0,0 +1,117 @@
+//! Per-file encode report, see --report.
+use anyhow::Context;
+use std::{fs::OpenOptions, io::Write, path::Path, sync::Mutex, time::Duration};
+
+const COLUMNS: [&str; 8] = [
+    "input",
+    "input_size",
+    "output_size",
+    "crf",
+    "vmaf",
+    "duration_secs",
+    "encode_fps",
+    "gpu",
+];
+
+/// A report row of a successful encode.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Row<'a> {
+    pub input: &'a Path,
+    pub input_size: u64,
+    pub output_size: u64,
+    pub crf: f32,
+    /// Verified or crf-search predicted VMAF.
+    pub vmaf: Option<f32>,
+    pub duration: Option<Duration>,
+    pub encode_fps: Option<f64>,
+    /// GPU used, e.g. "cuda:1", empty if the encode didn't use a GPU.
+    pub gpu: String,
+}
+
+impl Row<'_> {
+    fn values(&self) -> [String; 8] {
+        [
+            self.input.display().to_string(),
+            self.input_size.to_string(),
+            self.output_size.to_string(),
+            self.crf.to_string(),
+            self.vmaf.map(|v| format!("{v:.2}")).unwrap_or_default(),
+            self.duration
+                .map(|d| format!("{:.1}", d.as_secs_f64()))
+                .unwrap_or_default(),
+            self.encode_fps
+                .map(|fps| format!("{fps:.1}"))
+                .unwrap_or_default(),
+            self.gpu.clone(),
+        ]
+    }
+}
+
+/// Append `row` to the `report`, a markdown table if it has a ".md" extension
+/// otherwise csv, writing the header first if the report is new.
+pub fn append(report: &Path, row: &Row) -> anyhow::Result<()> {
+    // batch --jobs may finish concurrently
+    static LOCK: Mutex<()> = Mutex::new(());
+    let _lock = LOCK.lock().unwrap();
+
+    let markdown = report
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("md"));
+    let new = report.metadata().map_or(true, |m| m.len() == 0);
+    let mut lines = String::new();
+    if new {
+        lines += &format_line(&COLUMNS.map(String::from), markdown);
+        if markdown {
+            lines += &format_line(&COLUMNS.map(|_| "---".to_owned()), markdown);
+        }
+    }
+    lines += &format_line(&row.values(), markdown);
+
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(report)
+        .and_then(|mut file| file.write_all(lines.as_bytes()))
+        .with_context(|| format!("writing {}", report.display()))
+}
+
+fn format_line(values: &[String], markdown: bool) -> String {
+    match markdown {
+        true => {
+            let cells: Vec<_> = values.iter().map(|v| v.replace('|', "\\|")).collect();
+            format!("| {} |\n", cells.join(" | "))
+        }
+        false => {
+            let fields: Vec<_> = values
+                .iter()
+                .map(|v| match v.contains([',', '"', '\n']) {
+                    true => format!("\"{}\"", v.replace('"', "\"\"")),
+                    false => v.clone(),
+                })
+                .collect();
+            format!("{}\n", fields.join(","))
+        }
+    }
+}
+
+#[test]
+fn format_report_lines() {
+    let row = Row {
+        input: Path::new("films/a, b.mkv"),
+        input_size: 1000,
+        output_size: 400,
+        crf: 32.5,
+        vmaf: Some(95.123),
+        duration: Some(Duration::from_secs(90)),
+        encode_fps: None,
+        gpu: "cuda:1".into(),
+    };
+    assert_eq!(
+        format_line(&row.values(), false),
+        "\"films/a, b.mkv\",1000,400,32.5,95.12,90.0,,cuda:1\n"
+    );
+    assert_eq!(
+        format_line(&row.values(), true),
+        "| films/a, b.mkv | 1000 | 400 | 32.5 | 95.12 | 90.0 |  | cuda:1 |\n"
+    );
+}
@@ -0,0 +1,232 @@
+use crate::{
+    command::{auto_encode, encode::default_output_name},
+    console_ext::style,
+    ffmpeg,
+    ffprobe::{self, MediaKind},
+    process::job_env,
+    sink, temporary,
+};
+use anyhow::{Context, ensure};
+use clap::Parser;
+use futures_util::{StreamExt, stream};
+use indicatif::{HumanBytes, MultiProgress};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+/// Run auto-encode on every video in a directory, or matching a glob pattern,
+/// using the same args for each.
+///
+/// -i/--input should be a directory or a quoted glob, e.g. -i "videos/**/*.mkv".
+///
+/// Inputs that already have an encoded output, and previously encoded outputs
+/// themselves, are skipped. Failures do not stop the batch, a report of total
+/// size saved & any failures is printed at the end.
+#[derive(Parser)]
+#[clap(verbatim_doc_comment)]
+#[group(skip)]
+pub struct Args {
+    #[clap(flatten)]
+    pub auto_encode: auto_encode::Args,
+
+    /// Search input directories recursively.
+    #[arg(long)]
+    pub recursive: bool,
+
+    /// Only encode files whose names match this glob, e.g. --include "*.mkv".
+    /// May be specified multiple times.
+    #[arg(long)]
+    pub include: Vec<glob::Pattern>,
+
+    /// Skip files whose names match this glob, e.g. --exclude "*sample*".
+    /// May be specified multiple times.
+    #[arg(long)]
+    pub exclude: Vec<glob::Pattern>,
+
+    /// Number of files to encode concurrently.
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..))]
+    pub jobs: u16,
+}
+
+pub async fn batch(
+    Args {
+        auto_encode: args,
+        recursive,
+        include,
+        exclude,
+        jobs,
+    }: Args,
+) -> anyhow::Result<()> {
+    ensure!(
+        args.encode.output.is_none(),
+        "--output is not supported by batch, outputs use the default naming"
+    );
+    ensure!(
+        args.encode.emit_script.is_none(),
+        "--emit-script is not supported by batch"
+    );
+
+    let keep = args.search.sample.keep;
+    let encoder = &args.search.args.encoder;
+    let pre = ffmpeg::pre_extension_name(encoder.as_str());
+
+    let mut inputs = Vec::new();
+    let mut skipped = 0_usize;
+    for input in find_inputs(&args.search.args.input, recursive)? {
+        // non-unicode names are matched lossily rather than skipped
+        let Some(name) = input.file_name().map(|n| n.to_string_lossy()) else {
+            continue;
+        };
+        if (!include.is_empty() && !include.iter().any(|p| p.matches(&name)))
+            || exclude.iter().any(|p| p.matches(&name))
+        {
+            continue;
+        }
+        // previous output, e.g. vid.av1.mkv
+        let is_output = input
+            .file_stem()
+            .is_some_and(|s| s.to_string_lossy().ends_with(&format!(".{pre}")));
+        if is_output || default_output_name(&input, encoder, MediaKind::Video).exists() {
+            skipped += 1;
+            continue;
+        }
+        inputs.push(input);
+    }
+
+    let progress = MultiProgress::new();
+    // free job slots, see --process-env "{job}"
+    let slots = Mutex::new((0..jobs).rev().collect::<Vec<_>>());
+    let results: Vec<_> = stream::iter(inputs)
+        .map(|input| {
+            let mut args = args.clone();
+            args.search.args.input = input.clone();
+            let progress = &progress;
+            let slots = &slots;
+            temporary::scope(async move {
+                let slot = slots.lock().unwrap().pop().unwrap_or_default();
+                let result = job_env::scope(slot, auto_encode::run(args, progress))
+                    .await
+                    .map(|e| (e.output, e.best.and_then(|b| b.accepted_nearest_note())));
+                slots.lock().unwrap().push(slot);
+                temporary::clean(keep).await;
+                if let Err(err) = &result {
+                    progress.suspend(|| {
+                        eprintln!(
+                            "{} {}: {err}",
+                            style!("Failed").red().bold(),
+                            input.display()
+                        )
+                    });
+                }
+                (input, result)
+            })
+        })
+        .buffer_unordered(jobs.into())
+        .collect()
+        .await;
+
+    let mut encoded = 0_usize;
+    let mut input_size = 0;
+    let mut output_size = 0;
+    let mut failures = Vec::new();
+    // results accepted with --accept-nearest
+    let mut nearest = Vec::new();
+    for (input, result) in results {
+        match result {
+            Ok((output, nearest_note)) => {
+                encoded += 1;
+                if let Some(note) = nearest_note {
+                    nearest.push((input.clone(), note));
+                }
+                input_size += fs::metadata(&input).map(|m| m.len()).unwrap_or(0);
+                output_size += fs::metadata(&output).map(|m| m.len()).unwrap_or(0);
+            }
+            Err(err) => failures.push((input, err)),
+        }
+    }
+
+    if job_env::dry_run() {
+        // nothing was encoded, see --dry-run
+        ensure!(failures.is_empty(), "{} files failed", failures.len());
+        return Ok(());
+    }
+
+    sink::emit(sink::Event::Batch {
+        encoded,
+        skipped,
+        failed: failures.len(),
+        input_size,
+        output_size,
+        saved: input_size.saturating_sub(output_size),
+    })
+    .await;
+
+    print!(
+        "Encoded {encoded}, skipped {skipped}, failed {}",
+        failures.len()
+    );
+    match nearest.len() {
+        0 => println!(),
+        n => println!(", accepted nearest {n}"),
+    }
+    if encoded > 0 {
+        let percent = output_size as f64 * 100.0 / input_size.max(1) as f64;
+        println!(
+            "Saved {} ({} -> {}, {percent:.0}% of input size)",
+            HumanBytes(input_size.saturating_sub(output_size)),
+            HumanBytes(input_size),
+            HumanBytes(output_size),
+        );
+    }
+    for (input, note) in &nearest {
+        println!("{} {}: {note}", style!("Nearest").yellow(), input.display());
+    }
+    for (input, err) in &failures {
+        println!("{} {}: {err:#}", style!("Failed").red(), input.display());
+    }
+
+    ensure!(
+        failures.is_empty(),
+        "{} of {} files failed to encode",
+        failures.len(),
+        encoded + failures.len()
+    );
+    Ok(())
+}
+
+/// Returns video files in `input` directory, or matching the `input` glob pattern.
+fn find_inputs(input: &Path, recursive: bool) -> anyhow::Result<Vec<PathBuf>> {
+    let pattern = input.to_string_lossy();
+    let mut files = if pattern.contains(['*', '?', '[']) {
+        glob::glob(&pattern)
+            .context("invalid input glob")?
+            .filter_map(Result::ok)
+            .filter(|f| f.is_file())
+            .collect()
+    } else if input.is_dir() {
+        let mut files = vec![];
+        read_dir_files(input, recursive, &mut files)?;
+        files
+    } else {
+        anyhow::bail!("input must be a directory or glob pattern");
+    };
+    files.retain(|f| ffprobe::is_video(f).unwrap_or(false));
+    files.sort();
+    Ok(files)
+}
+
+fn read_dir_files(dir: &Path, recursive: bool, files: &mut Vec<PathBuf>) -> anyhow::Result<()> {
+    for entry in fs::read_dir(dir).with_context(|| format!("reading {}", dir.display()))? {
+        let path = entry?.path();
+        if path.is_dir() {
+            if recursive {
+                read_dir_files(&path, recursive, files)?;
+            }
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,177 @@
+use crate::command::sample_encode::cache;
+use anyhow::{Context, bail};
+use clap::{Parser, Subcommand, ValueHint};
+use std::{
+    fs,
+    io::{BufReader, Read, Write},
+    path::{Path, PathBuf},
+};
+
+/// Archive file name of the sample-encode cache entries.
+const CACHE_ENTRY: &str = "sample-encode-cache.jsonl";
+/// Archive file name of the config file, including profiles.
+const CONFIG_ENTRY: &str = "config.toml";
+
+/// Back up or restore the sample-encode cache & config file, including profiles,
+/// using a portable .tar.zst archive.
+///
+/// E.g. to keep cached crf-search results when migrating machines:
+/// `ab-av1 cache export ab-av1.tar.zst` then on the new machine
+/// `ab-av1 cache import ab-av1.tar.zst`.
+#[derive(Parser)]
+#[clap(verbatim_doc_comment)]
+#[group(skip)]
+pub struct Args {
+    #[command(subcommand)]
+    pub action: Action,
+}
+
+#[derive(Subcommand)]
+pub enum Action {
+    /// Write the cache & config to an archive.
+    Export {
+        /// Archive output file, e.g. "ab-av1.tar.zst".
+        #[arg(value_hint = ValueHint::FilePath)]
+        archive: PathBuf,
+    },
+    /// Restore the cache & config from an archive.
+    ///
+    /// Cache entries are merged into the existing cache. An existing config file is kept
+    /// unless using --overwrite-config.
+    Import {
+        /// Archive file written by `cache export`.
+        #[arg(value_hint = ValueHint::FilePath)]
+        archive: PathBuf,
+        /// Replace an existing config file with the archived config.
+        #[arg(long)]
+        overwrite_config: bool,
+    },
+}
+
+pub async fn cache(Args { action }: Args, config: Option<PathBuf>) -> anyhow::Result<()> {
+    match action {
+        Action::Export { archive } => export(&archive, config.as_deref()),
+        Action::Import {
+            archive,
+            overwrite_config,
+        } => import(&archive, config.as_deref(), overwrite_config),
+    }
+}
+
+fn export(archive: &Path, config: Option<&Path>) -> anyhow::Result<()> {
+    let mut entries = vec![];
+    let count = cache::export(&mut entries).context("reading sample-encode cache")?;
+    let config = match config {
+        Some(path) if path.exists() => {
+            Some(fs::read(path).with_context(|| format!("reading {}", path.display()))?)
+        }
+        _ => None,
+    };
+
+    let file =
+        fs::File::create(archive).with_context(|| format!("creating {}", archive.display()))?;
+    write_archive(file, &entries, config.as_deref())
+        .with_context(|| format!("writing {}", archive.display()))?;
+
+    println!("Exported {count} cache entries to {}", archive.display());
+    if config.is_some() {
+        println!("Exported config");
+    }
+    Ok(())
+}
+
+fn import(archive: &Path, config: Option<&Path>, overwrite_config: bool) -> anyhow::Result<()> {
+    let file = fs::File::open(archive).with_context(|| format!("opening {}", archive.display()))?;
+    let Archive {
+        cache_entries,
+        config: archived_config,
+    } = read_archive(file).with_context(|| format!("reading {}", archive.display()))?;
+
+    if let Some(entries) = cache_entries {
+        let (imported, skipped) =
+            cache::import(BufReader::new(&entries[..])).context("importing cache entries")?;
+        println!("Imported {imported} cache entries");
+        if skipped > 0 {
+            println!("Skipped {skipped} cache entries incompatible with this version");
+        }
+    }
+
+    if let Some(archived_config) = archived_config {
+        let path = config.context("no config path found, use --config")?;
+        if path.exists() && !overwrite_config {
+            println!(
+                "Kept existing config {}, use --overwrite-config to replace",
+                path.display()
+            );
+        } else {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            fs::write(path, archived_config)
+                .with_context(|| format!("writing {}", path.display()))?;
+            println!("Imported config {}", path.display());
+        }
+    }
+    Ok(())
+}
+
+/// Contents of a cache archive.
+#[derive(Debug, Default, PartialEq)]
+struct Archive {
+    cache_entries: Option<Vec<u8>>,
+    config: Option<Vec<u8>>,
+}
+
+fn write_archive(
+    out: impl Write,
+    cache_entries: &[u8],
+    config: Option<&[u8]>,
+) -> anyhow::Result<()> {
+    let mut tar = tar::Builder::new(zstd::Encoder::new(out, 0)?);
+    let mut append = |name: &str, data: &[u8]| {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as _);
+        header.set_mode(0o644);
+        header.set_cksum();
+        tar.append_data(&mut header, name, data)
+    };
+    append(CACHE_ENTRY, cache_entries)?;
+    if let Some(config) = config {
+        append(CONFIG_ENTRY, config)?;
+    }
+    tar.into_inner()?.finish()?;
+    Ok(())
+}
+
+fn read_archive(input: impl Read) -> anyhow::Result<Archive> {
+    let mut tar = tar::Archive::new(zstd::Decoder::new(input)?);
+    let mut archive = Archive::default();
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        let mut data = vec![];
+        entry.read_to_end(&mut data)?;
+        match entry.path()?.to_str() {
+            Some(CACHE_ENTRY) => archive.cache_entries = Some(data),
+            Some(CONFIG_ENTRY) => archive.config = Some(data),
+            name => bail!("unexpected archive entry {name:?}"),
+        }
+    }
+    Ok(archive)
+}
+
+#[test]
+fn archive_roundtrip() {
+    let mut data = vec![];
+    write_archive(&mut data, b"{\"key\":\"abc\"}\n", Some(b"preset = \"6\"\n")).unwrap();
+    assert_eq!(
+        read_archive(&data[..]).unwrap(),
+        Archive {
+            cache_entries: Some(b"{\"key\":\"abc\"}\n".to_vec()),
+            config: Some(b"preset = \"6\"\n".to_vec()),
+        }
+    );
+
+    let mut data = vec![];
+    write_archive(&mut data, b"", None).unwrap();
+    assert_eq!(read_archive(&data[..]).unwrap().config, None);
+}
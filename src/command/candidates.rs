@@ -0,0 +1,195 @@
+use crate::{
+    command::{PROGRESS_CHARS, args},
+    error::Error,
+    ffmpeg, ffprobe,
+    float::TerseF32,
+    process::{CommandExt, CommandJobEnv, FfmpegOut, ensure_success, program},
+    sample, stdin,
+};
+use anyhow::{Context, ensure};
+use clap::{Parser, ValueHint};
+use indicatif::{ProgressBar, ProgressStyle};
+use std::{fmt::Write, path::PathBuf, process::Stdio, time::Duration};
+use tokio::process::Command;
+use tokio_stream::StreamExt;
+
+/// Render a side by side comparison clip of a scene encoded at several crfs,
+/// each labelled with its crf. Useful to agree on acceptable quality before
+/// batch runs.
+///
+/// The comparison is encoded with near-lossless libx264 so the candidates'
+/// artifacts stay visible.
+#[derive(Parser)]
+#[clap(verbatim_doc_comment)]
+#[group(skip)]
+pub struct Args {
+    #[clap(flatten)]
+    pub args: args::Encode,
+
+    /// Candidate crfs, e.g. `--crfs 24,28,32`.
+    #[arg(long, value_delimiter = ',', required = true)]
+    pub crfs: Vec<f32>,
+
+    /// Start of the scene to compare. Defaults to the middle of the input.
+    #[arg(long, value_parser = humantime::parse_duration)]
+    pub scene_start: Option<Duration>,
+
+    /// Duration of the comparison clip.
+    #[arg(long, default_value = "5s", value_parser = humantime::parse_duration)]
+    pub scene_duration: Duration,
+
+    /// Also include the unencoded source as the first, "source" labelled, tile.
+    #[arg(long)]
+    pub source: bool,
+
+    /// Stack candidates vertically instead of side by side.
+    #[arg(long)]
+    pub vstack: bool,
+
+    /// Output file. Defaults to the input name with extension ".candidates.mp4".
+    #[arg(short, long, value_hint = ValueHint::FilePath)]
+    pub output: Option<PathBuf>,
+
+    /// Directory to store temporary sample data in.
+    /// Defaults to using the input's directory.
+    #[arg(long, env = "AB_AV1_TEMP_DIR", value_hint = ValueHint::DirPath)]
+    pub temp_dir: Option<PathBuf>,
+}
+
+pub async fn candidates(
+    Args {
+        args,
+        crfs,
+        scene_start,
+        scene_duration,
+        source,
+        vstack,
+        output,
+        temp_dir,
+    }: Args,
+) -> anyhow::Result<()> {
+    stdin::ensure_seekable(&args.input)?;
+    let probe = args.probe_input().await?;
+    ensure!(!probe.is_image, "candidates does not support images");
+    let duration = probe.duration.clone()?;
+    let fps = probe.fps.clone()?;
+    let start = scene_start.unwrap_or_else(|| duration.saturating_sub(scene_duration) / 2);
+    ensure!(start < duration, "--scene-start is after the input end");
+    let frames = ((scene_duration.as_secs_f64() * fps).round() as u32).max(1);
+    let output = output.unwrap_or_else(|| args.input.with_extension("candidates.mp4"));
+
+    let bar = ProgressBar::new(frames as u64 * crfs.len() as u64).with_style(
+        ProgressStyle::default_bar()
+            .template("{spinner:.cyan.bold} {elapsed_precise:.bold} {prefix} {wide_bar:.cyan/blue} ({msg}eta {eta})")?
+            .progress_chars(PROGRESS_CHARS)
+    );
+    bar.enable_steady_tick(Duration::from_millis(100));
+    bar.set_message("sampling, ");
+
+    let scene = sample::copy(
+        &args.input,
+        args.video_stream(),
+        args.start() + start,
+        false,
+        frames,
+        temp_dir.clone(),
+    )
+    .await?;
+    let scene_probe = ffprobe::probe(&scene);
+    let resolution = scene_probe.resolution.context("missing scene resolution")?;
+
+    let mut tiles = vec![];
+    if source {
+        tiles.push(("source".to_owned(), scene.clone()));
+    }
+    let mut enc_args = args.clone();
+    enc_args.resolve_autocrop()?;
+    enc_args.input = scene.clone();
+    enc_args.video_stream = None;
+    for (idx, crf) in crfs.into_iter().enumerate() {
+        bar.set_prefix(format!("crf {}", TerseF32(crf)));
+        bar.set_message("encoding, ");
+        let (encoded, mut enc) = ffmpeg::encode_sample(
+            enc_args.to_encoder_args(crf, &scene_probe)?,
+            temp_dir.clone(),
+            "mkv",
+        )?;
+        while let Some(progress) = enc.next().await {
+            if let FfmpegOut::Progress { frame, fps, .. } = progress? {
+                bar.set_position(idx as u64 * frames as u64 + frame.min(frames as _));
+                if fps > 0.0 {
+                    bar.set_message(format!("enc {fps} fps, "));
+                }
+            }
+        }
+        tiles.push((format!("crf {}", TerseF32(crf)), encoded));
+    }
+
+    bar.set_prefix("");
+    bar.set_message("stacking, ");
+    let labels: Vec<_> = tiles.iter().map(|(label, _)| label.as_str()).collect();
+    let mut cmd = Command::new(program::ffmpeg());
+    cmd.job_env().arg("-y");
+    for (_, tile) in &tiles {
+        cmd.arg2("-i", tile);
+    }
+    let out = cmd
+        .arg2("-filter_complex", stack_filter(&labels, resolution, vstack))
+        .arg2("-map", "[out]")
+        .arg2("-c:v", "libx264")
+        .arg2("-crf", 10)
+        .arg2("-preset", "fast")
+        .arg(&output)
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .map_err(Error::spawn("ffmpeg stack candidates"))?;
+    ensure_success("ffmpeg stack candidates", &out)?;
+    bar.finish_and_clear();
+
+    println!("Wrote {}", output.display());
+    Ok(())
+}
+
+/// Filter scaling each input to `resolution`, burning in its label & stacking them.
+fn stack_filter(labels: &[&str], (width, height): (u32, u32), vstack: bool) -> String {
+    let mut filter = String::new();
+    for (idx, label) in labels.iter().enumerate() {
+        _ = write!(
+            filter,
+            "[{idx}:v]scale={width}:{height},format=yuv420p,\
+             drawtext=text='{label}':x=16:y=16:fontsize=h/18:fontcolor=white:box=1:boxcolor=black@0.6:boxborderw=8\
+             [v{idx}];"
+        );
+    }
+    if labels.len() == 1 {
+        filter.push_str("[v0]null[out]");
+    } else {
+        for idx in 0..labels.len() {
+            _ = write!(filter, "[v{idx}]");
+        }
+        let stack = match vstack {
+            true => "vstack",
+            false => "hstack",
+        };
+        _ = write!(filter, "{stack}=inputs={}[out]", labels.len());
+    }
+    filter
+}
+
+#[test]
+fn stack_filter_hstack() {
+    assert_eq!(
+        stack_filter(&["crf 24", "crf 28.5"], (1280, 720), false),
+        "[0:v]scale=1280:720,format=yuv420p,\
+         drawtext=text='crf 24':x=16:y=16:fontsize=h/18:fontcolor=white:box=1:boxcolor=black@0.6:boxborderw=8[v0];\
+         [1:v]scale=1280:720,format=yuv420p,\
+         drawtext=text='crf 28.5':x=16:y=16:fontsize=h/18:fontcolor=white:box=1:boxcolor=black@0.6:boxborderw=8[v1];\
+         [v0][v1]hstack=inputs=2[out]"
+    );
+}
+
+#[test]
+fn stack_filter_single() {
+    assert!(stack_filter(&["crf 30"], (640, 360), true).ends_with("[v0];[v0]null[out]"));
+}
@@ -0,0 +1,174 @@
+use crate::{
+    command::{args, sample_encode::cache},
+    process::job_env,
+    temporary,
+};
+use anyhow::Context;
+use clap::{Parser, ValueHint};
+use indicatif::HumanBytes;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+/// Remove old ab-av1 artifacts according to retention limits.
+///
+/// * Temp directories, e.g. ".ab-av1-Hu8Jx0bGm1ds", older than --max-age.
+///   These are left by --keep or interrupted runs & include samples & per-frame score logs.
+///   Temp directories of crashed runs are removed regardless of age, those of running
+///   processes are never removed.
+/// * --resume checkpoints & segments older than --max-age.
+/// * The sample-encode cache, if larger than --cache-max-size & not in use.
+///
+/// Retention limits may be set in the config file, e.g. in a `[clean]` table
+/// `max-age = "14d"` & `cache-max-size = "5GB"`.
+#[derive(Parser)]
+#[clap(verbatim_doc_comment)]
+#[group(skip)]
+pub struct Args {
+    /// Directories to search for artifacts. Defaults to the current directory.
+    #[arg(value_hint = ValueHint::DirPath)]
+    pub dirs: Vec<PathBuf>,
+
+    /// Search directories recursively.
+    #[arg(long)]
+    pub recursive: bool,
+
+    /// Remove artifacts last modified longer ago than this.
+    #[arg(long, default_value = "7d", value_parser = humantime::parse_duration)]
+    pub max_age: Duration,
+
+    /// Maximum sample-encode cache size, e.g. "5GB". A larger cache is removed.
+    #[arg(long, value_parser = args::parse_size)]
+    pub cache_max_size: Option<u64>,
+}
+
+pub async fn clean(
+    Args {
+        mut dirs,
+        recursive,
+        max_age,
+        cache_max_size,
+    }: Args,
+) -> anyhow::Result<()> {
+    // global --dry-run prints what would be removed without removing anything
+    let dry_run = job_env::dry_run();
+    if dirs.is_empty() {
+        dirs.push(".".into());
+    }
+    let now = SystemTime::now();
+
+    let mut artifacts = vec![];
+    for dir in &dirs {
+        find_artifacts(dir, recursive, &mut artifacts)
+            .with_context(|| format!("searching {}", dir.display()))?;
+    }
+    artifacts.retain(|path| {
+        // temp dirs of running processes are kept, even if not modified recently
+        !temporary::in_use(path)
+            && (temporary::is_orphan(path)
+                || fs::metadata(path)
+                    .and_then(|m| m.modified())
+                    .is_ok_and(|modified| {
+                        now.duration_since(modified).unwrap_or_default() > max_age
+                    }))
+    });
+
+    let mut removed = 0;
+    for path in artifacts {
+        let size = disk_size(&path);
+        println!("Removing {} ({})", path.display(), HumanBytes(size));
+        if !dry_run {
+            remove(&path)?;
+        }
+        removed += size;
+    }
+
+    if let Some(max) = cache_max_size {
+        let db = cache::db_path();
+        let size = disk_size(&db);
+        if size > max && cache::db_in_use() {
+            println!(
+                "Skipping sample-encode cache {} ({} > {}) in use by a running ab-av1",
+                db.display(),
+                HumanBytes(size),
+                HumanBytes(max)
+            );
+        } else if size > max {
+            println!(
+                "Removing sample-encode cache {} ({} > {})",
+                db.display(),
+                HumanBytes(size),
+                HumanBytes(max)
+            );
+            if !dry_run {
+                remove(&db)?;
+            }
+            removed += size;
+        }
+    }
+
+    match dry_run {
+        true => println!("Would free {}", HumanBytes(removed)),
+        false => println!("Freed {}", HumanBytes(removed)),
+    }
+    Ok(())
+}
+
+/// Returns true for ab-av1 temp dirs & --resume checkpoint files.
+fn is_artifact(name: &str) -> bool {
+    name.starts_with(temporary::DIR_PREFIX)
+        || name.ends_with(".ab-av1-segments")
+        || name.ends_with(".ab-av1-progress.json")
+        || name.ends_with(".ab-av1-progress.json.tmp")
+}
+
+fn find_artifacts(dir: &Path, recursive: bool, found: &mut Vec<PathBuf>) -> anyhow::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let name = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or_default();
+        if is_artifact(name) {
+            found.push(path);
+        } else if recursive && path.is_dir() {
+            find_artifacts(&path, recursive, found)?;
+        }
+    }
+    Ok(())
+}
+
+fn remove(path: &Path) -> anyhow::Result<()> {
+    match path.is_dir() {
+        true => fs::remove_dir_all(path),
+        false => fs::remove_file(path),
+    }
+    .with_context(|| format!("removing {}", path.display()))
+}
+
+/// Total size of a file or directory contents.
+fn disk_size(path: &Path) -> u64 {
+    let Ok(meta) = fs::symlink_metadata(path) else {
+        return 0;
+    };
+    if !meta.is_dir() {
+        return meta.len();
+    }
+    fs::read_dir(path)
+        .into_iter()
+        .flatten()
+        .filter_map(Result::ok)
+        .map(|entry| disk_size(&entry.path()))
+        .sum()
+}
+
+#[test]
+fn artifact_names() {
+    assert!(is_artifact(".ab-av1-Hu8Jx0bGm1ds"));
+    assert!(is_artifact("vid.av1.mkv.ab-av1-progress.json"));
+    assert!(is_artifact("vid.av1.mkv.ab-av1-segments"));
+    assert!(!is_artifact("vid.av1.mkv"));
+    assert!(!is_artifact("ab-av1"));
+}
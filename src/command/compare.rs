@@ -0,0 +1,263 @@
+use crate::{
+    command::{
+        PROGRESS_CHARS,
+        args::Encoder,
+        crf_search::{self, Sample},
+        sample_encode::{self, Work},
+    },
+    console_ext::style,
+    float::TerseF32,
+    stdin,
+};
+use anyhow::ensure;
+use clap::Parser;
+use indicatif::{HumanBytes, HumanDuration, ProgressBar, ProgressStyle};
+use std::{fmt::Write, pin::pin, sync::Arc, time::Duration};
+use tokio_stream::StreamExt;
+
+/// Run the same crf-search with several encoders & compare the predicted size,
+/// encode time & score of each. E.g. to decide between CPU AV1 & GPU HEVC.
+///
+/// Each encoder searches its own default crf range, unless --min-crf/--max-crf
+/// are set, using the same samples, min score & max encoded percent.
+#[derive(Parser)]
+#[clap(verbatim_doc_comment)]
+#[group(skip)]
+#[command(mut_arg("encoder", |a| a.short(None).long("compare-base-encoder").hide(true)))]
+pub struct Args {
+    /// Encoders to compare, e.g. `-e libsvtav1 -e libx265 -e hevc_nvenc`.
+    #[arg(short = 'e', long = "encoder", required = true)]
+    pub encoders: Vec<Encoder>,
+
+    #[clap(flatten)]
+    pub search: crf_search::Args,
+}
+
+/// crf-search outcome of an encoder.
+struct Row {
+    encoder: Encoder,
+    result: Result<Sample, String>,
+}
+
+pub async fn compare(Args { encoders, search }: Args) -> anyhow::Result<()> {
+    let mut encoders = encoders;
+    dedup(&mut encoders);
+    ensure!(
+        encoders.len() > 1,
+        "compare requires at least 2 different encoders"
+    );
+
+    let bar = ProgressBar::new(crf_search::BAR_LEN).with_style(
+        ProgressStyle::default_bar()
+            .template("{spinner:.cyan.bold} {elapsed_precise:.bold} {prefix} {wide_bar:.cyan/blue} ({msg}eta {eta})")?
+            .progress_chars(PROGRESS_CHARS)
+    );
+    bar.enable_steady_tick(Duration::from_millis(100));
+
+    stdin::ensure_seekable(&search.args.input)?;
+    let probe = search.args.probe_input().await?;
+    ensure!(!probe.is_image, "compare does not support images");
+    let mut search = search;
+    search.print_libvmaf_fallback(&bar);
+    search.lengthen_subsampled(&probe, &bar);
+    let min_score = search.min_score();
+    let min_frame_vmaf = search.min_frame_vmaf;
+    let max_encoded_percent = search.max_percent();
+    let thorough = search.thorough;
+    let probe = Arc::new(probe);
+
+    let total = encoders.len();
+    let mut rows = Vec::with_capacity(total);
+    for (idx, encoder) in encoders.into_iter().enumerate() {
+        let vcodec = encoder.as_str();
+        bar.println(style!("Searching {vcodec}").dim().to_string());
+        let mut args = search.clone();
+        args.args.encoder = encoder.clone();
+        args.sample
+            .set_extension_from_input(&args.args.input, &encoder, &probe);
+
+        let mut run = pin!(crf_search::run(args, probe.clone()));
+        let mut result = Err("no crf-search result".to_owned());
+        while let Some(update) = run.next().await {
+            let update = match update {
+                Ok(update) => update,
+                Err(crf_search::Error::NoGoodCrf { last }) => {
+                    last.print_attempt(&bar, min_score, min_frame_vmaf, max_encoded_percent);
+                    result = Err("failed to find a suitable crf".to_owned());
+                    break;
+                }
+                Err(crf_search::Error::Other(err)) => {
+                    bar.println(style!("Warning: {vcodec}: {err}").yellow().to_string());
+                    result = Err(err.to_string());
+                    break;
+                }
+            };
+            match update {
+                crf_search::Update::Status {
+                    crf_run,
+                    crf,
+                    sample:
+                        sample_encode::Status {
+                            work,
+                            fps,
+                            progress,
+                            sample,
+                            samples,
+                            ..
+                        },
+                } => {
+                    let encoder_progress = crf_search::guess_progress(crf_run, progress, thorough)
+                        / crf_search::BAR_LEN as f64;
+                    bar.set_position(
+                        ((idx as f64 + encoder_progress.min(1.0)) * crf_search::BAR_LEN as f64
+                            / total as f64) as _,
+                    );
+                    bar.set_prefix(format!("{vcodec} crf {} {sample}/{samples}", TerseF32(crf)));
+                    match work {
+                        Work::Encode if fps <= 0.0 => bar.set_message("encoding,  "),
+                        _ if fps <= 0.0 => bar.set_message(format!("{},       ", work.fps_label())),
+                        _ => bar.set_message(format!("{} {fps} fps, ", work.fps_label())),
+                    }
+                }
+                crf_search::Update::SampleResult { .. } => {}
+                crf_search::Update::RunResult(sample) => {
+                    sample.print_attempt(&bar, min_score, min_frame_vmaf, max_encoded_percent)
+                }
+                crf_search::Update::Done(best) => {
+                    best.print_attempt(&bar, min_score, min_frame_vmaf, max_encoded_percent);
+                    result = Ok(best);
+                }
+            }
+        }
+        rows.push(Row { encoder, result });
+    }
+    bar.finish_and_clear();
+
+    print!("{}", table(&rows));
+    ensure!(
+        rows.iter().any(|r| r.result.is_ok()),
+        "No encoder found a suitable crf"
+    );
+    Ok(())
+}
+
+/// Remove repeated encoders, keeping the first.
+fn dedup(encoders: &mut Vec<Encoder>) {
+    let mut seen = Vec::with_capacity(encoders.len());
+    encoders.retain(|e| {
+        let new = !seen.contains(&e.as_str().to_owned());
+        seen.push(e.as_str().to_owned());
+        new
+    });
+}
+
+/// Aligned table of each encoder's predicted size, encode time & score.
+fn table(rows: &[Row]) -> String {
+    let mut lines = vec![[
+        "encoder".to_owned(),
+        "crf".into(),
+        "score".into(),
+        "size".into(),
+        "percent".into(),
+        "time".into(),
+        "fps".into(),
+    ]];
+    for Row { encoder, result } in rows {
+        lines.push(match result {
+            Ok(best) => [
+                encoder.as_str().to_owned(),
+                TerseF32(best.crf()).to_string(),
+                format!("{} {:.2}", best.enc.score_kind, best.enc.score),
+                HumanBytes(best.enc.prediction.size).to_string(),
+                format!("{:.0}%", best.enc.encode_percent),
+                HumanDuration(best.enc.prediction.time).to_string(),
+                match best.enc.prediction.fps {
+                    fps if fps > 0.0 => format!("{fps:.1}"),
+                    _ => "-".into(),
+                },
+            ],
+            Err(reason) => [
+                encoder.as_str().to_owned(),
+                "-".into(),
+                reason.clone(),
+                String::new(),
+                String::new(),
+                String::new(),
+                String::new(),
+            ],
+        });
+    }
+
+    let mut widths = [0; 7];
+    for line in &lines {
+        for (w, cell) in widths.iter_mut().zip(line) {
+            *w = (*w).max(cell.chars().count());
+        }
+    }
+    let mut out = String::new();
+    for line in &lines {
+        let mut row = String::new();
+        for (cell, w) in line.iter().zip(widths) {
+            _ = write!(row, "{cell:<w$}  ");
+        }
+        out.push_str(row.trim_end());
+        out.push('\n');
+    }
+    out
+}
+
+#[test]
+fn compare_table() {
+    let enc = |score, size, fps| Sample {
+        enc: sample_encode::Output {
+            score,
+            score_error: 0.0,
+            score_kind: sample_encode::ScoreKind::Vmaf,
+            min_frame_score: None,
+            frame_stats: None,
+            psnr: None,
+            ssim: None,
+            encode_percent: size as f64 / 20_000.0,
+            prediction: sample_encode::Prediction {
+                size,
+                size_error: 0,
+                time: Duration::from_secs(90),
+                fps,
+            },
+            from_cache: false,
+        },
+        crf_increment: 1.0,
+        q: 30,
+        quantization_limited: false,
+        iteration_limited: false,
+        score_shortfall: None,
+    };
+    let rows = [
+        Row {
+            encoder: "libsvtav1".parse().unwrap(),
+            result: Ok(enc(95.5, 1_000_000, 42.0)),
+        },
+        Row {
+            encoder: "hevc_nvenc".parse().unwrap(),
+            result: Ok(enc(95.25, 1_500_000, 0.0)),
+        },
+        Row {
+            encoder: "libx265".parse().unwrap(),
+            result: Err("failed to find a suitable crf".into()),
+        },
+    ];
+    assert_eq!(
+        table(&rows),
+        "encoder     crf  score                          size        percent  time       fps\n\
+         libsvtav1   30   VMAF 95.50                     976.56 KiB  50%      2 minutes  42.0\n\
+         hevc_nvenc  30   VMAF 95.25                     1.43 MiB    75%      2 minutes  -\n\
+         libx265     -    failed to find a suitable crf\n"
+    );
+
+    let mut encoders: Vec<Encoder> = ["libx265", "libsvtav1", "libx265"]
+        .iter()
+        .map(|e| e.parse().unwrap())
+        .collect();
+    dedup(&mut encoders);
+    assert_eq!(encoders.len(), 2);
+}
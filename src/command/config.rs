@@ -0,0 +1,120 @@
+use crate::config;
+use anyhow::{Context, ensure};
+use clap::{CommandFactory, Parser, Subcommand};
+use std::path::PathBuf;
+
+/// Show or modify the config file of persistent default args.
+///
+/// Config keys are long arg names used as defaults by all commands that
+/// accept them, e.g. `preset = "6"`. Command specific defaults may be set
+/// in a table named after the command, e.g. `[crf-search]`.
+///
+/// Named profiles, e.g. `[profile.anime]`, bundle values used with `--profile anime`.
+///
+/// Args specified on the command line override config values.
+///
+/// The default config path is `~/.config/ab-av1/config.toml` on linux,
+/// override with --config or AB_AV1_CONFIG.
+#[derive(Parser)]
+#[clap(verbatim_doc_comment)]
+#[group(skip)]
+pub struct Args {
+    #[command(subcommand)]
+    pub action: Action,
+}
+
+#[derive(Subcommand)]
+pub enum Action {
+    /// Print the config file path & contents.
+    Show,
+    /// Set a default arg value, e.g. `ab-av1 config set preset 6`.
+    Set {
+        /// Long arg name, e.g. "preset" or "min-vmaf".
+        key: String,
+        /// Value, parsed as toml or otherwise used as a string.
+        /// E.g. "6", "true", '["film-grain=8", "tune=0"]'.
+        value: String,
+        /// Only set the default for this command, e.g. "crf-search".
+        #[arg(long)]
+        command: Option<String>,
+        /// Set the value in this profile, e.g. "anime".
+        #[arg(long, conflicts_with = "command")]
+        profile: Option<String>,
+    },
+    /// Remove a default arg value.
+    Unset {
+        /// Long arg name, e.g. "preset".
+        key: String,
+        /// Remove the default for this command, e.g. "crf-search".
+        #[arg(long)]
+        command: Option<String>,
+        /// Remove the value from this profile, e.g. "anime".
+        #[arg(long, conflicts_with = "command")]
+        profile: Option<String>,
+    },
+}
+
+pub async fn config(Args { action }: Args, path: Option<PathBuf>) -> anyhow::Result<()> {
+    let path = path.context("no config path found, use --config")?;
+    let mut config = config::load(&path)?;
+
+    match action {
+        Action::Show => {
+            eprintln!("# {}", path.display());
+            print!("{}", toml::to_string(&config)?);
+        }
+        Action::Set {
+            key,
+            value,
+            command,
+            profile,
+        } => {
+            let cli = crate::cli::Cli::command();
+            ensure!(
+                config::is_valid_key(&cli, command.as_deref(), &key),
+                "unknown arg `{key}`"
+            );
+            let value = format!("v = {value}")
+                .parse::<toml::Table>()
+                .ok()
+                .and_then(|mut t| t.remove("v"))
+                .unwrap_or(toml::Value::String(value));
+
+            let table = match (command, profile) {
+                (Some(cmd), _) => subtable(&mut config, &cmd)?,
+                (_, Some(profile)) => subtable(subtable(&mut config, "profile")?, &profile)?,
+                _ => &mut config,
+            };
+            table.insert(key, value);
+            config::save(&path, &config)?;
+        }
+        Action::Unset {
+            key,
+            command,
+            profile,
+        } => {
+            let table = match (command, profile) {
+                (Some(cmd), _) => config.get_mut(&cmd).and_then(|t| t.as_table_mut()),
+                (_, Some(profile)) => config
+                    .get_mut("profile")
+                    .and_then(|t| t.get_mut(&profile))
+                    .and_then(|t| t.as_table_mut()),
+                _ => Some(&mut config),
+            };
+            if let Some(table) = table {
+                table.remove(&key);
+            }
+            config::save(&path, &config)?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns the named sub-table, inserting it if necessary.
+fn subtable<'a>(table: &'a mut toml::Table, name: &str) -> anyhow::Result<&'a mut toml::Table> {
+    table
+        .entry(name)
+        .or_insert_with(|| toml::Table::new().into())
+        .as_table_mut()
+        .with_context(|| format!("config `{name}` is not a table"))
+}
@@ -1,26 +1,36 @@
 mod err;
+pub mod planner;
+mod tui;
 
 pub use err::Error;
 
+use planner::{Plan, Planner};
+
 use crate::{
     command::{
         PROGRESS_CHARS, args,
-        sample_encode::{self, Work},
+        sample_encode::{self, ScoreKind, Work},
     },
+    compat,
     console_ext::style,
-    ffprobe::{self, Ffprobe},
+    ffprobe::Ffprobe,
     float::TerseF32,
+    log,
+    process::job_env,
+    sink, stdin, vmaf,
 };
 use anyhow::Context;
 use clap::{ArgAction, Parser};
 use console::style;
-use futures_util::{Stream, StreamExt};
-use indicatif::{HumanBytes, HumanDuration, ProgressBar, ProgressStyle};
-use log::info;
+use futures_util::{Stream, StreamExt, stream::LocalBoxStream};
+use indicatif::{HumanBytes, HumanDuration, ProgressBar, ProgressDrawTarget, ProgressStyle};
 use std::{io::IsTerminal, pin::pin, sync::Arc, time::Duration};
+use tracing::info;
 
-const BAR_LEN: u64 = 1024 * 1024 * 1024;
+pub const BAR_LEN: u64 = 1024 * 1024 * 1024;
 const DEFAULT_MIN_VMAF: f32 = 95.0;
+/// Default min PSNR, used when ffmpeg lacks libvmaf.
+const DEFAULT_MIN_PSNR: f32 = 42.0;
 
 /// Interpolated binary search using sample-encode to find the best crf
 /// value delivering min-vmaf & max-encoded-percent.
@@ -32,7 +42,7 @@ const DEFAULT_MIN_VMAF: f32 = 95.0;
 /// * Predicted full encode time
 ///
 /// Use -v to print per-sample results.
-#[derive(Parser)]
+#[derive(Parser, Clone)]
 #[clap(verbatim_doc_comment)]
 #[group(skip)]
 pub struct Args {
@@ -51,10 +61,43 @@ pub struct Args {
     #[arg(long, group = "min_score")]
     pub min_xpsnr: Option<f32>,
 
+    /// Desired min PSNR score to deliver.
+    ///
+    /// Enables use of PSNR for score analysis instead of VMAF. PSNR is also used,
+    /// with a default of 42, if ffmpeg lacks libvmaf.
+    #[arg(long, group = "min_score")]
+    pub min_psnr: Option<f32>,
+
+    /// Desired min --metric-cmd score to deliver.
+    ///
+    /// Required when using an external metric command.
+    #[arg(long, group = "min_score", requires = "metric_cmd")]
+    pub min_metric: Option<f32>,
+
+    /// Desired min per-frame VMAF score, e.g. 70, in addition to the mean --min-vmaf.
+    ///
+    /// Avoids crf values where the mean score is fine but some frames are poor.
+    #[arg(long, conflicts_with_all = ["min_xpsnr", "min_psnr", "metric_cmd"])]
+    pub min_frame_vmaf: Option<f32>,
+
     /// Maximum desired encoded size percentage of the input size.
     #[arg(long, default_value_t = 80.0)]
     pub max_encoded_percent: f32,
 
+    /// Maximum predicted encoded video stream size, e.g. "4GB", "700MiB".
+    ///
+    /// Without a min score, e.g. --min-vmaf, searches for the lowest crf predicted
+    /// to fit. Otherwise this is an additional constraint.
+    #[arg(long, value_parser = args::parse_size)]
+    pub max_size: Option<u64>,
+
+    /// Maximum predicted encoded video stream size percentage of the input size, e.g. "40%".
+    ///
+    /// Without a min score, e.g. --min-vmaf, searches for the lowest crf predicted
+    /// to fit. Otherwise this is an additional constraint.
+    #[arg(long, value_parser = args::parse_percent)]
+    pub max_size_percent: Option<f32>,
+
     /// Minimum (highest quality) crf value to try.
     ///
     /// [default: 10, 2 for mpeg2video]
@@ -63,7 +106,7 @@ pub struct Args {
 
     /// Maximum (lowest quality) crf value to try.
     ///
-    /// [default: 55, 46 for x264,x265, 255 for rav1e,av1_vaapi, 30 for mpeg2video]
+    /// [default: 55, 46 for x264,x265, 255 for rav1e,av1_vaapi,av1_amf, 30 for mpeg2video]
     #[arg(long)]
     pub max_crf: Option<f32>,
 
@@ -74,9 +117,35 @@ pub struct Args {
     #[arg(long)]
     pub thorough: bool,
 
+    /// Maximum number of crf attempts, i.e. sample-encode runs. When reached the best
+    /// attempt so far is used, which may be further from the min score than desired.
+    ///
+    /// Useful to bound the time of searches, e.g. with --thorough or small --crf-increment.
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_iterations: Option<u32>,
+
+    /// If no crf within the crf range & size limits delivers the min score, e.g. --min-vmaf,
+    /// use the nearest achievable crf instead of failing, i.e. the highest quality attempt
+    /// fitting the size limits. The score shortfall is printed & included in json output.
+    ///
+    /// Useful to avoid failures in long unattended, e.g. batch, runs.
+    #[arg(long)]
+    pub accept_nearest: bool,
+
+    /// While a crf is scored, speculatively start sample-encoding the crf likely to be
+    /// planned next, interpolated from previous attempts. The speculative run is
+    /// cancelled if the guess is wrong.
+    ///
+    /// Reduces search time when encoding & scoring use different resources,
+    /// e.g. NVENC encoding with CPU VMAF.
+    #[arg(long)]
+    pub eager_search: bool,
+
     /// Constant rate factor search increment precision.
     ///
     /// [default: 1.0, 0.1 for x264,x265,vp9]
+    ///
+    /// Values below 1 require an encoder supporting decimal crf values, e.g. x264, x265.
     #[arg(long)]
     pub crf_increment: Option<f32>,
 
@@ -89,6 +158,16 @@ pub struct Args {
     )]
     pub cache: bool,
 
+    /// Stdout message format `human` or `json`. Used by crf-search.
+    #[arg(long, value_enum, default_value_t = StdoutFormat::Human)]
+    pub stdout_format: StdoutFormat,
+
+    /// Show an interactive terminal interface with the search attempts, sample results,
+    /// GPU utilization & a quality/size chart. Press `a` to accept the best crf so far
+    /// ending the search early, or `q` to abort. Used by crf-search.
+    #[arg(long)]
+    pub tui: bool,
+
     #[clap(flatten)]
     pub sample: args::Sample,
 
@@ -101,13 +180,78 @@ pub struct Args {
     #[clap(flatten)]
     pub xpsnr: args::Xpsnr,
 
-    #[command(flatten)]
-    pub verbose: clap_verbosity_flag::Verbosity,
+    #[clap(flatten)]
+    pub metric: args::MetricCmd,
 }
 
 impl Args {
     pub fn min_score(&self) -> f32 {
-        self.min_vmaf.or(self.min_xpsnr).unwrap_or(DEFAULT_MIN_VMAF)
+        match self.size_target_only() {
+            // any score is acceptable
+            true => 0.0,
+            false if self.libvmaf_fallback() => DEFAULT_MIN_PSNR,
+            false => self
+                .min_vmaf
+                .or(self.min_xpsnr)
+                .or(self.min_psnr)
+                .or(self.min_metric)
+                .unwrap_or(DEFAULT_MIN_VMAF),
+        }
+    }
+
+    /// Maximum encoded percent including any --max-size-percent.
+    pub fn max_percent(&self) -> f32 {
+        self.max_size_percent.map_or(self.max_encoded_percent, |p| {
+            p.min(self.max_encoded_percent)
+        })
+    }
+
+    /// Searching for the best quality crf predicted to fit a size target, without a min score.
+    pub fn size_target_only(&self) -> bool {
+        (self.max_size.is_some() || self.max_size_percent.is_some())
+            && self.min_vmaf.is_none()
+            && self.min_xpsnr.is_none()
+            && self.min_psnr.is_none()
+            && self.min_metric.is_none()
+            && self.min_frame_vmaf.is_none()
+    }
+
+    /// VMAF scoring is needed but ffmpeg lacks libvmaf, so PSNR should be used instead.
+    pub fn libvmaf_fallback(&self) -> bool {
+        self.min_xpsnr.is_none()
+            && self.min_psnr.is_none()
+            && self.metric.metric_cmd.is_none()
+            && !vmaf::libvmaf_available()
+            && vmaf::cuda::ffmpeg().is_none()
+            && !compat::upstream()
+    }
+
+    /// Set VMAF n_subsample for long inputs & lengthen samples if it would score too few frames.
+    /// See [`sample_encode::lengthen_subsampled`].
+    pub fn lengthen_subsampled(&mut self, probe: &Ffprobe, bar: &ProgressBar) {
+        let vmaf_scoring = self.min_xpsnr.is_none()
+            && self.min_psnr.is_none()
+            && self.metric.metric_cmd.is_none()
+            && !self.libvmaf_fallback();
+        if vmaf_scoring {
+            sample_encode::lengthen_subsampled(&mut self.sample, &mut self.vmaf, probe, bar);
+        }
+    }
+
+    /// Print a notice if falling back to PSNR, see [`Self::libvmaf_fallback`].
+    pub fn print_libvmaf_fallback(&self, bar: &ProgressBar) {
+        if self.libvmaf_fallback() {
+            let min = match self.size_target_only() {
+                true => String::new(),
+                false => format!(" --min-psnr {DEFAULT_MIN_PSNR}"),
+            };
+            bar.println(
+                style!("Warning: ffmpeg lacks libvmaf, using PSNR{min} instead of VMAF")
+                    .yellow()
+                    .bold()
+                    .to_string(),
+            );
+        }
     }
 }
 
@@ -119,157 +263,336 @@ pub async fn crf_search(mut args: Args) -> anyhow::Result<()> {
     );
     bar.enable_steady_tick(Duration::from_millis(100));
 
-    let probe = ffprobe::probe(&args.args.input);
+    stdin::ensure_seekable(&args.args.input)?;
+    let probe = args.args.probe_input().await?;
     let input_is_image = probe.is_image;
     args.sample
         .set_extension_from_input(&args.args.input, &args.args.encoder, &probe);
 
+    args.print_libvmaf_fallback(&bar);
+    args.lengthen_subsampled(&probe, &bar);
+    if job_env::dry_run() {
+        bar.finish_and_clear();
+        print_dry_run(args, &probe)?;
+        return Ok(());
+    }
+
     let min_score = args.min_score();
-    let max_encoded_percent = args.max_encoded_percent;
+    let min_frame_vmaf = args.min_frame_vmaf;
+    let max_encoded_percent = args.max_percent();
     let thorough = args.thorough;
-    let enc_args = args.args.clone();
-    let verbose = args.verbose;
-
-    let mut run = pin!(run(args, probe.into()));
-    while let Some(update) = run.next().await {
-        let update = update.inspect_err(|e| {
-            if let Error::NoGoodCrf { last } = e {
-                last.print_attempt(&bar, min_score, max_encoded_percent);
-            }
-        })?;
-        match update {
-            Update::Status {
-                crf_run,
-                crf,
-                sample:
-                    sample_encode::Status {
-                        work,
-                        fps,
-                        progress,
-                        sample,
-                        samples,
-                        full_pass,
+    let stdout_format = args.stdout_format;
+    let probe = Arc::new(probe);
+
+    let mut tui = match args.tui {
+        true if tui::Tui::supported() => {
+            bar.set_draw_target(ProgressDrawTarget::hidden());
+            let target = tui::Target {
+                min_score,
+                min_frame_vmaf,
+                max_encoded_percent,
+                max_size: args.max_size,
+                size_target_only: args.size_target_only(),
+            };
+            Some(tui::Tui::start(
+                args.args.input.display().to_string(),
+                target,
+            )?)
+        }
+        true => {
+            bar.println(
+                style!("Warning: --tui requires a terminal")
+                    .yellow()
+                    .to_string(),
+            );
+            None
+        }
+        false => None,
+    };
+
+    'search: loop {
+        let enc_args = args.args.clone();
+        let mut run = pin!(run(args.clone(), probe.clone()));
+        loop {
+            let update = match &mut tui {
+                Some(tui) => tokio::select! {
+                    update = run.next() => update,
+                    action = tui.action() => match action {
+                        tui::Action::Abort => anyhow::bail!("crf-search aborted"),
+                        tui::Action::Accept(best) => Some(Ok(Update::Done(best))),
                     },
-            } => {
-                bar.set_position(guess_progress(crf_run, progress, thorough) as _);
-                let crf = TerseF32(crf);
-                match full_pass {
-                    true => bar.set_prefix(format!("crf {crf} full pass")),
-                    false => bar.set_prefix(format!("crf {crf} {sample}/{samples}")),
+                },
+                None => run.next().await,
+            };
+            let Some(update) = update else { break };
+            let update = match update {
+                Err(Error::Other(err)) => match enc_args.hw_fallback(&err) {
+                    Some((fallback, warning)) => {
+                        bar.println(style!("Warning: {warning}").yellow().to_string());
+                        args.args = fallback;
+                        continue 'search;
+                    }
+                    None => return Err(err),
+                },
+                update => update.inspect_err(|e| {
+                    if let Error::NoGoodCrf { last } = e {
+                        last.print_attempt(&bar, min_score, min_frame_vmaf, max_encoded_percent);
+                    }
+                })?,
+            };
+            if let Some(tui) = &mut tui {
+                tui.update(&update);
+                _ = tui.draw();
+            }
+            match update {
+                Update::Status {
+                    crf_run,
+                    crf,
+                    sample:
+                        sample_encode::Status {
+                            work,
+                            fps,
+                            progress,
+                            sample,
+                            samples,
+                            full_pass,
+                        },
+                } => {
+                    bar.set_position(guess_progress(crf_run, progress, thorough) as _);
+                    let crf = TerseF32(crf);
+                    match full_pass {
+                        true => bar.set_prefix(format!("crf {crf} full pass")),
+                        false => bar.set_prefix(format!("crf {crf} {sample}/{samples}")),
+                    }
+                    let label = work.fps_label();
+                    match work {
+                        Work::Encode if fps <= 0.0 => bar.set_message("encoding,  "),
+                        _ if fps <= 0.0 => bar.set_message(format!("{label},       ")),
+                        _ => bar.set_message(format!("{label} {fps} fps, ")),
+                    }
                 }
-                let label = work.fps_label();
-                match work {
-                    Work::Encode if fps <= 0.0 => bar.set_message("encoding,  "),
-                    _ if fps <= 0.0 => bar.set_message(format!("{label},       ")),
-                    _ => bar.set_message(format!("{label} {fps} fps, ")),
+                Update::SampleResult {
+                    crf,
+                    sample,
+                    result,
+                } => {
+                    if log::verbose(1) {
+                        result.print_attempt(&bar, sample, Some(crf))
+                    }
                 }
-            }
-            Update::SampleResult {
-                crf,
-                sample,
-                result,
-            } => {
-                if verbose
-                    .log_level()
-                    .is_some_and(|lvl| lvl > log::Level::Error)
-                {
-                    result.print_attempt(&bar, sample, Some(crf))
+                Update::RunResult(result) => {
+                    result.print_attempt(&bar, min_score, min_frame_vmaf, max_encoded_percent)
                 }
-            }
-            Update::RunResult(result) => result.print_attempt(&bar, min_score, max_encoded_percent),
-            Update::Done(best) => {
-                info!("crf {} successful", best.crf());
-                bar.finish_with_message("");
-                if std::io::stderr().is_terminal() {
-                    eprintln!(
-                        "\n{} {}\n",
-                        style("Encode with:").dim(),
-                        style(enc_args.encode_hint(best.crf())).dim().italic(),
-                    );
+                Update::Done(best) => {
+                    // restore the terminal before printing results
+                    drop(tui.take());
+                    info!("crf {} successful", best.crf());
+                    bar.finish_with_message("");
+                    if std::io::stderr().is_terminal() {
+                        eprintln!(
+                            "\n{} {}\n",
+                            style("Encode with:").dim(),
+                            style(enc_args.encode_hint(best.crf())).dim().italic(),
+                        );
+                    }
+                    if best.quantization_limited && std::io::stderr().is_terminal() {
+                        eprintln!("{}", style(best.quantization_limited_note()).dim());
+                    }
+                    if best.iteration_limited {
+                        eprintln!("{}", style(best.iteration_limited_note()).yellow());
+                    }
+                    if let Some(note) = best.accepted_nearest_note() {
+                        eprintln!("{}", style(note).yellow());
+                    }
+                    stdout_format.print_result(&best, input_is_image);
+                    sink::emit(sink::Event::CrfSearch {
+                        input: &enc_args.input,
+                        result: result_json(&best),
+                    })
+                    .await;
+                    return Ok(());
                 }
-                StdoutFormat::Human.print_result(&best, input_is_image);
-                return Ok(());
             }
         }
+        unreachable!()
     }
-    unreachable!()
 }
 
-pub fn run(
-    Args {
+/// Returns the crf planner, sample-encode args & crf increment of a search.
+fn setup(args: Args) -> Result<(Planner, sample_encode::Args, f32), Error> {
+    let min_score = args.min_score();
+    let size_target_only = args.size_target_only();
+    let max_encoded_percent = args.max_percent();
+    let libvmaf_fallback = args.libvmaf_fallback();
+    let Args {
         args,
-        min_vmaf,
+        min_vmaf: _,
         min_xpsnr,
-        max_encoded_percent,
+        min_psnr,
+        min_metric,
+        min_frame_vmaf,
+        max_encoded_percent: _,
+        max_size,
+        max_size_percent: _,
         min_crf,
         max_crf,
         crf_increment,
         thorough,
+        max_iterations,
+        accept_nearest,
+        eager_search: _,
         sample,
         cache,
+        stdout_format: _,
+        tui: _,
         vmaf,
         score,
         xpsnr,
-        verbose: _,
-    }: Args,
-    input_probe: Arc<Ffprobe>,
-) -> impl Stream<Item = Result<Update, Error>> {
-    async_stream::try_stream! {
-        let default_max_crf = args.encoder.default_max_crf();
-        let max_crf = max_crf.unwrap_or(default_max_crf);
-        let default_min_crf = args.encoder.default_min_crf();
-        let min_crf = min_crf.unwrap_or(default_min_crf);
-        Error::ensure_other(min_crf < max_crf, "Invalid --min-crf & --max-crf")?;
-        // by default use vmaf 95, otherwise use whatever is specified
-        let min_score = min_vmaf.or(min_xpsnr).unwrap_or(DEFAULT_MIN_VMAF);
-
-        // Whether to make the 2nd iteration on the ~20%/~80% crf point instead of the min/max to
-        // improve interpolation by narrowing the crf range a 20% (or 30%) subrange.
-        //
-        // 20/80% is preferred to 25/75% to account for searches in the "middle" benefitting from
-        // having both bounds computed after the 2nd iteration, whereas the two edges must compute
-        // the min/max crf on the 3rd iter.
-        //
-        // If a custom crf range is being used under half the default, this 2nd cut is not needed.
-        let cut_on_iter2 = (max_crf - min_crf) > (default_max_crf - default_min_crf) * 0.5;
-
-        let crf_increment = crf_increment
-            .unwrap_or_else(|| args.encoder.default_crf_increment())
-            .max(0.001);
-
-        let min_q = q_from_crf(min_crf, crf_increment);
-        let max_q = q_from_crf(max_crf, crf_increment);
-        let mut q: u64 = (min_q + max_q) / 2;
-
-        let mut args = sample_encode::Args {
-            args: args.clone(),
-            crf: 0.0,
-            sample: sample.clone(),
-            cache,
-            stdout_format: sample_encode::StdoutFormat::Json,
-            vmaf: vmaf.clone(),
-            score: score.clone(),
-            xpsnr: min_xpsnr.is_some(),
-            xpsnr_opts: xpsnr,
-        };
+        metric,
+    } = args;
+
+    let default_max_crf = args.encoder.default_max_crf();
+    let max_crf = max_crf.unwrap_or(default_max_crf);
+    let default_min_crf = args.encoder.default_min_crf();
+    let min_crf = min_crf.unwrap_or(default_min_crf);
+    Error::ensure_other(min_crf < max_crf, "Invalid --min-crf & --max-crf")?;
+    Error::ensure_other(
+        metric.metric_cmd.is_none() || min_metric.is_some(),
+        "--metric-cmd requires --min-metric",
+    )?;
+    Error::ensure_other(
+        !libvmaf_fallback || min_frame_vmaf.is_none(),
+        "--min-frame-vmaf requires ffmpeg with libvmaf",
+    )?;
+    // Whether to make the 2nd iteration on the ~20%/~80% crf point instead of the min/max to
+    // improve interpolation by narrowing the crf range a 20% (or 30%) subrange.
+    //
+    // 20/80% is preferred to 25/75% to account for searches in the "middle" benefitting from
+    // having both bounds computed after the 2nd iteration, whereas the two edges must compute
+    // the min/max crf on the 3rd iter.
+    //
+    // If a custom crf range is being used under half the default, this 2nd cut is not needed.
+    let cut_on_iter2 = (max_crf - min_crf) > (default_max_crf - default_min_crf) * 0.5;
+
+    let crf_increment = crf_increment
+        .unwrap_or_else(|| args.encoder.default_crf_increment())
+        .max(0.001);
+    Error::ensure_other(
+        crf_increment.fract() == 0.0 || args.encoder.supports_decimal_crf(),
+        "decimal --crf-increment is not supported by this encoder",
+    )?;
+
+    let mut planner = Planner::new(
+        q_from_crf(min_crf, crf_increment),
+        q_from_crf(max_crf, crf_increment),
+        cut_on_iter2,
+        crf_increment,
+        thorough,
+        min_score,
+        min_frame_vmaf,
+        size_target_only,
+        max_encoded_percent,
+        max_size,
+    );
+    planner.accept_nearest = accept_nearest;
+    planner.max_runs = max_iterations.map(|n| n as _);
 
-        let mut crf_attempts = Vec::new();
+    let args = sample_encode::Args {
+        args,
+        crf: 0.0,
+        preset_sweep: None,
+        sample,
+        frame_scores: min_frame_vmaf.is_some(),
+        cache,
+        stdout_format: sample_encode::StdoutFormat::Json,
+        vmaf,
+        score,
+        xpsnr: min_xpsnr.is_some(),
+        psnr: min_psnr.is_some() || libvmaf_fallback,
+        xpsnr_opts: xpsnr,
+        metric,
+    };
+    Ok((planner, args, crf_increment))
+}
+
+/// Print the commands of the first sample at the initial crf of the search without
+/// running them, see --dry-run. Returns the initial crf.
+pub fn print_dry_run(args: Args, probe: &Ffprobe) -> anyhow::Result<f32> {
+    let (planner, mut args, crf_increment) = setup(args).map_err(anyhow::Error::from)?;
+    args.crf = planner.initial_q().to_crf(crf_increment);
+    sample_encode::print_dry_run(&args, probe)?;
+    Ok(args.crf)
+}
+
+pub fn run(args: Args, input_probe: Arc<Ffprobe>) -> impl Stream<Item = Result<Update, Error>> {
+    async_stream::try_stream! {
+        let eager_search = args.eager_search;
+        let (mut planner, mut args, crf_increment) = setup(args)?;
+        let mut q = planner.initial_q();
+        let mut eager: Option<EagerRun> = None;
 
         for run in 1.. {
-            // how much we're prepared to go higher than the min-vmaf
-            let higher_tolerance = match thorough {
-                true => 0.05,
-                // increment 1.0 => +0.1, +0.2, +0.4, +0.8 ..
-                // increment 0.1 => +0.1, +0.1, +0.1, +0.16 ..
-                _ => (crf_increment * 2_f32.powi(run as i32 - 1) * 0.1).max(0.1),
-            };
             args.crf = q.to_crf(crf_increment);
 
-            let mut sample_enc = pin!(sample_encode::run(args.clone(), input_probe.clone()));
+            // use the speculative run if it guessed right, otherwise dropping cancels it
+            let (mut sample_enc, buffered) = match eager.take() {
+                Some(eager) if eager.q == q && !eager.failed => {
+                    info!("crf {} eager run used", args.crf);
+                    (eager.run, eager.updates)
+                }
+                _ => (sample_encode_run(&args, &input_probe), vec![]),
+            };
             let mut sample_enc_output = None;
-            while let Some(update) = sample_enc.next().await {
+            for update in buffered {
+                match update {
+                    sample_encode::Update::SampleResult { sample, result } => {
+                        yield Update::SampleResult { crf: args.crf, sample, result };
+                    }
+                    sample_encode::Update::Done(output) => sample_enc_output = Some(output),
+                    sample_encode::Update::Status(_) => {}
+                }
+            }
+            loop {
+                let update = match eager.as_mut().filter(|e| !e.done) {
+                    Some(eager) => tokio::select! {
+                        update = sample_enc.next() => update,
+                        update = eager.run.next() => {
+                            match update {
+                                Some(Ok(sample_encode::Update::Status(_))) => {}
+                                Some(Ok(update)) => eager.updates.push(update),
+                                Some(Err(err)) => {
+                                    info!("eager run failed: {err}");
+                                    eager.failed = true;
+                                    eager.done = true;
+                                }
+                                None => eager.done = true,
+                            }
+                            continue;
+                        }
+                    },
+                    None => sample_enc.next().await,
+                };
+                let Some(update) = update else { break };
                 match update? {
                     sample_encode::Update::Status(status) => {
+                        // start the likely next run once the last sample is being scored
+                        if eager_search
+                            && eager.is_none()
+                            && status.sample == status.samples
+                            && matches!(status.work, Work::Score(_))
+                            && let Some(next) = planner.guess_next(q)
+                        {
+                            let mut eager_args = args.clone();
+                            eager_args.crf = next.to_crf(crf_increment);
+                            info!("crf {} eager run started", eager_args.crf);
+                            eager = Some(EagerRun {
+                                q: next,
+                                run: sample_encode_run(&eager_args, &input_probe),
+                                updates: vec![],
+                                done: false,
+                                failed: false,
+                            });
+                        }
                         yield Update::Status { crf_run: run, crf: args.crf, sample: status };
                     }
                     sample_encode::Update::SampleResult { sample, result } => {
@@ -283,79 +606,61 @@ pub fn run(
                 crf_increment,
                 q,
                 enc: sample_enc_output.context("no sample output?")?,
+                quantization_limited: false,
+                iteration_limited: false,
+                score_shortfall: None,
             };
 
-            crf_attempts.push(sample.clone());
-            let sample_small_enough = sample.enc.encode_percent <= max_encoded_percent as _;
-
-            if sample.enc.score > min_score {
-                // good
-                if sample_small_enough && sample.enc.score < min_score + higher_tolerance {
-                    yield Update::Done(sample);
-                    return;
+            match planner.next(sample.clone())? {
+                Plan::Next(next) => {
+                    yield Update::RunResult(sample);
+                    q = next;
                 }
-                let u_bound = crf_attempts
-                    .iter()
-                    .filter(|s| s.q > sample.q)
-                    .min_by_key(|s| s.q);
-
-                match u_bound {
-                    Some(upper) if upper.q == sample.q + 1 => {
-                        Error::ensure_or_no_good_crf(sample_small_enough, &sample)?;
-                        yield Update::Done(sample);
-                        return;
-                    }
-                    Some(upper) => {
-                        q = vmaf_lerp_q(min_score, upper, &sample);
-                    }
-                    None if sample.q == max_q => {
-                        Error::ensure_or_no_good_crf(sample_small_enough, &sample)?;
-                        yield Update::Done(sample);
-                        return;
-                    }
-                    None if cut_on_iter2 && run == 1 && sample.q + 1 < max_q => {
-                        q = (sample.q as f32 * 0.4 + max_q as f32 * 0.6).round() as _;
+                Plan::Done { best, last } => {
+                    if let Some(last) = last {
+                        yield Update::RunResult(last);
                     }
-                    None => q = max_q,
-                };
-            } else {
-                // not good enough
-                if !sample_small_enough || sample.q == min_q {
-                    Err(Error::NoGoodCrf { last: sample.clone() })?;
+                    yield Update::Done(best);
+                    return;
                 }
-
-                let l_bound = crf_attempts
-                    .iter()
-                    .filter(|s| s.q < sample.q)
-                    .max_by_key(|s| s.q);
-
-                match l_bound {
-                    Some(lower) if lower.q + 1 == sample.q => {
-                        Error::ensure_or_no_good_crf(lower.enc.encode_percent <= max_encoded_percent as _, &sample)?;
-                        yield Update::RunResult(sample.clone());
-                        yield Update::Done(lower.clone());
-                        return;
-                    }
-                    Some(lower) => {
-                        q = vmaf_lerp_q(min_score, &sample, lower);
-                    }
-                    None if cut_on_iter2 && run == 1 && sample.q > min_q + 1 => {
-                        q = (sample.q as f32 * 0.4 + min_q as f32 * 0.6).round() as _;
-                    }
-                    None => q = min_q,
-                };
             }
-            yield Update::RunResult(sample.clone());
         }
         unreachable!();
     }
 }
 
+fn sample_encode_run(
+    args: &sample_encode::Args,
+    input_probe: &Arc<Ffprobe>,
+) -> LocalBoxStream<'static, anyhow::Result<sample_encode::Update>> {
+    Box::pin(sample_encode::run(args.clone(), input_probe.clone()))
+}
+
+/// Speculative sample-encode run of the likely next q, see --eager-search.
+struct EagerRun {
+    q: u64,
+    run: LocalBoxStream<'static, anyhow::Result<sample_encode::Update>>,
+    /// Updates received before the run is used.
+    updates: Vec<sample_encode::Update>,
+    /// The run finished, or failed.
+    done: bool,
+    failed: bool,
+}
+
 #[derive(Debug, Clone)]
 pub struct Sample {
     pub enc: sample_encode::Output,
     pub crf_increment: f32,
     pub q: u64,
+    /// The next worse crf, one crf-increment higher, was tested & failed to deliver the
+    /// min score. So this is the conservative result, though the score may be further
+    /// above the min score than desired.
+    pub quantization_limited: bool,
+    /// The search stopped at --max-iterations, so this is the best attempt so far.
+    pub iteration_limited: bool,
+    /// The min score minus the score of this result, if accepted as the nearest to
+    /// delivering the min score with --accept-nearest.
+    pub score_shortfall: Option<f32>,
 }
 
 impl Sample {
@@ -363,13 +668,67 @@ impl Sample {
         self.q.to_crf(self.crf_increment)
     }
 
-    pub fn print_attempt(&self, bar: &ProgressBar, min_score: f32, max_encoded_percent: f32) {
+    /// Human explanation of a quantization limited result.
+    pub fn quantization_limited_note(&self) -> String {
+        let next = TerseF32(self.crf() + self.crf_increment);
+        let mut note = format!(
+            "Quantization limited: crf {next} did not meet the min score, \
+             crf {} is the closest conservative value",
+            TerseF32(self.crf())
+        );
+        if self.crf_increment > 0.1 {
+            note.push_str(", a smaller --crf-increment may get closer if the encoder supports it");
+        }
+        note
+    }
+
+    /// Human explanation of an iteration limited result.
+    pub fn iteration_limited_note(&self) -> String {
+        format!(
+            "Iteration limited: the search stopped at --max-iterations, \
+             crf {} is the best attempt so far",
+            TerseF32(self.crf())
+        )
+    }
+
+    /// Human explanation of a result accepted with --accept-nearest, `None` for other results.
+    pub fn accepted_nearest_note(&self) -> Option<String> {
+        let shortfall = self.score_shortfall?;
+        Some(format!(
+            "Accepted nearest: no crf met the min score within the crf range & size limits, \
+             crf {} {} {:.2} is {shortfall:.2} below",
+            TerseF32(self.crf()),
+            self.enc.score_kind,
+            self.enc.score,
+        ))
+    }
+
+    /// Score ± error, e.g. " ±0.31", if estimated.
+    fn score_error_label(&self) -> String {
+        match self.enc.score_error {
+            e if e > 0.0 && !compat::upstream() => format!(" ±{e:.2}"),
+            _ => String::new(),
+        }
+    }
+
+    pub fn print_attempt(
+        &self,
+        bar: &ProgressBar,
+        min_score: f32,
+        min_frame_vmaf: Option<f32>,
+        max_encoded_percent: f32,
+    ) {
         if bar.is_hidden() {
             info!(
-                "crf {} {} {:.2} ({:.0}%){}",
+                "crf {} {} {:.2}{}{} ({:.0}%){}",
                 TerseF32(self.crf()),
                 self.enc.score_kind,
                 self.enc.score,
+                self.score_error_label(),
+                match self.enc.min_frame_score {
+                    Some(min) => format!(" min-frame {min:.2}"),
+                    None => String::new(),
+                },
                 self.enc.encode_percent,
                 if self.enc.from_cache { " (cache)" } else { "" }
             );
@@ -380,6 +739,7 @@ impl Sample {
         let mut crf = style(TerseF32(self.crf()));
         let vmaf_label = style(self.enc.score_kind).dim();
         let mut vmaf = style(self.enc.score);
+        let error = style(self.score_error_label()).dim();
         let mut percent = style!("{:.0}%", self.enc.encode_percent);
         let open = style("(").dim();
         let close = style(")").dim();
@@ -396,9 +756,20 @@ impl Sample {
             crf = crf.red().bright();
             percent = percent.red().bright();
         }
+        let min_frame = match self.enc.min_frame_score {
+            Some(min) => {
+                let mut min_frame = style(format!("{min:.2}"));
+                if min_frame_vmaf.is_some_and(|floor| min < floor) {
+                    crf = crf.red().bright();
+                    min_frame = min_frame.red().bright();
+                }
+                format!(" {} {min_frame}", style("min-frame").dim())
+            }
+            None => String::new(),
+        };
 
         bar.println(format!(
-            "{crf_label} {crf} {vmaf_label} {vmaf:.2} {open}{percent}{close}{cache_msg}"
+            "{crf_label} {crf} {vmaf_label} {vmaf:.2}{error}{min_frame} {open}{percent}{close}{cache_msg}"
         ));
     }
 }
@@ -406,6 +777,7 @@ impl Sample {
 #[derive(Debug, Clone, Copy, clap::ValueEnum)]
 pub enum StdoutFormat {
     Human,
+    Json,
 }
 
 impl StdoutFormat {
@@ -415,47 +787,63 @@ impl StdoutFormat {
                 let crf = style(TerseF32(sample.crf())).bold().green();
                 let enc = &sample.enc;
                 let score = style(enc.score).bold().green();
+                let error = sample.score_error_label();
                 let score_kind = enc.score_kind;
-                let size = style(HumanBytes(enc.predicted_encode_size)).bold().green();
+                let size = style(HumanBytes(enc.prediction.size)).bold().green();
                 let percent = style!("{}%", enc.encode_percent.round()).bold().green();
-                let time = style(HumanDuration(enc.predicted_encode_time)).bold();
+                let time = style(HumanDuration(enc.prediction.time)).bold();
                 let enc_description = match image {
                     true => "image",
                     false => "video stream",
                 };
                 println!(
-                    "crf {crf} {score_kind} {score:.2} predicted {enc_description} size {size} ({percent}) taking {time}"
+                    "crf {crf} {score_kind} {score:.2}{error} predicted {enc_description} size {size} ({percent}) taking {time}"
                 );
             }
+            Self::Json => println!("{}", result_json(sample)),
         }
     }
 }
 
-/// Produce a q value between given samples using vmaf score linear interpolation
-/// so the output q value should produce the `min_vmaf`.
-///
-/// Note: `worse_q` will be a numerically higher q value (worse quality),
-///       `better_q` a numerically lower q value (better quality).
-///
-/// # Issues
-/// Crf values do not linearly map to VMAF changes (or anything?) so this is a flawed method,
-/// though it seems to work better than a binary search.
-/// Perhaps a better approximation of a general crf->vmaf model could be found.
-/// This would be helpful particularly for small crf-increments.
-fn vmaf_lerp_q(min_vmaf: f32, worse_q: &Sample, better_q: &Sample) -> u64 {
-    assert!(
-        worse_q.enc.score <= min_vmaf
-            && worse_q.enc.score < better_q.enc.score
-            && worse_q.q > better_q.q,
-        "invalid vmaf_lerp_crf usage: ({min_vmaf}, {worse_q:?}, {better_q:?})"
-    );
-
-    let vmaf_diff = better_q.enc.score - worse_q.enc.score;
-    let vmaf_factor = (min_vmaf - worse_q.enc.score) / vmaf_diff;
-
-    let q_diff = worse_q.q - better_q.q;
-    let lerp = (worse_q.q as f32 - q_diff as f32 * vmaf_factor).round() as u64;
-    lerp.clamp(better_q.q + 1, worse_q.q - 1)
+/// Json crf-search result, as printed with `--stdout-format json`.
+pub fn result_json(sample: &Sample) -> serde_json::Value {
+    let enc = &sample.enc;
+    let mut json = serde_json::json!({
+        "crf": sample.crf(),
+        "predicted_encode_percent": enc.encode_percent,
+    });
+    if !compat::upstream() {
+        json["quantization_limited"] = sample.quantization_limited.into();
+        if sample.iteration_limited {
+            json["iteration_limited"] = true.into();
+        }
+        if enc.score_error > 0.0 {
+            json["score_error"] = enc.score_error.into();
+        }
+    }
+    if let Some(shortfall) = sample.score_shortfall {
+        json["score_shortfall"] = shortfall.into();
+    }
+    enc.prediction.add_json(&mut json);
+    match enc.score_kind {
+        ScoreKind::Vmaf => json["vmaf"] = enc.score.into(),
+        ScoreKind::Xpsnr => json["xpsnr"] = enc.score.into(),
+        ScoreKind::Psnr => json["psnr"] = enc.score.into(),
+        ScoreKind::Metric => json["metric"] = enc.score.into(),
+    }
+    if let Some(min) = enc.min_frame_score {
+        json["min_frame_vmaf"] = min.into();
+    }
+    if let Some(stats) = &enc.frame_stats {
+        json["frame_vmaf"] = serde_json::json!(stats);
+    }
+    if let Some(psnr) = enc.psnr {
+        json["psnr"] = psnr.into();
+    }
+    if let Some(ssim) = enc.ssim {
+        json["ssim"] = ssim.into();
+    }
+    json
 }
 
 /// sample_progress: [0, 1]
@@ -476,11 +864,11 @@ pub fn guess_progress(run: usize, sample_progress: f32, thorough: bool) -> f64 {
 /// * crf=33.5, inc=0.1 -> q=335
 /// * crf=27, inc=1 -> q=27
 #[inline]
-fn q_from_crf(crf: f32, crf_increment: f32) -> u64 {
+pub fn q_from_crf(crf: f32, crf_increment: f32) -> u64 {
     (f64::from(crf) / f64::from(crf_increment)).round() as _
 }
 
-trait QualityValue {
+pub trait QualityValue {
     fn to_crf(self, crf_increment: f32) -> f32;
 }
 impl QualityValue for u64 {
@@ -496,6 +884,46 @@ fn q_crf_conversions() {
     assert_eq!(q_from_crf(27.0, 1.0), 27);
 }
 
+#[test]
+fn quantization_limited_note() {
+    let sample = Sample {
+        enc: sample_encode::Output {
+            score: 95.6,
+            score_error: 0.0,
+            score_kind: ScoreKind::Vmaf,
+            min_frame_score: None,
+            frame_stats: None,
+            psnr: None,
+            ssim: None,
+            encode_percent: 40.0,
+            prediction: sample_encode::Prediction {
+                size: 1000,
+                time: Duration::from_secs(60),
+                ..<_>::default()
+            },
+            from_cache: false,
+        },
+        crf_increment: 1.0,
+        q: 32,
+        quantization_limited: true,
+        iteration_limited: false,
+        score_shortfall: None,
+    };
+    let note = sample.quantization_limited_note();
+    assert!(note.contains("crf 33 did not meet"), "{note}");
+    assert!(note.contains("crf 32 is the closest"), "{note}");
+    assert!(note.contains("--crf-increment"), "{note}");
+    assert_eq!(sample.accepted_nearest_note(), None);
+
+    let sample = Sample {
+        score_shortfall: Some(0.4),
+        ..sample
+    };
+    let note = sample.accepted_nearest_note().unwrap();
+    assert!(note.ends_with("crf 32 VMAF 95.60 is 0.40 below"), "{note}");
+    assert_eq!(result_json(&sample)["score_shortfall"], 0.4_f32);
+}
+
 #[derive(Debug)]
 pub enum Update {
     Status {
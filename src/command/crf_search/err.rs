@@ -3,7 +3,7 @@ use std::fmt;
 
 #[derive(Debug)]
 pub enum Error {
-    NoGoodCrf { last: Sample },
+    NoGoodCrf { last: Box<Sample> },
     Other(anyhow::Error),
 }
 
@@ -17,7 +17,9 @@ impl Error {
 
     pub fn ensure_or_no_good_crf(condition: bool, last: &Sample) -> Result<(), Self> {
         if !condition {
-            return Err(Self::NoGoodCrf { last: last.clone() });
+            return Err(Self::NoGoodCrf {
+                last: Box::new(last.clone()),
+            });
         }
         Ok(())
     }
@@ -0,0 +1,576 @@
+//! Pure crf search logic: proposes the next q to sample & decides when the search is done.
+//!
+//! Has no io, so the same search behaviour can be driven by any sample encoder.
+use super::{Error, Sample};
+
+/// Crf search state & limits.
+#[derive(Debug, Clone)]
+pub struct Planner {
+    pub min_q: u64,
+    pub max_q: u64,
+    /// Make the 2nd iteration on the ~20%/~80% q point instead of the min/max.
+    pub cut_on_iter2: bool,
+    pub crf_increment: f32,
+    pub thorough: bool,
+    pub min_score: f32,
+    pub min_frame_vmaf: Option<f32>,
+    /// Search for the best quality q that fits the size limits, ignoring scores.
+    pub size_target_only: bool,
+    pub max_encoded_percent: f32,
+    pub max_size: Option<u64>,
+    /// If the min score can't be delivered, finish with the nearest attempt instead of failing.
+    pub accept_nearest: bool,
+    /// Finish with the best attempt so far after this many attempts.
+    pub max_runs: Option<usize>,
+    /// Completed sample attempts.
+    attempts: Vec<Sample>,
+}
+
+/// Result of a search iteration.
+#[derive(Debug, Clone)]
+#[allow(clippy::large_enum_variant)] // short lived, boxing wouldn't help
+pub enum Plan {
+    /// Sample this q next.
+    Next(u64),
+    /// Search is complete with the `best` sample. `last` is the final attempted sample,
+    /// if it is not also the best.
+    Done { best: Sample, last: Option<Sample> },
+}
+
+impl Planner {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        min_q: u64,
+        max_q: u64,
+        cut_on_iter2: bool,
+        crf_increment: f32,
+        thorough: bool,
+        min_score: f32,
+        min_frame_vmaf: Option<f32>,
+        size_target_only: bool,
+        max_encoded_percent: f32,
+        max_size: Option<u64>,
+    ) -> Self {
+        Self {
+            min_q,
+            max_q,
+            cut_on_iter2,
+            crf_increment,
+            thorough,
+            min_score,
+            min_frame_vmaf,
+            size_target_only,
+            max_encoded_percent,
+            max_size,
+            accept_nearest: false,
+            max_runs: None,
+            attempts: vec![],
+        }
+    }
+
+    /// The first q to sample.
+    pub fn initial_q(&self) -> u64 {
+        (self.min_q + self.max_q) / 2
+    }
+
+    /// Guess the q planned after sampling `q`, before its result is known, by
+    /// interpolating the score & size of the two nearest attempts. See --eager-search.
+    ///
+    /// Returns `None` with fewer than two attempts or if the search would likely finish.
+    pub fn guess_next(&self, q: u64) -> Option<u64> {
+        let mut nearest: Vec<_> = self.attempts.iter().filter(|s| s.q != q).collect();
+        nearest.sort_by_key(|s| s.q.abs_diff(q));
+        let [a, b, ..] = nearest[..] else {
+            return None;
+        };
+        if a.q == b.q {
+            return None;
+        }
+        let t = (q as f64 - a.q as f64) / (b.q as f64 - a.q as f64);
+        let lerp = |a: f64, b: f64| a + (b - a) * t;
+
+        let mut guess = a.clone();
+        guess.q = q;
+        guess.enc.score = lerp(a.enc.score.into(), b.enc.score.into()) as f32;
+        guess.enc.min_frame_score = a
+            .enc
+            .min_frame_score
+            .zip(b.enc.min_frame_score)
+            .map(|(a, b)| lerp(a.into(), b.into()) as f32);
+        guess.enc.encode_percent = lerp(a.enc.encode_percent, b.enc.encode_percent).max(0.0);
+        guess.enc.prediction.size =
+            lerp(a.enc.prediction.size as f64, b.enc.prediction.size as f64).max(0.0) as u64;
+        match self.clone().next(guess) {
+            Ok(Plan::Next(next)) if next != q => Some(next),
+            _ => None,
+        }
+    }
+
+    /// Predicted size as a proportion of the size limits, > 1 is too large.
+    pub fn size_ratio(&self, s: &Sample) -> f64 {
+        let percent_ratio = s.enc.encode_percent / self.max_encoded_percent as f64;
+        match self.max_size {
+            Some(max) => percent_ratio.max(s.enc.prediction.size as f64 / max as f64),
+            None => percent_ratio,
+        }
+    }
+
+    /// Add the result of sampling the last proposed q & plan the next step.
+    pub fn next(&mut self, sample: Sample) -> Result<Plan, Error> {
+        self.attempts.push(sample.clone());
+        let plan = match self.size_target_only {
+            true => self.next_size_target(sample.clone()),
+            false => match self.next_min_score(sample.clone()) {
+                Err(Error::NoGoodCrf { last }) if self.accept_nearest => self.nearest(*last),
+                plan => plan,
+            },
+        };
+        match plan {
+            Ok(Plan::Next(_)) if self.max_runs.is_some_and(|max| self.run() >= max) => {
+                self.best_so_far(sample)
+            }
+            plan => plan,
+        }
+    }
+
+    /// Finish with the best attempt so far, after reaching `max_runs`. Fails, or uses the
+    /// nearest attempt with `accept_nearest`, if no attempt is good.
+    fn best_so_far(&self, last: Sample) -> Result<Plan, Error> {
+        let good = self
+            .attempts
+            .iter()
+            .filter(|s| self.size_ratio(s) <= 1.0 && (self.size_target_only || self.good_score(s)));
+        let best = match self.size_target_only {
+            true => good.min_by_key(|s| s.q),
+            false => good.max_by_key(|s| s.q),
+        };
+        match best {
+            Some(best) => Ok(Plan::Done {
+                last: Some(last).filter(|l| l.q != best.q),
+                best: Sample {
+                    iteration_limited: true,
+                    ..best.clone()
+                },
+            }),
+            None if self.accept_nearest && !self.size_target_only => self.nearest(last),
+            None => Err(Error::NoGoodCrf {
+                last: Box::new(last),
+            }),
+        }
+    }
+
+    /// Returns true if the sample delivers the min score & any per-frame floor.
+    fn good_score(&self, s: &Sample) -> bool {
+        s.enc.score > self.min_score
+            && self
+                .min_frame_vmaf
+                .is_none_or(|floor| s.enc.min_frame_score.is_some_and(|min| min >= floor))
+    }
+
+    /// The attempt nearest to delivering the min score, i.e. the highest quality attempt
+    /// fitting the size limits. Fails if no attempt fits the size limits.
+    fn nearest(&self, last: Sample) -> Result<Plan, Error> {
+        let Some(nearest) = self
+            .attempts
+            .iter()
+            .filter(|s| self.size_ratio(s) <= 1.0)
+            .max_by(|a, b| {
+                a.enc
+                    .score
+                    .total_cmp(&b.enc.score)
+                    .then_with(|| b.q.cmp(&a.q))
+            })
+        else {
+            return Err(Error::NoGoodCrf {
+                last: Box::new(last),
+            });
+        };
+        let best = Sample {
+            score_shortfall: Some((self.min_score - nearest.enc.score).max(0.0)),
+            ..nearest.clone()
+        };
+        Ok(Plan::Done {
+            last: Some(last).filter(|l| l.q != best.q),
+            best,
+        })
+    }
+
+    fn run(&self) -> usize {
+        self.attempts.len()
+    }
+
+    /// Lowest attempted q higher than `q`.
+    fn upper_bound(&self, q: u64) -> Option<&Sample> {
+        self.attempts.iter().filter(|s| s.q > q).min_by_key(|s| s.q)
+    }
+
+    /// Highest attempted q lower than `q`.
+    fn lower_bound(&self, q: u64) -> Option<&Sample> {
+        self.attempts.iter().filter(|s| s.q < q).max_by_key(|s| s.q)
+    }
+
+    /// Search for the highest q delivering the min score & fitting the size limits.
+    fn next_min_score(&self, sample: Sample) -> Result<Plan, Error> {
+        let Self {
+            min_q,
+            max_q,
+            cut_on_iter2,
+            crf_increment,
+            thorough,
+            min_score,
+            min_frame_vmaf,
+            ..
+        } = *self;
+        let run = self.run();
+
+        // how much we're prepared to go higher than the min-vmaf
+        let higher_tolerance = match thorough {
+            true => 0.05,
+            // increment 1.0 => +0.1, +0.2, +0.4, +0.8 ..
+            // increment 0.1 => +0.1, +0.1, +0.1, +0.16 ..
+            _ => (crf_increment * 2_f32.powi(run as i32 - 1) * 0.1).max(0.1),
+        };
+        let sample_small_enough = self.size_ratio(&sample) <= 1.0;
+        // close enough to the mean target, or to the per-frame floor
+        let close_enough = sample.enc.score < min_score + higher_tolerance
+            || min_frame_vmaf.is_some_and(|floor| {
+                sample
+                    .enc
+                    .min_frame_score
+                    .is_some_and(|min| min < floor + higher_tolerance)
+            });
+
+        let q = if self.good_score(&sample) {
+            // good
+            if sample_small_enough && close_enough {
+                return Ok(Plan::Done {
+                    best: sample,
+                    last: None,
+                });
+            }
+            match self.upper_bound(sample.q) {
+                Some(upper) if upper.q == sample.q + 1 => {
+                    // the next crf is too low quality, can't get closer to the target
+                    Error::ensure_or_no_good_crf(sample_small_enough, &sample)?;
+                    return Ok(Plan::Done {
+                        best: Sample {
+                            quantization_limited: true,
+                            ..sample
+                        },
+                        last: None,
+                    });
+                }
+                Some(upper) => next_q(min_score, min_frame_vmaf, upper, &sample)?,
+                None if sample.q == max_q => {
+                    Error::ensure_or_no_good_crf(sample_small_enough, &sample)?;
+                    return Ok(Plan::Done {
+                        best: sample,
+                        last: None,
+                    });
+                }
+                None if cut_on_iter2 && run == 1 && sample.q + 1 < max_q => {
+                    (sample.q as f32 * 0.4 + max_q as f32 * 0.6).round() as _
+                }
+                None => max_q,
+            }
+        } else {
+            // not good enough
+            if !sample_small_enough || sample.q == min_q {
+                return Err(Error::NoGoodCrf {
+                    last: Box::new(sample),
+                });
+            }
+            match self.lower_bound(sample.q) {
+                Some(lower) if lower.q + 1 == sample.q => {
+                    // the previous crf is the conservative choice, can't get closer to the target
+                    Error::ensure_or_no_good_crf(self.size_ratio(lower) <= 1.0, &sample)?;
+                    return Ok(Plan::Done {
+                        best: Sample {
+                            quantization_limited: true,
+                            ..lower.clone()
+                        },
+                        last: Some(sample),
+                    });
+                }
+                Some(lower) => next_q(min_score, min_frame_vmaf, &sample, lower)?,
+                None if cut_on_iter2 && run == 1 && sample.q > min_q + 1 => {
+                    (sample.q as f32 * 0.4 + min_q as f32 * 0.6).round() as _
+                }
+                None => min_q,
+            }
+        };
+        Ok(Plan::Next(q))
+    }
+
+    /// Search for the lowest q fitting the size limits.
+    fn next_size_target(&self, sample: Sample) -> Result<Plan, Error> {
+        let Self {
+            min_q,
+            max_q,
+            cut_on_iter2,
+            ..
+        } = *self;
+        let run = self.run();
+        let size_ratio = |s: &Sample| self.size_ratio(s);
+
+        let q = if size_ratio(&sample) <= 1.0 {
+            // fits, try better quality
+            match self.lower_bound(sample.q) {
+                Some(lower) if lower.q + 1 == sample.q => {
+                    return Ok(Plan::Done {
+                        best: sample,
+                        last: None,
+                    });
+                }
+                Some(lower) => size_lerp_q(lower, &sample, size_ratio),
+                None if sample.q == min_q => {
+                    return Ok(Plan::Done {
+                        best: sample,
+                        last: None,
+                    });
+                }
+                None if cut_on_iter2 && run == 1 && sample.q > min_q + 1 => {
+                    (sample.q as f32 * 0.4 + min_q as f32 * 0.6).round() as _
+                }
+                None => min_q,
+            }
+        } else {
+            // too large, try worse quality
+            if sample.q == max_q {
+                return Err(Error::NoGoodCrf {
+                    last: Box::new(sample),
+                });
+            }
+            match self.upper_bound(sample.q) {
+                Some(upper) if upper.q == sample.q + 1 => {
+                    return Ok(Plan::Done {
+                        best: upper.clone(),
+                        last: Some(sample),
+                    });
+                }
+                Some(upper) => size_lerp_q(&sample, upper, size_ratio),
+                None if cut_on_iter2 && run == 1 && sample.q + 1 < max_q => {
+                    (sample.q as f32 * 0.4 + max_q as f32 * 0.6).round() as _
+                }
+                None => max_q,
+            }
+        };
+        Ok(Plan::Next(q))
+    }
+}
+
+/// Produce a q value between a failed `worse_q` & good `better_q` sample.
+///
+/// If `worse_q` failed only the per-frame floor, interpolates using the min frame
+/// scores, or bisects if that isn't possible. Otherwise uses [`vmaf_lerp_q`].
+fn next_q(
+    min_score: f32,
+    min_frame_vmaf: Option<f32>,
+    worse_q: &Sample,
+    better_q: &Sample,
+) -> Result<u64, Error> {
+    if worse_q.enc.score <= min_score {
+        return vmaf_lerp_q(min_score, worse_q, better_q);
+    }
+    Ok(
+        match (
+            min_frame_vmaf,
+            worse_q.enc.min_frame_score,
+            better_q.enc.min_frame_score,
+        ) {
+            (Some(floor), Some(worse), Some(better)) if worse <= floor && worse < better => {
+                lerp_q(floor, (worse_q.q, worse), (better_q.q, better))
+            }
+            _ => ((worse_q.q + better_q.q) / 2).clamp(better_q.q + 1, worse_q.q - 1),
+        },
+    )
+}
+
+/// Produce a q value between given samples using vmaf score linear interpolation
+/// so the output q value should produce the `min_vmaf`.
+///
+/// Note: `worse_q` will be a numerically higher q value (worse quality),
+///       `better_q` a numerically lower q value (better quality).
+///       Errors otherwise, e.g. if a library user reports a worse score for a lower crf.
+///
+/// # Issues
+/// Crf values do not linearly map to VMAF changes (or anything?) so this is a flawed method,
+/// though it seems to work better than a binary search.
+/// Perhaps a better approximation of a general crf->vmaf model could be found.
+/// This would be helpful particularly for small crf-increments.
+fn vmaf_lerp_q(min_vmaf: f32, worse_q: &Sample, better_q: &Sample) -> Result<u64, Error> {
+    if !(worse_q.enc.score <= min_vmaf
+        && worse_q.enc.score < better_q.enc.score
+        && worse_q.q > better_q.q)
+    {
+        return Err(Error::Other(anyhow::anyhow!(
+            "invalid vmaf_lerp_q usage: ({min_vmaf}, {worse_q:?}, {better_q:?})"
+        )));
+    }
+
+    Ok(lerp_q(
+        min_vmaf,
+        (worse_q.q, worse_q.enc.score),
+        (better_q.q, better_q.enc.score),
+    ))
+}
+
+/// Linearly interpolate a q value expected to produce the `target` score
+/// between `(q, score)` pairs.
+fn lerp_q(target: f32, (worse_q, worse): (u64, f32), (better_q, better): (u64, f32)) -> u64 {
+    let vmaf_factor = (target - worse) / (better - worse);
+    let q_diff = worse_q - better_q;
+    let lerp = (worse_q as f32 - q_diff as f32 * vmaf_factor).round() as u64;
+    lerp.clamp(better_q + 1, worse_q - 1)
+}
+
+/// Produce a q value between given samples expected to encode at the size limit,
+/// interpolating the log of the [`Sample`] size ratios, where a ratio of 1 is at the limit.
+///
+/// Encoded size roughly halves with every few crf increments, so log size is
+/// closer to linear than size.
+fn size_lerp_q(larger: &Sample, smaller: &Sample, size_ratio: impl Fn(&Sample) -> f64) -> u64 {
+    let (large_q, small_q) = (larger.q, smaller.q);
+    let (large, small) = (size_ratio(larger).ln(), size_ratio(smaller).ln());
+    let factor = match large - small {
+        d if d > 0.0 && d.is_finite() => large / d,
+        _ => 0.5,
+    };
+    let lerp = (large_q as f64 + (small_q - large_q) as f64 * factor).round() as u64;
+    lerp.clamp(large_q + 1, small_q - 1)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::command::sample_encode::{self, ScoreKind};
+    use std::time::Duration;
+
+    fn sample(q: u64, score: f32, encode_percent: f64) -> Sample {
+        Sample {
+            enc: sample_encode::Output {
+                score,
+                score_error: 0.0,
+                score_kind: ScoreKind::Vmaf,
+                min_frame_score: None,
+                frame_stats: None,
+                psnr: None,
+                ssim: None,
+                encode_percent,
+                prediction: sample_encode::Prediction {
+                    size: 1000,
+                    time: Duration::from_secs(60),
+                    ..<_>::default()
+                },
+                from_cache: false,
+            },
+            crf_increment: 1.0,
+            q,
+            quantization_limited: false,
+            iteration_limited: false,
+            score_shortfall: None,
+        }
+    }
+
+    /// Run a search with a simple model of score & size by q.
+    fn search(mut planner: Planner, model: impl Fn(u64) -> (f32, f64)) -> Result<Sample, Error> {
+        let mut q = planner.initial_q();
+        for _ in 0..20 {
+            let (score, percent) = model(q);
+            match planner.next(sample(q, score, percent))? {
+                Plan::Next(next) => q = next,
+                Plan::Done { best, .. } => return Ok(best),
+            }
+        }
+        panic!("search did not finish");
+    }
+
+    #[test]
+    fn plan_min_score() {
+        let planner = Planner::new(10, 55, true, 1.0, false, 95.0, None, false, 80.0, None);
+        let best = search(planner, |q| (110.0 - q as f32 * 0.5, 100.0 - q as f64)).unwrap();
+        assert_eq!(best.q, 29, "{best:?}");
+    }
+
+    #[test]
+    fn guess_next_q() {
+        let model = |q: u64| sample(q, 110.0 - q as f32 * 0.5, 100.0 - q as f64);
+        let mut planner = Planner::new(10, 55, true, 1.0, false, 95.0, None, false, 80.0, None);
+        let mut q = planner.initial_q();
+        assert_eq!(planner.guess_next(q), None, "no attempts");
+        for _ in 0..2 {
+            match planner.next(model(q)).unwrap() {
+                Plan::Next(next) => q = next,
+                plan => panic!("{plan:?}"),
+            }
+        }
+        // a linear model is guessed correctly
+        let guess = planner.guess_next(q);
+        match planner.clone().next(model(q)).unwrap() {
+            Plan::Next(next) => assert_eq!(guess, Some(next)),
+            Plan::Done { .. } => assert_eq!(guess, None),
+        }
+    }
+
+    #[test]
+    fn plan_accept_nearest() {
+        // max score 94.5 at the min crf
+        let model = |q| (99.5 - q as f32 * 0.5, 100.0 - q as f64);
+        let mut planner = Planner::new(10, 55, true, 1.0, false, 95.0, None, false, 95.0, None);
+        assert!(matches!(
+            search(planner.clone(), model),
+            Err(Error::NoGoodCrf { .. })
+        ));
+
+        planner.accept_nearest = true;
+        let best = search(planner.clone(), model).unwrap();
+        assert_eq!(best.q, 10, "{best:?}");
+        assert_eq!(best.score_shortfall, Some(0.5));
+
+        // nothing fits the size limits
+        planner.max_encoded_percent = 50.0;
+        assert!(matches!(
+            search(planner, model),
+            Err(Error::NoGoodCrf { .. })
+        ));
+    }
+
+    #[test]
+    fn plan_max_runs() {
+        let model = |q| (110.0 - q as f32 * 0.5, 100.0 - q as f64);
+        let mut planner = Planner::new(10, 55, true, 1.0, false, 95.0, None, false, 95.0, None);
+        planner.max_runs = Some(2);
+        // 32 (94) -> 19 (100.5)
+        let best = search(planner.clone(), model).unwrap();
+        assert_eq!(best.q, 19, "{best:?}");
+        assert!(best.iteration_limited);
+
+        // no good attempt in 1 run
+        planner.max_runs = Some(1);
+        assert!(matches!(
+            search(planner, model),
+            Err(Error::NoGoodCrf { .. })
+        ));
+    }
+
+    #[test]
+    fn plan_size_target() {
+        let planner = Planner::new(10, 55, true, 1.0, false, 0.0, None, true, 40.0, None);
+        let best = search(planner, |q| (95.0, 100.0 - q as f64 * 2.0)).unwrap();
+        assert_eq!(best.q, 30, "{best:?}");
+    }
+
+    #[test]
+    fn size_lerp() {
+        let ratio = |s: &Sample| s.enc.encode_percent / 40.0;
+        // 80% -> 20% over 20 crf, 40% is halfway in log size
+        assert_eq!(
+            size_lerp_q(&sample(20, 95.0, 80.0), &sample(40, 95.0, 20.0), ratio),
+            30
+        );
+        assert_eq!(
+            size_lerp_q(&sample(20, 95.0, 90.0), &sample(22, 95.0, 39.0), ratio),
+            21
+        );
+    }
+}
@@ -0,0 +1,486 @@
+//! Interactive crf-search terminal interface, see --tui.
+//!
+//! Drawn on the alternate screen showing the search attempts as a tree, sample results
+//! as they complete, GPU telemetry & a score/size chart of the attempts.
+use super::{Sample, Update};
+use crate::{
+    cuda::{caps, monitor::Monitor},
+    float::TerseF32,
+};
+use console::{Key, Term, style};
+use std::{
+    fmt::Write,
+    io::{self, IsTerminal},
+};
+use tokio::sync::mpsc;
+
+/// Number of recent sample results shown.
+const SAMPLE_LINES: usize = 6;
+const CHART_HEIGHT: usize = 10;
+/// Width of chart y-axis labels, e.g. "95.0 ┤".
+const AXIS_WIDTH: usize = 7;
+
+/// Search goals, used to mark attempts & find the best so far.
+#[derive(Debug, Clone, Copy)]
+pub struct Target {
+    pub min_score: f32,
+    pub min_frame_vmaf: Option<f32>,
+    pub max_encoded_percent: f32,
+    pub max_size: Option<u64>,
+    /// Best is the highest quality attempt fitting the size limits, ignoring scores.
+    pub size_target_only: bool,
+}
+
+impl Target {
+    fn fits_size(&self, s: &Sample) -> bool {
+        s.enc.encode_percent <= self.max_encoded_percent as f64
+            && self.max_size.is_none_or(|max| s.enc.prediction.size <= max)
+    }
+
+    fn meets(&self, s: &Sample) -> bool {
+        self.fits_size(s)
+            && (self.size_target_only
+                || s.enc.score >= self.min_score
+                    && self
+                        .min_frame_vmaf
+                        .is_none_or(|floor| s.enc.min_frame_score.is_some_and(|min| min >= floor)))
+    }
+}
+
+/// Keyboard action.
+#[derive(Debug)]
+pub enum Action {
+    Abort,
+    /// Accept the best attempt so far, ending the search early.
+    Accept(Sample),
+}
+
+/// Terminal interface, restoring the terminal when dropped.
+pub struct Tui {
+    term: Term,
+    keys: mpsc::UnboundedReceiver<Key>,
+    monitor: Option<Monitor>,
+    view: View,
+    #[cfg(unix)]
+    termios: Option<libc::termios>,
+}
+
+impl Tui {
+    /// Returns true if stdin & stderr are terminals, required for the interface.
+    pub fn supported() -> bool {
+        io::stdin().is_terminal() && io::stderr().is_terminal()
+    }
+
+    pub fn start(title: String, target: Target) -> io::Result<Self> {
+        let term = Term::stderr();
+        #[cfg(unix)]
+        let termios = termios();
+        term.write_str("\x1b[?1049h")?;
+        term.hide_cursor()?;
+
+        let (tx, keys) = mpsc::unbounded_channel();
+        let input = term.clone();
+        std::thread::spawn(move || {
+            while let Ok(key) = input.read_key() {
+                if tx.send(key).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let gpu = match caps::gpu() {
+            Some(gpu) => gpu.name,
+            None => "not detected".into(),
+        };
+        Ok(Self {
+            term,
+            keys,
+            monitor: Monitor::start(),
+            view: View::new(title, target, gpu),
+            #[cfg(unix)]
+            termios,
+        })
+    }
+
+    pub fn update(&mut self, update: &Update) {
+        self.view.update(update);
+    }
+
+    /// Redraw the whole screen.
+    pub fn draw(&mut self) -> io::Result<()> {
+        if let Some(sample) = self.monitor.as_mut().and_then(|m| m.sample()) {
+            self.view.gpu = sample.to_string().trim_end_matches(", ").into();
+        }
+        let (height, width) = self.term.size();
+        let mut out = "\x1b[H".to_owned();
+        // leave the last line empty to avoid scrolling
+        for line in self
+            .view
+            .lines(width.into())
+            .iter()
+            .take(usize::from(height).saturating_sub(1))
+        {
+            let line = console::truncate_str(line, width.into(), "…");
+            writeln!(out, "{line}\x1b[K").unwrap();
+        }
+        out.push_str("\x1b[J");
+        self.term.write_str(&out)
+    }
+
+    /// Wait for a keyboard action. Cancel safe.
+    pub async fn action(&mut self) -> Action {
+        while let Some(key) = self.keys.recv().await {
+            match key {
+                Key::Char('q') | Key::Escape => return Action::Abort,
+                Key::Char('a') | Key::Enter => match self.view.best() {
+                    Some(best) => return Action::Accept(best),
+                    None => {
+                        self.view.notice = Some("no attempt meets the target yet".into());
+                        _ = self.draw();
+                    }
+                },
+                _ => {}
+            }
+        }
+        // no keyboard input
+        std::future::pending().await
+    }
+}
+
+impl Drop for Tui {
+    fn drop(&mut self) {
+        // the input thread may be blocked reading a key in raw mode
+        #[cfg(unix)]
+        if let Some(termios) = &self.termios {
+            // SAFETY: termios was initialized by tcgetattr
+            unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSADRAIN, termios) };
+        }
+        _ = self.term.show_cursor();
+        _ = self.term.write_str("\x1b[?1049l");
+    }
+}
+
+/// Returns the current stdin terminal attributes.
+#[cfg(unix)]
+fn termios() -> Option<libc::termios> {
+    let mut termios = std::mem::MaybeUninit::uninit();
+    // SAFETY: tcgetattr initializes termios when successful
+    match unsafe { libc::tcgetattr(libc::STDIN_FILENO, termios.as_mut_ptr()) } {
+        0 => Some(unsafe { termios.assume_init() }),
+        _ => None,
+    }
+}
+
+/// Search state to draw.
+#[derive(Debug)]
+struct View {
+    title: String,
+    target: Target,
+    /// Completed crf attempts in search order.
+    attempts: Vec<Sample>,
+    /// Recent sample results.
+    samples: Vec<String>,
+    status: String,
+    gpu: String,
+    notice: Option<String>,
+}
+
+impl View {
+    fn new(title: String, target: Target, gpu: String) -> Self {
+        Self {
+            title,
+            target,
+            attempts: vec![],
+            samples: vec![],
+            status: "starting".into(),
+            gpu,
+            notice: None,
+        }
+    }
+
+    fn update(&mut self, update: &Update) {
+        match update {
+            Update::Status { crf, sample, .. } => {
+                let crf = TerseF32(*crf);
+                let mut status = match sample.full_pass {
+                    true => format!("crf {crf} full pass"),
+                    false => format!("crf {crf} sample {}/{}", sample.sample, sample.samples),
+                };
+                write!(
+                    status,
+                    " {} {:.0}%",
+                    sample.work.fps_label(),
+                    sample.progress * 100.0
+                )
+                .unwrap();
+                if sample.fps > 0.0 {
+                    write!(status, " {} fps", sample.fps).unwrap();
+                }
+                self.status = status;
+            }
+            Update::SampleResult {
+                crf,
+                sample,
+                result,
+            } => {
+                if self.samples.len() == SAMPLE_LINES {
+                    self.samples.remove(0);
+                }
+                self.samples.push(format!(
+                    "crf {} sample {sample} {} {:.2} ({:.0}%){}",
+                    TerseF32(*crf),
+                    result.score_kind,
+                    result.score,
+                    result.encoded_size as f64 * 100.0 / result.sample_size.max(1) as f64,
+                    if result.from_cache { " (cache)" } else { "" },
+                ));
+            }
+            Update::RunResult(sample) => {
+                self.attempts.push(sample.clone());
+                self.notice = None;
+            }
+            Update::Done(_) => {}
+        }
+    }
+
+    /// The best attempt so far meeting the target, the highest crf delivering the min score,
+    /// or the lowest crf fitting a size target.
+    fn best(&self) -> Option<Sample> {
+        let meeting = self.attempts.iter().filter(|s| self.target.meets(s));
+        let best = match self.target.size_target_only {
+            true => meeting.min_by_key(|s| s.q),
+            false => meeting.max_by_key(|s| s.q),
+        };
+        best.cloned()
+    }
+
+    fn lines(&self, width: usize) -> Vec<String> {
+        let best_q = self.best().map(|b| b.q);
+        let mut lines = vec![
+            format!("{} {}", style("ab-av1 crf-search").bold(), self.title),
+            String::new(),
+            style("Search").bold().to_string(),
+        ];
+        if self.attempts.is_empty() {
+            lines.push(style("  waiting for the first attempt").dim().to_string());
+        }
+        for (idx, s) in self.attempts.iter().enumerate() {
+            let indent = "   ".repeat(idx.min(8));
+            let branch = if idx == 0 { "●" } else { "└─" };
+            let mark = match self.target.meets(s) {
+                true if best_q == Some(s.q) => style("★ best").green().bold(),
+                true => style("✓").green(),
+                false => style("✗").red(),
+            };
+            lines.push(format!(
+                "  {indent}{branch} crf {} {} {:.2} ({:.0}%) {mark}",
+                TerseF32(s.crf()),
+                s.enc.score_kind,
+                s.enc.score,
+                s.enc.encode_percent,
+            ));
+        }
+
+        lines.push(String::new());
+        lines.push(style("Samples").bold().to_string());
+        lines.extend(self.samples.iter().map(|s| format!("  {s}")));
+
+        lines.push(String::new());
+        lines.push(format!("{} {}", style("GPU").bold(), self.gpu));
+
+        lines.push(String::new());
+        lines.push(format!(
+            "{} {}",
+            style("Quality/size").bold(),
+            style("score by encoded percent, labelled by crf").dim()
+        ));
+        lines.extend(self.chart(width, CHART_HEIGHT));
+
+        lines.push(String::new());
+        lines.push(style(&self.status).cyan().to_string());
+        let accept = match best_q {
+            Some(_) => format!(
+                "a/enter accept crf {}",
+                TerseF32(self.best().unwrap().crf())
+            ),
+            None => "a/enter accept best".into(),
+        };
+        let mut footer = style(format!("{accept} · q/esc abort")).dim().to_string();
+        if let Some(notice) = &self.notice {
+            write!(footer, " {}", style(notice).yellow()).unwrap();
+        }
+        lines.push(footer);
+        lines
+    }
+
+    /// Returns lines of a chart plotting attempt scores against encoded percent, with the
+    /// min score & max encoded percent marked.
+    fn chart(&self, width: usize, height: usize) -> Vec<String> {
+        let cols = width.saturating_sub(AXIS_WIDTH + 1).max(10);
+        let Target {
+            min_score,
+            max_encoded_percent,
+            size_target_only,
+            ..
+        } = self.target;
+        let show_min = !size_target_only && min_score > 0.0;
+
+        let scores = self.attempts.iter().map(|s| s.enc.score);
+        let (mut lo, mut hi) = match show_min {
+            true => (min_score, min_score),
+            false => (f32::MAX, f32::MIN),
+        };
+        for score in scores {
+            lo = lo.min(score);
+            hi = hi.max(score);
+        }
+        if lo > hi {
+            (lo, hi) = (0.0, 100.0);
+        }
+        let (lo, hi) = (lo.floor() - 1.0, hi.ceil() + 1.0);
+        let x_max = self
+            .attempts
+            .iter()
+            .map(|s| s.enc.encode_percent as f32)
+            .fold(max_encoded_percent, f32::max)
+            * 1.1;
+
+        let row = |score: f32| (((hi - score) / (hi - lo)) * (height - 1) as f32).round() as usize;
+        let col = |percent: f32| ((percent / x_max) * (cols - 1) as f32).round() as usize;
+
+        let mut grid = vec![vec![" ".to_owned(); cols]; height];
+        if show_min {
+            let r = row(min_score);
+            for cell in &mut grid[r] {
+                *cell = style("┄").dim().to_string();
+            }
+        }
+        let max_col = col(max_encoded_percent).min(cols - 1);
+        for line in &mut grid {
+            line[max_col] = style("┆").dim().to_string();
+        }
+        for s in &self.attempts {
+            let (r, c) = (
+                row(s.enc.score).min(height - 1),
+                col(s.enc.encode_percent as f32).min(cols - 1),
+            );
+            let point = match self.target.meets(s) {
+                true => style("●").green(),
+                false => style("●").red(),
+            };
+            grid[r][c] = point.to_string();
+            for (offset, ch) in TerseF32(s.crf()).to_string().chars().enumerate() {
+                if let Some(cell) = grid[r].get_mut(c + 1 + offset) {
+                    *cell = ch.to_string();
+                }
+            }
+        }
+
+        let mut lines: Vec<_> = grid
+            .into_iter()
+            .enumerate()
+            .map(|(r, cells)| {
+                let label = match r {
+                    0 => format!("{hi:>5.1} ┤"),
+                    r if r == height - 1 => format!("{lo:>5.1} ┤"),
+                    _ => "      │".into(),
+                };
+                format!("{label}{}", cells.concat())
+            })
+            .collect();
+        lines.push(format!("      └{}", "─".repeat(cols)));
+        let x_max_label = format!("{x_max:.0}%");
+        lines.push(format!(
+            "       0%{x_max_label:>width$}",
+            width = cols.saturating_sub(2)
+        ));
+        lines
+    }
+}
+
+#[cfg(test)]
+fn test_sample(q: u64, score: f32, encode_percent: f64) -> Sample {
+    use crate::command::sample_encode::{Output, Prediction, ScoreKind};
+    Sample {
+        enc: Output {
+            score,
+            score_error: 0.0,
+            score_kind: ScoreKind::Vmaf,
+            min_frame_score: None,
+            frame_stats: None,
+            psnr: None,
+            ssim: None,
+            encode_percent,
+            prediction: Prediction::default(),
+            from_cache: false,
+        },
+        crf_increment: 1.0,
+        q,
+        quantization_limited: false,
+        iteration_limited: false,
+        score_shortfall: None,
+    }
+}
+
+#[test]
+fn tui_best_attempt() {
+    let target = Target {
+        min_score: 95.0,
+        min_frame_vmaf: None,
+        max_encoded_percent: 80.0,
+        max_size: None,
+        size_target_only: false,
+    };
+    let mut view = View::new("vid.mkv".into(), target, "-".into());
+    assert!(view.best().is_none());
+
+    view.update(&Update::RunResult(test_sample(32, 94.1, 40.0)));
+    assert!(view.best().is_none());
+    view.update(&Update::RunResult(test_sample(24, 96.5, 60.0)));
+    view.update(&Update::RunResult(test_sample(28, 95.4, 50.0)));
+    view.update(&Update::RunResult(test_sample(10, 99.0, 90.0)));
+    assert_eq!(view.best().map(|b| b.q), Some(28));
+
+    view.target.size_target_only = true;
+    assert_eq!(view.best().map(|b| b.q), Some(24), "best quality that fits");
+}
+
+#[test]
+fn tui_lines() {
+    let target = Target {
+        min_score: 95.0,
+        min_frame_vmaf: None,
+        max_encoded_percent: 80.0,
+        max_size: None,
+        size_target_only: false,
+    };
+    let mut view = View::new("vid.mkv".into(), target, "RTX 3060".into());
+    view.update(&Update::RunResult(test_sample(32, 94.1, 40.0)));
+    view.update(&Update::RunResult(test_sample(24, 96.5, 60.0)));
+
+    let lines: Vec<_> = view
+        .lines(80)
+        .iter()
+        .map(|l| console::strip_ansi_codes(l).into_owned())
+        .collect();
+    assert!(
+        lines.contains(&"  ● crf 32 VMAF 94.10 (40%) ✗".to_owned()),
+        "{lines:#?}"
+    );
+    assert!(
+        lines.contains(&"     └─ crf 24 VMAF 96.50 (60%) ★ best".to_owned()),
+        "{lines:#?}"
+    );
+    assert!(lines.contains(&"GPU RTX 3060".to_owned()), "{lines:#?}");
+    assert_eq!(lines.last().unwrap(), "a/enter accept crf 24 · q/esc abort");
+
+    let chart = view.chart(80, CHART_HEIGHT);
+    assert_eq!(chart.len(), CHART_HEIGHT + 2);
+    let chart: Vec<_> = chart
+        .iter()
+        .map(|l| console::strip_ansi_codes(l).into_owned())
+        .collect();
+    assert!(chart[0].starts_with(" 98.0 ┤"), "{chart:#?}");
+    assert!(chart.iter().any(|l| l.contains("●32")), "{chart:#?}");
+    assert!(chart.iter().any(|l| l.contains("●24")), "{chart:#?}");
+}
@@ -0,0 +1,302 @@
+use crate::{
+    console_ext::style,
+    cuda::caps,
+    process::{CommandJobEnv, program},
+    vmaf::{self, cuda::Support},
+};
+use anyhow::bail;
+use clap::Parser;
+use console::style;
+use std::{
+    io,
+    process::{Command, Stdio},
+};
+
+/// Check the environment for the CUDA pipeline, printing pass/fail results
+/// with suggested fixes.
+///
+/// * ffmpeg & ffprobe versions.
+/// * ffmpeg NVENC encoders, CUVID decoders, CUDA hwaccel & filters, e.g. libvmaf,
+///   libvmaf_cuda & scale_cuda.
+/// * GPU driver & VRAM using nvidia-smi.
+/// * A libvmaf_cuda probe run.
+///
+/// Exits with an error if any check fails. Warnings are missing optional features.
+#[derive(Parser)]
+#[clap(verbatim_doc_comment)]
+#[group(skip)]
+pub struct Args {}
+
+/// Result of a check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Pass,
+    Warn,
+    Fail,
+}
+
+struct Check {
+    status: Status,
+    name: &'static str,
+    detail: String,
+    /// Suggested fix, if not passed.
+    fix: Option<&'static str>,
+}
+
+impl Check {
+    fn new(status: Status, name: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            status,
+            name,
+            detail: detail.into(),
+            fix: None,
+        }
+    }
+
+    fn fix(mut self, fix: &'static str) -> Self {
+        if self.status != Status::Pass {
+            self.fix = Some(fix);
+        }
+        self
+    }
+
+    fn print(&self) {
+        let mark = match self.status {
+            Status::Pass => style("✓").green().bold(),
+            Status::Warn => style("!").yellow().bold(),
+            Status::Fail => style("✗").red().bold(),
+        };
+        println!("{mark} {}: {}", style(self.name).bold(), self.detail);
+        if let Some(fix) = self.fix {
+            println!("  {}", style!("→ {fix}").dim());
+        }
+    }
+}
+
+pub async fn doctor(Args {}: Args) -> anyhow::Result<()> {
+    let mut checks = vec![];
+
+    let ffmpeg = version("ffmpeg");
+    checks.push(
+        match &ffmpeg {
+            Some(v) => Check::new(Status::Pass, "ffmpeg", v),
+            None => Check::new(Status::Fail, "ffmpeg", "not found"),
+        }
+        .fix("install ffmpeg & ensure it is in PATH"),
+    );
+    checks.push(
+        match version("ffprobe") {
+            Some(v) => Check::new(Status::Pass, "ffprobe", v),
+            None => Check::new(Status::Fail, "ffprobe", "not found"),
+        }
+        .fix("install ffprobe, usually packaged with ffmpeg, & ensure it is in PATH"),
+    );
+
+    if ffmpeg.is_some() {
+        checks.extend(ffmpeg_checks());
+    }
+    checks.push(gpu_check());
+    checks.push(vmaf_cuda_check());
+
+    for check in &checks {
+        check.print();
+    }
+
+    let failed = checks.iter().filter(|c| c.status == Status::Fail).count();
+    let warned = checks.iter().filter(|c| c.status == Status::Warn).count();
+    println!(
+        "\n{} passed, {warned} warnings, {failed} failed",
+        checks.len() - failed - warned
+    );
+    if failed > 0 {
+        bail!("{failed} checks failed");
+    }
+    Ok(())
+}
+
+/// Checks of ffmpeg features.
+fn ffmpeg_checks() -> Vec<Check> {
+    let encoders = ffmpeg_list("-encoders");
+    let decoders = ffmpeg_list("-decoders");
+    let filters = ffmpeg_list("-filters");
+    let hwaccels = ffmpeg_list("-hwaccels");
+
+    let nvenc = names(&encoders, |n| n.ends_with("_nvenc"));
+    let cuvid = names(&decoders, |n| n.ends_with("_cuvid"));
+    // -hwaccels lists one name per line
+    let cuda_hwaccel = hwaccels.lines().any(|l| l.trim() == "cuda");
+    let has_filter = |filter: &str| !names(&filters, |n| n == filter).is_empty();
+
+    let listed = |status, name, found: Vec<&str>, fix| {
+        let detail = match found.is_empty() {
+            true => "none".into(),
+            false => found.join(", "),
+        };
+        Check::new(status, name, detail).fix(fix)
+    };
+    let filter = |filter: &'static str, missing, fix| {
+        let status = match has_filter(filter) {
+            true => Status::Pass,
+            false => missing,
+        };
+        let detail = match status {
+            Status::Pass => "available",
+            _ => "missing",
+        };
+        Check::new(status, filter, detail).fix(fix)
+    };
+
+    vec![
+        listed(
+            match nvenc.is_empty() {
+                true => Status::Warn,
+                false => Status::Pass,
+            },
+            "NVENC encoders",
+            nvenc,
+            "use an ffmpeg built with --enable-nvenc for GPU encoding, e.g. -e hevc_nvenc",
+        ),
+        listed(
+            match cuvid.is_empty() {
+                true => Status::Warn,
+                false => Status::Pass,
+            },
+            "CUVID decoders",
+            cuvid,
+            "use an ffmpeg built with --enable-cuvid for --cuda-decoder",
+        ),
+        Check::new(
+            match cuda_hwaccel {
+                true => Status::Pass,
+                false => Status::Warn,
+            },
+            "CUDA hwaccel",
+            match cuda_hwaccel {
+                true => "available",
+                false => "missing",
+            },
+        )
+        .fix(
+            "use an ffmpeg built with --enable-cuda-nvcc or --enable-ffnvcodec for NVDEC decoding",
+        ),
+        filter(
+            "libvmaf",
+            Status::Warn,
+            "use an ffmpeg built with --enable-libvmaf, otherwise crf-search falls back to PSNR",
+        ),
+        filter(
+            "libvmaf_cuda",
+            Status::Warn,
+            "use an ffmpeg built with libvmaf CUDA support, or set VMAF_CUDA_PATH to one, for GPU VMAF",
+        ),
+        filter(
+            "scale_cuda",
+            Status::Warn,
+            "use an ffmpeg built with --enable-cuda-nvcc or --enable-cuda-llvm for GPU scaling",
+        ),
+        filter(
+            "hwupload_cuda",
+            Status::Warn,
+            "use an ffmpeg built with --enable-ffnvcodec to upload frames for libvmaf_cuda",
+        ),
+    ]
+}
+
+/// nvidia-smi GPU driver & VRAM check.
+fn gpu_check() -> Check {
+    let check = match caps::query(None) {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            Check::new(Status::Warn, "GPU", "nvidia-smi not found")
+        }
+        Err(err) => Check::new(Status::Fail, "GPU", err.to_string()),
+        Ok(gpus) if gpus.is_empty() => Check::new(Status::Fail, "GPU", "no GPU found"),
+        Ok(gpus) => {
+            let gpus: Vec<_> = gpus
+                .iter()
+                .map(|gpu| {
+                    let nvenc = match gpu.nvenc_sessions {
+                        Some(sessions) => format!(", {sessions} NVENC sessions open"),
+                        None => String::new(),
+                    };
+                    format!(
+                        "{} (driver {}, {}/{} MiB VRAM used{nvenc})",
+                        gpu.name, gpu.driver, gpu.vram_used_mib, gpu.vram_total_mib
+                    )
+                })
+                .collect();
+            Check::new(Status::Pass, "GPU", gpus.join("; "))
+        }
+    };
+    check.fix("install the NVIDIA driver & ensure the GPU is visible, e.g. in containers")
+}
+
+/// libvmaf_cuda probe run check.
+fn vmaf_cuda_check() -> Check {
+    match vmaf::cuda::support() {
+        Support::Available(ffmpeg) => Check::new(
+            Status::Pass,
+            "VMAF CUDA",
+            format!("libvmaf_cuda works using {}", ffmpeg.display()),
+        ),
+        Support::Missing => Check::new(
+            Status::Warn,
+            "VMAF CUDA",
+            "no ffmpeg with libvmaf_cuda, scoring uses CPU libvmaf",
+        )
+        .fix("set VMAF_CUDA_PATH to an ffmpeg built with libvmaf CUDA support"),
+        Support::Failed(err) => Check::new(Status::Fail, "VMAF CUDA", err.as_str())
+            .fix("check the GPU driver, CUDA runtime & available VRAM"),
+    }
+}
+
+/// Returns the version line, e.g. "ffmpeg version 7.1", of an ffmpeg `program`.
+fn version(program: &str) -> Option<String> {
+    let out = Command::new(program::locate(program))
+        .job_env()
+        .args(["-hide_banner", "-version"])
+        .stdin(Stdio::null())
+        .output()
+        .ok()?;
+    let out = String::from_utf8_lossy(&out.stdout);
+    let line = out.lines().next()?;
+    Some(line.split(" Copyright").next().unwrap_or(line).to_owned())
+}
+
+/// Returns the stdout of an ffmpeg list `arg`, e.g. "-encoders".
+fn ffmpeg_list(arg: &str) -> String {
+    Command::new(program::ffmpeg())
+        .job_env()
+        .args(["-hide_banner", arg])
+        .stdin(Stdio::null())
+        .output()
+        .map(|out| String::from_utf8_lossy(&out.stdout).into_owned())
+        .unwrap_or_default()
+}
+
+/// Returns names of an ffmpeg `-encoders`, `-decoders` or `-filters` list
+/// matching `filter`. Names are the second column, after the flags.
+fn names(list: &str, filter: impl Fn(&str) -> bool) -> Vec<&str> {
+    list.lines()
+        .filter_map(|l| l.split_whitespace().nth(1))
+        .filter(|n| filter(n))
+        .collect()
+}
+
+#[test]
+fn parse_ffmpeg_lists() {
+    let encoders = "Encoders:
+ V..... = Video
+ ------
+ V....D libsvtav1            SVT-AV1(Scalable Video Technology for AV1) encoder (codec av1)
+ V....D av1_nvenc            NVIDIA NVENC av1 encoder (codec av1)
+ V....D hevc_nvenc           NVIDIA NVENC hevc encoder (codec hevc)";
+    assert_eq!(
+        names(encoders, |n| n.ends_with("_nvenc")),
+        ["av1_nvenc", "hevc_nvenc"]
+    );
+
+    let filters = " ... libvmaf           VV->V      Calculate the VMAF between two video streams.
+ ... scale_cuda        V->V       GPU accelerated video resizer";
+    assert_eq!(names(filters, |n| n == "scale_cuda"), ["scale_cuda"]);
+    assert!(names(filters, |n| n == "libvmaf_cuda").is_empty());
+}
@@ -1,19 +1,27 @@
+pub mod provenance;
+mod resume;
+pub mod script;
+
 use crate::{
+    chunk,
     command::{
         PROGRESS_CHARS, SmallDuration,
-        args::{self, Encoder},
+        args::{self, Encoder, PixelFormat},
     },
+    compat,
     console_ext::style,
-    ffmpeg,
-    ffprobe::{self, Ffprobe},
+    cuda::monitor::Monitor,
+    ffmpeg::{self, FfmpegEncodeArgs, SubtitleSelect, TwoPass},
+    ffprobe::{self, Ffprobe, MediaKind},
     log::ProgressLogger,
-    process::FfmpegOut,
+    process::{FfmpegOut, FfmpegOutStream, job_env},
+    scheduler, stdin,
     temporary::{self, TempKind},
 };
+use anyhow::{Context, ensure};
 use clap::Parser;
 use console::style;
 use indicatif::{HumanBytes, ProgressBar, ProgressStyle};
-use log::info;
 use std::{
     path::{Path, PathBuf},
     sync::Arc,
@@ -21,9 +29,10 @@ use std::{
 };
 use tokio::fs;
 use tokio_stream::StreamExt;
+use tracing::info;
 
 /// Invoke ffmpeg to encode a video or image.
-#[derive(Parser)]
+#[derive(Parser, Clone)]
 #[group(skip)]
 pub struct Args {
     #[clap(flatten)]
@@ -37,7 +46,7 @@ pub struct Args {
     pub encode: args::EncodeToOutput,
 }
 
-pub async fn encode(args: Args) -> anyhow::Result<()> {
+pub async fn encode(mut args: Args) -> anyhow::Result<()> {
     let bar = ProgressBar::new(1).with_style(
         ProgressStyle::default_bar()
             .template("{spinner:.cyan.bold} {elapsed_precise:.bold} {wide_bar:.cyan/blue} ({msg}eta {eta})")?
@@ -45,8 +54,19 @@ pub async fn encode(args: Args) -> anyhow::Result<()> {
     );
     bar.enable_steady_tick(Duration::from_millis(100));
 
-    let probe = ffprobe::probe(&args.args.input);
-    run(args, probe.into(), &bar).await
+    let probe = Arc::new(args.args.probe_input().await?);
+    loop {
+        match run(args.clone(), probe.clone(), &bar).await {
+            Err(err) => match args.args.hw_fallback(&err) {
+                Some((fallback, warning)) => {
+                    bar.println(style!("Warning: {warning}").yellow().to_string());
+                    args.args = fallback;
+                }
+                None => return Err(err),
+            },
+            done => return done,
+        }
+    }
 }
 
 pub async fn run(
@@ -59,17 +79,64 @@ pub async fn run(
                 audio_codec,
                 downmix_to_stereo,
                 video_only,
+                no_subs,
+                sub_langs,
+                map,
+                mp4_compat,
+                reproducible,
+                metadata,
+                provenance,
+                two_pass,
+                resume,
+                chunked,
+                workers,
+                gpus,
+                scene_threshold,
+                also_encode,
+                emit_script,
+                chunks,
             },
     }: Args,
     probe: Arc<Ffprobe>,
     bar: &ProgressBar,
 ) -> anyhow::Result<()> {
     let defaulting_output = output.is_none();
+    if stdin::is_stdin(&args.input) {
+        ensure!(!defaulting_output, "--output is required for stdin input");
+        ensure!(
+            !resume && !chunked,
+            "--resume & --chunked are not supported for stdin input"
+        );
+        ensure!(
+            also_encode
+                .as_ref()
+                .is_none_or(|also| also.output.is_some()),
+            "--also-encode output=FILE is required for stdin input"
+        );
+        ensure!(
+            emit_script.is_none(),
+            "--emit-script is not supported for stdin input"
+        );
+    }
     // let probe = ffprobe::probe(&args.input);
-    let output =
-        output.unwrap_or_else(|| default_output_name(&args.input, &args.encoder, probe.is_image));
+    let output = output
+        .unwrap_or_else(|| default_output_name(&args.input, &args.encoder, probe.media_kind()));
+    check_output(&output, &args.encoder, video_only, &probe)?;
     // output is temporary until encoding has completed successfully
     temporary::add(&output, TempKind::NotKeepable);
+    let also_output = also_encode.as_ref().map(|also| also.output(&args.input));
+    if let (Some(also), Some(also_output)) = (&also_encode, &also_output) {
+        ffmpeg::check_output_container(also_output, &also.vcodec, video_only)?;
+        ensure!(
+            !resume && !chunked,
+            "--also-encode is not supported with --resume or --chunked"
+        );
+        ensure!(
+            *also_output != output,
+            "--also-encode output must differ from the main output"
+        );
+        temporary::add(also_output, TempKind::NotKeepable);
+    }
 
     if defaulting_output {
         let out = shell_escape::escape(output.display().to_string().into());
@@ -79,9 +146,70 @@ pub async fn run(
 
     let mut enc_args = args.to_encoder_args(crf, &probe)?;
     enc_args.video_only = video_only;
+    enc_args.subtitles = match (no_subs, sub_langs) {
+        (true, _) => SubtitleSelect::None,
+        (_, langs) if !langs.is_empty() => SubtitleSelect::Languages(langs),
+        _ => SubtitleSelect::All,
+    };
+    enc_args.maps.extend(map);
+    for map in &enc_args.maps {
+        probe.validate_map(map)?;
+    }
+    if args.is_trimmed() {
+        ensure!(
+            !resume && !chunked,
+            "--start & --duration are not supported with --resume or --chunked"
+        );
+        enc_args.input_args.extend(args.trim_input_args());
+    }
+    enc_args.mp4_compat = mp4_compat;
+    if let Some(animation) = probe.animation.filter(|_| {
+        output
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("avif"))
+    }) {
+        // keep the input's looping, ffmpeg defaults to looping forever
+        enc_args.output_args.push("-loop".to_owned().into());
+        enc_args
+            .output_args
+            .push(animation.plays.to_string().into());
+    }
+    enc_args.reproducible = reproducible;
+    enc_args.metadata = args::MetadataArg::to_output_metadata(&metadata);
+    if !compat::upstream() {
+        enc_args
+            .metadata
+            .tags
+            .push(("ENCODER_SETTINGS".into(), args.encode_hint(crf).into()));
+    }
+    let resources = enc_args.resources();
+    let mut gpu = match resources.nvdec || resources.nvenc {
+        true => Monitor::start(),
+        false => None,
+    };
     let has_audio = probe.has_audio;
+    let two_pass =
+        match two_pass {
+            true => {
+                ensure!(
+                    !resume && !chunked && also_encode.is_none(),
+                    "--two-pass is not supported with --resume, --chunked or --also-encode"
+                );
+                ensure!(
+                    !stdin::is_stdin(&args.input) && !probe.is_image,
+                    "--two-pass is not supported for stdin or image inputs"
+                );
+                let two_pass = TwoPass::of(&enc_args.vcodec);
+                Some(two_pass.with_context(|| {
+                    format!("--two-pass is not supported by {}", enc_args.vcodec)
+                })?)
+            }
+            false => None,
+        };
+    let first_pass = two_pass.filter(|tp| tp.has_first_pass());
     if let Ok(d) = &probe.duration {
-        bar.set_length(d.as_micros_u64().max(1));
+        let passes = if first_pass.is_some() { 2 } else { 1 };
+        bar.set_length(d.as_micros_u64().max(1) * passes);
     }
 
     // only downmix if achannels > 3
@@ -90,43 +218,142 @@ pub async fn run(
     if stereo_downmix && audio_codec == Some("copy") {
         anyhow::bail!("--stereo-downmix cannot be used with --acodec copy");
     }
+    if mp4_compat {
+        for warning in mp4_compat_warnings(&output, &enc_args, &probe, audio_codec) {
+            bar.println(style!("Warning: {warning}").yellow().to_string());
+        }
+    }
+
+    if emit_script.is_some() || job_env::dry_run() {
+        let cmd = ffmpeg::encode_command(
+            enc_args,
+            &output,
+            has_audio,
+            audio_codec,
+            stereo_downmix,
+            also_encode.as_ref().zip(also_output.as_deref()),
+        );
+        // nothing was encoded
+        temporary::unadd(&output);
+        if let Some(also_output) = &also_output {
+            temporary::unadd(also_output);
+        }
+        bar.finish_and_clear();
+        match &emit_script {
+            Some(script) => {
+                let comments = [
+                    format!("Generated by ab-av1 v{}", env!("CARGO_PKG_VERSION")),
+                    format!("Encodes {}", output.display()),
+                    format!("Equivalent to: {}", args.encode_hint(crf)),
+                ];
+                script::write(script, cmd.as_std(), &comments)?;
+                eprintln!(
+                    "{} {}",
+                    style("Wrote encode script").dim(),
+                    style(script.display()).dim().bold()
+                );
+            }
+            None => script::print(&format!("Encode {}", output.display()), cmd.as_std()),
+        }
+        return Ok(());
+    }
 
     info!(
         "encoding {}",
         output.file_name().and_then(|n| n.to_str()).unwrap_or("")
     );
+    let encode_start = Instant::now();
 
-    let mut enc = ffmpeg::encode(enc_args, &output, has_audio, audio_codec, stereo_downmix)?;
-    let mut logger = ProgressLogger::new(module_path!(), Instant::now());
-    let mut stream_sizes = None;
-    while let Some(progress) = enc.next().await {
-        match progress? {
-            FfmpegOut::Progress { fps, time, .. } => {
-                if fps > 0.0 {
-                    bar.set_message(format!("{fps} fps, "));
-                }
-                if let Ok(d) = &probe.duration {
-                    bar.set_position(time.as_micros_u64());
-                    logger.update(*d, time, fps);
+    let stream_sizes = if resume || chunked {
+        ensure!(
+            !probe.is_image,
+            "--resume & --chunked are not supported for images"
+        );
+        let segments = match chunked {
+            true => {
+                let opts = chunk::Opts {
+                    workers,
+                    gpus,
+                    scene_threshold,
+                };
+                chunk::encode_chunks(&enc_args, &output, &probe, chunks, &opts, bar).await?
+            }
+            false => resume::encode_segments(&enc_args, &output, &probe, bar).await?,
+        };
+        bar.set_message("muxing, ");
+        let enc = ffmpeg::concat_segments(
+            enc_args,
+            &segments,
+            &output,
+            has_audio,
+            audio_codec,
+            stereo_downmix,
+        )?;
+        run_encode(enc, &probe, bar, None, &mut gpu).await?
+    } else {
+        let _permit = scheduler::acquire(enc_args.resources()).await;
+        let mut enc_args = enc_args;
+        if let Some(two_pass) = first_pass {
+            let stats =
+                temporary::process_dir(output.parent().map(Path::to_path_buf)).join("2pass");
+            for file in two_pass.stats_files(&stats) {
+                temporary::add(file, TempKind::NotKeepable);
+            }
+            let enc = ffmpeg::encode_first_pass(enc_args.clone(), two_pass, &stats)?;
+            run_encode(enc, &probe, bar, Some(1), &mut gpu).await?;
+            two_pass.add_args(2, &stats, &mut enc_args.output_args);
+        } else if let Some(two_pass) = two_pass {
+            two_pass.add_args(2, Path::new(""), &mut enc_args.output_args);
+        }
+        let pass = first_pass.map(|_| 2);
+        let mut retries = 0;
+        loop {
+            let enc = ffmpeg::encode(
+                enc_args.clone(),
+                &output,
+                has_audio,
+                audio_codec,
+                stereo_downmix,
+                also_encode.as_ref().zip(also_output.as_deref()),
+            )?;
+            match run_encode(enc, &probe, bar, pass, &mut gpu).await {
+                Err(err)
+                    if retries < ffmpeg::NVDEC_OOM_RETRIES
+                        && ffmpeg::is_nvdec_oom(&err)
+                        && enc_args.reduce_hw_frames() =>
+                {
+                    retries += 1;
+                    bar.println(
+                        style!(
+                            "Warning: NVDEC out of memory, retry {retries} with fewer hw frames"
+                        )
+                        .yellow()
+                        .to_string(),
+                    );
+                    tokio::time::sleep(ffmpeg::NVDEC_OOM_RETRY_DELAY * retries).await;
                 }
+                result => break result?,
             }
-            FfmpegOut::StreamSizes {
-                video,
-                audio,
-                subtitle,
-                other,
-            } => stream_sizes = Some((video, audio, subtitle, other)),
         }
-    }
-    enc.wait().await?; // ensure process has exited
+    };
     bar.finish();
 
     // successful encode, so don't delete it!
     temporary::unadd(&output);
+    if let Some(also_output) = &also_output {
+        temporary::unadd(also_output);
+    }
+    if resume {
+        resume::clean(&output);
+    }
+    if provenance {
+        let json = provenance::json(&args, crf, encode_start.elapsed());
+        provenance::write(&output, &json)?;
+    }
 
     // print output info
     let output_size = fs::metadata(&output).await?.len();
-    let output_percent = 100.0 * output_size as f64 / fs::metadata(&args.input).await?.len() as f64;
+    let output_percent = 100.0 * output_size as f64 / probe.input_len(&args.input)? as f64;
     let output_size = style(HumanBytes(output_size)).dim().bold();
     let output_percent = style!("{}%", output_percent.round()).dim().bold();
     eprint!(
@@ -150,40 +377,198 @@ pub async fn run(
         }
     }
     eprintln!("{}", style(")").dim());
+    if let Some(summary) = gpu.as_ref().and_then(Monitor::summary) {
+        eprintln!("{} {}", style("GPU").dim(), style(summary).dim());
+    }
+    if let Some(also_output) = &also_output {
+        let size = style(HumanBytes(fs::metadata(also_output).await?.len()))
+            .dim()
+            .bold();
+        eprintln!(
+            "{} {} {size}",
+            style("Also encoded").dim(),
+            style(also_output.display()).dim(),
+        );
+    }
 
     Ok(())
+}
+
+/// Run an ffmpeg encode to completion updating the progress `bar`.
+///
+/// Two-pass encode progress of `pass` 1 & 2 is shown across the whole bar.
+/// `gpu` telemetry, if monitoring, is appended to the bar message.
+///
+/// Returns the output (video, audio, subtitle, other) stream sizes, if reported.
+async fn run_encode(
+    mut enc: FfmpegOutStream,
+    probe: &Ffprobe,
+    bar: &ProgressBar,
+    pass: Option<u8>,
+    gpu: &mut Option<Monitor>,
+) -> anyhow::Result<Option<(u64, u64, u64, u64)>> {
+    let mut logger = ProgressLogger::new("encode", Instant::now());
+    let mut stream_sizes = None;
+    let pass_label = pass.map(|p| format!("pass {p}/2, ")).unwrap_or_default();
+    while let Some(progress) = enc.next().await {
+        match progress? {
+            FfmpegOut::Progress { fps, time, .. } => {
+                if fps > 0.0 {
+                    let gpu = gpu.as_mut().and_then(Monitor::sample);
+                    let gpu = gpu.map(|s| s.to_string()).unwrap_or_default();
+                    bar.set_message(format!("{pass_label}{fps} fps, {gpu}"));
+                }
+                if let Ok(d) = &probe.duration {
+                    let offset = match pass {
+                        Some(2) => d.as_micros_u64(),
+                        _ => 0,
+                    };
+                    bar.set_position(offset + time.as_micros_u64());
+                    logger.update(*d, time, fps);
+                }
+            }
+            FfmpegOut::StreamSizes {
+                video,
+                audio,
+                subtitle,
+                other,
+            } => stream_sizes = Some((video, audio, subtitle, other)),
+            FfmpegOut::EncoderStats(stats) => info!("encoder stats: {stats}"),
+        }
+    }
+    enc.wait().await?; // ensure process has exited
+    Ok(stream_sizes)
+}
+
+/// Returns reasons the output may fail to play on common hardware mp4 players.
+fn mp4_compat_warnings(
+    output: &Path,
+    enc_args: &FfmpegEncodeArgs,
+    probe: &Ffprobe,
+    audio_codec: Option<&str>,
+) -> Vec<String> {
+    let mut warnings = vec![];
+    if output.extension().and_then(|e| e.to_str()) != Some("mp4") {
+        warnings.push("--mp4-compat has no effect for non-mp4 outputs".into());
+        return warnings;
+    }
+    if ffmpeg::mp4_compat_tag(&enc_args.vcodec).is_none() {
+        warnings.push(format!(
+            "{} output is not commonly supported by hardware mp4 players",
+            enc_args.vcodec
+        ));
+    }
+    if let Some(pix_fmt) = PixelFormat::opt_max(enc_args.pix_fmt, probe.pixel_format())
+        .filter(|p| !matches!(p, PixelFormat::Yuv420p | PixelFormat::Yuv420p10le))
+    {
+        warnings.push(format!(
+            "pixel format {pix_fmt} is not supported by most hardware decoders, use --pix-format yuv420p10le"
+        ));
+    }
+    if let Some((w, h)) = probe.resolution.filter(|(w, h)| w * h > 4096 * 2304) {
+        warnings.push(format!(
+            "resolution {w}x{h} exceeds common hardware decoder limits"
+        ));
+    }
+    if let Some(fps) = probe.fps.as_ref().ok().filter(|fps| **fps > 60.5) {
+        warnings.push(format!(
+            "frame rate {fps:.2} exceeds common hardware decoder limits"
+        ));
+    }
+    if let Some(acodec) = audio_codec.filter(|c| {
+        matches!(
+            *c,
+            "libopus" | "opus" | "flac" | "libvorbis" | "vorbis" | "pcm_s16le" | "pcm_s24le"
+        )
+    }) {
+        warnings.push(format!(
+            "{acodec} audio in mp4 is not widely supported, consider --acodec aac"
+        ));
+    }
+    warnings
+}
 
-    // Run VMAF analysis
-    let vmaf_result = vmaf::run_vmaf(
-        &args.input,
-        &output,
-        &args.vmaf_model,
-        args.vmaf_cuda,
-        args.vmaf_surfaces
-    )?;
-
-    bar.println(format!(
-        "VMAF Score: {:.2}, PSNR: {:.2}dB, SSIM: {:.4}",
-        vmaf_result.vmaf_score, vmaf_result.psnr, vmaf_result.ssim
-    ))    
+/// Returns an error if the `output` container cannot hold the encoded video, or other
+/// streams. See [`ffmpeg::check_output_container`].
+pub fn check_output(
+    output: &Path,
+    encoder: &Encoder,
+    video_only: bool,
+    probe: &Ffprobe,
+) -> anyhow::Result<()> {
+    let single_stream = video_only || probe.is_image || probe.streams.len() <= 1;
+    ffmpeg::check_output_container(output, encoder.as_str(), single_stream)
 }
 
-/// * vid.mp4 -> "mp4"
+/// * vid.mp4 -> "mp4", if the encoder supports mp4
 /// * vid.??? -> "mkv"
 /// * image.??? -> "avif"
-pub fn default_output_ext(input: &Path, encoder: &Encoder, is_image: bool) -> &'static str {
-    if is_image {
-        return encoder.default_image_ext();
+/// * animation.gif -> "avif", if the encoder is av1, otherwise "mp4"
+pub fn default_output_ext(input: &Path, encoder: &Encoder, kind: MediaKind) -> &'static str {
+    match kind {
+        MediaKind::Image => return encoder.default_image_ext(),
+        MediaKind::Animation => return encoder.default_animation_ext(),
+        MediaKind::Video => {}
     }
-    match input.extension().and_then(|e| e.to_str()) {
-        Some("mp4") => "mp4",
+    match ffprobe::local_name(input)
+        .extension()
+        .and_then(|e| e.to_str())
+    {
+        Some("mp4") if ffmpeg::container_supports("mp4", encoder.as_str()) => "mp4",
         _ => "mkv",
     }
 }
 
 /// E.g. vid.mkv -> "vid.av1.mkv"
-pub fn default_output_name(input: &Path, encoder: &Encoder, is_image: bool) -> PathBuf {
+///
+/// Url inputs output to the current directory, e.g. "https://example.com/vid.mkv" -> "vid.av1.mkv".
+pub fn default_output_name(input: &Path, encoder: &Encoder, kind: MediaKind) -> PathBuf {
     let pre = ffmpeg::pre_extension_name(encoder.as_str());
-    let ext = default_output_ext(input, encoder, is_image);
-    input.with_extension(format!("{pre}.{ext}"))
+    let ext = default_output_ext(input, encoder, kind);
+    ffprobe::local_name(input).with_extension(format!("{pre}.{ext}"))
+}
+
+#[test]
+fn mp4_compat_warnings_422() {
+    let enc_args = FfmpegEncodeArgs {
+        input: Path::new("vid.mkv"),
+        video_stream: 0,
+        vcodec: "libsvtav1".into(),
+        vfilter: None,
+        pix_fmt: Some(PixelFormat::Yuv422p10le),
+        crf: 30.0,
+        preset: None,
+        output_args: vec![],
+        input_args: vec![],
+        video_only: false,
+        subtitles: <_>::default(),
+        maps: vec![],
+        mp4_compat: true,
+        reproducible: false,
+        metadata: <_>::default(),
+    };
+    let probe = Ffprobe {
+        duration: Ok(Duration::from_secs(300)),
+        has_audio: true,
+        max_audio_channels: None,
+        fps: Ok(24.0),
+        resolution: Some((1920, 1080)),
+        is_image: false,
+        animation: None,
+        trimmed_from: None,
+        pix_fmt: None,
+        field_order: None,
+        size: None,
+        streams: vec![],
+    };
+
+    let warnings = mp4_compat_warnings(Path::new("vid.av1.mp4"), &enc_args, &probe, None);
+    assert_eq!(warnings.len(), 1, "{warnings:?}");
+    assert!(warnings[0].contains("yuv422p10le"), "{warnings:?}");
+
+    let warnings = mp4_compat_warnings(Path::new("vid.av1.mkv"), &enc_args, &probe, None);
+    assert_eq!(
+        warnings,
+        ["--mp4-compat has no effect for non-mp4 outputs".to_owned()]
+    );
 }
@@ -0,0 +1,107 @@
+//! Encode provenance sidecar file, see --provenance.
+use crate::{
+    command::args,
+    process::{CommandJobEnv, program},
+};
+use anyhow::Context;
+use std::{
+    env,
+    ffi::OsString,
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    time::{Duration, SystemTime},
+};
+
+/// Sidecar file path, e.g. "vid.av1.mkv.ab-av1.json".
+pub fn path(output: &Path) -> PathBuf {
+    let mut path = OsString::from(output);
+    path.push(".ab-av1.json");
+    path.into()
+}
+
+/// Returns the provenance json of an encode of `args` at `crf` that took `encode_time`.
+pub fn json(args: &args::Encode, crf: f32, encode_time: Duration) -> serde_json::Value {
+    let ffmpeg_version = ffmpeg_version_output();
+    serde_json::json!({
+        "ab_av1_version": env!("CARGO_PKG_VERSION"),
+        "command": env::args().collect::<Vec<_>>(),
+        "encode_command": args.encode_hint(crf),
+        "ffmpeg_version": parse_ffmpeg_version(&ffmpeg_version),
+        "encoder": args.encoder.as_str(),
+        "encoder_version": encoder_version(args.encoder.as_str())
+            .or_else(|| parse_libavcodec_version(&ffmpeg_version)),
+        "crf": crf,
+        "encode_secs": encode_time.as_secs_f64(),
+        "finished": humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+    })
+}
+
+/// Write the provenance `json` of the `output`, see [`path`].
+pub fn write(output: &Path, json: &serde_json::Value) -> anyhow::Result<()> {
+    let path = path(output);
+    fs::write(&path, serde_json::to_vec_pretty(json)?)
+        .with_context(|| format!("writing {}", path.display()))
+}
+
+fn ffmpeg_version_output() -> String {
+    Command::new(program::ffmpeg())
+        .job_env()
+        .args(["-hide_banner", "-version"])
+        .stdin(Stdio::null())
+        .output()
+        .map(|out| String::from_utf8_lossy(&out.stdout).into_owned())
+        .unwrap_or_default()
+}
+
+/// Returns the standalone encoder version, e.g. "SVT-AV1 v2.3.0", if known.
+fn encoder_version(vcodec: &str) -> Option<String> {
+    if vcodec != "libsvtav1" {
+        return None;
+    }
+    let out = Command::new("SvtAv1EncApp")
+        .job_env()
+        .arg("--version")
+        .stdin(Stdio::null())
+        .output()
+        .ok()?;
+    let out = String::from_utf8_lossy(&out.stdout);
+    Some(out.lines().next()?.trim().to_owned()).filter(|v| !v.is_empty())
+}
+
+/// Parse the version line of `ffmpeg -version`, e.g. "ffmpeg version 7.1".
+fn parse_ffmpeg_version(out: &str) -> Option<&str> {
+    let line = out.lines().next()?;
+    Some(line.split(" Copyright").next().unwrap_or(line).trim())
+}
+
+/// Parse the libavcodec version of `ffmpeg -version`, e.g. "libavcodec 61.19.100".
+fn parse_libavcodec_version(out: &str) -> Option<String> {
+    let line = out
+        .lines()
+        .find(|l| l.trim_start().starts_with("libavcodec"))?;
+    let version = line
+        .split('/')
+        .next()?
+        .trim_start()
+        .strip_prefix("libavcodec")?;
+    Some(format!("libavcodec {}", version.replace(' ', "")))
+}
+
+#[test]
+fn parse_versions() {
+    let out = "ffmpeg version 7.1 Copyright (c) 2000-2024 the FFmpeg developers\n\
+               built with gcc 14.2.1\n\
+               libavutil      59. 39.100 / 59. 39.100\n\
+               libavcodec     61. 19.100 / 61. 19.100\n";
+    assert_eq!(parse_ffmpeg_version(out), Some("ffmpeg version 7.1"));
+    assert_eq!(
+        parse_libavcodec_version(out).as_deref(),
+        Some("libavcodec 61.19.100")
+    );
+    assert_eq!(parse_libavcodec_version("ffmpeg version 7.1"), None);
+    assert_eq!(
+        path(Path::new("dir/vid.av1.mkv")),
+        Path::new("dir/vid.av1.mkv.ab-av1.json")
+    );
+}
@@ -0,0 +1,241 @@
+//! Resumable segmented encoding using a checkpoint file.
+use crate::{
+    command::{SmallDuration, sample_encode::cache::BlakeStdHasher},
+    console_ext::style,
+    ffmpeg::{self, FfmpegEncodeArgs},
+    ffprobe::Ffprobe,
+    log::ProgressLogger,
+    process::FfmpegOut,
+    scheduler,
+};
+use anyhow::Context;
+use indicatif::ProgressBar;
+use serde::{Deserialize, Serialize};
+use std::{
+    ffi::OsString,
+    fs,
+    hash::Hash,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+use tokio_stream::StreamExt;
+use tracing::info;
+
+/// Approximate duration of each segment, i.e. the most work lost by an interruption.
+const SEGMENT_TIME: Duration = Duration::from_secs(60);
+
+/// Progress of a resumable encode.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Checkpoint {
+    /// Hash of the input & encoder args, a different hash means segments can't be reused.
+    hash: String,
+    /// Number of ffmpeg runs, used to name each run's segments uniquely.
+    runs: u32,
+    /// Completed segments in order.
+    segments: Vec<Segment>,
+    /// All video has been encoded.
+    complete: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Segment {
+    /// File name in the segment directory.
+    file: String,
+    /// End time of the segment in the input.
+    end: Duration,
+}
+
+impl Checkpoint {
+    fn load(path: &Path) -> Option<Self> {
+        let data = fs::read(path).ok()?;
+        serde_json::from_slice(&data).ok()
+    }
+
+    /// Write to a temp file then rename so an interruption can't corrupt the checkpoint.
+    fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let tmp = with_suffix(path, ".tmp");
+        fs::write(&tmp, serde_json::to_vec(self)?)?;
+        fs::rename(&tmp, path).context("writing checkpoint")
+    }
+
+    fn end(&self) -> Duration {
+        self.segments.last().map(|s| s.end).unwrap_or_default()
+    }
+}
+
+/// Checkpoint file path, e.g. "vid.av1.mkv.ab-av1-progress.json".
+pub fn checkpoint_path(output: &Path) -> PathBuf {
+    with_suffix(output, ".ab-av1-progress.json")
+}
+
+/// Segment directory path, e.g. "vid.av1.mkv.ab-av1-segments".
+fn segment_dir(output: &Path) -> PathBuf {
+    with_suffix(output, ".ab-av1-segments")
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = OsString::from(path);
+    path.push(suffix);
+    path.into()
+}
+
+/// Encode the video to segments next to the `output`, continuing from the checkpoint
+/// of a previous interrupted run with the same input & args.
+///
+/// Returns the path of an ffmpeg concat list of all segments.
+pub async fn encode_segments(
+    enc_args: &FfmpegEncodeArgs<'_>,
+    output: &Path,
+    probe: &Ffprobe,
+    bar: &ProgressBar,
+) -> anyhow::Result<PathBuf> {
+    let checkpoint_path = checkpoint_path(output);
+    let dir = segment_dir(output);
+    let hash = args_hash(enc_args, probe)?;
+
+    let mut checkpoint = match Checkpoint::load(&checkpoint_path) {
+        Some(mut c) if c.hash == hash => {
+            // only trust segments that still exist
+            if let Some(idx) = c.segments.iter().position(|s| !dir.join(&s.file).exists()) {
+                c.segments.truncate(idx);
+                c.complete = false;
+            }
+            c
+        }
+        _ => {
+            let _ = fs::remove_dir_all(&dir);
+            Checkpoint {
+                hash,
+                ..<_>::default()
+            }
+        }
+    };
+    fs::create_dir_all(&dir).with_context(|| format!("creating {}", dir.display()))?;
+
+    if !checkpoint.complete {
+        let start = checkpoint.end();
+        if !start.is_zero() {
+            let from = humantime::format_duration(Duration::from_secs(start.as_secs()));
+            info!("resuming encode from {from}");
+            bar.println(style!("Resuming encode from {from}").dim().to_string());
+        }
+        let run = checkpoint.runs;
+        checkpoint.runs += 1;
+        checkpoint.save(&checkpoint_path)?;
+
+        let list = dir.join(format!("{run:05}.csv"));
+        let _ = fs::remove_file(&list);
+        let permit = scheduler::acquire(enc_args.resources()).await;
+        let mut enc = ffmpeg::encode_segments(
+            enc_args.clone(),
+            start,
+            SEGMENT_TIME,
+            &dir.join(format!("{run:05}-%05d.mkv")),
+            &list,
+        )?;
+        let mut logger = ProgressLogger::new("encode", Instant::now());
+        let mut listed = 0;
+        while let Some(progress) = enc.next().await {
+            if let FfmpegOut::Progress { fps, time, .. } = progress? {
+                if fps > 0.0 {
+                    bar.set_message(format!("{fps} fps, "));
+                }
+                if let Ok(d) = &probe.duration {
+                    bar.set_position((start + time).as_micros_u64());
+                    logger.update(d.saturating_sub(start), time, fps);
+                }
+            }
+            if sync_segments(&mut checkpoint, &list, start, &mut listed) {
+                checkpoint.save(&checkpoint_path)?;
+            }
+        }
+        enc.wait().await?;
+        drop(permit);
+
+        sync_segments(&mut checkpoint, &list, start, &mut listed);
+        checkpoint.complete = true;
+        checkpoint.save(&checkpoint_path)?;
+    }
+
+    let concat: String = checkpoint
+        .segments
+        .iter()
+        .map(|s| format!("file '{}'\n", s.file))
+        .collect();
+    let concat_list = dir.join("segments.txt");
+    fs::write(&concat_list, concat)?;
+    Ok(concat_list)
+}
+
+/// Remove the checkpoint & segments after a successful encode.
+pub fn clean(output: &Path) {
+    let _ = fs::remove_file(checkpoint_path(output));
+    let _ = fs::remove_dir_all(segment_dir(output));
+}
+
+/// Add newly completed segments from the ffmpeg csv segment `list` of the current run.
+/// Returns true if any were added.
+fn sync_segments(
+    checkpoint: &mut Checkpoint,
+    list: &Path,
+    start: Duration,
+    listed: &mut usize,
+) -> bool {
+    let Ok(csv) = fs::read_to_string(list) else {
+        return false;
+    };
+    let new: Vec<_> = parse_segment_list(&csv, start)
+        .into_iter()
+        .skip(*listed)
+        .collect();
+    if new.is_empty() {
+        return false;
+    }
+    *listed += new.len();
+    checkpoint.segments.extend(new);
+    true
+}
+
+/// Parse ffmpeg csv segment list "file,start,end" lines, adding the run `start` offset.
+///
+/// Only complete lines are returned, as the last may be partially written.
+fn parse_segment_list(csv: &str, start: Duration) -> Vec<Segment> {
+    csv.split_inclusive('\n')
+        .filter(|line| line.ends_with('\n'))
+        .map_while(|line| {
+            let mut fields = line.trim().rsplitn(3, ',');
+            let end: f64 = fields.next()?.parse().ok()?;
+            let _start = fields.next()?;
+            let file = fields.next()?.trim_matches('"');
+            Some(Segment {
+                file: file.to_owned(),
+                end: start + Duration::from_secs_f64(end.max(0.0)),
+            })
+        })
+        .collect()
+}
+
+/// Hash of everything affecting the encoded video, including the input size & mtime
+/// so an input modified in place invalidates the checkpoint.
+fn args_hash(enc_args: &FfmpegEncodeArgs<'_>, probe: &Ffprobe) -> anyhow::Result<String> {
+    let input_len = probe.input_len(enc_args.input)?;
+    let input_mtime = fs::metadata(enc_args.input).and_then(|m| m.modified()).ok();
+    let mut hasher = blake3::Hasher::new();
+    let mut std_hasher = BlakeStdHasher(&mut hasher);
+    enc_args.input.hash(&mut std_hasher);
+    input_len.hash(&mut std_hasher);
+    input_mtime.hash(&mut std_hasher);
+    enc_args.sample_encode_hash(&mut std_hasher);
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+#[test]
+fn parse_segment_list_partial() {
+    let csv = "00001-00000.mkv,0.000000,60.060000\n\
+               00001-00001.mkv,60.060000,120.120000\n\
+               00001-00002.mkv,120.12";
+    let segments = parse_segment_list(csv, Duration::from_secs(30));
+    assert_eq!(segments.len(), 2, "{segments:?}");
+    assert_eq!(segments[0].file, "00001-00000.mkv");
+    assert_eq!(segments[1].end, Duration::from_secs_f64(150.12));
+}
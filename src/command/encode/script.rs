@@ -0,0 +1,119 @@
+//! Standalone shell scripts reproducing an encode, see --emit-script, & shell
+//! escaped commands printed by --dry-run.
+use anyhow::Context;
+use std::{borrow::Cow, fmt::Write, fs, path::Path, process::Command};
+
+/// Args only used to report progress to ab-av1, omitted from scripts.
+const PROGRESS_ARGS: &[&[&str]] = &[&["-progress", "pipe:1"], &["-nostats"]];
+
+/// Write a shell script to `path` running the ffmpeg `cmd`, preceded by `comments`.
+pub fn write(path: &Path, cmd: &Command, comments: &[String]) -> anyhow::Result<()> {
+    fs::write(path, script(cmd, comments))
+        .with_context(|| format!("writing {}", path.display()))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o755))?;
+    }
+    Ok(())
+}
+
+/// Print the `cmd`, preceded by a `comment`, to stdout.
+pub fn print(comment: &str, cmd: &Command) {
+    println!("# {comment}\n{}", command(cmd));
+}
+
+fn script(cmd: &Command, comments: &[String]) -> String {
+    let mut script = "#!/bin/sh\n".to_owned();
+    for comment in comments {
+        for line in comment.lines() {
+            writeln!(script, "# {line}").unwrap();
+        }
+    }
+    script.push_str("set -e\n");
+    script.push_str(&command(cmd));
+    script
+}
+
+/// Returns shell lines running `cmd`, one option & its value per line.
+fn command(cmd: &Command) -> String {
+    let mut script = String::new();
+
+    // env set by --process-env
+    for (key, val) in cmd.get_envs() {
+        if let Some(val) = val {
+            writeln!(
+                script,
+                "export {}={}",
+                key.to_string_lossy(),
+                escape(&val.to_string_lossy())
+            )
+            .unwrap();
+        }
+    }
+    if let Some(dir) = cmd.get_current_dir() {
+        writeln!(script, "cd {}", escape(&dir.to_string_lossy())).unwrap();
+    }
+
+    script.push_str(&escape(&cmd.get_program().to_string_lossy()));
+    let args: Vec<_> = cmd.get_args().map(|a| a.to_string_lossy()).collect();
+    let mut args = args.as_slice();
+    while let Some(arg) = args.first() {
+        if let Some(skip) = PROGRESS_ARGS
+            .iter()
+            .find(|p| args.len() >= p.len() && args.iter().zip(p.iter()).all(|(a, p)| a == p))
+        {
+            args = &args[skip.len()..];
+            continue;
+        }
+        // one option & its value per line
+        match arg.starts_with('-') && arg.len() > 1 {
+            true => write!(script, " \\\n  {}", escape(arg)).unwrap(),
+            false => write!(script, " {}", escape(arg)).unwrap(),
+        }
+        args = &args[1..];
+    }
+    script.push('\n');
+    script
+}
+
+fn escape(s: &str) -> Cow<'_, str> {
+    shell_escape::escape(s.into())
+}
+
+#[test]
+fn encode_script() {
+    let mut cmd = Command::new("ffmpeg");
+    cmd.env("CUDA_VISIBLE_DEVICES", "1").args([
+        "-progress",
+        "pipe:1",
+        "-nostats",
+        "-hwaccel",
+        "cuda",
+        "-y",
+        "-i",
+        "my vid.mkv",
+        "-c:v:0",
+        "libsvtav1",
+        "-crf",
+        "32",
+        "-vf",
+        "scale=1280:-2",
+        "out.mkv",
+    ]);
+    assert_eq!(
+        script(&cmd, &["Encode my vid.mkv".into()]),
+        "#!/bin/sh
+# Encode my vid.mkv
+set -e
+export CUDA_VISIBLE_DEVICES=1
+ffmpeg \\
+  -hwaccel cuda \\
+  -y \\
+  -i 'my vid.mkv' \\
+  '-c:v:0' libsvtav1 \\
+  -crf 32 \\
+  -vf 'scale=1280:-2' out.mkv
+"
+    );
+}
@@ -0,0 +1,521 @@
+use crate::{
+    command::{PROGRESS_CHARS, args::PixelFormat, encode::script},
+    error::Error,
+    float::TerseF32,
+    process::{CommandExt, CommandJobEnv, ensure_success, job_env, program},
+    temporary::{self, TempKind},
+};
+use anyhow::{Context, ensure};
+use clap::{Parser, ValueEnum, ValueHint};
+use console::style;
+use indicatif::{HumanBytes, ProgressBar, ProgressStyle};
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+    process::Stdio,
+    time::Duration,
+};
+use tokio::process::Command;
+
+/// Image extensions read from directory inputs.
+const IMAGE_EXTS: &[&str] = &[
+    "png", "jpg", "jpeg", "webp", "bmp", "tif", "tiff", "ppm", "pgm", "exr",
+];
+
+/// Encode images to AVIF or JPEG-XL, searching for the lowest quality meeting a
+/// SSIMULACRA2 or butteraugli target.
+///
+/// Inputs may be image files or directories of images, e.g. an image sequence,
+/// each image being searched & encoded separately.
+///
+/// Scoring runs the libjxl `ssimulacra2` or `butteraugli_main` tools, which must be
+/// installed, comparing PNG decodes of the original & encoded image.
+#[derive(Parser)]
+#[clap(verbatim_doc_comment)]
+#[group(skip)]
+pub struct Args {
+    /// Input image files, or directories of images.
+    #[arg(required = true, value_hint = ValueHint::AnyPath)]
+    pub inputs: Vec<PathBuf>,
+
+    /// Encoder. libsvtav1 & libaom-av1 write AVIF, libjxl writes JPEG-XL.
+    #[arg(long, value_enum, default_value_t = ImageEncoder::Libsvtav1)]
+    pub encoder: ImageEncoder,
+
+    /// Encoder preset, or effort for libjxl. Defaults to 4 for av1 encoders & 7 for libjxl.
+    #[arg(long)]
+    pub preset: Option<u8>,
+
+    /// Pixel format of av1 encodes. Defaults to yuv444p10le for libaom-av1,
+    /// otherwise yuv420p10le as svt-av1 only supports 4:2:0.
+    #[arg(long, value_enum)]
+    pub pix_format: Option<PixelFormat>,
+
+    /// Image quality metric to target.
+    #[arg(long, value_enum, default_value_t = ImageMetric::Ssimulacra2)]
+    pub metric: ImageMetric,
+
+    /// Target score. The minimum SSIMULACRA2 score, default 80, or the maximum
+    /// butteraugli distance, default 1.5.
+    #[arg(long)]
+    pub target: Option<f32>,
+
+    /// Directory to write encoded images to. Defaults to alongside each input.
+    #[arg(long, value_hint = ValueHint::DirPath)]
+    pub output_dir: Option<PathBuf>,
+
+    /// Directory to store temporary data in.
+    /// Defaults to the current working directory.
+    #[arg(long, env = "AB_AV1_TEMP_DIR", value_hint = ValueHint::DirPath)]
+    pub temp_dir: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ImageEncoder {
+    Libsvtav1,
+    #[value(name = "libaom-av1")]
+    LibaomAv1,
+    Libjxl,
+}
+
+impl ImageEncoder {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Libsvtav1 => "libsvtav1",
+            Self::LibaomAv1 => "libaom-av1",
+            Self::Libjxl => "libjxl",
+        }
+    }
+
+    fn ext(self) -> &'static str {
+        match self {
+            Self::Libjxl => "jxl",
+            _ => "avif",
+        }
+    }
+
+    /// Name of the quality arg searched, higher values meaning lower quality.
+    fn quality_name(self) -> &'static str {
+        match self {
+            Self::Libjxl => "distance",
+            _ => "crf",
+        }
+    }
+
+    /// Returns the min, max & step of searched quality values.
+    fn quality_range(self) -> (f32, f32, f32) {
+        match self {
+            Self::Libjxl => (0.1, 15.0, 0.05),
+            _ => (1.0, 63.0, 1.0),
+        }
+    }
+
+    /// Ffmpeg output args encoding a still image at `quality`.
+    fn args(self, quality: f32, preset: Option<u8>, pix_fmt: Option<PixelFormat>) -> Vec<String> {
+        let q = TerseF32(quality).to_string();
+        let mut args: Vec<String> = match self {
+            Self::Libsvtav1 => vec![
+                "-crf".into(),
+                q,
+                "-preset".into(),
+                preset.unwrap_or(4).to_string(),
+                // still-picture mode, tuned for single frame AVIF
+                "-svtav1-params".into(),
+                "avif=1".into(),
+            ],
+            Self::LibaomAv1 => vec![
+                "-crf".into(),
+                q,
+                "-b:v".into(),
+                "0".into(),
+                "-cpu-used".into(),
+                preset.unwrap_or(4).to_string(),
+                "-still-picture".into(),
+                "1".into(),
+            ],
+            Self::Libjxl => vec![
+                "-distance".into(),
+                q,
+                "-effort".into(),
+                preset.unwrap_or(7).to_string(),
+            ],
+        };
+        let pix_fmt = match self {
+            Self::Libjxl => None,
+            Self::LibaomAv1 => Some(pix_fmt.unwrap_or(PixelFormat::Yuv444p10le)),
+            Self::Libsvtav1 => Some(pix_fmt.unwrap_or(PixelFormat::Yuv420p10le)),
+        };
+        if let Some(pix_fmt) = pix_fmt {
+            args.extend(["-pix_fmt".into(), pix_fmt.to_string()]);
+        }
+        args
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ImageMetric {
+    Ssimulacra2,
+    Butteraugli,
+}
+
+impl ImageMetric {
+    fn program(self) -> &'static Path {
+        match self {
+            Self::Ssimulacra2 => program::locate("ssimulacra2"),
+            Self::Butteraugli => program::locate("butteraugli_main"),
+        }
+    }
+
+    fn default_target(self) -> f32 {
+        match self {
+            Self::Ssimulacra2 => 80.0,
+            Self::Butteraugli => 1.5,
+        }
+    }
+
+    /// Returns true if `score` meets the `target`, butteraugli distances being lower is better.
+    fn meets(self, score: f32, target: f32) -> bool {
+        match self {
+            Self::Ssimulacra2 => score >= target,
+            Self::Butteraugli => score <= target,
+        }
+    }
+}
+
+impl fmt::Display for ImageMetric {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ssimulacra2 => "SSIMULACRA2".fmt(f),
+            Self::Butteraugli => "butteraugli".fmt(f),
+        }
+    }
+}
+
+/// Best encode of an image.
+struct Encoded {
+    quality: f32,
+    score: f32,
+    size: u64,
+}
+
+pub async fn image(
+    Args {
+        inputs,
+        encoder,
+        preset,
+        pix_format,
+        metric,
+        target,
+        output_dir,
+        temp_dir,
+    }: Args,
+) -> anyhow::Result<()> {
+    let target = target.unwrap_or(metric.default_target());
+    let images = expand_inputs(&inputs)?;
+    ensure!(!images.is_empty(), "no images found");
+
+    let bar = ProgressBar::new(images.len() as _).with_style(
+        ProgressStyle::default_bar()
+            .template("{spinner:.cyan.bold} {elapsed_precise:.bold} {prefix} {wide_bar:.cyan/blue} ({msg}eta {eta})")?
+            .progress_chars(PROGRESS_CHARS)
+    );
+    bar.enable_steady_tick(Duration::from_millis(100));
+
+    let temp_dir = temporary::process_dir(temp_dir);
+    let (mut total_input, mut total_output) = (0, 0);
+    for input in images {
+        let output = output_path(&input, output_dir.as_deref(), encoder);
+        if job_env::dry_run() {
+            bar.finish_and_clear();
+            let (min, max, _) = encoder.quality_range();
+            let cmd = encode_command(
+                &input,
+                &output,
+                encoder,
+                encoder.args((min + max) / 2.0, preset, pix_format),
+            );
+            script::print(&format!("Encode {}", input.display()), cmd.as_std());
+            continue;
+        }
+        bar.set_prefix(
+            input
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+        );
+
+        let reference = temp_dir.join(format!("{}.ref.png", file_stem(&input)));
+        decode_png(&input, &reference).await?;
+        temporary::add(&reference, TempKind::NotKeepable);
+
+        let best = search(
+            &input, &reference, &temp_dir, &output, encoder, preset, pix_format, metric, target,
+            &bar,
+        )
+        .await?;
+        let input_size = fs::metadata(&input).map_or(0, |m| m.len());
+        total_input += input_size;
+        total_output += best.size;
+
+        let percent = 100.0 * best.size as f64 / input_size.max(1) as f64;
+        bar.println(format!(
+            "{} {} {}, {metric} {}, size {} ({})",
+            output.display(),
+            encoder.quality_name(),
+            style(TerseF32(best.quality)).green(),
+            style(format!("{:.2}", best.score)).green(),
+            HumanBytes(best.size),
+            style(format!("{percent:.0}%")).green(),
+        ));
+        bar.inc(1);
+    }
+    bar.finish_and_clear();
+
+    if total_input > 0 && bar.length() > Some(1) {
+        eprintln!(
+            "{} images, {} ({:.0}%)",
+            bar.length().unwrap_or_default(),
+            HumanBytes(total_output),
+            100.0 * total_output as f64 / total_input as f64
+        );
+    }
+    Ok(())
+}
+
+/// Binary search the lowest quality meeting the `target`, writing it to `output`.
+#[allow(clippy::too_many_arguments)]
+async fn search(
+    input: &Path,
+    reference: &Path,
+    temp_dir: &Path,
+    output: &Path,
+    encoder: ImageEncoder,
+    preset: Option<u8>,
+    pix_format: Option<PixelFormat>,
+    metric: ImageMetric,
+    target: f32,
+    bar: &ProgressBar,
+) -> anyhow::Result<Encoded> {
+    let (min, max, step) = encoder.quality_range();
+    let steps = ((max - min) / step).round() as u32;
+    let quality = |idx: u32| min + idx as f32 * step;
+
+    let (mut lo, mut hi) = (0, steps);
+    let mut best: Option<(Encoded, PathBuf)> = None;
+    let mut last_score = None;
+    while lo <= hi {
+        let idx = lo + (hi - lo) / 2;
+        let q = quality(idx);
+        bar.set_message(format!("{} {}, ", encoder.quality_name(), TerseF32(q)));
+
+        let encoded = temp_dir.join(format!(
+            "{}.{}{}.{}",
+            file_stem(input),
+            encoder.quality_name(),
+            TerseF32(q),
+            encoder.ext()
+        ));
+        temporary::add(&encoded, TempKind::Keepable);
+        let out = encode_command(
+            input,
+            &encoded,
+            encoder,
+            encoder.args(q, preset, pix_format),
+        )
+        .output()
+        .await
+        .map_err(Error::spawn("ffmpeg encode image"))?;
+        ensure_success("ffmpeg encode image", &out)?;
+
+        let score = score(metric, reference, &encoded).await?;
+        last_score = Some(score);
+        if metric.meets(score, target) {
+            let size = fs::metadata(&encoded)?.len();
+            best = Some((
+                Encoded {
+                    quality: q,
+                    score,
+                    size,
+                },
+                encoded,
+            ));
+            lo = idx + 1;
+        } else if idx == 0 {
+            break;
+        } else {
+            hi = idx - 1;
+        }
+    }
+
+    let (best, encoded) = best.with_context(|| {
+        format!(
+            "no {} met {metric} {target}, best {:.2} at {} {}",
+            encoder.quality_name(),
+            last_score.unwrap_or_default(),
+            encoder.quality_name(),
+            TerseF32(min),
+        )
+    })?;
+    fs::copy(&encoded, output).with_context(|| format!("writing {}", output.display()))?;
+    Ok(best)
+}
+
+/// Returns the images of `inputs`, directories being expanded to their images in name order.
+fn expand_inputs(inputs: &[PathBuf]) -> anyhow::Result<Vec<PathBuf>> {
+    let mut images = vec![];
+    for input in inputs {
+        if !input.is_dir() {
+            images.push(input.clone());
+            continue;
+        }
+        let mut dir_images: Vec<_> = fs::read_dir(input)
+            .with_context(|| format!("reading {}", input.display()))?
+            .filter_map(|entry| Some(entry.ok()?.path()))
+            .filter(|path| is_image(path))
+            .collect();
+        dir_images.sort();
+        images.extend(dir_images);
+    }
+    Ok(images)
+}
+
+fn is_image(path: &Path) -> bool {
+    path.is_file()
+        && path
+            .extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| IMAGE_EXTS.contains(&e.to_ascii_lowercase().as_str()))
+}
+
+/// Returns the output path of `input`, e.g. "photo.png" -> "photo.avif".
+fn output_path(input: &Path, output_dir: Option<&Path>, encoder: ImageEncoder) -> PathBuf {
+    let output = input.with_extension(encoder.ext());
+    match (output_dir, output.file_name()) {
+        (Some(dir), Some(name)) => dir.join(name),
+        _ => output,
+    }
+}
+
+fn file_stem(path: &Path) -> String {
+    path.file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// Returns the ffmpeg command encoding the first frame of `input` to `output`.
+fn encode_command(
+    input: &Path,
+    output: &Path,
+    encoder: ImageEncoder,
+    enc_args: Vec<String>,
+) -> Command {
+    let mut cmd = Command::new(program::ffmpeg());
+    cmd.job_env()
+        .kill_on_drop(true)
+        .arg("-y")
+        .arg2("-i", input)
+        .arg2("-frames:v", 1)
+        .arg2("-c:v", encoder.as_str())
+        .args(enc_args)
+        .arg(output)
+        .stdin(Stdio::null());
+    cmd
+}
+
+/// Decode the first frame of `input` to a 16-bit rgb png, readable by the metric tools.
+async fn decode_png(input: &Path, png: &Path) -> anyhow::Result<()> {
+    let out = Command::new(program::ffmpeg())
+        .job_env()
+        .kill_on_drop(true)
+        .arg("-y")
+        .arg2("-i", input)
+        .arg2("-frames:v", 1)
+        .arg2("-pix_fmt", "rgb48be")
+        .arg(png)
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .map_err(Error::spawn("ffmpeg decode png"))?;
+    ensure_success("ffmpeg decode png", &out)
+}
+
+/// Score the `encoded` image vs the `reference` png.
+async fn score(metric: ImageMetric, reference: &Path, encoded: &Path) -> anyhow::Result<f32> {
+    let mut png = encoded.as_os_str().to_owned();
+    png.push(".png");
+    let png = PathBuf::from(png);
+    decode_png(encoded, &png).await?;
+    temporary::add(&png, TempKind::NotKeepable);
+
+    let out = Command::new(metric.program())
+        .job_env()
+        .kill_on_drop(true)
+        .arg(reference)
+        .arg(&png)
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .map_err(Error::spawn("image metric"))?;
+    ensure_success("image metric", &out)?;
+    parse_score(&String::from_utf8_lossy(&out.stdout))
+        .with_context(|| format!("no {metric} score in output"))
+}
+
+/// Parse the first score line of `ssimulacra2` or `butteraugli_main` stdout.
+fn parse_score(stdout: &str) -> Option<f32> {
+    stdout.lines().find_map(|l| l.trim().parse().ok())
+}
+
+#[test]
+fn parse_image_scores() {
+    assert_eq!(parse_score("86.823150\n"), Some(86.82315));
+    assert_eq!(
+        parse_score("1.2345678\n3-norm: 0.654321\n"),
+        Some(1.2345678)
+    );
+    assert_eq!(parse_score("error\n"), None);
+}
+
+#[test]
+fn image_encoder_args() {
+    assert_eq!(
+        ImageEncoder::Libsvtav1.args(32.0, None, None),
+        [
+            "-crf",
+            "32",
+            "-preset",
+            "4",
+            "-svtav1-params",
+            "avif=1",
+            "-pix_fmt",
+            "yuv420p10le"
+        ]
+    );
+    assert_eq!(
+        ImageEncoder::LibaomAv1.args(20.0, Some(6), None),
+        [
+            "-crf",
+            "20",
+            "-b:v",
+            "0",
+            "-cpu-used",
+            "6",
+            "-still-picture",
+            "1",
+            "-pix_fmt",
+            "yuv444p10le"
+        ]
+    );
+    assert_eq!(
+        ImageEncoder::Libjxl.args(1.25, Some(9), Some(PixelFormat::Yuv420p)),
+        ["-distance", "1.25", "-effort", "9"]
+    );
+    assert_eq!(
+        output_path(
+            Path::new("a/b.png"),
+            Some(Path::new("out")),
+            ImageEncoder::Libjxl
+        ),
+        Path::new("out/b.jxl")
+    );
+}
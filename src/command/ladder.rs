@@ -0,0 +1,286 @@
+use crate::{
+    command::{args, auto_encode},
+    console_ext::style,
+    ffprobe,
+    process::job_env,
+    stdin, temporary,
+};
+use anyhow::{Context, bail, ensure};
+use clap::{Parser, ValueHint};
+use indicatif::{HumanBytes, MultiProgress};
+use std::{
+    fmt::{self, Write},
+    fs,
+    path::PathBuf,
+    time::Duration,
+};
+
+/// Encode an input at several resolutions & bitrates for adaptive bitrate streaming,
+/// each rung using its own crf-search.
+///
+/// Rungs are HEIGHT:MAX_BITRATE, e.g. "720:3M". The max bitrate is used as the rung's
+/// crf-search --max-size. Without a min score, e.g. --min-vmaf, each rung uses
+/// the best quality crf predicted to fit its bitrate.
+///
+/// Lower rungs are upscaled for VMAF analysis, consider `--vmaf-scaler spline36`
+/// for less biased scores.
+///
+/// Outputs to --output-dir:
+/// * Video only mp4 renditions, e.g. "720p.mp4", with aligned keyframes ready for
+///   HLS/DASH packaging. Keyframes use a fixed --keyint, default 10s, without scene
+///   change keyframes, supported by libsvtav1, libx264, libx265 & *_nvenc.
+/// * "ladder.csv" with the crf, score & bitrate of each rung.
+#[derive(Parser)]
+#[clap(verbatim_doc_comment)]
+#[group(skip)]
+pub struct Args {
+    #[clap(flatten)]
+    pub auto_encode: auto_encode::Args,
+
+    /// Ladder rungs as HEIGHT:MAX_BITRATE, e.g. `--rungs 1080:6M,720:3M,480:1200k`.
+    /// The bitrate may be omitted to only use the min score, e.g. `720`.
+    ///
+    /// Rungs taller than the input are skipped.
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_value = "1080:6M,720:3M,480:1200k,360:700k",
+        value_parser = parse_rung
+    )]
+    pub rungs: Vec<Rung>,
+
+    /// Output directory. Defaults to the input name with a ".ladder" extension.
+    #[arg(long, value_hint = ValueHint::DirPath)]
+    pub output_dir: Option<PathBuf>,
+}
+
+/// A rendition resolution & max bitrate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rung {
+    pub height: u32,
+    /// Bits per second.
+    pub bitrate: Option<u64>,
+}
+
+impl fmt::Display for Rung {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}p", self.height)
+    }
+}
+
+/// Parse "HEIGHT[:MAX_BITRATE]", e.g. "720:3M".
+fn parse_rung(s: &str) -> Result<Rung, String> {
+    let (height, bitrate) = match s.split_once(':') {
+        Some((h, b)) => (h, Some(args::parse_size(b)?)),
+        None => (s, None),
+    };
+    let height = height
+        .trim()
+        .trim_end_matches('p')
+        .parse()
+        .map_err(|_| format!("invalid rung height `{height}`"))?;
+    Ok(Rung { height, bitrate })
+}
+
+pub async fn ladder(
+    Args {
+        auto_encode: mut args,
+        rungs,
+        output_dir,
+    }: Args,
+) -> anyhow::Result<()> {
+    ensure!(
+        args.encode.output.is_none(),
+        "--output is not supported by ladder, use --output-dir"
+    );
+    ensure!(
+        args.encode.also_encode.is_none(),
+        "--also-encode is not supported by ladder"
+    );
+    fixed_gop(&mut args.search.args)?;
+    let keep = args.search.sample.keep;
+    let input = args.search.args.input.clone();
+    stdin::ensure_seekable(&input)?;
+    let probe = args.search.args.probe_input().await?;
+    ensure!(!probe.is_image, "ladder does not support images");
+    let duration = probe.duration.clone()?;
+    let input_height = probe.resolution.map(|(_, h)| h);
+
+    let dir = output_dir.unwrap_or_else(|| input.with_extension("ladder"));
+    if !job_env::dry_run() {
+        fs::create_dir_all(&dir).with_context(|| format!("creating {}", dir.display()))?;
+    }
+
+    let progress = MultiProgress::new();
+    let mut csv =
+        String::from("rung,width,height,max_bitrate,crf,metric,score,bitrate,size,file\n");
+    for rung in rungs {
+        if input_height.is_some_and(|h| rung.height > h) {
+            progress.println(
+                style!("Skipping {rung}, taller than input")
+                    .dim()
+                    .to_string(),
+            )?;
+            continue;
+        }
+        progress.println(style!("Encoding {rung}").dim().to_string())?;
+
+        let mut args = args.clone();
+        let search = &mut args.search;
+        let scale = format!("scale=-2:{}", rung.height);
+        search.args.vfilter = Some(match search.args.vfilter.take() {
+            Some(vf) => format!("{vf},{scale}"),
+            None => scale,
+        });
+        if let Some(bitrate) = rung.bitrate {
+            let max_size = (bitrate as f64 * duration.as_secs_f64() / 8.0) as u64;
+            search.max_size = Some(search.max_size.map_or(max_size, |m| m.min(max_size)));
+        }
+        args.encode.output = Some(dir.join(format!("{rung}.mp4")));
+        args.encode.video_only = true;
+        args.encode.mp4_compat = true;
+
+        let encoded = temporary::scope(async {
+            let encoded = auto_encode::run(args, &progress).await;
+            temporary::clean(keep).await;
+            encoded
+        })
+        .await
+        .with_context(|| format!("{rung}"))?;
+        if job_env::dry_run() {
+            continue;
+        }
+
+        let size = fs::metadata(&encoded.output)?.len();
+        let bitrate = (size as f64 * 8.0 / duration.as_secs_f64().max(0.001)) as u64;
+        let width = ffprobe::probe(&encoded.output)
+            .resolution
+            .map(|(w, _)| w.to_string())
+            .unwrap_or_default();
+        let (crf, metric, score) = match &encoded.best {
+            Some(best) => (
+                best.crf().to_string(),
+                best.enc.score_kind.display_str(),
+                format!("{:.2}", best.enc.score),
+            ),
+            None => <_>::default(),
+        };
+        writeln!(
+            csv,
+            "{rung},{width},{},{},{crf},{metric},{score},{bitrate},{size},{}",
+            rung.height,
+            rung.bitrate.map(|b| b.to_string()).unwrap_or_default(),
+            encoded
+                .output
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy(),
+        )?;
+        println!(
+            "{rung} crf {crf} {metric} {score} {} kbps, {} ({})",
+            bitrate / 1000,
+            HumanBytes(size),
+            encoded.output.display(),
+        );
+    }
+
+    if job_env::dry_run() {
+        return Ok(());
+    }
+    let csv_path = dir.join("ladder.csv");
+    fs::write(&csv_path, csv).with_context(|| format!("writing {}", csv_path.display()))?;
+    println!("Wrote {}", csv_path.display());
+    Ok(())
+}
+
+/// Use a fixed keyframe interval without scene change keyframes, which would
+/// misalign renditions. Keyframes are then only placed every --keyint, so
+/// `-keyint_min` isn't needed.
+///
+/// Errors for encoders without a known way to disable scene change keyframes.
+fn fixed_gop(args: &mut args::Encode) -> anyhow::Result<()> {
+    args.keyint
+        .get_or_insert(args::KeyInterval::Duration(Duration::from_secs(10)));
+    match args.encoder.as_str() {
+        "libsvtav1" => {
+            ensure!(
+                args.scd != Some(true),
+                "--scd true is not supported by ladder, renditions need aligned keyframes"
+            );
+            args.scd = Some(false);
+        }
+        "libx264" => args.enc_args.push("-sc_threshold=0".into()),
+        "libx265" => {
+            // x265 open gops also reference frames across keyframes
+            let params = "scenecut=0:open-gop=0";
+            match args
+                .enc_args
+                .iter_mut()
+                .find(|a| a.starts_with("-x265-params="))
+            {
+                Some(arg) => *arg = format!("{arg}:{params}"),
+                None => args.enc_args.push(format!("-x265-params={params}")),
+            }
+        }
+        e if e.ends_with("_nvenc") => args.enc_args.push("-no-scenecut=1".into()),
+        e => bail!(
+            "ladder does not support {e}, use an encoder that can disable scene change \
+             keyframes: libsvtav1, libx264, libx265 or *_nvenc"
+        ),
+    }
+    Ok(())
+}
+
+#[test]
+fn fixed_gop_args() {
+    let encode = |args: &[&str]| {
+        let mut enc = args::Encode::parse_from(["x", "-i", "vid.mkv"].iter().chain(args));
+        fixed_gop(&mut enc).map(|_| enc)
+    };
+
+    let svt = encode(&[]).unwrap();
+    assert_eq!(svt.scd, Some(false));
+    assert_eq!(svt.keyint.unwrap().to_string(), "10s");
+    assert!(encode(&["--scd", "true"]).is_err());
+
+    let x264 = encode(&["-e", "libx264", "--keyint", "48"]).unwrap();
+    assert_eq!(x264.enc_args, ["-sc_threshold=0"]);
+    assert_eq!(x264.keyint.unwrap().to_string(), "48");
+
+    let x265 = encode(&["-e", "libx265", "--enc", "x265-params=aq-mode=3"]).unwrap();
+    assert_eq!(
+        x265.enc_args,
+        ["-x265-params=aq-mode=3:scenecut=0:open-gop=0"]
+    );
+
+    let nvenc = encode(&["-e", "hevc_nvenc"]).unwrap();
+    assert_eq!(nvenc.enc_args, ["-no-scenecut=1"]);
+
+    assert!(encode(&["-e", "libaom-av1"]).is_err());
+}
+
+#[test]
+fn parse_rungs() {
+    assert_eq!(
+        parse_rung("720:3M"),
+        Ok(Rung {
+            height: 720,
+            bitrate: Some(3_000_000)
+        })
+    );
+    assert_eq!(
+        parse_rung("480p:1200k"),
+        Ok(Rung {
+            height: 480,
+            bitrate: Some(1_200_000)
+        })
+    );
+    assert_eq!(
+        parse_rung("1080"),
+        Ok(Rung {
+            height: 1080,
+            bitrate: None
+        })
+    );
+    assert!(parse_rung("tall:3M").is_err());
+}
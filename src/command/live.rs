@@ -0,0 +1,273 @@
+use crate::{
+    command::args::{self, KeyInterval},
+    console_ext::style,
+    ffmpeg, ffprobe,
+    process::FfmpegOut,
+    stdin,
+};
+use anyhow::ensure;
+use clap::Parser;
+use indicatif::{HumanDuration, ProgressBar, ProgressStyle};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio_stream::StreamExt;
+use tracing::info;
+
+/// Live transcode, e.g. from a capture device or stream, using a previously searched crf.
+///
+/// Encode speed is monitored & if it falls below realtime the encode is restarted with
+/// a faster preset. Live inputs continue from the current live position, --realtime file
+/// inputs from the last encoded position.
+///
+/// Keyframes are placed every --segment-duration, which is also the HLS segment duration
+/// for .m3u8 outputs. HLS playlists are continued after restarts, other file outputs
+/// continue in numbered parts, e.g. "live.1.mkv".
+///
+/// Capture devices may be used with --enc-input, e.g.
+/// `--enc-input f=v4l2 -i /dev/video0`.
+#[derive(Parser)]
+#[clap(verbatim_doc_comment)]
+#[group(skip)]
+pub struct Args {
+    #[clap(flatten)]
+    pub args: args::Encode,
+
+    /// Encoder constant rate factor, e.g. from a previous crf-search of similar content.
+    #[arg(long)]
+    pub crf: f32,
+
+    /// Output file or url, e.g. "live.m3u8" or "srt://example.com:9000".
+    #[arg(short, long)]
+    pub output: PathBuf,
+
+    /// Read the input at its native frame rate, e.g. to restream a file as a live source.
+    #[arg(long)]
+    pub realtime: bool,
+
+    /// Keyframe interval & HLS segment duration. Shorter segments reduce latency.
+    #[arg(long, default_value = "2s", value_parser = humantime::parse_duration)]
+    pub segment_duration: Duration,
+
+    /// Minimum encode speed, as a multiple of realtime, before switching to a faster preset.
+    #[arg(long, default_value_t = 1.0)]
+    pub min_speed: f32,
+
+    /// Duration encode speed is measured over.
+    #[arg(long, default_value = "10s", value_parser = humantime::parse_duration)]
+    pub speed_window: Duration,
+}
+
+pub async fn live(
+    Args {
+        mut args,
+        crf,
+        output,
+        realtime,
+        segment_duration,
+        min_speed,
+        speed_window,
+    }: Args,
+) -> anyhow::Result<()> {
+    ensure!(min_speed > 0.0, "--min-speed must be positive");
+    ensure!(
+        !args.is_trimmed(),
+        "--start & --duration are not supported by live"
+    );
+    let probe = args.probe_input().await?;
+    ensure!(!probe.is_image, "live does not support images");
+    let can_restart = !stdin::is_stdin(&args.input);
+    // keyframes at segment boundaries, if the fps is known
+    if args.keyint.is_none() && probe.fps.is_ok() {
+        args.keyint = Some(KeyInterval::Duration(segment_duration));
+    }
+    args.scd.get_or_insert(false);
+    let hls = output.extension().is_some_and(|e| e == "m3u8");
+
+    let bar = ProgressBar::new_spinner().with_style(
+        ProgressStyle::default_spinner()
+            .template("{spinner:.cyan.bold} {elapsed_precise:.bold} {prefix} {msg}")?,
+    );
+    bar.enable_steady_tick(Duration::from_millis(100));
+
+    let mut preset = args.preset.clone();
+    // encoded input position, used to resume --realtime file inputs
+    let mut position = Duration::ZERO;
+    let mut part = 0;
+    loop {
+        let mut args = args.clone();
+        args.preset = preset.clone();
+        let mut enc_args = args.to_encoder_args(crf, &probe)?;
+        preset = enc_args.preset.clone();
+        let faster = faster_preset(&enc_args.vcodec, preset.as_deref()).filter(|_| can_restart);
+        if realtime {
+            enc_args.input_args.push(Arc::new("-re".into()));
+            if !position.is_zero() {
+                enc_args.input_args.push(Arc::new("-ss".into()));
+                enc_args
+                    .input_args
+                    .push(Arc::new(position.as_secs_f64().to_string()));
+            }
+        }
+        if hls {
+            enc_args.output_args.extend(
+                [
+                    "-hls_time".to_owned(),
+                    segment_duration.as_secs_f64().to_string(),
+                    "-hls_flags".into(),
+                    "append_list".into(),
+                ]
+                .map(Arc::new),
+            );
+        }
+        let part_output = match part {
+            0 => output.clone(),
+            _ if hls || ffprobe::is_url(&output) => output.clone(),
+            n => part_name(&output, n),
+        };
+        bar.set_prefix(format!("preset {}", preset.as_deref().unwrap_or("default")));
+        info!("live encoding {}", part_output.display());
+
+        let mut enc = ffmpeg::encode(enc_args, &part_output, probe.has_audio, None, false, None)?;
+        let mut monitor = SpeedMonitor::new(speed_window, Instant::now());
+        let mut slow = false;
+        let mut time = Duration::ZERO;
+        while let Some(progress) = enc.next().await {
+            if let FfmpegOut::Progress { fps, time: t, .. } = progress? {
+                time = t;
+                bar.set_message(format!(
+                    "{} encoded, {fps} fps",
+                    HumanDuration(position + t)
+                ));
+                if let Some(speed) = monitor.update(t, Instant::now()) {
+                    bar.set_message(format!(
+                        "{} encoded, {fps} fps, {speed:.2}x",
+                        HumanDuration(position + t)
+                    ));
+                    if speed < min_speed as f64 {
+                        let warning = match &faster {
+                            Some(faster) => {
+                                format!("encode speed {speed:.2}x, restarting with preset {faster}")
+                            }
+                            None => format!("encode speed {speed:.2}x is below realtime"),
+                        };
+                        bar.println(style!("Warning: {warning}").yellow().to_string());
+                        slow = faster.is_some();
+                    }
+                }
+            }
+            if slow {
+                break;
+            }
+        }
+        if !slow {
+            enc.wait().await?;
+            break;
+        }
+        drop(enc); // kill the slow encode
+        position += time;
+        preset = faster;
+        part += 1;
+    }
+    bar.finish();
+    Ok(())
+}
+
+/// Measures encode speed as a multiple of realtime over a wall time window.
+#[derive(Debug)]
+struct SpeedMonitor {
+    window: Duration,
+    wall_start: Instant,
+    media_start: Duration,
+}
+
+impl SpeedMonitor {
+    fn new(window: Duration, now: Instant) -> Self {
+        Self {
+            window,
+            wall_start: now,
+            media_start: Duration::ZERO,
+        }
+    }
+
+    /// Update with the encoded media `time`, returns the speed each time a window completes.
+    fn update(&mut self, time: Duration, now: Instant) -> Option<f64> {
+        let wall = now.duration_since(self.wall_start);
+        if wall < self.window || wall.is_zero() {
+            return None;
+        }
+        let speed = time.saturating_sub(self.media_start).as_secs_f64() / wall.as_secs_f64();
+        self.wall_start = now;
+        self.media_start = time;
+        Some(speed)
+    }
+}
+
+/// Returns the next faster preset for the `vcodec`, if any.
+fn faster_preset(vcodec: &str, preset: Option<&str>) -> Option<Arc<str>> {
+    const X26X: &[&str] = &[
+        "placebo",
+        "veryslow",
+        "slower",
+        "slow",
+        "medium",
+        "fast",
+        "faster",
+        "veryfast",
+        "superfast",
+        "ultrafast",
+    ];
+    let max: u8 = match vcodec {
+        "libsvtav1" => 13,
+        "libaom-av1" => 8,
+        "librav1e" => 10,
+        "libx264" | "libx265" => {
+            let idx = X26X.iter().position(|p| *p == preset.unwrap_or("medium"))?;
+            return X26X.get(idx + 1).map(|p| (*p).into());
+        }
+        e if e.ends_with("_nvenc") => {
+            // p1 fastest - p7 slowest
+            let p: u8 = preset.unwrap_or("p4").strip_prefix('p')?.parse().ok()?;
+            return (p > 1).then(|| format!("p{}", p - 1).into());
+        }
+        _ => return None,
+    };
+    let p: u8 = preset?.parse().ok()?;
+    (p < max).then(|| (p + 1).to_string().into())
+}
+
+/// E.g. ("live.mkv", 1) -> "live.1.mkv".
+fn part_name(output: &Path, part: u32) -> PathBuf {
+    match output.extension().and_then(|e| e.to_str()) {
+        Some(ext) => output.with_extension(format!("{part}.{ext}")),
+        None => output.with_extension(part.to_string()),
+    }
+}
+
+#[test]
+fn faster_presets() {
+    assert_eq!(faster_preset("libsvtav1", Some("8")), Some("9".into()));
+    assert_eq!(faster_preset("libsvtav1", Some("13")), None);
+    assert_eq!(faster_preset("libx264", None), Some("fast".into()));
+    assert_eq!(faster_preset("libx265", Some("ultrafast")), None);
+    assert_eq!(faster_preset("hevc_nvenc", Some("p5")), Some("p4".into()));
+    assert_eq!(faster_preset("hevc_nvenc", Some("p1")), None);
+    assert_eq!(faster_preset("libvpx-vp9", Some("2")), None);
+}
+
+#[test]
+fn speed_monitor_window() {
+    let start = Instant::now();
+    let mut monitor = SpeedMonitor::new(Duration::from_secs(10), start);
+    assert_eq!(
+        monitor.update(Duration::from_secs(4), start + Duration::from_secs(5)),
+        None
+    );
+    let speed = monitor.update(Duration::from_secs(8), start + Duration::from_secs(10));
+    assert_eq!(speed, Some(0.8));
+    let speed = monitor.update(Duration::from_secs(20), start + Duration::from_secs(20));
+    assert_eq!(speed, Some(1.2));
+    assert_eq!(part_name(Path::new("live.mkv"), 2), Path::new("live.2.mkv"));
+}
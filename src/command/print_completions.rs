@@ -1,7 +1,13 @@
-use clap::{CommandFactory, Parser};
+use crate::process::{CommandJobEnv, program};
+use clap::{CommandFactory, Parser, builder::PossibleValue, builder::PossibleValuesParser};
 use clap_complete::Shell;
+use std::process::Stdio;
 
 /// Print shell completions.
+///
+/// --encoder values are completed with the video encoders of the installed ffmpeg,
+/// listed when the completions are printed. Descriptions are included for shells
+/// that support them, e.g. zsh & fish.
 #[derive(Parser)]
 #[group(skip)]
 pub struct Args {
@@ -11,10 +17,108 @@ pub struct Args {
 }
 
 pub fn print_completions(Args { shell }: Args) {
+    let encoders = ffmpeg_encoders();
     clap_complete::generate(
         shell,
-        &mut crate::Command::command(),
+        &mut with_encoders(crate::cli::Cli::command(), &encoders),
         "ab-av1",
         &mut std::io::stdout(),
     );
 }
+
+/// Returns `cmd` with --encoder args, including those of subcommands, completing `encoders`.
+fn with_encoders(mut cmd: clap::Command, encoders: &[PossibleValue]) -> clap::Command {
+    if encoders.is_empty() {
+        return cmd;
+    }
+    if cmd.get_arguments().any(|a| a.get_id() == "encoder") {
+        cmd = cmd.mut_arg("encoder", |arg| {
+            let mut values = encoders.to_vec();
+            // defaults must be valid values, even if ffmpeg lacks them
+            for default in arg.get_default_values() {
+                let default = default.to_string_lossy();
+                if !values.iter().any(|v| v.matches(&default, false)) {
+                    values.push(PossibleValue::new(default.into_owned()));
+                }
+            }
+            arg.value_parser(PossibleValuesParser::new(values))
+        });
+    }
+    let subcommands: Vec<_> = cmd
+        .get_subcommands()
+        .map(|s| s.get_name().to_owned())
+        .collect();
+    for name in subcommands {
+        cmd = cmd.mut_subcommand(name, |s| with_encoders(s, encoders));
+    }
+    cmd
+}
+
+/// Returns the video encoders of the installed ffmpeg, empty if unavailable.
+fn ffmpeg_encoders() -> Vec<PossibleValue> {
+    let list = std::process::Command::new(program::ffmpeg())
+        .job_env()
+        .args(["-hide_banner", "-encoders"])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .map(|out| String::from_utf8_lossy(&out.stdout).into_owned())
+        .unwrap_or_default();
+    video_encoders(&list)
+        .into_iter()
+        .map(|(name, about)| PossibleValue::new(name.to_owned()).help(about.to_owned()))
+        .collect()
+}
+
+/// Returns the name & description of video encoders in `ffmpeg -encoders` output.
+fn video_encoders(list: &str) -> Vec<(&str, &str)> {
+    list.lines()
+        // skip the flags legend, which precedes the "------" separator
+        .skip_while(|l| l.trim() != "------")
+        .skip(1)
+        .filter_map(|l| {
+            let (flags, rest) = l.trim().split_once(char::is_whitespace)?;
+            if !flags.starts_with('V') {
+                return None;
+            }
+            let rest = rest.trim_start();
+            let (name, about) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+            Some((name, about.trim()))
+        })
+        .collect()
+}
+
+#[test]
+fn parse_video_encoders() {
+    let list = "Encoders:
+ V..... = Video
+ A..... = Audio
+ ------
+ V....D libsvtav1            SVT-AV1(Scalable Video Technology for AV1) encoder (codec av1)
+ V....D av1_nvenc            NVIDIA NVENC av1 encoder (codec av1)
+ A....D aac                  AAC (Advanced Audio Coding)
+ S..... ass                  ASS (Advanced SubStation Alpha) subtitle";
+    assert_eq!(
+        video_encoders(list),
+        [
+            (
+                "libsvtav1",
+                "SVT-AV1(Scalable Video Technology for AV1) encoder (codec av1)"
+            ),
+            ("av1_nvenc", "NVIDIA NVENC av1 encoder (codec av1)"),
+        ]
+    );
+    assert!(video_encoders("").is_empty());
+}
+
+#[test]
+fn completions_with_encoders() {
+    let encoders = [PossibleValue::new("av1_nvenc").help("NVIDIA NVENC av1 encoder")];
+    let mut cmd = with_encoders(crate::cli::Cli::command(), &encoders);
+    let mut out = vec![];
+    clap_complete::generate(Shell::Bash, &mut cmd, "ab-av1", &mut out);
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.contains("av1_nvenc"), "{out}");
+    // default value added
+    assert!(out.contains("libsvtav1"), "{out}");
+}
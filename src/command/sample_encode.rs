@@ -1,36 +1,44 @@
-mod cache;
+pub mod cache;
 
 use crate::{
     command::{
         PROGRESS_CHARS, SmallDuration,
-        args::{self, PixelFormat},
+        args::{self, PixelFormat, SampleSelection, VmafPool},
+        encode::script,
         sample_encode::cache::ScoringInfo,
     },
+    compat,
     console_ext::style,
+    encoder_stats::EncoderStats,
     ffmpeg::{self, FfmpegEncodeArgs},
-    ffprobe::{self, Ffprobe},
-    log::ProgressLogger,
-    process::FfmpegOut,
-    sample, temporary,
-    vmaf::{self, VmafOut},
+    ffprobe::{self, Ffprobe, MediaKind},
+    log::{InSpan, ProgressLogger},
+    metric,
+    process::{FfmpegOut, job_env, program},
+    sample,
+    scheduler::{self, Resources},
+    stdin,
+    temporary::{self, TempKind},
+    vmaf::{self, FrameStats, VmafOut},
     xpsnr::{self, XpsnrOut},
 };
 use anyhow::{Context, ensure};
 use clap::{ArgAction, Parser};
 use console::style;
-use futures_util::Stream;
+use futures_util::{Stream, stream::LocalBoxStream};
 use indicatif::{HumanBytes, HumanDuration, ProgressBar, ProgressStyle};
-use log::info;
 use std::{
     fmt::Display,
     io::{self, IsTerminal},
+    ops::RangeInclusive,
     path::{Path, PathBuf},
     pin::pin,
     sync::Arc,
     time::{Duration, Instant},
 };
 use tokio::fs;
-use tokio_stream::StreamExt;
+use tokio_stream::{StreamExt, wrappers::UnboundedReceiverStream};
+use tracing::{info, info_span, warn};
 
 /// Encode & analyse input samples to predict how a full encode would go.
 /// This is much quicker than a full encode/vmaf run.
@@ -50,9 +58,23 @@ pub struct Args {
     #[arg(long)]
     pub crf: f32,
 
+    /// Sample-encode at each preset of a range, e.g. `--preset-sweep 4..10`, to compare
+    /// encode fps & score at the --crf and recommend a preset.
+    #[arg(
+        long,
+        value_parser = parse_preset_range,
+        conflicts_with = "preset",
+        allow_hyphen_values = true
+    )]
+    pub preset_sweep: Option<RangeInclusive<i32>>,
+
     #[clap(flatten)]
     pub sample: args::Sample,
 
+    /// Calculate per-frame VMAF scores to determine the min frame score.
+    #[arg(skip)]
+    pub frame_scores: bool,
+
     /// Enable sample-encode caching.
     #[arg(
         long,
@@ -76,8 +98,15 @@ pub struct Args {
     pub xpsnr_opts: args::Xpsnr,
 
     /// Calculate a XPSNR score instead of VMAF.
-    #[arg(long)]
+    #[arg(long, conflicts_with = "metric_cmd")]
     pub xpsnr: bool,
+
+    /// Calculate a PSNR score instead of VMAF. Works with ffmpeg builds lacking libvmaf.
+    #[arg(long, conflicts_with_all = ["xpsnr", "metric_cmd"])]
+    pub psnr: bool,
+
+    #[clap(flatten)]
+    pub metric: args::MetricCmd,
 }
 
 pub async fn sample_encode(mut args: Args) -> anyhow::Result<()> {
@@ -91,9 +120,24 @@ pub async fn sample_encode(mut args: Args) -> anyhow::Result<()> {
     );
     bar.enable_steady_tick(Duration::from_millis(100));
 
-    let probe = ffprobe::probe(&args.args.input);
+    stdin::ensure_seekable(&args.args.input)?;
+    let probe = args.args.probe_input().await?;
     args.sample
         .set_extension_from_input(&args.args.input, &args.args.encoder, &probe);
+    if !args.xpsnr && !args.psnr && args.metric.metric_cmd.is_none() {
+        lengthen_subsampled(&mut args.sample, &mut args.vmaf, &probe, &bar);
+    }
+
+    if let Some(presets) = &args.preset_sweep {
+        args.args.preset = Some(presets.start().to_string().into());
+    }
+    if job_env::dry_run() {
+        bar.finish_and_clear();
+        return print_dry_run(&args, &probe);
+    }
+    if let Some(presets) = args.preset_sweep.take() {
+        return preset_sweep(args, probe, presets, bar).await;
+    }
 
     let enc_args = args.args.clone();
     let crf = args.crf;
@@ -140,74 +184,257 @@ pub async fn sample_encode(mut args: Args) -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Sample-encode at each of the `presets`, see --preset-sweep.
+async fn preset_sweep(
+    mut args: Args,
+    probe: Ffprobe,
+    presets: RangeInclusive<i32>,
+    bar: ProgressBar,
+) -> anyhow::Result<()> {
+    let stdout_fmt = args.stdout_format;
+    let bar_len = bar.length().unwrap_or(1) as f32;
+    let count = presets.clone().count() as f32;
+    let probe = Arc::new(probe);
+
+    let mut results = vec![];
+    for (idx, preset) in presets.enumerate() {
+        args.args.preset = Some(preset.to_string().into());
+        let mut run = pin!(run(args.clone(), probe.clone()));
+        while let Some(update) = run.next().await {
+            match update? {
+                Update::Status(Status {
+                    work,
+                    fps,
+                    progress,
+                    sample,
+                    samples,
+                    full_pass,
+                }) => {
+                    match full_pass {
+                        true => bar.set_prefix(format!("Preset {preset} full pass")),
+                        false => bar.set_prefix(format!("Preset {preset} {sample}/{samples}")),
+                    }
+                    let label = work.fps_label();
+                    match work {
+                        Work::Encode if fps <= 0.0 => bar.set_message("encoding,  "),
+                        _ if fps <= 0.0 => bar.set_message(format!("{label},       ")),
+                        _ => bar.set_message(format!("{label} {fps} fps, ")),
+                    }
+                    bar.set_position(((idx as f32 + progress) / count * bar_len).round() as _);
+                }
+                Update::SampleResult { .. } => {}
+                Update::Done(output) => {
+                    bar.println(format!(
+                        "{} {preset} {} {:.2} {} {:.0}% {}",
+                        style("- preset").dim(),
+                        style(output.score_kind).dim(),
+                        output.score,
+                        HumanBytes(output.prediction.size),
+                        output.encode_percent,
+                        style!("taking {}", HumanDuration(output.prediction.time)).dim(),
+                    ));
+                    results.push((preset, output));
+                }
+            }
+        }
+    }
+    bar.finish_and_clear();
+
+    let recommended = recommend_preset(&results);
+    match stdout_fmt {
+        StdoutFormat::Human => {
+            if let Some((_, description)) = recommended {
+                println!("{description}");
+            }
+        }
+        StdoutFormat::Json => {
+            let presets: Vec<_> = results
+                .iter()
+                .map(|(preset, out)| {
+                    let mut json = serde_json::json!({
+                        "preset": preset,
+                        "score_kind": out.score_kind.fps_label(),
+                        "score": out.score,
+                        "predicted_encode_percent": out.encode_percent,
+                    });
+                    out.prediction.add_json(&mut json);
+                    json
+                })
+                .collect();
+            let json = serde_json::json!({
+                "presets": presets,
+                "recommended_preset": recommended.map(|(preset, _)| preset),
+            });
+            println!("{json}");
+        }
+    }
+    Ok(())
+}
+
+/// Max score loss, compared to the slowest preset, of a recommended --preset-sweep preset.
+const PRESET_SWEEP_MAX_SCORE_LOSS: f32 = 0.5;
+
+/// Returns the fastest preset scoring within [`PRESET_SWEEP_MAX_SCORE_LOSS`] of the
+/// slowest preset & a description of the tradeoff.
+fn recommend_preset(results: &[(i32, Output)]) -> Option<(i32, String)> {
+    let (base_preset, base) = results.first()?;
+    let speedup = |out: &Output| {
+        base.prediction.time.as_secs_f64() / out.prediction.time.as_secs_f64().max(0.001)
+    };
+    let (preset, out) = results
+        .iter()
+        .filter(|(_, out)| base.score - out.score <= PRESET_SWEEP_MAX_SCORE_LOSS)
+        .max_by(|(_, a), (_, b)| speedup(a).total_cmp(&speedup(b)))?;
+    if preset == base_preset {
+        return Some((
+            *preset,
+            format!(
+                "preset {preset} recommended, faster presets lose over {PRESET_SWEEP_MAX_SCORE_LOSS} {}",
+                base.score_kind
+            ),
+        ));
+    }
+    Some((
+        *preset,
+        format!(
+            "preset {preset} is {:.1}x faster than {base_preset} with {:+.2} {}",
+            speedup(out),
+            out.score - base.score,
+            out.score_kind
+        ),
+    ))
+}
+
+fn parse_preset_range(s: &str) -> Result<RangeInclusive<i32>, String> {
+    let (start, end) = s
+        .split_once("..")
+        .ok_or_else(|| format!("invalid preset range `{s}`, expected START..END e.g. 4..10"))?;
+    let parse = |preset: &str| {
+        preset
+            .trim()
+            .parse::<i32>()
+            .map_err(|_| format!("invalid preset `{preset}`"))
+    };
+    let (start, end) = (parse(start)?, parse(end)?);
+    if start >= end {
+        return Err(format!(
+            "invalid preset range `{s}`, START must be below END"
+        ));
+    }
+    Ok(start..=end)
+}
+
+/// Set VMAF n_subsample for long inputs, see [`args::Sample::auto_n_subsample`],
+/// and lengthen samples if n_subsample would score too few frames, printing a warning.
+/// See [`args::Sample::lengthen_for_subsample`].
+pub fn lengthen_subsampled(
+    sample: &mut args::Sample,
+    vmaf: &mut args::Vmaf,
+    probe: &Ffprobe,
+    bar: &ProgressBar,
+) {
+    if let Some(n) = sample.auto_n_subsample(vmaf, probe) {
+        bar.println(
+            style!(
+                "VMAF scoring 1 in {n} sample frames for this long input, \
+                 scores may differ by ~0.1 from scoring every frame. \
+                 Use --vmaf n_subsample=1 to score all frames"
+            )
+            .dim()
+            .to_string(),
+        );
+        *vmaf = vmaf.clone().with_n_subsample(n);
+    }
+    if let Some(original) = sample.lengthen_for_subsample(vmaf, probe) {
+        bar.println(
+            style!(
+                "Warning: --vmaf n_subsample={} scores under {} frames, lengthening samples {:.0}s -> {:.0}s. \
+                 Lower n_subsample for shorter samples",
+                vmaf.n_subsample(),
+                args::MIN_SCORED_FRAMES,
+                original.as_secs_f64(),
+                sample.sample_duration.as_secs_f64().ceil(),
+            )
+            .yellow()
+            .to_string(),
+        );
+    }
+}
+
 pub fn run(
     Args {
         args,
         crf,
+        preset_sweep: _,
         sample: sample_args,
+        frame_scores,
         cache,
         stdout_format: _,
-        vmaf,
+        mut vmaf,
         score,
         xpsnr,
+        psnr,
         xpsnr_opts,
+        metric,
     }: Args,
     input_probe: Arc<Ffprobe>,
 ) -> impl Stream<Item = anyhow::Result<Update>> {
+    let span = info_span!("sample_encode", crf);
+    // samples are scored with the first --vmaf-model only
+    vmaf.vmaf_model.truncate(1);
     async_stream::try_stream! {
+        stdin::ensure_seekable(&args.input)?;
         let input = Arc::new(args.input.clone());
         let input_pix_fmt = input_probe.pixel_format();
-        let input_is_image = input_probe.is_image;
-        let input_len = fs::metadata(&*input).await?.len();
+        let input_kind = input_probe.media_kind();
+        let input_len = input_probe.input_len(&input)?;
         let enc_args = args.to_encoder_args(crf, &input_probe)?;
+        let ref_vfilter = args.reference_vfilter(&input_probe)?;
         let duration = input_probe.duration.clone()?;
         let input_fps = input_probe.fps.clone()?;
         let samples = sample_args.sample_count(duration).max(1);
         let keep = sample_args.keep;
-        let temp_dir = sample_args.temp_dir;
-        let scoring = match xpsnr {
-            true => ScoringInfo::Xpsnr(&xpsnr_opts, &score),
+        let temp_dir = sample_args.temp_dir.clone();
+        let from_keyframes = sample_args.samples_from_keyframes;
+        // samples are copied from the trimmed portion of the input, see --start
+        let trim_start = args.start();
+        let video_stream = args.video_stream();
+        // full passes copy a sample of the trimmed portion or selected video stream
+        let full_pass_copy = args.is_trimmed() || video_stream > 0;
+        let vmaf_pool = vmaf.vmaf_pool;
+        let also_scores = vmaf.also_psnr || vmaf.also_ssim;
+        // samples are stream copies, so decode like the input
+        let ref_nvdec = vmaf.reference_nvdec(&input_probe);
+        let scoring = match (xpsnr, psnr, &metric.metric_cmd) {
+            (_, _, Some(_)) => ScoringInfo::Metric(&metric, vmaf_pool, &score),
+            (true, _, _) => ScoringInfo::Xpsnr(&xpsnr_opts, &score),
+            (_, true, _) => ScoringInfo::Psnr(&xpsnr_opts, &score),
             _ => ScoringInfo::Vmaf(&vmaf, &score),
         };
 
-        let (samples, sample_duration, full_pass) = {
-            if input_is_image {
-                (1, duration.max(Duration::from_secs(1)), true)
-            } else if sample_args.sample_duration.is_zero()
-                || sample_args.sample_duration * samples as _ >= duration.mul_f64(0.85)
-            {
-                // if the sample time is most of the full input time just encode the whole thing
-                (1, duration, true)
-            } else {
-                let sample_duration = if input_fps > 0.0 {
-                    // if sample-length is lower than a single frame use the frame time
-                    let one_frame_duration = Duration::from_secs_f64(1.0 / input_fps);
-                    sample_args.sample_duration.max(one_frame_duration)
-                } else {
-                    sample_args.sample_duration
-                };
-                (samples, sample_duration, false)
-            }
-        };
+        let (samples, sample_duration, full_pass) =
+            sample_plan(&sample_args, samples, duration, input_fps, input_kind);
         let sample_duration_us = sample_duration.as_micros_u64();
+        let starts = sample_starts(&sample_args, &input, video_stream, trim_start, samples, sample_duration, duration, full_pass).await;
 
         // Start creating copy samples async, this is IO bound & not cpu intensive
-        let (tx, mut sample_tasks) = tokio::sync::mpsc::unbounded_channel();
+        let (tx, sample_tasks) = tokio::sync::mpsc::unbounded_channel();
         let sample_temp = temp_dir.clone();
         let sample_in = input.clone();
-        tokio::task::spawn_local(async move {
-            if full_pass {
+        tokio::task::spawn_local(job_env::inherit_scope(temporary::inherit_scope(async move {
+            if full_pass && !full_pass_copy {
                 // Use the entire video as a single sample
                 let _ = tx.send((0, Ok((sample_in.clone(), input_len))));
             } else {
-                for sample_idx in 0..samples {
+                for (sample_idx, start) in (0..).zip(starts) {
                     let sample = sample(
                         sample_in.clone(),
-                        sample_idx,
-                        samples,
+                        video_stream,
+                        trim_start + start,
                         sample_duration,
-                        duration,
+                        trim_start + duration,
                         input_fps,
+                        from_keyframes,
                         sample_temp.clone(),
                     )
                     .await;
@@ -216,266 +443,483 @@ pub fn run(
                     }
                 }
             }
-        });
-
-        let mut results = Vec::new();
-        loop {
-            let (sample_idx, sample) = match sample_tasks.recv().await {
-                Some(s) => s,
-                None => break,
-            };
-            let sample_n = sample_idx + 1;
-            let (sample, sample_size) = sample?;
-
-            info!("encoding sample {sample_n}/{samples} crf {crf}");
-            yield Update::Status(Status {
-                work: Work::Encode,
-                fps: 0.0,
-                progress: sample_idx as f32 / samples as f32,
-                full_pass,
-                sample: sample_n,
-                samples,
-            });
+        })));
+
+        // samples encoded & scored concurrently, see --parallel-samples
+        let sample_jobs = sample_args.sample_jobs(samples);
+        let sample_runs = UnboundedReceiverStream::new(sample_tasks).map(|(sample_idx, sample)| {
+            let (input, enc_args, sample_args, temp_dir) = (&input, &enc_args, &sample_args, &temp_dir);
+            let (vmaf, score, ref_vfilter) = (&vmaf, &score, &ref_vfilter);
+            let sample_run: LocalBoxStream<'_, anyhow::Result<SampleUpdate>> = Box::pin(async_stream::try_stream! {
+                let sample_n = sample_idx + 1;
+                let (sample, sample_size) = sample?;
+
+                info!("encoding sample {sample_n}/{samples} crf {crf}");
+                yield SampleUpdate::Status(sample_idx, Status {
+                    work: Work::Encode,
+                    fps: 0.0,
+                    progress: 0.0,
+                    full_pass,
+                    sample: sample_n,
+                    samples,
+                });
 
-            // encode sample
-            let result = match cache::cached_encode(
-                cache,
-                &sample,
-                duration,
-                input.extension(),
-                input_len,
-                full_pass,
-                &enc_args,
-                scoring,
-            )
-            .await
-            {
-                (Some(result), _) => {
-                    if samples > 1 {
-                        result.log_attempt(sample_n, samples, crf);
+                // encode sample
+                let result = match cache::cached_encode(
+                    cache,
+                    &sample,
+                    duration,
+                    input.extension(),
+                    input_len,
+                    full_pass,
+                    enc_args,
+                    scoring,
+                )
+                .await
+                {
+                    (Some(result), _) if !frame_scores || result.min_frame_score.is_some() => {
+                        if samples > 1 {
+                            result.log_attempt(sample_n, samples, crf);
+                        }
+                        result
                     }
-                    result
-                }
-                (None, key) => {
-                    let b = Instant::now();
-                    let mut logger = ProgressLogger::new(module_path!(), b);
-                    let (encoded_sample, mut output) = ffmpeg::encode_sample(
-                        FfmpegEncodeArgs {
+                    (_, key) => {
+                        let b = Instant::now();
+                        let mut logger = ProgressLogger::new("encode", b);
+                        let permit = scheduler::acquire(enc_args.resources()).await;
+                        let mut sample_enc_args = FfmpegEncodeArgs {
                             input: &sample,
                             ..enc_args.clone()
-                        },
-                        temp_dir.clone(),
-                        sample_args.extension.as_deref().unwrap_or("mkv"),
-                    )?;
-                    while let Some(enc_progress) = output.next().await {
-                        if let FfmpegOut::Progress { time, fps, .. } = enc_progress? {
-                            yield Update::Status(Status {
-                                work: Work::Encode,
-                                fps,
-                                progress: (time.as_micros_u64() + sample_idx * sample_duration_us * 2) as f32
-                                    / (sample_duration_us * samples * 2) as f32,
-                                full_pass,
-                                sample: sample_n,
-                                samples,
-                            });
-                            logger.update(sample_duration, time, fps);
-                        }
-                    }
-                    output.wait().await?; // ensure process has exited
-
-                    let encode_time = b.elapsed();
-                    let encoded_size = fs::metadata(&encoded_sample).await?.len();
-                    let encoded_probe = ffprobe::probe(&encoded_sample);
-
-                    let result = match scoring {
-                        ScoringInfo::Vmaf(..) => {
-                            yield Update::Status(Status {
-                                work: Work::Score(ScoreKind::Vmaf),
-                                fps: 0.0,
-                                progress: (sample_idx as f32 + 0.5) / samples as f32,
-                                full_pass,
-                                sample: sample_n,
-                                samples,
-                            });
-                            let vmaf = vmaf::run(
-                                &sample,
-                                &encoded_sample,
-                                &vmaf.ffmpeg_lavfi(
-                                    encoded_probe.resolution,
-                                    PixelFormat::opt_max(enc_args.pix_fmt, input_pix_fmt),
-                                    score.reference_vfilter.as_deref().or(args.vfilter.as_deref()),
-                                ),
-                                vmaf.fps(),
+                        };
+                        let mut retries = 0;
+                        let mut encoder_stats = None;
+                        let encoded_sample = loop {
+                            let (encoded_sample, mut output) = ffmpeg::encode_sample(
+                                sample_enc_args.clone(),
+                                temp_dir.clone(),
+                                sample_args.extension.as_deref().unwrap_or("mkv"),
                             )?;
-                            let mut vmaf = pin!(vmaf);
-                            let mut logger = ProgressLogger::new("ab_av1::vmaf", Instant::now());
-                            let mut vmaf_score = None;
-                            while let Some(vmaf) = vmaf.next().await {
-                                match vmaf {
-                                    VmafOut::Done(score) => {
-                                        vmaf_score = Some(score);
-                                        break;
-                                    }
-                                    VmafOut::Progress(FfmpegOut::Progress { time, fps, .. }) => {
-                                        yield Update::Status(Status {
-                                            work: Work::Score(ScoreKind::Vmaf),
+                            let mut enc_result = Ok(());
+                            while let Some(enc_progress) = output.next().await {
+                                match enc_progress {
+                                    Ok(FfmpegOut::Progress { time, fps, .. }) => {
+                                        yield SampleUpdate::Status(sample_idx, Status {
+                                            work: Work::Encode,
                                             fps,
-                                            progress: (sample_duration_us +
-                                                time.as_micros_u64() +
-                                                sample_idx * sample_duration_us * 2) as f32
-                                                / (sample_duration_us * samples * 2) as f32,
+                                            progress: time.as_micros_u64() as f32
+                                                / (sample_duration_us * 2) as f32,
                                             full_pass,
                                             sample: sample_n,
                                             samples,
                                         });
                                         logger.update(sample_duration, time, fps);
                                     }
-                                    VmafOut::Progress(_) => {}
-                                    VmafOut::Err(e) => Err(e)?,
+                                    Ok(FfmpegOut::EncoderStats(stats)) => encoder_stats = Some(stats),
+                                    Ok(_) => {}
+                                    Err(err) => enc_result = Err(err),
                                 }
                             }
-
-                            EncodeResult {
-                                score: vmaf_score.context("no vmaf score")?,
-                                score_kind: ScoreKind::Vmaf,
-                                sample_size,
-                                encoded_size,
-                                encode_time,
-                                sample_duration: encoded_probe
-                                    .duration
-                                    .ok()
-                                    .filter(|d| !d.is_zero())
-                                    .unwrap_or(sample_duration),
-                                from_cache: false,
+                            output.wait().await?; // ensure process has exited
+                            match enc_result {
+                                Err(err) if retries < ffmpeg::NVDEC_OOM_RETRIES
+                                    && ffmpeg::is_nvdec_oom(&err)
+                                    && sample_enc_args.reduce_hw_frames() =>
+                                {
+                                    retries += 1;
+                                    warn!("NVDEC out of memory, retry {retries} with fewer hw frames");
+                                    tokio::time::sleep(ffmpeg::NVDEC_OOM_RETRY_DELAY * retries).await;
+                                }
+                                result => {
+                                    result?;
+                                    break encoded_sample;
+                                }
                             }
-                        }
-                        ScoringInfo::Xpsnr(..) => {
-                            yield Update::Status(Status {
-                                work: Work::Score(ScoreKind::Xpsnr),
-                                fps: 0.0,
-                                progress: (sample_idx as f32 + 0.5) / samples as f32,
-                                full_pass,
-                                sample: sample_n,
-                                samples,
-                            });
-
-                            let lavfi = super::xpsnr::lavfi(
-                                score.reference_vfilter.as_deref().or(args.vfilter.as_deref())
-                            );
-                            let xpsnr_out = xpsnr::run(&sample, &encoded_sample, &lavfi, xpsnr_opts.fps())?;
-                            let mut xpsnr_out = pin!(xpsnr_out);
-                            let mut logger = ProgressLogger::new("ab_av1::xpsnr", Instant::now());
-                            let mut score = None;
-                            while let Some(next) = xpsnr_out.next().await {
-                                match next {
-                                    XpsnrOut::Done(s) => {
-                                        score = Some(s);
-                                        break;
+                        };
+                        drop(permit);
+
+                        let encode_time = b.elapsed();
+                        let encoded_size = fs::metadata(&encoded_sample).await?.len();
+                        let encoded_probe = ffprobe::probe(&encoded_sample);
+
+                        let result = match scoring {
+                            ScoringInfo::Vmaf(..) => {
+                                yield SampleUpdate::Status(sample_idx, Status {
+                                    work: Work::Score(ScoreKind::Vmaf),
+                                    fps: 0.0,
+                                    progress: 0.5,
+                                    full_pass,
+                                    sample: sample_n,
+                                    samples,
+                                });
+                                let vmaf_ref_vfilter =
+                                    score.reference_vfilter_or(ref_vfilter.as_deref());
+                                let cuda_ffmpeg = vmaf::cuda::ffmpeg();
+                                let ref_nvdec = ref_nvdec.filter(|_| cuda_ffmpeg.is_none());
+                                let _permit = scheduler::acquire(match cuda_ffmpeg {
+                                    Some(_) => Resources::vmaf_cuda(),
+                                    None => Resources {
+                                        nvdec: ref_nvdec.is_some(),
+                                        ..Resources::vmaf_cpu()
+                                    },
+                                }).await;
+                                let mut lavfi = match cuda_ffmpeg {
+                                    Some(_) => {
+                                        vmaf.ffmpeg_lavfi_cuda(encoded_probe.resolution, vmaf_ref_vfilter)
                                     }
-                                    XpsnrOut::Progress(FfmpegOut::Progress { time, fps, .. }) => {
-                                        yield Update::Status(Status {
-                                            work: Work::Score(ScoreKind::Xpsnr),
-                                            fps,
-                                            progress: (sample_duration_us +
-                                                time.as_micros_u64() +
-                                                sample_idx * sample_duration_us * 2) as f32
-                                                / (sample_duration_us * samples * 2) as f32,
-                                            full_pass,
-                                            sample: sample_n,
-                                            samples,
-                                        });
-                                        logger.update(sample_duration, time, fps);
+                                    None => vmaf.ffmpeg_lavfi(
+                                        encoded_probe.resolution,
+                                        PixelFormat::opt_max(enc_args.pix_fmt, input_pix_fmt),
+                                        vmaf_ref_vfilter,
+                                        ref_nvdec,
+                                    ),
+                                };
+                                let frame_log = (frame_scores || vmaf_pool != VmafPool::Mean).then(|| {
+                                    let mut log = encoded_sample.clone().into_os_string();
+                                    log.push(".vmaf.json");
+                                    let log = PathBuf::from(log);
+                                    temporary::add(&log, TempKind::NotKeepable);
+                                    lavfi.push_str(&vmaf::frame_log_opts(&log));
+                                    log
+                                });
+                                let vmaf = vmaf::run(
+                                    cuda_ffmpeg.unwrap_or(program::ffmpeg()),
+                                    cuda_ffmpeg.is_some() && vmaf.cuda_nvdec(vmaf_ref_vfilter),
+                                    ref_nvdec.is_some(),
+                                    &sample,
+                                    &encoded_sample,
+                                    &lavfi,
+                                    vmaf.fps(),
+                                )?;
+                                let mut vmaf = pin!(vmaf);
+                                let mut logger = ProgressLogger::new("vmaf", Instant::now());
+                                let mut vmaf_score = None;
+                                let (mut psnr, mut ssim) = (None, None);
+                                while let Some(vmaf) = vmaf.next().await {
+                                    match vmaf {
+                                        VmafOut::Done(score) => {
+                                            vmaf_score = Some(score);
+                                            // the frame log, psnr & ssim are written after the score
+                                            if frame_log.is_none() && !also_scores {
+                                                break;
+                                            }
+                                        }
+                                        VmafOut::Psnr(score) => psnr = Some(score),
+                                        VmafOut::Ssim(score) => ssim = Some(score),
+                                        VmafOut::ModelScores(_) => {}
+                                        VmafOut::Progress(FfmpegOut::Progress { time, fps, .. }) => {
+                                            yield SampleUpdate::Status(sample_idx, Status {
+                                                work: Work::Score(ScoreKind::Vmaf),
+                                                fps,
+                                                progress: (sample_duration_us + time.as_micros_u64()) as f32
+                                                    / (sample_duration_us * 2) as f32,
+                                                full_pass,
+                                                sample: sample_n,
+                                                samples,
+                                            });
+                                            logger.update(sample_duration, time, fps);
+                                        }
+                                        VmafOut::Progress(_) => {}
+                                        VmafOut::Err(e) => Err(e)?,
                                     }
-                                    XpsnrOut::Progress(_) => {}
-                                    XpsnrOut::Err(e) => Err(e)?,
+                                }
+
+                                let frame_stats = match &frame_log {
+                                    Some(log) => {
+                                        let stats = vmaf::frame_stats(log)?;
+                                        let _ = fs::remove_file(log).await;
+                                        Some(stats)
+                                    }
+                                    None => None,
+                                };
+
+                                EncodeResult {
+                                    score: match frame_stats {
+                                        Some(stats) if vmaf_pool != VmafPool::Mean => stats.pooled(vmaf_pool),
+                                        _ => vmaf_score.context("no vmaf score")?,
+                                    },
+                                    score_kind: ScoreKind::Vmaf,
+                                    min_frame_score: frame_stats.map(|s| s.min),
+                                    frame_stats,
+                                    psnr,
+                                    ssim,
+                                    sample_size,
+                                    encoded_size,
+                                    encode_time,
+                                    sample_duration: encoded_probe
+                                        .duration
+                                        .ok()
+                                        .filter(|d| !d.is_zero())
+                                        .unwrap_or(sample_duration),
+                                    encoder_stats,
+                                    from_cache: false,
                                 }
                             }
+                            ScoringInfo::Xpsnr(..) | ScoringInfo::Psnr(..) => {
+                                let score_kind = match scoring {
+                                    ScoringInfo::Psnr(..) => ScoreKind::Psnr,
+                                    _ => ScoreKind::Xpsnr,
+                                };
+                                yield SampleUpdate::Status(sample_idx, Status {
+                                    work: Work::Score(score_kind),
+                                    fps: 0.0,
+                                    progress: 0.5,
+                                    full_pass,
+                                    sample: sample_n,
+                                    samples,
+                                });
+
+                                let xpsnr_ref_vfilter =
+                                    score.reference_vfilter_or(ref_vfilter.as_deref());
+                                let lavfi = match score_kind {
+                                    ScoreKind::Psnr => super::xpsnr::psnr_lavfi(xpsnr_ref_vfilter),
+                                    _ => super::xpsnr::lavfi(xpsnr_ref_vfilter),
+                                };
+                                let _permit = scheduler::acquire(Resources::vmaf_cpu()).await;
+                                let xpsnr_out = xpsnr::run(
+                                    score_kind,
+                                    &sample,
+                                    &encoded_sample,
+                                    &lavfi,
+                                    xpsnr_opts.fps(),
+                                )?;
+                                let mut xpsnr_out = pin!(xpsnr_out);
+                                let mut logger = ProgressLogger::new(score_kind.fps_label(), Instant::now());
+                                let mut score = None;
+                                while let Some(next) = xpsnr_out.next().await {
+                                    match next {
+                                        XpsnrOut::Done(s) => {
+                                            score = Some(s);
+                                            break;
+                                        }
+                                        XpsnrOut::Progress(FfmpegOut::Progress { time, fps, .. }) => {
+                                            yield SampleUpdate::Status(sample_idx, Status {
+                                                work: Work::Score(score_kind),
+                                                fps,
+                                                progress: (sample_duration_us + time.as_micros_u64()) as f32
+                                                    / (sample_duration_us * 2) as f32,
+                                                full_pass,
+                                                sample: sample_n,
+                                                samples,
+                                            });
+                                            logger.update(sample_duration, time, fps);
+                                        }
+                                        XpsnrOut::Progress(_) => {}
+                                        XpsnrOut::Err(e) => Err(e)?,
+                                    }
+                                }
 
-                            EncodeResult {
-                                score: score.context("no xpsnr score")?,
-                                score_kind: ScoreKind::Xpsnr,
-                                sample_size,
-                                encoded_size,
-                                encode_time,
-                                sample_duration: encoded_probe
-                                    .duration
-                                    .ok()
-                                    .filter(|d| !d.is_zero())
-                                    .unwrap_or(sample_duration),
-                                from_cache: false,
+                                EncodeResult {
+                                    score: score
+                                        .with_context(|| format!("no {} score", score_kind.fps_label()))?,
+                                    score_kind,
+                                    min_frame_score: None,
+                                    frame_stats: None,
+                                    psnr: None,
+                                    ssim: None,
+                                    sample_size,
+                                    encoded_size,
+                                    encode_time,
+                                    sample_duration: encoded_probe
+                                        .duration
+                                        .ok()
+                                        .filter(|d| !d.is_zero())
+                                        .unwrap_or(sample_duration),
+                                    encoder_stats,
+                                    from_cache: false,
+                                }
+                            }
+                            ScoringInfo::Metric(metric, ..) => {
+                                yield SampleUpdate::Status(sample_idx, Status {
+                                    work: Work::Score(ScoreKind::Metric),
+                                    fps: 0.0,
+                                    progress: 0.5,
+                                    full_pass,
+                                    sample: sample_n,
+                                    samples,
+                                });
+
+                                let _permit = scheduler::acquire(Resources::vmaf_cpu()).await;
+                                let score = metric::run(
+                                    metric.metric_cmd.as_deref().context("no --metric-cmd")?,
+                                    &sample,
+                                    &encoded_sample,
+                                    score.reference_vfilter_or(ref_vfilter.as_deref()),
+                                    vmaf_pool,
+                                )
+                                .await?;
+
+                                EncodeResult {
+                                    score,
+                                    score_kind: ScoreKind::Metric,
+                                    min_frame_score: None,
+                                    frame_stats: None,
+                                    psnr: None,
+                                    ssim: None,
+                                    sample_size,
+                                    encoded_size,
+                                    encode_time,
+                                    sample_duration: encoded_probe
+                                        .duration
+                                        .ok()
+                                        .filter(|d| !d.is_zero())
+                                        .unwrap_or(sample_duration),
+                                    encoder_stats,
+                                    from_cache: false,
+                                }
                             }
+                        };
+
+                        if samples > 1 {
+                            result.log_attempt(sample_n, samples, crf);
                         }
-                    };
 
-                    if samples > 1 {
-                        result.log_attempt(sample_n, samples, crf);
-                    }
+                        if let Some(k) = key {
+                            cache::cache_result(k, &result).await?;
+                        }
 
-                    if let Some(k) = key {
-                        cache::cache_result(k, &result).await?;
-                    }
+                        // Early clean, unless other samples are running. Note: Avoid cleaning copy samples
+                        if sample_jobs == 1 {
+                            temporary::clean(true).await;
+                        }
+                        if !keep {
+                            let _ = tokio::fs::remove_file(encoded_sample).await;
+                        }
 
-                    // Early clean. Note: Avoid cleaning copy samples
-                    temporary::clean(true).await;
-                    if !keep {
-                        let _ = tokio::fs::remove_file(encoded_sample).await;
+                        result
                     }
+                };
 
-                    result
-                }
-            };
 
-            results.push(result.clone());
-            yield Update::SampleResult { sample: sample_n, result };
+                yield SampleUpdate::Done(sample_idx, result);
+            });
+            sample_run
+        });
+        let mut sample_runs = pin!(futures_util::StreamExt::flatten_unordered(sample_runs, sample_jobs));
+
+        let mut results = Vec::new();
+        let mut progress = vec![0.0; samples as usize];
+        while let Some(update) = sample_runs.next().await {
+            match update? {
+                SampleUpdate::Status(sample_idx, mut status) => {
+                    progress[sample_idx as usize] = status.progress;
+                    status.progress = progress.iter().sum::<f32>() / samples as f32;
+                    yield Update::Status(status);
+                }
+                SampleUpdate::Done(sample_idx, result) => {
+                    progress[sample_idx as usize] = 1.0;
+                    results.push(result.clone());
+                    yield Update::SampleResult { sample: sample_idx + 1, result };
+                }
+            }
+        }
+        if sample_jobs > 1 {
+            temporary::clean(true).await;
         }
 
         let score_kind = results.score_kind();
         let output = Output {
-            score: results.mean_score(),
+            score: match (score_kind, vmaf_pool) {
+                // worst case pooling should be the worst of all samples
+                (ScoreKind::Vmaf | ScoreKind::Metric, VmafPool::Min) => results.min_score(),
+                _ => results.mean_score(),
+            },
+            score_error: match (score_kind, vmaf_pool) {
+                (ScoreKind::Vmaf | ScoreKind::Metric, VmafPool::Min) => 0.0,
+                _ => results.score_error(),
+            },
             score_kind,
-            // Using file size * encode_percent can over-estimate. However, if it ends up less
-            // than the duration estimation it may turn out to be more accurate.
-            predicted_encode_size: results
-                .estimate_encode_size_by_duration(duration, full_pass)
-                .min(estimate_encode_size_by_file_percent(&results, &input, full_pass).await?),
+            min_frame_score: results.min_frame_score(),
+            frame_stats: results.frame_stats(),
+            psnr: results.mean_of(|r| r.psnr),
+            ssim: results.mean_of(|r| r.ssim),
             encode_percent: results.encoded_percent_size(),
-            predicted_encode_time: results.estimate_encode_time(duration, full_pass),
+            prediction: Prediction::from_results(&results, duration, input_fps, input_len, full_pass),
             from_cache: results.iter().all(|r| r.from_cache),
         };
         info!(
             "crf {crf} {score_kind} {:.2} predicted video stream size {} ({:.0}%) taking {}{}",
             output.score,
-            HumanBytes(output.predicted_encode_size),
+            HumanBytes(output.prediction.size),
             output.encode_percent,
-            HumanDuration(output.predicted_encode_time),
+            HumanDuration(output.prediction.time),
             if output.from_cache { " (cache)" } else { "" }
         );
 
         yield Update::Done(output);
     }
+    .in_span(span)
+}
+
+/// Returns the start of each sample, relative to any --start, see --sample-selection.
+#[allow(clippy::too_many_arguments)]
+async fn sample_starts(
+    sample_args: &args::Sample,
+    input: &Path,
+    video_stream: usize,
+    trim_start: Duration,
+    samples: u64,
+    sample_duration: Duration,
+    duration: Duration,
+    full_pass: bool,
+) -> Vec<Duration> {
+    let complexity = match sample_args.sample_selection {
+        _ if full_pass => None,
+        SampleSelection::Even => None,
+        SampleSelection::Complexity => {
+            sample::complexity_starts(
+                input,
+                video_stream,
+                trim_start,
+                samples,
+                sample_duration,
+                duration,
+            )
+            .await
+        }
+    };
+    complexity.unwrap_or_else(|| {
+        (0..samples)
+            .map(|idx| sample_start(idx, samples, sample_duration, duration))
+            .collect()
+    })
 }
 
-/// Copy a sample from the input to the temp_dir (or input dir).
+/// Copy a sample starting at `sample_start` from the input to the temp_dir (or input dir).
+#[allow(clippy::too_many_arguments)]
 async fn sample(
     input: Arc<PathBuf>,
-    sample_idx: u64,
-    samples: u64,
+    video_stream: usize,
+    mut sample_start: Duration,
     sample_duration: Duration,
     duration: Duration,
     fps: f64,
+    from_keyframes: bool,
     temp_dir: Option<PathBuf>,
 ) -> anyhow::Result<(Arc<PathBuf>, u64)> {
-    let sample_n = sample_idx + 1;
-
-    let sample_start = (duration.saturating_sub(sample_duration * samples as _)
-        / (samples as u32 + 1))
-        * sample_n as _
-        + sample_duration * sample_idx as _;
-
-    let sample_frames = ((sample_duration.as_secs_f64() * fps).round() as u32).max(1);
-    let floor_to_sec = sample_duration >= Duration::from_secs(2);
+    let (sample_frames, mut floor_to_sec) = sample_frames(sample_duration, fps);
+    if from_keyframes
+        && let Some(keyframe) = sample::next_keyframe(
+            &input,
+            video_stream,
+            sample_start,
+            sample_duration,
+            duration,
+        )
+        .await
+    {
+        // keyframe times must not be floored
+        (sample_start, floor_to_sec) = (keyframe, false);
+    }
 
-    let sample = sample::copy(&input, sample_start, floor_to_sec, sample_frames, temp_dir).await?;
+    let sample = sample::copy(
+        &input,
+        video_stream,
+        sample_start,
+        floor_to_sec,
+        sample_frames,
+        temp_dir,
+    )
+    .await?;
     let sample_size = fs::metadata(&sample).await?.len();
     ensure!(
         // ffmpeg copy may fail successfully and give us a small/empty output
@@ -485,17 +929,227 @@ async fn sample(
     Ok((sample.into(), sample_size))
 }
 
+/// Returns the number of samples, sample duration & whether the whole input is
+/// encoded as a single sample.
+fn sample_plan(
+    sample_args: &args::Sample,
+    samples: u64,
+    duration: Duration,
+    input_fps: f64,
+    kind: MediaKind,
+) -> (u64, Duration, bool) {
+    if kind == MediaKind::Image {
+        (1, duration.max(Duration::from_secs(1)), true)
+    } else if kind == MediaKind::Animation {
+        // animations are short & their codecs, e.g. gif, can't be copied into mkv samples
+        (1, duration, true)
+    } else if sample_args.sample_duration.is_zero()
+        || sample_args.sample_duration * samples as _ >= duration.mul_f64(0.85)
+    {
+        // if the sample time is most of the full input time just encode the whole thing
+        (1, duration, true)
+    } else {
+        let sample_duration = if input_fps > 0.0 {
+            // if sample-length is lower than a single frame use the frame time
+            let one_frame_duration = Duration::from_secs_f64(1.0 / input_fps);
+            sample_args.sample_duration.max(one_frame_duration)
+        } else {
+            sample_args.sample_duration
+        };
+        (samples, sample_duration, false)
+    }
+}
+
+/// Returns the start of sample `sample_idx`, samples are evenly spaced in the input.
+fn sample_start(
+    sample_idx: u64,
+    samples: u64,
+    sample_duration: Duration,
+    duration: Duration,
+) -> Duration {
+    (duration.saturating_sub(sample_duration * samples as _) / (samples as u32 + 1))
+        * (sample_idx + 1) as _
+        + sample_duration * sample_idx as _
+}
+
+/// Returns the sample frame count & whether to floor the start to a whole second.
+fn sample_frames(sample_duration: Duration, fps: f64) -> (u32, bool) {
+    (
+        ((sample_duration.as_secs_f64() * fps).round() as u32).max(1),
+        sample_duration >= Duration::from_secs(2),
+    )
+}
+
+/// Print the commands to create, encode & score the first sample without running
+/// them, see --dry-run.
+pub fn print_dry_run(
+    Args {
+        args,
+        crf,
+        sample: sample_args,
+        vmaf,
+        score,
+        xpsnr,
+        psnr,
+        xpsnr_opts,
+        metric,
+        ..
+    }: &Args,
+    probe: &Ffprobe,
+) -> anyhow::Result<()> {
+    let enc_args = args.to_encoder_args(*crf, probe)?;
+    let ref_vfilter = args.reference_vfilter(probe)?;
+    let ref_vfilter = score.reference_vfilter_or(ref_vfilter.as_deref());
+    let duration = probe.duration.clone()?;
+    let fps = probe.fps.clone()?;
+    let samples = sample_args.sample_count(duration).max(1);
+    let (samples, sample_duration, full_pass) =
+        sample_plan(sample_args, samples, duration, fps, probe.media_kind());
+
+    let trim_start = args.start();
+    let video_stream = args.video_stream();
+    let sample = match full_pass && !args.is_trimmed() && video_stream == 0 {
+        true => args.input.clone(),
+        false => {
+            let complexity_start = match sample_args.sample_selection {
+                SampleSelection::Even => None,
+                SampleSelection::Complexity => {
+                    let mut cmd = sample::packets_command(&args.input, video_stream);
+                    script::print("Sample complexity", &cmd);
+                    cmd.output()
+                        .ok()
+                        .and_then(|out| {
+                            let packets = sample::parse_packets(
+                                &String::from_utf8_lossy(&out.stdout),
+                                trim_start,
+                            );
+                            sample::pick_by_complexity(&packets, samples, sample_duration, duration)
+                        })
+                        .and_then(|starts| starts.first().copied())
+                }
+            };
+            let mut start = trim_start
+                + complexity_start
+                    .unwrap_or_else(|| sample_start(0, samples, sample_duration, duration));
+            let (frames, mut floor_to_sec) = sample_frames(sample_duration, fps);
+            if sample_args.samples_from_keyframes {
+                let mut cmd = sample::keyframe_command(&args.input, video_stream, start);
+                script::print("Sample 1 keyframe", &cmd);
+                let max_start = (trim_start + duration).saturating_sub(sample_duration);
+                if let Some(keyframe) = cmd
+                    .output()
+                    .ok()
+                    .and_then(|out| {
+                        sample::parse_keyframe(&String::from_utf8_lossy(&out.stdout), start)
+                    })
+                    .filter(|k| *k <= max_start)
+                {
+                    (start, floor_to_sec) = (keyframe, false);
+                }
+            }
+            let temp_dir = sample_args.temp_dir.clone();
+            let dest = sample::path(
+                &args.input,
+                video_stream,
+                start,
+                floor_to_sec,
+                frames,
+                temp_dir,
+            );
+            let cmd = sample::copy_command(
+                &args.input,
+                video_stream,
+                start,
+                floor_to_sec,
+                frames,
+                &dest,
+                false,
+            );
+            script::print(&format!("Sample 1/{samples}"), cmd.as_std());
+            dest
+        }
+    };
+
+    let pix_fmt = PixelFormat::opt_max(enc_args.pix_fmt, probe.pixel_format());
+    let (encoded, cmd) = ffmpeg::encode_sample_command(
+        FfmpegEncodeArgs {
+            input: &sample,
+            ..enc_args
+        },
+        sample_args.temp_dir.clone(),
+        sample_args.extension.as_deref().unwrap_or("mkv"),
+    );
+    script::print(
+        &format!("Encode sample 1/{samples} crf {crf}"),
+        cmd.as_std(),
+    );
+
+    let cmd = match (xpsnr, psnr, &metric.metric_cmd) {
+        (_, _, Some(metric_cmd)) => metric::command(metric_cmd, &sample, &encoded, ref_vfilter)?,
+        (true, _, _) => xpsnr::command(
+            &sample,
+            &encoded,
+            &super::xpsnr::lavfi(ref_vfilter),
+            xpsnr_opts.fps(),
+        ),
+        (_, true, _) => xpsnr::command(
+            &sample,
+            &encoded,
+            &super::xpsnr::psnr_lavfi(ref_vfilter),
+            xpsnr_opts.fps(),
+        ),
+        _ => {
+            // the encoded resolution is only known without a vfilter before encoding
+            let distorted_res = probe.resolution.filter(|_| args.vfilter.is_none());
+            let cuda_ffmpeg = vmaf::cuda::ffmpeg();
+            let ref_nvdec = vmaf
+                .reference_nvdec(probe)
+                .filter(|_| cuda_ffmpeg.is_none());
+            let lavfi = match cuda_ffmpeg {
+                Some(_) => vmaf.ffmpeg_lavfi_cuda(distorted_res, ref_vfilter),
+                None => vmaf.ffmpeg_lavfi(distorted_res, pix_fmt, ref_vfilter, ref_nvdec),
+            };
+            vmaf::command(
+                cuda_ffmpeg.unwrap_or(program::ffmpeg()),
+                cuda_ffmpeg.is_some() && vmaf.cuda_nvdec(ref_vfilter),
+                ref_nvdec.is_some(),
+                &sample,
+                &encoded,
+                &lavfi,
+                vmaf.fps(),
+            )
+        }
+    };
+    script::print(&format!("Score sample 1/{samples}"), cmd.as_std());
+    Ok(())
+}
+
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct EncodeResult {
     pub sample_size: u64,
     pub encoded_size: u64,
     pub score: f32,
     pub score_kind: ScoreKind,
+    /// Lowest per-frame score, if calculated.
+    #[serde(default)]
+    pub min_frame_score: Option<f32>,
+    /// Per-frame VMAF score stats, if calculated.
+    #[serde(default)]
+    pub frame_stats: Option<FrameStats>,
+    /// PSNR, if calculated with `--also-psnr`.
+    #[serde(default)]
+    pub psnr: Option<f32>,
+    /// SSIM, if calculated with `--also-ssim`.
+    #[serde(default)]
+    pub ssim: Option<f32>,
     pub encode_time: Duration,
     /// Duration of the sample.
     ///
     /// This should be close to `SAMPLE_SIZE` but may deviate due to how samples are cut.
     pub sample_duration: Duration,
+    /// Encoder summary, e.g. average QP per frame type, if reported by the encoder.
+    #[serde(default)]
+    pub encoder_stats: Option<EncoderStats>,
     /// Result read from cache.
     pub from_cache: bool,
 }
@@ -507,6 +1161,7 @@ impl EncodeResult {
             encoded_size,
             score,
             score_kind,
+            encoder_stats,
             from_cache,
             ..
         } = self;
@@ -520,6 +1175,9 @@ impl EncodeResult {
             .dim()
             .to_string(),
         );
+        if let Some(stats) = encoder_stats {
+            bar.println(style!("  {stats}").dim().to_string());
+        }
     }
 
     pub fn log_attempt(&self, sample_n: u64, samples: u64, crf: f32) {
@@ -543,6 +1201,9 @@ impl EncodeResult {
 pub enum ScoreKind {
     Vmaf,
     Xpsnr,
+    Psnr,
+    /// External `--metric-cmd` score.
+    Metric,
 }
 
 impl ScoreKind {
@@ -551,6 +1212,8 @@ impl ScoreKind {
         match self {
             Self::Vmaf => "vmaf",
             Self::Xpsnr => "xpsnr",
+            Self::Psnr => "psnr",
+            Self::Metric => "metric",
         }
     }
 
@@ -559,6 +1222,8 @@ impl ScoreKind {
         match self {
             Self::Vmaf => "VMAF",
             Self::Xpsnr => "XPSNR",
+            Self::Psnr => "PSNR",
+            Self::Metric => "Metric",
         }
     }
 }
@@ -576,6 +1241,21 @@ trait EncodeResults {
 
     fn mean_score(&self) -> f32;
 
+    fn min_score(&self) -> f32;
+
+    /// Returns the ~95% confidence ± error of the mean score using the standard error
+    /// of sample scores, `0.0` with fewer than 2 results.
+    fn score_error(&self) -> f32;
+
+    /// Returns the lowest per-frame score, if calculated for all results.
+    fn min_frame_score(&self) -> Option<f32>;
+
+    /// Returns combined per-frame stats, if calculated for all results.
+    fn frame_stats(&self) -> Option<FrameStats>;
+
+    /// Returns the mean of an optional score, if calculated for all results.
+    fn mean_of(&self, score: impl Fn(&EncodeResult) -> Option<f32>) -> Option<f32>;
+
     /// Return estimated encoded **video stream** size by multiplying sample size by duration.
     fn estimate_encode_size_by_duration(
         &self,
@@ -608,6 +1288,42 @@ impl EncodeResults for Vec<EncodeResult> {
         self.iter().map(|r| r.score).sum::<f32>() / self.len() as f32
     }
 
+    fn min_score(&self) -> f32 {
+        self.iter()
+            .map(|r| r.score)
+            .reduce(f32::min)
+            .unwrap_or_default()
+    }
+
+    fn score_error(&self) -> f32 {
+        if self.len() < 2 {
+            return 0.0;
+        }
+        let n = self.len() as f32;
+        let mean = self.mean_score();
+        let variance = self.iter().map(|r| (r.score - mean).powi(2)).sum::<f32>() / (n - 1.0);
+        1.96 * (variance / n).sqrt()
+    }
+
+    fn min_frame_score(&self) -> Option<f32> {
+        self.iter()
+            .map(|r| r.min_frame_score)
+            .reduce(|a, b| Some(a?.min(b?)))?
+    }
+
+    fn frame_stats(&self) -> Option<FrameStats> {
+        let stats: Option<Vec<_>> = self.iter().map(|r| r.frame_stats).collect();
+        FrameStats::combine(&stats?)
+    }
+
+    fn mean_of(&self, score: impl Fn(&EncodeResult) -> Option<f32>) -> Option<f32> {
+        if self.is_empty() {
+            return None;
+        }
+        let sum: Option<f32> = self.iter().map(score).sum();
+        Some(sum? / self.len() as f32)
+    }
+
     fn estimate_encode_size_by_duration(
         &self,
         input_duration: Duration,
@@ -652,20 +1368,20 @@ impl EncodeResults for Vec<EncodeResult> {
 /// change to the input file size.
 ///
 /// This can over-estimate the larger the non-video proportion of the input.
-async fn estimate_encode_size_by_file_percent(
+fn estimate_encode_size_by_file_percent(
     results: &Vec<EncodeResult>,
-    input: &Path,
+    input_len: u64,
     single_full_pass: bool,
-) -> anyhow::Result<u64> {
+) -> u64 {
     if results.is_empty() {
-        return Ok(0);
+        return 0;
     }
     if single_full_pass {
-        return Ok(results[0].encoded_size);
+        return results[0].encoded_size;
     }
     let encode_proportion = results.encoded_percent_size() / 100.0;
 
-    Ok((fs::metadata(input).await?.len() as f64 * encode_proportion).round() as _)
+    (input_len as f64 * encode_proportion).round() as _
 }
 
 #[derive(Debug, Clone, Copy, clap::ValueEnum)]
@@ -679,10 +1395,14 @@ impl StdoutFormat {
         self,
         Output {
             score,
+            score_error,
             score_kind,
-            predicted_encode_size,
+            min_frame_score,
+            frame_stats,
+            psnr,
+            ssim,
             encode_percent,
-            predicted_encode_time,
+            prediction,
             from_cache: _,
         }: &Output,
         image: bool,
@@ -695,7 +1415,7 @@ impl StdoutFormat {
                     (v, _) => style(v).bold(),
                 };
                 let percent = encode_percent.round();
-                let size = match *predicted_encode_size {
+                let size = match prediction.size {
                     v if percent < 80.0 => style(HumanBytes(v)).bold().green(),
                     v if percent >= 100.0 => style(HumanBytes(v)).bold().red(),
                     v => style(HumanBytes(v)).bold(),
@@ -705,24 +1425,52 @@ impl StdoutFormat {
                     v if v >= 100.0 => style!("{}%", v).bold().red(),
                     v => style!("{}%", v).bold(),
                 };
-                let time = style(HumanDuration(*predicted_encode_time)).bold();
-                let enc_description = match image {
-                    true => "image",
-                    false => "video stream",
+                let score_error = match *score_error {
+                    e if e > 0.0 && !compat::upstream() => format!(" ±{e:.2}"),
+                    _ => String::new(),
+                };
+                let error = match prediction.size_error {
+                    0 => String::new(),
+                    _ if compat::upstream() => String::new(),
+                    e => format!(" ±{}", HumanBytes(e)),
+                };
+                let time = style(HumanDuration(prediction.time)).bold();
+                let (enc_description, fps) = match (image, prediction.fps) {
+                    (true, _) => ("image", String::new()),
+                    (false, fps) if fps > 0.0 && !compat::upstream() => {
+                        ("video stream", format!(" at {fps:.0} fps"))
+                    }
+                    (false, _) => ("video stream", String::new()),
                 };
                 println!(
-                    "{score_kind} {score:.2} predicted {enc_description} size {size} ({percent}) taking {time}"
+                    "{score_kind} {score:.2}{score_error} predicted {enc_description} size {size}{error} ({percent}) taking {time}{fps}"
                 );
             }
             Self::Json => {
                 let mut json = serde_json::json!({
-                    "predicted_encode_size": predicted_encode_size,
                     "predicted_encode_percent": encode_percent,
-                    "predicted_encode_seconds": predicted_encode_time.as_secs(),
                 });
+                prediction.add_json(&mut json);
                 match score_kind {
                     ScoreKind::Vmaf => json["vmaf"] = (*score).into(),
                     ScoreKind::Xpsnr => json["xpsnr"] = (*score).into(),
+                    ScoreKind::Psnr => json["psnr"] = (*score).into(),
+                    ScoreKind::Metric => json["metric"] = (*score).into(),
+                }
+                if *score_error > 0.0 && !compat::upstream() {
+                    json["score_error"] = (*score_error).into();
+                }
+                if let Some(min) = min_frame_score {
+                    json["min_frame_vmaf"] = (*min).into();
+                }
+                if let Some(stats) = frame_stats {
+                    json["frame_vmaf"] = serde_json::json!(stats);
+                }
+                if let Some(psnr) = psnr {
+                    json["psnr"] = (*psnr).into();
+                }
+                if let Some(ssim) = ssim {
+                    json["ssim"] = (*ssim).into();
                 }
                 println!("{json}");
             }
@@ -733,21 +1481,116 @@ impl StdoutFormat {
 /// Sample encode result.
 #[derive(Debug, Clone)]
 pub struct Output {
-    /// Sample mean score.
+    /// Sample mean score, or the lowest sample score with `--vmaf-pool min`.
     pub score: f32,
+    /// Estimated ± error of the mean `score` at ~95% confidence, from the variation of
+    /// sample scores. `0.0` with too few samples, or with `--vmaf-pool min`.
+    pub score_error: f32,
     pub score_kind: ScoreKind,
+    /// Lowest per-frame score of all samples, if calculated.
+    pub min_frame_score: Option<f32>,
+    /// Combined per-frame VMAF stats of all samples, if calculated.
+    pub frame_stats: Option<FrameStats>,
+    /// Sample mean PSNR, if calculated.
+    pub psnr: Option<f32>,
+    /// Sample mean SSIM, if calculated.
+    pub ssim: Option<f32>,
+    /// Sample mean encoded percentage.
+    pub encode_percent: f64,
+    /// Full encode size & time prediction.
+    pub prediction: Prediction,
+    /// All sample results were read from the cache.
+    pub from_cache: bool,
+}
+
+/// Full encode prediction from sample results.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Prediction {
     /// Estimated full encoded **video stream** size.
     ///
     /// Encoded sample size multiplied by duration.
-    pub predicted_encode_size: u64,
-    /// Sample mean encoded percentage.
-    pub encode_percent: f64,
+    pub size: u64,
+    /// Estimated ± error of `size` at ~95% confidence, from the variation of sample bitrates.
+    /// `0` if exact, i.e. a full pass, or there are too few samples to estimate.
+    pub size_error: u64,
     /// Estimated full encode time.
     ///
     /// Sample encode time multiplied by duration.
-    pub predicted_encode_time: Duration,
-    /// All sample results were read from the cache.
-    pub from_cache: bool,
+    pub time: Duration,
+    /// Measured sample encode fps, `0.0` may be interpreted as "unknown".
+    pub fps: f32,
+}
+
+impl Prediction {
+    pub fn from_results(
+        results: &Vec<EncodeResult>,
+        input_duration: Duration,
+        input_fps: f64,
+        input_len: u64,
+        single_full_pass: bool,
+    ) -> Self {
+        let sample_duration: Duration = results.iter().map(|r| r.sample_duration).sum();
+        let encode_time: Duration = results.iter().map(|r| r.encode_time).sum();
+        Self {
+            // Using file size * encode_percent can over-estimate. However, if it ends up less
+            // than the duration estimation it may turn out to be more accurate.
+            size: results
+                .estimate_encode_size_by_duration(input_duration, single_full_pass)
+                .min(estimate_encode_size_by_file_percent(
+                    results,
+                    input_len,
+                    single_full_pass,
+                )),
+            size_error: match single_full_pass {
+                true => 0,
+                false => estimate_encode_size_error(results, input_duration),
+            },
+            time: results.estimate_encode_time(input_duration, single_full_pass),
+            fps: match encode_time.is_zero() {
+                true => 0.0,
+                false => {
+                    (input_fps * sample_duration.as_secs_f64() / encode_time.as_secs_f64()) as _
+                }
+            },
+        }
+    }
+
+    /// Upper bound of the estimated size, `size + size_error`.
+    pub fn max_size(&self) -> u64 {
+        self.size + self.size_error
+    }
+
+    /// Add "predicted_encode_*" fields to a json object.
+    pub fn add_json(&self, json: &mut serde_json::Value) {
+        json["predicted_encode_size"] = self.size.into();
+        json["predicted_encode_seconds"] = self.time.as_secs().into();
+        if compat::upstream() {
+            return;
+        }
+        if self.size_error > 0 {
+            json["predicted_encode_size_error"] = self.size_error.into();
+        }
+        if self.fps > 0.0 {
+            json["predicted_encode_fps"] = self.fps.into();
+        }
+    }
+}
+
+/// Returns the ~95% confidence ± error of the duration size estimate using the
+/// standard error of the mean sample bitrate.
+fn estimate_encode_size_error(results: &[EncodeResult], input_duration: Duration) -> u64 {
+    if results.len() < 2 {
+        return 0;
+    }
+    let rates: Vec<_> = results
+        .iter()
+        .map(|r| r.encoded_size as f64 / r.sample_duration.as_secs_f64())
+        .collect();
+    let n = rates.len() as f64;
+    let mean = rates.iter().sum::<f64>() / n;
+    let variance = rates.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / (n - 1.0);
+    let std_err = (variance / n).sqrt();
+    (1.96 * std_err * input_duration.as_secs_f64()).round() as _
 }
 
 /// Kinds of sample-encode work.
@@ -794,3 +1637,111 @@ pub enum Update {
     },
     Done(Output),
 }
+
+/// Update of a single sample, see [`run`].
+enum SampleUpdate {
+    /// Status with progress of the sample.
+    Status(u64, Status),
+    Done(u64, EncodeResult),
+}
+
+#[test]
+fn prediction_from_results() {
+    let result = |encoded_size| EncodeResult {
+        sample_size: 10_000,
+        encoded_size,
+        score: 95.0,
+        score_kind: ScoreKind::Vmaf,
+        min_frame_score: None,
+        frame_stats: None,
+        psnr: None,
+        ssim: None,
+        encode_time: Duration::from_secs(4),
+        sample_duration: Duration::from_secs(20),
+        encoder_stats: None,
+        from_cache: false,
+    };
+    let results = vec![result(4000), result(5000), result(6000)];
+    let prediction =
+        Prediction::from_results(&results, Duration::from_secs(600), 24.0, 1_000_000, false);
+    assert_eq!(prediction.size, 150_000);
+    // 1.96 * sd 50 B/s / sqrt(3) * 600s
+    assert_eq!(prediction.size_error, 33_948);
+    assert_eq!(prediction.time, Duration::from_secs(120));
+    assert_eq!(prediction.fps, 120.0);
+
+    let prediction = Prediction::from_results(
+        &results[..1].to_vec(),
+        Duration::from_secs(600),
+        24.0,
+        1_000_000,
+        false,
+    );
+    assert_eq!(prediction.size_error, 0);
+}
+
+#[test]
+fn score_error_from_results() {
+    let result = |score| EncodeResult {
+        sample_size: 10_000,
+        encoded_size: 5000,
+        score,
+        score_kind: ScoreKind::Vmaf,
+        min_frame_score: None,
+        frame_stats: None,
+        psnr: None,
+        ssim: None,
+        encode_time: Duration::from_secs(4),
+        sample_duration: Duration::from_secs(20),
+        encoder_stats: None,
+        from_cache: false,
+    };
+    let results = vec![result(94.0), result(95.0), result(96.0)];
+    // 1.96 * sd 1.0 / sqrt(3)
+    assert!(
+        (results.score_error() - 1.1316).abs() < 0.001,
+        "{}",
+        results.score_error()
+    );
+    assert_eq!(vec![result(94.0)].score_error(), 0.0);
+}
+
+#[test]
+fn preset_sweep_recommendation() {
+    let out = |score, secs| Output {
+        score,
+        score_error: 0.0,
+        score_kind: ScoreKind::Vmaf,
+        min_frame_score: None,
+        frame_stats: None,
+        psnr: None,
+        ssim: None,
+        encode_percent: 50.0,
+        prediction: Prediction {
+            time: Duration::from_secs(secs),
+            ..<_>::default()
+        },
+        from_cache: false,
+    };
+    let results = [
+        (4, out(96.0, 420)),
+        (5, out(95.9, 300)),
+        (6, out(95.7, 200)),
+        (7, out(95.2, 120)),
+    ];
+    assert_eq!(
+        recommend_preset(&results),
+        Some((6, "preset 6 is 2.1x faster than 4 with -0.30 VMAF".into()))
+    );
+    assert_eq!(
+        recommend_preset(&results[..1]).map(|(p, _)| p),
+        Some(4),
+        "single preset"
+    );
+    assert_eq!(recommend_preset(&[]), None);
+
+    assert_eq!(parse_preset_range("4..10"), Ok(4..=10));
+    assert_eq!(parse_preset_range("-1..2"), Ok(-1..=2));
+    assert!(parse_preset_range("10..4").is_err());
+    assert!(parse_preset_range("fast").is_err());
+}
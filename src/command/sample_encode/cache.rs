@@ -1,15 +1,34 @@
-//! _sample-encode_ file system caching logic.
+//! _sample-encode_ & ffprobe file system caching logic.
 use crate::{
-    command::args::{ScoreArgs, Vmaf, Xpsnr},
+    command::args::{MetricCmd, ScoreArgs, Vmaf, VmafPool, Xpsnr},
     ffmpeg::FfmpegEncodeArgs,
+    ffprobe::Ffprobe,
 };
 use anyhow::Context;
+use serde::{Deserialize, Serialize};
 use std::{
     ffi::OsStr,
     hash::Hash,
-    path::Path,
+    io::{BufRead, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
     time::{Duration, Instant},
 };
+use tracing::debug;
+
+/// Set by [`init`] if the command disables caching with `--cache false`.
+static DISABLED: AtomicBool = AtomicBool::new(false);
+
+/// Set whether the command's --cache is enabled, also applying to ffprobe caching
+/// of commands that probe before sample-encoding. Should be called before probing.
+pub fn init(cache: bool) {
+    DISABLED.store(!cache, Ordering::Relaxed);
+}
+
+/// Returns false if caching is disabled by `--cache false` or `AB_AV1_CACHE=false`.
+fn enabled() -> bool {
+    !DISABLED.load(Ordering::Relaxed) && !std::env::var("AB_AV1_CACHE").is_ok_and(|v| v == "false")
+}
 
 /// Return a previous stored encode result for the same sample & args.
 #[allow(clippy::too_many_arguments)]
@@ -62,7 +81,7 @@ pub async fn cached_encode(
         }
         Ok(None) => (None, Some(key)),
         Err(err) => {
-            eprintln!("cache error: {err}");
+            report_error(err);
             (None, None)
         }
     }
@@ -72,6 +91,9 @@ pub async fn cached_encode(
 pub enum ScoringInfo<'a> {
     Vmaf(&'a Vmaf, &'a ScoreArgs),
     Xpsnr(&'a Xpsnr, &'a ScoreArgs),
+    Psnr(&'a Xpsnr, &'a ScoreArgs),
+    /// Per-frame plugin scores are pooled using --vmaf-pool.
+    Metric(&'a MetricCmd, VmafPool, &'a ScoreArgs),
 }
 
 pub async fn cache_result(key: Key, result: &super::EncodeResult) -> anyhow::Result<()> {
@@ -86,17 +108,149 @@ pub async fn cache_result(key: Key, result: &super::EncodeResult) -> anyhow::Res
     .and_then(|r| Ok(r?));
 
     if let Err(err) = insert {
-        eprintln!("cache error: {err}")
+        report_error(err);
     }
     Ok(())
 }
 
-fn open_db() -> sled::Result<sled::Db> {
-    const LOCK_MAX_WAIT: Duration = Duration::from_secs(2);
+/// Returns the cache key of an ffprobe of the `input` file, from its path, size & mtime.
+///
+/// `None` for inputs without file metadata, e.g. urls, or if caching is disabled
+/// with `--cache false` or `AB_AV1_CACHE=false`.
+pub fn probe_key(input: &Path) -> Option<Key> {
+    if !enabled() {
+        return None;
+    }
+    let meta = std::fs::metadata(input).ok()?;
+    let path = std::path::absolute(input).ok()?;
+    let mut hasher = blake3::Hasher::new();
+    // ffprobe result format may change between versions
+    (
+        env!("CARGO_PKG_VERSION"),
+        path,
+        meta.len(),
+        meta.modified().ok()?,
+    )
+        .hash(&mut BlakeStdHasher(&mut hasher));
+    Some(Key(hasher.finalize()))
+}
+
+/// Return a previous stored ffprobe result, see [`probe_key`].
+pub fn cached_probe(key: Key) -> Option<Ffprobe> {
+    let data = open_probe_tree()
+        .and_then(|tree| tree.get(key.0.to_hex().as_bytes()))
+        .map_err(report_error)
+        .ok()??;
+    serde_json::from_slice(&data).ok()
+}
 
+pub fn cache_probe(key: Key, probe: &Ffprobe) {
+    let insert = serde_json::to_vec(probe)
+        .map_err(anyhow::Error::from)
+        .and_then(|data| {
+            let tree = open_probe_tree()?;
+            tree.insert(key.0.to_hex().as_bytes(), data)?;
+            tree.flush()?;
+            Ok(())
+        });
+    if let Err(err) = insert {
+        report_error(err);
+    }
+}
+
+/// Ffprobe results are stored separately from sample-encode results.
+fn open_probe_tree() -> sled::Result<sled::Tree> {
+    open_db()?.open_tree("ffprobe")
+}
+
+/// Sample-encode cache db directory.
+pub fn db_path() -> PathBuf {
     let mut path = dirs::cache_dir().expect("no cache dir found");
     path.push("ab-av1");
     path.push("sample-encode-cache");
+    path
+}
+
+/// Returns true if the cache db is open, i.e. locked, by a running ab-av1 process.
+///
+/// sled locks its "db" file while open, so trying the same lock tells if it's in use.
+pub fn db_in_use() -> bool {
+    std::fs::File::options()
+        .read(true)
+        .write(true)
+        .open(db_path().join("db"))
+        .is_ok_and(|f| f.try_lock().is_err())
+}
+
+/// A portable cache entry, see [`export`].
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry {
+    key: String,
+    result: serde_json::Value,
+}
+
+/// Write all cached results as json lines, returns the number of entries written.
+pub fn export(mut out: impl Write) -> anyhow::Result<usize> {
+    let db = open_db()?;
+    let mut count = 0;
+    for kv in db.iter() {
+        let (key, data) = kv?;
+        let entry = Entry {
+            key: String::from_utf8_lossy(&key).into_owned(),
+            result: serde_json::from_slice(&data)?,
+        };
+        serde_json::to_writer(&mut out, &entry)?;
+        out.write_all(b"\n")?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Insert json lines written by [`export`] into the cache.
+///
+/// Returns the number of entries imported & skipped as incompatible with this version.
+pub fn import(lines: impl BufRead) -> anyhow::Result<(usize, usize)> {
+    let db = open_db()?;
+    let (mut imported, mut skipped) = (0, 0);
+    for line in lines.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let Entry { key, result } = serde_json::from_str(&line)?;
+        if serde_json::from_value::<super::EncodeResult>(result.clone()).is_err() {
+            skipped += 1;
+            continue;
+        }
+        db.insert(key.as_bytes(), serde_json::to_vec(&result)?)?;
+        imported += 1;
+    }
+    db.flush()?;
+    Ok((imported, skipped))
+}
+
+/// Print a cache error. The db being locked by another process, e.g. a concurrent
+/// ab-av1 run, is only logged as the lookup or insert is just skipped.
+fn report_error(err: impl Into<anyhow::Error>) {
+    let err = err.into();
+    match is_locked(&err) {
+        true => debug!("cache skipped: {err}"),
+        false => eprintln!("cache error: {err}"),
+    }
+}
+
+/// Returns true if `err` is sled failing to lock the db after [`open_db`] waited.
+fn is_locked(err: &anyhow::Error) -> bool {
+    matches!(
+        err.downcast_ref::<sled::Error>(),
+        Some(sled::Error::Io(io)) if io.to_string().starts_with("could not acquire lock")
+    )
+}
+
+fn open_db() -> sled::Result<sled::Db> {
+    const LOCK_MAX_WAIT: Duration = Duration::from_secs(2);
+
+    let path = db_path();
     let a = Instant::now();
     let mut db = sled::open(&path);
     while db.is_err() && a.elapsed() < LOCK_MAX_WAIT {
@@ -122,7 +276,9 @@ fn hash_encode(
     hasher.finalize()
 }
 
-struct BlakeStdHasher<'a>(&'a mut blake3::Hasher);
+/// Feeds [`Hash`] impls into a blake3 hasher, giving stable hashes across Rust versions
+/// unlike [`std::hash::DefaultHasher`].
+pub(crate) struct BlakeStdHasher<'a>(pub(crate) &'a mut blake3::Hasher);
 impl std::hash::Hasher for BlakeStdHasher<'_> {
     fn finish(&self) -> u64 {
         unimplemented!()
@@ -133,3 +289,30 @@ impl std::hash::Hasher for BlakeStdHasher<'_> {
         self.0.update(bytes);
     }
 }
+
+#[test]
+fn probe_keys() {
+    let file = std::env::temp_dir().join(format!("ab-av1-test-probe-{}.mkv", std::process::id()));
+    std::fs::write(&file, b"1234").unwrap();
+    let key = probe_key(&file).unwrap();
+    assert_eq!(probe_key(&file).unwrap().0, key.0);
+
+    std::fs::write(&file, b"12345").unwrap();
+    assert_ne!(probe_key(&file).unwrap().0, key.0, "size changed");
+
+    std::fs::remove_file(&file).unwrap();
+    assert!(probe_key(&file).is_none());
+    assert!(probe_key(Path::new("https://example.com/vid.mkv")).is_none());
+}
+
+#[test]
+fn locked_db_errors() {
+    let locked = sled::Error::Io(std::io::Error::other(
+        "could not acquire lock on \"/home/u/.cache/ab-av1/sample-encode-cache/db\": Os { code: 11 }",
+    ));
+    assert!(is_locked(&locked.into()));
+
+    let other = sled::Error::Unsupported("could not acquire lock".into());
+    assert!(!is_locked(&other.into()));
+    assert!(!is_locked(&anyhow::anyhow!("could not acquire lock")));
+}
@@ -0,0 +1,366 @@
+use crate::{
+    process::{CommandExt, ensure_success},
+    temporary,
+};
+use anyhow::{Context, bail, ensure};
+use clap::{Parser, ValueEnum};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::{env, fmt, fs, io::Read, path::Path, process::Stdio};
+use tokio::{io::AsyncWriteExt, process::Command};
+
+/// Update ab-av1 to the latest GitHub release for this platform.
+///
+/// Downloads are verified against the release "SHA256SUMS" asset, whose
+/// "SHA256SUMS.sig" signature must be made by the ab-av1 release signing key.
+/// Releases without signed checksums are not installed.
+///
+/// Requires `curl` & `ssh-keygen` in $PATH.
+#[derive(Parser)]
+#[clap(verbatim_doc_comment)]
+#[group(skip)]
+pub struct Args {
+    /// Release channel.
+    #[arg(long, value_enum, default_value_t = Channel::Stable)]
+    pub channel: Channel,
+
+    /// GitHub repository to download releases from.
+    #[arg(long, default_value = default_repo())]
+    pub repo: String,
+
+    /// Only check for an update, don't install it.
+    #[arg(long)]
+    pub check: bool,
+
+    /// Install even if the release is not newer than the current version.
+    #[arg(long)]
+    pub force: bool,
+}
+
+/// Release channel.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+#[clap(rename_all = "kebab-case")]
+pub enum Channel {
+    /// Latest versioned release.
+    Stable,
+    /// Latest build of the main branch, the "nightly" tagged release.
+    Nightly,
+}
+
+impl fmt::Display for Channel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Stable => "stable",
+            Self::Nightly => "nightly",
+        })
+    }
+}
+
+/// Signer identity & public key of the release signing key. The private key is the
+/// `RELEASE_SIGNING_KEY` secret used by the release workflow to sign SHA256SUMS.
+const RELEASE_SIGNER: &str = "ab-av1-release";
+const RELEASE_PUBKEY: &str =
+    "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAILGg7F8NCYj2KrHN5z491TunnW/gXTSGLIe/UKvzPaqL";
+
+/// "owner/name" of the package repository.
+fn default_repo() -> &'static str {
+    env!("CARGO_PKG_REPOSITORY").trim_start_matches("https://github.com/")
+}
+
+#[derive(Debug, Deserialize)]
+struct Release {
+    tag_name: String,
+    assets: Vec<Asset>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Asset {
+    name: String,
+    browser_download_url: String,
+}
+
+pub async fn self_update(
+    Args {
+        channel,
+        repo,
+        check,
+        force,
+    }: Args,
+) -> anyhow::Result<()> {
+    let api = match channel {
+        Channel::Stable => format!("https://api.github.com/repos/{repo}/releases/latest"),
+        Channel::Nightly => format!("https://api.github.com/repos/{repo}/releases/tags/nightly"),
+    };
+    let release: Release = serde_json::from_slice(&curl(&api, None).await?)
+        .with_context(|| format!("invalid release json from {api}"))?;
+
+    let current = env!("CARGO_PKG_VERSION");
+    let newer = channel == Channel::Nightly || is_newer(&release.tag_name, current);
+    if !newer && !force {
+        println!(
+            "ab-av1 {current} is up to date ({channel} {})",
+            release.tag_name
+        );
+        return Ok(());
+    }
+    if check {
+        println!(
+            "Update available: {current} -> {channel} {}",
+            release.tag_name
+        );
+        return Ok(());
+    }
+
+    let asset =
+        find_asset(&release.assets, env::consts::OS, env::consts::ARCH).with_context(|| {
+            format!(
+                "no {} {} asset in release {}",
+                env::consts::OS,
+                env::consts::ARCH,
+                release.tag_name
+            )
+        })?;
+    let sums = verified_sha256_sums(&release.assets).await?;
+    let expected = parse_sha256(&sums, &asset.name)
+        .with_context(|| format!("no sha256 checksum published for {}", asset.name))?;
+
+    let exe = env::current_exe().context("current exe")?;
+    let download = exe.with_extension("download");
+    curl(&asset.browser_download_url, Some(&download)).await?;
+    let verified = verify_and_extract(&download, &asset.name, &expected);
+    let installed = verified.and_then(|binary| replace_exe(&exe, &binary));
+    _ = fs::remove_file(&download);
+    installed?;
+
+    println!("Updated ab-av1 {current} -> {channel} {}", release.tag_name);
+    Ok(())
+}
+
+/// GET the `url` into `output`, or return the body.
+async fn curl(url: &str, output: Option<&Path>) -> anyhow::Result<Vec<u8>> {
+    let out = Command::new("curl")
+        .args(["-fsSL", "-H", "Accept: application/vnd.github+json"])
+        .arg2_opt("-o", output)
+        .arg(url)
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .context("curl")?;
+    ensure_success("curl", &out).with_context(|| format!("GET {url}"))?;
+    Ok(out.stdout)
+}
+
+/// Returns true if the release `tag`, e.g. "v0.10.0", is a newer version than `current`.
+fn is_newer(tag: &str, current: &str) -> bool {
+    fn version(v: &str) -> Option<(u64, u64, u64)> {
+        let v = v.trim_start_matches('v');
+        let v = v.split_once('-').map_or(v, |(v, _pre)| v);
+        let mut parts = v.split('.').map(|n| n.parse().ok());
+        Some((
+            parts.next()??,
+            parts.next()??,
+            parts.next().flatten().unwrap_or(0),
+        ))
+    }
+    match (version(tag), version(current)) {
+        (Some(tag), Some(current)) => tag > current,
+        _ => false,
+    }
+}
+
+/// Find the binary or .tar.zst release asset for the `os` & `arch`.
+fn find_asset<'a>(assets: &'a [Asset], os: &str, arch: &str) -> Option<&'a Asset> {
+    let os = match os {
+        "macos" => "darwin",
+        os => os,
+    };
+    let is_binary = |name: &str| {
+        !name.ends_with(".sha256")
+            && !name.ends_with(".sig")
+            && !name.ends_with(".minisig")
+            && !name.eq_ignore_ascii_case("SHA256SUMS")
+    };
+    assets
+        .iter()
+        .filter(|a| is_binary(&a.name))
+        .find(|a| a.name.contains(os) && a.name.contains(arch))
+        .or_else(|| {
+            // e.g. "ab-av1.exe", only built for x86_64 windows
+            (os == "windows" && arch == "x86_64")
+                .then(|| assets.iter().find(|a| a.name == "ab-av1.exe"))
+                .flatten()
+        })
+}
+
+/// Download the release SHA256SUMS & verify its SHA256SUMS.sig signature.
+async fn verified_sha256_sums(assets: &[Asset]) -> anyhow::Result<String> {
+    let find = |name: &str| {
+        assets
+            .iter()
+            .find(|a| a.name == name)
+            .with_context(|| format!("no {name} published with the release"))
+    };
+    let sums = curl(&find("SHA256SUMS")?.browser_download_url, None).await?;
+    let sig = curl(&find("SHA256SUMS.sig")?.browser_download_url, None).await?;
+    verify_signature(&sums, &sig)
+        .await
+        .context("SHA256SUMS signature verification failed")?;
+    Ok(String::from_utf8_lossy(&sums).into_owned())
+}
+
+/// Verify the ssh signature `sig` of `data` was made by the [`RELEASE_PUBKEY`],
+/// using `ssh-keygen -Y verify`.
+async fn verify_signature(data: &[u8], sig: &[u8]) -> anyhow::Result<()> {
+    let dir = temporary::private_dir("self-update").context("creating self-update temp dir")?;
+    let verified = async {
+        let signers = dir.join("allowed_signers");
+        fs::write(&signers, allowed_signers())?;
+        let sig_file = dir.join("SHA256SUMS.sig");
+        fs::write(&sig_file, sig)?;
+
+        let mut verify = Command::new("ssh-keygen")
+            .args(["-Y", "verify", "-n", "file", "-I", RELEASE_SIGNER, "-f"])
+            .arg(&signers)
+            .arg("-s")
+            .arg(&sig_file)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context("ssh-keygen")?;
+        let mut stdin = verify.stdin.take().context("ssh-keygen stdin")?;
+        stdin.write_all(data).await?;
+        drop(stdin);
+        let out = verify.wait_with_output().await?;
+        ensure_success("ssh-keygen", &out)
+    }
+    .await;
+    _ = fs::remove_dir_all(&dir);
+    verified
+}
+
+/// `ssh-keygen -Y verify` allowed signers file content trusting only the [`RELEASE_PUBKEY`].
+fn allowed_signers() -> String {
+    format!("{RELEASE_SIGNER} namespaces=\"file\" {RELEASE_PUBKEY}\n")
+}
+
+/// Parse a sha256sum style "<hex>  <name>" checksum for `name`. A single hex value,
+/// without a name, is also accepted.
+fn parse_sha256(sums: &str, name: &str) -> Option<String> {
+    let is_sha = |s: &str| s.len() == 64 && s.bytes().all(|b| b.is_ascii_hexdigit());
+    sums.lines().find_map(|line| {
+        let mut parts = line.split_whitespace();
+        let sha = parts.next().filter(|s| is_sha(s))?;
+        match parts.next().map(|n| n.trim_start_matches('*')) {
+            None => Some(sha.to_ascii_lowercase()),
+            Some(n) if n == name => Some(sha.to_ascii_lowercase()),
+            Some(_) => None,
+        }
+    })
+}
+
+/// Verify the `download` sha256 & return the binary, extracting it if a .tar.zst.
+fn verify_and_extract(
+    download: &Path,
+    asset_name: &str,
+    expected: &str,
+) -> anyhow::Result<Vec<u8>> {
+    let data = fs::read(download).with_context(|| format!("reading {}", download.display()))?;
+    let sha = format!("{:x}", Sha256::digest(&data));
+    ensure!(
+        sha == expected,
+        "{asset_name} sha256 mismatch, expected {expected} got {sha}"
+    );
+    if !asset_name.ends_with(".tar.zst") {
+        return Ok(data);
+    }
+
+    let mut tar = tar::Archive::new(zstd::Decoder::new(&data[..])?);
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        let name = entry.path()?.file_name().map(|n| n.to_owned());
+        if name.is_some_and(|n| n == "ab-av1" || n == "ab-av1.exe") {
+            let mut binary = vec![];
+            entry.read_to_end(&mut binary)?;
+            return Ok(binary);
+        }
+    }
+    bail!("no ab-av1 binary in {asset_name}")
+}
+
+/// Replace the running `exe` with the new `binary`.
+fn replace_exe(exe: &Path, binary: &[u8]) -> anyhow::Result<()> {
+    let new = exe.with_extension("new");
+    fs::write(&new, binary).with_context(|| format!("writing {}", new.display()))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&new, fs::Permissions::from_mode(0o755))?;
+    }
+    // windows cannot overwrite a running exe, but can rename it
+    let old = exe.with_extension("old");
+    if cfg!(windows) {
+        _ = fs::remove_file(&old);
+        fs::rename(exe, &old).with_context(|| format!("renaming {}", exe.display()))?;
+    }
+    fs::rename(&new, exe).with_context(|| format!("replacing {}", exe.display()))?;
+    Ok(())
+}
+
+#[test]
+fn release_versions() {
+    assert!(is_newer("v0.10.0", "0.9.4"));
+    assert!(is_newer("v0.9.5", "0.9.4"));
+    assert!(!is_newer("v0.9.4", "0.9.4"));
+    assert!(!is_newer("v0.9.3-rc.1", "0.9.4"));
+    assert!(!is_newer("nightly", "0.9.4"));
+}
+
+#[test]
+fn fork_release_repo() {
+    // updates must come from this fork, not upstream ab-av1
+    assert_eq!(default_repo(), "mtvento/ab-av1-cuda");
+}
+
+#[test]
+fn release_assets() {
+    let assets: Vec<_> = [
+        "ab-av1-0.10.0-x86_64-unknown-linux-musl.tar.zst",
+        "ab-av1-0.10.0-x86_64-unknown-linux-musl.tar.zst.sha256",
+        "ab-av1.exe",
+        "SHA256SUMS",
+        "SHA256SUMS.sig",
+    ]
+    .into_iter()
+    .map(|name| Asset {
+        name: name.into(),
+        browser_download_url: format!("https://example.com/{name}"),
+    })
+    .collect();
+    let find = |os, arch| find_asset(&assets, os, arch).map(|a| a.name.as_str());
+    assert_eq!(
+        find("linux", "x86_64"),
+        Some("ab-av1-0.10.0-x86_64-unknown-linux-musl.tar.zst")
+    );
+    assert_eq!(find("windows", "x86_64"), Some("ab-av1.exe"));
+    assert_eq!(find("linux", "aarch64"), None);
+}
+
+#[test]
+fn sha256_sums() {
+    let sha = "a".repeat(64);
+    let sums = format!("{}  other.exe\n{sha} *ab-av1.exe\n", "b".repeat(64));
+    assert_eq!(parse_sha256(&sums, "ab-av1.exe"), Some(sha.clone()));
+    assert_eq!(parse_sha256(&sums, "missing"), None);
+    assert_eq!(parse_sha256(&format!("{sha}\n"), "ab-av1.exe"), Some(sha));
+    assert_eq!(parse_sha256("not a sha  ab-av1.exe", "ab-av1.exe"), None);
+}
+
+#[test]
+fn release_allowed_signers() {
+    assert_eq!(
+        allowed_signers(),
+        "ab-av1-release namespaces=\"file\" \
+         ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAILGg7F8NCYj2KrHN5z491TunnW/gXTSGLIe/UKvzPaqL\n"
+    );
+}
@@ -0,0 +1,365 @@
+use crate::{
+    command::{
+        PROGRESS_CHARS, args,
+        sample_encode::{self, ScoreKind, Work},
+    },
+    float::TerseF32,
+    stdin,
+};
+use anyhow::{Context, ensure};
+use clap::{ArgAction, Parser, ValueHint};
+use console::style;
+use indicatif::{HumanBytes, ProgressBar, ProgressStyle};
+use std::{fmt::Write, fs, path::PathBuf, pin::pin, sync::Arc, time::Duration};
+use tokio_stream::StreamExt;
+
+const BAR_LEN: u64 = 1024 * 1024 * 1024;
+
+/// Sample-encode at each crf of a range to output the quality vs size curve.
+///
+/// Outputs a line per crf:
+/// * Mean sample score
+/// * Predicted full encode size
+/// * Predicted encoded percent
+#[derive(Parser)]
+#[clap(verbatim_doc_comment)]
+#[group(skip)]
+pub struct Args {
+    #[clap(flatten)]
+    pub args: args::Encode,
+
+    /// Crf range START..END to sweep, e.g. `--crf 20..45`.
+    #[arg(long, value_parser = parse_crf_range)]
+    pub crf: CrfRange,
+
+    /// Crf increment between each sample-encode.
+    #[arg(long, default_value_t = 2.0)]
+    pub step: f32,
+
+    /// Also write the curve to this file. An ".svg" extension plots the curve,
+    /// otherwise writes a gnuplot data file.
+    #[arg(long, value_hint = ValueHint::FilePath)]
+    pub plot: Option<PathBuf>,
+
+    #[clap(flatten)]
+    pub sample: args::Sample,
+
+    /// Enable sample-encode caching.
+    #[arg(
+        long,
+        default_value_t = true,
+        env = "AB_AV1_CACHE",
+        action(ArgAction::Set)
+    )]
+    pub cache: bool,
+
+    #[clap(flatten)]
+    pub vmaf: args::Vmaf,
+
+    #[clap(flatten)]
+    pub score: args::ScoreArgs,
+
+    #[clap(flatten)]
+    pub xpsnr_opts: args::Xpsnr,
+
+    /// Calculate a XPSNR score instead of VMAF.
+    #[arg(long, conflicts_with = "metric_cmd")]
+    pub xpsnr: bool,
+
+    /// Calculate a PSNR score instead of VMAF. Works with ffmpeg builds lacking libvmaf.
+    #[arg(long, conflicts_with_all = ["xpsnr", "metric_cmd"])]
+    pub psnr: bool,
+
+    #[clap(flatten)]
+    pub metric: args::MetricCmd,
+}
+
+/// Inclusive crf range, see --crf.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CrfRange {
+    pub start: f32,
+    pub end: f32,
+}
+
+impl CrfRange {
+    /// Crfs from start to end, inclusive, every `step`.
+    fn crfs(self, step: f32) -> Vec<f32> {
+        let n = ((self.end - self.start) / step + 1e-3).floor() as usize;
+        // round off accumulated float error, e.g. 20.300001
+        (0..=n)
+            .map(|i| ((self.start + step * i as f32) * 1000.0).round() / 1000.0)
+            .collect()
+    }
+}
+
+fn parse_crf_range(s: &str) -> Result<CrfRange, String> {
+    let (start, end) = s
+        .split_once("..")
+        .ok_or_else(|| format!("invalid crf range `{s}`, expected START..END e.g. 20..45"))?;
+    let parse = |crf: &str| {
+        crf.trim()
+            .parse::<f32>()
+            .map_err(|_| format!("invalid crf `{crf}`"))
+    };
+    let range = CrfRange {
+        start: parse(start)?,
+        end: parse(end)?,
+    };
+    if range.start > range.end {
+        return Err(format!(
+            "invalid crf range `{s}`, START must not exceed END"
+        ));
+    }
+    Ok(range)
+}
+
+/// Sample-encode result at a crf.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Point {
+    crf: f32,
+    score: f32,
+    size: u64,
+    encode_percent: f64,
+}
+
+pub async fn sweep(
+    Args {
+        args,
+        crf,
+        step,
+        plot,
+        mut sample,
+        cache,
+        mut vmaf,
+        score,
+        xpsnr_opts,
+        xpsnr,
+        psnr,
+        metric,
+    }: Args,
+) -> anyhow::Result<()> {
+    ensure!(step > 0.0, "--step must be positive");
+    ensure!(
+        step.fract() == 0.0 || args.encoder.supports_decimal_crf(),
+        "--step below 1 is not supported by this encoder"
+    );
+    let crfs = crf.crfs(step);
+
+    let bar = ProgressBar::new(BAR_LEN).with_style(
+        ProgressStyle::default_bar()
+            .template("{spinner:.cyan.bold} {elapsed_precise:.bold} {prefix} {wide_bar:.cyan/blue} ({msg}eta {eta})")?
+            .progress_chars(PROGRESS_CHARS)
+    );
+    bar.enable_steady_tick(Duration::from_millis(100));
+
+    stdin::ensure_seekable(&args.input)?;
+    let probe = args.probe_input().await?;
+    ensure!(!probe.is_image, "sweep does not support images");
+    sample.set_extension_from_input(&args.input, &args.encoder, &probe);
+    if !xpsnr && !psnr && metric.metric_cmd.is_none() {
+        sample_encode::lengthen_subsampled(&mut sample, &mut vmaf, &probe, &bar);
+    }
+    let probe = Arc::new(probe);
+
+    let mut points = Vec::with_capacity(crfs.len());
+    let mut score_kind = ScoreKind::Vmaf;
+    for (idx, crf) in crfs.iter().copied().enumerate() {
+        let sample_args = sample_encode::Args {
+            args: args.clone(),
+            crf,
+            preset_sweep: None,
+            sample: sample.clone(),
+            frame_scores: false,
+            cache,
+            stdout_format: sample_encode::StdoutFormat::Json,
+            vmaf: vmaf.clone(),
+            score: score.clone(),
+            xpsnr,
+            psnr,
+            xpsnr_opts,
+            metric: metric.clone(),
+        };
+        let mut run = pin!(sample_encode::run(sample_args, probe.clone()));
+        let mut output = None;
+        while let Some(update) = run.next().await {
+            match update? {
+                sample_encode::Update::Status(status) => {
+                    let progress = (idx as f32 + status.progress) / crfs.len() as f32;
+                    bar.set_position((progress * BAR_LEN as f32) as _);
+                    bar.set_prefix(format!(
+                        "crf {} {}/{}",
+                        TerseF32(crf),
+                        status.sample,
+                        status.samples
+                    ));
+                    match status.work {
+                        Work::Encode if status.fps <= 0.0 => bar.set_message("encoding,  "),
+                        work if status.fps <= 0.0 => {
+                            bar.set_message(format!("{},       ", work.fps_label()))
+                        }
+                        work => {
+                            bar.set_message(format!("{} {} fps, ", work.fps_label(), status.fps))
+                        }
+                    }
+                }
+                sample_encode::Update::SampleResult { .. } => {}
+                sample_encode::Update::Done(out) => output = Some(out),
+            }
+        }
+        let output = output.context("no sample output?")?;
+        score_kind = output.score_kind;
+        let point = Point {
+            crf,
+            score: output.score,
+            size: output.prediction.size,
+            encode_percent: output.encode_percent,
+        };
+        bar.println(format!(
+            "{} {} {} {:.2} {} {}{:.0}%{}",
+            style("- crf").dim(),
+            TerseF32(crf),
+            style(score_kind).dim(),
+            point.score,
+            HumanBytes(point.size),
+            style("(").dim(),
+            point.encode_percent,
+            style(")").dim(),
+        ));
+        points.push(point);
+    }
+    bar.finish_and_clear();
+
+    print!("{}", data(&points, score_kind));
+    if let Some(plot) = plot {
+        let contents = match plot
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("svg"))
+        {
+            true => svg(&points, score_kind),
+            false => data(&points, score_kind),
+        };
+        fs::write(&plot, contents).with_context(|| format!("writing {}", plot.display()))?;
+    }
+    Ok(())
+}
+
+/// Whitespace separated columns, usable as a gnuplot data file.
+fn data(points: &[Point], score_kind: ScoreKind) -> String {
+    let mut out = format!("# crf {score_kind} size encode_percent\n");
+    for p in points {
+        _ = writeln!(
+            out,
+            "{} {:.2} {} {:.1}",
+            TerseF32(p.crf),
+            p.score,
+            p.size,
+            p.encode_percent
+        );
+    }
+    out
+}
+
+/// Plot score & encoded percent against crf.
+fn svg(points: &[Point], score_kind: ScoreKind) -> String {
+    const W: f32 = 640.0;
+    const H: f32 = 400.0;
+    const PAD: f32 = 48.0;
+
+    let (min_crf, max_crf) = bounds(points.iter().map(|p| p.crf));
+    let (min_score, max_score) = bounds(points.iter().map(|p| p.score));
+    let max_percent = bounds(points.iter().map(|p| p.encode_percent as f32)).1;
+    let x = |crf: f32| PAD + (crf - min_crf) / (max_crf - min_crf).max(1e-3) * (W - 2.0 * PAD);
+    let y =
+        |v: f32, min: f32, max: f32| H - PAD - (v - min) / (max - min).max(1e-3) * (H - 2.0 * PAD);
+    let polyline = |value: &dyn Fn(&Point) -> f32, min: f32, max: f32| {
+        let mut line = String::new();
+        for p in points {
+            _ = write!(line, "{:.1},{:.1} ", x(p.crf), y(value(p), min, max));
+        }
+        line.trim_end().to_owned()
+    };
+
+    let mut out = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{W}\" height=\"{H}\" font-family=\"sans-serif\" font-size=\"12\">\n"
+    );
+    _ = writeln!(
+        out,
+        "<rect width=\"{W}\" height=\"{H}\" fill=\"white\"/>\n\
+         <path d=\"M{PAD},{PAD} V{b} H{r}\" fill=\"none\" stroke=\"black\"/>",
+        b = H - PAD,
+        r = W - PAD,
+    );
+    _ = writeln!(
+        out,
+        "<polyline points=\"{}\" fill=\"none\" stroke=\"#1f77b4\" stroke-width=\"2\"/>",
+        polyline(&|p| p.score, min_score, max_score)
+    );
+    _ = writeln!(
+        out,
+        "<polyline points=\"{}\" fill=\"none\" stroke=\"#ff7f0e\" stroke-width=\"2\"/>",
+        polyline(&|p| p.encode_percent as f32, 0.0, max_percent)
+    );
+    _ = writeln!(
+        out,
+        "<text x=\"{PAD}\" y=\"{}\">crf {} .. {}</text>\n\
+         <text x=\"{PAD}\" y=\"20\" fill=\"#1f77b4\">{score_kind} {min_score:.2} .. {max_score:.2}</text>\n\
+         <text x=\"{}\" y=\"20\" fill=\"#ff7f0e\">encoded 0 .. {max_percent:.0}%</text>",
+        H - 16.0,
+        TerseF32(min_crf),
+        TerseF32(max_crf),
+        W / 2.0,
+    );
+    out.push_str("</svg>\n");
+    out
+}
+
+fn bounds(values: impl Iterator<Item = f32>) -> (f32, f32) {
+    values.fold((f32::MAX, f32::MIN), |(min, max), v| {
+        (min.min(v), max.max(v))
+    })
+}
+
+#[test]
+fn crf_range() {
+    let range = parse_crf_range("20..45").unwrap();
+    assert_eq!(
+        range,
+        CrfRange {
+            start: 20.0,
+            end: 45.0
+        }
+    );
+    assert_eq!(range.crfs(5.0), [20.0, 25.0, 30.0, 35.0, 40.0, 45.0]);
+    assert_eq!(range.crfs(10.0), [20.0, 30.0, 40.0]);
+    assert_eq!(
+        parse_crf_range("20..21").unwrap().crfs(0.25),
+        [20.0, 20.25, 20.5, 20.75, 21.0]
+    );
+    assert!(parse_crf_range("45..20").is_err());
+    assert!(parse_crf_range("20-45").is_err());
+}
+
+#[test]
+fn sweep_data() {
+    let points = [
+        Point {
+            crf: 20.0,
+            score: 97.5,
+            size: 2_000_000,
+            encode_percent: 40.0,
+        },
+        Point {
+            crf: 30.0,
+            score: 94.25,
+            size: 1_000_000,
+            encode_percent: 20.0,
+        },
+    ];
+    assert_eq!(
+        data(&points, ScoreKind::Vmaf),
+        "# crf VMAF size encode_percent\n20 97.50 2000000 40.0\n30 94.25 1000000 20.0\n"
+    );
+    let svg = svg(&points, ScoreKind::Vmaf);
+    assert!(svg.contains("points=\"48.0,48.0 592.0,352.0\""), "{svg}");
+    assert!(svg.ends_with("</svg>\n"));
+}
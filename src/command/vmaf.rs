@@ -1,19 +1,23 @@
-use crate::cudavmaf;
 use crate::{
     command::{
         PROGRESS_CHARS,
-        args::{self, PixelFormat},
+        args::{self, PixelFormat, VmafPool},
+        encode::script,
+        sample_encode::StdoutFormat,
     },
-    ffprobe,
+    console_ext::style,
+    ffmpeg,
+    ffprobe::{self, Ffprobe},
     log::ProgressLogger,
-    process::FfmpegOut,
-    vmaf::{self, VmafOut},
+    process::{FfmpegOut, job_env, program},
+    temporary::{self, TempKind},
+    vmaf::{self, VmafOut, cuda::Support},
 };
 use anyhow::Context;
-use clap::Parser;
+use clap::{Parser, ValueHint};
 use indicatif::{ProgressBar, ProgressStyle};
 use std::{
-    path::PathBuf,
+    path::{Path, PathBuf},
     pin::pin,
     time::{Duration, Instant},
 };
@@ -42,63 +46,246 @@ pub struct Args {
 
     #[clap(flatten)]
     pub score: args::ScoreArgs,
+
+    /// Write the libvmaf per-frame json log, including VMAF & feature scores
+    /// e.g. motion & adm, to this file. The lowest scoring frames are also printed.
+    #[arg(long, value_hint = ValueHint::FilePath)]
+    pub vmaf_export: Option<PathBuf>,
+
+    /// Number of lowest scoring frames to print with --vmaf-export or --save-comparisons.
+    #[arg(long, default_value_t = 5)]
+    pub worst_frames: usize,
+
+    /// Save side-by-side reference (left) vs distorted png comparisons of the
+    /// --worst-frames lowest scoring frames to this directory, to visually check
+    /// whether score dips matter.
+    #[arg(long, value_hint = ValueHint::DirPath)]
+    pub save_comparisons: Option<PathBuf>,
+
+    /// Stdout message format `human` or `json`.
+    #[arg(long, value_enum, default_value_t = StdoutFormat::Human)]
+    pub stdout_format: StdoutFormat,
+
+    /// Maximum reference vs distorted duration difference. Larger differences fail
+    /// before scoring, as misaligned frames would give invalid scores.
+    ///
+    /// Not checked when --reference-vfilter trims or retimes the reference.
+    #[arg(long, default_value = "500ms", value_parser = humantime::parse_duration)]
+    pub sync_tolerance: Duration,
 }
 
-pub async fn vmaf(
-    Args {
-        reference,
-        distorted,
-        vmaf,
-        score,
-    }: Args,
-) -> anyhow::Result<()> {
+pub async fn vmaf(mut args: Args) -> anyhow::Result<()> {
     let bar = ProgressBar::new(1).with_style(
         ProgressStyle::default_bar()
             .template("{spinner:.cyan.bold} {elapsed_precise:.bold} {wide_bar:.cyan/blue} ({msg}eta {eta})")?
             .progress_chars(PROGRESS_CHARS)
     );
     bar.enable_steady_tick(Duration::from_millis(100));
+    if job_env::dry_run() {
+        return print_dry_run(args, &bar);
+    }
+    let vmaf_export = args.vmaf_export.clone();
+    let worst_frames = args.worst_frames;
+    let stdout_format = args.stdout_format;
+    let save_comparisons = args.save_comparisons.clone();
+    let (reference, distorted) = (args.reference.clone(), args.distorted.clone());
+    let ref_vfilter = args.score.reference_vfilter.clone();
+    // per-frame scores are needed to find the lowest scoring frames
+    let frame_log = match (&vmaf_export, &save_comparisons) {
+        (None, Some(_)) => {
+            let mut log = args.distorted.as_os_str().to_owned();
+            log.push(".vmaf.json");
+            let log = PathBuf::from(log);
+            temporary::add(&log, TempKind::NotKeepable);
+            args.vmaf_export = Some(log.clone());
+            Some(log)
+        }
+        (export, _) => export.clone(),
+    };
+
+    let Scores {
+        vmaf,
+        psnr,
+        ssim,
+        models,
+        fps,
+    } = score(args, &bar).await?;
+
+    let mut worst = vec![];
+    if let Some(export) = &vmaf_export {
+        eprintln!("Wrote per-frame scores to {}", export.display());
+    }
+    if let Some(log) = &frame_log {
+        worst = vmaf::log_worst_frames(log, worst_frames)?;
+        for worst in &worst {
+            let time = fps
+                .filter(|fps| *fps > 0.0)
+                .map(|fps| format!(" {}", timestamp(worst.frame as f64 / fps)))
+                .unwrap_or_default();
+            eprintln!(
+                "{}",
+                style!("- frame {}{time} VMAF {:.2}", worst.frame, worst.vmaf).dim()
+            );
+        }
+    }
+    if let Some(dir) = &save_comparisons {
+        let fps = fps
+            .filter(|fps| *fps > 0.0)
+            .context("--save-comparisons requires a known distorted fps")?;
+        let resolution = ffprobe::probe(&distorted).resolution;
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("failed to create {}", dir.display()))?;
+        for worst in &worst {
+            // seek half a frame early so rounding can't skip past the frame
+            let time = Duration::from_secs_f64((worst.frame as f64 - 0.5).max(0.0) / fps);
+            let png = dir.join(format!("frame-{}-vmaf-{:.2}.png", worst.frame, worst.vmaf));
+            ffmpeg::save_comparison(
+                &reference,
+                &distorted,
+                time,
+                ref_vfilter.as_deref(),
+                resolution,
+                &png,
+            )
+            .await?;
+        }
+        eprintln!("Wrote {} comparisons to {}", worst.len(), dir.display());
+    }
+
+    match stdout_format {
+        StdoutFormat::Human => {
+            println!("{vmaf}");
+            if let Some(psnr) = psnr {
+                println!("PSNR {psnr}");
+            }
+            if let Some(ssim) = ssim {
+                println!("SSIM {ssim}");
+            }
+            for (model, score) in &models {
+                println!("VMAF {model} {score}");
+            }
+        }
+        StdoutFormat::Json => println!("{}", json(vmaf, psnr, ssim, &models, &worst)),
+    }
+    Ok(())
+}
+
+/// Json stdout of the scores & any --vmaf-export lowest scoring frames.
+fn json(
+    vmaf: f32,
+    psnr: Option<f32>,
+    ssim: Option<f32>,
+    models: &[(String, f32)],
+    worst: &[vmaf::FrameScore],
+) -> serde_json::Value {
+    let mut json = serde_json::json!({ "vmaf": vmaf });
+    if let Some(psnr) = psnr {
+        json["psnr"] = psnr.into();
+    }
+    if let Some(ssim) = ssim {
+        json["ssim"] = ssim.into();
+    }
+    if !models.is_empty() {
+        json["models"] = models
+            .iter()
+            .map(|(model, score)| (model.clone(), serde_json::Value::from(*score)))
+            .collect();
+    }
+    if !worst.is_empty() {
+        json["worst_frames"] = worst
+            .iter()
+            .map(|f| serde_json::json!({ "frame": f.frame, "vmaf": f.vmaf }))
+            .collect();
+    }
+    json
+}
+
+/// Full VMAF scores.
+#[derive(Debug, Clone)]
+pub struct Scores {
+    /// VMAF score, pooled using --vmaf-pool.
+    pub vmaf: f32,
+    pub psnr: Option<f32>,
+    pub ssim: Option<f32>,
+    /// Mean scores of additional --vmaf-model models.
+    pub models: Vec<(String, f32)>,
+    /// Input fps, if known.
+    pub fps: Option<f64>,
+}
+
+/// Calculate the VMAF score, updating the `bar` progress.
+pub async fn score(
+    Args {
+        reference,
+        distorted,
+        vmaf,
+        score,
+        vmaf_export,
+        worst_frames: _,
+        save_comparisons: _,
+        stdout_format: _,
+        sync_tolerance,
+    }: Args,
+    bar: &ProgressBar,
+) -> anyhow::Result<Scores> {
     bar.set_message("vmaf running, ");
 
     let dprobe = ffprobe::probe(&distorted);
     let rprobe = ffprobe::probe(&reference);
+    if !score.reference_vfilter.as_deref().is_some_and(retimes) {
+        vmaf::check_sync(&rprobe, &dprobe, sync_tolerance)?;
+    }
     let nframes = dprobe.nframes().or_else(|_| rprobe.nframes());
     let duration = dprobe.duration.as_ref().or(rprobe.duration.as_ref());
     if let Ok(nframes) = nframes {
         bar.set_length(nframes);
     }
 
-    let mut vmaf = if Path::new("vmaf_cuda").exists() {
-        pin!(cudavmaf::run_cuda(
-            &reference,
-            &distorted,
-            &vmaf.ffmpeg_lavfi(
-            dprobe.resolution,
-            PixelFormat::opt_max(dprobe.pixel_format(), rprobe.pixel_format()),
-            score.reference_vfilter.as_deref(),
-        ),
-            vmaf.fps()
-        )?)
-    } else {
-        pin!(vmaf::run(
-        &reference,
+    let vmaf_pool = vmaf.vmaf_pool;
+    let also_scores = vmaf.also_psnr || vmaf.also_ssim;
+    let model_names: Vec<_> = vmaf
+        .vmaf_models()
+        .iter()
+        .skip(1)
+        .map(|m| m.to_string())
+        .collect();
+    let VmafRun {
+        ffmpeg,
+        nvdec,
+        ref_nvdec,
+        lavfi,
+        fps,
+        frame_log,
+    } = VmafRun::new(
+        &dprobe,
+        &rprobe,
         &distorted,
-        &vmaf.ffmpeg_lavfi(
-            dprobe.resolution,
-            PixelFormat::opt_max(dprobe.pixel_format(), rprobe.pixel_format()),
-            score.reference_vfilter.as_deref(),
-        ),
-            vmaf.fps(),
-        )?);
-    };
+        &vmaf,
+        &score,
+        vmaf_export.clone(),
+        bar,
+    )?;
+    let mut vmaf = pin!(vmaf::run(
+        ffmpeg, nvdec, ref_nvdec, &reference, &distorted, &lavfi, fps,
+    )?);
 
-    let mut logger = ProgressLogger::new(module_path!(), Instant::now());
+    let mut logger = ProgressLogger::new("vmaf", Instant::now());
     let mut vmaf_score = None;
+    let (mut psnr, mut ssim) = (None, None);
+    let mut models = vec![];
     while let Some(vmaf) = vmaf.next().await {
         match vmaf {
             VmafOut::Done(score) => {
                 vmaf_score = Some(score);
-                break;
+                // the frame log, psnr, ssim & other model scores are written after the score
+                if frame_log.is_none() && !also_scores && model_names.is_empty() {
+                    break;
+                }
+            }
+            VmafOut::Psnr(score) => psnr = Some(score),
+            VmafOut::Ssim(score) => ssim = Some(score),
+            VmafOut::ModelScores(scores) => {
+                models = model_names.iter().cloned().zip(scores).collect();
             }
             VmafOut::Progress(FfmpegOut::Progress {
                 frame, fps, time, ..
@@ -113,12 +300,221 @@ pub async fn vmaf(
                     logger.update(*total, time, fps);
                 }
             }
-            VmafOut::Progress(FfmpegOut::StreamSizes { .. }) => {}
+            VmafOut::Progress(FfmpegOut::StreamSizes { .. } | FfmpegOut::EncoderStats(_)) => {}
             VmafOut::Err(e) => return Err(e),
         }
     }
     bar.finish();
 
-    println!("{}", vmaf_score.context("no vmaf score")?);
+    let vmaf = match &frame_log {
+        Some(log) => {
+            let stats = vmaf::frame_stats(log)?;
+            if vmaf_export.is_none() {
+                let _ = std::fs::remove_file(log);
+            }
+            stats.pooled(vmaf_pool)
+        }
+        None => vmaf_score.context("no vmaf score")?,
+    };
+    Ok(Scores {
+        vmaf,
+        psnr,
+        ssim,
+        models,
+        fps: dprobe.fps.as_ref().or(rprobe.fps.as_ref()).ok().copied(),
+    })
+}
+
+/// Print the ffmpeg libvmaf command without running it, see --dry-run.
+fn print_dry_run(
+    Args {
+        reference,
+        distorted,
+        vmaf,
+        score,
+        vmaf_export,
+        worst_frames: _,
+        save_comparisons: _,
+        stdout_format: _,
+        sync_tolerance: _,
+    }: Args,
+    bar: &ProgressBar,
+) -> anyhow::Result<()> {
+    let dprobe = ffprobe::probe(&distorted);
+    let rprobe = ffprobe::probe(&reference);
+    let run = VmafRun::new(
+        &dprobe,
+        &rprobe,
+        &distorted,
+        &vmaf,
+        &score,
+        vmaf_export,
+        bar,
+    )?;
+    bar.finish_and_clear();
+    let cmd = vmaf::command(
+        run.ffmpeg,
+        run.nvdec,
+        run.ref_nvdec,
+        &reference,
+        &distorted,
+        &run.lavfi,
+        run.fps,
+    );
+    script::print(&format!("VMAF {}", distorted.display()), cmd.as_std());
     Ok(())
 }
+
+/// [`vmaf::run`] args scoring a distorted file vs reference.
+struct VmafRun {
+    ffmpeg: &'static Path,
+    nvdec: bool,
+    ref_nvdec: bool,
+    lavfi: String,
+    fps: Option<f32>,
+    /// Per-frame json log written by libvmaf.
+    frame_log: Option<PathBuf>,
+}
+
+impl VmafRun {
+    fn new(
+        dprobe: &Ffprobe,
+        rprobe: &Ffprobe,
+        distorted: &Path,
+        vmaf: &args::Vmaf,
+        score: &args::ScoreArgs,
+        vmaf_export: Option<PathBuf>,
+        bar: &ProgressBar,
+    ) -> anyhow::Result<Self> {
+        // normalize mismatched fps, unless the --reference-vfilter already converts it
+        let user_ref_vfilter = score.reference_vfilter.as_deref();
+        let converts_fps = user_ref_vfilter.is_some_and(|vf| vf.contains("fps="));
+        let fps_vfilter = match (&rprobe.fps, &dprobe.fps) {
+            (Ok(r_fps), Ok(d_fps)) if !converts_fps => vmaf::fps_normalization(*r_fps, *d_fps)?,
+            _ => None,
+        };
+        if let (Some(fps_vfilter), Ok(r_fps)) = (&fps_vfilter, &rprobe.fps) {
+            bar.println(
+                style!(
+                    "Reference {r_fps:.3} fps differs from distorted, normalizing with {fps_vfilter}"
+                )
+                .dim()
+                .to_string(),
+            );
+        }
+        let ref_vfilter = match (user_ref_vfilter, &fps_vfilter) {
+            (Some(vf), Some(fps)) => Some(format!("{vf},{fps}")),
+            (vf, fps) => vf.map(str::to_owned).or_else(|| fps.clone()),
+        };
+        let ref_vfilter = ref_vfilter.as_deref();
+        // --vmaf-fps `-r` reinterprets timestamps, which would break fps normalization
+        let input_fps = match fps_vfilter {
+            Some(_) => None,
+            None => vmaf.fps(),
+        };
+
+        let cuda_ffmpeg = match vmaf::cuda::support() {
+            Support::Available(ffmpeg) => Some(ffmpeg.as_path()),
+            Support::Missing => None,
+            Support::Failed(err) => {
+                bar.println(
+                    style!("Warning: libvmaf_cuda unusable, falling back to CPU libvmaf: {err}")
+                        .yellow()
+                        .to_string(),
+                );
+                None
+            }
+        };
+        let ref_nvdec = cuda_ffmpeg
+            .is_none()
+            .then(|| vmaf.reference_nvdec(rprobe))
+            .flatten();
+        let mut lavfi = match cuda_ffmpeg {
+            Some(_) => {
+                bar.set_message("vmaf cuda running, ");
+                vmaf.ffmpeg_lavfi_cuda(dprobe.resolution, ref_vfilter)
+            }
+            None => vmaf.ffmpeg_lavfi(
+                dprobe.resolution,
+                PixelFormat::opt_max(dprobe.pixel_format(), rprobe.pixel_format()),
+                ref_vfilter,
+                ref_nvdec,
+            ),
+        };
+        let frame_log = match vmaf_export {
+            Some(export) => Some(export),
+            None if vmaf.vmaf_pool != VmafPool::Mean => {
+                let mut log = distorted.as_os_str().to_owned();
+                log.push(".vmaf.json");
+                let log = PathBuf::from(log);
+                temporary::add(&log, TempKind::NotKeepable);
+                Some(log)
+            }
+            None => None,
+        };
+        if let Some(log) = &frame_log {
+            lavfi.push_str(&vmaf::frame_log_opts(log));
+        }
+
+        Ok(Self {
+            ffmpeg: cuda_ffmpeg.unwrap_or(program::ffmpeg()),
+            nvdec: cuda_ffmpeg.is_some() && vmaf.cuda_nvdec(ref_vfilter),
+            ref_nvdec: ref_nvdec.is_some(),
+            lavfi,
+            fps: input_fps,
+            frame_log,
+        })
+    }
+}
+
+/// Returns true if the `vfilter` may change the duration, e.g. trimming.
+fn retimes(vfilter: &str) -> bool {
+    vfilter.split(',').any(|vf| {
+        let name = vf.trim().split(['=', '@']).next().unwrap_or_default();
+        matches!(
+            name,
+            "trim" | "select" | "setpts" | "tpad" | "loop" | "reverse" | "concat"
+        )
+    })
+}
+
+/// Format seconds as "HH:MM:SS.mmm".
+fn timestamp(secs: f64) -> String {
+    let ms = (secs * 1000.0).round() as u64;
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        ms / 3_600_000,
+        ms / 60_000 % 60,
+        ms / 1000 % 60,
+        ms % 1000
+    )
+}
+
+#[test]
+fn retiming_vfilters() {
+    assert!(retimes("scale=1280:-1,trim=start=2"));
+    assert!(retimes("select@s='gte(n,10)'"));
+    assert!(!retimes("scale=1280:-1,fps=24"));
+}
+
+#[test]
+fn frame_timestamp() {
+    assert_eq!(timestamp(83.5), "00:01:23.500");
+    assert_eq!(timestamp(3725.04), "01:02:05.040");
+}
+
+#[test]
+fn json_scores() {
+    assert_eq!(
+        json(95.5, None, None, &[], &[]).to_string(),
+        r#"{"vmaf":95.5}"#
+    );
+    let worst = [vmaf::FrameScore {
+        frame: 12,
+        vmaf: 80.25,
+    }];
+    assert_eq!(
+        json(95.5, Some(42.5), None, &[("1k".into(), 97.5)], &worst).to_string(),
+        r#"{"models":{"1k":97.5},"psnr":42.5,"vmaf":95.5,"worst_frames":[{"frame":12,"vmaf":80.25}]}"#
+    );
+}
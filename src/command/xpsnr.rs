@@ -1,8 +1,8 @@
 use crate::{
-    command::{PROGRESS_CHARS, args},
+    command::{PROGRESS_CHARS, args, encode::script, sample_encode::ScoreKind},
     ffprobe,
     log::ProgressLogger,
-    process::FfmpegOut,
+    process::{FfmpegOut, job_env},
     xpsnr::{self, XpsnrOut},
 };
 use anyhow::Context;
@@ -54,6 +54,14 @@ pub async fn xpsnr(
     bar.enable_steady_tick(Duration::from_millis(100));
     bar.set_message("xpsnr running, ");
 
+    let lavfi = lavfi(score.reference_vfilter.as_deref());
+    if job_env::dry_run() {
+        bar.finish_and_clear();
+        let cmd = xpsnr::command(&reference, &distorted, &lavfi, xpsnr.fps());
+        script::print(&format!("XPSNR {}", distorted.display()), cmd.as_std());
+        return Ok(());
+    }
+
     let dprobe = ffprobe::probe(&distorted);
     let rprobe = LazyLock::new(|| ffprobe::probe(&reference));
     let nframes = dprobe.nframes().or_else(|_| rprobe.nframes());
@@ -66,12 +74,13 @@ pub async fn xpsnr(
     }
 
     let mut xpsnr_out = pin!(xpsnr::run(
+        ScoreKind::Xpsnr,
         &reference,
         &distorted,
-        &lavfi(score.reference_vfilter.as_deref()),
+        &lavfi,
         xpsnr.fps(),
     )?);
-    let mut logger = ProgressLogger::new(module_path!(), Instant::now());
+    let mut logger = ProgressLogger::new("xpsnr", Instant::now());
     let mut score = None;
     while let Some(next) = xpsnr_out.next().await {
         match next {
@@ -92,7 +101,7 @@ pub async fn xpsnr(
                     logger.update(*total, time, fps);
                 }
             }
-            XpsnrOut::Progress(FfmpegOut::StreamSizes { .. }) => {}
+            XpsnrOut::Progress(FfmpegOut::StreamSizes { .. } | FfmpegOut::EncoderStats(_)) => {}
             XpsnrOut::Err(e) => return Err(e),
         }
     }
@@ -109,6 +118,14 @@ pub fn lavfi(ref_vfilter: Option<&str>) -> Cow<'static, str> {
     }
 }
 
+/// Returns ffmpeg `filter_complex` value for calculating PSNR, e.g. when libvmaf is missing.
+pub fn psnr_lavfi(ref_vfilter: Option<&str>) -> Cow<'static, str> {
+    match ref_vfilter {
+        None => "psnr".into(),
+        Some(vf) => format!("[0:v]{vf}[ref];[ref][1:v]psnr").into(),
+    }
+}
+
 #[test]
 fn test_lavfi_default() {
     assert_eq!(lavfi(None), "xpsnr=stats_file=-");
@@ -122,3 +139,12 @@ fn test_lavfi_ref_vfilter() {
          [ref][1:v]xpsnr=stats_file=-"
     );
 }
+
+#[test]
+fn test_psnr_lavfi_ref_vfilter() {
+    assert_eq!(psnr_lavfi(None), "psnr");
+    assert_eq!(
+        psnr_lavfi(Some("scale=1280:-1")),
+        "[0:v]scale=1280:-1[ref];[ref][1:v]psnr"
+    );
+}
@@ -0,0 +1,82 @@
+//! Upstream ab-av1 compatibility mode.
+use clap::{Parser, builder::FalseyValueParser};
+use std::{env, ffi::OsString, sync::OnceLock};
+
+/// Env var enabling --upstream-compat.
+pub const UPSTREAM_COMPAT_ENV: &str = "AB_AV1_UPSTREAM_COMPAT";
+
+/// Upstream default values of args with different defaults in this fork.
+const UPSTREAM_DEFAULTS: &[(&str, &str)] = &[("deinterlace", "off"), ("hw_fallback", "off")];
+
+static UPSTREAM: OnceLock<bool> = OnceLock::new();
+
+#[cfg(test)]
+thread_local! {
+    /// Upstream mode of the current test thread, see [`with_upstream`].
+    static TEST_UPSTREAM: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+}
+
+#[derive(Debug, Parser, Clone, Copy, Default)]
+#[group(skip)]
+pub struct Args {
+    /// Behave like upstream ab-av1, so scripts written for it work unmodified.
+    ///
+    /// The config file is ignored, CUDA VMAF is only used if VMAF_CUDA_PATH is set,
+    /// --deinterlace & --hw-fallback default to off, --sample-every defaults to a fixed 12m,
+    /// VMAF n_subsample isn't set automatically, outputs aren't tagged with ENCODER_SETTINGS,
+    /// a missing libvmaf is an error instead of falling back to PSNR & fork specific output,
+    /// e.g. prediction error bounds, is omitted.
+    /// Explicitly used fork args, e.g. --cuda-decoder, still work.
+    #[arg(
+        long,
+        global = true,
+        env = UPSTREAM_COMPAT_ENV,
+        value_parser = FalseyValueParser::new()
+    )]
+    pub upstream_compat: bool,
+}
+
+/// Set the mode. Should be called once before running commands.
+pub fn init(Args { upstream_compat }: Args) {
+    let _ = UPSTREAM.set(upstream_compat);
+}
+
+/// Returns true if behaving like upstream ab-av1.
+pub fn upstream() -> bool {
+    #[cfg(test)]
+    if TEST_UPSTREAM.get() {
+        return true;
+    }
+    UPSTREAM.get().copied().unwrap_or(false)
+}
+
+/// Run `f` behaving like upstream on the current thread, without setting the global mode.
+#[cfg(test)]
+pub fn with_upstream<T>(f: impl FnOnce() -> T) -> T {
+    TEST_UPSTREAM.set(true);
+    let out = f();
+    TEST_UPSTREAM.set(false);
+    out
+}
+
+/// Returns true if --upstream-compat is in the raw cli `args` or env,
+/// i.e. before parsing.
+pub fn requested(args: &[OsString]) -> bool {
+    let env_enabled = env::var(UPSTREAM_COMPAT_ENV).is_ok_and(|v| {
+        !matches!(
+            v.to_ascii_lowercase().as_str(),
+            "" | "0" | "n" | "no" | "f" | "false" | "off"
+        )
+    });
+    env_enabled || args.iter().any(|arg| arg == "--upstream-compat")
+}
+
+/// Use upstream default values for the `cmd` args.
+pub fn upstream_defaults(mut cmd: clap::Command) -> clap::Command {
+    for (id, default) in UPSTREAM_DEFAULTS {
+        if cmd.get_arguments().any(|arg| arg.get_id() == id) {
+            cmd = cmd.mut_arg(id, |arg| arg.default_value(default));
+        }
+    }
+    cmd
+}
@@ -0,0 +1,509 @@
+//! Persistent default args loaded from a toml config file.
+//!
+//! Config keys are long arg names, e.g. `preset = "6"`. Top level keys apply to all
+//! commands that accept them, tables named after a command, e.g. `[crf-search]`,
+//! apply only to that command & take precedence.
+//!
+//! Named profiles, e.g. `[profile.anime]`, are used with `--profile anime` and take
+//! precedence over other config values.
+use anyhow::{Context, bail};
+use clap::{Arg, ArgAction, Command};
+use std::{
+    env,
+    ffi::OsString,
+    fs,
+    path::{Path, PathBuf},
+};
+use tracing::debug;
+
+/// Env var that may be used instead of `--config`.
+pub const CONFIG_ENV: &str = "AB_AV1_CONFIG";
+
+/// Commands that don't use config values.
+const SKIP_COMMANDS: &[&str] = &["cache", "config", "print-completions", "self-update"];
+
+/// Default config file path, e.g. `~/.config/ab-av1/config.toml` on linux.
+pub fn default_path() -> Option<PathBuf> {
+    let mut path = dirs::config_dir()?;
+    path.push("ab-av1");
+    path.push("config.toml");
+    Some(path)
+}
+
+/// Returns the config path set by `--config`, `AB_AV1_CONFIG` or the default path.
+pub fn path(args: &[OsString]) -> Option<PathBuf> {
+    let mut args = args.iter().skip(1);
+    while let Some(arg) = args.next() {
+        let Some(arg) = arg.to_str() else { continue };
+        if arg == "--" {
+            break;
+        }
+        if let Some(path) = arg.strip_prefix("--config=") {
+            return Some(path.into());
+        }
+        if arg == "--config" {
+            return args.next().map(PathBuf::from);
+        }
+    }
+    env::var_os(CONFIG_ENV)
+        .map(PathBuf::from)
+        .or_else(default_path)
+}
+
+/// Load the config file, returns an empty table if the file doesn't exist.
+pub fn load(path: &Path) -> anyhow::Result<toml::Table> {
+    match fs::read_to_string(path) {
+        Ok(toml) => toml
+            .parse()
+            .with_context(|| format!("invalid config {}", path.display())),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(<_>::default()),
+        Err(err) => Err(err).with_context(|| format!("reading config {}", path.display())),
+    }
+}
+
+/// Write the config file, creating parent directories if necessary.
+pub fn save(path: &Path, config: &toml::Table) -> anyhow::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, toml::to_string(config)?)
+        .with_context(|| format!("writing config {}", path.display()))
+}
+
+/// Insert config values as args of the invoked subcommand.
+///
+/// Values are inserted directly after the subcommand name, before any user args.
+/// Config values are skipped for args set by the user, or conflicting with an arg
+/// set by the user, so user args replace config values rather than appending to them.
+/// Config values are also skipped for args with a set env var.
+pub fn apply(
+    cli: &Command,
+    config: &toml::Table,
+    mut args: Vec<OsString>,
+) -> anyhow::Result<Vec<OsString>> {
+    let Some((idx, cmd)) = find_subcommand(cli, &args) else {
+        return Ok(args);
+    };
+    if SKIP_COMMANDS.contains(&cmd.get_name()) {
+        return Ok(args);
+    }
+
+    // (long, key, value) with later layers overriding earlier ones
+    let mut values = vec![];
+    add_layer(&mut values, config);
+    if let Some(toml::Value::Table(cmd_config)) = config.get(cmd.get_name()) {
+        add_layer(&mut values, cmd_config);
+    }
+    // profile from args, otherwise a config default profile
+    let profile = find_profile(cmd, &args[idx + 1..]).or_else(|| {
+        let (.., value) = values.iter().find(|(long, ..)| long == "profile")?;
+        value.as_str().map(|p| p.to_owned())
+    });
+    if let Some(name) = profile {
+        let profile = config
+            .get("profile")
+            .and_then(|p| p.get(&name))
+            .and_then(|p| p.as_table())
+            .with_context(|| format!("profile `{name}` not found in config"))?;
+        add_layer(&mut values, profile);
+    }
+
+    // global args, e.g. --process-env, are not yet propagated to the subcommand
+    let cmd_args: Vec<_> = cmd
+        .get_arguments()
+        .chain(cli.get_arguments().filter(|a| a.is_global_set()))
+        .collect();
+    let user_args = user_args(&cmd_args, &args[1..]);
+    let conflicts = |arg: &Arg| match arg.is_global_set() {
+        true => cli.get_arg_conflicts_with(arg),
+        false => cmd.get_arg_conflicts_with(arg),
+    };
+
+    let mut config_args = vec![];
+    for (long, key, value) in values {
+        let Some(arg) = cmd_args.iter().find(|a| a.get_long() == Some(&long)) else {
+            debug!("config {key} not used by {}", cmd.get_name());
+            continue;
+        };
+        if arg.get_env().is_some_and(|e| env::var_os(e).is_some()) {
+            continue;
+        }
+        let overridden = user_args.iter().any(|user| {
+            user.get_id() == arg.get_id()
+                || conflicts(arg).iter().any(|a| a.get_id() == user.get_id())
+                || conflicts(user).iter().any(|a| a.get_id() == arg.get_id())
+        });
+        if overridden {
+            debug!("config {key} overridden by args");
+            continue;
+        }
+        match (arg.get_action(), value) {
+            (ArgAction::SetTrue, toml::Value::Boolean(true)) => {
+                config_args.push(format!("--{long}"));
+            }
+            (ArgAction::SetTrue, toml::Value::Boolean(false)) => {}
+            (ArgAction::SetTrue, _) => bail!("config {key} must be a boolean"),
+            (ArgAction::Count, toml::Value::Integer(n)) => {
+                config_args.extend((0..*n).map(|_| format!("--{long}")));
+            }
+            (ArgAction::Count, _) => bail!("config {key} must be an integer"),
+            (_, toml::Value::Array(values)) => {
+                for value in values {
+                    config_args.push(format!("--{long}={}", value_str(key, value)?));
+                }
+            }
+            (_, value) => config_args.push(format!("--{long}={}", value_str(key, value)?)),
+        }
+    }
+
+    args.splice(
+        idx + 1..idx + 1,
+        config_args.into_iter().map(OsString::from),
+    );
+    Ok(args)
+}
+
+/// Add config values overriding any existing values for the same arg.
+fn add_layer<'a>(values: &mut Vec<(String, &'a str, &'a toml::Value)>, layer: &'a toml::Table) {
+    for (key, value) in layer.iter().filter(|(_, v)| !v.is_table()) {
+        let long = key.replace('_', "-");
+        values.retain(|(l, ..)| *l != long);
+        values.push((long, key, value));
+    }
+}
+
+/// Returns the `--profile` arg value if used by the command.
+fn find_profile(cmd: &Command, args: &[OsString]) -> Option<String> {
+    if !cmd.get_arguments().any(|a| a.get_long() == Some("profile")) {
+        return None;
+    }
+    let mut args = args.iter().filter_map(|a| a.to_str());
+    let mut profile = None;
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            break;
+        }
+        if let Some(name) = arg.strip_prefix("--profile=") {
+            profile = Some(name.to_owned());
+        } else if arg == "--profile" {
+            profile = args.next().map(|n| n.to_owned());
+        }
+    }
+    profile
+}
+
+/// Returns the index & subcommand invoked by `args`.
+fn find_subcommand<'a>(cli: &'a Command, args: &[OsString]) -> Option<(usize, &'a Command)> {
+    let top_args: Vec<_> = cli.get_arguments().collect();
+    let mut idx = 1;
+    while let Some(arg) = args.get(idx).and_then(|a| a.to_str()) {
+        if arg == "--" {
+            return None;
+        }
+        if arg.starts_with('-') && arg != "-" {
+            // skip the value of top level args, e.g. `--max-gpu-jobs 2`, `-j 2`
+            let (_, value_next) = flag_args(&top_args, arg);
+            idx += if value_next { 2 } else { 1 };
+            continue;
+        }
+        return cli.find_subcommand(arg).map(|cmd| (idx, cmd));
+    }
+    None
+}
+
+/// Returns the `known` args set by `args`, e.g. `--preset 6`, `-vv`.
+fn user_args<'a>(known: &[&'a Arg], args: &[OsString]) -> Vec<&'a Arg> {
+    let mut user_args = vec![];
+    let mut args = args.iter().filter_map(|a| a.to_str());
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            break;
+        }
+        if !arg.starts_with('-') || arg == "-" {
+            continue;
+        }
+        let (flag_args, value_next) = flag_args(known, arg);
+        user_args.extend(flag_args);
+        if value_next {
+            args.next();
+        }
+    }
+    user_args
+}
+
+/// Returns the `known` args of a flag token, e.g. `--preset=6` or combined short flags
+/// `-vv`, & whether the next token is the flag's value.
+fn flag_args<'a>(known: &[&'a Arg], token: &str) -> (Vec<&'a Arg>, bool) {
+    if let Some(long) = token.strip_prefix("--") {
+        let (long, value) = match long.split_once('=') {
+            Some((long, _)) => (long, true),
+            None => (long, false),
+        };
+        return match known.iter().find(|a| a.get_long() == Some(long)) {
+            Some(arg) => (vec![*arg], !value && arg.get_action().takes_values()),
+            None => (vec![], false),
+        };
+    }
+
+    let mut args = vec![];
+    let shorts = token.trim_start_matches('-');
+    for (idx, short) in shorts.char_indices() {
+        let Some(arg) = known.iter().find(|a| a.get_short() == Some(short)) else {
+            break;
+        };
+        args.push(*arg);
+        if arg.get_action().takes_values() {
+            // value either attached, e.g. `-j2`, or the next token
+            let attached = idx + short.len_utf8() < shorts.len();
+            return (args, !attached);
+        }
+    }
+    (args, false)
+}
+
+fn value_str(key: &str, value: &toml::Value) -> anyhow::Result<String> {
+    Ok(match value {
+        toml::Value::String(s) => s.clone(),
+        toml::Value::Integer(n) => n.to_string(),
+        toml::Value::Float(n) => n.to_string(),
+        toml::Value::Boolean(b) => b.to_string(),
+        _ => bail!("config {key} has an unsupported value {value}"),
+    })
+}
+
+/// Returns true if `key` is a long arg of any command, or of `command` if specified.
+pub fn is_valid_key(cli: &Command, command: Option<&str>, key: &str) -> bool {
+    let long = key.replace('_', "-");
+    cli.get_subcommands()
+        .filter(|cmd| !SKIP_COMMANDS.contains(&cmd.get_name()))
+        .filter(|cmd| command.is_none_or(|c| cmd.get_name() == c))
+        .flat_map(|cmd| cmd.get_arguments())
+        .any(|a| a.get_long() == Some(&long))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn cli() -> Command {
+        Command::new("ab-av1")
+            .arg(Arg::new("max-gpu-jobs").long("max-gpu-jobs").global(true))
+            .subcommand(
+                Command::new("crf-search")
+                    .arg(Arg::new("preset").long("preset"))
+                    .arg(
+                        Arg::new("thorough")
+                            .long("thorough")
+                            .action(ArgAction::SetTrue),
+                    )
+                    .arg(Arg::new("svt").long("svt").action(ArgAction::Append))
+                    .arg(Arg::new("profile").long("profile")),
+            )
+            .subcommand(Command::new("encode").arg(Arg::new("preset").long("preset")))
+    }
+
+    fn args(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn apply_config() {
+        let config: toml::Table = r#"
+            preset = 6
+            min_vmaf = 95
+            svt = ["tune=0", "film-grain=8"]
+
+            [crf-search]
+            thorough = true
+            preset = "5"
+        "#
+        .parse()
+        .unwrap();
+
+        let applied = apply(
+            &cli(),
+            &config,
+            args(&[
+                "ab-av1",
+                "--max-gpu-jobs",
+                "2",
+                "crf-search",
+                "-i",
+                "vid.mkv",
+            ]),
+        )
+        .unwrap();
+        assert_eq!(
+            applied,
+            args(&[
+                "ab-av1",
+                "--max-gpu-jobs",
+                "2",
+                "crf-search",
+                "--svt=tune=0",
+                "--svt=film-grain=8",
+                "--preset=5",
+                "--thorough",
+                "-i",
+                "vid.mkv",
+            ])
+        );
+
+        let applied = apply(
+            &cli(),
+            &config,
+            args(&["ab-av1", "encode", "--preset", "8"]),
+        )
+        .unwrap();
+        assert_eq!(applied, args(&["ab-av1", "encode", "--preset", "8"]));
+    }
+
+    #[test]
+    fn apply_config_overridden_by_args() {
+        let cli = cli()
+            .arg(
+                Arg::new("verbose")
+                    .short('v')
+                    .action(ArgAction::Count)
+                    .global(true),
+            )
+            .arg(Arg::new("jobs").short('j').global(true))
+            .mut_subcommand("crf-search", |cmd| {
+                cmd.arg(
+                    Arg::new("min-vmaf")
+                        .long("min-vmaf")
+                        .conflicts_with("min-xpsnr"),
+                )
+                .arg(Arg::new("min-xpsnr").long("min-xpsnr"))
+            });
+        let config: toml::Table = r#"
+            min_vmaf = 95
+            svt = "tune=0"
+            thorough = true
+        "#
+        .parse()
+        .unwrap();
+
+        let applied = apply(
+            &cli,
+            &config,
+            args(&[
+                "ab-av1",
+                "-v",
+                "-j",
+                "2",
+                "crf-search",
+                "--svt",
+                "tune=1",
+                "--min-xpsnr=40",
+            ]),
+        )
+        .unwrap();
+        assert_eq!(
+            applied,
+            args(&[
+                "ab-av1",
+                "-v",
+                "-j",
+                "2",
+                "crf-search",
+                "--thorough",
+                "--svt",
+                "tune=1",
+                "--min-xpsnr=40",
+            ])
+        );
+
+        // attached short values & combined short flags
+        let applied = apply(
+            &cli,
+            &config,
+            args(&["ab-av1", "-vvj2", "crf-search", "--min-vmaf", "90"]),
+        )
+        .unwrap();
+        assert_eq!(
+            applied,
+            args(&[
+                "ab-av1",
+                "-vvj2",
+                "crf-search",
+                "--svt=tune=0",
+                "--thorough",
+                "--min-vmaf",
+                "90",
+            ])
+        );
+    }
+
+    #[test]
+    fn apply_global_config() {
+        let cli = cli().arg(
+            Arg::new("process-env")
+                .long("process-env")
+                .global(true)
+                .action(ArgAction::Append),
+        );
+        let config: toml::Table = r#"
+            [encode]
+            process-env = ["CUDA_VISIBLE_DEVICES=1"]
+        "#
+        .parse()
+        .unwrap();
+
+        let applied = apply(&cli, &config, args(&["ab-av1", "encode"])).unwrap();
+        assert_eq!(
+            applied,
+            args(&["ab-av1", "encode", "--process-env=CUDA_VISIBLE_DEVICES=1"])
+        );
+    }
+
+    #[test]
+    fn apply_profile() {
+        let config: toml::Table = r#"
+            preset = 6
+
+            [crf-search]
+            preset = "5"
+
+            [profile.anime]
+            preset = 4
+            svt = "tune=0"
+        "#
+        .parse()
+        .unwrap();
+
+        let applied = apply(
+            &cli(),
+            &config,
+            args(&["ab-av1", "crf-search", "--profile", "anime"]),
+        )
+        .unwrap();
+        assert_eq!(
+            applied,
+            args(&[
+                "ab-av1",
+                "crf-search",
+                "--preset=4",
+                "--svt=tune=0",
+                "--profile",
+                "anime",
+            ])
+        );
+
+        let err = apply(
+            &cli(),
+            &config,
+            args(&["ab-av1", "crf-search", "--profile=x"]),
+        );
+        assert!(err.is_err());
+    }
+
+    #[test]
+    fn valid_keys() {
+        let cli = cli();
+        assert!(is_valid_key(&cli, None, "preset"));
+        assert!(is_valid_key(&cli, Some("crf-search"), "thorough"));
+        assert!(!is_valid_key(&cli, Some("encode"), "thorough"));
+        assert!(!is_valid_key(&cli, None, "nope"));
+    }
+}
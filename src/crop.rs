@@ -0,0 +1,179 @@
+//! Black bar detection for `--cuda-filters autocrop`.
+use crate::{
+    error::Error,
+    process::{CommandExt, CommandJobEnv, program},
+};
+use anyhow::anyhow;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::{LazyLock, Mutex},
+    time::Duration,
+};
+use tokio::process::Command;
+use tracing::{debug, info};
+
+/// Number of evenly spaced windows analysed by [`detect`].
+const DETECT_WINDOWS: u32 = 5;
+/// Number of frames analysed in each window.
+const DETECT_FRAMES: u32 = 60;
+
+/// Detected crop filters per input & video stream.
+static DETECTED: LazyLock<Mutex<HashMap<(PathBuf, usize), String>>> = LazyLock::new(<_>::default);
+
+/// Detect black bars of `input` video stream `video_stream` using ffmpeg cropdetect,
+/// returning a crop filter, e.g. "crop=1920:800:0:140".
+///
+/// Analyses [`DETECT_FRAMES`] in each of [`DETECT_WINDOWS`] spread over the `duration`
+/// after `start`, using the least cropping window so dark scenes don't over crop.
+/// Few enough frames are analysed to decode in software, also working for --hw-fallback.
+/// Results are cached per input & stream, see [`detected`].
+pub async fn detect(
+    input: &Path,
+    video_stream: usize,
+    start: Duration,
+    duration: Option<Duration>,
+) -> anyhow::Result<String> {
+    if let Some(crop) = detected(input, video_stream) {
+        return Ok(crop);
+    }
+
+    let windows = match duration {
+        Some(_) => DETECT_WINDOWS,
+        None => 1,
+    };
+    let mut best: Option<Crop> = None;
+    for window in 0..windows {
+        let offset = duration
+            .unwrap_or_default()
+            .mul_f64((window as f64 + 0.5) / windows as f64);
+        let mut cmd = Command::new(program::ffmpeg());
+        cmd.job_env()
+            .arg2("-ss", (start + offset).as_secs_f32())
+            .arg2("-i", input)
+            .arg2("-map", format!("0:v:{video_stream}"))
+            .arg2("-vf", "cropdetect=24:16:0")
+            .arg2("-frames:v", DETECT_FRAMES)
+            .args(["-an", "-sn", "-dn", "-f", "null", "-"])
+            .stdin(Stdio::null())
+            .kill_on_drop(true);
+        debug!("cmd `{}`", cmd.to_cmd_str());
+        let output = cmd
+            .output()
+            .await
+            .map_err(Error::spawn("ffmpeg cropdetect"))?;
+
+        let crop = Crop::parse(&String::from_utf8_lossy(&output.stderr));
+        debug!("window {window} {crop:?}");
+        if let Some(crop) = crop.filter(|c| best.is_none_or(|b| c.area() > b.area())) {
+            best = Some(crop);
+        }
+    }
+
+    let crop = best.ok_or_else(|| anyhow!("No crop detected"))?.to_string();
+    info!("autocrop detected {crop} in {}", input.display());
+    DETECTED
+        .lock()
+        .unwrap()
+        .insert((input.into(), video_stream), crop.clone());
+    Ok(crop)
+}
+
+/// Returns the crop filter of `input` video stream `video_stream` if already [`detect`]ed.
+pub fn detected(input: &Path, video_stream: usize) -> Option<String> {
+    DETECTED
+        .lock()
+        .unwrap()
+        .get(&(input.into(), video_stream))
+        .cloned()
+}
+
+/// cropdetect "crop=W:H:X:Y" result.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Crop {
+    w: u32,
+    h: u32,
+    x: u32,
+    y: u32,
+}
+
+impl Crop {
+    /// Parse the last cropdetect result of ffmpeg `stderr`, which with reset=0
+    /// covers all analysed frames.
+    fn parse(stderr: &str) -> Option<Self> {
+        let crop = stderr
+            .lines()
+            .rev()
+            .filter(|l| l.contains("Parsed_cropdetect"))
+            .find_map(|l| l.split_whitespace().find_map(|s| s.strip_prefix("crop=")))?;
+        let mut dims = crop.split(':').map(|n| n.parse::<u32>().ok());
+        let mut next = || dims.next().flatten();
+        let crop = Self {
+            w: next()?,
+            h: next()?,
+            x: next()?,
+            y: next()?,
+        };
+        // cropdetect logs negative sizes before any non-black frame
+        (crop.w > 0 && crop.h > 0).then_some(crop)
+    }
+
+    fn area(self) -> u64 {
+        u64::from(self.w) * u64::from(self.h)
+    }
+}
+
+impl std::fmt::Display for Crop {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Self { w, h, x, y } = self;
+        write!(f, "crop={w}:{h}:{x}:{y}")
+    }
+}
+
+#[test]
+fn parse_cropdetect() {
+    const FFMPEG_OUT: &str = "\
+[Parsed_cropdetect_0 @ 0x5581c0] x1:0 x2:1919 y1:138 y2:941 w:1920 h:800 x:0 y:140 pts:1001 t:0.041708 limit:0.094118 crop=1920:800:0:140
+[Parsed_cropdetect_0 @ 0x5581c0] x1:0 x2:1919 y1:136 y2:943 w:1920 h:800 x:0 y:140 pts:2002 t:0.083417 limit:0.094118 crop=1920:800:0:140
+[Parsed_cropdetect_0 @ 0x5581c0] x1:0 x2:1919 y1:132 y2:947 w:1920 h:816 x:0 y:132 pts:3003 t:0.125125 limit:0.094118 crop=1920:816:0:132
+[out#0/null @ 0x5581d0] video:26kB audio:0kB subtitle:0kB other streams:0kB global headers:0kB muxing overhead: unknown
+frame=   60 fps=0.0 q=-0.0 Lsize=N/A time=00:00:02.46 bitrate=N/A speed=5.1x";
+    let crop = Crop::parse(FFMPEG_OUT).unwrap();
+    assert_eq!(
+        crop,
+        Crop {
+            w: 1920,
+            h: 816,
+            x: 0,
+            y: 132
+        }
+    );
+    assert_eq!(crop.to_string(), "crop=1920:816:0:132");
+
+    // all black frames
+    const BLACK: &str = "\
+[Parsed_cropdetect_0 @ 0x5581c0] x1:1919 x2:0 y1:1079 y2:0 w:-1904 h:-1072 x:1912 y:1076 pts:1001 t:0.041708 limit:0.094118 crop=-1904:-1072:1912:1076";
+    assert_eq!(Crop::parse(BLACK), None);
+    assert_eq!(Crop::parse("frame=   60 fps=0.0"), None);
+}
+
+#[test]
+fn detected_cache() {
+    let input = Path::new("/tmp/ab-av1-crop-cache-test.mkv");
+    assert_eq!(detected(input, 0), None);
+
+    DETECTED
+        .lock()
+        .unwrap()
+        .insert((input.into(), 0), "crop=1920:800:0:140".into());
+    assert_eq!(detected(input, 0).as_deref(), Some("crop=1920:800:0:140"));
+    assert_eq!(detected(input, 1), None);
+
+    // cached results are returned without running ffmpeg
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    let crop = rt.block_on(detect(input, 0, Duration::ZERO, None));
+    assert_eq!(crop.unwrap(), "crop=1920:800:0:140");
+}
@@ -0,0 +1,233 @@
+//! GPU capability probing, used to tune NVDEC surfaces, GPU job concurrency
+//! & NVENC sessions.
+use crate::process::{CommandJobEnv, job_env};
+use std::{
+    collections::HashMap,
+    env, io,
+    process::{Command, Stdio},
+    sync::{LazyLock, Mutex},
+};
+use tracing::debug;
+
+/// NVDEC surfaces, i.e. `-extra_hw_frames`, used if GPU VRAM is unknown.
+pub const DEFAULT_SURFACES: usize = 16;
+/// Supported range of --cuda-surfaces.
+pub const SURFACES_RANGE: std::ops::RangeInclusive<usize> = 8..=32;
+/// VRAM per concurrent GPU job used by auto --max-gpu-jobs.
+const VRAM_PER_GPU_JOB_MIB: u64 = 6 * 1024;
+/// Maximum auto --max-gpu-jobs, as NVDEC/NVENC engines saturate beyond this.
+const MAX_AUTO_GPU_JOBS: u16 = 3;
+
+/// nvidia-smi `--query-gpu` fields, see [`parse_gpus`].
+const QUERY_FIELDS: &str = "name,driver_version,memory.used,memory.total";
+/// NVENC & NVDEC fields, queried after [`QUERY_FIELDS`] if supported by the driver.
+const CODEC_QUERY_FIELDS: &str =
+    "encoder.stats.sessionCount,utilization.encoder,utilization.decoder";
+
+/// A GPU reported by nvidia-smi.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Gpu {
+    pub name: String,
+    pub driver: String,
+    pub vram_used_mib: u64,
+    pub vram_total_mib: u64,
+    /// NVENC sessions open, e.g. by other processes, `None` if unknown or the GPU
+    /// has no NVENC.
+    pub nvenc_sessions: Option<u32>,
+    /// NVENC utilization percent, `None` if unknown.
+    pub nvenc_percent: Option<u32>,
+    /// NVDEC utilization percent, `None` if unknown.
+    pub nvdec_percent: Option<u32>,
+}
+
+/// Query all GPUs, or just the nvidia-smi `device` index or UUID, using nvidia-smi.
+///
+/// Drivers that can't report NVENC/NVDEC stats are queried without them.
+pub fn query(device: Option<&str>) -> io::Result<Vec<Gpu>> {
+    match nvidia_smi(&format!("{QUERY_FIELDS},{CODEC_QUERY_FIELDS}"), device) {
+        Ok(csv) => Ok(parse_gpus(&csv)),
+        Err(err) => {
+            debug!("nvidia-smi nvenc/nvdec query failed: {err}");
+            Ok(parse_gpus(&nvidia_smi(QUERY_FIELDS, device)?))
+        }
+    }
+}
+
+fn nvidia_smi(fields: &str, device: Option<&str>) -> io::Result<String> {
+    let mut cmd = Command::new("nvidia-smi");
+    cmd.job_env()
+        .arg(format!("--query-gpu={fields}"))
+        .arg("--format=csv,noheader,nounits")
+        .stdin(Stdio::null());
+    if let Some(device) = device {
+        cmd.arg("-i").arg(device);
+    }
+    let out = cmd.output()?;
+    if !out.status.success() {
+        // nvidia-smi prints errors to stdout
+        return Err(io::Error::other(
+            String::from_utf8_lossy(&out.stdout).trim().to_owned(),
+        ));
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).into_owned())
+}
+
+/// Returns the GPU used by ffmpeg by default, i.e. cuda device 0, if detected.
+pub fn gpu() -> Option<Gpu> {
+    device(0)
+}
+
+/// Returns the GPU of ffmpeg cuda device `index`, e.g. `-gpu 1`, if detected.
+/// Results are cached per device.
+pub fn device(index: u8) -> Option<Gpu> {
+    static GPUS: LazyLock<Mutex<HashMap<String, Option<Gpu>>>> = LazyLock::new(<_>::default);
+
+    let id = device_id(index)?;
+    let mut gpus = GPUS.lock().unwrap_or_else(|e| e.into_inner());
+    gpus.entry(id)
+        .or_insert_with_key(|id| {
+            let gpu = query(Some(id))
+                .ok()
+                .and_then(|gpus| gpus.into_iter().next());
+            debug!("cuda caps device {id} {gpu:?}");
+            gpu
+        })
+        .clone()
+}
+
+/// Returns the nvidia-smi index or UUID of ffmpeg cuda device `index`, honouring
+/// CUDA_VISIBLE_DEVICES of spawned processes, e.g. set with --process-env.
+/// `None` if the device isn't visible.
+///
+/// Note: Visible indices are matched to nvidia-smi's PCI bus order, as used by cuda
+/// with `CUDA_DEVICE_ORDER=PCI_BUS_ID`.
+pub fn device_id(index: u8) -> Option<String> {
+    let visible = job_env::process_env("CUDA_VISIBLE_DEVICES")
+        .or_else(|| env::var("CUDA_VISIBLE_DEVICES").ok());
+    visible_device_id(visible.as_deref(), index)
+}
+
+fn visible_device_id(cuda_visible_devices: Option<&str>, index: u8) -> Option<String> {
+    match cuda_visible_devices {
+        None => Some(index.to_string()),
+        Some(visible) => visible
+            .split(',')
+            .map(str::trim)
+            .nth(index.into())
+            .filter(|id| !id.is_empty())
+            .map(Into::into),
+    }
+}
+
+/// Returns auto --max-gpu-jobs, one per [`VRAM_PER_GPU_JOB_MIB`] of the detected GPU,
+/// or `1` if unknown.
+pub fn auto_gpu_jobs() -> u16 {
+    gpu_jobs(gpu().map(|g| g.vram_total_mib))
+}
+
+/// Returns auto --cuda-surfaces for each of `gpu_jobs` sharing the detected GPU.
+pub fn auto_surfaces(gpu_jobs: u16) -> usize {
+    surfaces(gpu().map(|g| g.vram_total_mib), gpu_jobs)
+}
+
+fn gpu_jobs(vram_mib: Option<u64>) -> u16 {
+    vram_mib.map_or(1, |vram| {
+        (vram / VRAM_PER_GPU_JOB_MIB).clamp(1, MAX_AUTO_GPU_JOBS.into()) as _
+    })
+}
+
+/// More surfaces avoid decoder stalls with deep filter & encoder pipelines,
+/// fewer avoid NVDEC out of memory errors on small GPUs.
+fn surfaces(vram_mib: Option<u64>, gpu_jobs: u16) -> usize {
+    let Some(vram) = vram_mib else {
+        return DEFAULT_SURFACES;
+    };
+    match vram / u64::from(gpu_jobs.max(1)) {
+        0..3072 => 8,
+        3072..6144 => 16,
+        6144..12288 => 24,
+        _ => 32,
+    }
+}
+
+fn parse_gpus(csv: &str) -> Vec<Gpu> {
+    csv.lines()
+        .filter_map(|l| {
+            let mut fields = l.split(',').map(str::trim);
+            let name = fields.next().filter(|n| !n.is_empty())?;
+            let driver = fields.next()?;
+            let vram_used_mib = fields.next()?.parse().ok()?;
+            let vram_total_mib = fields.next()?.parse().ok()?;
+            // "[N/A]" or "[Not Supported]" if unknown
+            let mut codec_field = || fields.next().and_then(|f| f.parse().ok());
+            Some(Gpu {
+                name: name.into(),
+                driver: driver.into(),
+                vram_used_mib,
+                vram_total_mib,
+                nvenc_sessions: codec_field(),
+                nvenc_percent: codec_field(),
+                nvdec_percent: codec_field(),
+            })
+        })
+        .collect()
+}
+
+#[test]
+fn tune_from_vram() {
+    assert_eq!(
+        parse_gpus("NVIDIA GeForce GTX 1050 Ti, 535.183.01, 312, 4096\n"),
+        [Gpu {
+            name: "NVIDIA GeForce GTX 1050 Ti".into(),
+            driver: "535.183.01".into(),
+            vram_used_mib: 312,
+            vram_total_mib: 4096,
+            nvenc_sessions: None,
+            nvenc_percent: None,
+            nvdec_percent: None,
+        }]
+    );
+    assert_eq!(
+        parse_gpus("NVIDIA GeForce RTX 3060, 550.90.07, 2048, 12288, 2, 37, 18\n"),
+        [Gpu {
+            name: "NVIDIA GeForce RTX 3060".into(),
+            driver: "550.90.07".into(),
+            vram_used_mib: 2048,
+            vram_total_mib: 12288,
+            nvenc_sessions: Some(2),
+            nvenc_percent: Some(37),
+            nvdec_percent: Some(18),
+        }]
+    );
+    assert_eq!(
+        parse_gpus("Tesla T4, 535.183.01, 0, 15360, 0, [N/A], [N/A]\n")[0].nvdec_percent,
+        None
+    );
+    assert!(parse_gpus("No devices were found").is_empty());
+
+    assert_eq!(gpu_jobs(None), 1);
+    assert_eq!(gpu_jobs(Some(4096)), 1);
+    assert_eq!(gpu_jobs(Some(16384)), 2);
+    assert_eq!(gpu_jobs(Some(81920)), MAX_AUTO_GPU_JOBS);
+
+    assert_eq!(surfaces(None, 1), DEFAULT_SURFACES);
+    assert_eq!(surfaces(Some(2048), 1), 8);
+    assert_eq!(surfaces(Some(4096), 1), 16);
+    assert_eq!(surfaces(Some(24564), 1), 32);
+    assert_eq!(surfaces(Some(24564), 3), 24);
+    assert!(SURFACES_RANGE.contains(&surfaces(Some(1024), 4)));
+}
+
+#[test]
+fn cuda_visible_device_ids() {
+    assert_eq!(visible_device_id(None, 0).as_deref(), Some("0"));
+    assert_eq!(visible_device_id(None, 2).as_deref(), Some("2"));
+    assert_eq!(visible_device_id(Some("1"), 0).as_deref(), Some("1"));
+    assert_eq!(visible_device_id(Some("1"), 1), None);
+    assert_eq!(visible_device_id(Some("2, 0"), 1).as_deref(), Some("0"));
+    assert_eq!(
+        visible_device_id(Some("GPU-8e5c1b2a-0000-4f00-9000-1234567890ab"), 0).as_deref(),
+        Some("GPU-8e5c1b2a-0000-4f00-9000-1234567890ab")
+    );
+    assert_eq!(visible_device_id(Some(""), 0), None);
+}
@@ -0,0 +1,185 @@
+//! NVML GPU telemetry shown during encodes, requires the `gpu-monitor` feature.
+use indicatif::HumanBytes;
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+/// Minimum time between NVML samples.
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A GPU telemetry reading.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sample {
+    /// NVENC utilization percent.
+    pub enc_percent: u32,
+    /// NVDEC utilization percent.
+    pub dec_percent: u32,
+    pub vram_used: u64,
+    pub vram_total: u64,
+    /// GPU core temperature in °C.
+    pub temp_c: u32,
+    pub power_mw: u32,
+}
+
+impl fmt::Display for Sample {
+    /// Progress bar message, e.g. "enc 63% dec 41% 1.9/4.0 GiB 64°C 71W, ".
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "enc {}% dec {}% {:.1}/{:.1} GiB {}°C {}W, ",
+            self.enc_percent,
+            self.dec_percent,
+            gib(self.vram_used),
+            gib(self.vram_total),
+            self.temp_c,
+            self.power_mw / 1000,
+        )
+    }
+}
+
+/// Aggregate of all samples of an encode.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Summary {
+    samples: u32,
+    enc_percent_sum: u64,
+    dec_percent_sum: u64,
+    power_mw_sum: u64,
+    peak_vram: u64,
+    peak_temp_c: u32,
+}
+
+impl Summary {
+    fn add(&mut self, sample: Sample) {
+        self.samples += 1;
+        self.enc_percent_sum += u64::from(sample.enc_percent);
+        self.dec_percent_sum += u64::from(sample.dec_percent);
+        self.power_mw_sum += u64::from(sample.power_mw);
+        self.peak_vram = self.peak_vram.max(sample.vram_used);
+        self.peak_temp_c = self.peak_temp_c.max(sample.temp_c);
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let n = u64::from(self.samples.max(1));
+        write!(
+            f,
+            "NVENC {}%, NVDEC {}%, {}W avg, peak {} VRAM, peak {}°C",
+            self.enc_percent_sum / n,
+            self.dec_percent_sum / n,
+            self.power_mw_sum / n / 1000,
+            HumanBytes(self.peak_vram),
+            self.peak_temp_c,
+        )
+    }
+}
+
+/// Samples the GPU used by ffmpeg by default, honouring CUDA_VISIBLE_DEVICES,
+/// at most once per [`SAMPLE_INTERVAL`].
+pub struct Monitor {
+    #[cfg(feature = "gpu-monitor")]
+    nvml: nvml_wrapper::Nvml,
+    last: Option<(Instant, Option<Sample>)>,
+    summary: Summary,
+}
+
+impl Monitor {
+    /// Returns a monitor if built with the `gpu-monitor` feature & NVML is available.
+    pub fn start() -> Option<Self> {
+        #[cfg(feature = "gpu-monitor")]
+        match nvml_wrapper::Nvml::init() {
+            Ok(nvml) => {
+                return Some(Self {
+                    nvml,
+                    last: None,
+                    summary: <_>::default(),
+                });
+            }
+            Err(err) => tracing::debug!("nvml unavailable: {err}"),
+        }
+        None
+    }
+
+    /// Returns the latest sample, reading a new one if [`SAMPLE_INTERVAL`] has elapsed.
+    pub fn sample(&mut self) -> Option<Sample> {
+        if let Some((at, sample)) = self.last
+            && at.elapsed() < SAMPLE_INTERVAL
+        {
+            return sample;
+        }
+        let sample = self.read();
+        if let Some(sample) = sample {
+            self.summary.add(sample);
+        }
+        self.last = Some((Instant::now(), sample));
+        sample
+    }
+
+    /// Returns the summary of all samples, if any.
+    pub fn summary(&self) -> Option<Summary> {
+        Some(self.summary).filter(|s| s.samples > 0)
+    }
+
+    #[cfg(feature = "gpu-monitor")]
+    fn read(&self) -> Option<Sample> {
+        use nvml_wrapper::enum_wrappers::device::TemperatureSensor;
+
+        let id = super::caps::device_id(0)?;
+        let read = || -> Result<Sample, nvml_wrapper::error::NvmlError> {
+            let device = match id.parse() {
+                Ok(index) => self.nvml.device_by_index(index)?,
+                Err(_) => self.nvml.device_by_uuid(id.as_str())?,
+            };
+            let memory = device.memory_info()?;
+            Ok(Sample {
+                enc_percent: device.encoder_utilization()?.utilization,
+                dec_percent: device.decoder_utilization()?.utilization,
+                vram_used: memory.used,
+                vram_total: memory.total,
+                temp_c: device.temperature(TemperatureSensor::Gpu)?,
+                power_mw: device.power_usage()?,
+            })
+        };
+        read()
+            .inspect_err(|err| tracing::debug!("nvml sample failed: {err}"))
+            .ok()
+    }
+
+    #[cfg(not(feature = "gpu-monitor"))]
+    fn read(&self) -> Option<Sample> {
+        None
+    }
+}
+
+fn gib(bytes: u64) -> f64 {
+    bytes as f64 / (1024 * 1024 * 1024) as f64
+}
+
+#[test]
+fn sample_summary() {
+    let sample = Sample {
+        enc_percent: 60,
+        dec_percent: 40,
+        vram_used: 2 * 1024 * 1024 * 1024,
+        vram_total: 4 * 1024 * 1024 * 1024,
+        temp_c: 64,
+        power_mw: 71_500,
+    };
+    assert_eq!(sample.to_string(), "enc 60% dec 40% 2.0/4.0 GiB 64°C 71W, ");
+
+    let mut summary = Summary::default();
+    summary.add(sample);
+    summary.add(Sample {
+        enc_percent: 80,
+        dec_percent: 20,
+        vram_used: 1024 * 1024 * 1024,
+        temp_c: 70,
+        power_mw: 80_500,
+        ..sample
+    });
+    assert_eq!(
+        summary.to_string(),
+        "NVENC 70%, NVDEC 30%, 76W avg, peak 2.00 GiB VRAM, peak 70°C"
+    );
+}
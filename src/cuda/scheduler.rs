@@ -0,0 +1,78 @@
+//! NVENC session limits of consumer GPUs.
+//!
+//! GeForce drivers limit concurrent NVENC sessions per system, failing further
+//! sessions with e.g. "OpenEncodeSessionEx failed: out of memory". NVENC jobs are
+//! queued within the detected limit, less sessions already open by other processes,
+//! in addition to --max-gpu-jobs.
+use super::caps::{self, Gpu};
+use std::sync::LazyLock;
+use tokio::sync::{Semaphore, SemaphorePermit};
+use tracing::debug;
+
+/// NVENC session semaphore, `None` if sessions are unlimited or unknown.
+static SESSIONS: LazyLock<Option<Semaphore>> = LazyLock::new(|| {
+    let gpu = caps::gpu()?;
+    // sessions already open, e.g. by other processes, count towards the limit
+    let open = gpu
+        .nvenc_sessions
+        .map_or(0, |n| u16::try_from(n).unwrap_or(u16::MAX));
+    let limit = session_limit(&gpu).map(|n| n.saturating_sub(open).max(1));
+    debug!(
+        "nvenc session limit {limit:?}, {:?} open",
+        gpu.nvenc_sessions
+    );
+    limit.map(|n| Semaphore::new(n.into()))
+});
+
+/// Wait for a free NVENC session. Returns `None` if sessions are not limited.
+pub async fn acquire_nvenc() -> Option<SemaphorePermit<'static>> {
+    let sessions = SESSIONS.as_ref()?;
+    Some(sessions.acquire().await.expect("semaphore closed"))
+}
+
+/// Returns the concurrent NVENC session limit of the `gpu` driver,
+/// `None` if unlimited, i.e. professional & data center GPUs.
+pub fn session_limit(gpu: &Gpu) -> Option<u16> {
+    let consumer = gpu.name.contains("GeForce") || gpu.name.contains("TITAN");
+    if !consumer {
+        return None;
+    }
+    let driver_major: u32 = gpu.driver.split('.').next()?.parse().ok()?;
+    Some(match driver_major {
+        ..450 => 2,
+        450..530 => 3,
+        530..551 => 5,
+        _ => 8,
+    })
+}
+
+#[test]
+fn nvenc_session_limits() {
+    let gpu = |name: &str, driver: &str| Gpu {
+        name: name.into(),
+        driver: driver.into(),
+        vram_used_mib: 0,
+        vram_total_mib: 4096,
+        nvenc_sessions: None,
+        nvenc_percent: None,
+        nvdec_percent: None,
+    };
+    assert_eq!(
+        session_limit(&gpu("NVIDIA GeForce GTX 1050 Ti", "440.100")),
+        Some(2)
+    );
+    assert_eq!(
+        session_limit(&gpu("NVIDIA GeForce GTX 1050 Ti", "525.147.05")),
+        Some(3)
+    );
+    assert_eq!(
+        session_limit(&gpu("NVIDIA GeForce RTX 3060", "535.183.01")),
+        Some(5)
+    );
+    assert_eq!(
+        session_limit(&gpu("NVIDIA GeForce RTX 4070", "560.35.03")),
+        Some(8)
+    );
+    assert_eq!(session_limit(&gpu("NVIDIA RTX A4000", "535.183.01")), None);
+    assert_eq!(session_limit(&gpu("Tesla T4", "470.57.02")), None);
+}
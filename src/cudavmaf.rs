@@ -1,43 +0,0 @@
-// src/vmaf.rs
-use anyhow::{Context, Result};
-use std::{
-    path::Path,
-    process::{Command, Stdio},
-};
-
-pub struct VmafResult {
-    pub vmaf_score: f32,
-    pub psnr: f32,
-    pub ssim: f32,
-}
-
-pub fn run_vmaf(
-    reference: &Path,
-    distorted: &Path,
-    model: &Path,
-    cuda: bool,
-    surfaces: usize,
-) -> Result<VmafResult> {
-    let mut cmd = Command::new("vmaf");
-    
-    if cuda {
-        cmd.arg("--cuda")
-           .arg("--surfaces").arg(surfaces.to_string());
-    }
-
-    let output = cmd
-        .arg("--reference").arg(reference)
-        .arg("--distorted").arg(distorted)
-        .arg("--model").arg(model)
-        .arg("--json")
-        .stdout(Stdio::piped())
-        .output()
-        .context("Failed to execute VMAF")?;
-
-    parse_vmaf_output(&output.stdout)
-}
-
-fn parse_vmaf_output(output: &[u8]) -> Result<VmafResult> {
-    // Implement JSON parsing logic here
-    unimplemented!()
-}
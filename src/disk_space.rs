@@ -0,0 +1,115 @@
+//! Disk space checks, accounting for temp files & outputs sharing a filesystem.
+use anyhow::bail;
+use indicatif::HumanBytes;
+use std::path::{Path, PathBuf};
+use tracing::debug;
+
+/// Extra space required on top of predictions, as they're only estimates.
+const MARGIN_PERCENT: u64 = 10;
+/// Minimum extra space required, e.g. for container overhead & audio.
+const MIN_MARGIN: u64 = 64 * 1024 * 1024;
+
+/// Ensure there is enough available disk space to write each `(dir, bytes)`.
+///
+/// Bytes written to directories on the same filesystem are summed, e.g. temp
+/// files & the final output, so their combined peak usage must fit.
+/// Filesystems whose available space cannot be determined are not checked.
+pub fn ensure(needs: &[(&Path, u64)]) -> anyhow::Result<()> {
+    let needs = needs
+        .iter()
+        .filter(|(_, bytes)| *bytes > 0)
+        .filter_map(|(dir, bytes)| {
+            let dir = existing_dir(dir)?;
+            Some((filesystem_id(&dir)?, dir, *bytes))
+        });
+
+    for (dir, needed) in group_by_filesystem(needs) {
+        let Ok(available) = fs4::available_space(&dir) else {
+            continue;
+        };
+        debug!(
+            "{}: {} needed, {} available",
+            dir.display(),
+            HumanBytes(needed),
+            HumanBytes(available)
+        );
+        if !has_space(needed, available) {
+            bail!(
+                "not enough disk space in {}, ~{} needed for temp files & output but {} available. \
+                 Free up space or use --temp-dir on another filesystem",
+                dir.display(),
+                HumanBytes(with_margin(needed)),
+                HumanBytes(available),
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Returns true if `needed` bytes, plus a margin, fit in `available` bytes.
+fn has_space(needed: u64, available: u64) -> bool {
+    with_margin(needed) <= available
+}
+
+fn with_margin(bytes: u64) -> u64 {
+    bytes + (bytes * MARGIN_PERCENT / 100).max(MIN_MARGIN)
+}
+
+/// Sum bytes per filesystem, using the first dir of each filesystem.
+fn group_by_filesystem<Id: PartialEq>(
+    needs: impl Iterator<Item = (Id, PathBuf, u64)>,
+) -> Vec<(PathBuf, u64)> {
+    let mut groups: Vec<(Id, PathBuf, u64)> = vec![];
+    for (id, dir, bytes) in needs {
+        match groups.iter_mut().find(|(gid, ..)| *gid == id) {
+            Some((_, _, total)) => *total += bytes,
+            None => groups.push((id, dir, bytes)),
+        }
+    }
+    groups.into_iter().map(|(_, dir, b)| (dir, b)).collect()
+}
+
+/// Returns `dir`, or the working dir if empty, or its closest existing ancestor
+/// as output dirs may not yet exist.
+fn existing_dir(dir: &Path) -> Option<PathBuf> {
+    let dir = match dir.as_os_str().is_empty() {
+        true => std::env::current_dir().ok()?,
+        false => std::path::absolute(dir).ok()?,
+    };
+    dir.ancestors().find(|d| d.is_dir()).map(Path::to_path_buf)
+}
+
+#[cfg(unix)]
+fn filesystem_id(dir: &Path) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(dir.metadata().ok()?.dev())
+}
+
+/// The volume prefix, e.g. "C:".
+#[cfg(not(unix))]
+fn filesystem_id(dir: &Path) -> Option<PathBuf> {
+    let dir = dir.canonicalize().ok()?;
+    Some(dir.components().next()?.as_os_str().into())
+}
+
+#[test]
+fn shared_filesystem_needs_summed() {
+    let needs = [
+        (1, PathBuf::from("/tmp"), 100),
+        (2, PathBuf::from("/mnt/out"), 50),
+        (1, PathBuf::from("/tmp/out"), 200),
+    ];
+    assert_eq!(
+        group_by_filesystem(needs.into_iter()),
+        vec![
+            (PathBuf::from("/tmp"), 300),
+            (PathBuf::from("/mnt/out"), 50)
+        ]
+    );
+
+    assert!(has_space(0, MIN_MARGIN));
+    assert!(!has_space(1, MIN_MARGIN));
+    let gib = 1024 * 1024 * 1024;
+    assert!(has_space(10 * gib, 11 * gib));
+    assert!(!has_space(10 * gib, 10 * gib + MIN_MARGIN));
+}
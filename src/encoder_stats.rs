@@ -0,0 +1,189 @@
+//! Parsing of encoder summary stats, e.g. average QP per frame type, from ffmpeg stderr.
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Encoder summary of an encode.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EncoderStats {
+    /// Average QP of all frames.
+    pub avg_qp: f32,
+    /// Stats of each frame type, e.g. I, P & B, if reported.
+    #[serde(default)]
+    pub frame_types: Vec<FrameTypeStats>,
+}
+
+/// Encoder summary of a frame type.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FrameTypeStats {
+    /// Frame type, e.g. "I".
+    pub kind: String,
+    pub frames: u64,
+    pub avg_qp: f32,
+    /// Percentage of all output bits allocated to frames of this type.
+    pub bits_percent: f32,
+}
+
+impl EncoderStats {
+    /// Parse stats from encoder summary lines in ffmpeg stderr.
+    ///
+    /// * libx264 & libx265 per frame type lines, e.g.
+    ///   `x265 [info]: frame I:      2, Avg QP:23.59  kb/s: 10297.27`.
+    /// * libsvtav1 `stat-report=1` summary table, which has an overall "Average QP".
+    pub fn parse(stderr: &str) -> Option<Self> {
+        parse_frame_types(stderr).or_else(|| parse_svt_summary(stderr))
+    }
+}
+
+impl fmt::Display for EncoderStats {
+    /// e.g. "avg QP 29.53, I 2 frames QP 23.59 8% bits, P 60 frames QP 25.58 61% bits".
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "avg QP {:.2}", self.avg_qp)?;
+        for t in &self.frame_types {
+            write!(
+                f,
+                ", {} {} frames QP {:.2} {:.0}% bits",
+                t.kind, t.frames, t.avg_qp, t.bits_percent
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Parse libx264/libx265 "frame X:" lines. Per type sizes are per frame averages,
+/// "size:" bytes for x264 & "kb/s:" for x265, so bits are proportional to `frames * size`.
+fn parse_frame_types(stderr: &str) -> Option<EncoderStats> {
+    let mut types: Vec<(FrameTypeStats, f64)> = vec![];
+    for line in stderr.lines() {
+        let Some(stats) = line.find("frame ").map(|idx| &line[idx + "frame ".len()..]) else {
+            continue;
+        };
+        let Some((kind, rest)) = stats.split_once(':') else {
+            continue;
+        };
+        if !matches!(kind, "I" | "P" | "B" | "b") {
+            continue;
+        }
+        let (Some(frames), Some(avg_qp)) = (
+            value(rest, "").and_then(|v| v.parse().ok()),
+            value(rest, "Avg QP:").and_then(|v| v.parse().ok()),
+        ) else {
+            continue;
+        };
+        let size: f64 = value(rest, "kb/s:")
+            .or_else(|| value(rest, "size:"))
+            .and_then(|v| v.parse().ok())
+            .unwrap_or_default();
+        let stats = FrameTypeStats {
+            kind: kind.into(),
+            frames,
+            avg_qp,
+            bits_percent: 0.0,
+        };
+        // replace earlier stats of the same type, e.g. from a previous pass
+        types.retain(|(t, _)| t.kind != kind);
+        types.push((stats, frames as f64 * size));
+    }
+
+    let frames: u64 = types.iter().map(|(t, _)| t.frames).sum();
+    if frames == 0 {
+        return None;
+    }
+    let bits: f64 = types.iter().map(|(_, bits)| bits).sum();
+    let avg_qp = types
+        .iter()
+        .map(|(t, _)| t.avg_qp as f64 * t.frames as f64)
+        .sum::<f64>()
+        / frames as f64;
+    let frame_types = types
+        .into_iter()
+        .map(|(mut t, t_bits)| {
+            if bits > 0.0 {
+                t.bits_percent = (100.0 * t_bits / bits) as f32;
+            }
+            t
+        })
+        .collect();
+    Some(EncoderStats {
+        avg_qp: avg_qp as f32,
+        frame_types,
+    })
+}
+
+/// Parse the libsvtav1 `stat-report=1` tab separated summary table header & values.
+fn parse_svt_summary(stderr: &str) -> Option<EncoderStats> {
+    let mut lines = stderr.lines().skip_while(|l| !l.starts_with("SUMMARY"));
+    let header = lines.find(|l| l.contains("Average QP"))?;
+    let (header, values) = (columns(header), columns(lines.next()?));
+    let avg_qp = header
+        .iter()
+        .position(|c| *c == "Average QP")
+        .and_then(|idx| values.get(idx))
+        .and_then(|v| v.split_whitespace().next()?.parse().ok())?;
+    Some(EncoderStats {
+        avg_qp,
+        frame_types: vec![],
+    })
+}
+
+fn columns(line: &str) -> Vec<&str> {
+    line.split('\t')
+        .map(str::trim)
+        .filter(|c| !c.is_empty() && *c != "|")
+        .collect()
+}
+
+/// Returns the first value after `label`, delimited by whitespace or a comma.
+fn value<'a>(line: &'a str, label: &str) -> Option<&'a str> {
+    let line = &line[line.find(label)? + label.len()..];
+    line.split(|c: char| c.is_whitespace() || c == ',')
+        .find(|v| !v.is_empty())
+}
+
+#[test]
+fn parse_x265_stats() {
+    let stderr = "frame=  240 fps= 23 q=-0.0 Lsize=    1234kB time=00:00:10.00 bitrate=1011.2kbits/s speed=0.96x
+video:1210kB audio:0kB subtitle:0kB other streams:0kB global headers:2kB muxing overhead: 1.98%
+x265 [info]: frame I:      2, Avg QP:23.59  kb/s: 10297.27
+x265 [info]: frame P:     60, Avg QP:25.58  kb/s: 2497.06
+x265 [info]: frame B:    178, Avg QP:30.93  kb/s: 422.67
+x265 [info]: consecutive B-frames: 4.8% 3.2% 6.5% 85.5%";
+    let stats = EncoderStats::parse(stderr).unwrap();
+    assert!((stats.avg_qp - 29.53).abs() < 0.01, "{}", stats.avg_qp);
+    let kinds: Vec<_> = stats.frame_types.iter().map(|t| t.kind.as_str()).collect();
+    assert_eq!(kinds, ["I", "P", "B"]);
+    assert_eq!(stats.frame_types[2].frames, 178);
+    let bits: f32 = stats.frame_types.iter().map(|t| t.bits_percent).sum();
+    assert!((bits - 100.0).abs() < 0.01);
+    assert_eq!(stats.frame_types[0].bits_percent.round(), 8.0);
+    assert_eq!(
+        stats.to_string(),
+        "avg QP 29.53, I 2 frames QP 23.59 8% bits, P 60 frames QP 25.58 61% bits, \
+         B 178 frames QP 30.93 31% bits"
+    );
+}
+
+#[test]
+fn parse_x264_stats() {
+    let stderr = "[libx264 @ 0x5581c0] frame I:2     Avg QP:19.63  size: 45417
+[libx264 @ 0x5581c0] frame P:67    Avg QP:22.58  size: 11473
+[libx264 @ 0x5581c0] frame B:171   Avg QP:25.84  size:  2190
+[libx264 @ 0x5581c0] consecutive B-frames:  2.5%  5.0%";
+    let stats = EncoderStats::parse(stderr).unwrap();
+    assert_eq!(stats.frame_types.len(), 3);
+    assert_eq!(stats.frame_types[1].frames, 67);
+    assert_eq!(stats.frame_types[1].avg_qp, 22.58);
+    assert_eq!(stats.frame_types[1].bits_percent.round(), 62.0);
+}
+
+#[test]
+fn parse_svt_stats() {
+    let stderr = "Svt[info]: SVT [config]: preset 8
+SUMMARY --------------------------------- Channel 1  --------------------------------
+Total Frames\tAverage QP  \tY-PSNR   \tU-PSNR   \tV-PSNR   \t| \tBitrate
+         240\t    38.25 \t  41.20 dB\t  44.91 dB\t  45.30 dB\t| \t  812.41 kbps";
+    let stats = EncoderStats::parse(stderr).unwrap();
+    assert_eq!(stats.avg_qp, 38.25);
+    assert!(stats.frame_types.is_empty());
+
+    assert_eq!(EncoderStats::parse("frame=  240 fps= 23 q=-0.0"), None);
+}
@@ -0,0 +1,148 @@
+//! Typed errors for programmatic error handling.
+//!
+//! Internally errors are usually [`anyhow::Error`]s, which may wrap an [`Error`]
+//! created where the failure is detected. Converting an `anyhow::Error` into an
+//! [`Error`] recovers these typed errors.
+use crate::{command::crf_search, ffmpeg, ffprobe::ProbeError};
+use std::io;
+
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// An executable, e.g. ffmpeg, was not found.
+    #[error("{program} not found, is it installed & in PATH?")]
+    FfmpegNotFound { program: String },
+    /// A requested decoder is not supported by ffmpeg, e.g. a `*_cuvid` decoder.
+    #[error("CUDA decoder {decoder} not available. Supported: {}", available.join(", "))]
+    DecoderUnavailable {
+        decoder: String,
+        available: Vec<String>,
+    },
+    /// ffmpeg VMAF scoring failed.
+    #[error("ffmpeg vmaf failed\n---stderr---\n{stderr}\n------------")]
+    VmafFailed { stderr: String },
+    /// Failed to probe the input, e.g. the input doesn't exist or has no video stream.
+    #[error("{0}")]
+    ProbeError(String),
+    /// No crf delivered the min score within the max encoded percent.
+    #[error("Failed to find a suitable crf")]
+    CrfNotFound {
+        /// Score of the last crf attempted.
+        best_score: f32,
+    },
+    #[error(transparent)]
+    Other(anyhow::Error),
+}
+
+/// Process exit codes of error categories.
+pub mod exit_code {
+    /// Any other error.
+    pub const OTHER: i32 = 1;
+    /// ffmpeg, or another required executable, not found.
+    pub const FFMPEG_NOT_FOUND: i32 = 2;
+    /// No crf delivered the min score.
+    pub const CRF_NOT_FOUND: i32 = 3;
+    /// GPU/CUDA unavailable, e.g. an unsupported cuvid decoder or NVDEC out of memory.
+    pub const GPU_UNAVAILABLE: i32 = 4;
+    /// Failed to probe the input.
+    pub const PROBE_FAILED: i32 = 5;
+    /// Invalid cli args or config file, as in sysexits.h `EX_USAGE`.
+    pub const USAGE: i32 = 64;
+}
+
+impl Error {
+    /// Process exit code of the error category, see [`exit_code`].
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::FfmpegNotFound { .. } => exit_code::FFMPEG_NOT_FOUND,
+            Self::CrfNotFound { .. } => exit_code::CRF_NOT_FOUND,
+            Self::DecoderUnavailable { .. } => exit_code::GPU_UNAVAILABLE,
+            Self::Other(err) if ffmpeg::is_cuda_unavailable(err) => exit_code::GPU_UNAVAILABLE,
+            Self::ProbeError(_) => exit_code::PROBE_FAILED,
+            Self::VmafFailed { .. } | Self::Other(_) => exit_code::OTHER,
+        }
+    }
+
+    /// Map a process spawn error adding the `context`, e.g. "ffmpeg encode".
+    /// A missing executable is an [`Error::FfmpegNotFound`].
+    pub fn spawn(context: &'static str) -> impl FnOnce(io::Error) -> anyhow::Error {
+        move |err| match err.kind() {
+            io::ErrorKind::NotFound => Self::FfmpegNotFound {
+                program: context.split(' ').next().unwrap_or(context).into(),
+            }
+            .into(),
+            _ => anyhow::Error::new(err).context(context),
+        }
+    }
+}
+
+impl From<anyhow::Error> for Error {
+    fn from(err: anyhow::Error) -> Self {
+        // downcasting by value also works for errors with added context
+        let err = match err.downcast::<Self>() {
+            Ok(err) => return err,
+            Err(err) => err,
+        };
+        let err = match err.downcast::<crf_search::Error>() {
+            Ok(crf_search::Error::NoGoodCrf { last }) => {
+                return Self::CrfNotFound {
+                    best_score: last.enc.score,
+                };
+            }
+            Ok(crf_search::Error::Other(err)) => return err.into(),
+            Err(err) => err,
+        };
+        match err.downcast_ref::<ProbeError>() {
+            Some(probe) => Self::ProbeError(probe.to_string()),
+            None => Self::Other(err),
+        }
+    }
+}
+
+impl From<crf_search::Error> for Error {
+    fn from(err: crf_search::Error) -> Self {
+        anyhow::Error::new(err).into()
+    }
+}
+
+#[test]
+fn typed_errors_from_anyhow() {
+    use anyhow::Context;
+
+    let err: Error = anyhow::Error::from(Error::VmafFailed {
+        stderr: "oops".into(),
+    })
+    .into();
+    assert!(matches!(err, Error::VmafFailed { stderr } if stderr == "oops"));
+
+    // with added context
+    let err: Error = Err::<(), _>(Error::FfmpegNotFound {
+        program: "ffmpeg".into(),
+    })
+    .context("sample 1/3")
+    .unwrap_err()
+    .into();
+    assert!(matches!(err, Error::FfmpegNotFound { program } if program == "ffmpeg"));
+
+    let err = Error::spawn("ffmpeg encode")(io::ErrorKind::NotFound.into());
+    assert!(matches!(Error::from(err), Error::FfmpegNotFound { .. }));
+
+    let err = Error::spawn("ffmpeg encode")(io::ErrorKind::PermissionDenied.into());
+    assert!(matches!(Error::from(err), Error::Other(_)));
+
+    let err: Error = anyhow::anyhow!("something else").into();
+    assert!(matches!(err, Error::Other(_)));
+}
+
+#[test]
+fn error_exit_codes() {
+    assert_eq!(Error::CrfNotFound { best_score: 90.0 }.exit_code(), 3);
+    assert_eq!(
+        Error::from(anyhow::anyhow!(
+            "ffmpeg encode exit code 1\n[hevc_cuvid @ 0x55] Cannot load libcuda.so.1"
+        ))
+        .exit_code(),
+        exit_code::GPU_UNAVAILABLE
+    );
+    assert_eq!(Error::from(anyhow::anyhow!("oops")).exit_code(), 1);
+}
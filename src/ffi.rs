@@ -0,0 +1,207 @@
+//! C & wasm bindings of the [`CrfPlanner`], so frontends in other languages can
+//! embed the crf-search logic while running their own ffmpeg sample encodes.
+//!
+//! See `include/ab_av1.h` for the C declarations.
+use crate::api::{CrfPlan, CrfPlanner};
+
+/// [`ab_av1_planner_next`] result: sample the crf written to `crf_out` next.
+pub const AB_AV1_PLAN_NEXT: i32 = 0;
+/// [`ab_av1_planner_next`] result: done, the best crf is written to `crf_out`.
+pub const AB_AV1_PLAN_DONE: i32 = 1;
+/// [`ab_av1_planner_next`] result: no crf delivers the min score, or invalid args.
+pub const AB_AV1_PLAN_ERROR: i32 = -1;
+
+/// Returns a new planner, see [`CrfPlanner::new`]. Free with [`ab_av1_planner_free`].
+#[unsafe(no_mangle)]
+pub extern "C" fn ab_av1_planner_new(
+    min_crf: f32,
+    max_crf: f32,
+    crf_increment: f32,
+    min_score: f32,
+    max_encoded_percent: f32,
+) -> *mut CrfPlanner {
+    if !(crf_increment > 0.0 && min_crf <= max_crf) {
+        return std::ptr::null_mut();
+    }
+    Box::into_raw(Box::new(CrfPlanner::new(
+        min_crf,
+        max_crf,
+        crf_increment,
+        min_score,
+        max_encoded_percent,
+    )))
+}
+
+/// Returns the first crf to sample, `NaN` if `planner` is null.
+///
+/// # Safety
+/// `planner` must be null or returned by [`ab_av1_planner_new`] & not yet freed.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ab_av1_planner_initial_crf(planner: *const CrfPlanner) -> f32 {
+    match unsafe { planner.as_ref() } {
+        Some(planner) => planner.initial_crf(),
+        None => f32::NAN,
+    }
+}
+
+/// Adds the mean sample `score` & `encode_percent` of the last proposed `crf`.
+///
+/// Returns [`AB_AV1_PLAN_NEXT`] writing the next crf to sample to `crf_out`,
+/// [`AB_AV1_PLAN_DONE`] writing the best crf, or [`AB_AV1_PLAN_ERROR`], e.g. for
+/// non-finite args.
+///
+/// # Safety
+/// `planner` must be null or returned by [`ab_av1_planner_new`] & not yet freed.
+/// `crf_out` must be null or valid for writes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ab_av1_planner_next(
+    planner: *mut CrfPlanner,
+    crf: f32,
+    score: f32,
+    encode_percent: f64,
+    crf_out: *mut f32,
+) -> i32 {
+    let (Some(planner), Some(crf_out)) = (unsafe { planner.as_mut() }, unsafe { crf_out.as_mut() })
+    else {
+        return AB_AV1_PLAN_ERROR;
+    };
+    match planner.next(crf, score, encode_percent) {
+        Ok(CrfPlan::Next { crf }) => {
+            *crf_out = crf;
+            AB_AV1_PLAN_NEXT
+        }
+        Ok(CrfPlan::Done { crf, .. }) => {
+            *crf_out = crf;
+            AB_AV1_PLAN_DONE
+        }
+        Err(_) => AB_AV1_PLAN_ERROR,
+    }
+}
+
+/// Frees a planner.
+///
+/// # Safety
+/// `planner` must be null or returned by [`ab_av1_planner_new`] & not yet freed.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ab_av1_planner_free(planner: *mut CrfPlanner) {
+    if !planner.is_null() {
+        drop(unsafe { Box::from_raw(planner) });
+    }
+}
+
+/// wasm-bindgen planner, exported to js as `CrfPlanner`.
+#[cfg(feature = "wasm")]
+mod wasm {
+    use super::{CrfPlan, CrfPlanner};
+    use wasm_bindgen::prelude::*;
+
+    #[wasm_bindgen(js_name = CrfPlanner)]
+    pub struct WasmCrfPlanner {
+        planner: CrfPlanner,
+        best: Option<f32>,
+    }
+
+    #[wasm_bindgen(js_class = CrfPlanner)]
+    impl WasmCrfPlanner {
+        #[wasm_bindgen(constructor)]
+        pub fn new(
+            min_crf: f32,
+            max_crf: f32,
+            crf_increment: f32,
+            min_score: f32,
+            max_encoded_percent: f32,
+        ) -> Result<Self, JsError> {
+            if !(crf_increment > 0.0 && min_crf <= max_crf) {
+                return Err(JsError::new(
+                    "crf_increment must be positive & min_crf <= max_crf",
+                ));
+            }
+            Ok(Self {
+                planner: CrfPlanner::new(
+                    min_crf,
+                    max_crf,
+                    crf_increment,
+                    min_score,
+                    max_encoded_percent,
+                ),
+                best: None,
+            })
+        }
+
+        /// The first crf to sample.
+        #[wasm_bindgen(js_name = initialCrf)]
+        pub fn initial_crf(&self) -> f32 {
+            self.planner.initial_crf()
+        }
+
+        /// Adds the sample result of the last proposed crf. Returns the next crf to
+        /// sample, or `undefined` when done, see `bestCrf`.
+        pub fn next(
+            &mut self,
+            crf: f32,
+            score: f32,
+            encode_percent: f64,
+        ) -> Result<Option<f32>, JsError> {
+            match self.planner.next(crf, score, encode_percent)? {
+                CrfPlan::Next { crf } => Ok(Some(crf)),
+                CrfPlan::Done { crf, .. } => {
+                    self.best = Some(crf);
+                    Ok(None)
+                }
+            }
+        }
+
+        /// The best crf once done.
+        #[wasm_bindgen(js_name = bestCrf)]
+        pub fn best_crf(&self) -> Option<f32> {
+            self.best
+        }
+    }
+}
+
+#[test]
+fn c_planner_search() {
+    let planner = ab_av1_planner_new(10.0, 55.0, 1.0, 95.0, 80.0);
+    assert!(!planner.is_null());
+    let mut crf = unsafe { ab_av1_planner_initial_crf(planner) };
+    let best = loop {
+        let mut next = f32::NAN;
+        match unsafe { ab_av1_planner_next(planner, crf, 120.0 - crf, 20.0, &mut next) } {
+            AB_AV1_PLAN_NEXT => crf = next,
+            AB_AV1_PLAN_DONE => break next,
+            result => panic!("unexpected {result}"),
+        }
+    };
+    assert_eq!(best, 24.0);
+    unsafe { ab_av1_planner_free(planner) };
+
+    assert!(ab_av1_planner_new(55.0, 10.0, 1.0, 95.0, 80.0).is_null());
+    let mut out = 0.0;
+    let null = std::ptr::null_mut();
+    assert_eq!(
+        unsafe { ab_av1_planner_next(null, 30.0, 95.0, 20.0, &mut out) },
+        AB_AV1_PLAN_ERROR
+    );
+
+    let planner = ab_av1_planner_new(10.0, 55.0, 1.0, 95.0, 80.0);
+    for (crf, score, encode_percent) in [
+        (f32::NAN, 95.0, 20.0),
+        (30.0, f32::INFINITY, 20.0),
+        (30.0, 95.0, f64::NAN),
+    ] {
+        assert_eq!(
+            unsafe { ab_av1_planner_next(planner, crf, score, encode_percent, &mut out) },
+            AB_AV1_PLAN_ERROR
+        );
+    }
+    // a worse score at a lower crf errors rather than panicking
+    assert_eq!(
+        unsafe { ab_av1_planner_next(planner, 30.0, 85.0, 20.0, &mut out) },
+        AB_AV1_PLAN_NEXT
+    );
+    assert_eq!(
+        unsafe { ab_av1_planner_next(planner, 40.0, 90.0, 20.0, &mut out) },
+        AB_AV1_PLAN_ERROR
+    );
+    unsafe { ab_av1_planner_free(planner) };
+}
@@ -1,12 +1,16 @@
 //! ffmpeg encoding logic
 use crate::{
-    command::args::PixelFormat,
+    command::{
+        args::{AlsoEncode, PixelFormat},
+        crf_search,
+    },
+    error::Error,
     float::TerseF32,
-    process::{CommandExt, FfmpegOut, FfmpegOutStream},
+    process::{CommandExt, CommandJobEnv, FfmpegOut, FfmpegOutStream, program},
+    scheduler::Resources,
+    stdin,
     temporary::{self, TempKind},
 };
-use anyhow::Context;
-use log::debug;
 use std::{
     collections::HashSet,
     fmt::Write,
@@ -14,27 +18,189 @@ use std::{
     path::{Path, PathBuf},
     process::Stdio,
     sync::{Arc, LazyLock},
+    time::{Duration, UNIX_EPOCH},
 };
 use tokio::process::Command;
+use tracing::{debug, warn};
 
 /// Exposed ffmpeg encoding args.
 #[derive(Debug, Clone)]
 pub struct FfmpegEncodeArgs<'a> {
     pub input: &'a Path,
+    /// Input video stream to encode, e.g. 1 for "0:v:1".
+    pub video_stream: usize,
     pub vcodec: Arc<str>,
-    pub vfilter: Option<&'a str>,
+    pub vfilter: Option<Arc<str>>,
     pub pix_fmt: Option<PixelFormat>,
     pub crf: f32,
     pub preset: Option<Arc<str>>,
     pub output_args: Vec<Arc<String>>,
     pub input_args: Vec<Arc<String>>,
     pub video_only: bool,
+    pub subtitles: SubtitleSelect,
+    /// User `-map` values, see [`with_user_maps`].
+    pub maps: Vec<Arc<str>>,
+    pub mp4_compat: bool,
+    pub reproducible: bool,
+    pub metadata: OutputMetadata,
+}
+
+/// Max retries of an encode failing with a transient NVDEC out of memory error.
+pub const NVDEC_OOM_RETRIES: u32 = 3;
+
+/// Delay before the first NVDEC out of memory retry, multiplied by the retry number.
+pub const NVDEC_OOM_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Returns true if the ffmpeg error is a CUDA/NVDEC out of memory error.
+///
+/// These may be transient, e.g. VRAM pressure from other applications.
+pub fn is_nvdec_oom(err: &anyhow::Error) -> bool {
+    if is_nvenc_session_limit(err) {
+        return false;
+    }
+    let err = format!("{err:#}").to_lowercase();
+    err.contains("cuda_error_out_of_memory")
+        || err.contains("no decoder surfaces left")
+        || (err.contains("cuvid") || err.contains("cuda")) && err.contains("out of memory")
+}
+
+/// Returns true if NVENC failed to open an encode session, e.g. as a consumer GPU's
+/// concurrent session limit is used by other processes. Reported by ffmpeg as
+/// "OpenEncodeSessionEx failed: out of memory (10)", despite free VRAM.
+pub fn is_nvenc_session_limit(err: &anyhow::Error) -> bool {
+    format!("{err:#}")
+        .to_lowercase()
+        .contains("openencodesessionex failed")
+}
+
+/// Returns true if the error indicates CUDA/NVDEC is unusable, e.g. no GPU or driver.
+pub fn is_cuda_unavailable(err: &anyhow::Error) -> bool {
+    let msg = format!("{err:#}").to_lowercase();
+    is_nvdec_oom(err)
+        || is_nvenc_session_limit(err)
+        || msg.contains("cannot load libcuda")
+        || msg.contains("cuda_error_no_device")
+        || msg.contains("no cuda-capable device")
+        || msg.contains("cuinit(0) failed")
+}
+
+/// Returns the reason a hardware decoder, filter or encoder is missing or failed,
+/// e.g. an unavailable `*_cuvid` decoder or an ffmpeg stderr line like
+/// `[hevc_nvenc @ 0x55d0] OpenEncodeSessionEx failed: out of memory (10)`.
+///
+/// Returns `None` for errors unrelated to hardware acceleration.
+pub fn hw_failure_reason(err: &anyhow::Error) -> Option<String> {
+    /// Hardware acceleration names ffmpeg uses in filter, codec & device errors.
+    const HW: &[&str] = &[
+        "cuda", "cuvid", "nvenc", "nvdec", "_npp", "qsv", "libmfx", "libvpl", "vaapi", "amf",
+        "hwupload", "hwaccel",
+    ];
+    const FAILURE: &[&str] = &[
+        "error",
+        "fail",
+        "cannot",
+        "unknown",
+        "no such",
+        "not supported",
+        "unsupported",
+        "invalid",
+        "out of memory",
+    ];
+
+    if let Some(Error::DecoderUnavailable { decoder, .. }) = err.downcast_ref() {
+        return Some(format!("CUDA decoder {decoder} not available"));
+    }
+    if let Some(crf_search::Error::Other(err)) = err.downcast_ref() {
+        return hw_failure_reason(err);
+    }
+    let msg = format!("{err:#}");
+    // ignore the cmd, which mentions hardware args whether or not they failed
+    let stderr = msg
+        .split_once("---stderr---")
+        .map_or(msg.as_str(), |(_, s)| s);
+    stderr
+        .lines()
+        .map(str::trim)
+        // ffmpeg banner lists --enable-cuda etc
+        .filter(|line| !line.contains("configuration:"))
+        .find(|line| {
+            let line = line.to_lowercase();
+            HW.iter().any(|hw| line.contains(hw)) && FAILURE.iter().any(|f| line.contains(f))
+        })
+        .map(str::to_owned)
+}
+
+/// Returns true if the `ffmpeg` executable has the named `filter`, e.g. "libvmaf".
+pub fn has_filter(ffmpeg: &Path, filter: &str) -> bool {
+    lists(ffmpeg, "-filters", filter)
+}
+
+/// Returns true if the `ffmpeg` executable has the named `encoder`, e.g. "hevc_qsv".
+pub fn has_encoder(ffmpeg: &Path, encoder: &str) -> bool {
+    lists(ffmpeg, "-encoders", encoder)
+}
+
+/// Returns the names of the `ffmpeg` executable's filters, or None if they can't be listed.
+pub fn filters(ffmpeg: &Path) -> Option<Vec<String>> {
+    let out = std::process::Command::new(ffmpeg)
+        .job_env()
+        .args(["-hide_banner", "-filters"])
+        .stdin(Stdio::null())
+        .output()
+        .ok()
+        .filter(|out| out.status.success())?;
+    let names: Vec<_> = String::from_utf8_lossy(&out.stdout)
+        .lines()
+        .filter_map(filter_list_name)
+        .collect();
+    (!names.is_empty()).then_some(names)
+}
+
+/// Filter name of a `ffmpeg -filters` line, e.g. " TSC scale  V->V  Scale the input video".
+fn filter_list_name(line: &str) -> Option<String> {
+    let mut words = line.split_whitespace();
+    let (_flags, name, io) = (words.next()?, words.next()?, words.next()?);
+    io.contains("->").then(|| name.to_owned())
+}
+
+/// Returns true if an ffmpeg list `arg`, e.g. "-filters", includes `name`.
+fn lists(ffmpeg: &Path, arg: &str, name: &str) -> bool {
+    std::process::Command::new(ffmpeg)
+        .job_env()
+        .args(["-hide_banner", arg])
+        .stdin(Stdio::null())
+        .output()
+        .is_ok_and(|out| {
+            String::from_utf8_lossy(&out.stdout)
+                .split_whitespace()
+                .any(|w| w == name)
+        })
+}
+
+/// Which input subtitle streams to include in an encoded output.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum SubtitleSelect {
+    #[default]
+    All,
+    None,
+    /// Subtitle streams tagged with any of these languages.
+    Languages(Vec<Arc<str>>),
+}
+
+/// Output metadata tags, see --metadata.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OutputMetadata {
+    /// Don't carry over the input global & stream tags, e.g. title & language.
+    pub strip: bool,
+    /// Global tags to set, e.g. ("title", "My Video").
+    pub tags: Vec<(Arc<str>, Arc<str>)>,
 }
 
 impl FfmpegEncodeArgs<'_> {
     pub fn sample_encode_hash(&self, state: &mut impl Hasher) {
         static SVT_AV1_V: LazyLock<Vec<u8>> = LazyLock::new(|| {
             std::process::Command::new("SvtAv1EncApp")
+                .job_env()
                 .arg("--version")
                 .output()
                 .map(|o| o.stdout)
@@ -53,14 +219,90 @@ impl FfmpegEncodeArgs<'_> {
         self.crf.to_bits().hash(state);
         self.preset.hash(state);
         self.output_args.hash(state);
-        self.input_args.hash(state);
+        // -extra_hw_frames doesn't affect output & is auto tuned per GPU,
+        // hash as the previous default 16 to retain cached results
+        let mut hw_frames = false;
+        for arg in &self.input_args {
+            match hw_frames {
+                true => "16".hash(state),
+                false => arg.hash(state),
+            }
+            hw_frames = arg.as_str() == "-extra_hw_frames";
+        }
+        self.input_args.len().hash(state);
+    }
+
+    /// Halve the `-extra_hw_frames` input arg, set by --cuda-surfaces, to reduce
+    /// GPU memory use. Returns false if not set or already minimal.
+    pub fn reduce_hw_frames(&mut self) -> bool {
+        const MIN: u32 = 2;
+
+        let Some(idx) = self
+            .input_args
+            .windows(2)
+            .position(|w| w[0].as_str() == "-extra_hw_frames")
+        else {
+            return false;
+        };
+        match self.input_args[idx + 1].parse::<u32>() {
+            Ok(frames) if frames > MIN => {
+                let reduced = (frames / 2).max(MIN);
+                debug!("reducing -extra_hw_frames {frames} -> {reduced}");
+                self.input_args[idx + 1] = Arc::new(reduced.to_string());
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Shared resources an encode with these args will use.
+    pub fn resources(&self) -> Resources {
+        const HW_ENCODERS: &[&str] = &[
+            "_nvenc",
+            "_vaapi",
+            "_qsv",
+            "_vulkan",
+            "_amf",
+            "_videotoolbox",
+        ];
+
+        let nvdec = self
+            .input_args
+            .windows(2)
+            .any(|w| w[0].as_str() == "-hwaccel" && matches!(w[1].as_str(), "cuda" | "nvdec"))
+            || self.input_args.iter().any(|a| a.ends_with("_cuvid"));
+        Resources {
+            nvdec,
+            nvenc: self.vcodec.ends_with("_nvenc"),
+            cpu_encode: !HW_ENCODERS.iter().any(|hw| self.vcodec.ends_with(hw)),
+            vmaf_cpu: false,
+        }
     }
 }
 
 /// Encode a sample.
 pub fn encode_sample(
+    args: FfmpegEncodeArgs,
+    temp_dir: Option<PathBuf>,
+    dest_ext: &str,
+) -> anyhow::Result<(PathBuf, FfmpegOutStream)> {
+    let (dest, mut cmd) = encode_sample_command(args, temp_dir, dest_ext);
+    temporary::add(&dest, TempKind::Keepable);
+
+    let cmd_str = cmd.to_cmd_str();
+    debug!("cmd `{cmd_str}`");
+
+    let enc = cmd.spawn().map_err(Error::spawn("ffmpeg encode_sample"))?;
+
+    let stream = FfmpegOut::stream(enc, "ffmpeg encode_sample", cmd_str);
+    Ok((dest, stream))
+}
+
+/// Returns the temp file path & command [`encode_sample`] runs.
+pub fn encode_sample_command(
     FfmpegEncodeArgs {
         input,
+        video_stream: _,
         vcodec,
         vfilter,
         pix_fmt,
@@ -69,10 +311,15 @@ pub fn encode_sample(
         output_args,
         input_args,
         video_only: _,
+        subtitles: _,
+        maps: _,
+        mp4_compat: _,
+        reproducible: _,
+        metadata: _,
     }: FfmpegEncodeArgs,
     temp_dir: Option<PathBuf>,
     dest_ext: &str,
-) -> anyhow::Result<(PathBuf, FfmpegOutStream)> {
+) -> (PathBuf, Command) {
     let pre = pre_extension_name(&vcodec);
     let crf_str = format!("{}", TerseF32(crf)).replace('.', "_");
     let dest_file_name = match &preset {
@@ -83,37 +330,350 @@ pub fn encode_sample(
     let mut dest = temporary::process_dir(temp_dir);
     dest.push(dest_file_name);
 
-    temporary::add(&dest, TempKind::Keepable);
-
-    let mut cmd = Command::new("ffmpeg");
-    cmd.kill_on_drop(true)
+    let mut cmd = Command::new(program::ffmpeg());
+    cmd.job_env()
+        .kill_on_drop(true)
         .arg("-y")
+        .arg2("-progress", "pipe:1")
+        .arg("-nostats")
         .args(input_args.iter().map(|a| &**a))
         .arg2("-i", input)
         .arg2("-c:v", &*vcodec)
         .args(output_args.iter().map(|a| &**a))
-        .arg2(vcodec.crf_arg(), crf)
+        .args(vcodec.crf_args(crf))
         .arg2_opt("-pix_fmt", pix_fmt.map(|v| v.as_str()))
         .arg2_opt(vcodec.preset_arg(), preset)
         .arg2_opt("-vf", vfilter)
         .arg("-an")
         .arg(&dest)
         .stdin(Stdio::null())
-        .stdout(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    (dest, cmd)
+}
+
+/// Encode the input's main video stream, starting at `start`, to ~`segment_time` long
+/// matroska segment files named using the ffmpeg `pattern`, e.g. "dir/00000-%05d.mkv".
+///
+/// Completed segments are appended to the csv `list` as "file,start,end".
+pub fn encode_segments(
+    FfmpegEncodeArgs {
+        input,
+        video_stream,
+        vcodec,
+        vfilter,
+        pix_fmt,
+        crf,
+        preset,
+        output_args,
+        input_args,
+        video_only: _,
+        subtitles: _,
+        maps: _,
+        mp4_compat: _,
+        reproducible: _,
+        metadata: _,
+    }: FfmpegEncodeArgs,
+    start: Duration,
+    segment_time: Duration,
+    pattern: &Path,
+    list: &Path,
+) -> anyhow::Result<FfmpegOutStream> {
+    let mut cmd = Command::new(program::ffmpeg());
+    cmd.job_env()
+        .kill_on_drop(true)
+        .arg("-y")
+        .arg2("-progress", "pipe:1")
+        .arg("-nostats")
+        .args(input_args.iter().map(|a| &**a))
+        .arg2_if(!start.is_zero(), "-ss", start.as_secs_f32())
+        .arg2("-i", input)
+        .arg2("-map", format!("0:v:{video_stream}"))
+        .arg2("-c:v", &*vcodec)
+        .args(output_args.iter().map(|a| &**a))
+        .args(vcodec.crf_args(crf))
+        .arg2_opt("-pix_fmt", pix_fmt.map(|v| v.as_str()))
+        .arg2_opt(vcodec.preset_arg(), preset)
+        .arg2_opt("-vf", vfilter)
+        .arg("-an")
+        .arg("-sn")
+        .arg("-dn")
+        .arg2("-f", "segment")
+        .arg2("-segment_time", segment_time.as_secs_f32())
+        .arg2("-segment_format", "matroska")
+        .arg2("-segment_list", list)
+        .arg2("-segment_list_type", "csv")
+        .arg2("-reset_timestamps", "1")
+        .arg(pattern)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
         .stderr(Stdio::piped());
     let cmd_str = cmd.to_cmd_str();
     debug!("cmd `{cmd_str}`");
 
-    let enc = cmd.spawn().context("ffmpeg encode_sample")?;
+    let enc = cmd
+        .spawn()
+        .map_err(Error::spawn("ffmpeg encode_segments"))?;
 
-    let stream = FfmpegOut::stream(enc, "ffmpeg encode_sample", cmd_str);
-    Ok((dest, stream))
+    Ok(FfmpegOut::stream(enc, "ffmpeg encode_segments", cmd_str))
+}
+
+/// Encode the input's main video stream from `start`, for `duration` if specified, to a
+/// video only `dest` file.
+pub fn encode_chunk(
+    FfmpegEncodeArgs {
+        input,
+        video_stream,
+        vcodec,
+        vfilter,
+        pix_fmt,
+        crf,
+        preset,
+        output_args,
+        input_args,
+        video_only: _,
+        subtitles: _,
+        maps: _,
+        mp4_compat: _,
+        reproducible: _,
+        metadata: _,
+    }: FfmpegEncodeArgs,
+    start: Duration,
+    duration: Option<Duration>,
+    dest: &Path,
+) -> anyhow::Result<FfmpegOutStream> {
+    let mut cmd = Command::new(program::ffmpeg());
+    cmd.job_env()
+        .kill_on_drop(true)
+        .arg("-y")
+        .arg2("-progress", "pipe:1")
+        .arg("-nostats")
+        .args(input_args.iter().map(|a| &**a))
+        .arg2_if(!start.is_zero(), "-ss", start.as_secs_f32())
+        .arg2("-i", input)
+        .arg2_opt("-t", duration.map(|d| d.as_secs_f32()))
+        .arg2("-map", format!("0:v:{video_stream}"))
+        .arg2("-c:v", &*vcodec)
+        .args(output_args.iter().map(|a| &**a))
+        .args(vcodec.crf_args(crf))
+        .arg2_opt("-pix_fmt", pix_fmt.map(|v| v.as_str()))
+        .arg2_opt(vcodec.preset_arg(), preset)
+        .arg2_opt("-vf", vfilter)
+        .arg("-an")
+        .arg("-sn")
+        .arg("-dn")
+        .arg(dest)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    let cmd_str = cmd.to_cmd_str();
+    debug!("cmd `{cmd_str}`");
+
+    let enc = cmd.spawn().map_err(Error::spawn("ffmpeg encode_chunk"))?;
+
+    Ok(FfmpegOut::stream(enc, "ffmpeg encode_chunk", cmd_str))
 }
 
 /// Encode to output.
+///
+/// If `also` is set, also encodes a secondary output from the same decoded & filtered frames.
 pub fn encode(
+    args: FfmpegEncodeArgs,
+    output: &Path,
+    has_audio: bool,
+    audio_codec: Option<&str>,
+    downmix_to_stereo: bool,
+    also: Option<(&AlsoEncode, &Path)>,
+) -> anyhow::Result<FfmpegOutStream> {
+    output_cmd(
+        args,
+        None,
+        output,
+        has_audio,
+        audio_codec,
+        downmix_to_stereo,
+        also,
+    )
+}
+
+/// Run the first pass of a two-pass encode, writing encoder stats to `stats`.
+///
+/// Only the main video stream is encoded & the output discarded.
+pub fn encode_first_pass(
+    mut args: FfmpegEncodeArgs,
+    two_pass: TwoPass,
+    stats: &Path,
+) -> anyhow::Result<FfmpegOutStream> {
+    two_pass.add_args(1, stats, &mut args.output_args);
+    args.output_args
+        .extend(["-f", "null"].map(|a| Arc::new(a.to_owned())));
+    args.video_only = true;
+    args.maps = vec![];
+    args.mp4_compat = false;
+    output_cmd(args, None, Path::new("-"), false, None, false, None)
+}
+
+/// How an encoder supports two-pass encoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TwoPass {
+    /// ffmpeg `-pass` & `-passlogfile`, e.g. libvpx-vp9, libx264, libaom-av1.
+    Pass,
+    /// libx265 `-x265-params pass=N:stats=FILE`.
+    X265,
+    /// nvenc `-multipass fullres`, both passes run within a single encode.
+    Nvenc,
+}
+
+impl TwoPass {
+    /// Returns the two-pass support of the `vcodec`, if any.
+    pub fn of(vcodec: &str) -> Option<Self> {
+        match vcodec {
+            "libvpx-vp9" | "libvpx" | "libx264" | "libaom-av1" => Some(Self::Pass),
+            "libx265" => Some(Self::X265),
+            e if e.ends_with("_nvenc") => Some(Self::Nvenc),
+            _ => None,
+        }
+    }
+
+    /// Returns true if a separate first pass encode is run.
+    pub fn has_first_pass(self) -> bool {
+        self != Self::Nvenc
+    }
+
+    /// Adds output args for `pass` 1 or 2 using `stats` as the stats file.
+    pub fn add_args(self, pass: u8, stats: &Path, output_args: &mut Vec<Arc<String>>) {
+        let stats = stats.to_string_lossy();
+        match self {
+            Self::Pass => {
+                output_args.push(Arc::new("-pass".into()));
+                output_args.push(Arc::new(pass.to_string()));
+                output_args.push(Arc::new("-passlogfile".into()));
+                output_args.push(Arc::new(stats.into()));
+            }
+            Self::X265 => {
+                // x265 stats paths can't contain ':' as it separates the params
+                let params = format!("pass={pass}:stats={}", stats.replace(':', "\\:"));
+                match output_args
+                    .iter()
+                    .position(|a| a.as_str() == "-x265-params")
+                {
+                    Some(idx) if idx + 1 < output_args.len() => {
+                        output_args[idx + 1] =
+                            Arc::new(format!("{}:{params}", output_args[idx + 1]));
+                    }
+                    _ => {
+                        output_args.push(Arc::new("-x265-params".into()));
+                        output_args.push(Arc::new(params));
+                    }
+                }
+            }
+            Self::Nvenc => {
+                if !output_args.iter().any(|a| a.as_str() == "-multipass") {
+                    output_args.push(Arc::new("-multipass".into()));
+                    output_args.push(Arc::new("fullres".into()));
+                }
+            }
+        }
+    }
+
+    /// Files written by the encoder using `stats`.
+    pub fn stats_files(self, stats: &Path) -> Vec<PathBuf> {
+        let with_suffix = |suffix: &str| {
+            let mut file = stats.as_os_str().to_owned();
+            file.push(suffix);
+            PathBuf::from(file)
+        };
+        match self {
+            Self::Pass => vec![with_suffix("-0.log"), with_suffix("-0.log.mbtree")],
+            Self::X265 => vec![stats.to_owned(), with_suffix(".cutree")],
+            Self::Nvenc => vec![],
+        }
+    }
+}
+
+/// Mux already encoded video `segments`, an ffmpeg concat demuxer list, with the other
+/// input streams to output.
+pub fn concat_segments(
+    args: FfmpegEncodeArgs,
+    segments: &Path,
+    output: &Path,
+    has_audio: bool,
+    audio_codec: Option<&str>,
+    downmix_to_stereo: bool,
+) -> anyhow::Result<FfmpegOutStream> {
+    output_cmd(
+        args,
+        Some(segments),
+        output,
+        has_audio,
+        audio_codec,
+        downmix_to_stereo,
+        None,
+    )
+}
+
+/// Returns the command that [`encode`] would run, without spawning it.
+pub fn encode_command(
+    args: FfmpegEncodeArgs,
+    output: &Path,
+    has_audio: bool,
+    audio_codec: Option<&str>,
+    downmix_to_stereo: bool,
+    also: Option<(&AlsoEncode, &Path)>,
+) -> Command {
+    output_command(
+        args,
+        None,
+        output,
+        has_audio,
+        audio_codec,
+        downmix_to_stereo,
+        also,
+    )
+}
+
+/// Encode, or if using `segments` copy, the video & mux with other input streams to output.
+fn output_cmd(
+    args: FfmpegEncodeArgs,
+    segments: Option<&Path>,
+    output: &Path,
+    has_audio: bool,
+    audio_codec: Option<&str>,
+    downmix_to_stereo: bool,
+    also: Option<(&AlsoEncode, &Path)>,
+) -> anyhow::Result<FfmpegOutStream> {
+    let from_stdin = stdin::is_stdin(args.input);
+    let mut cmd = output_command(
+        args,
+        segments,
+        output,
+        has_audio,
+        audio_codec,
+        downmix_to_stereo,
+        also,
+    );
+    cmd.stdin(match from_stdin {
+        true => Stdio::piped(),
+        false => Stdio::null(),
+    })
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped());
+    let cmd_str = cmd.to_cmd_str();
+    debug!("cmd `{cmd_str}`");
+
+    let mut enc = cmd.spawn().map_err(Error::spawn("ffmpeg encode"))?;
+    if from_stdin {
+        stdin::pipe_to(enc.stdin.take())?;
+    }
+
+    Ok(FfmpegOut::stream(enc, "ffmpeg encode", cmd_str))
+}
+
+/// Build the [`output_cmd`] command.
+fn output_command(
     FfmpegEncodeArgs {
         input,
+        video_stream,
         vcodec,
         vfilter,
         pix_fmt,
@@ -122,71 +682,605 @@ pub fn encode(
         output_args,
         input_args,
         video_only,
+        subtitles,
+        maps: user_maps,
+        mp4_compat,
+        reproducible,
+        metadata,
     }: FfmpegEncodeArgs,
+    segments: Option<&Path>,
     output: &Path,
     has_audio: bool,
     audio_codec: Option<&str>,
     downmix_to_stereo: bool,
-) -> anyhow::Result<FfmpegOutStream> {
+    also: Option<(&AlsoEncode, &Path)>,
+) -> Command {
+    let encode_video = segments.is_none();
+    // filter once & split the frames between both outputs
+    let split_vfilter = vfilter.clone().filter(|_| encode_video && also.is_some());
     let oargs: HashSet<_> = output_args.iter().map(|a| a.as_str()).collect();
     let output_ext = output.extension().and_then(|e| e.to_str());
 
-    let add_faststart = output_ext == Some("mp4") && !oargs.contains("-movflags");
+    let mp4 = output_ext == Some("mp4");
+    let add_faststart = mp4 && !oargs.contains("-movflags");
     let matroska = matches!(output_ext, Some("mkv") | Some("webm"));
     let add_cues_to_front = matroska && !oargs.contains("-cues_to_front");
+    let mp4_compat = mp4_compat && mp4;
+    let compat_tag = mp4_compat_tag(&vcodec).filter(|_| mp4_compat && !oargs.contains("-tag:v"));
 
-    let audio_codec = audio_codec.unwrap_or(if downmix_to_stereo && has_audio {
-        "libopus"
-    } else {
-        "copy"
+    let audio_codec = audio_codec.unwrap_or(match (downmix_to_stereo && has_audio, mp4_compat) {
+        (true, true) => "aac",
+        (true, false) => "libopus",
+        _ => "copy",
     });
+    let subtitle_codec = match mp4_compat {
+        true => "mov_text",
+        false => "copy",
+    };
 
     let set_ba_128k = audio_codec == "libopus" && !oargs.contains("-b:a");
     let downmix_to_stereo = downmix_to_stereo && !oargs.contains("-ac");
-    let map = match video_only {
-        true => "0:v:0",
-        false => "0",
-    };
+    let main_video = format!("0:v:{video_stream}");
+    let mut maps = with_user_maps(stream_maps(video_only, &subtitles, matroska), &user_maps);
+    maps = selected_video_maps(maps, video_stream);
+    if segments.is_some() {
+        maps = segment_stream_maps(maps, &main_video);
+    }
+    if split_vfilter.is_some() {
+        maps = split_stream_maps(maps, &main_video);
+    }
     // This doesn't seem to work on .mp4 files
-    let mut metadata = format!(
-        "AB_AV1_FFMPEG_ARGS=-c:v {vcodec} {} {crf}",
-        vcodec.crf_arg()
+    let mut args_tag = format!(
+        "AB_AV1_FFMPEG_ARGS=-c:v {vcodec} {}",
+        vcodec.crf_args(crf).join(" ")
     );
     if let Some(preset) = &preset {
-        write!(&mut metadata, " {} {preset}", vcodec.preset_arg()).unwrap();
+        write!(&mut args_tag, " {} {preset}", vcodec.preset_arg()).unwrap();
     }
 
-    let mut cmd = Command::new("ffmpeg");
-    cmd.kill_on_drop(true)
-        .args(input_args.iter().map(|a| &**a))
-        .arg("-y")
-        .arg2("-i", input)
-        .arg2("-map", map)
+    let mut cmd = Command::new(program::ffmpeg());
+    cmd.job_env()
+        .kill_on_drop(true)
+        .arg2("-progress", "pipe:1")
+        .arg("-nostats");
+    match segments {
+        None => cmd.args(input_args.iter().map(|a| &**a)).arg("-y"),
+        Some(list) => cmd
+            .arg("-y")
+            .arg2("-f", "concat")
+            .arg2("-safe", "0")
+            .arg2("-i", list),
+    };
+    let other_streams_input = match segments {
+        None => "0",
+        Some(_) => "1",
+    };
+    cmd.arg2("-i", input)
+        .arg2_opt(
+            "-filter_complex",
+            split_vfilter
+                .as_ref()
+                .map(|vf| format!("[{main_video}]{vf},split=2[vout][also]")),
+        )
+        .args(maps.iter().flat_map(|m| ["-map", m]))
+        .arg2_if(!video_only, "-map_chapters", other_streams_input)
+        .arg2_if(metadata.strip, "-map_metadata", "-1")
         .arg2("-c:v", "copy")
-        .arg2("-c:v:0", &*vcodec)
-        .arg2("-metadata", metadata)
+        .arg2_if(encode_video, "-c:v:0", &*vcodec)
+        .arg2("-metadata", args_tag)
+        .args(
+            metadata
+                .tags
+                .iter()
+                .flat_map(|(k, v)| ["-metadata".to_owned(), format!("{k}={v}")]),
+        )
         .arg2("-c:a", audio_codec)
-        .arg2("-c:s", "copy")
-        .args(output_args.iter().map(|a| &**a))
-        .arg2(vcodec.crf_arg(), crf)
-        .arg2_opt("-pix_fmt", pix_fmt.map(|v| v.as_str()))
-        .arg2_opt(vcodec.preset_arg(), preset)
-        .arg2_opt("-vf", vfilter)
-        .arg_if(matroska, "-dn") // "Only audio, video, and subtitles are supported for Matroska"
+        .arg2("-c:s", subtitle_codec)
+        .args(output_args.iter().map(|a| &**a));
+    if encode_video {
+        cmd.args(vcodec.crf_args(crf))
+            .arg2_opt("-pix_fmt", pix_fmt.map(|v| v.as_str()))
+            .arg2_opt(vcodec.preset_arg(), preset)
+            .arg2_opt("-vf", vfilter.filter(|_| split_vfilter.is_none()));
+    }
+    cmd.arg_if(matroska || mp4_compat, "-dn") // "Only audio, video, and subtitles are supported for Matroska"
+        .arg2_opt("-tag:v", compat_tag)
         .arg2_if(downmix_to_stereo, "-ac", 2)
         .arg2_if(set_ba_128k, "-b:a", "128k")
         .arg2_if(add_faststart, "-movflags", "+faststart")
         .arg2_if(add_cues_to_front, "-cues_to_front", "y")
-        .arg(output)
+        .arg2_opt(
+            "-metadata",
+            source_date_epoch().map(|t| format!("creation_time={t}")),
+        );
+    if reproducible {
+        cmd.arg2("-fflags", "+bitexact")
+            .arg2("-flags:v", "+bitexact")
+            .arg2("-flags:a", "+bitexact");
+    }
+    cmd.arg(output);
+    if let Some((also, also_output)) = also {
+        let vcodec = &also.vcodec;
+        let video_map = match &split_vfilter {
+            Some(_) => "[also]",
+            None => &main_video,
+        };
+        cmd.arg2("-map", video_map)
+            .arg2_if(!video_only, "-map", "0:a?")
+            .arg2("-c:v", &**vcodec)
+            .args(also.crf.map(|crf| vcodec.crf_args(crf)).unwrap_or_default())
+            .arg2_opt(vcodec.preset_arg(), also.preset.as_deref())
+            .arg2("-pix_fmt", "yuv420p")
+            .arg2_opt("-tag:v", mp4_compat_tag(vcodec))
+            .arg2("-c:a", "aac")
+            .arg2_if(downmix_to_stereo, "-ac", 2)
+            .arg2("-movflags", "+faststart")
+            .arg2_opt(
+                "-metadata",
+                source_date_epoch().map(|t| format!("creation_time={t}")),
+            );
+        if reproducible {
+            cmd.arg2("-fflags", "+bitexact")
+                .arg2("-flags:v", "+bitexact")
+                .arg2("-flags:a", "+bitexact");
+        }
+        cmd.arg(also_output);
+    }
+    cmd
+}
+
+/// Output `creation_time` from env var `SOURCE_DATE_EPOCH`, if set.
+fn source_date_epoch() -> Option<String> {
+    let epoch = std::env::var("SOURCE_DATE_EPOCH").ok()?;
+    let time = epoch_creation_time(&epoch);
+    if time.is_none() {
+        warn!("Ignoring invalid SOURCE_DATE_EPOCH={epoch}");
+    }
+    time
+}
+
+/// Converts unix seconds to an rfc3339 `creation_time`.
+fn epoch_creation_time(epoch: &str) -> Option<String> {
+    let secs = epoch.trim().parse().ok()?;
+    let time = UNIX_EPOCH + Duration::from_secs(secs);
+    Some(humantime::format_rfc3339_seconds(time).to_string())
+}
+
+/// Converts [`stream_maps`] for use with encoded video segments as input 0 & the
+/// original input, providing all other streams, as input 1.
+fn segment_stream_maps(maps: Vec<String>, main_video: &str) -> Vec<String> {
+    let mut segment_maps = vec!["0:v:0".to_owned()];
+    for map in maps {
+        match map.as_str() {
+            m if m == main_video => {}
+            // other input video streams, e.g. cover art
+            "0:v" => segment_maps.extend(["1:v".into(), "-1:v:0".into()]),
+            _ => segment_maps.push(map.replacen("0:", "1:", 1)),
+        }
+    }
+    segment_maps
+}
+
+/// Composes user `--map` values with the default `maps`.
+///
+/// Negative only user maps, e.g. "-0:a:1", remove streams from the defaults. Otherwise
+/// the user maps replace the defaults, except the main video stream which is always mapped.
+fn with_user_maps(mut maps: Vec<String>, user_maps: &[Arc<str>]) -> Vec<String> {
+    if user_maps.iter().all(|m| m.starts_with('-')) {
+        maps.extend(user_maps.iter().map(|m| m.to_string()));
+        return maps;
+    }
+    let mut with_user = vec!["0:v:0".to_owned()];
+    with_user.extend(
+        user_maps
+            .iter()
+            .filter(|m| !matches!(&***m, "0:v:0" | "0:V:0"))
+            .map(|m| m.to_string()),
+    );
+    with_user
+}
+
+/// Converts [`stream_maps`] to use the input `video_stream`, e.g. 1 for "0:v:1", as the
+/// main video instead of the first. Other input video streams are not mapped.
+fn selected_video_maps(maps: Vec<String>, video_stream: usize) -> Vec<String> {
+    if video_stream == 0 {
+        return maps;
+    }
+    let main_video = format!("0:v:{video_stream}");
+    let mut selected = vec![main_video.clone()];
+    selected.extend(
+        maps.into_iter()
+            .filter(|m| !matches!(m.as_str(), "0:v" | "0:v:0" | "0:V:0") && *m != main_video),
+    );
+    selected
+}
+
+/// Converts [`stream_maps`] to use the split filtered main video stream "[vout]" for
+/// the main video & the input for any other video streams, e.g. cover art.
+fn split_stream_maps(maps: Vec<String>, main_video: &str) -> Vec<String> {
+    let mut split_maps = vec![];
+    for map in maps {
+        match map.as_str() {
+            m if m == main_video => split_maps.push("[vout]".into()),
+            "0:v" => split_maps.extend(["[vout]".into(), "0:v".into(), "-0:v:0".into()]),
+            _ => split_maps.push(map),
+        }
+    }
+    split_maps
+}
+
+/// Returns ffmpeg `-map` values selecting the input streams to include in an encoded output.
+///
+/// Optional `?` maps are used so inputs without e.g. subtitles still work.
+fn stream_maps(video_only: bool, subtitles: &SubtitleSelect, matroska: bool) -> Vec<String> {
+    if video_only {
+        return vec!["0:v:0".into()];
+    }
+
+    let mut maps = vec!["0:v".to_owned(), "0:a?".into()];
+    match subtitles {
+        SubtitleSelect::All => maps.push("0:s?".into()),
+        SubtitleSelect::None => {}
+        SubtitleSelect::Languages(langs) => {
+            maps.extend(langs.iter().map(|l| format!("0:s:m:language:{l}?")));
+        }
+    }
+    maps.push("0:d?".into());
+    if matroska {
+        // attachments, e.g. fonts, are only supported by matroska
+        maps.push("0:t?".into());
+    }
+    maps
+}
+
+/// Write a png of the `reference`, left, & `distorted` frames at `time` side-by-side.
+///
+/// The reference is filtered with any `ref_vfilter` & scaled to the distorted `resolution`.
+pub async fn save_comparison(
+    reference: &Path,
+    distorted: &Path,
+    time: Duration,
+    ref_vfilter: Option<&str>,
+    resolution: Option<(u32, u32)>,
+    png: &Path,
+) -> anyhow::Result<()> {
+    let output = Command::new(program::ffmpeg())
+        .job_env()
+        .kill_on_drop(true)
+        .arg("-y")
+        .arg2("-ss", time.as_secs_f64().to_string())
+        .arg2("-i", reference)
+        .arg2("-ss", time.as_secs_f64().to_string())
+        .arg2("-i", distorted)
+        .arg2(
+            "-filter_complex",
+            comparison_filter(ref_vfilter, resolution),
+        )
+        .arg2("-frames:v", 1)
+        .arg(png)
         .stdin(Stdio::null())
-        .stdout(Stdio::null())
-        .stderr(Stdio::piped());
-    let cmd_str = cmd.to_cmd_str();
-    debug!("cmd `{cmd_str}`");
+        .output()
+        .await
+        .map_err(Error::spawn("ffmpeg comparison"))?;
+    anyhow::ensure!(
+        output.status.success(),
+        "ffmpeg comparison failed: {}",
+        String::from_utf8_lossy(&output.stderr).trim()
+    );
+    Ok(())
+}
 
-    let enc = cmd.spawn().context("ffmpeg encode")?;
+/// Returns the `-filter_complex` stacking the reference & distorted, see [`save_comparison`].
+fn comparison_filter(ref_vfilter: Option<&str>, resolution: Option<(u32, u32)>) -> String {
+    let mut reference = String::from("[0:v]");
+    if let Some(vf) = ref_vfilter {
+        reference.push_str(vf);
+        reference.push(',');
+    }
+    if let Some((w, h)) = resolution {
+        write!(reference, "scale={w}:{h},").unwrap();
+    }
+    format!("{reference}format=rgb24[ref];[1:v]format=rgb24[dis];[ref][dis]hstack")
+}
 
-    Ok(FfmpegOut::stream(enc, "ffmpeg encode", cmd_str))
+#[test]
+fn comparison_filters() {
+    assert_eq!(
+        comparison_filter(None, Some((1280, 720))),
+        "[0:v]scale=1280:720,format=rgb24[ref];[1:v]format=rgb24[dis];[ref][dis]hstack"
+    );
+    assert_eq!(
+        comparison_filter(Some("crop=1920:800"), None),
+        "[0:v]crop=1920:800,format=rgb24[ref];[1:v]format=rgb24[dis];[ref][dis]hstack"
+    );
+}
+
+#[test]
+fn parse_filter_list() {
+    let list = "Filters:
+  T.. = Timeline support
+  ... = Source or sink filter
+ ... hwupload_cuda     V->V       Upload a system memory frame to a CUDA device.
+ TSC scale             V->V       Scale the input video size and/or convert the image format.
+ ... libvmaf           VV->V      Calculate the VMAF between two video streams.";
+    let names: Vec<_> = list.lines().filter_map(filter_list_name).collect();
+    assert_eq!(names, ["hwupload_cuda", "scale", "libvmaf"]);
+}
+
+#[test]
+fn resources_nvenc_cuvid() {
+    let args = FfmpegEncodeArgs {
+        input: Path::new("vid.mkv"),
+        video_stream: 0,
+        vcodec: "hevc_nvenc".into(),
+        vfilter: None,
+        pix_fmt: None,
+        crf: 30.0,
+        preset: None,
+        output_args: vec![],
+        input_args: vec![Arc::new("-c:v".into()), Arc::new("h264_cuvid".into())],
+        video_only: false,
+        subtitles: <_>::default(),
+        maps: vec![],
+        mp4_compat: false,
+        reproducible: false,
+        metadata: <_>::default(),
+    };
+    assert_eq!(
+        args.resources(),
+        Resources {
+            nvdec: true,
+            nvenc: true,
+            cpu_encode: false,
+            vmaf_cpu: false,
+        }
+    );
+
+    let args = FfmpegEncodeArgs {
+        vcodec: "libsvtav1".into(),
+        input_args: vec![],
+        ..args
+    };
+    assert_eq!(
+        args.resources(),
+        Resources {
+            cpu_encode: true,
+            ..<_>::default()
+        }
+    );
+}
+
+#[test]
+fn stream_maps_default() {
+    assert_eq!(
+        stream_maps(false, &SubtitleSelect::All, true),
+        ["0:v", "0:a?", "0:s?", "0:d?", "0:t?"]
+    );
+    assert_eq!(
+        stream_maps(false, &SubtitleSelect::All, false),
+        ["0:v", "0:a?", "0:s?", "0:d?"]
+    );
+}
+
+#[test]
+fn stream_maps_subtitles() {
+    assert_eq!(
+        stream_maps(false, &SubtitleSelect::None, true),
+        ["0:v", "0:a?", "0:d?", "0:t?"]
+    );
+    assert_eq!(
+        stream_maps(
+            false,
+            &SubtitleSelect::Languages(vec!["eng".into(), "jpn".into()]),
+            true
+        ),
+        [
+            "0:v",
+            "0:a?",
+            "0:s:m:language:eng?",
+            "0:s:m:language:jpn?",
+            "0:d?",
+            "0:t?"
+        ]
+    );
+}
+
+#[test]
+fn stream_maps_video_only() {
+    assert_eq!(stream_maps(true, &SubtitleSelect::All, true), ["0:v:0"]);
+}
+
+#[test]
+fn segment_stream_maps_shift_input() {
+    assert_eq!(
+        segment_stream_maps(stream_maps(false, &SubtitleSelect::All, true), "0:v:0"),
+        ["0:v:0", "1:v", "-1:v:0", "1:a?", "1:s?", "1:d?", "1:t?"]
+    );
+    assert_eq!(
+        segment_stream_maps(stream_maps(true, &SubtitleSelect::All, true), "0:v:0"),
+        ["0:v:0"]
+    );
+}
+
+#[test]
+fn user_stream_maps() {
+    let defaults = || stream_maps(false, &SubtitleSelect::All, true);
+    assert_eq!(with_user_maps(defaults(), &[]), defaults());
+    assert_eq!(
+        with_user_maps(defaults(), &["-0:a:1".into()]),
+        ["0:v", "0:a?", "0:s?", "0:d?", "0:t?", "-0:a:1"]
+    );
+    assert_eq!(
+        with_user_maps(
+            defaults(),
+            &["0:a:1".into(), "0:v:0".into(), "0:s:0".into()]
+        ),
+        ["0:v:0", "0:a:1", "0:s:0"]
+    );
+    assert_eq!(
+        segment_stream_maps(with_user_maps(defaults(), &["0:a:1".into()]), "0:v:0"),
+        ["0:v:0", "1:a:1"]
+    );
+}
+
+#[test]
+fn selected_video_stream_maps() {
+    let defaults = || stream_maps(false, &SubtitleSelect::All, true);
+    assert_eq!(selected_video_maps(defaults(), 0), defaults());
+    assert_eq!(
+        selected_video_maps(defaults(), 1),
+        ["0:v:1", "0:a?", "0:s?", "0:d?", "0:t?"]
+    );
+    assert_eq!(
+        segment_stream_maps(selected_video_maps(defaults(), 1), "0:v:1"),
+        ["0:v:0", "1:a?", "1:s?", "1:d?", "1:t?"]
+    );
+    assert_eq!(
+        split_stream_maps(selected_video_maps(defaults(), 2), "0:v:2"),
+        ["[vout]", "0:a?", "0:s?", "0:d?", "0:t?"]
+    );
+}
+
+#[test]
+fn output_containers() {
+    assert!(check_output_container(Path::new("vid.av1.mkv"), "libsvtav1", false).is_ok());
+    assert!(check_output_container(Path::new("vid.av1.WEBM"), "av1_nvenc", false).is_ok());
+    assert!(check_output_container(Path::new("vid.x265.webm"), "libx265", false).is_err());
+    assert!(check_output_container(Path::new("vid.x264.mp4"), "libx264", false).is_ok());
+    assert!(check_output_container(Path::new("img.x265.265"), "libx265", true).is_ok());
+    assert!(check_output_container(Path::new("vid.x265.265"), "libx265", false).is_err());
+    assert!(check_output_container(Path::new("img.avif"), "libx264", true).is_err());
+    assert!(check_output_container(Path::new("vid.ffv1.nut"), "ffv1", false).is_ok());
+    assert!(!container_supports("mp4", "libvpx"));
+    assert!(container_supports("mkv", "libvpx"));
+}
+
+#[test]
+fn two_pass_args() {
+    let stats = Path::new("/tmp/2pass");
+    let mut args = vec![];
+    TwoPass::Pass.add_args(2, stats, &mut args);
+    assert_eq!(
+        args,
+        ["-pass", "2", "-passlogfile", "/tmp/2pass"].map(|a| Arc::new(a.to_owned()))
+    );
+
+    let mut args = ["-x265-params", "aq-mode=3"]
+        .map(|a| Arc::new(a.to_owned()))
+        .to_vec();
+    TwoPass::X265.add_args(1, stats, &mut args);
+    assert_eq!(args[1].as_str(), "aq-mode=3:pass=1:stats=/tmp/2pass");
+
+    let mut args = vec![];
+    TwoPass::Nvenc.add_args(2, stats, &mut args);
+    assert_eq!(
+        args,
+        ["-multipass", "fullres"].map(|a| Arc::new(a.to_owned()))
+    );
+
+    assert_eq!(TwoPass::of("libsvtav1"), None);
+    assert_eq!(TwoPass::of("av1_nvenc"), Some(TwoPass::Nvenc));
+}
+
+#[test]
+fn split_stream_maps_filtered_video() {
+    assert_eq!(
+        split_stream_maps(stream_maps(false, &SubtitleSelect::None, false), "0:v:0"),
+        ["[vout]", "0:v", "-0:v:0", "0:a?", "0:d?"]
+    );
+    assert_eq!(
+        split_stream_maps(stream_maps(true, &SubtitleSelect::All, true), "0:v:0"),
+        ["[vout]"]
+    );
+}
+
+/// Mp4 video codec tag that hardware players expect, if known for the `vcodec`.
+///
+/// E.g. ffmpeg defaults to "hev1" for hevc which many players, notably Apple's, refuse.
+pub fn mp4_compat_tag(vcodec: &str) -> Option<&'static str> {
+    match vcodec {
+        "libsvtav1" | "libaom-av1" | "librav1e" => Some("av01"),
+        e if e.starts_with("av1_") => Some("av01"),
+        "libx265" => Some("hvc1"),
+        e if e.starts_with("hevc_") => Some("hvc1"),
+        "libx264" => Some("avc1"),
+        e if e.starts_with("h264_") => Some("avc1"),
+        _ => None,
+    }
+}
+
+/// Video codec family of the `vcodec` encoder, e.g. "libsvtav1" -> "av1", if known.
+fn codec_family(vcodec: &str) -> Option<&'static str> {
+    match vcodec {
+        "libsvtav1" | "libaom-av1" | "librav1e" => Some("av1"),
+        e if e.starts_with("av1_") => Some("av1"),
+        "libx265" => Some("hevc"),
+        e if e.starts_with("hevc_") => Some("hevc"),
+        "libx264" => Some("h264"),
+        e if e.starts_with("h264_") => Some("h264"),
+        "libvpx-vp9" => Some("vp9"),
+        e if e.starts_with("vp9_") => Some("vp9"),
+        "libvpx" => Some("vp8"),
+        e if e.starts_with("vp8_") => Some("vp8"),
+        _ => None,
+    }
+}
+
+/// Returns the codec families an output container `ext` can mux & whether the
+/// container is a raw single stream format, or `None` if the container is unknown.
+fn container_codecs(ext: &str) -> Option<(&'static [&'static str], bool)> {
+    const ALL: &[&str] = &["av1", "hevc", "h264", "vp9", "vp8"];
+    Some(match ext {
+        "mkv" => (ALL, false),
+        "mp4" | "mov" | "m4v" => (&["av1", "hevc", "h264", "vp9"], false),
+        "webm" => (&["av1", "vp9", "vp8"], false),
+        "avif" => (&["av1"], true),
+        "ivf" => (&["av1", "vp9", "vp8"], true),
+        "obu" => (&["av1"], true),
+        "264" | "h264" => (&["h264"], true),
+        "265" | "h265" | "hevc" => (&["hevc"], true),
+        _ => return None,
+    })
+}
+
+/// Returns true if the output container `ext` is known to support `vcodec` output.
+///
+/// Unknown containers & encoders are assumed to be supported.
+pub fn container_supports(ext: &str, vcodec: &str) -> bool {
+    match (
+        container_codecs(&ext.to_ascii_lowercase()),
+        codec_family(vcodec),
+    ) {
+        (Some((codecs, _)), Some(family)) => codecs.contains(&family),
+        _ => true,
+    }
+}
+
+/// Returns an error if ffmpeg can't mux `vcodec` video into the `output` container,
+/// e.g. hevc into .webm, or if a raw single stream output, e.g. ".265", would need
+/// to hold other streams.
+pub fn check_output_container(
+    output: &Path,
+    vcodec: &str,
+    single_stream: bool,
+) -> anyhow::Result<()> {
+    let Some(ext) = output.extension().and_then(|e| e.to_str()) else {
+        return Ok(());
+    };
+    let Some((codecs, raw)) = container_codecs(&ext.to_ascii_lowercase()) else {
+        return Ok(());
+    };
+    if let Some(family) = codec_family(vcodec).filter(|f| !codecs.contains(f)) {
+        anyhow::bail!(
+            "{vcodec} ({family}) cannot be muxed into .{ext} outputs, supported: {}",
+            codecs.join(", ")
+        );
+    }
+    anyhow::ensure!(
+        !raw || single_stream,
+        ".{ext} outputs hold a single video stream, use --video-only or another container"
+    );
+    Ok(())
 }
 
 pub fn pre_extension_name(vcodec: &str) -> &str {
@@ -201,32 +1295,158 @@ pub fn pre_extension_name(vcodec: &str) -> &str {
 trait VCodecSpecific {
     /// Arg to use preset values with, normally `-preset`.
     fn preset_arg(&self) -> &str;
-    /// Arg to use crf values with, normally `-crf`.
-    fn crf_arg(&self) -> &str;
+    /// Args to use crf values with, normally `-crf`.
+    fn crf_arg_names(&self) -> &[&str];
+
+    /// Crf args & values, e.g. `["-crf", "32"]`.
+    fn crf_args(&self, crf: f32) -> Vec<String> {
+        self.crf_arg_names()
+            .iter()
+            .flat_map(|arg| [arg.to_string(), crf.to_string()])
+            .collect()
+    }
 }
 impl VCodecSpecific for Arc<str> {
     fn preset_arg(&self) -> &str {
         match &**self {
             "libaom-av1" | "libvpx-vp9" => "-cpu-used",
             "librav1e" => "-speed",
+            e if e.ends_with("_amf") => "-quality",
             _ => "-preset",
         }
     }
 
-    fn crf_arg(&self) -> &str {
+    fn crf_arg_names(&self) -> &[&str] {
         // use crf-like args to support encoders that don't have crf
         match &**self {
             // https://ffmpeg.org//ffmpeg-codecs.html#librav1e
             // https://github.com/fraunhoferhhi/vvenc/wiki/FFmpeg-Integration#fix-qp-mode-constant-quality-mode
-            "librav1e" | "libvvenc" => "-qp",
-            "mpeg2video" => "-q",
+            "librav1e" | "libvvenc" => &["-qp"],
+            "mpeg2video" => &["-q"],
             // https://ffmpeg.org//ffmpeg-codecs.html#VAAPI-encoders
-            e if e.ends_with("_vaapi") => "-q",
-            e if e.ends_with("_vulkan") => "-qp",
-            e if e.ends_with("_nvenc") => "-cq",
+            e if e.ends_with("_vaapi") => &["-q"],
+            e if e.ends_with("_vulkan") => &["-qp"],
+            e if e.ends_with("_nvenc") => &["-cq"],
             // https://ffmpeg.org//ffmpeg-codecs.html#QSV-Encoders
-            e if e.ends_with("_qsv") => "-global_quality",
-            _ => "-crf",
+            e if e.ends_with("_qsv") => &["-global_quality"],
+            // constant qp per frame type with `-rc cqp`, only h264_amf has a b-frame qp in all versions
+            "h264_amf" => &["-qp_i", "-qp_p", "-qp_b"],
+            e if e.ends_with("_amf") => &["-qp_i", "-qp_p"],
+            _ => &["-crf"],
         }
     }
 }
+
+#[test]
+fn vcodec_crf_args() {
+    let vcodec = |v: &str| Arc::<str>::from(v);
+    assert_eq!(vcodec("libsvtav1").crf_args(32.5), ["-crf", "32.5"]);
+    assert_eq!(vcodec("hevc_nvenc").crf_args(30.0), ["-cq", "30"]);
+    assert_eq!(
+        vcodec("h264_amf").crf_args(24.0),
+        ["-qp_i", "24", "-qp_p", "24", "-qp_b", "24"]
+    );
+    assert_eq!(
+        vcodec("av1_amf").crf_args(120.0),
+        ["-qp_i", "120", "-qp_p", "120"]
+    );
+    assert_eq!(vcodec("hevc_amf").preset_arg(), "-quality");
+}
+
+#[test]
+fn reduce_hw_frames() {
+    let mut args = FfmpegEncodeArgs {
+        input: Path::new("vid.mkv"),
+        video_stream: 0,
+        vcodec: "hevc_nvenc".into(),
+        vfilter: None,
+        pix_fmt: None,
+        crf: 30.0,
+        preset: None,
+        output_args: vec![],
+        input_args: [
+            "-hwaccel",
+            "cuda",
+            "-extra_hw_frames",
+            "5",
+            "-c:v",
+            "h264_cuvid",
+        ]
+        .map(|a| Arc::new(a.to_owned()))
+        .into(),
+        video_only: false,
+        subtitles: <_>::default(),
+        maps: vec![],
+        mp4_compat: false,
+        reproducible: false,
+        metadata: <_>::default(),
+    };
+    assert!(args.reduce_hw_frames());
+    assert_eq!(*args.input_args[3], "2");
+    assert!(!args.reduce_hw_frames());
+}
+
+#[test]
+fn nvdec_oom_errors() {
+    assert!(is_nvdec_oom(&anyhow::anyhow!(
+        "ffmpeg encode exit code 1\n[h264_cuvid @ 0x55] ctx->cvdl->cuvidCreateDecoder(...) \
+         failed -> CUDA_ERROR_OUT_OF_MEMORY: out of memory"
+    )));
+    assert!(is_nvdec_oom(&anyhow::anyhow!("No decoder surfaces left")));
+    assert!(!is_nvdec_oom(&anyhow::anyhow!("Cannot allocate memory")));
+
+    let session_limit = anyhow::anyhow!(
+        "ffmpeg encode exit code 1\n----cmd-----\nffmpeg -hwaccel cuda ...\n\
+         [hevc_nvenc @ 0x55] OpenEncodeSessionEx failed: out of memory (10): (no details)"
+    );
+    assert!(is_nvenc_session_limit(&session_limit));
+    assert!(!is_nvdec_oom(&session_limit));
+    assert!(is_cuda_unavailable(&session_limit));
+}
+
+#[test]
+fn hw_failure_reasons() {
+    let err = anyhow::anyhow!(
+        "ffmpeg encode exit code 1\n----cmd-----\nffmpeg -hwaccel cuda -c:v h264_cuvid ...\n\
+         ---stderr---\n  configuration: --enable-cuda-nvcc --enable-nvenc\n\
+         [AVHWDeviceContext @ 0x55] cu->cuInit(0) failed -> CUDA_ERROR_NO_DEVICE: no CUDA-capable device is detected\n\
+         Device creation failed: -542398533.\n------------"
+    );
+    assert_eq!(
+        hw_failure_reason(&err).as_deref(),
+        Some(
+            "[AVHWDeviceContext @ 0x55] cu->cuInit(0) failed -> CUDA_ERROR_NO_DEVICE: \
+             no CUDA-capable device is detected"
+        )
+    );
+    let err = anyhow::anyhow!("No such filter: 'scale_cuda'");
+    assert_eq!(
+        hw_failure_reason(&err).as_deref(),
+        Some("No such filter: 'scale_cuda'")
+    );
+    let err = anyhow::Error::from(Error::DecoderUnavailable {
+        decoder: "av1_cuvid".into(),
+        available: vec!["h264_cuvid".into()],
+    });
+    assert_eq!(
+        hw_failure_reason(&err).as_deref(),
+        Some("CUDA decoder av1_cuvid not available")
+    );
+
+    // hardware args in the cmd & stream mapping aren't failures
+    let err = anyhow::anyhow!(
+        "ffmpeg encode exit code 1\n----cmd-----\nffmpeg -hwaccel cuda ... -c:v hevc_nvenc\n\
+         ---stderr---\nStream #0:0 -> #0:0 (h264 (h264_cuvid) -> hevc (hevc_nvenc))\n\
+         out.mkv: No space left on device\n------------"
+    );
+    assert_eq!(hw_failure_reason(&err), None);
+}
+
+#[test]
+fn source_date_epoch_creation_time() {
+    assert_eq!(
+        epoch_creation_time("1700000000").as_deref(),
+        Some("2023-11-14T22:13:20Z")
+    );
+    assert_eq!(epoch_creation_time("yesterday"), None);
+}
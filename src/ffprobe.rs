@@ -1,8 +1,20 @@
 //! ffprobe logic
-use crate::command::args::PixelFormat;
-use anyhow::{Context, anyhow};
-use std::{fmt, fs::File, io::Read, path::Path, time::Duration};
+use crate::{
+    command::{args::PixelFormat, sample_encode::cache},
+    process::{long_path, program},
+    stdin,
+};
+use anyhow::{Context, anyhow, bail};
+use std::{
+    fmt::{self, Write},
+    fs::{self, File},
+    io::Read,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tracing::debug;
 
+#[derive(serde::Serialize, serde::Deserialize)]
 pub struct Ffprobe {
     /// Duration of video.
     pub duration: Result<Duration, ProbeError>,
@@ -13,8 +25,56 @@ pub struct Ffprobe {
     /// Video frame rate.
     pub fps: Result<f64, ProbeError>,
     pub resolution: Option<(u32, u32)>,
+    /// A still image, not an animation.
     pub is_image: bool,
+    /// Animated GIF or APNG info.
+    pub animation: Option<Animation>,
+    /// Full input duration if `duration` is trimmed, see [`Ffprobe::trim`].
+    pub trimmed_from: Option<Duration>,
     pub pix_fmt: Option<String>,
+    /// Video field order, e.g. "progressive", "tt", "bb".
+    pub field_order: Option<String>,
+    /// Input size in bytes, if reported.
+    pub size: Option<u64>,
+    pub streams: Vec<StreamInfo>,
+}
+
+/// Animated GIF or APNG input info.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Animation {
+    pub frames: u64,
+    /// Number of times the animation plays, 0 meaning forever.
+    pub plays: u32,
+}
+
+/// Kind of input media, e.g. deciding the default output container.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MediaKind {
+    Video,
+    Image,
+    Animation,
+}
+
+/// Input stream summary.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct StreamInfo {
+    /// E.g. "video", "audio", "subtitle".
+    pub codec_type: String,
+    pub codec_name: Option<String>,
+    pub language: Option<String>,
+    /// Stream bitrate in bits/s, if reported.
+    pub bit_rate: Option<u64>,
+    /// Video stream details, `None` for other streams.
+    pub video: Option<VideoStreamInfo>,
+}
+
+/// Video stream details, see [`Ffprobe::select_video_stream`].
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct VideoStreamInfo {
+    pub fps: Option<f64>,
+    pub resolution: Option<(u32, u32)>,
+    pub pix_fmt: Option<String>,
+    pub field_order: Option<String>,
 }
 
 impl Ffprobe {
@@ -23,7 +83,164 @@ impl Ffprobe {
         PixelFormat::try_from(pf).ok()
     }
 
+    pub fn media_kind(&self) -> MediaKind {
+        match (self.is_image, self.animation) {
+            (true, _) => MediaKind::Image,
+            (_, Some(_)) => MediaKind::Animation,
+            _ => MediaKind::Video,
+        }
+    }
+
+    /// Returns true if the video uses a palette, e.g. most GIFs.
+    pub fn is_paletted(&self) -> bool {
+        self.pix_fmt.as_deref() == Some("pal8")
+    }
+
+    /// Trim the duration to the portion from `start`, at most `duration` long.
+    pub fn trim(&mut self, start: Duration, duration: Option<Duration>) {
+        let Ok(full) = self.duration.clone() else {
+            return;
+        };
+        self.trimmed_from = Some(full);
+        self.duration = match full.checked_sub(start).filter(|d| !d.is_zero()) {
+            Some(rest) => Ok(duration.map_or(rest, |d| d.min(rest))),
+            None => Err(ProbeError(format!(
+                "--start {} is after the input end",
+                humantime::format_duration(start)
+            ))),
+        };
+    }
+
+    /// Use the `n`th video stream, e.g. 1 for "0:v:1", for fps, resolution etc.
+    ///
+    /// If there is no such stream the fps & duration become errors listing the
+    /// available video streams.
+    pub fn select_video_stream(&mut self, n: usize) {
+        if self.streams.is_empty() {
+            return;
+        }
+        let mut videos = self.streams.iter().filter_map(|s| s.video.as_ref());
+        let Some(video) = videos.nth(n).cloned() else {
+            let count = self.streams.iter().filter(|s| s.video.is_some()).count();
+            let err = ProbeError(format!(
+                "--video-stream {n} not found, input has {count} video stream(s)"
+            ));
+            self.fps = Err(err.clone());
+            self.duration = Err(err);
+            return;
+        };
+        self.fps = video
+            .fps
+            .ok_or_else(|| ProbeError("invalid ffprobe video frame rate".into()));
+        self.resolution = video.resolution;
+        self.pix_fmt = video.pix_fmt;
+        self.field_order = video.field_order;
+    }
+
+    /// Returns true if the video field order is interlaced.
+    pub fn is_interlaced(&self) -> bool {
+        matches!(self.field_order.as_deref(), Some("tt" | "bb" | "tb" | "bt"))
+    }
+
+    /// Returns the `input` size in bytes, read from file metadata or, for urls, ffprobe.
+    /// Trimmed inputs return the size proportional to the trimmed duration.
+    ///
+    /// Stdin inputs return the bytes piped so far.
+    pub fn input_len(&self, input: &Path) -> anyhow::Result<u64> {
+        if stdin::is_stdin(input) {
+            return Ok(stdin::piped_len());
+        }
+        let len = match is_url(input) {
+            true => self
+                .size
+                .with_context(|| format!("unknown size of {}", input.display())),
+            false => fs::metadata(input)
+                .with_context(|| format!("{}", input.display()))
+                .map(|m| m.len()),
+        }?;
+        Ok(match (self.trimmed_from, &self.duration) {
+            (Some(full), Ok(trimmed)) if !full.is_zero() => {
+                (len as f64 * trimmed.as_secs_f64() / full.as_secs_f64()).round() as _
+            }
+            _ => len,
+        })
+    }
+
+    /// Returns an error listing the available streams if the ffmpeg `-map` value
+    /// does not match any input stream, e.g. "0:a:3" for an input with 2 audio streams.
+    ///
+    /// Optional "?" maps & specifiers that aren't understood are not checked.
+    pub fn validate_map(&self, map: &str) -> anyhow::Result<()> {
+        let spec = map.strip_prefix('-').unwrap_or(map);
+        if spec.ends_with('?') || self.streams.is_empty() {
+            return Ok(());
+        }
+        let mut parts = spec.split(':');
+        let input = parts.next().unwrap_or_default();
+        let matches = match parts.collect::<Vec<_>>().as_slice() {
+            _ if input != "0" => false,
+            [] => true,
+            [idx] if idx.bytes().all(|b| b.is_ascii_digit()) => {
+                idx.parse().is_ok_and(|idx: usize| idx < self.streams.len())
+            }
+            [kind, rest @ ..] => {
+                let codec_type = match *kind {
+                    "v" | "V" => "video",
+                    "a" => "audio",
+                    "s" => "subtitle",
+                    "d" => "data",
+                    "t" => "attachment",
+                    _ => return Ok(()),
+                };
+                let mut streams = self.streams.iter().filter(|s| s.codec_type == codec_type);
+                match rest {
+                    [] => streams.next().is_some(),
+                    [idx] => match idx.parse::<usize>() {
+                        Ok(idx) => streams.nth(idx).is_some(),
+                        Err(_) => return Ok(()),
+                    },
+                    ["m", "language", lang] => {
+                        streams.any(|s| s.language.as_deref() == Some(*lang))
+                    }
+                    _ => return Ok(()),
+                }
+            }
+        };
+        if !matches {
+            let mut available = String::new();
+            for (idx, stream) in self.streams.iter().enumerate() {
+                _ = write!(available, "\n  0:{idx} {}", stream.codec_type);
+                if let Some(codec) = &stream.codec_name {
+                    _ = write!(available, " {codec}");
+                }
+                if let Some(lang) = &stream.language {
+                    _ = write!(available, " ({lang})");
+                }
+            }
+            bail!("--map {map} matches no input stream, available streams:{available}");
+        }
+        Ok(())
+    }
+
+    /// Returns the estimated size in bytes of the audio, subtitle & other non-video streams
+    /// from their reported bitrates. Streams without one, e.g. in matroska, aren't counted.
+    pub fn non_video_size(&self) -> u64 {
+        let Ok(duration) = &self.duration else {
+            return 0;
+        };
+        self.streams
+            .iter()
+            .filter(|s| s.codec_type != "video")
+            .filter_map(|s| s.bit_rate)
+            .map(|bps| (bps as f64 / 8.0 * duration.as_secs_f64()).round() as u64)
+            .sum()
+    }
+
     pub fn nframes(&self) -> Result<u64, ProbeError> {
+        if let Some(animation) = self.animation {
+            // frame delays vary, so fps * duration is inaccurate
+            return Ok(animation.frames);
+        }
         match (&self.fps, &self.duration) {
             (Ok(fps), Ok(duration)) => {
                 let frames = (fps * duration.as_secs_f64()).round();
@@ -39,12 +256,59 @@ impl Ffprobe {
 }
 
 /// Try to ffprobe the given input.
+///
+/// Stdin inputs are probed using the buffered start of stdin.
+#[tracing::instrument(name = "probe", skip_all, fields(input = %input.display()))]
 pub fn probe(input: &Path) -> Ffprobe {
-    let is_image = is_image(input).unwrap_or(false);
+    if stdin::is_stdin(input) {
+        return match stdin::head_file() {
+            Ok(head) => probe_uncached(&head),
+            Err(err) => Ffprobe {
+                duration: Err(ProbeError(format!("{err}"))),
+                fps: Err(ProbeError(format!("{err}"))),
+                has_audio: true,
+                max_audio_channels: None,
+                resolution: None,
+                is_image: false,
+                animation: None,
+                trimmed_from: None,
+                pix_fmt: None,
+                field_order: None,
+                size: None,
+                streams: vec![],
+            },
+        };
+    }
+
+    // probing is slow for large network files, so results are cached by path, size & mtime
+    let key = cache::probe_key(input);
+    if let Some(probe) = key.and_then(cache::cached_probe) {
+        debug!("using cached ffprobe of {}", input.display());
+        return probe;
+    }
+    let probe = probe_uncached(input);
+    if let Some(key) = key.filter(|_| probe.duration.is_ok()) {
+        cache::cache_probe(key, &probe);
+    }
+    probe
+}
 
-    let probe = match ffprobe::ffprobe(input) {
+fn probe_uncached(input: &Path) -> Ffprobe {
+    let header = file_header(input).unwrap_or_default();
+    let animation = match header.starts_with(b"GIF8") {
+        // gif frames are counted by walking all the blocks
+        true => fs::read(input).ok().and_then(|data| read_animation(&data)),
+        false => read_animation(&header),
+    };
+    let is_image = animation.is_none() && infer::is_image(&header);
+
+    let config = ffprobe::Config::builder()
+        .ffprobe_bin(program::ffprobe())
+        .build();
+    let probe = match ffprobe::ffprobe_config(config, long_path(input)) {
         Ok(p) => p,
         Err(err) => {
+            debug!("ffprobe failed: {err}");
             return Ffprobe {
                 duration: Err(ProbeError(format!("ffprobe: {err}"))),
                 fps: Err(ProbeError(format!("ffprobe: {err}"))),
@@ -52,7 +316,12 @@ pub fn probe(input: &Path) -> Ffprobe {
                 max_audio_channels: None,
                 resolution: None,
                 is_image: false,
+                animation: None,
+                trimmed_from: None,
                 pix_fmt: None,
+                field_order: None,
+                size: None,
+                streams: vec![],
             };
         }
     };
@@ -80,11 +349,38 @@ pub fn probe(input: &Path) -> Ffprobe {
             Some((w, h))
         });
 
+    let size = probe.format.size.as_deref().and_then(|s| s.parse().ok());
+    let streams = probe
+        .streams
+        .iter()
+        .map(|s| StreamInfo {
+            codec_type: s.codec_type.clone().unwrap_or_default(),
+            codec_name: s.codec_name.clone(),
+            language: s.tags.as_ref().and_then(|t| t.language.clone()),
+            bit_rate: s.bit_rate.as_deref().and_then(|b| b.parse().ok()),
+            video: (s.codec_type.as_deref() == Some("video")).then(|| VideoStreamInfo {
+                fps: parse_frame_rate(&s.avg_frame_rate)
+                    .or_else(|| parse_frame_rate(&s.r_frame_rate)),
+                resolution: s
+                    .width
+                    .and_then(|w| u32::try_from(w).ok())
+                    .zip(s.height.and_then(|h| u32::try_from(h).ok())),
+                pix_fmt: s.pix_fmt.clone(),
+                field_order: s.field_order.clone(),
+            }),
+        })
+        .collect();
+    let field_order = probe
+        .streams
+        .iter()
+        .filter(|s| s.codec_type.as_deref() == Some("video"))
+        .find_map(|s| s.field_order.clone());
     let pix_fmt = probe
         .streams
         .into_iter()
         .filter(|s| s.codec_type.as_deref() == Some("video"))
         .find_map(|s| s.pix_fmt);
+    debug!("duration {duration:?}, fps {fps:?}, resolution {resolution:?}, pix_fmt {pix_fmt:?}");
 
     Ffprobe {
         duration: duration.map_err(ProbeError::from),
@@ -93,16 +389,143 @@ pub fn probe(input: &Path) -> Ffprobe {
         max_audio_channels,
         resolution,
         is_image,
+        animation,
+        trimmed_from: None,
         pix_fmt,
+        field_order,
+        size,
+        streams,
     }
 }
 
-fn is_image(path: &Path) -> anyhow::Result<bool> {
+/// Returns true if the input is a network url, e.g. "https://example.com/vid.mkv",
+/// instead of a local file.
+pub fn is_url(input: &Path) -> bool {
+    input
+        .to_str()
+        .and_then(|s| s.split_once("://"))
+        .is_some_and(|(scheme, _)| {
+            scheme.len() > 1
+                && scheme
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '+')
+        })
+}
+
+/// Returns a local file name for the `input`, which may be a url.
+///
+/// Url query strings & fragments are removed, e.g.
+/// "https://example.com/vid.mkv?token=abc" -> "vid.mkv".
+pub fn local_name(input: &Path) -> PathBuf {
+    match input.to_str().filter(|_| is_url(input)) {
+        Some(url) => {
+            let path = url.split(['?', '#']).next().unwrap_or_default();
+            let name = path
+                .rsplit('/')
+                .find(|s| !s.is_empty() && !s.contains("://"))
+                .unwrap_or("input");
+            name.into()
+        }
+        None => input.into(),
+    }
+}
+
+/// Returns the first 8KiB of the file.
+fn file_header(path: &Path) -> anyhow::Result<Vec<u8>> {
     let file = File::open(path)?;
     let mut file_header = Vec::with_capacity(8192);
     file.take(8192).read_to_end(&mut file_header)?;
+    Ok(file_header)
+}
+
+/// Returns animation info of GIF & APNG `data` with multiple frames.
+fn read_animation(data: &[u8]) -> Option<Animation> {
+    let animation = match data {
+        [b'G', b'I', b'F', b'8', ..] => read_gif_animation(data)?,
+        [0x89, b'P', b'N', b'G', ..] => read_apng_animation(data)?,
+        _ => return None,
+    };
+    Some(animation).filter(|a| a.frames > 1)
+}
+
+/// Walks the GIF blocks counting image descriptors & reading any NETSCAPE2.0 loop count.
+fn read_gif_animation(data: &[u8]) -> Option<Animation> {
+    /// Returns the length of the color table indicated by `packed` flags.
+    fn color_table_len(packed: u8) -> usize {
+        match packed & 0x80 {
+            0 => 0,
+            _ => 3 << ((packed & 0x07) + 1),
+        }
+    }
+    /// Returns the index after the data sub-blocks starting at `idx`.
+    fn skip_sub_blocks(data: &[u8], mut idx: usize) -> Option<usize> {
+        loop {
+            let len = *data.get(idx)? as usize;
+            idx += 1 + len;
+            if len == 0 {
+                return Some(idx);
+            }
+        }
+    }
+
+    let mut idx = 13 + color_table_len(*data.get(10)?);
+    let mut frames = 0;
+    // no loop extension means play once
+    let mut plays = 1;
+    loop {
+        match *data.get(idx)? {
+            // image descriptor
+            0x2C => {
+                frames += 1;
+                let packed = *data.get(idx + 9)?;
+                // skip descriptor, local color table & lzw min code size
+                idx = skip_sub_blocks(data, idx + 11 + color_table_len(packed))?;
+            }
+            // extension
+            0x21 => {
+                let label = *data.get(idx + 1)?;
+                let app = data.get(idx + 2..idx + 17)?;
+                if label == 0xFF && app[..12] == *b"\x0bNETSCAPE2.0" && app[12..14] == [3, 1] {
+                    // repetitions after the first play
+                    let repeats = u16::from_le_bytes([app[14], *data.get(idx + 17)?]);
+                    plays = match repeats {
+                        0 => 0,
+                        n => u32::from(n) + 1,
+                    };
+                }
+                idx = skip_sub_blocks(data, idx + 2)?;
+            }
+            // trailer
+            0x3B => break,
+            _ => return None,
+        }
+    }
+    Some(Animation { frames, plays })
+}
+
+/// Reads the APNG acTL chunk, which must precede the image data.
+fn read_apng_animation(data: &[u8]) -> Option<Animation> {
+    let mut idx = 8;
+    loop {
+        let len = u32::from_be_bytes(data.get(idx..idx + 4)?.try_into().ok()?) as usize;
+        let kind = data.get(idx + 4..idx + 8)?;
+        match kind {
+            b"acTL" => {
+                let chunk = data.get(idx + 8..idx + 16)?;
+                return Some(Animation {
+                    frames: u32::from_be_bytes(chunk[..4].try_into().ok()?).into(),
+                    plays: u32::from_be_bytes(chunk[4..].try_into().ok()?),
+                });
+            }
+            b"IDAT" | b"IEND" => return None,
+            _ => idx += 12 + len,
+        }
+    }
+}
 
-    Ok(infer::is_image(&file_header))
+/// Returns true if the file header indicates a video container.
+pub fn is_video(path: &Path) -> anyhow::Result<bool> {
+    Ok(infer::is_video(&file_header(path)?))
 }
 
 fn read_duration(probe: &ffprobe::FfProbe) -> anyhow::Result<Duration> {
@@ -146,7 +569,7 @@ pub fn parse_frame_rate(rate: &str) -> Option<f64> {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub struct ProbeError(String);
 
 impl fmt::Display for ProbeError {
@@ -162,3 +585,201 @@ impl From<anyhow::Error> for ProbeError {
 }
 
 impl std::error::Error for ProbeError {}
+
+#[test]
+fn validate_maps() {
+    let stream = |codec_type: &str, language: Option<&str>| StreamInfo {
+        codec_type: codec_type.into(),
+        codec_name: None,
+        language: language.map(Into::into),
+        bit_rate: None,
+        video: None,
+    };
+    let probe = Ffprobe {
+        duration: Ok(Duration::from_secs(60)),
+        has_audio: true,
+        max_audio_channels: None,
+        fps: Ok(24.0),
+        resolution: None,
+        is_image: false,
+        animation: None,
+        trimmed_from: None,
+        pix_fmt: None,
+        field_order: None,
+        size: None,
+        streams: vec![
+            stream("video", None),
+            stream("audio", Some("eng")),
+            stream("audio", Some("jpn")),
+            stream("subtitle", Some("eng")),
+        ],
+    };
+    for ok in [
+        "0:a:1",
+        "-0:a:0",
+        "0:s:m:language:eng",
+        "0:3",
+        "0:a:5?",
+        "0:p:1",
+        "0",
+    ] {
+        assert!(probe.validate_map(ok).is_ok(), "{ok}");
+    }
+    for err in ["0:a:2", "0:d", "0:s:m:language:fre", "0:4", "1:a"] {
+        assert!(probe.validate_map(err).is_err(), "{err}");
+    }
+    let err = probe.validate_map("0:a:2").unwrap_err().to_string();
+    assert!(err.contains("0:2 audio (jpn)"), "{err}");
+}
+
+#[test]
+fn non_video_size() {
+    let stream = |codec_type: &str, bit_rate: Option<u64>| StreamInfo {
+        codec_type: codec_type.into(),
+        codec_name: None,
+        language: None,
+        bit_rate,
+        video: None,
+    };
+    let mut probe = Ffprobe {
+        duration: Ok(Duration::from_secs(60)),
+        has_audio: true,
+        max_audio_channels: None,
+        fps: Ok(24.0),
+        resolution: None,
+        is_image: false,
+        animation: None,
+        trimmed_from: None,
+        pix_fmt: None,
+        field_order: None,
+        size: None,
+        streams: vec![
+            stream("video", Some(4_000_000)),
+            stream("audio", Some(128_000)),
+            stream("audio", None),
+            stream("subtitle", Some(800)),
+        ],
+    };
+    assert_eq!(probe.non_video_size(), 960_000 + 6_000);
+
+    probe.trim(Duration::ZERO, Some(Duration::from_secs(30)));
+    assert_eq!(probe.non_video_size(), 480_000 + 3_000);
+
+    probe.duration = Err(ProbeError("no duration".into()));
+    assert_eq!(probe.non_video_size(), 0);
+}
+
+#[test]
+fn url_local_name() {
+    assert!(is_url(Path::new("https://example.com/a/vid.mkv?token=x")));
+    assert!(is_url(Path::new("smb://nas/share/vid.mkv")));
+    assert!(!is_url(Path::new("vid.mkv")));
+    assert!(!is_url(Path::new("C:\\vid.mkv")));
+    assert_eq!(
+        local_name(Path::new("https://example.com/a/vid.mkv?token=x#t=5")),
+        Path::new("vid.mkv")
+    );
+    assert_eq!(
+        local_name(Path::new("dir/vid.mkv")),
+        Path::new("dir/vid.mkv")
+    );
+}
+
+#[test]
+fn read_gif_apng_animation() {
+    let mut gif = b"GIF89a\x02\x00\x02\x00\x80\x00\x00".to_vec();
+    gif.extend([0; 6]); // global color table
+    gif.extend(b"\x21\xFF\x0bNETSCAPE2.0\x03\x01\x02\x00\x00");
+    for _ in 0..3 {
+        gif.extend(b"\x21\xF9\x04\x00\x0a\x00\x00\x00"); // graphic control
+        gif.extend(b"\x2C\x00\x00\x00\x00\x02\x00\x02\x00\x00\x02\x02\x44\x01\x00");
+    }
+    gif.push(0x3B);
+    assert_eq!(
+        read_animation(&gif),
+        Some(Animation {
+            frames: 3,
+            plays: 3
+        })
+    );
+
+    let mut apng = b"\x89PNG\r\n\x1a\n".to_vec();
+    apng.extend(b"\x00\x00\x00\x0dIHDR");
+    apng.extend([0; 13 + 4]);
+    apng.extend(b"\x00\x00\x00\x08acTL\x00\x00\x00\x18\x00\x00\x00\x00");
+    assert_eq!(
+        read_animation(&apng),
+        Some(Animation {
+            frames: 24,
+            plays: 0
+        })
+    );
+
+    // single frame
+    let still = [&apng[..33], b"\x00\x00\x00\x00IDAT"].concat();
+    assert_eq!(read_animation(&still), None);
+}
+
+#[test]
+fn trim_duration() {
+    let mut probe = Ffprobe {
+        duration: Ok(Duration::from_secs(600)),
+        has_audio: false,
+        max_audio_channels: None,
+        fps: Ok(24.0),
+        resolution: None,
+        is_image: false,
+        animation: None,
+        trimmed_from: None,
+        pix_fmt: None,
+        field_order: None,
+        size: None,
+        streams: vec![],
+    };
+    probe.trim(Duration::from_secs(500), Some(Duration::from_secs(300)));
+    assert_eq!(probe.duration, Ok(Duration::from_secs(100)));
+    assert_eq!(probe.trimmed_from, Some(Duration::from_secs(600)));
+    assert_eq!(probe.nframes(), Ok(2400));
+
+    probe.duration = Ok(Duration::from_secs(600));
+    probe.trim(Duration::from_secs(600), None);
+    assert!(probe.duration.is_err(), "start after the end");
+}
+
+#[test]
+fn select_video_stream() {
+    let video = |fps: f64, w: u32, h: u32| StreamInfo {
+        codec_type: "video".into(),
+        codec_name: None,
+        language: None,
+        bit_rate: None,
+        video: Some(VideoStreamInfo {
+            fps: Some(fps),
+            resolution: Some((w, h)),
+            pix_fmt: Some("yuv420p".into()),
+            field_order: None,
+        }),
+    };
+    let mut probe = Ffprobe {
+        duration: Ok(Duration::from_secs(60)),
+        has_audio: false,
+        max_audio_channels: None,
+        fps: Ok(24.0),
+        resolution: Some((1920, 1080)),
+        is_image: false,
+        animation: None,
+        trimmed_from: None,
+        pix_fmt: Some("yuv420p".into()),
+        field_order: None,
+        size: None,
+        streams: vec![video(24.0, 1920, 1080), video(30.0, 1280, 720)],
+    };
+    probe.select_video_stream(1);
+    assert_eq!(probe.fps, Ok(30.0));
+    assert_eq!(probe.resolution, Some((1280, 720)));
+
+    probe.select_video_stream(2);
+    let err = probe.fps.unwrap_err().to_string();
+    assert!(err.contains("input has 2 video stream(s)"), "{err}");
+    assert!(probe.duration.is_err());
+}
@@ -0,0 +1,225 @@
+//! Source noise measurement & matched denoise + film-grain synthesis settings.
+use crate::{
+    error::Error,
+    process::{CommandJobEnv, program},
+};
+use anyhow::Context;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::{LazyLock, Mutex},
+    time::Duration,
+};
+use tracing::{debug, info};
+
+/// Denoiser used to measure noise. Noise is the difference between the source & this.
+const MEASURE_DENOISE: &str = "hqdn3d=4:3:6:4.5";
+
+/// Number of frames analysed by [`measure_noise`].
+const MEASURE_FRAMES: u32 = 240;
+
+/// Denoise & film-grain settings for a noise level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Settings {
+    /// svt-av1 `film-grain` strength.
+    pub film_grain: u8,
+    /// Mild denoise filter applied before encoding, grain synthesis replaces the removed noise.
+    pub denoise: &'static str,
+}
+
+/// Settings by measured noise, from noisiest to cleanest.
+///
+/// Noise is measured as the PSNR-Y of the source vs a denoised copy,
+/// lower PSNR means more noise removed by the denoiser.
+///
+/// | PSNR-Y  | film-grain | denoise                |
+/// |---------|------------|------------------------|
+/// | < 34    | 16         | hqdn3d=3:3:6:6         |
+/// | 34-38   | 12         | hqdn3d=2:2:4:4         |
+/// | 38-42   | 8          | hqdn3d=1.5:1.5:3:3     |
+/// | 42-45   | 4          | hqdn3d=1:1:2:2         |
+/// | >= 45   | clean, no denoise or grain synthesis |
+const MAPPING: &[(f32, Settings)] = &[
+    (
+        34.0,
+        Settings {
+            film_grain: 16,
+            denoise: "hqdn3d=3:3:6:6",
+        },
+    ),
+    (
+        38.0,
+        Settings {
+            film_grain: 12,
+            denoise: "hqdn3d=2:2:4:4",
+        },
+    ),
+    (
+        42.0,
+        Settings {
+            film_grain: 8,
+            denoise: "hqdn3d=1.5:1.5:3:3",
+        },
+    ),
+    (
+        45.0,
+        Settings {
+            film_grain: 4,
+            denoise: "hqdn3d=1:1:2:2",
+        },
+    ),
+];
+
+/// Returns denoise & film-grain settings for a measured noise PSNR-Y,
+/// `None` if the source is clean.
+pub fn settings(noise_psnr: f32) -> Option<Settings> {
+    MAPPING
+        .iter()
+        .find(|(below, _)| noise_psnr < *below)
+        .map(|(_, settings)| *settings)
+}
+
+/// Measure the noise of the `input` `video_stream` as the PSNR-Y of the source vs a
+/// denoised copy.
+///
+/// Analyses frames from 10% into the `duration`, if known, to skip intros.
+/// Results are cached per input.
+pub fn measure_noise(
+    input: &Path,
+    video_stream: usize,
+    duration: Option<Duration>,
+) -> anyhow::Result<f32> {
+    type Key = (PathBuf, usize);
+    static MEASURED: LazyLock<Mutex<HashMap<Key, f32>>> = LazyLock::new(<_>::default);
+
+    let key = (input.to_owned(), video_stream);
+    if let Some(psnr) = MEASURED.lock().unwrap().get(&key) {
+        return Ok(*psnr);
+    }
+
+    let start = duration.unwrap_or_default().mul_f32(0.1);
+    let psnr = measure_psnr_y(input, video_stream, start, MEASURE_FRAMES)?;
+    if let Some(Settings { film_grain, .. }) = settings(psnr) {
+        info!(
+            "noise detected in {} (psnr-y {psnr:.2}), using film-grain={film_grain}",
+            input.display()
+        );
+    }
+
+    MEASURED.lock().unwrap().insert(key, psnr);
+    Ok(psnr)
+}
+
+/// Estimate an svt-av1 `film-grain` level for the `input` `video_stream`, `None` if the
+/// source is clean.
+///
+/// Measures noise in samples at 25%, 50% & 75% of the `duration`, if known, mapping
+/// the median PSNR-Y like [`settings`]. Results are cached per input.
+pub fn estimate_film_grain(
+    input: &Path,
+    video_stream: usize,
+    duration: Option<Duration>,
+) -> anyhow::Result<Option<u8>> {
+    type Key = (PathBuf, usize);
+    static ESTIMATED: LazyLock<Mutex<HashMap<Key, Option<u8>>>> = LazyLock::new(<_>::default);
+
+    let key = (input.to_owned(), video_stream);
+    if let Some(film_grain) = ESTIMATED.lock().unwrap().get(&key) {
+        return Ok(*film_grain);
+    }
+
+    let mut samples = match duration {
+        Some(duration) => {
+            let mut samples = vec![];
+            for pos in [0.25, 0.5, 0.75] {
+                let start = duration.mul_f32(pos);
+                let psnr = measure_psnr_y(input, video_stream, start, MEASURE_FRAMES / 3)?;
+                samples.push(psnr);
+            }
+            samples
+        }
+        None => vec![measure_psnr_y(
+            input,
+            video_stream,
+            Duration::ZERO,
+            MEASURE_FRAMES,
+        )?],
+    };
+    samples.sort_by(f32::total_cmp);
+    let psnr = samples[samples.len() / 2];
+    let film_grain = settings(psnr).map(|s| s.film_grain);
+    info!(
+        "{} noise psnr-y {psnr:.2}, using film-grain={}",
+        input.display(),
+        film_grain.unwrap_or(0)
+    );
+
+    ESTIMATED.lock().unwrap().insert(key, film_grain);
+    Ok(film_grain)
+}
+
+/// Measure the PSNR-Y of `frames` of the `input` `video_stream` from `start` vs a
+/// denoised copy.
+fn measure_psnr_y(
+    input: &Path,
+    video_stream: usize,
+    start: Duration,
+    frames: u32,
+) -> anyhow::Result<f32> {
+    let output = Command::new(program::ffmpeg())
+        .job_env()
+        .arg("-ss")
+        .arg(start.as_secs_f32().to_string())
+        .arg("-i")
+        .arg(input)
+        .arg("-map")
+        .arg(format!("0:v:{video_stream}"))
+        .arg("-vf")
+        .arg(format!("split[a][b];[b]{MEASURE_DENOISE}[c];[a][c]psnr"))
+        .arg("-frames:v")
+        .arg(frames.to_string())
+        .args(["-an", "-sn", "-dn", "-f", "null", "-"])
+        .output()
+        .map_err(Error::spawn("ffmpeg noise measurement"))?;
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let psnr = parse_psnr_y(&stderr).context("could not parse ffmpeg psnr output")?;
+    debug!("noise psnr-y {psnr} from {start:?}");
+    Ok(psnr)
+}
+
+/// Parse the PSNR-Y from ffmpeg psnr filter output, e.g.
+/// "[Parsed_psnr_2 @ 0x5555] PSNR y:38.513 u:44.121 v:44.870 average:39.844 min:37.1 max:40.9".
+fn parse_psnr_y(stderr: &str) -> Option<f32> {
+    let line = stderr.lines().rev().find(|l| l.contains("PSNR y:"))?;
+    let y = &line[line.find("PSNR y:")? + "PSNR y:".len()..];
+    match y.split_whitespace().next()? {
+        // identical frames, i.e. no noise
+        "inf" => Some(f32::INFINITY),
+        y => y.parse().ok(),
+    }
+}
+
+#[test]
+fn parse_psnr() {
+    const FFMPEG_OUT: &str = "\
+frame=  240 fps=110 q=-0.0 Lsize=N/A time=00:00:10.01 bitrate=N/A speed=4.6x
+[Parsed_psnr_2 @ 0x5555] PSNR y:38.513 u:44.121 v:44.870 average:39.844 min:37.105 max:40.912
+";
+    assert_eq!(parse_psnr_y(FFMPEG_OUT), Some(38.513));
+    assert_eq!(
+        parse_psnr_y("[Parsed_psnr_2 @ 0x5555] PSNR y:inf u:inf v:inf average:inf"),
+        Some(f32::INFINITY)
+    );
+    assert_eq!(parse_psnr_y("frame=  240 fps=110"), None);
+}
+
+#[test]
+fn settings_mapping() {
+    assert_eq!(settings(30.0).map(|s| s.film_grain), Some(16));
+    assert_eq!(settings(38.5).map(|s| s.film_grain), Some(8));
+    assert_eq!(settings(44.9).map(|s| s.film_grain), Some(4));
+    assert_eq!(settings(45.0), None);
+    assert_eq!(settings(f32::INFINITY), None);
+}
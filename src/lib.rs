@@ -0,0 +1,40 @@
+//! AV1 encoding with fast VMAF sampling.
+//!
+//! The `ab-av1` command line tool can also be embedded as a library, see
+//! [`CrfSearch`], [`AutoEncode`] & [`Vmaf`]. The crf-search logic is also available
+//! without encoding as [`CrfPlanner`], with C & wasm bindings in [`ffi`].
+mod api;
+mod chunk;
+#[doc(hidden)]
+pub mod cli;
+mod command;
+mod compat;
+mod config;
+mod console_ext;
+mod crop;
+mod cuda;
+mod disk_space;
+mod encoder_stats;
+mod error;
+pub mod ffi;
+mod ffmpeg;
+mod ffprobe;
+mod float;
+mod grain;
+mod log;
+mod metric;
+mod process;
+mod sample;
+mod scheduler;
+mod sink;
+mod stdin;
+mod telecine;
+mod temporary;
+mod vmaf;
+mod xpsnr;
+
+pub use api::{
+    AutoEncode, AutoEncodeResult, CrfPlan, CrfPlanner, CrfSearch, CrfSearchResult, Progress, Vmaf,
+    VmafScore,
+};
+pub use error::Error;
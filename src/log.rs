@@ -1,19 +1,154 @@
+//! Logging, using tracing spans for each phase, e.g. probe, sample, encode & vmaf.
+//!
+//! `log` records of dependencies are forwarded to tracing.
+use anyhow::Context as _;
+use clap::Parser;
+use clap_verbosity_flag::{Verbosity, VerbosityFilter};
+use futures_util::Stream;
 use indicatif::HumanDuration;
-use log::{Level, info, log_enabled};
-use std::time::{Duration, Instant};
+use std::{
+    fs::File,
+    io::{self, IsTerminal},
+    path::PathBuf,
+    pin::Pin,
+    sync::{Mutex, OnceLock},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+use tracing::{Level, Span, info, level_filters::LevelFilter};
+use tracing_subscriber::{
+    EnvFilter, Layer, filter::Targets, layer::SubscriberExt, util::SubscriberInitExt,
+};
+
+/// Target of ffmpeg stderr lines, logged at trace level.
+pub const FFMPEG_STDERR: &str = "ab_av1::ffmpeg::stderr";
+
+static VERBOSITY: OnceLock<VerbosityFilter> = OnceLock::new();
+
+#[derive(Debug, Parser, Clone)]
+#[group(skip)]
+pub struct Args {
+    /// Log to stderr, -v info, -vv debug, e.g. ffmpeg commands, & -vvv trace, e.g.
+    /// ffmpeg stderr. Also prints crf-search attempts, -vv including each sample.
+    ///
+    /// By default info is logged only if stderr is not a terminal. RUST_LOG, if set,
+    /// takes precedence, e.g. RUST_LOG=ab_av1::vmaf=debug.
+    #[command(flatten)]
+    pub verbose: Verbosity,
+
+    /// Write a trace log to this file, including the full stderr of ffmpeg processes,
+    /// for post-mortem debugging of failures. The file is overwritten.
+    #[arg(long, global = true)]
+    pub log_file: Option<PathBuf>,
+}
+
+/// Initialize logging to stderr & any --log-file. Should be called once.
+pub fn init(Args { verbose, log_file }: Args) -> anyhow::Result<()> {
+    let _ = VERBOSITY.set(verbose.filter());
+
+    // progress bars hide logs on terminals by default
+    let stderr_filter = EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| EnvFilter::new(format!("ab_av1={}", stderr_level(verbose.filter()))));
+    let stderr = tracing_subscriber::fmt::layer()
+        .with_writer(io::stderr)
+        .with_ansi(io::stderr().is_terminal())
+        .with_filter(stderr_filter);
+
+    let file = match log_file {
+        Some(path) => {
+            let file =
+                File::create(&path).with_context(|| format!("--log-file {}", path.display()))?;
+            let layer = tracing_subscriber::fmt::layer()
+                .with_writer(Mutex::new(file))
+                .with_ansi(false)
+                .with_filter(
+                    Targets::new()
+                        .with_target("ab_av1", Level::TRACE)
+                        .with_default(Level::INFO),
+                );
+            Some(layer)
+        }
+        None => None,
+    };
+
+    tracing_subscriber::registry()
+        .with(stderr)
+        .with(file)
+        .try_init()?;
+    Ok(())
+}
+
+/// Returns true if at least `count` -v flags are set, e.g. `verbose(2)` for -vv.
+pub fn verbose(count: u8) -> bool {
+    let verbosity = VERBOSITY.get().copied().unwrap_or(VerbosityFilter::Error);
+    let verbose = match verbosity {
+        VerbosityFilter::Off | VerbosityFilter::Error => 0,
+        VerbosityFilter::Warn => 1,
+        VerbosityFilter::Info => 2,
+        VerbosityFilter::Debug => 3,
+        VerbosityFilter::Trace => 4,
+    };
+    verbose >= count
+}
+
+/// Returns the stderr log level of the `verbosity`, where -v is
+/// [`VerbosityFilter::Warn`] as errors are printed regardless.
+fn stderr_level(verbosity: VerbosityFilter) -> LevelFilter {
+    match verbosity {
+        VerbosityFilter::Off => LevelFilter::OFF,
+        VerbosityFilter::Error if io::stderr().is_terminal() => LevelFilter::OFF,
+        VerbosityFilter::Error | VerbosityFilter::Warn => LevelFilter::INFO,
+        VerbosityFilter::Info => LevelFilter::DEBUG,
+        VerbosityFilter::Debug | VerbosityFilter::Trace => LevelFilter::TRACE,
+    }
+}
+
+pin_project_lite::pin_project! {
+    /// Stream entering a span whenever polled, see [`InSpan::in_span`].
+    #[must_use = "streams do nothing unless polled"]
+    pub struct SpanStream<S> {
+        #[pin]
+        inner: S,
+        span: Span,
+    }
+}
+
+impl<S: Stream> Stream for SpanStream<S> {
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+        let _enter = this.span.enter();
+        this.inner.poll_next(cx)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+pub trait InSpan: Stream + Sized {
+    /// Returns a stream logging within `span`, like [`tracing::Instrument`] for futures.
+    fn in_span(self, span: Span) -> SpanStream<Self> {
+        SpanStream { inner: self, span }
+    }
+}
+
+impl<S: Stream> InSpan for S {}
 
 /// Struct that info logs progress messages on a stream action like encoding.
 #[derive(Debug)]
 pub struct ProgressLogger {
-    target: &'static str,
+    action: &'static str,
     start: Instant,
     log_count: u32,
 }
 
 impl ProgressLogger {
-    pub fn new(target: &'static str, start: Instant) -> Self {
+    /// New logger of an `action`, e.g. "vmaf".
+    pub fn new(action: &'static str, start: Instant) -> Self {
         Self {
-            target,
+            action,
             start,
             log_count: 0,
         }
@@ -24,7 +159,7 @@ impl ProgressLogger {
     /// * `complete` the duration that has been completed at this time
     /// * `fps` frames per second
     pub fn update(&mut self, total: Duration, completed: Duration, fps: f32) {
-        if log_enabled!(Level::Info) && completed > Duration::ZERO {
+        if tracing::enabled!(Level::INFO) && completed > Duration::ZERO {
             let done = completed.as_secs_f64() / total.as_secs_f64();
 
             let elapsed = self.start.elapsed();
@@ -39,8 +174,8 @@ impl ProgressLogger {
 
             let eta = Duration::from_secs_f64(elapsed.as_secs_f64() / done).saturating_sub(elapsed);
             info!(
-                target: self.target,
-                "{:.0}%, {fps} fps, eta {}",
+                "{} {:.0}%, {fps} fps, eta {}",
+                self.action,
                 done * 100.0,
                 HumanDuration(eta)
             );
@@ -0,0 +1,213 @@
+//! External metric plugin logic.
+//!
+//! A metric plugin is an executable run once per sample with the reference & distorted
+//! file paths. It must print a json object to stdout, as the last line if other output
+//! is printed, with either:
+//! * `"score"`: The pooled score of the distorted video.
+//! * `"frames"`: Per-frame scores, pooled by ab-av1 using --vmaf-pool, e.g. the mean.
+//!
+//! Higher scores must mean better quality. A non-zero exit code is treated as an error.
+use crate::{
+    command::args::VmafPool,
+    process::{CommandExt, CommandJobEnv, long_path, program},
+};
+use anyhow::{Context, bail, ensure};
+use std::{
+    ffi::{OsStr, OsString},
+    path::Path,
+    process::Stdio,
+};
+use tokio::process::Command;
+use tracing::{debug, info};
+
+/// Env var set for plugins to the vfilter that should be applied to the reference, if any.
+pub const REFERENCE_VFILTER_ENV: &str = "AB_AV1_REFERENCE_VFILTER";
+
+/// Run the external metric `cmd` & return the pooled score.
+///
+/// `cmd` arguments are whitespace separated. `{ref}` & `{dist}` are replaced with
+/// the reference & distorted paths, otherwise the paths are appended.
+/// Per-frame scores are pooled using `pool`.
+#[tracing::instrument(name = "metric", skip_all)]
+pub async fn run(
+    cmd: &str,
+    reference: &Path,
+    distorted: &Path,
+    reference_vfilter: Option<&str>,
+    pool: VmafPool,
+) -> anyhow::Result<f32> {
+    info!(
+        "metric {} vs reference {}",
+        distorted.file_name().and_then(|n| n.to_str()).unwrap_or(""),
+        reference.file_name().and_then(|n| n.to_str()).unwrap_or(""),
+    );
+
+    let mut cmd = command(cmd, reference, distorted, reference_vfilter)?;
+    let program = cmd.as_std().get_program().to_string_lossy().into_owned();
+    debug!("cmd `{}`", cmd.to_cmd_str());
+
+    let output = cmd
+        .output()
+        .await
+        .with_context(|| format!("metric-cmd {program}"))?;
+    ensure!(
+        output.status.success(),
+        "metric-cmd {program} failed ({}): {}",
+        output.status,
+        String::from_utf8_lossy(&output.stderr).trim()
+    );
+    parse_score(&String::from_utf8_lossy(&output.stdout), pool)
+}
+
+/// Returns the external metric command [`run`] runs.
+pub fn command(
+    cmd: &str,
+    reference: &Path,
+    distorted: &Path,
+    reference_vfilter: Option<&str>,
+) -> anyhow::Result<Command> {
+    let args = command_args(cmd, &long_path(reference), &long_path(distorted));
+    let (program, args) = args.split_first().context("empty --metric-cmd")?;
+    let program = program.to_string_lossy();
+
+    let mut cmd = Command::new(program::locate(&program));
+    cmd.job_env()
+        .kill_on_drop(true)
+        .args(args)
+        .stdin(Stdio::null());
+    match reference_vfilter {
+        Some(vf) => cmd.env(REFERENCE_VFILTER_ENV, vf),
+        None => cmd.env_remove(REFERENCE_VFILTER_ENV),
+    };
+    Ok(cmd)
+}
+
+/// Paths are kept as `OsStr`, so non-unicode paths are passed unchanged.
+fn command_args(cmd: &str, reference: &OsStr, distorted: &OsStr) -> Vec<OsString> {
+    let mut placeholders = false;
+    let mut args: Vec<_> = cmd
+        .split_whitespace()
+        .map(|mut arg| {
+            let mut out = OsString::new();
+            while let Some(idx) = arg.find('{') {
+                let (before, rest) = arg.split_at(idx);
+                out.push(before);
+                let (placeholder, value) = [("{ref}", reference), ("{dist}", distorted)]
+                    .into_iter()
+                    .find(|(p, _)| rest.starts_with(p))
+                    .unwrap_or(("{", OsStr::new("{")));
+                placeholders |= placeholder != "{";
+                out.push(value);
+                arg = &rest[placeholder.len()..];
+            }
+            out.push(arg);
+            out
+        })
+        .collect();
+    if !placeholders {
+        args.extend([reference.to_owned(), distorted.to_owned()]);
+    }
+    args
+}
+
+/// Parse plugin json stdout, pooling any per-frame scores using `pool`.
+fn parse_score(stdout: &str, pool: VmafPool) -> anyhow::Result<f32> {
+    #[derive(serde::Deserialize)]
+    struct Output {
+        score: Option<f32>,
+        frames: Option<Vec<f32>>,
+    }
+
+    let stdout = stdout.trim();
+    let out: Output = serde_json::from_str(stdout)
+        .or_else(|_| serde_json::from_str(stdout.lines().last().unwrap_or_default()))
+        .context("metric-cmd output is not a json object")?;
+    match out {
+        Output {
+            score: Some(score), ..
+        } => Ok(score),
+        Output {
+            frames: Some(frames),
+            ..
+        } if !frames.is_empty() => Ok(pool_frames(frames, pool)),
+        _ => bail!("metric-cmd output has no \"score\" or \"frames\""),
+    }
+}
+
+/// Pool non-empty per-frame scores like [`crate::vmaf::FrameStats`], but exactly as
+/// plugin scores may use any range.
+fn pool_frames(mut frames: Vec<f32>, pool: VmafPool) -> f32 {
+    let n = frames.len() as f64;
+    // nearest-rank percentile
+    let mut percentile = |p: f64| {
+        frames.sort_by(f32::total_cmp);
+        let rank = ((p / 100.0 * n).ceil() as usize).clamp(1, frames.len());
+        frames[rank - 1]
+    };
+    match pool {
+        VmafPool::Mean => (frames.iter().map(|s| *s as f64).sum::<f64>() / n) as _,
+        // offset by 1 like libvmaf to handle zero scores
+        VmafPool::HarmonicMean => {
+            let harmonic_sum: f64 = frames.iter().map(|s| 1.0 / (*s as f64 + 1.0)).sum();
+            (n / harmonic_sum - 1.0) as _
+        }
+        VmafPool::P5 => percentile(5.0),
+        VmafPool::P1 => percentile(1.0),
+        VmafPool::Min => frames.iter().copied().fold(f32::INFINITY, f32::min),
+    }
+}
+
+#[test]
+fn command_args_placeholders() {
+    assert_eq!(
+        command_args("my-metric --fast", "ref.mkv".as_ref(), "dist.mkv".as_ref()),
+        ["my-metric", "--fast", "ref.mkv", "dist.mkv"]
+    );
+    assert_eq!(
+        command_args(
+            "my-metric -r {ref} -d {dist}",
+            "ref.mkv".as_ref(),
+            "my vid.mkv".as_ref()
+        ),
+        ["my-metric", "-r", "ref.mkv", "-d", "my vid.mkv"]
+    );
+    assert_eq!(
+        command_args(
+            "my-metric --in={ref},{dist} {x}",
+            "a.mkv".as_ref(),
+            "b.mkv".as_ref()
+        ),
+        ["my-metric", "--in=a.mkv,b.mkv", "{x}"]
+    );
+}
+
+#[test]
+fn parse_plugin_output() {
+    let mean = VmafPool::Mean;
+    assert_eq!(parse_score(r#"{"score": 93.5}"#, mean).unwrap(), 93.5);
+    assert_eq!(
+        parse_score("loading model...\n{\"frames\": [90, 92, 97]}\n", mean).unwrap(),
+        93.0
+    );
+    assert!(parse_score(r#"{"frames": []}"#, mean).is_err());
+    assert!(parse_score("93.5 nope", mean).is_err());
+
+    // already pooled scores are used as is
+    assert_eq!(
+        parse_score(r#"{"score": 93.5}"#, VmafPool::Min).unwrap(),
+        93.5
+    );
+}
+
+#[test]
+fn pool_plugin_frames() {
+    // 100 frames -19..=80, as plugin scores may be outside the VMAF 0-100 range
+    let frames: Vec<f32> = (1..=100).rev().map(|s| s as f32 - 20.0).collect();
+    assert_eq!(pool_frames(frames.clone(), VmafPool::Mean), 30.5);
+    assert_eq!(pool_frames(frames.clone(), VmafPool::P5), -15.0);
+    assert_eq!(pool_frames(frames.clone(), VmafPool::P1), -19.0);
+    assert_eq!(pool_frames(frames.clone(), VmafPool::Min), -19.0);
+
+    let harmonic = pool_frames(vec![0.0, 3.0], VmafPool::HarmonicMean);
+    assert_eq!(harmonic, 0.6);
+}
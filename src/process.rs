@@ -1,5 +1,10 @@
 pub mod child;
+pub mod job_env;
+pub mod program;
 
+pub use job_env::CommandJobEnv;
+
+use crate::{encoder_stats::EncoderStats, log::FFMPEG_STDERR};
 use anyhow::{anyhow, ensure};
 use std::{
     borrow::Cow,
@@ -16,8 +21,10 @@ use time::macros::format_description;
 use tokio::process::Child;
 use tokio_process_stream::{Item, ProcessChunkStream};
 use tokio_stream::Stream;
+use tracing::{Level, Span, info_span, trace};
 
 pub fn ensure_success(name: &'static str, out: &Output) -> anyhow::Result<()> {
+    trace_stderr(&out.stderr);
     ensure!(
         out.status.success(),
         "{name} exit code {}\n---stderr---\n{}\n------------",
@@ -53,6 +60,15 @@ pub fn exit_ok_stderr(
     exit_ok(name, done).map_err(|e| cmd_err(e, cmd_str, stderr))
 }
 
+/// Trace log each `stderr` line, e.g. for --log-file.
+fn trace_stderr(stderr: &[u8]) {
+    if tracing::enabled!(target: FFMPEG_STDERR, Level::TRACE) {
+        for line in String::from_utf8_lossy(stderr).lines() {
+            trace!(target: FFMPEG_STDERR, "{}", line.trim_end());
+        }
+    }
+}
+
 pub fn cmd_err(err: impl Display, cmd_str: &str, stderr: &Chunks) -> anyhow::Error {
     anyhow!(
         "{err}\n----cmd-----\n{cmd_str}\n---stderr---\n{}\n------------",
@@ -66,6 +82,10 @@ pub enum FfmpegOut {
         frame: u64,
         fps: f32,
         time: Duration,
+        /// Output bitrate kbit/s, if known.
+        bitrate: Option<f32>,
+        /// Processing speed as a multiple of realtime, if known.
+        speed: Option<f32>,
     },
     StreamSizes {
         video: u64,
@@ -73,6 +93,8 @@ pub enum FfmpegOut {
         subtitle: u64,
         other: u64,
     },
+    /// Encoder summary, parsed from stderr after successfully exiting.
+    EncoderStats(EncoderStats),
 }
 
 impl FfmpegOut {
@@ -90,6 +112,8 @@ impl FfmpegOut {
                 frame,
                 fps,
                 time: Duration::new(h as u64 * 60 * 60 + m as u64 * 60 + s as u64, ns),
+                bitrate: parse_label_substr("bitrate=", line).and_then(parse_bitrate),
+                speed: parse_label_substr("speed=", line).and_then(parse_speed),
             });
         }
         if line.starts_with("video:") && line.contains("muxing overhead") {
@@ -111,12 +135,81 @@ impl FfmpegOut {
         FfmpegOutStream {
             chunk_stream: ProcessChunkStream::from(child),
             chunks: <_>::default(),
+            progress: <_>::default(),
             name,
             cmd_str,
+            span: info_span!("encode", process = name),
         }
     }
 }
 
+/// Parser of ffmpeg `-progress pipe:1` output.
+///
+/// Progress is written as blocks of "key=value" lines, each block ending with a
+/// "progress=continue" or "progress=end" line. Unlike the stderr stats line this
+/// format is stable & unaffected by locale.
+#[derive(Debug, Default)]
+pub struct ProgressParser {
+    /// Incomplete trailing line.
+    partial: Vec<u8>,
+    frame: Option<u64>,
+    fps: Option<f32>,
+    time: Option<Duration>,
+    bitrate: Option<f32>,
+    speed: Option<f32>,
+}
+
+impl ProgressParser {
+    /// Parse a stdout chunk, returning the latest completed progress block if any.
+    pub fn push(&mut self, chunk: &[u8]) -> Option<FfmpegOut> {
+        self.partial.extend(chunk);
+
+        let mut out = None;
+        while let Some(eol) = self.partial.iter().position(|b| *b == b'\n') {
+            let line: Vec<_> = self.partial.drain(..=eol).collect();
+            let line = String::from_utf8_lossy(&line);
+            let Some((key, value)) = line.trim().split_once('=') else {
+                continue;
+            };
+            let value = value.trim();
+            match key {
+                "frame" => self.frame = value.parse().ok(),
+                "fps" => self.fps = value.parse().ok(),
+                "out_time_us" => self.time = value.parse().ok().map(Duration::from_micros),
+                "bitrate" => self.bitrate = parse_bitrate(value),
+                "speed" => self.speed = parse_speed(value),
+                "progress" => {
+                    if let Some(progress) = self.finish_block() {
+                        out = Some(progress);
+                    }
+                }
+                _ => {}
+            }
+        }
+        out
+    }
+
+    fn finish_block(&mut self) -> Option<FfmpegOut> {
+        let Self {
+            frame,
+            fps,
+            time,
+            bitrate,
+            speed,
+            ..
+        } = self;
+        let progress = FfmpegOut::Progress {
+            frame: frame.take().unwrap_or_default(),
+            fps: fps.take().unwrap_or_default(),
+            // "N/A" before the first output frame
+            time: time.take()?,
+            bitrate: bitrate.take(),
+            speed: speed.take(),
+        };
+        Some(progress)
+    }
+}
+
 /// Parse a ffmpeg `label=  value ` type substring.
 fn parse_label_substr<'a>(label: &str, line: &'a str) -> Option<&'a str> {
     let line = &line[line.find(label)? + label.len()..];
@@ -131,6 +224,16 @@ fn parse_label_substr<'a>(label: &str, line: &'a str) -> Option<&'a str> {
     Some(&line[val_start..val_end])
 }
 
+/// Parse e.g. "1193.2kbits/s".
+fn parse_bitrate(val: &str) -> Option<f32> {
+    val.strip_suffix("kbits/s")?.trim().parse().ok()
+}
+
+/// Parse e.g. "3.94x".
+fn parse_speed(val: &str) -> Option<f32> {
+    val.strip_suffix('x')?.trim().parse().ok()
+}
+
 fn parse_label_size(label: &str, line: &str) -> Option<u64> {
     let size = parse_label_substr(label, line)?;
     let kbs: u64 = size.strip_suffix("kB")?.parse().ok()?;
@@ -162,6 +265,10 @@ impl Chunks {
         }
 
         self.out.extend(chunk);
+        // skip progress lines, overwritten by the next push
+        if !chunk.ends_with(b"\r") {
+            trace_stderr(chunk);
+        }
 
         // if too long remove lines until small
         while self.out.len() > MAX_LEN {
@@ -200,6 +307,19 @@ impl Chunks {
         self.out.splice(..next_eol + 1, []);
     }
 
+    /// Stored output as a trimmed string.
+    pub fn to_trimmed_string(&self) -> String {
+        String::from_utf8_lossy(&self.out).trim().to_owned()
+    }
+
+    /// Stored lines, oldest first.
+    pub fn lines(&self) -> impl Iterator<Item = &str> {
+        self.out
+            .split(|b| *b == b'\n')
+            .flat_map(|l| l.split(|b| *b == b'\r'))
+            .filter_map(|l| std::str::from_utf8(l).ok())
+    }
+
     pub fn rfind_line(&self, predicate: impl Fn(&str) -> bool) -> Option<&str> {
         self.rfind_line_map(|line| predicate(line).then_some(line))
     }
@@ -233,6 +353,8 @@ pin_project_lite::pin_project! {
         name: &'static str,
         cmd_str: String,
         chunks: Chunks,
+        progress: ProgressParser,
+        span: Span,
     }
 }
 
@@ -249,6 +371,8 @@ impl Stream for FfmpegOutStream {
     type Item = anyhow::Result<FfmpegOut>;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let span = self.span.clone();
+        let _enter = span.enter();
         loop {
             match ready!(self.as_mut().project().chunk_stream.poll_next(cx)) {
                 Some(item) => match item {
@@ -258,13 +382,21 @@ impl Stream for FfmpegOutStream {
                             return Poll::Ready(Some(Ok(out)));
                         }
                     }
-                    Item::Stdout(_) => {}
+                    Item::Stdout(chunk) => {
+                        if let Some(out) = self.progress.push(&chunk) {
+                            return Poll::Ready(Some(Ok(out)));
+                        }
+                    }
                     Item::Done(code) => {
                         if let Err(err) =
                             exit_ok_stderr(self.name, code, &self.cmd_str, &self.chunks)
                         {
                             return Poll::Ready(Some(Err(err)));
                         }
+                        let stderr = String::from_utf8_lossy(&self.chunks.out);
+                        if let Some(stats) = EncoderStats::parse(&stderr) {
+                            return Poll::Ready(Some(Ok(FfmpegOut::EncoderStats(stats))));
+                        }
                     }
                 },
                 None => return Poll::Ready(None),
@@ -286,6 +418,8 @@ fn parse_ffmpeg_progress_chunk() {
             frame: 288,
             fps: 94.0,
             time: Duration::new(60 * 60 + 23 * 60 + 12, 340_000_000),
+            bitrate: None,
+            speed: Some(3.94),
         })
     );
 }
@@ -299,6 +433,31 @@ fn parse_ffmpeg_progress_line() {
             frame: 161,
             fps: 73.0,
             time: Duration::new(6, 710_000_000),
+            bitrate: Some(1193201.6),
+            speed: Some(3.03),
+        })
+    );
+}
+
+#[test]
+fn parse_ffmpeg_progress_pipe() {
+    let mut parser = ProgressParser::default();
+    let out = "frame=0\nfps=0.00\nstream_0_0_q=0.0\nbitrate=N/A\ntotal_size=N/A\n\
+               out_time_us=N/A\nout_time_ms=N/A\nout_time=N/A\ndup_frames=0\ndrop_frames=0\n\
+               speed=N/A\nprogress=continue\nframe=161\nfps=73.41\nstream_0_0_q=28.0\n\
+               bitrate=1193.2kbits/s\ntotal_size=1000";
+    // no output time yet & 2nd block incomplete
+    assert_eq!(parser.push(out.as_bytes()), None);
+    let out = "448\nout_time_us=6710000\nout_time_ms=6710000\nout_time=00:00:06.710000\n\
+               dup_frames=0\ndrop_frames=0\nspeed=3.03x\nprogress=continue\n";
+    assert_eq!(
+        parser.push(out.as_bytes()),
+        Some(FfmpegOut::Progress {
+            frame: 161,
+            fps: 73.41,
+            time: Duration::new(6, 710_000_000),
+            bitrate: Some(1193.2),
+            speed: Some(3.03),
         })
     );
 }
@@ -309,6 +468,28 @@ fn parse_ffmpeg_progress_na_time() {
     assert_eq!(FfmpegOut::try_parse(out), None);
 }
 
+#[test]
+fn verbatim_long_paths() {
+    let dir = "C:\\Users\\me\\Videos\\".to_owned() + &"long dir name\\".repeat(20);
+    let path = format!("{dir}vid.mkv");
+    assert_eq!(verbatim_path(&path), Some(format!("\\\\?\\{path}")));
+    assert_eq!(verbatim_path("C:\\Videos\\vid.mkv"), None, "short path");
+    assert_eq!(
+        verbatim_path(&format!("\\\\?\\{path}")),
+        None,
+        "already verbatim"
+    );
+
+    let unc = format!("\\\\nas\\share/{}", "long dir name/".repeat(20));
+    assert_eq!(
+        verbatim_path(&unc),
+        Some(format!(
+            "\\\\?\\UNC\\nas\\share\\{}",
+            "long dir name\\".repeat(20)
+        ))
+    );
+}
+
 #[test]
 fn parse_ffmpeg_stream_sizes() {
     let out = "video:2897022kB audio:537162kB subtitle:0kB other streams:0kB global headers:0kB muxing overhead: 0.289700%\n";
@@ -366,15 +547,18 @@ impl CommandExt for tokio::process::Command {
     }
 
     fn to_cmd_str(&self) -> String {
+        // quote args with spaces, e.g. windows paths, so the cmd can be copy pasted
         let cmd = self.as_std();
-        cmd.get_args().map(|a| a.to_string_lossy()).fold(
-            cmd.get_program().to_string_lossy().to_string(),
-            |mut all, next| {
-                all.push(' ');
-                all += &next;
-                all
-            },
-        )
+        cmd.get_args()
+            .map(|a| shell_escape::escape(a.to_string_lossy()))
+            .fold(
+                shell_escape::escape(cmd.get_program().to_string_lossy()).into_owned(),
+                |mut all, next| {
+                    all.push(' ');
+                    all += &next;
+                    all
+                },
+            )
     }
 }
 
@@ -395,8 +579,54 @@ impl_arg_string_as_ref!(String);
 impl_arg_string_as_ref!(&'_ String);
 impl_arg_string_as_ref!(&'_ str);
 impl_arg_string_as_ref!(&'_ &'_ str);
-impl_arg_string_as_ref!(&'_ std::path::Path);
-impl_arg_string_as_ref!(&'_ std::path::PathBuf);
+
+impl ArgString for &'_ std::path::Path {
+    fn arg_string(&self) -> Cow<'_, OsStr> {
+        long_path(self)
+    }
+}
+
+impl ArgString for &'_ std::path::PathBuf {
+    fn arg_string(&self) -> Cow<'_, OsStr> {
+        long_path(self)
+    }
+}
+
+/// Windows `MAX_PATH`, longer paths need a `\\?\` prefix for programs, e.g. ffmpeg,
+/// that don't opt in to long path support.
+const MAX_PATH: usize = 260;
+
+/// Returns the `path` argument, on Windows using a verbatim `\\?\` path if it is
+/// longer than `MAX_PATH`. Urls & stdin are returned unchanged.
+pub fn long_path(path: &std::path::Path) -> Cow<'_, OsStr> {
+    if cfg!(windows)
+        && !crate::ffprobe::is_url(path)
+        && !crate::stdin::is_stdin(path)
+        && let Some(verbatim) = std::path::absolute(path)
+            .ok()
+            .and_then(|p| p.to_str().and_then(verbatim_path))
+    {
+        return Cow::Owned(verbatim.into());
+    }
+    Cow::Borrowed(path.as_os_str())
+}
+
+/// Returns the `\\?\` verbatim form of an absolute Windows `path` that is too long
+/// for `MAX_PATH`, `None` if not needed.
+fn verbatim_path(path: &str) -> Option<String> {
+    if path.len() < MAX_PATH || path.starts_with(r"\\?\") || path.starts_with(r"\\.\") {
+        return None;
+    }
+    // verbatim paths are not normalized, so must only use `\` separators
+    let path = path.replace('/', r"\");
+    if let Some(unc) = path.strip_prefix(r"\\") {
+        return Some(format!(r"\\?\UNC\{unc}"));
+    }
+    let bytes = path.as_bytes();
+    let drive =
+        bytes.len() > 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':' && bytes[2] == b'\\';
+    drive.then(|| format!(r"\\?\{path}"))
+}
 
 macro_rules! impl_arg_string_display {
     ($t:ty) => {
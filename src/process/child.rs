@@ -1,4 +1,10 @@
-use log::info;
+//! Spawned child process lifetimes, ensuring children don't outlive ab-av1.
+//!
+//! On unix async children are spawned in their own process group, see [`CommandJobEnv`],
+//! so a terminal Ctrl-C is handled by ab-av1 killing them. On Windows ab-av1 runs in a
+//! job object that kills all children when it exits, even if killed itself.
+//!
+//! [`CommandJobEnv`]: super::CommandJobEnv
 use std::{
     io::IsTerminal,
     mem,
@@ -12,9 +18,61 @@ use tokio::{
     time::{Instant, timeout_at},
 };
 use tokio_process_stream::ProcessChunkStream;
+use tracing::{debug, info};
 
 static RUNNING: LazyLock<Mutex<Vec<ProcessChunkStream>>> = LazyLock::new(<_>::default);
 
+/// Setup so child processes are killed when ab-av1 exits.
+/// Should be called once before any processes are spawned.
+pub fn init() {
+    #[cfg(windows)]
+    kill_on_exit_job();
+}
+
+/// Resolves when ab-av1 should stop, i.e. on Ctrl-C, or SIGTERM & SIGHUP on unix,
+/// or console close, logoff & shutdown on Windows.
+pub async fn interrupted() {
+    #[cfg(unix)]
+    {
+        use signal::unix::{SignalKind, signal};
+        let recv = |kind| async move {
+            match signal(kind) {
+                Ok(mut sig) => _ = sig.recv().await,
+                Err(err) => {
+                    debug!("signal handler: {err}");
+                    std::future::pending().await
+                }
+            }
+        };
+        tokio::select! {
+            _ = signal::ctrl_c() => {}
+            _ = recv(SignalKind::terminate()) => {}
+            _ = recv(SignalKind::hangup()) => {}
+        }
+    }
+    #[cfg(windows)]
+    {
+        use signal::windows;
+        macro_rules! recv {
+            ($sig:expr) => {
+                async {
+                    match $sig {
+                        Ok(mut sig) => _ = sig.recv().await,
+                        Err(_) => std::future::pending().await,
+                    }
+                }
+            };
+        }
+        tokio::select! {
+            _ = signal::ctrl_c() => {}
+            _ = recv!(windows::ctrl_break()) => {}
+            _ = recv!(windows::ctrl_close()) => {}
+            _ = recv!(windows::ctrl_logoff()) => {}
+            _ = recv!(windows::ctrl_shutdown()) => {}
+        }
+    }
+}
+
 /// Add a child process so it may be waited on before exiting.
 pub fn add(mut child: ProcessChunkStream) {
     let mut running = RUNNING.lock().unwrap();
@@ -27,12 +85,32 @@ pub fn add(mut child: ProcessChunkStream) {
     }
 }
 
+/// Terminate all child processes, that were added with [`add`], e.g. after Ctrl-C.
+///
+/// Children dropped earlier were already killed by `kill_on_drop`.
+pub fn kill() {
+    for proc in RUNNING.lock().unwrap().iter_mut() {
+        let Some(child) = proc.child_mut() else {
+            continue;
+        };
+        #[cfg(unix)]
+        if let Some(pid) = child.id() {
+            // SIGTERM the whole process group allowing ffmpeg to release GPU sessions
+            // SAFETY: killpg has no memory safety requirements
+            if unsafe { libc::killpg(pid as _, libc::SIGTERM) } == 0 {
+                continue;
+            }
+        }
+        _ = child.start_kill();
+    }
+}
+
 /// Wait for all child processes, that were added with [`add`], to exit.
 pub async fn wait() {
     // if waiting takes >500ms log what's happening
     let mut log_deadline = Some(Instant::now() + Duration::from_millis(500));
     let procs = mem::take(&mut *RUNNING.lock().unwrap());
-    let mut ctrl_c = pin!(signal::ctrl_c());
+    let mut ctrl_c = pin!(interrupted());
 
     for mut proc in procs {
         if let Some(child) = proc.child_mut() {
@@ -67,6 +145,52 @@ fn log_abort_wait() {
     }
 }
 
+/// Restore the terminal after an interruption, e.g. the cursor hidden by progress bars.
+pub fn restore_terminal() {
+    let term = console::Term::stderr();
+    if term.is_term() {
+        _ = term.show_cursor();
+    }
+}
+
+/// Assign ab-av1 to a job object that kills all processes in it, i.e. all children,
+/// when the last handle is closed on exit.
+#[cfg(windows)]
+fn kill_on_exit_job() {
+    use std::{ffi::c_void, ptr};
+    use windows_sys::Win32::System::{
+        JobObjects::{
+            AssignProcessToJobObject, CreateJobObjectW, JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE,
+            JOBOBJECT_EXTENDED_LIMIT_INFORMATION, JobObjectExtendedLimitInformation,
+            SetInformationJobObject,
+        },
+        Threading::GetCurrentProcess,
+    };
+
+    // SAFETY: valid args per the win32 docs, the job handle is intentionally
+    // never closed so the OS closes it on exit
+    unsafe {
+        let job = CreateJobObjectW(ptr::null(), ptr::null());
+        if job.is_null() {
+            debug!("CreateJobObjectW failed");
+            return;
+        }
+        let mut info: JOBOBJECT_EXTENDED_LIMIT_INFORMATION = mem::zeroed();
+        info.BasicLimitInformation.LimitFlags = JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+        let ok = SetInformationJobObject(
+            job,
+            JobObjectExtendedLimitInformation,
+            &info as *const _ as *const c_void,
+            mem::size_of_val(&info) as u32,
+        ) != 0
+            && AssignProcessToJobObject(job, GetCurrentProcess()) != 0;
+        if !ok {
+            // e.g. already in a job that doesn't allow nesting
+            debug!("kill on exit job object unavailable");
+        }
+    }
+}
+
 /// Wrapper that [`add`]s the inner on drop.
 #[derive(Debug)]
 pub struct AddOnDropChunkStream(Option<ProcessChunkStream>);
@@ -0,0 +1,143 @@
+//! Environment variable & working directory overrides for spawned processes.
+use anyhow::ensure;
+use clap::Parser;
+use std::{future::Future, path::PathBuf, sync::OnceLock};
+
+static OVERRIDES: OnceLock<Args> = OnceLock::new();
+
+tokio::task_local! {
+    /// Job slot, e.g. `0..jobs` for `batch --jobs`, used for "{job}" values.
+    static JOB: u16;
+}
+
+/// Overrides applied to all spawned processes, e.g. ffmpeg.
+#[derive(Debug, Parser, Clone, Default)]
+#[group(skip)]
+pub struct Args {
+    /// Environment variable set for spawned ffmpeg processes, e.g.
+    /// `--process-env SVT_LOG=4`. May be specified multiple times.
+    ///
+    /// "{job}" is replaced with the job slot, 0 to --jobs - 1 when using batch,
+    /// e.g. `--process-env CUDA_VISIBLE_DEVICES={job}` pins each job to a GPU.
+    #[arg(long, global = true, value_parser = parse_env)]
+    pub process_env: Vec<(String, String)>,
+
+    /// Working directory of spawned ffmpeg processes, e.g. for encoder log files.
+    /// Relative input & output paths are resolved from this directory by ffmpeg,
+    /// so should be absolute.
+    #[arg(long, global = true)]
+    pub process_cwd: Option<PathBuf>,
+
+    /// Print the ffmpeg commands that would run, shell escaped, instead of running them.
+    ///
+    /// Commands that search for a crf print the commands of the first sample at the
+    /// first crf, followed by the final encode using that crf. `clean` prints what
+    /// would be removed.
+    #[arg(long, global = true)]
+    pub dry_run: bool,
+}
+
+fn parse_env(arg: &str) -> anyhow::Result<(String, String)> {
+    let (key, val) = arg.split_once('=').unwrap_or((arg, ""));
+    ensure!(
+        !key.is_empty() && arg.contains('='),
+        "expected KEY=VALUE, e.g. CUDA_VISIBLE_DEVICES=0"
+    );
+    Ok((key.to_owned(), val.to_owned()))
+}
+
+/// Set the overrides. Should be called once before any processes are spawned.
+pub fn init(args: Args) {
+    let _ = OVERRIDES.set(args);
+}
+
+/// Returns true if --dry-run is set.
+pub fn dry_run() -> bool {
+    OVERRIDES.get().is_some_and(|o| o.dry_run)
+}
+
+/// Returns the --process-env value of `key` for the current job slot, if set.
+pub fn process_env(key: &str) -> Option<String> {
+    let overrides = OVERRIDES.get()?;
+    let job = JOB.try_with(|j| *j).unwrap_or(0);
+    overrides
+        .process_env
+        .iter()
+        .rfind(|(k, _)| k == key)
+        .map(|(_, val)| job_value(val, job))
+}
+
+/// Run `f` as job slot `job`.
+pub async fn scope<F: Future>(job: u16, f: F) -> F::Output {
+    JOB.scope(job, f).await
+}
+
+/// Wrap `f` so it runs in the current job slot, if any.
+/// Should be used for spawned tasks that may spawn processes.
+pub fn inherit_scope<F: Future>(f: F) -> impl Future<Output = F::Output> {
+    let job = JOB.try_with(|j| *j).ok();
+    async move {
+        match job {
+            Some(job) => JOB.scope(job, f).await,
+            None => f.await,
+        }
+    }
+}
+
+/// Apply environment & working directory overrides to a command.
+///
+/// On unix async commands are also spawned in their own process group, so a terminal
+/// Ctrl-C isn't sent to them directly but handled by ab-av1, see [`super::child`].
+/// Blocking std commands stay in the foreground group, as ab-av1 can't handle Ctrl-C
+/// while waiting on them, so they receive it & exit with ab-av1.
+pub trait CommandJobEnv {
+    fn job_env(&mut self) -> &mut Self;
+}
+
+macro_rules! impl_command_job_env {
+    ($cmd:ty, own_process_group = $own_group:literal) => {
+        impl CommandJobEnv for $cmd {
+            fn job_env(&mut self) -> &mut Self {
+                #[cfg(unix)]
+                if $own_group {
+                    #[allow(unused_imports)]
+                    use std::os::unix::process::CommandExt;
+                    self.process_group(0);
+                }
+                let Some(overrides) = OVERRIDES.get() else {
+                    return self;
+                };
+                let job = JOB.try_with(|j| *j).unwrap_or(0);
+                for (key, val) in &overrides.process_env {
+                    self.env(key, job_value(val, job));
+                }
+                if let Some(dir) = &overrides.process_cwd {
+                    self.current_dir(dir);
+                }
+                self
+            }
+        }
+    };
+}
+impl_command_job_env!(tokio::process::Command, own_process_group = true);
+impl_command_job_env!(std::process::Command, own_process_group = false);
+
+/// Replace "{job}" with the `job` slot.
+fn job_value(val: &str, job: u16) -> String {
+    val.replace("{job}", &job.to_string())
+}
+
+#[test]
+fn parse_env_args() {
+    assert_eq!(
+        parse_env("CUDA_VISIBLE_DEVICES={job}").unwrap(),
+        ("CUDA_VISIBLE_DEVICES".into(), "{job}".into())
+    );
+    assert_eq!(
+        parse_env("SVT_LOG=").unwrap(),
+        ("SVT_LOG".into(), "".into())
+    );
+    assert!(parse_env("SVT_LOG").is_err());
+    assert!(parse_env("=4").is_err());
+    assert_eq!(job_value("{job}", 1), "1");
+}
@@ -0,0 +1,132 @@
+//! Locating external programs, e.g. ffmpeg.
+//!
+//! Programs are run by name, found in PATH by the OS. On Windows, where ffmpeg is
+//! often installed without being added to PATH, the location is also looked up in
+//! the registry "App Paths" & common package manager directories. The working
+//! directory is never searched, so a stray ffmpeg.exe there isn't run.
+use std::{
+    collections::HashMap,
+    env,
+    path::{Path, PathBuf},
+    sync::{LazyLock, Mutex},
+};
+use tracing::debug;
+
+static LOCATED: LazyLock<Mutex<HashMap<String, &'static Path>>> = LazyLock::new(<_>::default);
+
+/// Returns the ffmpeg program to run.
+pub fn ffmpeg() -> &'static Path {
+    locate("ffmpeg")
+}
+
+/// Returns the ffprobe program to run.
+pub fn ffprobe() -> &'static Path {
+    locate("ffprobe")
+}
+
+/// Returns the program `name` to run, e.g. "ffmpeg".
+///
+/// Returns `name` unchanged if it is a path, found in PATH or cannot be located,
+/// so spawn errors still mention the plain name.
+pub fn locate(name: &str) -> &'static Path {
+    let mut located = LOCATED.lock().unwrap();
+    if let Some(path) = located.get(name) {
+        return path;
+    }
+    let path = match Path::new(name).components().count() > 1 || in_path(name) {
+        true => None,
+        false => fallback(name),
+    };
+    debug!("program {name} -> {path:?}");
+    let path: &'static Path = Box::leak(path.unwrap_or_else(|| name.into()).into_boxed_path());
+    located.insert(name.into(), path);
+    path
+}
+
+/// Returns true if `name` is an executable in a PATH directory.
+fn in_path(name: &str) -> bool {
+    let Some(paths) = env::var_os("PATH") else {
+        return false;
+    };
+    env::split_paths(&paths).any(|dir| executable(&dir, name).is_some())
+}
+
+/// Returns the path of executable `name` in `dir`, if it exists.
+fn executable(dir: &Path, name: &str) -> Option<PathBuf> {
+    let mut path = dir.join(name);
+    if cfg!(windows) && path.extension().is_none() {
+        path.set_extension("exe");
+    }
+    Some(path).filter(|p| p.is_file())
+}
+
+#[cfg(not(windows))]
+fn fallback(_name: &str) -> Option<PathBuf> {
+    None
+}
+
+#[cfg(windows)]
+fn fallback(name: &str) -> Option<PathBuf> {
+    use std::process::{Command, Stdio};
+
+    let run = |program: &str, args: &[&str]| {
+        Command::new(program)
+            .args(args)
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output()
+            .ok()
+            .filter(|out| out.status.success())
+            .map(|out| String::from_utf8_lossy(&out.stdout).into_owned())
+    };
+
+    // PATH is already searched by `in_path`. Unlike `where`, only explicit
+    // locations are checked, never the working directory
+    for root in ["HKCU", "HKLM"] {
+        let key = format!(r"{root}\SOFTWARE\Microsoft\Windows\CurrentVersion\App Paths\{name}.exe");
+        if let Some(path) = run("reg", &["query", &key, "/ve"])
+            .as_deref()
+            .and_then(parse_reg_default)
+            .filter(|p| p.is_file())
+        {
+            return Some(path);
+        }
+    }
+    // winget, scoop & chocolatey shims
+    [
+        env::var_os("LOCALAPPDATA").map(|d| PathBuf::from(d).join(r"Microsoft\WinGet\Links")),
+        env::var_os("USERPROFILE").map(|d| PathBuf::from(d).join(r"scoop\shims")),
+        env::var_os("ProgramData").map(|d| PathBuf::from(d).join(r"chocolatey\bin")),
+    ]
+    .into_iter()
+    .flatten()
+    .find_map(|dir| executable(&dir, name))
+}
+
+/// Returns the default value of `reg query KEY /ve` stdout, e.g.
+/// `    (Default)    REG_SZ    C:\ffmpeg\bin\ffmpeg.exe`.
+#[cfg_attr(not(windows), allow(unused))]
+fn parse_reg_default(stdout: &str) -> Option<PathBuf> {
+    stdout.lines().find_map(|line| {
+        let (_, value) = line
+            .split_once("REG_EXPAND_SZ")
+            .or_else(|| line.split_once("REG_SZ"))?;
+        let value = value.trim().trim_matches('"');
+        (!value.is_empty()).then(|| PathBuf::from(value))
+    })
+}
+
+#[test]
+fn parse_reg_app_paths() {
+    let reg = "\r\nHKEY_LOCAL_MACHINE\\SOFTWARE\\Microsoft\\Windows\\CurrentVersion\\App Paths\\ffmpeg.exe\r\n    \
+               (Default)    REG_SZ    \"C:\\Program Files\\ffmpeg\\bin\\ffmpeg.exe\"\r\n\r\n";
+    assert_eq!(
+        parse_reg_default(reg),
+        Some(r"C:\Program Files\ffmpeg\bin\ffmpeg.exe".into())
+    );
+    assert_eq!(
+        parse_reg_default("    (Default)    REG_SZ    \r\n"),
+        None,
+        "value not set"
+    );
+}
@@ -1,82 +1,376 @@
 //! ffmpeg logic
 use crate::{
-    process::{CommandExt, ensure_success},
+    error::Error,
+    ffprobe,
+    process::{CommandExt, CommandJobEnv, ensure_success, program},
     temporary::{self, TempKind},
 };
-use anyhow::Context;
 use std::{
+    collections::HashMap,
     path::{Path, PathBuf},
     process::Stdio,
+    sync::{LazyLock, Mutex},
     time::Duration,
 };
 use tokio::process::Command;
+use tracing::debug;
 
-/// Create a sample from `sample_start` + `frames`.
+/// Create a sample of the input `video_stream` from `sample_start` + `frames`.
 ///
 /// Fast as this uses `-c:v copy`.
+#[tracing::instrument(name = "sample", skip_all, fields(start = ?sample_start, frames))]
 pub async fn copy(
     input: &Path,
+    video_stream: usize,
     sample_start: Duration,
     floor_to_sec: bool,
     frames: u32,
     temp_dir: Option<PathBuf>,
 ) -> anyhow::Result<PathBuf> {
-    let mut sample_start_s = sample_start.as_secs_f32();
-    if floor_to_sec {
-        sample_start_s = sample_start_s.floor();
+    let dest = path(
+        input,
+        video_stream,
+        sample_start,
+        floor_to_sec,
+        frames,
+        temp_dir,
+    );
+    if dest.exists() {
+        return Ok(dest);
     }
+    temporary::add(&dest, TempKind::Keepable);
+
+    let mut cmd = copy_command(
+        input,
+        video_stream,
+        sample_start,
+        floor_to_sec,
+        frames,
+        &dest,
+        false,
+    );
+    debug!("cmd `{}`", cmd.to_cmd_str());
+    let mut out = cmd.output().await.map_err(Error::spawn("ffmpeg copy"))?;
 
+    if !out.status.success()
+        && String::from_utf8_lossy(&out.stderr)
+            .contains("Can't write packet with unknown timestamp")
+    {
+        // try +genpts workaround
+        let mut cmd = copy_command(
+            input,
+            video_stream,
+            sample_start,
+            floor_to_sec,
+            frames,
+            &dest,
+            true,
+        );
+        debug!("cmd `{}`", cmd.to_cmd_str());
+        out = cmd.output().await.map_err(Error::spawn("ffmpeg copy"))?;
+    }
+
+    ensure_success("ffmpeg copy", &out)?;
+    Ok(dest)
+}
+
+/// Returns the temp file path of a sample created by [`copy`].
+pub fn path(
+    input: &Path,
+    video_stream: usize,
+    sample_start: Duration,
+    floor_to_sec: bool,
+    frames: u32,
+    temp_dir: Option<PathBuf>,
+) -> PathBuf {
+    let sample_start_s = start_secs(sample_start, floor_to_sec);
+    let stream = match video_stream {
+        0 => String::new(),
+        n => format!("v{n}."),
+    };
     let mut dest = temporary::process_dir(temp_dir);
     // Always using mkv for the samples works better than, e.g. using mp4 for mp4s
     // see https://github.com/alexheretic/ab-av1/issues/82#issuecomment-1337306325
     dest.push(
-        input
-            .with_extension(format!("sample{sample_start_s}+{frames}f.mkv"))
+        ffprobe::local_name(input)
+            .with_extension(format!("sample{sample_start_s}+{frames}f.{stream}mkv"))
             .file_name()
             .unwrap(),
     );
-    if dest.exists() {
-        return Ok(dest);
-    }
-    temporary::add(&dest, TempKind::Keepable);
+    dest
+}
 
+/// Returns the command [`copy`] runs to create the sample `dest`,
+/// with `genpts` for inputs with missing timestamps.
+pub fn copy_command(
+    input: &Path,
+    video_stream: usize,
+    sample_start: Duration,
+    floor_to_sec: bool,
+    frames: u32,
+    dest: &Path,
+    genpts: bool,
+) -> Command {
     // Note: `-ss` before `-i` & `-frames:v` instead of `-t`
     // See https://github.com/alexheretic/ab-av1/issues/36#issuecomment-1146634936
-    let mut out = Command::new("ffmpeg")
+    let mut cmd = Command::new(program::ffmpeg());
+    cmd.job_env()
         .arg("-y")
-        .arg2("-ss", sample_start_s)
+        .arg2_if(genpts, "-fflags", "+genpts")
+        .arg2("-ss", start_secs(sample_start, floor_to_sec))
         .arg2("-i", input)
+        .arg2("-map", format!("0:v:{video_stream}"))
         .arg2("-frames:v", frames)
         .arg2("-c:v", "copy")
         .arg("-an")
         .arg("-sn")
-        .arg(&dest)
-        .stdin(Stdio::null())
-        .output()
-        .await
-        .context("ffmpeg copy")?;
+        .arg(dest)
+        .stdin(Stdio::null());
+    cmd
+}
 
-    if !out.status.success()
-        && String::from_utf8_lossy(&out.stderr)
-            .contains("Can't write packet with unknown timestamp")
-    {
-        out = Command::new("ffmpeg")
-            .arg("-y")
-            // try +genpts workaround
-            .arg2("-fflags", "+genpts")
-            .arg2("-ss", sample_start_s)
-            .arg2("-i", input)
-            .arg2("-frames:v", frames)
-            .arg2("-c:v", "copy")
-            .arg("-an")
-            .arg("-sn")
-            .arg(&dest)
-            .stdin(Stdio::null())
-            .output()
-            .await
-            .context("ffmpeg copy")?;
+/// Returns the first video keyframe at or after `start`, within [`KEYFRAME_SEARCH`],
+/// that leaves `sample_duration` before the input `duration` ends.
+pub async fn next_keyframe(
+    input: &Path,
+    video_stream: usize,
+    start: Duration,
+    sample_duration: Duration,
+    duration: Duration,
+) -> Option<Duration> {
+    let mut cmd = Command::from(keyframe_command(input, video_stream, start));
+    debug!("cmd `{}`", cmd.to_cmd_str());
+    let out = cmd.output().await.ok()?;
+    if !out.status.success() {
+        debug!(
+            "keyframe probe failed: {}",
+            String::from_utf8_lossy(&out.stderr).trim()
+        );
+        return None;
     }
+    let max_start = duration.saturating_sub(sample_duration);
+    parse_keyframe(&String::from_utf8_lossy(&out.stdout), start).filter(|k| *k <= max_start)
+}
 
-    ensure_success("ffmpeg copy", &out)?;
-    Ok(dest)
+/// How far after a planned sample start to look for a keyframe.
+const KEYFRAME_SEARCH: Duration = Duration::from_secs(10);
+
+/// Returns the ffprobe command listing video keyframe times from `start`, decoding
+/// only keyframes.
+pub fn keyframe_command(
+    input: &Path,
+    video_stream: usize,
+    start: Duration,
+) -> std::process::Command {
+    let mut cmd = std::process::Command::new(program::ffprobe());
+    cmd.job_env()
+        .args(["-v", "error", "-select_streams"])
+        .arg(format!("v:{video_stream}"))
+        .args(["-skip_frame", "nokey"])
+        .args(["-show_entries", "frame=best_effort_timestamp_time"])
+        .args(["-of", "csv=p=0", "-read_intervals"])
+        .arg(format!(
+            "{:.3}%+{}",
+            start.as_secs_f64(),
+            KEYFRAME_SEARCH.as_secs()
+        ))
+        .arg(input)
+        .stdin(Stdio::null());
+    cmd
+}
+
+/// Returns the first keyframe time at or after `start` of [`keyframe_command`] stdout,
+/// plus 1ms so seeking to it, using f32 seconds, doesn't land on the previous keyframe.
+pub fn parse_keyframe(stdout: &str, start: Duration) -> Option<Duration> {
+    stdout
+        .lines()
+        .filter_map(|l| l.trim().trim_end_matches(',').parse::<f64>().ok())
+        .filter(|t| *t >= 0.0)
+        .map(Duration::from_secs_f64)
+        .find(|t| *t >= start)
+        .map(|t| t + Duration::from_millis(1))
+}
+
+/// Returns `samples` sample starts spanning the complexity, i.e. bitrate, distribution of
+/// the input, see --sample-selection. `None` if the input is too short to choose or the
+/// packet probe fails.
+///
+/// Starts are relative to `trim_start`, the input `duration` being the trimmed duration.
+pub async fn complexity_starts(
+    input: &Path,
+    video_stream: usize,
+    trim_start: Duration,
+    samples: u64,
+    sample_duration: Duration,
+    duration: Duration,
+) -> Option<Vec<Duration>> {
+    type Key = (PathBuf, usize, Duration, u64, Duration);
+    // crf-search samples the same positions for each crf
+    static STARTS: LazyLock<Mutex<HashMap<Key, Option<Vec<Duration>>>>> =
+        LazyLock::new(<_>::default);
+
+    let key = (
+        input.to_owned(),
+        video_stream,
+        trim_start,
+        samples,
+        sample_duration,
+    );
+    if let Some(starts) = STARTS.lock().unwrap().get(&key) {
+        return starts.clone();
+    }
+    let mut cmd = Command::from(packets_command(input, video_stream));
+    debug!("cmd `{}`", cmd.to_cmd_str());
+    let starts = match cmd.output().await {
+        Ok(out) if out.status.success() => {
+            let packets = parse_packets(&String::from_utf8_lossy(&out.stdout), trim_start);
+            pick_by_complexity(&packets, samples, sample_duration, duration)
+        }
+        Ok(out) => {
+            debug!(
+                "packet probe failed: {}",
+                String::from_utf8_lossy(&out.stderr).trim()
+            );
+            None
+        }
+        Err(err) => {
+            debug!("packet probe failed: {err}");
+            None
+        }
+    };
+    debug!("complexity sample starts {starts:?}");
+    STARTS.lock().unwrap().insert(key, starts.clone());
+    starts
+}
+
+/// Returns the ffprobe command listing video packet times & sizes, without decoding.
+pub fn packets_command(input: &Path, video_stream: usize) -> std::process::Command {
+    let mut cmd = std::process::Command::new(program::ffprobe());
+    cmd.job_env()
+        .args(["-v", "error", "-select_streams"])
+        .arg(format!("v:{video_stream}"))
+        .args(["-show_entries", "packet=pts_time,dts_time,size"])
+        .args(["-of", "csv=p=0"])
+        .arg(input)
+        .stdin(Stdio::null());
+    cmd
+}
+
+/// Parse [`packets_command`] stdout into (seconds, bytes), e.g. "12.512000,12.470000,5342".
+///
+/// Times are relative to `start`, e.g. --start, earlier packets are skipped.
+pub fn parse_packets(stdout: &str, start: Duration) -> Vec<(f64, u64)> {
+    let start = start.as_secs_f64();
+    stdout
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.trim().trim_end_matches(',').split(',');
+            let pts = fields.next()?;
+            let dts = fields.next()?;
+            let size = fields.next()?.parse().ok()?;
+            let time: f64 = pts.parse().or_else(|_| dts.parse()).ok()?;
+            Some((time - start, size)).filter(|(t, _)| *t >= 0.0)
+        })
+        .collect()
+}
+
+/// Returns sample starts of windows of `sample_duration` at evenly spaced quantiles of
+/// window bitrate, from low to high complexity, in time order.
+///
+/// The first & last windows, often intros & credits, are not used. `None` if there are
+/// too few windows to choose from.
+pub fn pick_by_complexity(
+    packets: &[(f64, u64)],
+    samples: u64,
+    sample_duration: Duration,
+    duration: Duration,
+) -> Option<Vec<Duration>> {
+    let window = sample_duration.as_secs_f64();
+    if window <= 0.0 || samples == 0 {
+        return None;
+    }
+    let windows = (duration.as_secs_f64() / window).floor() as usize;
+    if windows < 2 * samples as usize + 2 {
+        return None;
+    }
+    let mut bytes = vec![0_u64; windows];
+    for &(time, size) in packets {
+        if let Some(b) = bytes.get_mut((time.max(0.0) / window) as usize) {
+            *b += size;
+        }
+    }
+    let mut ranked: Vec<_> = (1..windows - 1).collect();
+    ranked.sort_by_key(|&w| bytes[w]);
+
+    let mut starts: Vec<_> = (0..samples)
+        .map(|n| {
+            let rank = ((n as f64 + 0.5) / samples as f64 * ranked.len() as f64) as usize;
+            sample_duration * ranked[rank.min(ranked.len() - 1)] as u32
+        })
+        .collect();
+    starts.sort();
+    Some(starts)
+}
+
+fn start_secs(sample_start: Duration, floor_to_sec: bool) -> f32 {
+    match floor_to_sec {
+        true => sample_start.as_secs_f32().floor(),
+        false => sample_start.as_secs_f32(),
+    }
+}
+
+#[test]
+fn parse_keyframes() {
+    let stdout = "118.910000\n121.002000,\nN/A\n125.171000\n";
+    assert_eq!(
+        parse_keyframe(stdout, Duration::from_secs(120)),
+        Some(Duration::from_millis(121_003))
+    );
+    assert_eq!(parse_keyframe(stdout, Duration::from_secs(126)), None);
+    assert_eq!(parse_keyframe("", Duration::ZERO), None);
+}
+
+#[test]
+fn pick_samples_by_complexity() {
+    let packets = parse_packets(
+        "0.000000,N/A,1000\nN/A,10.000000,1000\n20.000000,20.000000,9000\n",
+        Duration::ZERO,
+    );
+    assert_eq!(packets, [(0.0, 1000), (10.0, 1000), (20.0, 9000)]);
+    let trimmed = parse_packets(
+        "5.000000,N/A,1000\n20.000000,N/A,9000\n",
+        Duration::from_secs(10),
+    );
+    assert_eq!(trimmed, [(10.0, 9000)], "relative to --start");
+
+    // 10s windows with bytes of window index * 100, except a complex window 3
+    let mut packets: Vec<_> = (0..12).map(|w| (w as f64 * 10.0 + 1.0, w * 100)).collect();
+    packets[3].1 = 5000;
+    let starts = pick_by_complexity(
+        &packets,
+        3,
+        Duration::from_secs(10),
+        Duration::from_secs(120),
+    )
+    .unwrap();
+    // windows 1..=10 ranked: 1,2,4,5,6,7,8,9,10,3 -> quantiles 1/6, 1/2, 5/6
+    assert_eq!(
+        starts,
+        [
+            Duration::from_secs(20),
+            Duration::from_secs(70),
+            Duration::from_secs(100)
+        ]
+    );
+
+    // too short to choose
+    assert_eq!(
+        pick_by_complexity(
+            &packets,
+            6,
+            Duration::from_secs(10),
+            Duration::from_secs(120)
+        ),
+        None
+    );
 }
@@ -0,0 +1,133 @@
+//! Central scheduler limiting how many concurrent jobs may use shared resources.
+use crate::cuda::{self, caps};
+use clap::Parser;
+use std::sync::{LazyLock, OnceLock};
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+static ARGS: OnceLock<Args> = OnceLock::new();
+/// Limits, created on first use so GPU capabilities are only probed if needed.
+static LIMITS: LazyLock<Limits> = LazyLock::new(|| ARGS.get().copied().unwrap_or_default().into());
+
+/// Concurrency limits shared by all jobs, e.g. when using `batch --jobs`.
+#[derive(Debug, Parser, Clone, Copy, Default)]
+#[group(skip)]
+pub struct Args {
+    /// Maximum concurrent jobs using the GPU, i.e. NVDEC decoding or NVENC encoding.
+    ///
+    /// Defaults to one job per 6GiB of GPU VRAM, up to 3, detected using nvidia-smi.
+    /// 1 if VRAM is unknown.
+    #[arg(long, global = true, value_parser = clap::value_parser!(u16).range(1..))]
+    pub max_gpu_jobs: Option<u16>,
+
+    /// Maximum concurrent jobs using the CPU, i.e. software encoding or VMAF/XPSNR scoring.
+    /// Unlimited if not specified.
+    #[arg(long, global = true, value_parser = clap::value_parser!(u16).range(1..))]
+    pub max_cpu_jobs: Option<u16>,
+}
+
+/// Shared resources a job needs.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Resources {
+    /// Decodes using NVDEC, e.g. `-hwaccel cuda` or a `*_cuvid` decoder.
+    pub nvdec: bool,
+    /// Encodes using NVENC, e.g. `hevc_nvenc`.
+    pub nvenc: bool,
+    /// Encodes in software, e.g. `libsvtav1`.
+    pub cpu_encode: bool,
+    /// Calculates VMAF or XPSNR on the CPU.
+    pub vmaf_cpu: bool,
+}
+
+impl Resources {
+    /// Resources for CPU VMAF/XPSNR scoring.
+    pub fn vmaf_cpu() -> Self {
+        Self {
+            vmaf_cpu: true,
+            ..<_>::default()
+        }
+    }
+
+    /// Resources for libvmaf_cuda scoring of NVDEC decoded or uploaded frames.
+    pub fn vmaf_cuda() -> Self {
+        Self {
+            nvdec: true,
+            ..<_>::default()
+        }
+    }
+
+    fn uses_gpu(self) -> bool {
+        self.nvdec || self.nvenc
+    }
+
+    fn uses_cpu(self) -> bool {
+        self.cpu_encode || self.vmaf_cpu
+    }
+}
+
+struct Limits {
+    gpu_jobs: u16,
+    gpu: Semaphore,
+    cpu: Option<Semaphore>,
+}
+
+/// Set the concurrency limits. Should be called once before any jobs are scheduled,
+/// otherwise default limits will be used.
+pub fn init(args: Args) {
+    let _ = ARGS.set(args);
+}
+
+/// Maximum concurrent jobs using the GPU, see --max-gpu-jobs.
+pub fn max_gpu_jobs() -> u16 {
+    LIMITS.gpu_jobs
+}
+
+impl From<Args> for Limits {
+    fn from(
+        Args {
+            max_gpu_jobs,
+            max_cpu_jobs,
+        }: Args,
+    ) -> Self {
+        let gpu_jobs = max_gpu_jobs.unwrap_or_else(caps::auto_gpu_jobs);
+        Self {
+            gpu_jobs,
+            gpu: Semaphore::new(gpu_jobs.into()),
+            cpu: max_cpu_jobs.map(|n| Semaphore::new(n.into())),
+        }
+    }
+}
+
+/// Held while a job is running, dropping releases the job's resources.
+#[must_use]
+pub struct Permit {
+    _gpu: Option<SemaphorePermit<'static>>,
+    _nvenc: Option<SemaphorePermit<'static>>,
+    _cpu: Option<SemaphorePermit<'static>>,
+}
+
+/// Wait until a job needing `resources` may run.
+///
+/// NVENC jobs also wait for a free NVENC session if the GPU driver limits them.
+///
+/// Resources are always acquired in the same order (gpu, nvenc then cpu) to avoid deadlocks.
+pub async fn acquire(resources: Resources) -> Permit {
+    let limits = &*LIMITS;
+
+    let gpu = match resources.uses_gpu() {
+        true => Some(limits.gpu.acquire().await.expect("semaphore closed")),
+        false => None,
+    };
+    let nvenc = match resources.nvenc {
+        true => cuda::scheduler::acquire_nvenc().await,
+        false => None,
+    };
+    let cpu = match (&limits.cpu, resources.uses_cpu()) {
+        (Some(cpu), true) => Some(cpu.acquire().await.expect("semaphore closed")),
+        _ => None,
+    };
+    Permit {
+        _gpu: gpu,
+        _nvenc: nvenc,
+        _cpu: cpu,
+    }
+}
@@ -0,0 +1,112 @@
+//! Piped stdin input, e.g. `-i -`.
+//!
+//! The start of stdin is buffered so it can be probed, then the buffered head &
+//! the rest of stdin are piped into a single ffmpeg encode.
+use crate::temporary::{self, TempKind};
+use anyhow::{Context, ensure};
+use std::{
+    io::Read,
+    path::{Path, PathBuf},
+    sync::{
+        LazyLock,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+};
+use tokio::{io::AsyncWriteExt, process::ChildStdin, sync::mpsc};
+use tracing::debug;
+
+/// Max bytes of stdin buffered for probing.
+const HEAD_LEN: u64 = 16 * 1024 * 1024;
+
+/// Start of stdin, read on first use.
+static HEAD: LazyLock<Vec<u8>> = LazyLock::new(|| {
+    let mut head = vec![];
+    if let Err(err) = std::io::stdin().take(HEAD_LEN).read_to_end(&mut head) {
+        debug!("reading stdin: {err}");
+    }
+    head
+});
+
+/// Total bytes piped from stdin.
+static PIPED_LEN: AtomicU64 = AtomicU64::new(0);
+
+/// Returns true if the input is stdin, i.e. "-" or "pipe:0".
+pub fn is_stdin(input: &Path) -> bool {
+    matches!(input.to_str(), Some("-" | "pipe:" | "pipe:0"))
+}
+
+/// Returns an error if the `input` is stdin, which cannot be seeked for sampling.
+pub fn ensure_seekable(input: &Path) -> anyhow::Result<()> {
+    ensure!(
+        !is_stdin(input),
+        "stdin input is not supported here as seeking is required, \
+         use `encode --crf` or save the input to a file"
+    );
+    Ok(())
+}
+
+/// Write the buffered start of stdin to a temp file for probing.
+pub fn head_file() -> anyhow::Result<PathBuf> {
+    let path = temporary::process_dir(None).join("stdin-head");
+    if !path.exists() {
+        temporary::add(&path, TempKind::NotKeepable);
+        std::fs::write(&path, &*HEAD).context("writing stdin head")?;
+    }
+    Ok(path)
+}
+
+/// Total bytes piped from stdin, once the input has been fully read.
+pub fn piped_len() -> u64 {
+    PIPED_LEN.load(Ordering::Relaxed)
+}
+
+/// Pipe the buffered head & the rest of stdin into the ffmpeg `child_stdin`.
+///
+/// Stdin can only be consumed once, so errors if already piped.
+pub fn pipe_to(child_stdin: Option<ChildStdin>) -> anyhow::Result<()> {
+    static PIPED: AtomicBool = AtomicBool::new(false);
+    ensure!(
+        !PIPED.swap(true, Ordering::Relaxed),
+        "stdin input has already been consumed"
+    );
+    let mut child_stdin = child_stdin.context("no ffmpeg stdin")?;
+
+    // std stdin is used for the rest as it may have buffered beyond the head
+    let (tx, mut rx) = mpsc::channel::<Vec<u8>>(16);
+    std::thread::spawn(move || {
+        let mut stdin = std::io::stdin().lock();
+        let mut buf = vec![0; 256 * 1024];
+        loop {
+            match stdin.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(n) if tx.blocking_send(buf[..n].to_vec()).is_err() => break,
+                Ok(_) => {}
+            }
+        }
+    });
+    tokio::spawn(async move {
+        let head = &*HEAD;
+        if child_stdin.write_all(head).await.is_err() {
+            return;
+        }
+        PIPED_LEN.fetch_add(head.len() as _, Ordering::Relaxed);
+        while let Some(chunk) = rx.recv().await {
+            if child_stdin.write_all(&chunk).await.is_err() {
+                return;
+            }
+            PIPED_LEN.fetch_add(chunk.len() as _, Ordering::Relaxed);
+        }
+        // dropping closes ffmpeg's stdin signalling the end of input
+        _ = child_stdin.shutdown().await;
+    });
+    Ok(())
+}
+
+#[test]
+fn stdin_inputs() {
+    assert!(is_stdin(Path::new("-")));
+    assert!(is_stdin(Path::new("pipe:0")));
+    assert!(!is_stdin(Path::new("vid.mkv")));
+    assert!(!is_stdin(Path::new("./-")));
+    assert!(ensure_seekable(Path::new("-")).is_err());
+}
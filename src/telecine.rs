@@ -0,0 +1,164 @@
+//! Telecine (3:2 pulldown) detection logic.
+use crate::{
+    error::Error,
+    process::{CommandJobEnv, program},
+};
+use anyhow::Context;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::{LazyLock, Mutex},
+    time::Duration,
+};
+use tracing::{debug, info};
+
+/// Inverse telecine filters: match fields, deinterlace any orphaned combed frames,
+/// then drop the duplicate frame in each cycle of 5.
+pub const FILTERS: &str = "fieldmatch,yadif=deint=interlaced,decimate";
+
+/// Output frame rate factor of [`FILTERS`], e.g. 29.97 -> 23.976.
+pub const FPS_FACTOR: f64 = 4.0 / 5.0;
+
+/// Number of frames analysed by [`detect`].
+const DETECT_FRAMES: u32 = 1500;
+
+/// Detect whether `input` video stream `video_stream` is telecined using ffmpeg idet statistics.
+///
+/// Analyses frames from 10% into the `duration` after `start`, if known, to skip intros.
+/// Results are cached per input, stream & start.
+pub fn detect(
+    input: &Path,
+    video_stream: usize,
+    start: Duration,
+    duration: Option<Duration>,
+) -> anyhow::Result<bool> {
+    type Key = (PathBuf, usize, Duration);
+    static DETECTED: LazyLock<Mutex<HashMap<Key, bool>>> = LazyLock::new(<_>::default);
+
+    let key = (input.to_owned(), video_stream, start);
+    if let Some(telecined) = DETECTED.lock().unwrap().get(&key) {
+        return Ok(*telecined);
+    }
+
+    let seek = start + duration.unwrap_or_default().mul_f32(0.1);
+    let output = Command::new(program::ffmpeg())
+        .job_env()
+        .arg("-ss")
+        .arg(seek.as_secs_f32().to_string())
+        .arg("-i")
+        .arg(input)
+        .arg("-map")
+        .arg(format!("0:v:{video_stream}"))
+        .args(["-vf", "idet", "-frames:v"])
+        .arg(DETECT_FRAMES.to_string())
+        .args(["-an", "-sn", "-dn", "-f", "null", "-"])
+        .output()
+        .map_err(Error::spawn("ffmpeg idet"))?;
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let idet = Idet::parse(&stderr).context("could not parse ffmpeg idet output")?;
+    debug!("{idet:?}");
+    let telecined = idet.is_telecined();
+    if telecined {
+        info!("telecine detected in {}", input.display());
+    }
+
+    DETECTED.lock().unwrap().insert(key, telecined);
+    Ok(telecined)
+}
+
+/// ffmpeg idet filter frame counts.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Idet {
+    /// Multi frame detection interlaced (tff + bff) frames.
+    interlaced: u64,
+    /// Multi frame detection progressive frames.
+    progressive: u64,
+    /// Frames with a repeated top or bottom field.
+    repeated: u64,
+    /// Frames without a repeated field.
+    not_repeated: u64,
+}
+
+impl Idet {
+    fn parse(stderr: &str) -> Option<Self> {
+        fn count(line: &str, label: &str) -> Option<u64> {
+            let idx = line.find(label)? + label.len();
+            line[idx..].split_whitespace().next()?.parse().ok()
+        }
+
+        let mut idet = None;
+        for line in stderr.lines() {
+            if line.contains("Multi frame detection:") {
+                let idet = idet.get_or_insert_with(Self::default);
+                idet.interlaced = count(line, "TFF:")? + count(line, "BFF:")?;
+                idet.progressive = count(line, "Progressive:")?;
+            } else if line.contains("Repeated Fields:") {
+                let idet = idet.get_or_insert_with(Self::default);
+                idet.repeated = count(line, "Top:")? + count(line, "Bottom:")?;
+                idet.not_repeated = count(line, "Neither:")?;
+            }
+        }
+        idet
+    }
+
+    /// 3:2 pulldown produces 2 combed frames in every 5 (~40%), or repeated fields.
+    /// Fully interlaced sources are mostly combed & progressive sources are not.
+    fn is_telecined(&self) -> bool {
+        let detected = self.interlaced + self.progressive;
+        let fields = self.repeated + self.not_repeated;
+        if detected == 0 {
+            return false;
+        }
+        let interlaced = self.interlaced as f64 / detected as f64;
+        let repeated = self.repeated as f64 / fields.max(1) as f64;
+        (0.2..=0.6).contains(&interlaced) || repeated >= 0.2
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_idet_telecined() {
+        const FFMPEG_OUT: &str = "\
+[Parsed_idet_0 @ 0x55b4c6a1c0] Repeated Fields: Neither:  1180 Top:    10 Bottom:    11
+[Parsed_idet_0 @ 0x55b4c6a1c0] Single frame detection: TFF:   512 BFF:     0 Progressive:   801 Undetermined:   188
+[Parsed_idet_0 @ 0x55b4c6a1c0] Multi frame detection: TFF:   598 BFF:     0 Progressive:   899 Undetermined:     4
+";
+        let idet = Idet::parse(FFMPEG_OUT).unwrap();
+        assert_eq!(
+            idet,
+            Idet {
+                interlaced: 598,
+                progressive: 899,
+                repeated: 21,
+                not_repeated: 1180,
+            }
+        );
+        assert!(idet.is_telecined());
+    }
+
+    #[test]
+    fn idet_not_telecined() {
+        let progressive = Idet {
+            interlaced: 3,
+            progressive: 1497,
+            repeated: 0,
+            not_repeated: 1500,
+        };
+        assert!(!progressive.is_telecined());
+
+        let interlaced = Idet {
+            interlaced: 1460,
+            progressive: 40,
+            repeated: 2,
+            not_repeated: 1498,
+        };
+        assert!(!interlaced.is_telecined());
+
+        assert_eq!(Idet::parse("frame= 1500 fps=900"), None);
+    }
+}
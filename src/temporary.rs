@@ -1,12 +1,38 @@
 //! temp file logic
 use std::{
     collections::HashMap,
-    env, iter,
+    env,
+    fs::{self, File},
+    io, iter,
     path::{Path, PathBuf},
-    sync::{LazyLock, Mutex},
+    sync::{
+        LazyLock, Mutex, OnceLock,
+        atomic::{AtomicU64, Ordering},
+    },
 };
+use tracing::debug;
 
-static TEMPS: LazyLock<Mutex<HashMap<PathBuf, TempKind>>> = LazyLock::new(<_>::default);
+/// Name prefix of per-process temp dirs, e.g. ".ab-av1-Hu8Jx0bGm1ds".
+pub const DIR_PREFIX: &str = ".ab-av1-";
+
+/// File in each process temp dir locked while the process is running.
+const LOCK_FILE: &str = "ab-av1.lock";
+
+/// Temp files with their kind & scope.
+type Temps = HashMap<PathBuf, (TempKind, Option<u64>)>;
+
+static TEMPS: LazyLock<Mutex<Temps>> = LazyLock::new(<_>::default);
+
+/// Held [`LOCK_FILE`]s of process temp dirs.
+static LOCKS: LazyLock<Mutex<HashMap<PathBuf, File>>> = LazyLock::new(<_>::default);
+
+/// Default parent of process temp dirs, i.e. the command's --temp-dir.
+static DEFAULT_PARENT: OnceLock<PathBuf> = OnceLock::new();
+
+tokio::task_local! {
+    /// Temp files added within a scope are only cleaned up by cleans in the same scope.
+    static SCOPE: u64;
+}
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TempKind {
@@ -16,9 +42,55 @@ pub enum TempKind {
     Keepable,
 }
 
+/// Set the command's --temp-dir, used as the default parent for [`process_dir`].
+pub fn init(temp_dir: Option<PathBuf>) {
+    if let Some(dir) = temp_dir {
+        _ = DEFAULT_PARENT.set(dir);
+    }
+}
+
 /// Add a file as temporary so it can be deleted later.
 pub fn add(file: impl Into<PathBuf>, kind: TempKind) {
-    TEMPS.lock().unwrap().insert(file.into(), kind);
+    TEMPS
+        .lock()
+        .unwrap()
+        .insert(file.into(), (kind, current_scope()));
+}
+
+/// Run `f` in a new temp file scope.
+///
+/// Temp files added within this scope will only be deleted by [`clean`] calls
+/// in the same scope, or by unscoped calls. This allows concurrent commands to
+/// clean their own temp files without deleting those in use by others.
+pub async fn scope<F: Future>(f: F) -> F::Output {
+    static NEXT_SCOPE: AtomicU64 = AtomicU64::new(1);
+    SCOPE
+        .scope(NEXT_SCOPE.fetch_add(1, Ordering::Relaxed), f)
+        .await
+}
+
+/// Wrap `f` so it runs in the current temp file scope, if any.
+/// Should be used for spawned tasks that may add temp files.
+pub fn inherit_scope<F: Future>(f: F) -> impl Future<Output = F::Output> {
+    let scope = current_scope();
+    async move {
+        match scope {
+            Some(scope) => SCOPE.scope(scope, f).await,
+            None => f.await,
+        }
+    }
+}
+
+fn current_scope() -> Option<u64> {
+    SCOPE.try_with(|s| *s).ok()
+}
+
+/// Returns true if a temp file in `scope` should be cleaned in the current scope.
+fn in_clean_scope(scope: Option<u64>) -> bool {
+    match current_scope() {
+        Some(current) => scope == Some(current),
+        None => true,
+    }
 }
 
 /// Remove a previously added file so that it won't be deleted later,
@@ -38,9 +110,18 @@ pub async fn clean(keep_keepables: bool) {
 
 /// Delete all added temporary files.
 pub async fn clean_all() {
-    let mut files: Vec<_> = std::mem::take(&mut *TEMPS.lock().unwrap())
-        .into_keys()
-        .collect();
+    let mut files: Vec<_> = {
+        let mut temps = TEMPS.lock().unwrap();
+        let files: Vec<_> = temps
+            .iter()
+            .filter(|(_, (_, scope))| in_clean_scope(*scope))
+            .map(|(f, _)| f.clone())
+            .collect();
+        for file in &files {
+            temps.remove(file);
+        }
+        files
+    };
     files.sort_by_key(|f| f.is_dir()); // rm dir at the end
 
     for file in files {
@@ -56,7 +137,7 @@ async fn clean_non_keepables() {
         .lock()
         .unwrap()
         .iter()
-        .filter(|(_, k)| **k == TempKind::NotKeepable)
+        .filter(|(_, (k, scope))| *k == TempKind::NotKeepable && in_clean_scope(*scope))
         .map(|(f, _)| f.clone())
         .collect();
     matching.sort_by_key(|f| f.is_dir()); // rm dir at the end
@@ -73,21 +154,138 @@ async fn clean_non_keepables() {
 /// Return a temporary directory that is distinct per process/run.
 ///
 /// Configured --temp-dir is used as a parent or, if not set, the current working dir.
+///
+/// On creation orphaned temp dirs of crashed runs in the same parent are removed.
 pub fn process_dir(conf_parent: Option<PathBuf>) -> PathBuf {
     static SUBDIR: LazyLock<String> = LazyLock::new(|| {
-        let mut subdir = String::from(".ab-av1-");
+        let mut subdir = String::from(DIR_PREFIX);
         subdir.extend(iter::repeat_with(fastrand::alphanumeric).take(12));
         subdir
     });
 
-    let mut temp_dir =
-        conf_parent.unwrap_or_else(|| env::current_dir().expect("current working directory"));
+    let mut temp_dir = conf_parent
+        .or_else(|| DEFAULT_PARENT.get().cloned())
+        .unwrap_or_else(|| env::current_dir().expect("current working directory"));
     temp_dir.push(&*SUBDIR);
 
     if !temp_dir.exists() {
+        if let Some(parent) = temp_dir.parent() {
+            remove_orphans(parent);
+        }
         add(&temp_dir, TempKind::Keepable);
-        std::fs::create_dir_all(&temp_dir).expect("failed to create temp-dir");
+        fs::create_dir_all(&temp_dir).expect("failed to create temp-dir");
+        lock(&temp_dir);
     }
 
     temp_dir
 }
+
+/// Create a new system temp dir only accessible by the current user,
+/// e.g. for files holding secrets. Fails if the dir already exists.
+pub fn private_dir(name: &str) -> io::Result<PathBuf> {
+    let dir = env::temp_dir().join(format!(
+        "ab-av1-{name}-{}-{:x}",
+        std::process::id(),
+        fastrand::u32(..)
+    ));
+    let mut builder = fs::DirBuilder::new();
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    builder.create(&dir)?;
+    Ok(dir)
+}
+
+/// Lock the [`LOCK_FILE`] in `dir` until [`release_locks`], marking it as in use.
+fn lock(dir: &Path) {
+    let path = dir.join(LOCK_FILE);
+    match File::create(&path).and_then(|f| f.try_lock().map(|_| f).map_err(<_>::into)) {
+        Ok(file) => _ = LOCKS.lock().unwrap().insert(path, file),
+        Err(err) => debug!("locking {}: {err}", path.display()),
+    }
+}
+
+/// Unlock & delete held [`LOCK_FILE`]s, then remove their dirs if now empty.
+///
+/// Should be called at the end of the program after the final [`clean`].
+pub fn release_locks() {
+    let locks = std::mem::take(&mut *LOCKS.lock().unwrap());
+    for (path, file) in locks {
+        drop(file);
+        _ = fs::remove_file(&path);
+        if let Some(dir) = path.parent() {
+            _ = fs::remove_dir(dir);
+        }
+    }
+}
+
+/// Returns true if `dir` is a process temp dir of a crashed run, i.e. its
+/// [`LOCK_FILE`] exists but is not locked.
+///
+/// Dirs kept with --keep have no lock file, so are not orphans.
+pub fn is_orphan(dir: &Path) -> bool {
+    let named = dir
+        .file_name()
+        .is_some_and(|n| n.to_string_lossy().starts_with(DIR_PREFIX));
+    if !named || LOCKS.lock().unwrap().contains_key(&dir.join(LOCK_FILE)) {
+        return false;
+    }
+    File::options()
+        .write(true)
+        .open(dir.join(LOCK_FILE))
+        .is_ok_and(|f| f.try_lock().is_ok())
+}
+
+/// Returns true if `dir` is a process temp dir of a running ab-av1, i.e. its
+/// [`LOCK_FILE`] is locked.
+pub fn in_use(dir: &Path) -> bool {
+    let path = dir.join(LOCK_FILE);
+    LOCKS.lock().unwrap().contains_key(&path)
+        || File::options()
+            .write(true)
+            .open(&path)
+            .is_ok_and(|f| f.try_lock().is_err())
+}
+
+/// Remove orphaned process temp dirs in `parent`.
+fn remove_orphans(parent: &Path) {
+    let Ok(entries) = fs::read_dir(parent) else {
+        return;
+    };
+    for dir in entries.filter_map(Result::ok).map(|e| e.path()) {
+        if dir.is_dir() && is_orphan(&dir) {
+            debug!("removing orphaned temp dir {}", dir.display());
+            _ = fs::remove_dir_all(&dir);
+        }
+    }
+}
+
+#[test]
+fn orphaned_dirs() {
+    let parent = env::temp_dir().join(format!("ab-av1-test-orphans-{}", std::process::id()));
+    let dir = |name: &str| {
+        let dir = parent.join(name);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    };
+    let crashed = dir(".ab-av1-crashed");
+    File::create(crashed.join(LOCK_FILE)).unwrap();
+    let running = dir(".ab-av1-running");
+    let lock = File::create(running.join(LOCK_FILE)).unwrap();
+    lock.try_lock().unwrap();
+    let kept = dir(".ab-av1-kept");
+    let other = dir("other");
+    File::create(other.join(LOCK_FILE)).unwrap();
+
+    assert!(is_orphan(&crashed));
+    assert!(!is_orphan(&running), "locked by a running process");
+    assert!(!is_orphan(&kept), "no lock file, e.g. --keep");
+    assert!(!is_orphan(&other), "not a temp dir");
+    assert!(in_use(&running));
+    assert!(!in_use(&crashed) && !in_use(&kept));
+
+    remove_orphans(&parent);
+    let remaining = [&crashed, &running, &kept, &other].map(|d| d.exists());
+    drop(lock);
+    _ = fs::remove_dir_all(&parent);
+    assert_eq!(remaining, [false, true, true, true]);
+}
@@ -1,49 +1,171 @@
 //! vmaf logic
-use crate::process::{Chunks, CommandExt, FfmpegOut, cmd_err, exit_ok_stderr};
+pub mod cuda;
+
+use crate::{
+    command::args::VmafPool,
+    error::Error,
+    ffmpeg,
+    ffprobe::Ffprobe,
+    log::InSpan,
+    process::{
+        Chunks, CommandExt, CommandJobEnv, FfmpegOut, ProgressParser, cmd_err, exit_ok_stderr,
+        program,
+    },
+};
 use anyhow::Context;
-use log::{debug, info};
-use std::{path::Path, process::Stdio};
+use serde::{
+    Deserializer,
+    de::{DeserializeSeed, IgnoredAny, MapAccess, SeqAccess, Visitor},
+};
+use std::{
+    fmt, fs::File, io::BufReader, mem, path::Path, process::Stdio, sync::LazyLock, time::Duration,
+};
 use tokio::process::Command;
 use tokio_process_stream::{Item, ProcessChunkStream};
 use tokio_stream::{Stream, StreamExt};
+use tracing::{debug, info, info_span};
+
+/// Returns true if the ffmpeg on PATH has the libvmaf filter. The result is cached.
+pub fn libvmaf_available() -> bool {
+    static AVAILABLE: LazyLock<bool> =
+        LazyLock::new(|| ffmpeg::has_filter(program::ffmpeg(), "libvmaf"));
+    *AVAILABLE
+}
+
+/// ffmpeg input args decoding with NVDEC into CUDA memory.
+const NVDEC_ARGS: [&str; 4] = ["-hwaccel", "cuda", "-hwaccel_output_format", "cuda"];
+
+/// Returns the pixel format NVDEC decodes the video of `probe` into, for use with
+/// `hwdownload`. `None` if the codec or pixel format isn't NVDEC decodable.
+pub fn nvdec_download_format(probe: &Ffprobe) -> Option<&'static str> {
+    let codec = probe
+        .streams
+        .iter()
+        .find(|s| s.codec_type == "video")?
+        .codec_name
+        .as_deref()?;
+    if !matches!(
+        codec,
+        "h264" | "hevc" | "av1" | "vp8" | "vp9" | "mpeg1video" | "mpeg2video" | "mpeg4" | "vc1"
+    ) {
+        return None;
+    }
+    Some(match probe.pix_fmt.as_deref()? {
+        "yuv420p" | "yuvj420p" | "nv12" => "nv12",
+        "yuv420p10le" | "p010le" => "p010le",
+        "yuv420p12le" => "p016le",
+        "yuv444p" | "yuvj444p" => "yuv444p",
+        "yuv444p10le" | "yuv444p12le" => "yuv444p16le",
+        _ => return None,
+    })
+}
+
+/// Returns a reference vfilter converting the `reference_fps` to the `distorted_fps`, if
+/// they differ, e.g. as the distorted was encoded with an fps filter. Otherwise frames
+/// would be misaligned & the score silently invalid.
+///
+/// Frames are dropped from, or duplicated in, the reference as the fps filter would have
+/// for the distorted. A higher distorted fps, e.g. frame interpolated, can't be normalized
+/// like this so is an error.
+pub fn fps_normalization(reference_fps: f64, distorted_fps: f64) -> anyhow::Result<Option<String>> {
+    // tolerate rounding differences, e.g. 23.976 vs 24000/1001
+    if (reference_fps - distorted_fps).abs() <= reference_fps * 0.001 {
+        return Ok(None);
+    }
+    anyhow::ensure!(
+        distorted_fps < reference_fps,
+        "distorted {distorted_fps:.3} fps is higher than reference {reference_fps:.3} fps, \
+         e.g. frame interpolated, so frames can't be aligned automatically. \
+         Use --reference-vfilter to convert the reference, e.g. `minterpolate=fps={distorted_fps:.3}`"
+    );
+    Ok(Some(format!("fps={distorted_fps}")))
+}
+
+/// Checks the reference & distorted durations are within `tolerance`, so frames line up.
+///
+/// Otherwise libvmaf `shortest=true` silently scores only the overlap, or frames misaligned
+/// by a section missing from one stream, giving bogus scores. Durations are compared as
+/// frame counts at the distorted fps, i.e. after any fps normalization.
+/// Images & unknown durations aren't checked.
+pub fn check_sync(
+    reference: &Ffprobe,
+    distorted: &Ffprobe,
+    tolerance: Duration,
+) -> anyhow::Result<()> {
+    if reference.is_image || distorted.is_image {
+        return Ok(());
+    }
+    let (Ok(r_duration), Ok(d_duration)) = (&reference.duration, &distorted.duration) else {
+        return Ok(());
+    };
+    let diff = r_duration.abs_diff(*d_duration);
+    if diff <= tolerance {
+        return Ok(());
+    }
 
-/// Calculate VMAF score using ffmpeg.
+    let describe = |probe: &Ffprobe, duration: &Duration| {
+        let mut desc = format!("{:.3}s", duration.as_secs_f64());
+        if let (Ok(frames), Ok(fps)) = (probe.nframes(), &probe.fps) {
+            desc += &format!(" {frames} frames @ {fps:.3} fps");
+        }
+        desc
+    };
+    let diff_frames = match &distorted.fps {
+        Ok(fps) => format!(" ~{:.0} frames", diff.as_secs_f64() * fps),
+        Err(_) => String::new(),
+    };
+    anyhow::bail!(
+        "reference & distorted are out of sync, differing by {:.3}s{diff_frames}: \
+         reference {}, distorted {}. Scores of misaligned streams are invalid, \
+         align the reference with --reference-vfilter, e.g. `trim=start=1`, \
+         or raise --sync-tolerance",
+        diff.as_secs_f64(),
+        describe(reference, r_duration),
+        describe(distorted, d_duration),
+    )
+}
+
+/// Calculate VMAF score using the `ffmpeg` executable, usually "ffmpeg".
+///
+/// With `nvdec` inputs are decoded with NVDEC & kept in CUDA memory, for use with
+/// [`Vmaf::ffmpeg_lavfi_cuda`](crate::command::args::Vmaf::ffmpeg_lavfi_cuda).
+/// With `ref_nvdec` only the reference is, for use with
+/// [`Vmaf::ffmpeg_lavfi`](crate::command::args::Vmaf::ffmpeg_lavfi).
 pub fn run(
+    ffmpeg: &Path,
+    nvdec: bool,
+    ref_nvdec: bool,
     reference: &Path,
     distorted: &Path,
     filter_complex: &str,
     fps: Option<f32>,
 ) -> anyhow::Result<impl Stream<Item = VmafOut> + use<>> {
+    let span = info_span!("vmaf");
+    let _enter = span.enter();
     info!(
         "vmaf {} vs reference {}",
         distorted.file_name().and_then(|n| n.to_str()).unwrap_or(""),
         reference.file_name().and_then(|n| n.to_str()).unwrap_or(""),
     );
 
-    let mut cmd = Command::new("ffmpeg");
-    cmd.kill_on_drop(true)
-        .arg2_opt("-r", fps)
-        .arg2("-i", distorted)
-        .arg2_opt("-r", fps)
-        .arg2("-i", reference)
-        .arg2("-filter_complex", filter_complex)
-        // Workaround unused streams causing ffmpeg memory leaks
-        // See https://github.com/alexheretic/ab-av1/issues/189
-        .arg("-an")
-        .arg("-sn")
-        .arg("-dn")
-        .arg2("-f", "null")
-        .arg("-")
-        .stdin(Stdio::null());
-
+    let cmd = command(
+        ffmpeg,
+        nvdec,
+        ref_nvdec,
+        reference,
+        distorted,
+        filter_complex,
+        fps,
+    );
     let cmd_str = cmd.to_cmd_str();
     debug!("cmd `{cmd_str}`");
     let mut vmaf = crate::process::child::AddOnDropChunkStream::from(
-        ProcessChunkStream::try_from(cmd).context("ffmpeg vmaf")?,
+        ProcessChunkStream::try_from(cmd).map_err(Error::spawn("ffmpeg vmaf"))?,
     );
 
     Ok(async_stream::stream! {
         let mut chunks = Chunks::default();
+        let mut progress = ProgressParser::default();
         let mut parsed_done = false;
         while let Some(next) = vmaf.next().await {
             match next {
@@ -55,10 +177,27 @@ pub fn run(
                         yield out;
                     }
                 }
-                Item::Stdout(_) => {}
+                Item::Stdout(chunk) => {
+                    if let Some(out) = progress.push(&chunk) {
+                        yield VmafOut::Progress(out);
+                    }
+                }
                 Item::Done(code) => {
                     if let Err(err) = exit_ok_stderr("ffmpeg vmaf", code, &cmd_str, &chunks) {
-                        yield VmafOut::Err(err);
+                        let stderr = chunks.to_trimmed_string();
+                        yield VmafOut::Err(anyhow::Error::new(Error::VmafFailed { stderr }).context(format!("{err}")));
+                    } else {
+                        // --also-psnr, --also-ssim results are logged at filter uninit
+                        if let Some(psnr) = chunks.rfind_line_map(psnr_from_line) {
+                            yield VmafOut::Psnr(psnr);
+                        }
+                        if let Some(ssim) = chunks.rfind_line_map(ssim_from_line) {
+                            yield VmafOut::Ssim(ssim);
+                        }
+                        let models: Vec<_> = chunks.lines().filter_map(score_from_line).skip(1).collect();
+                        if !models.is_empty() {
+                            yield VmafOut::ModelScores(models);
+                        }
                     }
                 }
             }
@@ -70,27 +209,302 @@ pub fn run(
                 &chunks,
             ));
         }
-    })
+    }
+    .in_span(span.clone()))
+}
+
+/// Returns the ffmpeg command [`run`] spawns.
+pub fn command(
+    ffmpeg: &Path,
+    nvdec: bool,
+    ref_nvdec: bool,
+    reference: &Path,
+    distorted: &Path,
+    filter_complex: &str,
+    fps: Option<f32>,
+) -> Command {
+    let mut cmd = Command::new(ffmpeg);
+    cmd.job_env()
+        .kill_on_drop(true)
+        .arg2("-progress", "pipe:1")
+        .arg("-nostats")
+        .arg2_opt("-r", fps)
+        .args(nvdec.then_some(NVDEC_ARGS).into_iter().flatten())
+        .arg2("-i", distorted)
+        .arg2_opt("-r", fps)
+        .args(
+            (nvdec || ref_nvdec)
+                .then_some(NVDEC_ARGS)
+                .into_iter()
+                .flatten(),
+        )
+        .arg2("-i", reference)
+        .arg2("-filter_complex", filter_complex)
+        // Workaround unused streams causing ffmpeg memory leaks
+        // See https://github.com/alexheretic/ab-av1/issues/189
+        .arg("-an")
+        .arg("-sn")
+        .arg("-dn")
+        .arg2("-f", "null")
+        .arg("-")
+        .stdin(Stdio::null());
+    cmd
+}
+
+/// Returns libvmaf options to write per-frame scores to a json `log`.
+///
+/// Should be appended to the libvmaf filter options.
+pub fn frame_log_opts(log: &Path) -> String {
+    // escape for use as a quoted filter option value
+    let log = log
+        .to_string_lossy()
+        .replace('\\', "/")
+        .replace('\'', "")
+        .replace(':', "\\:");
+    format!(":log_fmt=json:log_path='{log}'")
+}
+
+/// A frame's VMAF score from a libvmaf json log.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrameScore {
+    pub frame: u64,
+    pub vmaf: f32,
+}
+
+/// Stream per-frame VMAF scores from a libvmaf json log to `f`.
+///
+/// The log is parsed incrementally so memory use is flat regardless of the log size,
+/// which may be hundreds of MB for long inputs.
+pub fn for_each_frame(log: &Path, f: impl FnMut(FrameScore)) -> anyhow::Result<()> {
+    #[derive(serde::Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Frame {
+        frame_num: u64,
+        metrics: Metrics,
+    }
+    #[derive(serde::Deserialize)]
+    struct Metrics {
+        vmaf: f32,
+    }
+
+    /// Visits the top level log object, streaming the "frames" array.
+    struct LogVisitor<F>(F);
+    impl<'de, F: FnMut(FrameScore)> Visitor<'de> for LogVisitor<F> {
+        type Value = ();
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a libvmaf json log")
+        }
+
+        fn visit_map<A: MapAccess<'de>>(mut self, mut map: A) -> Result<(), A::Error> {
+            while let Some(key) = map.next_key::<String>()? {
+                match key.as_str() {
+                    "frames" => map.next_value_seed(FramesSeed(&mut self.0))?,
+                    _ => _ = map.next_value::<IgnoredAny>()?,
+                }
+            }
+            Ok(())
+        }
+    }
+
+    struct FramesSeed<'a, F>(&'a mut F);
+    impl<'de, F: FnMut(FrameScore)> DeserializeSeed<'de> for FramesSeed<'_, F> {
+        type Value = ();
+
+        fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+            deserializer.deserialize_seq(self)
+        }
+    }
+    impl<'de, F: FnMut(FrameScore)> Visitor<'de> for FramesSeed<'_, F> {
+        type Value = ();
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a frames array")
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+            while let Some(frame) = seq.next_element::<Frame>()? {
+                (self.0)(FrameScore {
+                    frame: frame.frame_num,
+                    vmaf: frame.metrics.vmaf,
+                });
+            }
+            Ok(())
+        }
+    }
+
+    let file = File::open(log).with_context(|| format!("{}", log.display()))?;
+    let mut de = serde_json::Deserializer::from_reader(BufReader::new(file));
+    de.deserialize_map(LogVisitor(f))
+        .and_then(|_| de.end())
+        .with_context(|| format!("invalid vmaf log {}", log.display()))
+}
+
+/// Read per-frame VMAF score stats from a libvmaf json log.
+pub fn frame_stats(log: &Path) -> anyhow::Result<FrameStats> {
+    let mut stats = FrameStatsBuilder::default();
+    for_each_frame(log, |f| stats.push(f.vmaf))?;
+    stats.build().context("no frames in vmaf log")
+}
+
+/// Returns the `n` lowest scoring frames of a libvmaf json log, lowest first.
+pub fn log_worst_frames(log: &Path, n: usize) -> anyhow::Result<Vec<FrameScore>> {
+    let mut worst = Vec::with_capacity(n * 2);
+    for_each_frame(log, |frame| {
+        worst.push(frame);
+        if worst.len() >= n.max(1) * 2 {
+            worst = worst_frames(mem::take(&mut worst), n);
+        }
+    })?;
+    Ok(worst_frames(worst, n))
+}
+
+/// Returns the `n` lowest scoring frames, lowest first.
+pub fn worst_frames(mut scores: Vec<FrameScore>, n: usize) -> Vec<FrameScore> {
+    scores.sort_by(|a, b| a.vmaf.total_cmp(&b.vmaf).then(a.frame.cmp(&b.frame)));
+    scores.truncate(n);
+    scores
+}
+
+/// Incrementally pools per-frame scores into [`FrameStats`] using constant memory.
+///
+/// Percentiles use a histogram of 0.01 wide score buckets.
+#[derive(Debug, Clone)]
+pub struct FrameStatsBuilder {
+    count: u64,
+    sum: f64,
+    harmonic_sum: f64,
+    min: f32,
+    /// Frame counts of scores 0.00, 0.01, ..., 100.00.
+    histogram: Vec<u32>,
+}
+
+impl Default for FrameStatsBuilder {
+    fn default() -> Self {
+        Self {
+            count: 0,
+            sum: 0.0,
+            harmonic_sum: 0.0,
+            min: f32::INFINITY,
+            histogram: vec![0; Self::BUCKETS],
+        }
+    }
+}
+
+impl FrameStatsBuilder {
+    const BUCKETS: usize = 10_001;
+
+    pub fn push(&mut self, score: f32) {
+        self.count += 1;
+        self.sum += score as f64;
+        self.harmonic_sum += 1.0 / (score as f64 + 1.0);
+        self.min = self.min.min(score);
+        let bucket = ((score as f64 * 100.0).round().max(0.0) as usize).min(Self::BUCKETS - 1);
+        self.histogram[bucket] += 1;
+    }
+
+    pub fn build(&self) -> Option<FrameStats> {
+        if self.count == 0 {
+            return None;
+        }
+        let n = self.count as f64;
+        // nearest-rank percentile
+        let percentile = |p: f64| {
+            let rank = ((p / 100.0 * n).ceil() as u64).clamp(1, self.count);
+            let mut seen = 0;
+            let bucket = self
+                .histogram
+                .iter()
+                .position(|c| {
+                    seen += *c as u64;
+                    seen >= rank
+                })
+                .unwrap_or_default();
+            (bucket as f64 / 100.0) as f32
+        };
+
+        Some(FrameStats {
+            mean: (self.sum / n) as _,
+            harmonic_mean: (n / self.harmonic_sum - 1.0) as _,
+            p5: percentile(5.0),
+            p1: percentile(1.0),
+            min: self.min,
+        })
+    }
+}
+
+/// Pooled per-frame VMAF scores.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct FrameStats {
+    pub mean: f32,
+    /// Harmonic mean, offset by 1 like libvmaf to handle zero scores.
+    pub harmonic_mean: f32,
+    /// 5th percentile.
+    pub p5: f32,
+    /// 1st percentile.
+    pub p1: f32,
+    pub min: f32,
+}
+
+impl FrameStats {
+    #[cfg(test)]
+    pub fn from_scores(scores: &[f32]) -> Option<Self> {
+        let mut stats = FrameStatsBuilder::default();
+        for score in scores {
+            stats.push(*score);
+        }
+        stats.build()
+    }
+
+    /// Combine the stats of multiple samples.
+    ///
+    /// Min is the lowest of all, other stats are averaged.
+    pub fn combine(stats: &[Self]) -> Option<Self> {
+        if stats.is_empty() {
+            return None;
+        }
+        let n = stats.len() as f32;
+        let mean_of = |f: fn(&Self) -> f32| stats.iter().map(f).sum::<f32>() / n;
+        Some(Self {
+            mean: mean_of(|s| s.mean),
+            harmonic_mean: mean_of(|s| s.harmonic_mean),
+            p5: mean_of(|s| s.p5),
+            p1: mean_of(|s| s.p1),
+            min: stats.iter().map(|s| s.min).fold(f32::INFINITY, f32::min),
+        })
+    }
+
+    pub fn pooled(&self, pool: VmafPool) -> f32 {
+        match pool {
+            VmafPool::Mean => self.mean,
+            VmafPool::HarmonicMean => self.harmonic_mean,
+            VmafPool::P5 => self.p5,
+            VmafPool::P1 => self.p1,
+            VmafPool::Min => self.min,
+        }
+    }
 }
 
 #[derive(Debug)]
 pub enum VmafOut {
     Progress(FfmpegOut),
     Done(f32),
+    /// Average PSNR, if calculated. Output after `Done`.
+    Psnr(f32),
+    /// All SSIM, if calculated. Output after `Done`.
+    Ssim(f32),
+    /// Scores of additional --vmaf-model models, in order. Output after `Done`.
+    ModelScores(Vec<f32>),
     Err(anyhow::Error),
 }
 
 impl VmafOut {
     fn try_from_chunk(chunk: &[u8], chunks: &mut Chunks) -> Option<Self> {
-        const SCORE_PREFIX: &str = "VMAF score: ";
-
         chunks.push(chunk);
 
-        if let Some(line) = chunks.rfind_line(|l| l.contains(SCORE_PREFIX)) {
-            let idx = line.find(SCORE_PREFIX).unwrap();
-            return Some(Self::Done(
-                line[idx + SCORE_PREFIX.len()..].trim().parse().ok()?,
-            ));
+        // the first model's score, multiple models each log a score
+        if let Some(score) = chunks.lines().find_map(score_from_line) {
+            return Some(Self::Done(score));
         }
         if let Some(progress) = FfmpegOut::try_parse(chunks.last_line()) {
             return Some(Self::Progress(progress));
@@ -99,10 +513,164 @@ impl VmafOut {
     }
 }
 
+/// Parse the VMAF score from e.g. "[Parsed_libvmaf_6 @ 0x5555] VMAF score: 94.826380".
+fn score_from_line(line: &str) -> Option<f32> {
+    const SCORE_PREFIX: &str = "VMAF score: ";
+    let idx = line.find(SCORE_PREFIX)?;
+    line[idx + SCORE_PREFIX.len()..].trim().parse().ok()
+}
+
+/// Parse the average PSNR from e.g.
+/// "[Parsed_psnr_4 @ 0x5555] PSNR y:38.51 u:44.12 v:44.87 average:39.84 min:37.10 max:40.91".
+pub fn psnr_from_line(line: &str) -> Option<f32> {
+    if !line.contains("PSNR ") {
+        return None;
+    }
+    let avg = &line[line.find("average:")? + "average:".len()..];
+    match avg.split_whitespace().next()? {
+        "inf" => Some(f32::INFINITY),
+        avg => avg.parse().ok(),
+    }
+}
+
+/// Parse the All SSIM from e.g.
+/// "[Parsed_ssim_5 @ 0x5555] SSIM Y:0.985 (18.2) U:0.991 (20.4) V:0.992 (20.9) All:0.987 (19.0)".
+fn ssim_from_line(line: &str) -> Option<f32> {
+    if !line.contains("SSIM ") {
+        return None;
+    }
+    let all = &line[line.find("All:")? + "All:".len()..];
+    all.split_whitespace().next()?.parse().ok()
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
+    #[test]
+    fn fps_mismatch_normalization() {
+        assert_eq!(fps_normalization(23.976, 24000.0 / 1001.0).unwrap(), None);
+        assert_eq!(
+            fps_normalization(60.0, 30.0).unwrap().as_deref(),
+            Some("fps=30")
+        );
+        let err = fps_normalization(30.0, 60.0).unwrap_err().to_string();
+        assert!(err.contains("minterpolate=fps=60.000"), "{err}");
+    }
+
+    #[test]
+    fn stream_sync() {
+        let probe = |secs: f64, fps: f64| Ffprobe {
+            duration: Ok(Duration::from_secs_f64(secs)),
+            has_audio: false,
+            max_audio_channels: None,
+            fps: Ok(fps),
+            resolution: None,
+            is_image: false,
+            animation: None,
+            trimmed_from: None,
+            pix_fmt: None,
+            field_order: None,
+            size: None,
+            streams: vec![],
+        };
+        let tolerance = Duration::from_millis(500);
+        check_sync(&probe(60.0, 24.0), &probe(60.2, 24.0), tolerance).unwrap();
+        check_sync(&probe(60.0, 60.0), &probe(60.0, 30.0), tolerance).unwrap();
+
+        let err = check_sync(&probe(60.0, 24.0), &probe(58.0, 24.0), tolerance)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("differing by 2.000s ~48 frames"), "{err}");
+        assert!(
+            err.contains(
+                "reference 60.000s 1440 frames @ 24.000 fps, distorted 58.000s 1392 frames"
+            ),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn frame_log_opts_escape() {
+        assert_eq!(
+            frame_log_opts(Path::new(r"C:\temp\vid.json")),
+            r":log_fmt=json:log_path='C\:/temp/vid.json'"
+        );
+    }
+
+    #[test]
+    fn parse_frame_stats() {
+        let log = std::env::temp_dir().join(format!("ab-av1-test-{}.json", std::process::id()));
+        std::fs::write(
+            &log,
+            r#"{"version":"3.0.0","frames":[
+                {"frameNum":0,"metrics":{"integer_adm2":0.98,"vmaf":97.1}},
+                {"frameNum":1,"metrics":{"integer_adm2":0.95,"vmaf":71.25}},
+                {"frameNum":2,"metrics":{"integer_adm2":0.97,"vmaf":95.5}}
+            ],"pooled_metrics":{}}"#,
+        )
+        .unwrap();
+        let stats = frame_stats(&log);
+        let worst = log_worst_frames(&log, 2);
+        let _ = std::fs::remove_file(&log);
+        let stats = stats.unwrap();
+        assert_eq!(stats.min, 71.25);
+        assert_eq!(stats.p5, 71.25);
+        let worst: Vec<_> = worst.unwrap().iter().map(|f| f.frame).collect();
+        assert_eq!(worst, [1, 2]);
+    }
+
+    #[test]
+    fn parse_psnr_ssim() {
+        assert_eq!(
+            psnr_from_line(
+                "[Parsed_psnr_4 @ 0x5555] PSNR y:38.51 u:44.12 v:44.87 average:39.84 min:37.10 max:40.91"
+            ),
+            Some(39.84)
+        );
+        assert_eq!(
+            ssim_from_line(
+                "[Parsed_ssim_5 @ 0x5555] SSIM Y:0.985 (18.2) U:0.991 (20.4) V:0.992 (20.9) All:0.987 (19.0)"
+            ),
+            Some(0.987)
+        );
+        assert_eq!(
+            psnr_from_line("[Parsed_libvmaf_6 @ 0x5555] VMAF score: 94.8"),
+            None
+        );
+    }
+
+    #[test]
+    fn worst_frame_scores() {
+        let scores = [97.1, 71.25, 95.5, 71.25]
+            .into_iter()
+            .enumerate()
+            .map(|(frame, vmaf)| FrameScore {
+                frame: frame as _,
+                vmaf,
+            })
+            .collect();
+        let worst: Vec<_> = worst_frames(scores, 3).iter().map(|f| f.frame).collect();
+        assert_eq!(worst, [1, 3, 2]);
+    }
+
+    #[test]
+    fn pool_frame_stats() {
+        let mut scores = vec![95.0; 98];
+        scores.extend([60.0, 80.0]);
+        let stats = FrameStats::from_scores(&scores).unwrap();
+        assert!((stats.mean - 94.5).abs() < 0.001, "{stats:?}");
+        assert!(stats.harmonic_mean < stats.mean, "{stats:?}");
+        assert_eq!(stats.p5, 95.0);
+        assert_eq!(stats.p1, 60.0);
+        assert_eq!(stats.pooled(VmafPool::Min), 60.0);
+
+        let combined =
+            FrameStats::combine(&[stats, FrameStats::from_scores(&[90.0, 91.0]).unwrap()]).unwrap();
+        assert_eq!(combined.min, 60.0);
+        assert!((combined.mean - 92.5).abs() < 0.001, "{combined:?}");
+    }
+
     #[test]
     fn parse_vmaf_score_207() {
         const FFMPEG_OUT: &str = r#"ffmpeg version n7.0.1 Copyright (c) 2000-2024 the FFmpeg developers
@@ -207,4 +775,17 @@ frame=  600 fps=102 q=-0.0 Lsize=N/A time=00:00:24.95 bitrate=N/A speed=4.24x"#;
 
         assert_eq!(vmaf_score, Some(94.82638), "failed to parse vmaf score");
     }
+
+    #[test]
+    fn parse_multiple_model_scores() {
+        let mut chunks = Chunks::default();
+        let out = VmafOut::try_from_chunk(
+            b"[Parsed_libvmaf_6 @ 0x5555] VMAF score: 93.250000\n\
+              [Parsed_libvmaf_6 @ 0x5555] VMAF score: 95.500000\n",
+            &mut chunks,
+        );
+        assert!(matches!(out, Some(VmafOut::Done(93.25))), "{out:?}");
+        let models: Vec<_> = chunks.lines().filter_map(score_from_line).skip(1).collect();
+        assert_eq!(models, [95.5]);
+    }
 }
@@ -0,0 +1,117 @@
+//! libvmaf CUDA support detection.
+use crate::{
+    compat, ffmpeg,
+    process::{CommandJobEnv, program},
+};
+use std::{
+    env,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::LazyLock,
+};
+use tracing::{debug, warn};
+
+/// Env var for an ffmpeg executable, or directory containing one, built with
+/// libvmaf_cuda. Checked before the ffmpeg on PATH.
+pub const VMAF_CUDA_PATH_ENV: &str = "VMAF_CUDA_PATH";
+
+/// libvmaf_cuda availability.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Support {
+    /// ffmpeg executable supporting libvmaf_cuda.
+    Available(PathBuf),
+    /// No ffmpeg with the libvmaf_cuda filter found.
+    Missing,
+    /// libvmaf_cuda found but failed a probe run, e.g. no usable GPU.
+    Failed(String),
+}
+
+/// Detect libvmaf_cuda support, checking `VMAF_CUDA_PATH` then the ffmpeg on PATH.
+///
+/// Each candidate must have the libvmaf_cuda filter & pass a tiny probe run.
+/// The result is cached.
+pub fn support() -> &'static Support {
+    static SUPPORT: LazyLock<Support> = LazyLock::new(|| {
+        let support = detect();
+        match &support {
+            Support::Failed(err) => warn!("libvmaf_cuda unusable, using CPU libvmaf: {err}"),
+            _ => debug!("libvmaf_cuda {support:?}"),
+        }
+        support
+    });
+    &SUPPORT
+}
+
+/// Returns the ffmpeg executable to use for libvmaf_cuda, if [`support`]ed.
+pub fn ffmpeg() -> Option<&'static Path> {
+    match support() {
+        Support::Available(ffmpeg) => Some(ffmpeg),
+        Support::Missing | Support::Failed(_) => None,
+    }
+}
+
+fn detect() -> Support {
+    let env_ffmpeg = env::var_os(VMAF_CUDA_PATH_ENV).map(|path| {
+        let path = PathBuf::from(path);
+        match path.is_dir() {
+            true => path.join("ffmpeg"),
+            false => path,
+        }
+    });
+
+    // upstream ab-av1 doesn't use libvmaf_cuda, unless explicitly configured
+    if env_ffmpeg.is_none() && compat::upstream() {
+        return Support::Missing;
+    }
+
+    let mut support = Support::Missing;
+    for ffmpeg in env_ffmpeg
+        .iter()
+        .map(PathBuf::as_path)
+        .chain([program::ffmpeg()])
+    {
+        if !ffmpeg::has_filter(ffmpeg, "libvmaf_cuda") {
+            if env_ffmpeg.as_deref() == Some(ffmpeg) {
+                support = Support::Failed(format!(
+                    "{VMAF_CUDA_PATH_ENV}={} has no libvmaf_cuda filter",
+                    ffmpeg.display()
+                ));
+            }
+            continue;
+        }
+        match probe(ffmpeg) {
+            Ok(()) => return Support::Available(ffmpeg.into()),
+            Err(err) => support = Support::Failed(format!("{}: {err}", ffmpeg.display())),
+        }
+    }
+    support
+}
+
+/// Score a few tiny generated frames with libvmaf_cuda.
+fn probe(ffmpeg: &Path) -> Result<(), String> {
+    const SOURCE: &str = "color=c=gray:s=320x240:r=25:d=0.2";
+
+    let out = Command::new(ffmpeg)
+        .job_env()
+        .args(["-hide_banner", "-loglevel", "error"])
+        .args(["-f", "lavfi", "-i", SOURCE, "-f", "lavfi", "-i", SOURCE])
+        .arg("-filter_complex")
+        .arg(
+            "[0:v]format=yuv420p,hwupload_cuda[dis];\
+             [1:v]format=yuv420p,hwupload_cuda[ref];\
+             [dis][ref]libvmaf_cuda",
+        )
+        .args(["-f", "null", "-"])
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|err| err.to_string())?;
+    match out.status.success() {
+        true => Ok(()),
+        false => Err(String::from_utf8_lossy(&out.stderr)
+            .lines()
+            .last()
+            .unwrap_or("probe failed")
+            .trim()
+            .to_owned()),
+    }
+}
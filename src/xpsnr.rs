@@ -1,58 +1,70 @@
-//! xpsnr logic
-use crate::process::{Chunks, CommandExt, FfmpegOut, cmd_err, exit_ok_stderr};
+//! xpsnr & psnr logic
+use crate::{
+    command::sample_encode::ScoreKind,
+    log::InSpan,
+    process::{
+        Chunks, CommandExt, CommandJobEnv, FfmpegOut, ProgressParser, cmd_err, exit_ok_stderr,
+        program,
+    },
+    vmaf::psnr_from_line,
+};
 use anyhow::Context;
-use log::{debug, info};
 use std::{path::Path, process::Stdio};
 use tokio::process::Command;
 use tokio_process_stream::{Item, ProcessChunkStream};
 use tokio_stream::{Stream, StreamExt};
+use tracing::{debug, info, info_span};
 
-/// Calculate XPSNR score using ffmpeg.
+/// Calculate XPSNR score, or PSNR if `kind` is [`ScoreKind::Psnr`], using ffmpeg.
 pub fn run(
+    kind: ScoreKind,
     reference: &Path,
     distorted: &Path,
     filter_complex: &str,
     fps: Option<f32>,
 ) -> anyhow::Result<impl Stream<Item = XpsnrOut> + use<>> {
+    let (name, parse_score): (_, fn(&str) -> Option<f32>) = match kind {
+        ScoreKind::Psnr => ("ffmpeg psnr", psnr_from_line),
+        _ => ("ffmpeg xpsnr", score_from_line),
+    };
+    let span = info_span!("xpsnr", kind = kind.fps_label());
+    let _enter = span.enter();
     info!(
-        "xpsnr {} vs reference {}",
+        "{} {} vs reference {}",
+        kind.fps_label(),
         distorted.file_name().and_then(|n| n.to_str()).unwrap_or(""),
         reference.file_name().and_then(|n| n.to_str()).unwrap_or(""),
     );
 
-    let mut cmd = Command::new("ffmpeg");
-    cmd.kill_on_drop(true)
-        .arg2_opt("-r", fps)
-        .arg2("-i", reference)
-        .arg2_opt("-r", fps)
-        .arg2("-i", distorted)
-        .arg2("-filter_complex", filter_complex)
-        .arg2("-f", "null")
-        .arg("-")
-        .stdin(Stdio::null());
+    let cmd = command(reference, distorted, filter_complex, fps);
 
     let cmd_str = cmd.to_cmd_str();
     debug!("cmd `{cmd_str}`");
     let mut xpsnr = crate::process::child::AddOnDropChunkStream::from(
-        ProcessChunkStream::try_from(cmd).context("ffmpeg xpsnr")?,
+        ProcessChunkStream::try_from(cmd).context(name)?,
     );
 
     Ok(async_stream::stream! {
         let mut chunks = Chunks::default();
+        let mut progress = ProgressParser::default();
         let mut parsed_done = false;
         while let Some(next) = xpsnr.next().await {
             match next {
                 Item::Stderr(chunk) => {
-                    if let Some(out) = XpsnrOut::try_from_chunk(&chunk, &mut chunks) {
+                    if let Some(out) = XpsnrOut::try_from_chunk(&chunk, &mut chunks, parse_score) {
                         if matches!(out, XpsnrOut::Done(_)) {
                             parsed_done = true;
                         }
                         yield out;
                     }
                 }
-                Item::Stdout(_) => {}
+                Item::Stdout(chunk) => {
+                    if let Some(out) = progress.push(&chunk) {
+                        yield XpsnrOut::Progress(out);
+                    }
+                }
                 Item::Done(code) => {
-                    if let Err(err) = exit_ok_stderr("ffmpeg xpsnr", code, &cmd_str, &chunks) {
+                    if let Err(err) = exit_ok_stderr(name, code, &cmd_str, &chunks) {
                         yield XpsnrOut::Err(err);
                     }
                 }
@@ -60,12 +72,36 @@ pub fn run(
         }
         if !parsed_done {
             yield XpsnrOut::Err(cmd_err(
-                "could not parse ffmpeg xpsnr score",
+                format!("could not parse {name} score"),
                 &cmd_str,
                 &chunks,
             ));
         }
-    })
+    }
+    .in_span(span.clone()))
+}
+
+/// Returns the ffmpeg command [`run`] spawns.
+pub fn command(
+    reference: &Path,
+    distorted: &Path,
+    filter_complex: &str,
+    fps: Option<f32>,
+) -> Command {
+    let mut cmd = Command::new(program::ffmpeg());
+    cmd.job_env()
+        .kill_on_drop(true)
+        .arg2("-progress", "pipe:1")
+        .arg("-nostats")
+        .arg2_opt("-r", fps)
+        .arg2("-i", reference)
+        .arg2_opt("-r", fps)
+        .arg2("-i", distorted)
+        .arg2("-filter_complex", filter_complex)
+        .arg2("-f", "null")
+        .arg("-")
+        .stdin(Stdio::null());
+    cmd
 }
 
 #[derive(Debug)]
@@ -76,10 +112,14 @@ pub enum XpsnrOut {
 }
 
 impl XpsnrOut {
-    fn try_from_chunk(chunk: &[u8], chunks: &mut Chunks) -> Option<Self> {
+    fn try_from_chunk(
+        chunk: &[u8],
+        chunks: &mut Chunks,
+        parse_score: fn(&str) -> Option<f32>,
+    ) -> Option<Self> {
         chunks.push(chunk);
 
-        if let Some(score) = chunks.rfind_line_map(score_from_line) {
+        if let Some(score) = chunks.rfind_line_map(parse_score) {
             return Some(Self::Done(score));
         }
         if let Some(progress) = FfmpegOut::try_parse(chunks.last_line()) {
@@ -225,7 +265,7 @@ XPSNR average, 1344 frames  y: 40.7139
             let chunk = &ffmpeg[start_idx..(start_idx + CHUNK_SIZE).min(FFMPEG_OUT.len())];
             // println!("* {}", String::from_utf8_lossy(chunk).trim());
 
-            if let Some(xpsnr) = XpsnrOut::try_from_chunk(chunk, &mut chunks) {
+            if let Some(xpsnr) = XpsnrOut::try_from_chunk(chunk, &mut chunks, score_from_line) {
                 println!("{xpsnr:?}");
                 if let XpsnrOut::Done(score) = xpsnr {
                     xpsnr_score = Some(score);
@@ -0,0 +1,72 @@
+//! Runs the ab-av1 binary with --upstream-compat.
+#![allow(unused_crate_dependencies)]
+
+use std::{fs, process::Command};
+
+const BIN: &str = env!("CARGO_BIN_EXE_ab-av1");
+
+#[test]
+fn upstream_compat_ignores_config() {
+    let config = std::env::temp_dir().join(format!("ab-av1-compat-{}.toml", std::process::id()));
+    fs::write(&config, "not valid toml = = =").unwrap();
+
+    let fork = Command::new(BIN)
+        .args(["print-completions", "bash"])
+        .env("AB_AV1_CONFIG", &config)
+        .output()
+        .unwrap();
+    let compat = Command::new(BIN)
+        .args(["--upstream-compat", "print-completions", "bash"])
+        .env("AB_AV1_CONFIG", &config)
+        .output()
+        .unwrap();
+    let compat_env = Command::new(BIN)
+        .args(["print-completions", "bash"])
+        .env("AB_AV1_CONFIG", &config)
+        .env("AB_AV1_UPSTREAM_COMPAT", "1")
+        .output()
+        .unwrap();
+    _ = fs::remove_file(&config);
+
+    assert_eq!(fork.status.code(), Some(64), "invalid config should fail");
+    for out in [compat, compat_env] {
+        assert!(
+            out.status.success(),
+            "{}",
+            String::from_utf8_lossy(&out.stderr)
+        );
+        assert!(String::from_utf8_lossy(&out.stdout).contains("ab-av1"));
+    }
+}
+
+#[test]
+fn upstream_compat_usage() {
+    // upstream usage errors, e.g. missing required args, are still errors
+    let out = Command::new(BIN)
+        .args(["--upstream-compat", "crf-search", "--preset", "8"])
+        .output()
+        .unwrap();
+    assert_eq!(out.status.code(), Some(2), "upstream usage exit code");
+    assert!(String::from_utf8_lossy(&out.stderr).contains("--input"));
+
+    let out = Command::new(BIN)
+        .args(["crf-search", "--preset", "8"])
+        .output()
+        .unwrap();
+    assert_eq!(out.status.code(), Some(64), "fork usage exit code");
+
+    for cmd in [
+        "sample-encode",
+        "crf-search",
+        "auto-encode",
+        "encode",
+        "vmaf",
+        "xpsnr",
+    ] {
+        let out = Command::new(BIN)
+            .args(["--upstream-compat", cmd, "--help"])
+            .output()
+            .unwrap();
+        assert!(out.status.success(), "{cmd} --help");
+    }
+}
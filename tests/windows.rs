@@ -0,0 +1,116 @@
+//! Runs the ab-av1 binary with Windows paths containing spaces, unicode & longer
+//! than `MAX_PATH`.
+#![allow(unused_crate_dependencies)]
+#![cfg(windows)]
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+const BIN: &str = env!("CARGO_BIN_EXE_ab-av1");
+
+/// Returns a new temp dir with spaces & unicode & a nested dir in it making
+/// paths longer than `MAX_PATH`.
+fn test_dirs(name: &str) -> (PathBuf, PathBuf) {
+    let root = std::env::temp_dir().join(format!("ab-av1 tést ✓ {name} {}", std::process::id()));
+    let mut dir = root.clone();
+    for _ in 0..12 {
+        dir.push("a long directory name");
+    }
+    fs::create_dir_all(&dir).unwrap();
+    (root, dir)
+}
+
+/// Returns the `\\?\` verbatim path, for external programs without long path support.
+fn verbatim(path: &Path) -> PathBuf {
+    format!(r"\\?\{}", std::path::absolute(path).unwrap().display()).into()
+}
+
+fn has_ffmpeg_filter(filter: &str) -> bool {
+    Command::new("ffmpeg")
+        .args(["-hide_banner", "-filters"])
+        .output()
+        .is_ok_and(|out| {
+            String::from_utf8_lossy(&out.stdout)
+                .split_whitespace()
+                .any(|w| w == filter)
+        })
+}
+
+#[test]
+fn config_in_long_unicode_path() {
+    let (root, dir) = test_dirs("config");
+    let config = dir.join("ab-av1 config ✓.toml");
+    assert!(config.as_os_str().len() > 260);
+
+    fs::write(&config, "").unwrap();
+    let valid = Command::new(BIN)
+        .args(["print-completions", "bash"])
+        .env("AB_AV1_CONFIG", &config)
+        .output()
+        .unwrap();
+    fs::write(&config, "not valid toml = = =").unwrap();
+    let invalid = Command::new(BIN)
+        .args(["print-completions", "bash"])
+        .env("AB_AV1_CONFIG", &config)
+        .output()
+        .unwrap();
+    _ = fs::remove_dir_all(root);
+
+    assert!(
+        valid.status.success(),
+        "{}",
+        String::from_utf8_lossy(&valid.stderr)
+    );
+    assert_eq!(
+        invalid.status.code(),
+        Some(64),
+        "invalid config should be read"
+    );
+}
+
+#[test]
+fn vmaf_long_unicode_paths() {
+    if !has_ffmpeg_filter("libvmaf") {
+        eprintln!("skipped: no ffmpeg with libvmaf");
+        return;
+    }
+    let (root, dir) = test_dirs("vmaf");
+    let video = dir.join("réf vid ✓.mkv");
+    let generated = Command::new("ffmpeg")
+        .args(["-hide_banner", "-y", "-f", "lavfi", "-i"])
+        .arg("testsrc2=size=320x240:rate=24:duration=1")
+        .args(["-c:v", "ffv1"])
+        .arg(verbatim(&video))
+        .output()
+        .unwrap();
+    assert!(
+        generated.status.success(),
+        "{}",
+        String::from_utf8_lossy(&generated.stderr)
+    );
+
+    let out = Command::new(BIN)
+        .arg("vmaf")
+        .arg("--reference")
+        .arg(&video)
+        .arg("--distorted")
+        .arg(&video)
+        .output()
+        .unwrap();
+    _ = fs::remove_dir_all(root);
+
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    let score: f32 = String::from_utf8_lossy(&out.stdout)
+        .lines()
+        .next()
+        .and_then(|l| l.trim().parse().ok())
+        .expect("vmaf score");
+    assert!(score > 99.0, "{score}");
+}